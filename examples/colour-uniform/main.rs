@@ -576,7 +576,8 @@ impl<B: Backend> RendererState<B> {
                 };
 
                 self.device.borrow_mut().queues.queues[0]
-                    .submit(submission, Some(framebuffer_fence));
+                    .submit(submission, Some(framebuffer_fence))
+                    .unwrap();
 
                 // present frame
                 if let Err(_) = self
@@ -1247,7 +1248,8 @@ impl<B: Backend> ImageState<B> {
             cmd_buffer.finish();
 
             device_state.queues.queues[0]
-                .submit_nosemaphores(iter::once(&cmd_buffer), Some(&mut transfered_image_fence));
+                .submit_nosemaphores(iter::once(&cmd_buffer), Some(&mut transfered_image_fence))
+                .unwrap();
         }
 
         ImageState {
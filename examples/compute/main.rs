@@ -105,7 +105,7 @@ fn main() {
     let (staging_memory, staging_buffer, staging_size) = unsafe {
         create_buffer::<back::Backend>(
             &device,
-            &memory_properties.memory_types,
+            &memory_properties,
             memory::Properties::CPU_VISIBLE | memory::Properties::COHERENT,
             buffer::Usage::TRANSFER_SRC | buffer::Usage::TRANSFER_DST,
             stride,
@@ -126,7 +126,7 @@ fn main() {
     let (device_memory, device_buffer, _device_buffer_size) = unsafe {
         create_buffer::<back::Backend>(
             &device,
-            &memory_properties.memory_types,
+            &memory_properties,
             memory::Properties::DEVICE_LOCAL,
             buffer::Usage::TRANSFER_SRC | buffer::Usage::TRANSFER_DST | buffer::Usage::STORAGE,
             stride,
@@ -199,7 +199,9 @@ fn main() {
         );
         command_buffer.finish();
 
-        queue_group.queues[0].submit_nosemaphores(Some(&command_buffer), Some(&fence));
+        queue_group.queues[0]
+            .submit_nosemaphores(Some(&command_buffer), Some(&fence))
+            .unwrap();
 
         device.wait_for_fence(&fence, !0).unwrap();
         command_pool.free(Some(command_buffer));
@@ -236,7 +238,7 @@ fn main() {
 
 unsafe fn create_buffer<B: Backend>(
     device: &B::Device,
-    memory_types: &[hal::MemoryType],
+    memory_properties: &hal::MemoryProperties,
     properties: memory::Properties,
     usage: buffer::Usage,
     stride: u64,
@@ -245,14 +247,9 @@ unsafe fn create_buffer<B: Backend>(
     let mut buffer = device.create_buffer(stride * len, usage).unwrap();
     let requirements = device.get_buffer_requirements(&buffer);
 
-    let ty = memory_types
-        .into_iter()
-        .enumerate()
-        .position(|(id, memory_type)| {
-            requirements.type_mask & (1 << id) != 0 && memory_type.properties.contains(properties)
-        })
-        .unwrap()
-        .into();
+    let ty = memory_properties
+        .find_memory_type(requirements.type_mask, properties)
+        .unwrap();
 
     let memory = device.allocate_memory(ty, requirements.size).unwrap();
     device.bind_buffer_memory(&memory, 0, &mut buffer).unwrap();
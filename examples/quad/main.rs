@@ -30,7 +30,7 @@ use hal::queue::Submission;
 use hal::{
     buffer, command, format as f, image as i, memory as m, pass, pool, pso, window::Extent2D,
 };
-use hal::{Backbuffer, DescriptorPool, FrameSync, Primitive, SwapchainConfig};
+use hal::{Backbuffer, DescriptorPool, FrameSync, PresentMode, Primitive, SwapchainConfig};
 use hal::{Device, Instance, PhysicalDevice, Surface, Swapchain};
 
 use std::fs;
@@ -357,14 +357,16 @@ fn main() {
 
         cmd_buffer.finish();
 
-        queue_group.queues[0].submit_nosemaphores(Some(&cmd_buffer), Some(&mut frame_fence));
+        queue_group.queues[0]
+            .submit_nosemaphores(Some(&cmd_buffer), Some(&mut frame_fence))
+            .unwrap();
 
         device
             .wait_for_fence(&frame_fence, !0)
             .expect("Can't wait for fence");
     }
 
-    let (caps, formats, _present_modes) = surface.compatibility(&mut adapter.physical_device);
+    let (caps, formats, present_modes) = surface.compatibility(&mut adapter.physical_device);
     println!("formats: {:?}", formats);
     let format = formats.map_or(f::Format::Rgba8Srgb, |formats| {
         formats
@@ -374,7 +376,8 @@ fn main() {
             .unwrap_or(formats[0])
     });
 
-    let swap_config = SwapchainConfig::from_caps(&caps, format, DIMS);
+    let swap_config = SwapchainConfig::from_caps(&caps, format, DIMS)
+        .with_preferred_modes(&present_modes, &[PresentMode::Mailbox]);
     println!("{:?}", swap_config);
     let extent = swap_config.extent.to_extent();
 
@@ -597,12 +600,13 @@ fn main() {
         if recreate_swapchain {
             device.wait_idle().unwrap();
 
-            let (caps, formats, _present_modes) =
+            let (caps, formats, present_modes) =
                 surface.compatibility(&mut adapter.physical_device);
             // Verify that previous format still exists so we may reuse it.
             assert!(formats.iter().any(|fs| fs.contains(&format)));
 
-            let swap_config = SwapchainConfig::from_caps(&caps, format, resize_dims);
+            let swap_config = SwapchainConfig::from_caps(&caps, format, resize_dims)
+                .with_preferred_modes(&present_modes, &[PresentMode::Mailbox]);
             println!("{:?}", swap_config);
             let extent = swap_config.extent.to_extent();
 
@@ -702,7 +706,9 @@ fn main() {
                 wait_semaphores: Some((&frame_semaphore, PipelineStage::BOTTOM_OF_PIPE)),
                 signal_semaphores: &[],
             };
-            queue_group.queues[0].submit(submission, Some(&mut frame_fence));
+            queue_group.queues[0]
+                .submit(submission, Some(&mut frame_fence))
+                .unwrap();
 
             // TODO: replace with semaphore
             device.wait_for_fence(&frame_fence, !0).unwrap();
@@ -2,8 +2,8 @@ use hal::format::Format;
 use hal::image::{Anisotropic, Filter, WrapMode};
 use hal::pso::{
     BlendDesc, BlendOp, BlendState, ColorBlendDesc, Comparison, DepthBias, DepthStencilDesc,
-    DepthTest, Face, Factor, FrontFace, PolygonMode, Rasterizer, Rect, Stage, State, StencilFace,
-    StencilOp, StencilTest, StencilValue, Viewport,
+    DepthTest, Face, Factor, FrontFace, LogicOp, Multisampling, PolygonMode, Rasterizer, Rect,
+    Stage, State, StencilFace, StencilOp, StencilTest, StencilValue, Viewport,
 };
 use hal::{IndexType, Primitive};
 
@@ -13,6 +13,7 @@ use winapi::shared::dxgiformat::*;
 use winapi::shared::minwindef::{FALSE, INT, TRUE};
 
 use winapi::um::d3d11::*;
+use winapi::um::d3d11_1::*;
 use winapi::um::d3dcommon::*;
 
 use std::mem;
@@ -29,6 +30,7 @@ pub fn viewable_format(format: DXGI_FORMAT) -> DXGI_FORMAT {
     match format {
         DXGI_FORMAT_D32_FLOAT_S8X24_UINT => DXGI_FORMAT_R32_FLOAT_X8X24_TYPELESS,
         DXGI_FORMAT_D32_FLOAT => DXGI_FORMAT_R32_FLOAT,
+        DXGI_FORMAT_D24_UNORM_S8_UINT => DXGI_FORMAT_R24_UNORM_X8_TYPELESS,
         DXGI_FORMAT_D16_UNORM => DXGI_FORMAT_R16_UNORM,
         _ => format,
     }
@@ -88,10 +90,14 @@ pub fn map_format(format: Format) -> Option<DXGI_FORMAT> {
         B10g11r11Ufloat => DXGI_FORMAT_R11G11B10_FLOAT,
         E5b9g9r9Ufloat => DXGI_FORMAT_R9G9B9E5_SHAREDEXP,
         D16Unorm => DXGI_FORMAT_D16_UNORM,
+        X8D24Unorm => DXGI_FORMAT_D24_UNORM_S8_UINT,
+        D24UnormS8Uint => DXGI_FORMAT_D24_UNORM_S8_UINT,
         D32Float => DXGI_FORMAT_D32_FLOAT,
         D32FloatS8Uint => DXGI_FORMAT_D32_FLOAT_S8X24_UINT,
         Bc1RgbUnorm => DXGI_FORMAT_BC1_UNORM,
         Bc1RgbSrgb => DXGI_FORMAT_BC1_UNORM_SRGB,
+        Bc1RgbaUnorm => DXGI_FORMAT_BC1_UNORM,
+        Bc1RgbaSrgb => DXGI_FORMAT_BC1_UNORM_SRGB,
         Bc2Unorm => DXGI_FORMAT_BC2_UNORM,
         Bc2Srgb => DXGI_FORMAT_BC2_UNORM_SRGB,
         Bc3Unorm => DXGI_FORMAT_BC3_UNORM,
@@ -263,6 +269,16 @@ impl DecomposedDxgiFormat {
                 copy_srv: Some(DXGI_FORMAT_R32_FLOAT_X8X24_TYPELESS),
             },
 
+            DXGI_FORMAT_D24_UNORM_S8_UINT => DecomposedDxgiFormat {
+                typeless: DXGI_FORMAT_R24G8_TYPELESS,
+                srv: Some(DXGI_FORMAT_R24_UNORM_X8_TYPELESS),
+                rtv: None,
+                uav: None,
+                dsv: Some(format),
+                copy_uav: None,
+                copy_srv: Some(DXGI_FORMAT_R24_UNORM_X8_TYPELESS),
+            },
+
             DXGI_FORMAT_D32_FLOAT => DecomposedDxgiFormat {
                 typeless: DXGI_FORMAT_R32_TYPELESS,
                 srv: Some(DXGI_FORMAT_R32_FLOAT),
@@ -626,15 +642,65 @@ fn map_blend_targets(
     targets
 }
 
-pub(crate) fn map_blend_desc(desc: &BlendDesc) -> D3D11_BLEND_DESC {
+pub(crate) fn map_blend_desc(
+    desc: &BlendDesc,
+    multisampling: &Option<Multisampling>,
+) -> D3D11_BLEND_DESC {
     D3D11_BLEND_DESC {
-        // TODO: msaa
-        AlphaToCoverageEnable: FALSE,
+        AlphaToCoverageEnable: match *multisampling {
+            Some(ref ms) if ms.alpha_coverage => TRUE,
+            _ => FALSE,
+        },
         IndependentBlendEnable: TRUE,
         RenderTarget: map_blend_targets(&desc.targets),
     }
 }
 
+fn map_logic_op(op: LogicOp) -> D3D11_LOGIC_OP {
+    match op {
+        LogicOp::Clear => D3D11_LOGIC_OP_CLEAR,
+        LogicOp::And => D3D11_LOGIC_OP_AND,
+        LogicOp::AndReverse => D3D11_LOGIC_OP_AND_REVERSE,
+        LogicOp::Copy => D3D11_LOGIC_OP_COPY,
+        LogicOp::AndInverted => D3D11_LOGIC_OP_AND_INVERTED,
+        LogicOp::NoOp => D3D11_LOGIC_OP_NOOP,
+        LogicOp::Xor => D3D11_LOGIC_OP_XOR,
+        LogicOp::Or => D3D11_LOGIC_OP_OR,
+        LogicOp::Nor => D3D11_LOGIC_OP_NOR,
+        LogicOp::Equivalent => D3D11_LOGIC_OP_EQUIV,
+        LogicOp::Invert => D3D11_LOGIC_OP_INVERT,
+        LogicOp::OrReverse => D3D11_LOGIC_OP_OR_REVERSE,
+        LogicOp::CopyInverted => D3D11_LOGIC_OP_COPY_INVERTED,
+        LogicOp::OrInverted => D3D11_LOGIC_OP_OR_INVERTED,
+        LogicOp::Nand => D3D11_LOGIC_OP_NAND,
+        LogicOp::Set => D3D11_LOGIC_OP_SET,
+    }
+}
+
+/// Blend description of D3D11.1, only needed for logic ops.
+pub(crate) fn map_blend_desc1(
+    desc: &BlendDesc,
+    multisampling: &Option<Multisampling>,
+    logic_op: LogicOp,
+) -> D3D11_BLEND_DESC1 {
+    let base = map_blend_desc(desc, multisampling);
+    let mut targets: [D3D11_RENDER_TARGET_BLEND_DESC1; 8] = [unsafe { mem::zeroed() }; 8];
+    for (target, base) in targets.iter_mut().zip(base.RenderTarget.iter()) {
+        target.RenderTargetWriteMask = base.RenderTargetWriteMask;
+    }
+
+    // Logic ops replace blending and are only read from the first target,
+    // which requires `IndependentBlendEnable` to be disabled.
+    targets[0].LogicOpEnable = TRUE;
+    targets[0].LogicOp = map_logic_op(logic_op);
+
+    D3D11_BLEND_DESC1 {
+        AlphaToCoverageEnable: base.AlphaToCoverageEnable,
+        IndependentBlendEnable: FALSE,
+        RenderTarget: targets,
+    }
+}
+
 pub fn map_comparison(func: Comparison) -> D3D11_COMPARISON_FUNC {
     match func {
         Comparison::Never => D3D11_COMPARISON_NEVER,
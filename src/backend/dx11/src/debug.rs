@@ -1,10 +1,12 @@
-use winapi::um::d3d11;
+use winapi::um::{d3d11, d3dcommon};
 
 use wio::com::ComPtr;
 use wio::wide::ToWide;
 
+use std::env;
 use std::ffi::OsStr;
-use std::{env, fmt};
+#[cfg(debug_assertions)]
+use std::fmt;
 
 // TODO: replace with new winapi version when available
 #[allow(bad_style, unused)]
@@ -30,7 +32,7 @@ mod temp {
 #[must_use]
 #[cfg(debug_assertions)]
 pub struct DebugScope {
-    annotation: ComPtr<temp::ID3DUserDefinedAnnotation>,
+    annotation: Annotation,
 }
 
 #[cfg(debug_assertions)]
@@ -39,24 +41,8 @@ impl DebugScope {
         context: &ComPtr<d3d11::ID3D11DeviceContext>,
         args: fmt::Arguments,
     ) -> Option<Self> {
-        let name = format!("{}", args);
-
-        // debugging with visual studio and its ilk *really* doesn't like calling this on a
-        // deferred context when replaying a capture, compared to renderdoc
-        if unsafe { context.GetType() } == d3d11::D3D11_DEVICE_CONTEXT_DEFERRED {
-            // TODO: find a better way to detect either if RD or VS is active debugger
-            if env::var("GFX_NO_RENDERDOC").is_ok() {
-                return None;
-            }
-        }
-
-        let annotation = context.cast::<temp::ID3DUserDefinedAnnotation>().unwrap();
-        let msg: &OsStr = name.as_ref();
-        let msg: Vec<u16> = msg.to_wide_null();
-
-        unsafe {
-            annotation.BeginEvent(msg.as_ptr() as _);
-        }
+        let annotation = annotation(context)?;
+        begin_event(&annotation, &format!("{}", args));
 
         Some(DebugScope { annotation })
     }
@@ -65,24 +51,48 @@ impl DebugScope {
 #[cfg(debug_assertions)]
 impl Drop for DebugScope {
     fn drop(&mut self) {
-        unsafe {
-            self.annotation.EndEvent();
-        }
+        end_event(&self.annotation);
     }
 }
 
 #[cfg(debug_assertions)]
 pub fn debug_marker(context: &ComPtr<d3d11::ID3D11DeviceContext>, args: fmt::Arguments) {
-    let name = format!("{}", args);
+    if let Some(annotation) = annotation(context) {
+        set_marker(&annotation, &format!("{}", args));
+    }
+}
+
+pub type Annotation = ComPtr<temp::ID3DUserDefinedAnnotation>;
 
-    // same here
+pub fn annotation(context: &ComPtr<d3d11::ID3D11DeviceContext>) -> Option<Annotation> {
+    // debugging with visual studio and its ilk *really* doesn't like calling this on a
+    // deferred context when replaying a capture, compared to renderdoc
     if unsafe { context.GetType() } == d3d11::D3D11_DEVICE_CONTEXT_DEFERRED {
+        // TODO: find a better way to detect either if RD or VS is active debugger
         if env::var("GFX_NO_RENDERDOC").is_ok() {
-            return;
+            return None;
         }
     }
 
-    let annotation = context.cast::<temp::ID3DUserDefinedAnnotation>().unwrap();
+    context.cast::<temp::ID3DUserDefinedAnnotation>().ok()
+}
+
+pub fn begin_event(annotation: &Annotation, name: &str) {
+    let msg: &OsStr = name.as_ref();
+    let msg: Vec<u16> = msg.to_wide_null();
+
+    unsafe {
+        annotation.BeginEvent(msg.as_ptr() as _);
+    }
+}
+
+pub fn end_event(annotation: &Annotation) {
+    unsafe {
+        annotation.EndEvent();
+    }
+}
+
+pub fn set_marker(annotation: &Annotation, name: &str) {
     let msg: &OsStr = name.as_ref();
     let msg: Vec<u16> = msg.to_wide_null();
 
@@ -90,3 +100,15 @@ pub fn debug_marker(context: &ComPtr<d3d11::ID3D11DeviceContext>, args: fmt::Arg
         annotation.SetMarker(msg.as_ptr() as _);
     }
 }
+
+/// Name the object, as shown by the debugging tools and the debug layer messages.
+pub unsafe fn set_object_name(object: *mut d3d11::ID3D11DeviceChild, name: &str) {
+    if object.is_null() {
+        return;
+    }
+    (*object).SetPrivateData(
+        &d3dcommon::WKPDID_D3DDebugObjectName,
+        name.len() as _,
+        name.as_ptr() as *const _,
+    );
+}
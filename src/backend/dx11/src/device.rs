@@ -5,9 +5,9 @@ use hal::range::RangeArg;
 use hal::{buffer, device, error, format, image, mapping, memory, pass, pool, pso, query};
 
 use winapi::shared::dxgi::{IDXGISwapChain, DXGI_SWAP_CHAIN_DESC, DXGI_SWAP_EFFECT_DISCARD};
-use winapi::shared::minwindef::TRUE;
+use winapi::shared::minwindef::{TRUE, UINT};
 use winapi::shared::{dxgiformat, dxgitype, winerror};
-use winapi::um::{d3d11, d3d11sdklayers, d3dcommon};
+use winapi::um::{d3d11, d3d11_1, d3d11sdklayers, d3dcommon};
 use winapi::Interface;
 
 use wio::com::ComPtr;
@@ -17,19 +17,20 @@ use std::cell::RefCell;
 use std::mem;
 use std::ops::Range;
 use std::ptr;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 
 use parking_lot::{Condvar, Mutex};
 
 use {
     Backend, Buffer, BufferView, CommandPool, ComputePipeline, Descriptor, DescriptorPool,
-    DescriptorSetLayout, Fence, Framebuffer, GraphicsPipeline, Image, ImageView, InternalBuffer,
-    InternalImage, Memory, MemoryHeapFlags, PipelineBinding, PipelineLayout, QueryPool, RawFence,
-    RegisterMapping, RegisterRemapping, RenderPass, Sampler, Semaphore, ShaderModule, SubpassDesc,
-    Surface, Swapchain, ViewInfo,
+    DescriptorSetLayout, Event, Fence, Framebuffer, GraphicsPipeline, Image, ImageView,
+    InternalBuffer, InternalImage, Memory, MemoryHeapFlags, PipelineBinding, PipelineLayout,
+    QueryPool, RawFence, RegisterMapping, RegisterRemapping, RenderPass, Sampler, Semaphore,
+    ShaderModule, SubpassDesc, Surface, Swapchain, ViewInfo,
 };
 
-use {conv, internal, shader};
+use {conv, debug, internal, shader};
 
 struct InputLayout {
     raw: ComPtr<d3d11::ID3D11InputLayout>,
@@ -105,13 +106,28 @@ impl Device {
     fn create_blend_state(
         &self,
         blend_desc: &pso::BlendDesc,
+        multisampling: &Option<pso::Multisampling>,
     ) -> Result<ComPtr<d3d11::ID3D11BlendState>, pso::CreationError> {
         let mut blend = ptr::null_mut();
-        let desc = conv::map_blend_desc(blend_desc);
 
-        let hr = unsafe {
-            self.raw
-                .CreateBlendState(&desc, &mut blend as *mut *mut _ as *mut *mut _)
+        let hr = match blend_desc.logic_op {
+            Some(logic_op) => {
+                let device1 = self
+                    .raw
+                    .cast::<d3d11_1::ID3D11Device1>()
+                    .map_err(|_| pso::CreationError::Other)?;
+                let desc = conv::map_blend_desc1(blend_desc, multisampling, logic_op);
+                unsafe {
+                    device1.CreateBlendState1(&desc, &mut blend as *mut *mut _ as *mut *mut _)
+                }
+            }
+            None => {
+                let desc = conv::map_blend_desc(blend_desc, multisampling);
+                unsafe {
+                    self.raw
+                        .CreateBlendState(&desc, &mut blend as *mut *mut _ as *mut *mut _)
+                }
+            }
         };
 
         if winerror::SUCCEEDED(hr) {
@@ -958,7 +974,7 @@ impl hal::Device<Backend> for Device {
             &desc.input_assembler,
         )?;
         let rasterizer_state = self.create_rasterizer_state(&desc.rasterizer)?;
-        let blend_state = self.create_blend_state(&desc.blender)?;
+        let blend_state = self.create_blend_state(&desc.blender, &desc.multisampling)?;
         let depth_stencil_state = Some(self.create_depth_stencil_state(&desc.depth_stencil)?);
 
         let vs = self.create_vertex_shader(vs)?;
@@ -993,6 +1009,10 @@ impl hal::Device<Backend> for Device {
             input_layout: layout.raw,
             rasterizer_state,
             blend_state,
+            sample_mask: desc
+                .multisampling
+                .as_ref()
+                .map_or(!0, |ms| ms.sample_mask as UINT),
             depth_stencil_state,
             baked_states: desc.baked_states.clone(),
             required_bindings: layout.required_bindings,
@@ -1027,11 +1047,14 @@ impl hal::Device<Backend> for Device {
         _renderpass: &RenderPass,
         attachments: I,
         extent: image::Extent,
-    ) -> Result<Framebuffer, device::OutOfMemory>
+    ) -> Result<Framebuffer, device::FramebufferError>
     where
         I: IntoIterator,
         I::Item: Borrow<ImageView>,
     {
+        if extent.depth > d3d11::D3D11_REQ_TEXTURE2D_ARRAY_AXIS_DIMENSION {
+            return Err(device::FramebufferError::TooManyLayers);
+        }
         Ok(Framebuffer {
             attachments: attachments
                 .into_iter()
@@ -1072,7 +1095,7 @@ impl hal::Device<Backend> for Device {
             bind |= d3d11::D3D11_BIND_SHADER_RESOURCE;
         }
 
-        if usage.intersects(Usage::TRANSFER_DST | Usage::STORAGE) {
+        if usage.intersects(Usage::TRANSFER_DST | Usage::STORAGE | Usage::STORAGE_TEXEL) {
             bind |= d3d11::D3D11_BIND_UNORDERED_ACCESS;
         }
 
@@ -1136,7 +1159,7 @@ impl hal::Device<Backend> for Device {
         );
 
         #[allow(non_snake_case)]
-        let MiscFlags = if buffer.bind
+        let mut MiscFlags = if buffer.bind
             & (d3d11::D3D11_BIND_SHADER_RESOURCE | d3d11::D3D11_BIND_UNORDERED_ACCESS)
             != 0
         {
@@ -1144,6 +1167,9 @@ impl hal::Device<Backend> for Device {
         } else {
             0
         };
+        if buffer.internal.usage.contains(buffer::Usage::INDIRECT) {
+            MiscFlags |= d3d11::D3D11_RESOURCE_MISC_DRAWINDIRECT_ARGS;
+        }
 
         let initial_data = memory
             .host_visible
@@ -1356,13 +1382,90 @@ impl hal::Device<Backend> for Device {
         Ok(())
     }
 
+    unsafe fn set_buffer_name(&self, buffer: &mut Buffer, name: &str) {
+        debug::set_object_name(buffer.internal.raw as *mut _, name);
+        if let Some(disjoint_cb) = buffer.internal.disjoint_cb {
+            debug::set_object_name(disjoint_cb as *mut _, name);
+        }
+    }
+
     unsafe fn create_buffer_view<R: RangeArg<u64>>(
         &self,
-        _buffer: &Buffer,
-        _format: Option<format::Format>,
-        _range: R,
+        buffer: &Buffer,
+        format: Option<format::Format>,
+        range: R,
     ) -> Result<BufferView, buffer::ViewCreationError> {
-        unimplemented!()
+        let (dxgi_format, format_desc) = match format.and_then(conv::map_format) {
+            Some(fmt) => (fmt, format.unwrap().surface_desc()),
+            None => return Err(buffer::ViewCreationError::UnsupportedFormat { format }),
+        };
+
+        let start = *range.start().unwrap_or(&0);
+        let end = *range.end().unwrap_or(&buffer.requirements.size);
+
+        let bytes_per_texel = (format_desc.bits / 8) as u64;
+        assert_eq!(start % bytes_per_texel, 0);
+        let first_element = (start / bytes_per_texel) as u32;
+        let num_elements = ((end - start) / bytes_per_texel) as u32;
+
+        let srv_handle = if buffer.bind & d3d11::D3D11_BIND_SHADER_RESOURCE != 0 {
+            let mut desc = mem::zeroed::<d3d11::D3D11_SHADER_RESOURCE_VIEW_DESC>();
+            desc.Format = dxgi_format;
+            desc.ViewDimension = d3dcommon::D3D11_SRV_DIMENSION_BUFFEREX;
+            *desc.u.BufferEx_mut() = d3d11::D3D11_BUFFEREX_SRV {
+                FirstElement: first_element,
+                NumElements: num_elements,
+                Flags: 0,
+            };
+
+            let mut srv = ptr::null_mut();
+            let hr = self.raw.CreateShaderResourceView(
+                buffer.internal.raw as *mut _,
+                &desc,
+                &mut srv as *mut *mut _ as *mut *mut _,
+            );
+
+            if !winerror::SUCCEEDED(hr) {
+                error!("CreateShaderResourceView failed: 0x{:x}", hr);
+                return Err(buffer::ViewCreationError::UnsupportedFormat { format });
+            }
+
+            Some(ComPtr::from_raw(srv))
+        } else {
+            None
+        };
+
+        let uav_handle = if buffer.bind & d3d11::D3D11_BIND_UNORDERED_ACCESS != 0 {
+            let mut desc = mem::zeroed::<d3d11::D3D11_UNORDERED_ACCESS_VIEW_DESC>();
+            desc.Format = dxgi_format;
+            desc.ViewDimension = d3d11::D3D11_UAV_DIMENSION_BUFFER;
+            *desc.u.Buffer_mut() = d3d11::D3D11_BUFFER_UAV {
+                FirstElement: first_element,
+                NumElements: num_elements,
+                Flags: 0,
+            };
+
+            let mut uav = ptr::null_mut();
+            let hr = self.raw.CreateUnorderedAccessView(
+                buffer.internal.raw as *mut _,
+                &desc,
+                &mut uav as *mut *mut _ as *mut *mut _,
+            );
+
+            if !winerror::SUCCEEDED(hr) {
+                error!("CreateUnorderedAccessView failed: 0x{:x}", hr);
+                return Err(buffer::ViewCreationError::UnsupportedFormat { format });
+            }
+
+            Some(ComPtr::from_raw(uav))
+        } else {
+            None
+        };
+
+        Ok(BufferView {
+            srv_handle,
+            uav_handle,
+        })
     }
 
     unsafe fn create_image(
@@ -1768,14 +1871,23 @@ impl hal::Device<Backend> for Device {
         Ok(())
     }
 
+    unsafe fn set_image_name(&self, image: &mut Image, name: &str) {
+        debug::set_object_name(image.internal.raw as *mut _, name);
+    }
+
     unsafe fn create_image_view(
         &self,
         image: &Image,
         view_kind: image::ViewKind,
         format: format::Format,
-        _swizzle: format::Swizzle,
+        swizzle: format::Swizzle,
         range: image::SubresourceRange,
     ) -> Result<ImageView, image::ViewError> {
+        // D3D11 views can't remap the components.
+        if swizzle != format::Swizzle::NO {
+            return Err(image::ViewError::Unsupported);
+        }
+
         let info = ViewInfo {
             resource: image.internal.raw,
             kind: image.kind,
@@ -1892,7 +2004,7 @@ impl hal::Device<Backend> for Device {
     unsafe fn create_descriptor_set_layout<I, J>(
         &self,
         layout_bindings: I,
-        _immutable_samplers: J,
+        immutable_samplers: J,
     ) -> Result<DescriptorSetLayout, device::OutOfMemory>
     where
         I: IntoIterator,
@@ -1903,6 +2015,8 @@ impl hal::Device<Backend> for Device {
         use pso::DescriptorType::*;
 
         let mut bindings = Vec::new();
+        let mut immutable_sampler_iter = immutable_samplers.into_iter();
+        let mut layout_immutable_samplers = Vec::new();
 
         let mut mapping = Vec::new();
         let mut num_t = 0;
@@ -1914,6 +2028,15 @@ impl hal::Device<Backend> for Device {
         for binding in layout_bindings {
             let binding = binding.borrow();
 
+            if binding.immutable_samplers {
+                // Bindings only hold a single descriptor, skip the remaining samplers.
+                let mut samplers = immutable_sampler_iter.by_ref().take(binding.count);
+                if let Some(sampler) = samplers.next() {
+                    layout_immutable_samplers.push((binding.binding, sampler.borrow().as_raw()));
+                }
+                samplers.for_each(drop);
+            }
+
             let hlsl_reg = match binding.ty {
                 Sampler => {
                     num_s += 1;
@@ -2049,6 +2172,7 @@ impl hal::Device<Backend> for Device {
                 num_c: num_c as _,
                 num_u: num_u as _,
             },
+            immutable_samplers: layout_immutable_samplers,
         })
     }
 
@@ -2132,8 +2256,14 @@ impl hal::Device<Backend> for Device {
                                 Descriptor(image.srv_handle.clone().unwrap().as_raw() as *mut _);
                         }
                     }
-                    pso::Descriptor::UniformTexelBuffer(_buffer_view) => {}
-                    pso::Descriptor::StorageTexelBuffer(_buffer_view) => {}
+                    pso::Descriptor::UniformTexelBuffer(buffer_view) => unsafe {
+                        *handle =
+                            Descriptor(buffer_view.srv_handle.clone().unwrap().as_raw() as *mut _);
+                    },
+                    pso::Descriptor::StorageTexelBuffer(buffer_view) => unsafe {
+                        *handle =
+                            Descriptor(buffer_view.uav_handle.clone().unwrap().as_raw() as *mut _);
+                    },
                 }
             }
         }
@@ -2256,8 +2386,7 @@ impl hal::Device<Backend> for Device {
     }
 
     fn create_semaphore(&self) -> Result<Semaphore, device::OutOfMemory> {
-        // TODO:
-        Ok(Semaphore)
+        Ok(Semaphore::default())
     }
 
     fn create_fence(&self, signalled: bool) -> Result<Fence, device::OutOfMemory> {
@@ -2311,6 +2440,24 @@ impl hal::Device<Backend> for Device {
         Ok(*fence.mutex.lock())
     }
 
+    fn create_event(&self) -> Result<Event, device::OutOfMemory> {
+        Ok(Event(Arc::new(AtomicBool::new(false))))
+    }
+
+    unsafe fn get_event_status(&self, event: &Event) -> Result<bool, device::OomOrDeviceLost> {
+        Ok(event.0.load(Ordering::Acquire))
+    }
+
+    unsafe fn set_event(&self, event: &Event) -> Result<(), device::OutOfMemory> {
+        event.0.store(true, Ordering::Release);
+        Ok(())
+    }
+
+    unsafe fn reset_event(&self, event: &Event) -> Result<(), device::OutOfMemory> {
+        event.0.store(false, Ordering::Release);
+        Ok(())
+    }
+
     unsafe fn free_memory(&self, memory: Memory) {
         for (_range, internal) in memory.local_buffers.borrow_mut().iter() {
             unsafe {
@@ -2363,9 +2510,7 @@ impl hal::Device<Backend> for Device {
 
     unsafe fn destroy_buffer(&self, _buffer: Buffer) {}
 
-    unsafe fn destroy_buffer_view(&self, _view: BufferView) {
-        unimplemented!()
-    }
+    unsafe fn destroy_buffer_view(&self, _view: BufferView) {}
 
     unsafe fn destroy_image(&self, _image: Image) {
         // TODO:
@@ -2394,6 +2539,13 @@ impl hal::Device<Backend> for Device {
         //unimplemented!()
     }
 
+    unsafe fn destroy_event(&self, _event: Event) {}
+
+    unsafe fn calibrate_timestamps(&self) -> Option<query::TimestampCalibration> {
+        // D3D11 doesn't expose the GPU clock outside of queries.
+        None
+    }
+
     unsafe fn create_swapchain(
         &self,
         surface: &mut Surface,
@@ -47,6 +47,7 @@ use std::cell::RefCell;
 use std::mem;
 use std::ops::Range;
 use std::ptr;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 
 use std::os::raw::c_void;
@@ -80,7 +81,6 @@ macro_rules! debug_marker {
 }
 
 mod conv;
-#[cfg(debug_assertions)]
 mod debug;
 mod device;
 mod dxgi;
@@ -149,18 +149,33 @@ impl Instance {
 }
 
 fn get_features(
-    _device: ComPtr<d3d11::ID3D11Device>,
+    device: ComPtr<d3d11::ID3D11Device>,
     _feature_level: d3dcommon::D3D_FEATURE_LEVEL,
 ) -> hal::Features {
     use hal::Features;
 
-    let features =
+    let mut features =
         Features::ROBUST_BUFFER_ACCESS |
         Features::FULL_DRAW_INDEX_U32 |
+        Features::INDEPENDENT_BLENDING |
+        Features::DEPTH_CLAMP |
         Features::FORMAT_BC |
         Features::INSTANCE_RATE |
         Features::SAMPLER_MIP_LOD_BIAS;
 
+    // Only reported by the D3D11.1 runtime.
+    let mut options: d3d11::D3D11_FEATURE_DATA_D3D11_OPTIONS = unsafe { mem::zeroed() };
+    let hr = unsafe {
+        device.CheckFeatureSupport(
+            d3d11::D3D11_FEATURE_D3D11_OPTIONS,
+            &mut options as *mut _ as *mut _,
+            mem::size_of::<d3d11::D3D11_FEATURE_DATA_D3D11_OPTIONS>() as UINT,
+        )
+    };
+    if hr == winerror::S_OK && options.OutputMergerLogicOp != 0 {
+        features |= Features::LOGIC_OP;
+    }
+
     features
 }
 
@@ -348,6 +363,7 @@ impl hal::Instance for Instance {
                     d3d11::D3D11_CS_THREAD_GROUP_MAX_Z,
                 ],
                 max_compute_group_size: [d3d11::D3D11_CS_THREAD_GROUP_MAX_THREADS_PER_GROUP, 1, 1], // TODO
+                max_compute_shared_memory_size: d3d11::D3D11_CS_TGSM_REGISTER_COUNT as usize * 4,
                 max_vertex_input_attribute_offset: 255, // TODO
                 max_vertex_input_attributes: d3d11::D3D11_IA_VERTEX_INPUT_RESOURCE_SLOT_COUNT as _,
                 max_vertex_input_binding_stride:
@@ -365,7 +381,24 @@ impl hal::Instance for Instance {
                 max_color_attachments: 1,                // TODO
                 non_coherent_atom_size: 1,               // TODO
                 max_sampler_anisotropy: 16.,
+                max_draw_indirect_count: 0, // TODO
+                max_framebuffer_layers: d3d11::D3D11_REQ_TEXTURE2D_ARRAY_AXIS_DIMENSION as _,
                 min_vertex_input_binding_stride_alignment: 1,
+                // Descriptor sets are flattened into the shader slots.
+                max_bound_descriptor_sets: !0,
+                max_per_stage_descriptor_samplers: d3d11::D3D11_COMMONSHADER_SAMPLER_SLOT_COUNT
+                    as _,
+                max_per_stage_descriptor_uniform_buffers:
+                    d3d11::D3D11_COMMONSHADER_CONSTANT_BUFFER_API_SLOT_COUNT as _,
+                max_per_stage_descriptor_storage_buffers: d3d11::D3D11_PS_CS_UAV_REGISTER_COUNT
+                    as _,
+                max_per_stage_descriptor_sampled_images:
+                    d3d11::D3D11_COMMONSHADER_INPUT_RESOURCE_SLOT_COUNT as _,
+                max_per_stage_descriptor_storage_images: d3d11::D3D11_PS_CS_UAV_REGISTER_COUNT as _,
+                // Shader model 5 has no wave intrinsics.
+                subgroup_size: 0,
+                subgroup_operations: hal::SubgroupOperations::empty(),
+                subgroup_stages: pso::ShaderStageFlags::empty(),
             };
 
             let features = get_features(device.clone(), feature_level);
@@ -732,8 +765,11 @@ impl hal::Swapchain<Backend> for Swapchain {
     unsafe fn acquire_image(
         &mut self,
         _timeout_ns: u64,
-        _sync: hal::FrameSync<Backend>,
+        sync: hal::FrameSync<Backend>,
     ) -> Result<hal::SwapImageIndex, hal::AcquireError> {
+        if let hal::FrameSync::Semaphore(semaphore) = sync {
+            semaphore.signal();
+        }
         // TODO: non-`_DISCARD` swap effects have more than one buffer, `FLIP`
         //       effects are dxgi 1.3 (w10+?) in which case there is
         //       `GetCurrentBackBufferIndex()` on the swapchain
@@ -771,7 +807,8 @@ impl hal::queue::RawCommandQueue<Backend> for CommandQueue {
         &mut self,
         submission: hal::queue::Submission<Ic, Iw, Is>,
         fence: Option<&Fence>,
-    ) where
+    ) -> Result<(), hal::device::DeviceLost>
+    where
         T: 'a + Borrow<CommandBuffer>,
         Ic: IntoIterator<Item = &'a T>,
         S: 'a + Borrow<Semaphore>,
@@ -779,6 +816,9 @@ impl hal::queue::RawCommandQueue<Backend> for CommandQueue {
         Is: IntoIterator<Item = &'a S>,
     {
         let _scope = debug_scope!(&self.context, "Submit(fence={:?})", fence);
+        for (semaphore, _) in submission.wait_semaphores {
+            semaphore.borrow().wait();
+        }
         for cmd_buf in submission.command_buffers {
             let cmd_buf = cmd_buf.borrow();
 
@@ -803,28 +843,47 @@ impl hal::queue::RawCommandQueue<Backend> for CommandQueue {
                     sync.do_invalidate(&self.context);
                 }
             }
+            for &(ref event, value) in &cmd_buf.events {
+                event.0.store(value, Ordering::Release);
+            }
+        }
+        for semaphore in submission.signal_semaphores {
+            semaphore.borrow().signal();
         }
 
         if let Some(fence) = fence {
             *fence.mutex.lock() = true;
             fence.condvar.notify_all();
         }
+        Ok(())
     }
 
     unsafe fn present<'a, W, Is, S, Iw>(
         &mut self,
         swapchains: Is,
-        _wait_semaphores: Iw,
-    ) -> Result<(), ()>
+        wait_semaphores: Iw,
+    ) -> Result<(), hal::PresentError>
     where
         W: 'a + Borrow<Swapchain>,
         Is: IntoIterator<Item = (&'a W, hal::SwapImageIndex)>,
         S: 'a + Borrow<Semaphore>,
         Iw: IntoIterator<Item = &'a S>,
     {
+        for semaphore in wait_semaphores {
+            semaphore.borrow().wait();
+        }
         for (swapchain, _idx) in swapchains {
-            unsafe {
-                swapchain.borrow().dxgi_swapchain.Present(1, 0);
+            let hr = unsafe { swapchain.borrow().dxgi_swapchain.Present(1, 0) };
+            if winerror::FAILED(hr) {
+                return Err(match hr {
+                    winerror::DXGI_ERROR_DEVICE_REMOVED | winerror::DXGI_ERROR_DEVICE_RESET => {
+                        hal::PresentError::DeviceLost(hal::device::DeviceLost)
+                    }
+                    winerror::E_OUTOFMEMORY => {
+                        hal::PresentError::OutOfMemory(hal::device::OutOfMemory::OutOfHostMemory)
+                    }
+                    _ => panic!("Failed to present frame: 0x{:x}", hr),
+                });
             }
         }
 
@@ -942,6 +1001,8 @@ pub struct CommandBufferState {
     max_bindings: Option<u32>,
     #[derivative(Debug = "ignore")]
     viewports: Vec<d3d11::D3D11_VIEWPORT>,
+    #[derivative(Debug = "ignore")]
+    scissors: Vec<d3d11::D3D11_RECT>,
     vertex_buffers: Vec<*mut d3d11::ID3D11Buffer>,
     vertex_offsets: Vec<u32>,
     vertex_strides: Vec<u32>,
@@ -965,6 +1026,7 @@ impl CommandBufferState {
             required_bindings: None,
             max_bindings: None,
             viewports: Vec::new(),
+            scissors: Vec::new(),
             vertex_buffers: Vec::new(),
             vertex_offsets: Vec::new(),
             vertex_strides: Vec::new(),
@@ -984,6 +1046,7 @@ impl CommandBufferState {
         self.required_bindings = None;
         self.max_bindings = None;
         self.viewports.clear();
+        self.scissors.clear();
         self.vertex_buffers.clear();
         self.vertex_offsets.clear();
         self.vertex_strides.clear();
@@ -1033,13 +1096,31 @@ impl CommandBufferState {
         }
     }
 
-    pub fn set_viewports(&mut self, viewports: &[d3d11::D3D11_VIEWPORT]) {
-        self.viewports.clear();
-        self.viewports.extend(viewports);
+    pub fn set_viewports(&mut self, first: usize, viewports: &[d3d11::D3D11_VIEWPORT]) {
+        // DX only lets us set all the viewports at once
+        let end = first + viewports.len();
+        if self.viewports.len() < end {
+            self.viewports.resize(end, unsafe { mem::zeroed() });
+        }
+        self.viewports[first..end].copy_from_slice(viewports);
 
         self.dirty_flag.insert(DirtyStateFlag::VIEWPORTS);
     }
 
+    pub fn set_scissors(&mut self, first: usize, scissors: &[d3d11::D3D11_RECT]) {
+        let end = first + scissors.len();
+        if self.scissors.len() < end {
+            self.scissors.resize(end, unsafe { mem::zeroed() });
+        }
+        self.scissors[first..end].copy_from_slice(scissors);
+    }
+
+    pub fn bind_scissors(&mut self, context: &ComPtr<d3d11::ID3D11DeviceContext>) {
+        unsafe {
+            context.RSSetScissorRects(self.scissors.len() as _, self.scissors.as_ptr());
+        }
+    }
+
     pub fn bind_viewports(&mut self, context: &ComPtr<d3d11::ID3D11DeviceContext>) {
         if let Some(ref pipeline) = self.graphics_pipeline {
             if let Some(ref viewport) = pipeline.baked_states.viewport {
@@ -1099,19 +1180,19 @@ impl CommandBufferState {
 
     pub fn bind_blend_state(&mut self, context: &ComPtr<d3d11::ID3D11DeviceContext>) {
         if let Some(blend) = self.current_blend {
-            let blend_color = if let Some(ref pipeline) = self.graphics_pipeline {
-                pipeline
+            let (blend_color, sample_mask) = if let Some(ref pipeline) = self.graphics_pipeline {
+                let blend_color = pipeline
                     .baked_states
                     .blend_color
                     .or(self.blend_factor)
-                    .unwrap_or([0f32; 4])
+                    .unwrap_or([0f32; 4]);
+                (blend_color, pipeline.sample_mask)
             } else {
-                self.blend_factor.unwrap_or([0f32; 4])
+                (self.blend_factor.unwrap_or([0f32; 4]), !0)
             };
 
-            // TODO: MSAA
             unsafe {
-                context.OMSetBlendState(blend, &blend_color, !0);
+                context.OMSetBlendState(blend, &blend_color, sample_mask);
             }
 
             self.dirty_flag.remove(DirtyStateFlag::BLEND_STATE);
@@ -1162,24 +1243,39 @@ impl CommandBufferState {
                     context.RSSetScissorRects(1, [conv::map_rect(&scissor)].as_ptr());
                 }
 
-                if let Some((ref state, reference)) = pipeline.depth_stencil_state {
-                    let stencil_ref = if let pso::State::Static(reference) = reference {
-                        reference
-                    } else {
-                        self.stencil_ref.unwrap_or(0)
-                    };
-
-                    context.OMSetDepthStencilState(state.as_raw(), stencil_ref);
-                }
                 self.current_blend = Some(pipeline.blend_state.as_raw());
             }
         };
 
+        self.bind_depth_stencil_state(context);
         self.bind_blend_state(context);
 
         self.dirty_flag.remove(DirtyStateFlag::GRAPHICS_PIPELINE);
     }
 
+    pub fn set_stencil_ref(&mut self, value: pso::StencilValue) {
+        self.stencil_ref = Some(value);
+    }
+
+    pub fn bind_depth_stencil_state(&mut self, context: &ComPtr<d3d11::ID3D11DeviceContext>) {
+        let pipeline = match self.graphics_pipeline {
+            Some(ref pipeline) => pipeline,
+            None => return,
+        };
+
+        if let Some((ref state, reference)) = pipeline.depth_stencil_state {
+            let stencil_ref = if let pso::State::Static(reference) = reference {
+                reference
+            } else {
+                self.stencil_ref.unwrap_or(0)
+            };
+
+            unsafe {
+                context.OMSetDepthStencilState(state.as_raw(), stencil_ref);
+            }
+        }
+    }
+
     pub fn bind(&mut self, context: &ComPtr<d3d11::ID3D11DeviceContext>) {
         if self.dirty_flag.contains(DirtyStateFlag::RENDER_TARGETS) {
             self.bind_render_targets(context);
@@ -1220,6 +1316,9 @@ pub struct CommandBuffer {
     render_pass_cache: Option<RenderPassCache>,
 
     cache: CommandBufferState,
+
+    // host events to set or reset, in order, once the command list is executed
+    events: Vec<(Event, bool)>,
 }
 
 unsafe impl Send for CommandBuffer {}
@@ -1240,6 +1339,7 @@ impl CommandBuffer {
             invalidate_coherent_memory: Vec::new(),
             render_pass_cache: None,
             cache: CommandBufferState::new(),
+            events: Vec::new(),
         }
     }
 
@@ -1407,16 +1507,46 @@ impl CommandBuffer {
         self.invalidate_coherent_memory.clear();
         self.render_pass_cache = None;
         self.cache.clear();
+        self.events.clear();
     }
 }
 
 impl hal::command::RawCommandBuffer<Backend> for CommandBuffer {
     unsafe fn begin(
         &mut self,
-        _flags: command::CommandBufferFlags,
-        _info: command::CommandBufferInheritanceInfo<Backend>,
+        flags: command::CommandBufferFlags,
+        info: command::CommandBufferInheritanceInfo<Backend>,
     ) {
         self.reset();
+
+        // Command lists start from the default state, so secondary command buffers
+        // continuing a render pass need to bind the targets of the inherited subpass.
+        if flags.contains(command::CommandBufferFlags::RENDER_PASS_CONTINUE) {
+            if let (Some(subpass), Some(framebuffer)) = (info.subpass, info.framebuffer) {
+                let desc = &subpass.main_pass.subpasses[subpass.index];
+                let color_views = desc
+                    .color_attachments
+                    .iter()
+                    .map(|&(id, _)| {
+                        framebuffer.attachments[id]
+                            .rtv_handle
+                            .clone()
+                            .unwrap()
+                            .as_raw()
+                    })
+                    .collect::<Vec<_>>();
+                let ds_view = desc.depth_stencil_attachment.map(|(id, _)| {
+                    framebuffer.attachments[id]
+                        .dsv_handle
+                        .clone()
+                        .unwrap()
+                        .as_raw()
+                });
+
+                self.cache.set_render_targets(&color_views, ds_view);
+                self.cache.bind(&self.context);
+            }
+        }
     }
 
     unsafe fn finish(&mut self) {
@@ -1590,6 +1720,24 @@ impl hal::command::RawCommandBuffer<Backend> for CommandBuffer {
         // unimplemented!()
     }
 
+    unsafe fn set_event(&mut self, event: &Event, _: pso::PipelineStage) {
+        // Hazards are tracked by the driver, same as for `pipeline_barrier`.
+        self.events.push((event.clone(), true));
+    }
+
+    unsafe fn reset_event(&mut self, event: &Event, _: pso::PipelineStage) {
+        self.events.push((event.clone(), false));
+    }
+
+    unsafe fn wait_events<'a, I, J>(&mut self, _: I, _: Range<pso::PipelineStage>, _: J)
+    where
+        I: IntoIterator,
+        I::Item: Borrow<Event>,
+        J: IntoIterator,
+        J::Item: Borrow<memory::Barrier<'a, Backend>>,
+    {
+    }
+
     unsafe fn clear_image<T>(
         &mut self,
         image: &Image,
@@ -1667,16 +1815,49 @@ impl hal::command::RawCommandBuffer<Backend> for CommandBuffer {
 
     unsafe fn resolve_image<T>(
         &mut self,
-        _src: &Image,
+        src: &Image,
         _src_layout: image::Layout,
-        _dst: &Image,
+        dst: &Image,
         _dst_layout: image::Layout,
-        _regions: T,
+        regions: T,
     ) where
         T: IntoIterator,
         T::Item: Borrow<command::ImageResolve>,
     {
-        unimplemented!()
+        debug_assert_eq!(src.format, dst.format);
+        debug_assert!(src.kind.num_samples() > 1 && dst.kind.num_samples() == 1);
+
+        // Resolving requires a typed format, only color formats can be resolved.
+        let format = match src.decomposed_format.rtv {
+            Some(format) => format,
+            None => {
+                error!("Unable to resolve an image of format {:?}", src.format);
+                return;
+            }
+        };
+
+        for region in regions {
+            let r = region.borrow();
+            // `ResolveSubresource` always resolves the whole subresource.
+            if r.src_offset != image::Offset::ZERO || r.dst_offset != image::Offset::ZERO {
+                warn!("Image resolve offsets are not supported, resolving the whole subresource");
+            }
+
+            let layers = r
+                .src_subresource
+                .layers
+                .clone()
+                .zip(r.dst_subresource.layers.clone());
+            for (src_layer, dst_layer) in layers {
+                self.context.ResolveSubresource(
+                    dst.internal.raw,
+                    dst.calc_subresource(r.dst_subresource.level as _, dst_layer as _),
+                    src.internal.raw,
+                    src.calc_subresource(r.src_subresource.level as _, src_layer as _),
+                    format,
+                );
+            }
+        }
     }
 
     unsafe fn blit_image<T>(
@@ -1731,7 +1912,7 @@ impl hal::command::RawCommandBuffer<Backend> for CommandBuffer {
         self.cache.bind_vertex_buffers(&self.context);
     }
 
-    unsafe fn set_viewports<T>(&mut self, _first_viewport: u32, viewports: T)
+    unsafe fn set_viewports<T>(&mut self, first_viewport: u32, viewports: T)
     where
         T: IntoIterator,
         T::Item: Borrow<pso::Viewport>,
@@ -1744,12 +1925,12 @@ impl hal::command::RawCommandBuffer<Backend> for CommandBuffer {
             })
             .collect::<Vec<_>>();
 
-        // TODO: DX only lets us set all VPs at once, so cache in slice?
-        self.cache.set_viewports(&viewports);
+        self.cache
+            .set_viewports(first_viewport as usize, &viewports);
         self.cache.bind_viewports(&self.context);
     }
 
-    unsafe fn set_scissors<T>(&mut self, _first_scissor: u32, scissors: T)
+    unsafe fn set_scissors<T>(&mut self, first_scissor: u32, scissors: T)
     where
         T: IntoIterator,
         T::Item: Borrow<pso::Rect>,
@@ -1762,11 +1943,8 @@ impl hal::command::RawCommandBuffer<Backend> for CommandBuffer {
             })
             .collect::<Vec<_>>();
 
-        // TODO: same as for viewports
-        unsafe {
-            self.context
-                .RSSetScissorRects(scissors.len() as _, scissors.as_ptr());
-        }
+        self.cache.set_scissors(first_scissor as usize, &scissors);
+        self.cache.bind_scissors(&self.context);
     }
 
     unsafe fn set_blend_constants(&mut self, color: pso::ColorValue) {
@@ -1775,7 +1953,8 @@ impl hal::command::RawCommandBuffer<Backend> for CommandBuffer {
     }
 
     unsafe fn set_stencil_reference(&mut self, _faces: pso::Face, value: pso::StencilValue) {
-        self.cache.stencil_ref = Some(value);
+        self.cache.set_stencil_ref(value);
+        self.cache.bind_depth_stencil_state(&self.context);
     }
 
     unsafe fn set_stencil_read_mask(&mut self, _faces: pso::Face, value: pso::StencilValue) {
@@ -1960,15 +2139,50 @@ impl hal::command::RawCommandBuffer<Backend> for CommandBuffer {
         }
     }
 
-    unsafe fn dispatch_indirect(&mut self, _buffer: &Buffer, _offset: buffer::Offset) {
-        unimplemented!()
+    unsafe fn dispatch_indirect(&mut self, buffer: &Buffer, offset: buffer::Offset) {
+        if buffer.ty == MemoryHeapFlags::HOST_COHERENT {
+            self.defer_coherent_flush(buffer);
+        }
+
+        unsafe {
+            self.context
+                .DispatchIndirect(buffer.internal.raw, offset as _);
+        }
     }
 
-    unsafe fn fill_buffer<R>(&mut self, _buffer: &Buffer, _range: R, _data: u32)
+    unsafe fn fill_buffer<R>(&mut self, buffer: &Buffer, range: R, data: u32)
     where
         R: RangeArg<buffer::Offset>,
     {
-        unimplemented!()
+        let start = *range.start().unwrap_or(&0);
+        let end = *range.end().unwrap_or(&buffer.requirements.size);
+        assert_eq!(start % 4, 0);
+        assert_eq!(end % 4, 0);
+
+        if buffer.ty == MemoryHeapFlags::HOST_COHERENT {
+            self.defer_coherent_invalidate(buffer);
+        }
+
+        // Deferred contexts offset the source by the box on some drivers, which reads
+        // the same values as long as the pattern starts at the beginning of the buffer.
+        let pattern = vec![data; end as usize / 4];
+        unsafe {
+            self.context.UpdateSubresource(
+                buffer.internal.raw as _,
+                0,
+                &d3d11::D3D11_BOX {
+                    left: start as _,
+                    top: 0,
+                    front: 0,
+                    right: end as _,
+                    bottom: 1,
+                    back: 1,
+                },
+                pattern.as_ptr() as _,
+                0,
+                0,
+            );
+        }
     }
 
     unsafe fn update_buffer(&mut self, _buffer: &Buffer, _offset: buffer::Offset, _data: &[u8]) {
@@ -2090,22 +2304,42 @@ impl hal::command::RawCommandBuffer<Backend> for CommandBuffer {
 
     unsafe fn draw_indirect(
         &mut self,
-        _buffer: &Buffer,
-        _offset: buffer::Offset,
-        _draw_count: DrawCount,
-        _stride: u32,
+        buffer: &Buffer,
+        offset: buffer::Offset,
+        draw_count: DrawCount,
+        stride: u32,
     ) {
-        unimplemented!()
+        if buffer.ty == MemoryHeapFlags::HOST_COHERENT {
+            self.defer_coherent_flush(buffer);
+        }
+
+        for i in 0..draw_count {
+            let offset = offset + (i * stride) as buffer::Offset;
+            unsafe {
+                self.context
+                    .DrawInstancedIndirect(buffer.internal.raw, offset as _);
+            }
+        }
     }
 
     unsafe fn draw_indexed_indirect(
         &mut self,
-        _buffer: &Buffer,
-        _offset: buffer::Offset,
-        _draw_count: DrawCount,
-        _stride: u32,
+        buffer: &Buffer,
+        offset: buffer::Offset,
+        draw_count: DrawCount,
+        stride: u32,
     ) {
-        unimplemented!()
+        if buffer.ty == MemoryHeapFlags::HOST_COHERENT {
+            self.defer_coherent_flush(buffer);
+        }
+
+        for i in 0..draw_count {
+            let offset = offset + (i * stride) as buffer::Offset;
+            unsafe {
+                self.context
+                    .DrawIndexedInstancedIndirect(buffer.internal.raw, offset as _);
+            }
+        }
     }
 
     unsafe fn begin_query(&mut self, _query: query::Query<Backend>, _flags: query::ControlFlags) {
@@ -2155,12 +2389,58 @@ impl hal::command::RawCommandBuffer<Backend> for CommandBuffer {
         unimplemented!()
     }
 
-    unsafe fn execute_commands<'a, T, I>(&mut self, _buffers: I)
+    unsafe fn execute_commands<'a, T, I>(&mut self, buffers: I)
     where
         T: 'a + Borrow<CommandBuffer>,
         I: IntoIterator<Item = &'a T>,
     {
-        unimplemented!()
+        for buffer in buffers {
+            let buffer = buffer.borrow();
+
+            // Coherent memory used by the secondary command buffers is synchronized with ours.
+            for flush in &buffer.flush_coherent_memory {
+                if !self
+                    .flush_coherent_memory
+                    .iter()
+                    .any(|m| m.buffer == flush.buffer)
+                {
+                    self.flush_coherent_memory.push(flush.clone());
+                }
+            }
+            for invalidate in &buffer.invalidate_coherent_memory {
+                if !self
+                    .invalidate_coherent_memory
+                    .iter()
+                    .any(|m| m.buffer == invalidate.buffer)
+                {
+                    self.invalidate_coherent_memory.push(invalidate.clone());
+                }
+            }
+
+            self.context
+                .ExecuteCommandList(buffer.as_raw_list().as_raw(), FALSE);
+        }
+
+        // The context state is reset to the defaults after executing the command lists.
+        self.cache.clear();
+    }
+
+    unsafe fn begin_debug_marker(&mut self, name: &str, _color: u32) {
+        if let Some(annotation) = debug::annotation(&self.context) {
+            debug::begin_event(&annotation, name);
+        }
+    }
+
+    unsafe fn end_debug_marker(&mut self) {
+        if let Some(annotation) = debug::annotation(&self.context) {
+            debug::end_event(&annotation);
+        }
+    }
+
+    unsafe fn insert_debug_marker(&mut self, name: &str, _color: u32) {
+        if let Some(annotation) = debug::annotation(&self.context) {
+            debug::set_marker(&annotation, name);
+        }
     }
 }
 
@@ -2178,14 +2458,14 @@ enum SyncRange {
     Partial(Range<u64>),
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct MemoryFlush {
     host_memory: *mut u8,
     sync_range: SyncRange,
     buffer: *mut d3d11::ID3D11Buffer,
 }
 
-#[derive(Derivative)]
+#[derive(Clone, Derivative)]
 #[derivative(Debug)]
 pub struct MemoryInvalidate {
     #[derivative(Debug = "ignore")]
@@ -2544,8 +2824,26 @@ pub struct Buffer {
 unsafe impl Send for Buffer {}
 unsafe impl Sync for Buffer {}
 
-#[derive(Debug)]
-pub struct BufferView;
+impl Buffer {
+    /// Underlying buffer, null until the buffer is bound to memory.
+    ///
+    /// The buffer stays valid until it's destroyed.
+    pub fn as_raw(&self) -> *mut d3d11::ID3D11Buffer {
+        self.internal.raw
+    }
+}
+
+#[derive(Derivative)]
+#[derivative(Debug)]
+pub struct BufferView {
+    #[derivative(Debug = "ignore")]
+    srv_handle: Option<ComPtr<d3d11::ID3D11ShaderResourceView>>,
+    #[derivative(Debug = "ignore")]
+    uav_handle: Option<ComPtr<d3d11::ID3D11UnorderedAccessView>>,
+}
+
+unsafe impl Send for BufferView {}
+unsafe impl Sync for BufferView {}
 
 #[derive(Derivative)]
 #[derivative(Debug)]
@@ -2589,6 +2887,13 @@ unsafe impl Send for Image {}
 unsafe impl Sync for Image {}
 
 impl Image {
+    /// Underlying texture, null until the image is bound to memory.
+    ///
+    /// The texture stays valid until the image is destroyed.
+    pub fn as_raw(&self) -> *mut d3d11::ID3D11Resource {
+        self.internal.raw
+    }
+
     pub fn calc_subresource(&self, mip_level: UINT, layer: UINT) -> UINT {
         mip_level + (layer * self.mip_levels as UINT)
     }
@@ -2651,6 +2956,13 @@ pub struct Sampler {
 unsafe impl Send for Sampler {}
 unsafe impl Sync for Sampler {}
 
+impl Sampler {
+    /// Underlying sampler state, valid until the sampler is destroyed.
+    pub fn as_raw(&self) -> *mut d3d11::ID3D11SamplerState {
+        self.sampler_handle.as_raw()
+    }
+}
+
 #[derive(Derivative)]
 #[derivative(Debug)]
 pub struct ComputePipeline {
@@ -2687,6 +2999,7 @@ pub struct GraphicsPipeline {
     rasterizer_state: ComPtr<d3d11::ID3D11RasterizerState>,
     #[derivative(Debug = "ignore")]
     blend_state: ComPtr<d3d11::ID3D11BlendState>,
+    sample_mask: UINT,
     #[derivative(Debug = "ignore")]
     depth_stencil_state: Option<(
         ComPtr<d3d11::ID3D11DepthStencilState>,
@@ -2742,8 +3055,13 @@ pub struct DescriptorSetLayout {
     bindings: Vec<PipelineBinding>,
     handle_count: u32,
     register_remap: RegisterRemapping,
+    // Written into every allocated set, there are no static samplers to bake them into.
+    immutable_samplers: Vec<(pso::DescriptorBinding, *mut d3d11::ID3D11SamplerState)>,
 }
 
+unsafe impl Send for DescriptorSetLayout {}
+unsafe impl Sync for DescriptorSetLayout {}
+
 #[derive(Debug)]
 struct CoherentBufferFlushRange {
     device_buffer: *mut d3d11::ID3D11Buffer,
@@ -2949,7 +3267,7 @@ impl hal::DescriptorPool<Backend> for DescriptorPool {
                     *handle = Descriptor(ptr::null_mut());
                 }
 
-                DescriptorSet {
+                let set = DescriptorSet {
                     offset: range.start,
                     len,
                     handles: unsafe { self.handles.as_mut_ptr().offset(range.start as _) },
@@ -2958,7 +3276,16 @@ impl hal::DescriptorPool<Backend> for DescriptorPool {
                         flush_coherent_buffers: RefCell::new(Vec::new()),
                         invalidate_coherent_buffers: RefCell::new(Vec::new()),
                     }),
+                };
+
+                for &(binding, sampler) in &layout.immutable_samplers {
+                    let (_, offset, _) = set.get_handle_offset(binding);
+                    unsafe {
+                        *set.handles.offset(offset as isize) = Descriptor(sampler as *mut _);
+                    }
                 }
+
+                set
             })
             .map_err(|_| pso::AllocationError::OutOfPoolMemory)
     }
@@ -2986,8 +3313,28 @@ pub struct RawFence {
 
 pub type Fence = Arc<RawFence>;
 
-#[derive(Debug)]
-pub struct Semaphore;
+/// Binary semaphore, of which only the state is tracked to validate the usage.
+#[derive(Debug, Default)]
+pub struct Semaphore {
+    signaled: AtomicBool,
+}
+
+impl Semaphore {
+    fn signal(&self) {
+        let signaled = self.signaled.swap(true, Ordering::Relaxed);
+        debug_assert!(!signaled, "Semaphore is already signaled");
+    }
+
+    fn wait(&self) {
+        let signaled = self.signaled.swap(false, Ordering::Relaxed);
+        debug_assert!(signaled, "Semaphore is waited on without a signal");
+    }
+}
+
+/// Host-visible event, flipped by the queue as it executes the submitted
+/// `set_event` and `reset_event` commands in order.
+#[derive(Clone, Debug)]
+pub struct Event(Arc<AtomicBool>);
 #[derive(Debug)]
 pub struct QueryPool;
 
@@ -3028,6 +3375,7 @@ impl hal::Backend for Backend {
 
     type Fence = Fence;
     type Semaphore = Semaphore;
+    type Event = Event;
     type QueryPool = QueryPool;
 }
 
@@ -1,5 +1,6 @@
 use hal::backend::FastHashMap;
 use hal::format::Aspects;
+use hal::queue::QueueFamilyId;
 use hal::range::RangeArg;
 use hal::{buffer, command as com, format, image, memory, pass, pso, query};
 use hal::{
@@ -22,8 +23,8 @@ use device::ViewInfo;
 use root_constants::RootConstant;
 use smallvec::SmallVec;
 use {
-    conv, descriptors_cpu, device, internal, resource as r, validate_line_width, Backend, Device,
-    Shared, MAX_VERTEX_BUFFERS,
+    conv, descriptors_cpu, device, internal, resource as r, validate_line_width, wide_string,
+    Backend, Device, Shared, MAX_VERTEX_BUFFERS,
 };
 
 // Fixed size of the root signature.
@@ -45,6 +46,9 @@ fn get_rect(rect: &pso::Rect) -> d3d12::D3D12_RECT {
     }
 }
 
+// PIX metadata telling the debugger that the event data is a null-terminated UTF-16 string.
+const PIX_EVENT_UNICODE_VERSION: UINT = 0;
+
 fn div(a: u32, b: u32) -> u32 {
     (a + b - 1) / b
 }
@@ -269,6 +273,8 @@ pub struct CommandBuffer {
     raw: native::GraphicsCommandList,
     allocator: native::CommandAllocator,
     shared: Arc<Shared>,
+    // Queue family of the pool, required for ownership transfers.
+    family: QueueFamilyId,
 
     // Cache renderpasses for graphics operations
     pass_cache: Option<RenderPassCache>,
@@ -323,6 +329,8 @@ pub struct CommandBuffer {
     temporary_gpu_heaps: Vec<native::DescriptorHeap>,
     // Resources that need to be alive till the end of the GPU execution.
     retained_resources: Vec<native::Resource>,
+    // Host events to set or reset, in order, once the command list is submitted.
+    pub(crate) events: Vec<(r::Event, bool)>,
 }
 
 unsafe impl Send for CommandBuffer {}
@@ -341,11 +349,13 @@ impl CommandBuffer {
         raw: native::GraphicsCommandList,
         allocator: native::CommandAllocator,
         shared: Arc<Shared>,
+        family: QueueFamilyId,
     ) -> Self {
         CommandBuffer {
             raw,
             allocator,
             shared,
+            family,
             pass_cache: None,
             cur_subpass: !0,
             gr_pipeline: PipelineCache::new(),
@@ -363,6 +373,7 @@ impl CommandBuffer {
             rtv_pools: Vec::new(),
             temporary_gpu_heaps: Vec::new(),
             retained_resources: Vec::new(),
+            events: Vec::new(),
         }
     }
 
@@ -412,6 +423,7 @@ impl CommandBuffer {
                 resource.destroy();
             }
         }
+        self.events.clear();
     }
 
     // Indicates that the pipeline slot has been overriden with an internal pipeline.
@@ -751,6 +763,26 @@ impl CommandBuffer {
         }
     }
 
+    // Resources are transferred between queue families through the common state,
+    // which is accessible from all queue types: the release barrier of the source family
+    // transitions into it and the acquire barrier of the destination family out of it.
+    fn transfer_states(
+        &self,
+        families: &Option<Range<QueueFamilyId>>,
+        states: Range<d3d12::D3D12_RESOURCE_STATES>,
+    ) -> Range<d3d12::D3D12_RESOURCE_STATES> {
+        match *families {
+            Some(ref f) if f.start != f.end => {
+                if self.family == f.start {
+                    states.start..d3d12::D3D12_RESOURCE_STATE_COMMON
+                } else {
+                    d3d12::D3D12_RESOURCE_STATE_COMMON..states.end
+                }
+            }
+            _ => states,
+        }
+    }
+
     fn transition_barrier(
         transition: d3d12::D3D12_RESOURCE_TRANSITION_BARRIER,
     ) -> d3d12::D3D12_RESOURCE_BARRIER {
@@ -1139,14 +1171,14 @@ impl com::RawCommandBuffer<Backend> for CommandBuffer {
                     ref families,
                     ref range,
                 } => {
-                    // TODO: Implement queue family ownership transitions for dx12
-                    if let Some(f) = families {
-                        if f.start.0 != f.end.0 {
-                            unimplemented!("Queue family resource ownership transitions are not implemented for DX12 (attempted transition from queue family {} to {}", f.start.0, f.end.0);
-                        }
-                    }
-                    let state_src = conv::map_buffer_resource_state(states.start);
-                    let state_dst = conv::map_buffer_resource_state(states.end);
+                    let Range {
+                        start: state_src,
+                        end: state_dst,
+                    } = self.transfer_states(
+                        families,
+                        conv::map_buffer_resource_state(states.start)
+                            ..conv::map_buffer_resource_state(states.end),
+                    );
 
                     if state_src == state_dst {
                         continue;
@@ -1168,15 +1200,14 @@ impl com::RawCommandBuffer<Backend> for CommandBuffer {
                     ref families,
                     ref range,
                 } => {
-                    // TODO: Implement queue family ownership transitions for dx12
-                    if let Some(f) = families {
-                        if f.start.0 != f.end.0 {
-                            unimplemented!("Queue family resource ownership transitions are not implemented for DX12 (attempted transition from queue family {} to {}", f.start.0, f.end.0);
-                        }
-                    }
-                    let _ = range; //TODO: use subresource range
-                    let state_src = conv::map_image_resource_state(states.start.0, states.start.1);
-                    let state_dst = conv::map_image_resource_state(states.end.0, states.end.1);
+                    let Range {
+                        start: state_src,
+                        end: state_dst,
+                    } = self.transfer_states(
+                        families,
+                        conv::map_image_resource_state(states.start.0, states.start.1)
+                            ..conv::map_image_resource_state(states.end.0, states.end.1),
+                    );
 
                     if state_src == state_dst {
                         continue;
@@ -1191,19 +1222,35 @@ impl com::RawCommandBuffer<Backend> for CommandBuffer {
                             StateAfter: state_dst,
                         });
 
-                    if *range == target.to_subresource_range(range.aspects) {
+                    let format_aspects = target.surface_type.desc().aspects;
+                    if range.aspects.contains(format_aspects)
+                        && *range == target.to_subresource_range(range.aspects)
+                    {
                         // Only one barrier if it affects the whole image.
                         raw_barriers.push(bar);
                     } else {
-                        // Generate barrier for each layer/level combination.
-                        for level in range.levels.clone() {
-                            for layer in range.layers.clone() {
-                                {
-                                    let transition_barrier = &mut *bar.u.Transition_mut();
-                                    transition_barrier.Subresource =
-                                        target.calc_subresource(level as _, layer as _, 0);
+                        // Depth and stencil are stored in separate planes.
+                        let planes: &[UINT] =
+                            if !format_aspects.contains(Aspects::DEPTH | Aspects::STENCIL) {
+                                &[0]
+                            } else if range.aspects.contains(Aspects::DEPTH | Aspects::STENCIL) {
+                                &[0, 1]
+                            } else if range.aspects.contains(Aspects::STENCIL) {
+                                &[1]
+                            } else {
+                                &[0]
+                            };
+                        // Generate barrier for each plane/layer/level combination.
+                        for &plane in planes {
+                            for level in range.levels.clone() {
+                                for layer in range.layers.clone() {
+                                    {
+                                        let transition_barrier = &mut *bar.u.Transition_mut();
+                                        transition_barrier.Subresource =
+                                            target.calc_subresource(level as _, layer as _, plane);
+                                    }
+                                    raw_barriers.push(bar);
                                 }
-                                raw_barriers.push(bar);
                             }
                         }
                     }
@@ -1248,6 +1295,26 @@ impl com::RawCommandBuffer<Backend> for CommandBuffer {
             .ResourceBarrier(raw_barriers.len() as _, raw_barriers.as_ptr());
     }
 
+    unsafe fn set_event(&mut self, event: &r::Event, _: pso::PipelineStage) {
+        // Command lists execute in order, so the barriers
+        // recorded by `wait_events` are sufficient on the device.
+        self.events.push((event.clone(), true));
+    }
+
+    unsafe fn reset_event(&mut self, event: &r::Event, _: pso::PipelineStage) {
+        self.events.push((event.clone(), false));
+    }
+
+    unsafe fn wait_events<'a, I, J>(&mut self, _: I, stages: Range<pso::PipelineStage>, barriers: J)
+    where
+        I: IntoIterator,
+        I::Item: Borrow<r::Event>,
+        J: IntoIterator,
+        J::Item: Borrow<memory::Barrier<'a, Backend>>,
+    {
+        self.pipeline_barrier(stages, memory::Dependencies::empty(), barriers)
+    }
+
     unsafe fn clear_image<T>(
         &mut self,
         image: &r::Image,
@@ -1323,6 +1390,7 @@ impl com::RawCommandBuffer<Backend> for CommandBuffer {
                             caps: image::ViewCapabilities::empty(),
                             view_kind: image::ViewKind::D2Array,
                             format: attachment.dxgi_format,
+                            component_mapping: device::IDENTITY_MAPPING,
                             range: image::SubresourceRange {
                                 aspects: Aspects::COLOR,
                                 levels: attachment.mip_levels.0..attachment.mip_levels.1,
@@ -1357,6 +1425,7 @@ impl com::RawCommandBuffer<Backend> for CommandBuffer {
                             caps: image::ViewCapabilities::empty(),
                             view_kind: image::ViewKind::D2Array,
                             format: attachment.dxgi_format,
+                            component_mapping: device::IDENTITY_MAPPING,
                             range: image::SubresourceRange {
                                 aspects: if depth.is_some() {
                                     Aspects::DEPTH
@@ -1395,7 +1464,8 @@ impl com::RawCommandBuffer<Backend> for CommandBuffer {
     {
         let src = src.expect_bound();
         let dst = dst.expect_bound();
-        assert_eq!(src.descriptor.Format, dst.descriptor.Format);
+        assert_eq!(src.default_view_format, dst.default_view_format);
+        debug_assert!(src.descriptor.SampleDesc.Count > 1 && dst.descriptor.SampleDesc.Count == 1);
 
         {
             // Insert barrier for `COPY_DEST` to `RESOLVE_DEST` as we only expose
@@ -1412,7 +1482,8 @@ impl com::RawCommandBuffer<Backend> for CommandBuffer {
 
         for region in regions {
             let r = region.borrow();
-            for layer in 0..r.extent.depth as UINT {
+            let num_layers = r.src_subresource.layers.end - r.src_subresource.layers.start;
+            for layer in 0..num_layers as UINT {
                 self.raw.ResolveSubresource(
                     src.resource.as_mut_ptr(),
                     src.calc_subresource(
@@ -1426,7 +1497,7 @@ impl com::RawCommandBuffer<Backend> for CommandBuffer {
                         r.dst_subresource.layers.start as UINT + layer,
                         0,
                     ),
-                    src.descriptor.Format,
+                    src.default_view_format,
                 );
             }
         }
@@ -1481,7 +1552,8 @@ impl com::RawCommandBuffer<Backend> for CommandBuffer {
             kind: src.kind,
             caps: src.view_caps,
             view_kind: image::ViewKind::D2Array, // TODO
-            format: src.descriptor.Format,
+            format: src.default_view_format,
+            component_mapping: device::IDENTITY_MAPPING,
             range: image::SubresourceRange {
                 aspects: format::Aspects::COLOR, // TODO
                 levels: 0..src.descriptor.MipLevels as _,
@@ -1530,7 +1602,7 @@ impl com::RawCommandBuffer<Backend> for CommandBuffer {
                     // Create RTVs of the dst image for the miplevel of the current region
                     for i in 0..num_layers {
                         let mut desc = d3d12::D3D12_RENDER_TARGET_VIEW_DESC {
-                            Format: dst.descriptor.Format,
+                            Format: dst.default_view_format,
                             ViewDimension: d3d12::D3D12_RTV_DIMENSION_TEXTURE2DARRAY,
                             u: mem::zeroed(),
                         };
@@ -1546,7 +1618,7 @@ impl com::RawCommandBuffer<Backend> for CommandBuffer {
                         device.CreateRenderTargetView(dst.resource.as_mut_ptr(), &desc, view);
                     }
 
-                    (dst.descriptor.Format, filter)
+                    (dst.default_view_format, filter)
                 }
                 _ => unimplemented!(),
             };
@@ -1726,11 +1798,11 @@ impl com::RawCommandBuffer<Backend> for CommandBuffer {
             .enumerate();
 
         for (i, viewport) in viewports {
-            if i + first_viewport as usize >= self.viewport_cache.len() {
-                self.viewport_cache.push(viewport);
-            } else {
-                self.viewport_cache[i + first_viewport as usize] = viewport;
+            let index = i + first_viewport as usize;
+            if index >= self.viewport_cache.len() {
+                self.viewport_cache.resize(index + 1, mem::zeroed());
             }
+            self.viewport_cache[index] = viewport;
         }
 
         self.raw
@@ -1748,11 +1820,11 @@ impl com::RawCommandBuffer<Backend> for CommandBuffer {
             .enumerate();
 
         for (i, rect) in rects {
-            if i + first_scissor as usize >= self.scissor_cache.len() {
-                self.scissor_cache.push(rect);
-            } else {
-                self.scissor_cache[i + first_scissor as usize] = rect;
+            let index = i + first_scissor as usize;
+            if index >= self.scissor_cache.len() {
+                self.scissor_cache.resize(index + 1, mem::zeroed());
             }
+            self.scissor_cache[index] = rect;
         }
 
         self.raw
@@ -1927,7 +1999,7 @@ impl com::RawCommandBuffer<Backend> for CommandBuffer {
         if start % 4 != 0 || end % 4 != 0 {
             warn!("Fill buffer bounds have to be multiples of 4");
         }
-        let _rect = d3d12::D3D12_RECT {
+        let rect = d3d12::D3D12_RECT {
             left: start / bytes_per_unit,
             top: 0,
             right: end / bytes_per_unit,
@@ -1944,22 +2016,35 @@ impl com::RawCommandBuffer<Backend> for CommandBuffer {
         });
         self.raw.ResourceBarrier(1, &pre_barrier);
 
-        error!("fill_buffer currently unimplemented");
-        // TODO: GPU handle must be in the current heap. Atm we use a CPU descriptor heap for allocation
-        //       which is not shader visible.
-        /*
+        // The clear needs the view in a shader visible heap, besides the CPU one.
+        let device = self.shared.service_pipes.device.clone();
+        let (uav_heap, _) = device.create_descriptor_heap(
+            1,
+            descriptor::HeapType::CbvSrvUav,
+            descriptor::HeapFlags::SHADER_VISIBLE,
+            0,
+        );
         let handle = buffer.clear_uav.unwrap();
-        unsafe {
-            self.raw.ClearUnorderedAccessViewUint(
-                handle.gpu,
-                handle.cpu,
-                buffer.resource,
-                &[data as UINT; 4],
-                1,
-                &rect as *const _,
-            );
-        }
-        */
+        device.CopyDescriptorsSimple(
+            1,
+            uav_heap.start_cpu_descriptor(),
+            handle,
+            d3d12::D3D12_DESCRIPTOR_HEAP_TYPE_CBV_SRV_UAV,
+        );
+        self.raw.set_descriptor_heaps(&[uav_heap]);
+        self.temporary_gpu_heaps.push(uav_heap);
+        // The heaps and tables of the descriptor sets need to be bound again.
+        self.set_internal_graphics_pipeline();
+        self.comp_pipeline.user_data.dirty_all();
+
+        self.raw.ClearUnorderedAccessViewUint(
+            uav_heap.start_gpu_descriptor(),
+            handle,
+            buffer.resource.as_mut_ptr(),
+            &[data; 4],
+            1,
+            &rect,
+        );
 
         let post_barrier = Self::transition_barrier(d3d12::D3D12_RESOURCE_TRANSITION_BARRIER {
             pResource: buffer.resource.as_mut_ptr(),
@@ -2045,6 +2130,11 @@ impl com::RawCommandBuffer<Backend> for CommandBuffer {
                         src.descriptor.Format, dst.descriptor.Format);
                     return;
                 }
+                r::Place::Reserved => {
+                    error!("Unable to copy from a sparse image with format conversion: {:?} -> {:?}",
+                        src.descriptor.Format, dst.descriptor.Format);
+                    return;
+                }
                 r::Place::Heap { ref raw, offset } => (raw.as_mut_ptr(), offset),
             };
             assert_eq!(
@@ -2389,14 +2479,42 @@ impl com::RawCommandBuffer<Backend> for CommandBuffer {
 
     unsafe fn copy_query_pool_results(
         &mut self,
-        _pool: &r::QueryPool,
-        _queries: Range<query::Id>,
-        _buffer: &r::Buffer,
-        _offset: buffer::Offset,
-        _stride: buffer::Offset,
-        _flags: query::ResultFlags,
+        pool: &r::QueryPool,
+        queries: Range<query::Id>,
+        buffer: &r::Buffer,
+        offset: buffer::Offset,
+        stride: buffer::Offset,
+        flags: query::ResultFlags,
     ) {
-        unimplemented!()
+        // D3D12 always resolves 64-bit values and has no notion of availability,
+        // the results are only resolved once the queries have completed.
+        if !flags.contains(query::ResultFlags::BITS_64) {
+            warn!("32-bit query results are not supported, resolving 64-bit values");
+        }
+        if flags.contains(query::ResultFlags::WITH_AVAILABILITY) {
+            warn!("Query result availability is not supported");
+        }
+
+        let query_ty = match pool.ty {
+            native::query::HeapType::Occlusion => d3d12::D3D12_QUERY_TYPE_OCCLUSION,
+            native::query::HeapType::Timestamp => d3d12::D3D12_QUERY_TYPE_TIMESTAMP,
+            native::query::HeapType::PipelineStatistics => {
+                d3d12::D3D12_QUERY_TYPE_PIPELINE_STATISTICS
+            }
+            _ => unreachable!(),
+        };
+        let buffer = buffer.expect_bound();
+
+        for (i, id) in queries.enumerate() {
+            self.raw.ResolveQueryData(
+                pool.raw.as_mut_ptr(),
+                query_ty,
+                id,
+                1,
+                buffer.resource.as_mut_ptr(),
+                offset + i as buffer::Offset * stride,
+            );
+        }
     }
 
     unsafe fn write_timestamp(&mut self, _: pso::PipelineStage, query: query::Query<Backend>) {
@@ -2407,6 +2525,28 @@ impl com::RawCommandBuffer<Backend> for CommandBuffer {
         );
     }
 
+    unsafe fn begin_conditional_rendering(
+        &mut self,
+        buffer: &r::Buffer,
+        offset: buffer::Offset,
+        inverted: bool,
+    ) {
+        let buffer = buffer.expect_bound();
+        // D3D12 skips the commands if the predicate matches the operation.
+        let op = if inverted {
+            d3d12::D3D12_PREDICATION_OP_NOT_EQUAL_ZERO
+        } else {
+            d3d12::D3D12_PREDICATION_OP_EQUAL_ZERO
+        };
+        self.raw
+            .SetPredication(buffer.resource.as_mut_ptr(), offset, op);
+    }
+
+    unsafe fn end_conditional_rendering(&mut self) {
+        self.raw
+            .SetPredication(ptr::null_mut(), 0, d3d12::D3D12_PREDICATION_OP_EQUAL_ZERO);
+    }
+
     unsafe fn push_graphics_constants(
         &mut self,
         _layout: &r::PipelineLayout,
@@ -2441,4 +2581,26 @@ impl com::RawCommandBuffer<Backend> for CommandBuffer {
             error!("TODO: execute_commands");
         }
     }
+
+    unsafe fn begin_debug_marker(&mut self, name: &str, _color: u32) {
+        let data = wide_string(name);
+        self.raw.BeginEvent(
+            PIX_EVENT_UNICODE_VERSION,
+            data.as_ptr() as *const _,
+            (data.len() * 2) as UINT,
+        );
+    }
+
+    unsafe fn end_debug_marker(&mut self) {
+        self.raw.EndEvent();
+    }
+
+    unsafe fn insert_debug_marker(&mut self, name: &str, _color: u32) {
+        let data = wide_string(name);
+        self.raw.SetMarker(
+            PIX_EVENT_UNICODE_VERSION,
+            data.as_ptr() as *const _,
+            (data.len() * 2) as UINT,
+        );
+    }
 }
@@ -5,11 +5,11 @@ use std::mem;
 
 use winapi::shared::basetsd::UINT8;
 use winapi::shared::dxgiformat::*;
-use winapi::shared::minwindef::{FALSE, INT, TRUE};
+use winapi::shared::minwindef::{FALSE, INT, TRUE, UINT};
 use winapi::um::d3d12::*;
 use winapi::um::d3dcommon::*;
 
-use hal::format::{Format, ImageFeature, SurfaceType};
+use hal::format::{Format, ImageFeature, SurfaceType, Swizzle};
 use hal::pso::DescriptorSetLayoutBinding;
 use hal::{buffer, image, pso, Primitive};
 
@@ -83,6 +83,8 @@ pub fn map_format(format: Format) -> Option<DXGI_FORMAT> {
         D32FloatS8Uint => DXGI_FORMAT_D32_FLOAT_S8X24_UINT,
         Bc1RgbUnorm => DXGI_FORMAT_BC1_UNORM,
         Bc1RgbSrgb => DXGI_FORMAT_BC1_UNORM_SRGB,
+        Bc1RgbaUnorm => DXGI_FORMAT_BC1_UNORM,
+        Bc1RgbaSrgb => DXGI_FORMAT_BC1_UNORM_SRGB,
         Bc2Unorm => DXGI_FORMAT_BC2_UNORM,
         Bc2Srgb => DXGI_FORMAT_BC2_UNORM_SRGB,
         Bc3Unorm => DXGI_FORMAT_BC3_UNORM,
@@ -112,6 +114,88 @@ pub fn map_format_dsv(surface: SurfaceType) -> Option<DXGI_FORMAT> {
     })
 }
 
+/// Typeless format sharing the layout of `format`, which allows the views
+/// to reinterpret the channels.
+pub fn map_format_typeless(format: DXGI_FORMAT) -> DXGI_FORMAT {
+    match format {
+        DXGI_FORMAT_R8_UNORM | DXGI_FORMAT_R8_SNORM | DXGI_FORMAT_R8_UINT | DXGI_FORMAT_R8_SINT => {
+            DXGI_FORMAT_R8_TYPELESS
+        }
+        DXGI_FORMAT_R8G8_UNORM
+        | DXGI_FORMAT_R8G8_SNORM
+        | DXGI_FORMAT_R8G8_UINT
+        | DXGI_FORMAT_R8G8_SINT => DXGI_FORMAT_R8G8_TYPELESS,
+        DXGI_FORMAT_R8G8B8A8_UNORM
+        | DXGI_FORMAT_R8G8B8A8_UNORM_SRGB
+        | DXGI_FORMAT_R8G8B8A8_SNORM
+        | DXGI_FORMAT_R8G8B8A8_UINT
+        | DXGI_FORMAT_R8G8B8A8_SINT => DXGI_FORMAT_R8G8B8A8_TYPELESS,
+        DXGI_FORMAT_B8G8R8A8_UNORM | DXGI_FORMAT_B8G8R8A8_UNORM_SRGB => {
+            DXGI_FORMAT_B8G8R8A8_TYPELESS
+        }
+        DXGI_FORMAT_R10G10B10A2_UNORM | DXGI_FORMAT_R10G10B10A2_UINT => {
+            DXGI_FORMAT_R10G10B10A2_TYPELESS
+        }
+        DXGI_FORMAT_R16_UNORM
+        | DXGI_FORMAT_R16_SNORM
+        | DXGI_FORMAT_R16_UINT
+        | DXGI_FORMAT_R16_SINT
+        | DXGI_FORMAT_R16_FLOAT => DXGI_FORMAT_R16_TYPELESS,
+        DXGI_FORMAT_R16G16_UNORM
+        | DXGI_FORMAT_R16G16_SNORM
+        | DXGI_FORMAT_R16G16_UINT
+        | DXGI_FORMAT_R16G16_SINT
+        | DXGI_FORMAT_R16G16_FLOAT => DXGI_FORMAT_R16G16_TYPELESS,
+        DXGI_FORMAT_R16G16B16A16_UNORM
+        | DXGI_FORMAT_R16G16B16A16_SNORM
+        | DXGI_FORMAT_R16G16B16A16_UINT
+        | DXGI_FORMAT_R16G16B16A16_SINT
+        | DXGI_FORMAT_R16G16B16A16_FLOAT => DXGI_FORMAT_R16G16B16A16_TYPELESS,
+        DXGI_FORMAT_R32_UINT | DXGI_FORMAT_R32_SINT | DXGI_FORMAT_R32_FLOAT => {
+            DXGI_FORMAT_R32_TYPELESS
+        }
+        DXGI_FORMAT_R32G32_UINT | DXGI_FORMAT_R32G32_SINT | DXGI_FORMAT_R32G32_FLOAT => {
+            DXGI_FORMAT_R32G32_TYPELESS
+        }
+        DXGI_FORMAT_R32G32B32_UINT | DXGI_FORMAT_R32G32B32_SINT | DXGI_FORMAT_R32G32B32_FLOAT => {
+            DXGI_FORMAT_R32G32B32_TYPELESS
+        }
+        DXGI_FORMAT_R32G32B32A32_UINT
+        | DXGI_FORMAT_R32G32B32A32_SINT
+        | DXGI_FORMAT_R32G32B32A32_FLOAT => DXGI_FORMAT_R32G32B32A32_TYPELESS,
+        DXGI_FORMAT_BC1_UNORM | DXGI_FORMAT_BC1_UNORM_SRGB => DXGI_FORMAT_BC1_TYPELESS,
+        DXGI_FORMAT_BC2_UNORM | DXGI_FORMAT_BC2_UNORM_SRGB => DXGI_FORMAT_BC2_TYPELESS,
+        DXGI_FORMAT_BC3_UNORM | DXGI_FORMAT_BC3_UNORM_SRGB => DXGI_FORMAT_BC3_TYPELESS,
+        DXGI_FORMAT_BC4_UNORM | DXGI_FORMAT_BC4_SNORM => DXGI_FORMAT_BC4_TYPELESS,
+        DXGI_FORMAT_BC5_UNORM | DXGI_FORMAT_BC5_SNORM => DXGI_FORMAT_BC5_TYPELESS,
+        DXGI_FORMAT_BC6H_UF16 | DXGI_FORMAT_BC6H_SF16 => DXGI_FORMAT_BC6H_TYPELESS,
+        DXGI_FORMAT_BC7_UNORM | DXGI_FORMAT_BC7_UNORM_SRGB => DXGI_FORMAT_BC7_TYPELESS,
+        _ => format,
+    }
+}
+
+pub fn map_swizzle(swizzle: Swizzle) -> UINT {
+    use hal::format::Component::*;
+
+    [swizzle.0, swizzle.1, swizzle.2, swizzle.3]
+        .iter()
+        .enumerate()
+        .fold(
+            D3D12_SHADER_COMPONENT_MAPPING_ALWAYS_SET_BIT_AVOIDING_ZEROMEM_MISTAKES,
+            |mapping, (i, &component)| {
+                let value = match component {
+                    R => D3D12_SHADER_COMPONENT_MAPPING_FROM_MEMORY_COMPONENT_0,
+                    G => D3D12_SHADER_COMPONENT_MAPPING_FROM_MEMORY_COMPONENT_1,
+                    B => D3D12_SHADER_COMPONENT_MAPPING_FROM_MEMORY_COMPONENT_2,
+                    A => D3D12_SHADER_COMPONENT_MAPPING_FROM_MEMORY_COMPONENT_3,
+                    Zero => D3D12_SHADER_COMPONENT_MAPPING_FORCE_VALUE_0,
+                    One => D3D12_SHADER_COMPONENT_MAPPING_FORCE_VALUE_1,
+                };
+                mapping | (value << D3D12_SHADER_COMPONENT_MAPPING_SHIFT as usize * i)
+            },
+        )
+}
+
 pub fn map_topology_type(primitive: Primitive) -> D3D12_PRIMITIVE_TOPOLOGY_TYPE {
     use hal::Primitive::*;
     match primitive {
@@ -231,8 +315,31 @@ fn map_blend_op(operation: pso::BlendOp) -> (D3D12_BLEND_OP, D3D12_BLEND, D3D12_
     }
 }
 
+fn map_logic_op(op: pso::LogicOp) -> D3D12_LOGIC_OP {
+    use hal::pso::LogicOp::*;
+    match op {
+        Clear => D3D12_LOGIC_OP_CLEAR,
+        And => D3D12_LOGIC_OP_AND,
+        AndReverse => D3D12_LOGIC_OP_AND_REVERSE,
+        Copy => D3D12_LOGIC_OP_COPY,
+        AndInverted => D3D12_LOGIC_OP_AND_INVERTED,
+        NoOp => D3D12_LOGIC_OP_NOOP,
+        Xor => D3D12_LOGIC_OP_XOR,
+        Or => D3D12_LOGIC_OP_OR,
+        Nor => D3D12_LOGIC_OP_NOR,
+        Equivalent => D3D12_LOGIC_OP_EQUIV,
+        Invert => D3D12_LOGIC_OP_INVERT,
+        OrReverse => D3D12_LOGIC_OP_OR_REVERSE,
+        CopyInverted => D3D12_LOGIC_OP_COPY_INVERTED,
+        OrInverted => D3D12_LOGIC_OP_OR_INVERTED,
+        Nand => D3D12_LOGIC_OP_NAND,
+        Set => D3D12_LOGIC_OP_SET,
+    }
+}
+
 pub fn map_render_targets(
     color_targets: &[pso::ColorBlendDesc],
+    logic_op: Option<pso::LogicOp>,
 ) -> [D3D12_RENDER_TARGET_BLEND_DESC; 8] {
     let dummy_target = D3D12_RENDER_TARGET_BLEND_DESC {
         BlendEnable: FALSE,
@@ -264,6 +371,16 @@ pub fn map_render_targets(
         }
     }
 
+    // Logic ops replace blending and are only read from the first target,
+    // which requires `IndependentBlendEnable` to be disabled.
+    if let Some(op) = logic_op {
+        for target in targets.iter_mut() {
+            target.BlendEnable = FALSE;
+        }
+        targets[0].LogicOpEnable = TRUE;
+        targets[0].LogicOp = map_logic_op(op);
+    }
+
     targets
 }
 
@@ -423,6 +540,9 @@ pub fn map_buffer_resource_state(access: buffer::Access) -> D3D12_RESOURCE_STATE
     if access.contains(Access::INDIRECT_COMMAND_READ) {
         state |= D3D12_RESOURCE_STATE_INDIRECT_ARGUMENT;
     }
+    if access.contains(Access::CONDITIONAL_RENDERING_READ) {
+        state |= D3D12_RESOURCE_STATE_PREDICATION;
+    }
     if access.contains(Access::SHADER_READ) {
         // SHADER_READ only allows SRV access
         state |= D3D12_RESOURCE_STATE_PIXEL_SHADER_RESOURCE
@@ -1,13 +1,15 @@
 use std::borrow::Borrow;
 use std::collections::{BTreeMap, VecDeque};
 use std::ops::Range;
-use std::{ffi, mem, ptr, slice};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::{ffi, mem, ptr, slice, thread, time};
 
 use spirv_cross::{hlsl, spirv, ErrorCode as SpirvErrorCode};
 
 use winapi::shared::minwindef::{FALSE, TRUE, UINT};
 use winapi::shared::{dxgi, dxgi1_2, dxgi1_4, dxgiformat, dxgitype, winerror};
-use winapi::um::{d3d12, d3dcompiler, synchapi, winbase, winnt};
+use winapi::um::{d3d12, d3dcompiler, profileapi, synchapi, winbase, winnt};
 use winapi::Interface;
 
 use hal::format::{Aspects, Format};
@@ -26,13 +28,16 @@ use pool::{CommandPoolAllocator, RawCommandPool};
 use range_alloc::RangeAllocator;
 use root_constants::RootConstant;
 use {
-    conv, descriptors_cpu, native, resource as r, root_constants, window as w, Backend as B,
-    Device, MemoryGroup, MAX_VERTEX_BUFFERS, NUM_HEAP_PROPERTIES, QUEUE_FAMILIES,
+    conv, descriptors_cpu, native, resource as r, root_constants, wide_string, window as w,
+    Backend as B, Device, MemoryGroup, MAX_VERTEX_BUFFERS, NUM_HEAP_PROPERTIES, QUEUE_FAMILIES,
 };
 
 // Register space used for root constants.
 const ROOT_CONSTANT_SPACE: u32 = 0;
 
+// Default component mapping of the shader resource views.
+pub(crate) const IDENTITY_MAPPING: UINT = 0x1688; // D3D12_DEFAULT_SHADER_4_COMPONENT_MAPPING
+
 const MEM_TYPE_MASK: u64 = 0x7;
 const MEM_TYPE_SHIFT: u64 = 3;
 
@@ -67,6 +72,7 @@ pub(crate) struct ViewInfo {
     pub(crate) caps: image::ViewCapabilities,
     pub(crate) view_kind: image::ViewKind,
     pub(crate) format: dxgiformat::DXGI_FORMAT,
+    pub(crate) component_mapping: UINT,
     pub(crate) range: image::SubresourceRange,
 }
 
@@ -740,7 +746,7 @@ impl Device {
         let mut desc = d3d12::D3D12_SHADER_RESOURCE_VIEW_DESC {
             Format: info.format,
             ViewDimension: 0,
-            Shader4ComponentMapping: 0x1688, // TODO: map swizzle
+            Shader4ComponentMapping: info.component_mapping,
             u: unsafe { mem::zeroed() },
         };
 
@@ -958,6 +964,178 @@ impl Device {
         });
         handle
     }
+
+    fn buffer_desc(buffer_unbound: &r::BufferUnbound) -> d3d12::D3D12_RESOURCE_DESC {
+        d3d12::D3D12_RESOURCE_DESC {
+            Dimension: d3d12::D3D12_RESOURCE_DIMENSION_BUFFER,
+            Alignment: 0,
+            Width: buffer_unbound.requirements.size,
+            Height: 1,
+            DepthOrArraySize: 1,
+            MipLevels: 1,
+            Format: dxgiformat::DXGI_FORMAT_UNKNOWN,
+            SampleDesc: dxgitype::DXGI_SAMPLE_DESC {
+                Count: 1,
+                Quality: 0,
+            },
+            Layout: d3d12::D3D12_TEXTURE_LAYOUT_ROW_MAJOR,
+            Flags: conv::map_buffer_flags(buffer_unbound.usage),
+        }
+    }
+
+    /// Create the internal views of a buffer backed by the given resource.
+    unsafe fn bind_buffer_resource(
+        &self,
+        buffer_unbound: &r::BufferUnbound,
+        resource: native::Resource,
+    ) -> r::BufferBound {
+        let clear_uav = if buffer_unbound.usage.contains(buffer::Usage::TRANSFER_DST) {
+            let handle = self.srv_uav_pool.lock().unwrap().alloc_handle();
+            let mut view_desc = d3d12::D3D12_UNORDERED_ACCESS_VIEW_DESC {
+                Format: dxgiformat::DXGI_FORMAT_R32_TYPELESS,
+                ViewDimension: d3d12::D3D12_UAV_DIMENSION_BUFFER,
+                u: mem::zeroed(),
+            };
+
+            *view_desc.u.Buffer_mut() = d3d12::D3D12_BUFFER_UAV {
+                FirstElement: 0,
+                NumElements: (buffer_unbound.requirements.size / 4) as _,
+                StructureByteStride: 0,
+                CounterOffsetInBytes: 0,
+                Flags: d3d12::D3D12_BUFFER_UAV_FLAG_RAW,
+            };
+
+            self.raw.CreateUnorderedAccessView(
+                resource.as_mut_ptr(),
+                ptr::null_mut(),
+                &view_desc,
+                handle,
+            );
+            Some(handle)
+        } else {
+            None
+        };
+
+        r::BufferBound {
+            resource,
+            requirements: buffer_unbound.requirements,
+            clear_uav,
+        }
+    }
+
+    /// Create the internal views of an image backed by the given resource.
+    unsafe fn bind_image_resource(
+        &self,
+        image_unbound: &r::ImageUnbound,
+        resource: native::Resource,
+        place: r::Place,
+    ) -> r::ImageBound {
+        use self::image::Usage;
+
+        let num_layers = image_unbound.kind.num_layers();
+        let info = ViewInfo {
+            resource,
+            kind: image_unbound.kind,
+            caps: image::ViewCapabilities::empty(),
+            view_kind: match image_unbound.kind {
+                image::Kind::D1(..) => image::ViewKind::D1Array,
+                image::Kind::D2(..) => image::ViewKind::D2Array,
+                image::Kind::D3(..) => image::ViewKind::D3,
+            },
+            format: image_unbound.default_view_format,
+            component_mapping: IDENTITY_MAPPING,
+            range: image::SubresourceRange {
+                aspects: Aspects::empty(),
+                levels: 0..0,
+                layers: 0..0,
+            },
+        };
+
+        //TODO: the clear_Xv is incomplete. We should support clearing images created without XXX_ATTACHMENT usage.
+        // for this, we need to check the format and force the `RENDER_TARGET` flag behind the user's back
+        // if the format supports being rendered into, allowing us to create clear_Xv
+        let format_properties = self.format_properties.get(image_unbound.format as usize);
+        let props = match image_unbound.tiling {
+            image::Tiling::Optimal => format_properties.optimal_tiling,
+            image::Tiling::Linear => format_properties.linear_tiling,
+        };
+        let can_clear_color = image_unbound
+            .usage
+            .intersects(Usage::TRANSFER_DST | Usage::COLOR_ATTACHMENT)
+            && props.contains(format::ImageFeature::COLOR_ATTACHMENT);
+        let can_clear_depth = image_unbound
+            .usage
+            .intersects(Usage::TRANSFER_DST | Usage::DEPTH_STENCIL_ATTACHMENT)
+            && props.contains(format::ImageFeature::DEPTH_STENCIL_ATTACHMENT);
+        let aspects = image_unbound.format.surface_desc().aspects;
+
+        r::ImageBound {
+            resource,
+            place,
+            surface_type: image_unbound.format.base_format().0,
+            kind: image_unbound.kind,
+            usage: image_unbound.usage,
+            view_caps: image_unbound.view_caps,
+            descriptor: image_unbound.desc,
+            default_view_format: image_unbound.default_view_format,
+            bytes_per_block: image_unbound.bytes_per_block,
+            block_dim: image_unbound.block_dim,
+            clear_cv: if aspects.contains(Aspects::COLOR) && can_clear_color {
+                (0..num_layers)
+                    .map(|layer| {
+                        self.view_image_as_render_target(ViewInfo {
+                            range: image::SubresourceRange {
+                                aspects: Aspects::COLOR,
+                                levels: 0..1, //TODO?
+                                layers: layer..layer + 1,
+                            },
+                            ..info.clone()
+                        })
+                        .unwrap()
+                    })
+                    .collect()
+            } else {
+                Vec::new()
+            },
+            clear_dv: if aspects.contains(Aspects::DEPTH) && can_clear_depth {
+                (0..num_layers)
+                    .map(|layer| {
+                        self.view_image_as_depth_stencil(ViewInfo {
+                            format: image_unbound.dsv_format,
+                            range: image::SubresourceRange {
+                                aspects: Aspects::DEPTH,
+                                levels: 0..1, //TODO?
+                                layers: layer..layer + 1,
+                            },
+                            ..info.clone()
+                        })
+                        .unwrap()
+                    })
+                    .collect()
+            } else {
+                Vec::new()
+            },
+            clear_sv: if aspects.contains(Aspects::STENCIL) && can_clear_depth {
+                (0..num_layers)
+                    .map(|layer| {
+                        self.view_image_as_depth_stencil(ViewInfo {
+                            format: image_unbound.dsv_format,
+                            range: image::SubresourceRange {
+                                aspects: Aspects::STENCIL,
+                                levels: 0..1, //TODO?
+                                layers: layer..layer + 1,
+                            },
+                            ..info.clone()
+                        })
+                        .unwrap()
+                    })
+                    .collect()
+            } else {
+                Vec::new()
+            },
+            requirements: image_unbound.requirements,
+        }
+    }
 }
 
 impl d::Device<B> for Device {
@@ -1084,6 +1262,7 @@ impl d::Device<B> for Device {
             device: self.raw,
             list_type,
             shared: self.shared.clone(),
+            family,
         })
     }
 
@@ -1645,10 +1824,16 @@ impl d::Device<B> for Device {
                         FALSE
                     }
                 }),
-                IndependentBlendEnable: TRUE,
-                RenderTarget: conv::map_render_targets(&desc.blender.targets),
+                IndependentBlendEnable: desc.blender.logic_op.is_none() as _,
+                RenderTarget: conv::map_render_targets(
+                    &desc.blender.targets,
+                    desc.blender.logic_op,
+                ),
             },
-            SampleMask: UINT::max_value(),
+            SampleMask: desc
+                .multisampling
+                .as_ref()
+                .map_or(UINT::max_value(), |ms| ms.sample_mask as UINT),
             RasterizerState: conv::map_rasterizer(&desc.rasterizer),
             DepthStencilState: conv::map_depth_stencil(&desc.depth_stencil),
             InputLayout: d3d12::D3D12_INPUT_LAYOUT_DESC {
@@ -1659,7 +1844,13 @@ impl d::Device<B> for Device {
                 },
                 NumElements: input_element_descs.len() as u32,
             },
-            IBStripCutValue: d3d12::D3D12_INDEX_BUFFER_STRIP_CUT_VALUE_DISABLED, // TODO
+            IBStripCutValue: match desc.input_assembler.primitive_restart {
+                pso::PrimitiveRestart::Disabled => {
+                    d3d12::D3D12_INDEX_BUFFER_STRIP_CUT_VALUE_DISABLED
+                }
+                pso::PrimitiveRestart::U16 => d3d12::D3D12_INDEX_BUFFER_STRIP_CUT_VALUE_0xFFFF,
+                pso::PrimitiveRestart::U32 => d3d12::D3D12_INDEX_BUFFER_STRIP_CUT_VALUE_0xFFFFFFFF,
+            },
             PrimitiveTopologyType: conv::map_topology_type(desc.input_assembler.primitive),
             NumRenderTargets: num_rtvs,
             RTVFormats: rtvs,
@@ -1780,11 +1971,14 @@ impl d::Device<B> for Device {
         _renderpass: &r::RenderPass,
         attachments: I,
         extent: image::Extent,
-    ) -> Result<r::Framebuffer, d::OutOfMemory>
+    ) -> Result<r::Framebuffer, d::FramebufferError>
     where
         I: IntoIterator,
         I::Item: Borrow<r::ImageView>,
     {
+        if extent.depth > d3d12::D3D12_REQ_TEXTURE2D_ARRAY_AXIS_DIMENSION {
+            return Err(d::FramebufferError::TooManyLayers);
+        }
         Ok(r::Framebuffer {
             attachments: attachments.into_iter().map(|att| *att.borrow()).collect(),
             layers: extent.depth as _,
@@ -1832,6 +2026,36 @@ impl d::Device<B> for Device {
         }))
     }
 
+    unsafe fn create_sparse_buffer(
+        &self,
+        size: u64,
+        usage: buffer::Usage,
+        _sparse: memory::SparseFlags,
+    ) -> Result<r::Buffer, buffer::CreationError> {
+        let tile_size = d3d12::D3D12_TILED_RESOURCE_TILE_SIZE_IN_BYTES as u64;
+        let mut buffer_unbound = *d::Device::create_buffer(self, size, usage)?.expect_unbound();
+        // Reserved resources are mapped in tiles.
+        buffer_unbound.requirements.size =
+            (buffer_unbound.requirements.size + tile_size - 1) / tile_size * tile_size;
+        buffer_unbound.requirements.alignment = tile_size;
+
+        let mut resource = native::Resource::null();
+        assert_eq!(
+            winerror::S_OK,
+            self.raw.clone().CreateReservedResource(
+                &Self::buffer_desc(&buffer_unbound),
+                d3d12::D3D12_RESOURCE_STATE_COMMON,
+                ptr::null(),
+                &d3d12::ID3D12Resource::uuidof(),
+                resource.mut_void(),
+            )
+        );
+
+        Ok(r::Buffer::Bound(
+            self.bind_buffer_resource(&buffer_unbound, resource),
+        ))
+    }
+
     unsafe fn get_buffer_requirements(&self, buffer: &r::Buffer) -> Requirements {
         match buffer {
             r::Buffer::Unbound(b) => b.requirements,
@@ -1858,21 +2082,7 @@ impl d::Device<B> for Device {
         }
 
         let mut resource = native::Resource::null();
-        let desc = d3d12::D3D12_RESOURCE_DESC {
-            Dimension: d3d12::D3D12_RESOURCE_DIMENSION_BUFFER,
-            Alignment: 0,
-            Width: buffer_unbound.requirements.size,
-            Height: 1,
-            DepthOrArraySize: 1,
-            MipLevels: 1,
-            Format: dxgiformat::DXGI_FORMAT_UNKNOWN,
-            SampleDesc: dxgitype::DXGI_SAMPLE_DESC {
-                Count: 1,
-                Quality: 0,
-            },
-            Layout: d3d12::D3D12_TEXTURE_LAYOUT_ROW_MAJOR,
-            Flags: conv::map_buffer_flags(buffer_unbound.usage),
-        };
+        let desc = Self::buffer_desc(&buffer_unbound);
 
         assert_eq!(
             winerror::S_OK,
@@ -1887,42 +2097,22 @@ impl d::Device<B> for Device {
             )
         );
 
-        let clear_uav = if buffer_unbound.usage.contains(buffer::Usage::TRANSFER_DST) {
-            let handle = self.srv_uav_pool.lock().unwrap().alloc_handle();
-            let mut view_desc = d3d12::D3D12_UNORDERED_ACCESS_VIEW_DESC {
-                Format: dxgiformat::DXGI_FORMAT_R32_TYPELESS,
-                ViewDimension: d3d12::D3D12_UAV_DIMENSION_BUFFER,
-                u: mem::zeroed(),
-            };
-
-            *view_desc.u.Buffer_mut() = d3d12::D3D12_BUFFER_UAV {
-                FirstElement: 0,
-                NumElements: (buffer_unbound.requirements.size / 4) as _,
-                StructureByteStride: 0,
-                CounterOffsetInBytes: 0,
-                Flags: d3d12::D3D12_BUFFER_UAV_FLAG_RAW,
-            };
-
-            self.raw.CreateUnorderedAccessView(
-                resource.as_mut_ptr(),
-                ptr::null_mut(),
-                &view_desc,
-                handle,
-            );
-            Some(handle)
-        } else {
-            None
-        };
-
-        *buffer = r::Buffer::Bound(r::BufferBound {
-            resource,
-            requirements: buffer_unbound.requirements,
-            clear_uav,
-        });
+        *buffer = r::Buffer::Bound(self.bind_buffer_resource(&buffer_unbound, resource));
 
         Ok(())
     }
 
+    unsafe fn set_buffer_name(&self, buffer: &mut r::Buffer, name: &str) {
+        match *buffer {
+            r::Buffer::Bound(ref buffer) => {
+                buffer.resource.SetName(wide_string(name).as_ptr());
+            }
+            r::Buffer::Unbound(_) => {
+                warn!("Unable to name the unbound buffer {:?}", name);
+            }
+        }
+    }
+
     unsafe fn create_buffer_view<R: RangeArg<u64>>(
         &self,
         buffer: &r::Buffer,
@@ -2036,6 +2226,11 @@ impl d::Device<B> for Device {
             ),
         };
 
+        let default_format = match conv::map_format(format) {
+            Some(format) => format,
+            None => return Err(image::CreationError::Format(format)),
+        };
+
         let desc = d3d12::D3D12_RESOURCE_DESC {
             Dimension: match kind {
                 image::Kind::D1(..) => d3d12::D3D12_RESOURCE_DIMENSION_TEXTURE1D,
@@ -2051,9 +2246,11 @@ impl d::Device<B> for Device {
                 kind.num_layers() as _
             },
             MipLevels: mip_levels as _,
-            Format: match conv::map_format(format) {
-                Some(format) => format,
-                None => return Err(image::CreationError::Format(format)),
+            // Views can only reinterpret the format of typeless resources.
+            Format: if view_caps.contains(image::ViewCapabilities::MUTABLE_FORMAT) {
+                conv::map_format_typeless(default_format)
+            } else {
+                default_format
             },
             SampleDesc: dxgitype::DXGI_SAMPLE_DESC {
                 Count: kind.num_samples() as _,
@@ -2079,7 +2276,8 @@ impl d::Device<B> for Device {
         };
 
         Ok(r::Image::Unbound(r::ImageUnbound {
-            dsv_format: conv::map_format_dsv(base_format.0).unwrap_or(desc.Format),
+            dsv_format: conv::map_format_dsv(base_format.0).unwrap_or(default_format),
+            default_view_format: default_format,
             desc,
             requirements: memory::Requirements {
                 size: alloc_info.SizeInBytes,
@@ -2097,6 +2295,87 @@ impl d::Device<B> for Device {
         }))
     }
 
+    unsafe fn create_sparse_image(
+        &self,
+        kind: image::Kind,
+        mip_levels: image::Level,
+        format: format::Format,
+        usage: image::Usage,
+        view_caps: image::ViewCapabilities,
+        _sparse: memory::SparseFlags,
+    ) -> Result<r::Image, image::CreationError> {
+        let image = d::Device::create_image(
+            self,
+            kind,
+            mip_levels,
+            format,
+            image::Tiling::Optimal,
+            usage,
+            view_caps,
+        )?;
+        let mut image_unbound = *image.expect_unbound();
+        // Reserved textures require the standard swizzle, mapped in tiles.
+        image_unbound.desc.Layout = d3d12::D3D12_TEXTURE_LAYOUT_64KB_UNDEFINED_SWIZZLE;
+
+        let mut resource = native::Resource::null();
+        assert_eq!(
+            winerror::S_OK,
+            self.raw.clone().CreateReservedResource(
+                &image_unbound.desc,
+                d3d12::D3D12_RESOURCE_STATE_COMMON,
+                ptr::null(),
+                &d3d12::ID3D12Resource::uuidof(),
+                resource.mut_void(),
+            )
+        );
+
+        let mut image_bound =
+            self.bind_image_resource(&image_unbound, resource, r::Place::Reserved);
+        let tile_size = d3d12::D3D12_TILED_RESOURCE_TILE_SIZE_IN_BYTES as u64;
+        image_bound.requirements.size = image_bound.tiling().num_tiles as u64 * tile_size;
+        image_bound.requirements.alignment = tile_size;
+
+        Ok(r::Image::Bound(image_bound))
+    }
+
+    unsafe fn get_image_sparse_requirements(
+        &self,
+        image: &r::Image,
+    ) -> Vec<image::SparseRequirements> {
+        let image = match *image {
+            r::Image::Bound(ref image) => image,
+            r::Image::Unbound(_) => return Vec::new(),
+        };
+        match image.place {
+            r::Place::Reserved => {}
+            _ => return Vec::new(),
+        }
+
+        let tiling = image.tiling();
+        let tile_size = d3d12::D3D12_TILED_RESOURCE_TILE_SIZE_IN_BYTES as u64;
+        let num_layers = image.kind.num_layers() as u64;
+        // The packed mips of each layer follow the tiles of its standard mips,
+        // the opaque memory of the image is the whole resource.
+        let layer_size = tiling.num_tiles as u64 / num_layers * tile_size;
+
+        vec![image::SparseRequirements {
+            aspects: image.surface_type.desc().aspects,
+            granularity: image::Extent {
+                width: tiling.tile_shape.WidthInTexels,
+                height: tiling.tile_shape.HeightInTexels,
+                depth: tiling.tile_shape.DepthInTexels,
+            },
+            mip_tail_first_level: tiling.packed_mips.NumStandardMips as _,
+            mip_tail_size: tiling.packed_mips.NumTilesForPackedMips as u64 * tile_size,
+            mip_tail_offset: tiling.packed_mips.StartTileIndexInOverallResource as u64 * tile_size,
+            mip_tail_stride: if num_layers > 1 {
+                Some(layer_size)
+            } else {
+                None
+            },
+        }]
+    }
+
     unsafe fn get_image_requirements(&self, image: &r::Image) -> Requirements {
         match image {
             r::Image::Bound(i) => i.requirements,
@@ -2146,8 +2425,6 @@ impl d::Device<B> for Device {
         offset: u64,
         image: &mut r::Image,
     ) -> Result<(), d::BindError> {
-        use self::image::Usage;
-
         let image_unbound = *image.expect_unbound();
         if image_unbound.requirements.type_mask & (1 << memory.type_id) == 0 {
             error!(
@@ -2161,7 +2438,6 @@ impl d::Device<B> for Device {
         }
 
         let mut resource = native::Resource::null();
-        let num_layers = image_unbound.kind.num_layers();
 
         assert_eq!(
             winerror::S_OK,
@@ -2176,131 +2452,55 @@ impl d::Device<B> for Device {
             )
         );
 
-        let info = ViewInfo {
-            resource,
-            kind: image_unbound.kind,
-            caps: image::ViewCapabilities::empty(),
-            view_kind: match image_unbound.kind {
-                image::Kind::D1(..) => image::ViewKind::D1Array,
-                image::Kind::D2(..) => image::ViewKind::D2Array,
-                image::Kind::D3(..) => image::ViewKind::D3,
-            },
-            format: image_unbound.desc.Format,
-            range: image::SubresourceRange {
-                aspects: Aspects::empty(),
-                levels: 0..0,
-                layers: 0..0,
-            },
+        let place = r::Place::Heap {
+            raw: memory.heap.clone(),
+            offset,
         };
-
-        //TODO: the clear_Xv is incomplete. We should support clearing images created without XXX_ATTACHMENT usage.
-        // for this, we need to check the format and force the `RENDER_TARGET` flag behind the user's back
-        // if the format supports being rendered into, allowing us to create clear_Xv
-        let format_properties = self.format_properties.get(image_unbound.format as usize);
-        let props = match image_unbound.tiling {
-            image::Tiling::Optimal => format_properties.optimal_tiling,
-            image::Tiling::Linear => format_properties.linear_tiling,
-        };
-        let can_clear_color = image_unbound
-            .usage
-            .intersects(Usage::TRANSFER_DST | Usage::COLOR_ATTACHMENT)
-            && props.contains(format::ImageFeature::COLOR_ATTACHMENT);
-        let can_clear_depth = image_unbound
-            .usage
-            .intersects(Usage::TRANSFER_DST | Usage::DEPTH_STENCIL_ATTACHMENT)
-            && props.contains(format::ImageFeature::DEPTH_STENCIL_ATTACHMENT);
-        let aspects = image_unbound.format.surface_desc().aspects;
-
-        *image = r::Image::Bound(r::ImageBound {
-            resource: resource,
-            place: r::Place::Heap {
-                raw: memory.heap.clone(),
-                offset,
-            },
-            surface_type: image_unbound.format.base_format().0,
-            kind: image_unbound.kind,
-            usage: image_unbound.usage,
-            view_caps: image_unbound.view_caps,
-            descriptor: image_unbound.desc,
-            bytes_per_block: image_unbound.bytes_per_block,
-            block_dim: image_unbound.block_dim,
-            clear_cv: if aspects.contains(Aspects::COLOR) && can_clear_color {
-                (0..num_layers)
-                    .map(|layer| {
-                        self.view_image_as_render_target(ViewInfo {
-                            range: image::SubresourceRange {
-                                aspects: Aspects::COLOR,
-                                levels: 0..1, //TODO?
-                                layers: layer..layer + 1,
-                            },
-                            ..info.clone()
-                        })
-                        .unwrap()
-                    })
-                    .collect()
-            } else {
-                Vec::new()
-            },
-            clear_dv: if aspects.contains(Aspects::DEPTH) && can_clear_depth {
-                (0..num_layers)
-                    .map(|layer| {
-                        self.view_image_as_depth_stencil(ViewInfo {
-                            format: image_unbound.dsv_format,
-                            range: image::SubresourceRange {
-                                aspects: Aspects::DEPTH,
-                                levels: 0..1, //TODO?
-                                layers: layer..layer + 1,
-                            },
-                            ..info.clone()
-                        })
-                        .unwrap()
-                    })
-                    .collect()
-            } else {
-                Vec::new()
-            },
-            clear_sv: if aspects.contains(Aspects::STENCIL) && can_clear_depth {
-                (0..num_layers)
-                    .map(|layer| {
-                        self.view_image_as_depth_stencil(ViewInfo {
-                            format: image_unbound.dsv_format,
-                            range: image::SubresourceRange {
-                                aspects: Aspects::STENCIL,
-                                levels: 0..1, //TODO?
-                                layers: layer..layer + 1,
-                            },
-                            ..info.clone()
-                        })
-                        .unwrap()
-                    })
-                    .collect()
-            } else {
-                Vec::new()
-            },
-            requirements: image_unbound.requirements,
-        });
+        *image = r::Image::Bound(self.bind_image_resource(&image_unbound, resource, place));
 
         Ok(())
     }
 
+    unsafe fn set_image_name(&self, image: &mut r::Image, name: &str) {
+        match *image {
+            r::Image::Bound(ref image) => {
+                image.resource.SetName(wide_string(name).as_ptr());
+            }
+            r::Image::Unbound(_) => {
+                warn!("Unable to name the unbound image {:?}", name);
+            }
+        }
+    }
+
     unsafe fn create_image_view(
         &self,
         image: &r::Image,
         view_kind: image::ViewKind,
         format: format::Format,
-        _swizzle: format::Swizzle,
+        swizzle: format::Swizzle,
         range: image::SubresourceRange,
     ) -> Result<r::ImageView, image::ViewError> {
         let image = image.expect_bound();
         let mip_levels = (range.levels.start, range.levels.end);
         let layers = (range.layers.start, range.layers.end);
 
+        let view_format = conv::map_format(format).ok_or(image::ViewError::BadFormat(format))?;
+        if view_format != image.default_view_format
+            && !image
+                .view_caps
+                .contains(image::ViewCapabilities::MUTABLE_FORMAT)
+        {
+            return Err(image::ViewError::BadFormat(format));
+        }
+
+        // Only the shader resource views apply the component mapping.
         let info = ViewInfo {
             resource: image.resource,
             kind: image.kind,
             caps: image.view_caps,
             view_kind,
-            format: conv::map_format(format).ok_or(image::ViewError::BadFormat(format))?,
+            format: view_format,
+            component_mapping: conv::map_swizzle(swizzle),
             range,
         };
 
@@ -2335,7 +2535,7 @@ impl d::Device<B> for Device {
             } else {
                 None
             },
-            dxgi_format: image.descriptor.Format,
+            dxgi_format: view_format,
             num_levels: image.descriptor.MipLevels as image::Level,
             mip_levels,
             layers,
@@ -2451,6 +2651,7 @@ impl d::Device<B> for Device {
         };
 
         Ok(r::DescriptorPool {
+            device: self.raw.clone(),
             heap_srv_cbv_uav,
             heap_sampler,
             pools: descriptor_pools,
@@ -2461,7 +2662,7 @@ impl d::Device<B> for Device {
     unsafe fn create_descriptor_set_layout<I, J>(
         &self,
         bindings: I,
-        _immutable_samplers: J,
+        immutable_samplers: J,
     ) -> Result<r::DescriptorSetLayout, d::OutOfMemory>
     where
         I: IntoIterator,
@@ -2471,6 +2672,10 @@ impl d::Device<B> for Device {
     {
         Ok(r::DescriptorSetLayout {
             bindings: bindings.into_iter().map(|b| b.borrow().clone()).collect(),
+            immutable_samplers: immutable_samplers
+                .into_iter()
+                .map(|s| s.borrow().handle)
+                .collect(),
         })
     }
 
@@ -2839,32 +3044,66 @@ impl d::Device<B> for Device {
             d::WaitFor::All => TRUE,
         };
 
-        let hr = {
-            // This block handles overflow when converting to u32 and always rounds up
-            // The Vulkan specification allows to wait more than specified
-            let timeout_ms = {
-                if timeout_ns > (<u32>::max_value() as u64) * 1_000_000 {
-                    <u32>::max_value()
-                } else {
-                    ((timeout_ns + 999_999) / 1_000_000) as u32
-                }
-            };
+        // This block handles overflow when converting to u32 and always rounds up
+        // The Vulkan specification allows to wait more than specified
+        let timeout_ms = {
+            if timeout_ns > (<u32>::max_value() as u64) * 1_000_000 {
+                winbase::INFINITE
+            } else {
+                ((timeout_ns + 999_999) / 1_000_000) as u32
+            }
+        };
 
-            synchapi::WaitForMultipleObjects(
-                fences.len() as u32,
+        let wait_events = |events: &[native::Event], timeout_ms: u32| {
+            let hr = synchapi::WaitForMultipleObjects(
+                events.len() as u32,
                 events.as_ptr() as *const _,
                 all,
                 timeout_ms,
-            )
+            );
+
+            const WAIT_OBJECT_LAST: u32 = winbase::WAIT_OBJECT_0 + winnt::MAXIMUM_WAIT_OBJECTS;
+            const WAIT_ABANDONED_LAST: u32 =
+                winbase::WAIT_ABANDONED_0 + winnt::MAXIMUM_WAIT_OBJECTS;
+            match hr {
+                winbase::WAIT_OBJECT_0...WAIT_OBJECT_LAST => true,
+                winbase::WAIT_ABANDONED_0...WAIT_ABANDONED_LAST => true, //TODO?
+                winerror::WAIT_TIMEOUT => false,
+                _ => panic!("Unexpected wait status 0x{:X}", hr),
+            }
         };
 
-        const WAIT_OBJECT_LAST: u32 = winbase::WAIT_OBJECT_0 + winnt::MAXIMUM_WAIT_OBJECTS;
-        const WAIT_ABANDONED_LAST: u32 = winbase::WAIT_ABANDONED_0 + winnt::MAXIMUM_WAIT_OBJECTS;
-        match hr {
-            winbase::WAIT_OBJECT_0...WAIT_OBJECT_LAST => Ok(true),
-            winbase::WAIT_ABANDONED_0...WAIT_ABANDONED_LAST => Ok(true), //TODO?
-            winerror::WAIT_TIMEOUT => Ok(false),
-            _ => panic!("Unexpected wait status 0x{:X}", hr),
+        let events = &events[..fences.len()];
+        let max_events = winnt::MAXIMUM_WAIT_OBJECTS as usize;
+        if events.len() <= max_events {
+            return Ok(wait_events(events, timeout_ms));
+        }
+
+        // Only a limited number of events can be waited for at once.
+        let start = time::Instant::now();
+        let remaining_ms = || {
+            let elapsed = start.elapsed();
+            let elapsed_ms = elapsed.as_secs() * 1000 + elapsed.subsec_millis() as u64;
+            match timeout_ms {
+                winbase::INFINITE => winbase::INFINITE,
+                _ => (timeout_ms as u64).saturating_sub(elapsed_ms) as u32,
+            }
+        };
+        match wait {
+            // The fences stay signaled, so the batches can be waited for one by one.
+            d::WaitFor::All => Ok(events
+                .chunks(max_events)
+                .all(|chunk| wait_events(chunk, remaining_ms()))),
+            // Any signaled fence completes the wait, so the batches are polled.
+            d::WaitFor::Any => loop {
+                if events.chunks(max_events).any(|chunk| wait_events(chunk, 0)) {
+                    return Ok(true);
+                }
+                if remaining_ms() == 0 {
+                    return Ok(false);
+                }
+                thread::sleep(time::Duration::from_millis(1));
+            },
         }
     }
 
@@ -2876,6 +3115,24 @@ impl d::Device<B> for Device {
         }
     }
 
+    fn create_event(&self) -> Result<r::Event, d::OutOfMemory> {
+        Ok(r::Event(Arc::new(AtomicBool::new(false))))
+    }
+
+    unsafe fn get_event_status(&self, event: &r::Event) -> Result<bool, d::OomOrDeviceLost> {
+        Ok(event.0.load(Ordering::Acquire))
+    }
+
+    unsafe fn set_event(&self, event: &r::Event) -> Result<(), d::OutOfMemory> {
+        event.0.store(true, Ordering::Release);
+        Ok(())
+    }
+
+    unsafe fn reset_event(&self, event: &r::Event) -> Result<(), d::OutOfMemory> {
+        event.0.store(false, Ordering::Release);
+        Ok(())
+    }
+
     unsafe fn free_memory(&self, memory: r::Memory) {
         memory.heap.destroy();
         if let Some(buffer) = memory.resource {
@@ -2993,6 +3250,34 @@ impl d::Device<B> for Device {
         semaphore.raw.destroy();
     }
 
+    unsafe fn destroy_event(&self, _event: r::Event) {}
+
+    unsafe fn calibrate_timestamps(&self) -> Option<query::TimestampCalibration> {
+        let mut frequency = 0;
+        let mut gpu = 0;
+        let mut cpu = 0;
+        if !winerror::SUCCEEDED(self.present_queue.GetTimestampFrequency(&mut frequency))
+            || !winerror::SUCCEEDED(self.present_queue.GetClockCalibration(&mut gpu, &mut cpu))
+        {
+            return None;
+        }
+
+        // The CPU timestamp comes from the performance counter, which also backs `Instant`.
+        let now = time::Instant::now();
+        let mut counter: winnt::LARGE_INTEGER = mem::zeroed();
+        let mut counter_frequency: winnt::LARGE_INTEGER = mem::zeroed();
+        profileapi::QueryPerformanceCounter(&mut counter);
+        profileapi::QueryPerformanceFrequency(&mut counter_frequency);
+        let elapsed = (*counter.QuadPart() as u64).saturating_sub(cpu) as f64
+            / *counter_frequency.QuadPart() as f64;
+
+        Some(query::TimestampCalibration {
+            cpu: now - time::Duration::from_nanos((elapsed * 1e9) as u64),
+            gpu,
+            period: (1e9 / frequency as f64) as f32,
+        })
+    }
+
     unsafe fn create_swapchain(
         &self,
         surface: &mut w::Surface,
@@ -3112,6 +3397,7 @@ impl d::Device<B> for Device {
                         Layout: d3d12::D3D12_TEXTURE_LAYOUT_UNKNOWN,
                         Flags: 0,
                     },
+                    default_view_format: rtv_desc.Format,
                     bytes_per_block,
                     block_dim,
                     clear_cv: vec![rtv_handle],
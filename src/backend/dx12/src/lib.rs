@@ -25,11 +25,12 @@ mod window;
 
 use descriptors_cpu::DescriptorCpuPool;
 use hal::adapter::DeviceType;
+use hal::device::DeviceLost;
 use hal::pso::PipelineStage;
 use hal::queue::{QueueFamilyId, Queues};
 use hal::{error, format as f, image, memory, Features, Limits, QueueType, SwapImageIndex};
 
-use winapi::shared::minwindef::TRUE;
+use winapi::shared::minwindef::{FALSE, TRUE, UINT};
 use winapi::shared::{dxgi, dxgi1_2, dxgi1_3, dxgi1_4, dxgi1_6, winerror};
 use winapi::um::{d3d12, d3d12sdklayers, dxgidebug, handleapi, synchapi, winbase};
 use winapi::Interface;
@@ -37,6 +38,7 @@ use winapi::Interface;
 use std::borrow::Borrow;
 use std::ffi::OsString;
 use std::os::windows::ffi::OsStringExt;
+use std::sync::atomic::Ordering;
 use std::sync::{Arc, Mutex};
 use std::{mem, ptr};
 
@@ -122,7 +124,7 @@ static HEAPS_CCUMA: [HeapProperties; NUM_HEAP_PROPERTIES] = [
 ];
 
 #[derive(Debug, Copy, Clone)]
-pub enum QueueFamily {
+pub enum QueueFamilyKind {
     // Specially marked present queue.
     // It's basically a normal 3D queue but D3D12 swapchain creation requires an
     // associated queue, which we don't know on `create_swapchain`.
@@ -130,52 +132,65 @@ pub enum QueueFamily {
     Normal(QueueType),
 }
 
+impl QueueFamilyKind {
+    fn queue_type(&self) -> QueueType {
+        match *self {
+            QueueFamilyKind::Present => QueueType::General,
+            QueueFamilyKind::Normal(ty) => ty,
+        }
+    }
+
+    fn native_type(&self) -> native::command_list::CmdListType {
+        use native::command_list::CmdListType;
+
+        match self.queue_type() {
+            QueueType::General | QueueType::Graphics => CmdListType::Direct,
+            QueueType::Compute => CmdListType::Compute,
+            QueueType::Transfer => CmdListType::Copy,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct QueueFamily {
+    kind: QueueFamilyKind,
+    // Tile mappings can be updated on all queues, as long as the device
+    // supports tiled resources.
+    sparse_binding: bool,
+}
+
 const MAX_QUEUES: usize = 16; // infinite, to be fair
 
 impl hal::QueueFamily for QueueFamily {
     fn queue_type(&self) -> QueueType {
-        match *self {
-            QueueFamily::Present => QueueType::General,
-            QueueFamily::Normal(ty) => ty,
-        }
+        self.kind.queue_type()
     }
     fn max_queues(&self) -> usize {
-        match *self {
-            QueueFamily::Present => 1,
-            QueueFamily::Normal(_) => MAX_QUEUES,
+        match self.kind {
+            QueueFamilyKind::Present => 1,
+            QueueFamilyKind::Normal(_) => MAX_QUEUES,
         }
     }
     fn id(&self) -> QueueFamilyId {
         // This must match the order exposed by `QUEUE_FAMILIES`
-        QueueFamilyId(match *self {
-            QueueFamily::Present => 0,
-            QueueFamily::Normal(QueueType::General) => 1,
-            QueueFamily::Normal(QueueType::Compute) => 2,
-            QueueFamily::Normal(QueueType::Transfer) => 3,
+        QueueFamilyId(match self.kind {
+            QueueFamilyKind::Present => 0,
+            QueueFamilyKind::Normal(QueueType::General) => 1,
+            QueueFamilyKind::Normal(QueueType::Compute) => 2,
+            QueueFamilyKind::Normal(QueueType::Transfer) => 3,
             _ => unreachable!(),
         })
     }
-}
-
-impl QueueFamily {
-    fn native_type(&self) -> native::command_list::CmdListType {
-        use hal::QueueFamily;
-        use native::command_list::CmdListType;
-
-        let queue_type = self.queue_type();
-        match queue_type {
-            QueueType::General | QueueType::Graphics => CmdListType::Direct,
-            QueueType::Compute => CmdListType::Compute,
-            QueueType::Transfer => CmdListType::Copy,
-        }
+    fn supports_sparse_binding(&self) -> bool {
+        self.sparse_binding
     }
 }
 
-static QUEUE_FAMILIES: [QueueFamily; 4] = [
-    QueueFamily::Present,
-    QueueFamily::Normal(QueueType::General),
-    QueueFamily::Normal(QueueType::Compute),
-    QueueFamily::Normal(QueueType::Transfer),
+static QUEUE_FAMILIES: [QueueFamilyKind; 4] = [
+    QueueFamilyKind::Present,
+    QueueFamilyKind::Normal(QueueType::General),
+    QueueFamilyKind::Normal(QueueType::Compute),
+    QueueFamilyKind::Normal(QueueType::Transfer),
 ];
 
 pub struct PhysicalDevice {
@@ -218,7 +233,7 @@ impl hal::PhysicalDevice<Backend> for PhysicalDevice {
 
         // Always create the presentation queue in case we want to build a swapchain.
         let (present_queue, hr_queue) = device_raw.create_command_queue(
-            QueueFamily::Present.native_type(),
+            QueueFamilyKind::Present.native_type(),
             native::queue::Priority::Normal,
             native::queue::CommandQueueFlags::empty(),
             0,
@@ -236,8 +251,8 @@ impl hal::PhysicalDevice<Backend> for PhysicalDevice {
 
                 let create_idle_event = || native::Event::create(true, false);
 
-                match family {
-                    QueueFamily::Present => {
+                match family.kind {
+                    QueueFamilyKind::Present => {
                         // Exactly **one** present queue!
                         // Number of queues need to be larger than 0 else it
                         // violates the specification.
@@ -249,8 +264,8 @@ impl hal::PhysicalDevice<Backend> for PhysicalDevice {
                         device.append_queue(queue.clone());
                         group.add_queue(queue);
                     }
-                    QueueFamily::Normal(_) => {
-                        let list_type = family.native_type();
+                    QueueFamilyKind::Normal(_) => {
+                        let list_type = family.kind.native_type();
                         for _ in 0..priorities.len() {
                             let (queue, hr_queue) = device_raw.create_command_queue(
                                 list_type,
@@ -417,6 +432,65 @@ impl CommandQueue {
     }
 }
 
+impl CommandQueue {
+    /// Make the following work wait for the semaphore to be signaled,
+    /// then unsignal it again so that it can be reused.
+    unsafe fn wait_semaphore(&mut self, semaphore: &resource::Semaphore) -> Result<(), DeviceLost> {
+        Self::check_result(self.raw.Wait(semaphore.raw.as_mut_ptr(), 1))?;
+        Self::check_result(self.raw.Signal(semaphore.raw.as_mut_ptr(), 0))
+    }
+
+    unsafe fn signal_semaphore(
+        &mut self,
+        semaphore: &resource::Semaphore,
+    ) -> Result<(), DeviceLost> {
+        Self::check_result(self.raw.Signal(semaphore.raw.as_mut_ptr(), 1))
+    }
+
+    /// Queue operations fail once the device is removed.
+    fn check_result(hr: winerror::HRESULT) -> Result<(), DeviceLost> {
+        match hr {
+            winerror::S_OK => Ok(()),
+            winerror::DXGI_ERROR_DEVICE_REMOVED | winerror::DXGI_ERROR_DEVICE_RESET => {
+                Err(DeviceLost)
+            }
+            _ => panic!("Queue operation failed: 0x{:x}", hr),
+        }
+    }
+
+    unsafe fn update_tile_mapping(
+        &mut self,
+        resource: native::Resource,
+        coordinate: d3d12::D3D12_TILED_RESOURCE_COORDINATE,
+        size: d3d12::D3D12_TILE_REGION_SIZE,
+        memory: Option<(&resource::Memory, u64)>,
+    ) {
+        let tile_size = d3d12::D3D12_TILED_RESOURCE_TILE_SIZE_IN_BYTES as u64;
+        let (heap, flags, heap_offset) = match memory {
+            Some((memory, offset)) => (
+                memory.heap.as_mut_ptr(),
+                d3d12::D3D12_TILE_RANGE_FLAG_NONE,
+                (offset / tile_size) as UINT,
+            ),
+            // Unbind the tiles.
+            None => (ptr::null_mut(), d3d12::D3D12_TILE_RANGE_FLAG_NULL, 0),
+        };
+
+        self.raw.UpdateTileMappings(
+            resource.as_mut_ptr(),
+            1,
+            &coordinate,
+            &size,
+            heap,
+            1,
+            &flags,
+            &heap_offset,
+            &size.NumTiles,
+            d3d12::D3D12_TILE_MAPPING_FLAG_NONE,
+        );
+    }
+}
+
 unsafe impl Send for CommandQueue {}
 unsafe impl Sync for CommandQueue {}
 
@@ -425,7 +499,8 @@ impl hal::queue::RawCommandQueue<Backend> for CommandQueue {
         &mut self,
         submission: hal::queue::Submission<Ic, Iw, Is>,
         fence: Option<&resource::Fence>,
-    ) where
+    ) -> Result<(), DeviceLost>
+    where
         T: 'a + Borrow<command::CommandBuffer>,
         Ic: IntoIterator<Item = &'a T>,
         S: 'a + Borrow<resource::Semaphore>,
@@ -437,39 +512,172 @@ impl hal::queue::RawCommandQueue<Backend> for CommandQueue {
         self.idle_fence.signal(0);
         synchapi::ResetEvent(self.idle_event.0);
 
-        // TODO: semaphores
-        let mut lists = submission
+        // D3D12 can't wait at a specific pipeline stage, so the whole submission waits.
+        for (semaphore, _) in submission.wait_semaphores {
+            self.wait_semaphore(semaphore.borrow())?;
+        }
+
+        let command_buffers = submission
             .command_buffers
             .into_iter()
-            .map(|buf| buf.borrow().as_raw_list())
+            .map(|buf| buf.borrow())
+            .collect::<Vec<_>>();
+        let mut lists = command_buffers
+            .iter()
+            .map(|buf| buf.as_raw_list())
             .collect::<Vec<_>>();
         self.raw
             .ExecuteCommandLists(lists.len() as _, lists.as_mut_ptr());
 
-        if let Some(fence) = fence {
-            assert_eq!(winerror::S_OK, self.raw.Signal(fence.raw.as_mut_ptr(), 1));
+        for &(ref event, value) in command_buffers.iter().flat_map(|buf| &buf.events) {
+            event.0.store(value, Ordering::Release);
+        }
+
+        for semaphore in submission.signal_semaphores {
+            self.signal_semaphore(semaphore.borrow())?;
+        }
+
+        match fence {
+            Some(fence) => Self::check_result(self.raw.Signal(fence.raw.as_mut_ptr(), 1)),
+            // The idle fence is reset already, signaling it again only reports the removal.
+            None => Self::check_result(self.raw.signal(self.idle_fence, 0)),
         }
     }
 
     unsafe fn present<'a, W, Is, S, Iw>(
         &mut self,
         swapchains: Is,
-        _wait_semaphores: Iw,
-    ) -> Result<(), ()>
+        wait_semaphores: Iw,
+    ) -> Result<(), hal::PresentError>
     where
         W: 'a + Borrow<window::Swapchain>,
         Is: IntoIterator<Item = (&'a W, SwapImageIndex)>,
         S: 'a + Borrow<resource::Semaphore>,
         Iw: IntoIterator<Item = &'a S>,
     {
-        // TODO: semaphores
+        // The swapchains present through this queue, so the waits apply to them.
+        for semaphore in wait_semaphores {
+            self.wait_semaphore(semaphore.borrow())
+                .map_err(hal::PresentError::DeviceLost)?;
+        }
+
         for (swapchain, _) in swapchains {
-            swapchain.borrow().inner.Present(1, 0);
+            let hr = swapchain.borrow().inner.Present(1, 0);
+            if winerror::FAILED(hr) {
+                return Err(match hr {
+                    winerror::DXGI_ERROR_DEVICE_REMOVED | winerror::DXGI_ERROR_DEVICE_RESET => {
+                        hal::PresentError::DeviceLost(hal::device::DeviceLost)
+                    }
+                    winerror::E_OUTOFMEMORY => {
+                        hal::PresentError::OutOfMemory(hal::device::OutOfMemory::OutOfHostMemory)
+                    }
+                    _ => panic!("Failed to present frame: 0x{:x}", hr),
+                });
+            }
         }
 
         Ok(())
     }
 
+    unsafe fn bind_sparse<'a, S, Iw, Is>(
+        &mut self,
+        binding: hal::queue::SparseBinding<'a, Backend, Iw, Is>,
+        fence: Option<&resource::Fence>,
+    ) where
+        S: 'a + Borrow<resource::Semaphore>,
+        Iw: IntoIterator<Item = &'a S>,
+        Is: IntoIterator<Item = &'a S>,
+    {
+        let tile_size = d3d12::D3D12_TILED_RESOURCE_TILE_SIZE_IN_BYTES as u64;
+
+        for semaphore in binding.wait_semaphores {
+            if let Err(DeviceLost) = self.wait_semaphore(semaphore.borrow()) {
+                error!("Binding sparse memory on a removed device");
+                return;
+            }
+        }
+
+        for &(buffer, binds) in binding.buffers {
+            let buffer = buffer.expect_bound();
+            for bind in binds {
+                let coordinate = d3d12::D3D12_TILED_RESOURCE_COORDINATE {
+                    X: (bind.resource_offset / tile_size) as _,
+                    Y: 0,
+                    Z: 0,
+                    Subresource: 0,
+                };
+                let size = d3d12::D3D12_TILE_REGION_SIZE {
+                    NumTiles: (bind.size / tile_size) as _,
+                    UseBox: FALSE,
+                    Width: 0,
+                    Height: 0,
+                    Depth: 0,
+                };
+                self.update_tile_mapping(buffer.resource, coordinate, size, bind.memory);
+            }
+        }
+
+        for &(image, binds) in binding.image_opaques {
+            let image = image.expect_bound();
+            let tiling = image.tiling();
+            for bind in binds {
+                // Regions without a box advance linearly through the tiles,
+                // spanning over the following subresources.
+                let tile = (bind.resource_offset / tile_size) as UINT;
+                let coordinate = image.tile_coordinate(&tiling, tile);
+                let size = d3d12::D3D12_TILE_REGION_SIZE {
+                    NumTiles: (bind.size / tile_size) as _,
+                    UseBox: FALSE,
+                    Width: 0,
+                    Height: 0,
+                    Depth: 0,
+                };
+                self.update_tile_mapping(image.resource, coordinate, size, bind.memory);
+            }
+        }
+
+        for &(image, binds) in binding.images {
+            let image = image.expect_bound();
+            let shape = image.tiling().tile_shape;
+            for bind in binds {
+                let coordinate = d3d12::D3D12_TILED_RESOURCE_COORDINATE {
+                    X: bind.offset.x as UINT / shape.WidthInTexels,
+                    Y: bind.offset.y as UINT / shape.HeightInTexels,
+                    Z: bind.offset.z as UINT / shape.DepthInTexels,
+                    Subresource: image.calc_subresource(
+                        bind.subresource.level as _,
+                        bind.subresource.layer as _,
+                        0,
+                    ),
+                };
+                let width = (bind.extent.width + shape.WidthInTexels - 1) / shape.WidthInTexels;
+                let height = (bind.extent.height + shape.HeightInTexels - 1) / shape.HeightInTexels;
+                let depth = (bind.extent.depth + shape.DepthInTexels - 1) / shape.DepthInTexels;
+                let size = d3d12::D3D12_TILE_REGION_SIZE {
+                    NumTiles: width * height * depth,
+                    UseBox: TRUE,
+                    Width: width,
+                    Height: height as _,
+                    Depth: depth as _,
+                };
+                self.update_tile_mapping(image.resource, coordinate, size, bind.memory);
+            }
+        }
+
+        let mut result = Ok(());
+        for semaphore in binding.signal_semaphores {
+            result = result.and(self.signal_semaphore(semaphore.borrow()));
+        }
+        if let Some(fence) = fence {
+            result = result.and(Self::check_result(
+                self.raw.Signal(fence.raw.as_mut_ptr(), 1),
+            ));
+        }
+        if let Err(DeviceLost) = result {
+            error!("Binding sparse memory on a removed device");
+        }
+    }
+
     fn wait_idle(&self) -> Result<(), error::HostExecutionError> {
         self.raw.signal(self.idle_fence, 1);
         assert_eq!(
@@ -485,6 +693,42 @@ impl hal::queue::RawCommandQueue<Backend> for CommandQueue {
     }
 }
 
+/// Number of descriptors accessible to a shader stage, depending on the resource binding tier.
+struct BindingLimits {
+    samplers: usize,
+    constant_buffers: usize,
+    shader_resource_views: usize,
+    unordered_access_views: usize,
+}
+
+impl BindingLimits {
+    fn new(tier: d3d12::D3D12_RESOURCE_BINDING_TIER) -> Self {
+        // Higher tiers only limit the views by the size of the descriptor heaps.
+        let heap_size = d3d12::D3D12_MAX_SHADER_VISIBLE_DESCRIPTOR_HEAP_SIZE_TIER_1 as usize;
+        match tier {
+            d3d12::D3D12_RESOURCE_BINDING_TIER_1 => BindingLimits {
+                samplers: d3d12::D3D12_COMMONSHADER_SAMPLER_SLOT_COUNT as _,
+                constant_buffers: d3d12::D3D12_COMMONSHADER_CONSTANT_BUFFER_API_SLOT_COUNT as _,
+                shader_resource_views: d3d12::D3D12_COMMONSHADER_INPUT_RESOURCE_SLOT_COUNT as _,
+                // Only 8 on feature level 11.0.
+                unordered_access_views: 8,
+            },
+            d3d12::D3D12_RESOURCE_BINDING_TIER_2 => BindingLimits {
+                samplers: d3d12::D3D12_MAX_SHADER_VISIBLE_SAMPLER_HEAP_SIZE as _,
+                constant_buffers: d3d12::D3D12_COMMONSHADER_CONSTANT_BUFFER_API_SLOT_COUNT as _,
+                shader_resource_views: heap_size,
+                unordered_access_views: d3d12::D3D12_UAV_SLOT_COUNT as _,
+            },
+            _ => BindingLimits {
+                samplers: d3d12::D3D12_MAX_SHADER_VISIBLE_SAMPLER_HEAP_SIZE as _,
+                constant_buffers: heap_size,
+                shader_resource_views: heap_size,
+                unordered_access_views: heap_size,
+            },
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 enum MemoryArchitecture {
     NUMA,
@@ -870,6 +1114,23 @@ impl hal::Instance for Instance {
                 }
             };
 
+            let sparse_features = {
+                let tier = features.TiledResourcesTier;
+                let mut sparse = Features::empty();
+                if tier >= d3d12::D3D12_TILED_RESOURCES_TIER_1 {
+                    sparse |= Features::SPARSE_BINDING
+                        | Features::SPARSE_RESIDENCY_BUFFER
+                        | Features::SPARSE_RESIDENCY_IMAGE_2D
+                        | Features::SPARSE_RESIDENCY_ALIASED;
+                }
+                if tier >= d3d12::D3D12_TILED_RESOURCES_TIER_3 {
+                    sparse |= Features::SPARSE_RESIDENCY_IMAGE_3D;
+                }
+                sparse
+            };
+
+            let binding_limits = BindingLimits::new(features.ResourceBindingTier);
+
             let heterogeneous_resource_heaps =
                 features.ResourceHeapTier != d3d12::D3D12_RESOURCE_HEAP_TIER_1;
 
@@ -1026,30 +1287,36 @@ impl hal::Instance for Instance {
                     // https://msdn.microsoft.com/de-de/library/windows/desktop/mt186615(v=vs.85).aspx
                     Features::ROBUST_BUFFER_ACCESS |
                     Features::IMAGE_CUBE_ARRAY |
+                    Features::INDEPENDENT_BLENDING |
                     Features::GEOMETRY_SHADER |
                     Features::TESSELLATION_SHADER |
                     Features::NON_FILL_POLYGON_MODE |
+                    Features::DEPTH_CLAMP |
                     if depth_bounds_test_supported { Features::DEPTH_BOUNDS } else { Features::empty() } |
-                    //logic_op: false, // Optional on feature level 11_0
+                    if features.OutputMergerLogicOp != 0 { Features::LOGIC_OP } else { Features::empty() } |
                     Features::MULTI_DRAW_INDIRECT |
                     Features::FORMAT_BC |
                     Features::INSTANCE_RATE |
-                    Features::SAMPLER_MIP_LOD_BIAS,
+                    Features::SAMPLER_MIP_LOD_BIAS |
+                    Features::MULTI_VIEWPORTS |
+                    Features::CONDITIONAL_RENDERING |
+                    sparse_features,
                 limits: Limits { // TODO
-                    max_texture_size: 0,
-                    max_texel_elements: 0,
-                    max_patch_size: 0,
-                    max_viewports: 0,
+                    max_texture_size: d3d12::D3D12_REQ_TEXTURE2D_U_OR_V_DIMENSION as _,
+                    max_texel_elements: 1 << d3d12::D3D12_REQ_BUFFER_RESOURCE_TEXEL_COUNT_2_TO_EXP,
+                    max_patch_size: d3d12::D3D12_IA_PATCH_MAX_CONTROL_POINT_COUNT as _,
+                    max_viewports: d3d12::D3D12_VIEWPORT_AND_SCISSORRECT_OBJECT_COUNT_PER_PIPELINE as _,
                     max_compute_group_count: [
+                        d3d12::D3D12_CS_DISPATCH_MAX_THREAD_GROUPS_PER_DIMENSION,
+                        d3d12::D3D12_CS_DISPATCH_MAX_THREAD_GROUPS_PER_DIMENSION,
+                        d3d12::D3D12_CS_DISPATCH_MAX_THREAD_GROUPS_PER_DIMENSION,
+                    ],
+                    max_compute_group_size: [
                         d3d12::D3D12_CS_THREAD_GROUP_MAX_X,
                         d3d12::D3D12_CS_THREAD_GROUP_MAX_Y,
                         d3d12::D3D12_CS_THREAD_GROUP_MAX_Z,
                     ],
-                    max_compute_group_size: [
-                        d3d12::D3D12_CS_THREAD_GROUP_MAX_THREADS_PER_GROUP,
-                        1, //TODO
-                        1, //TODO
-                    ],
+                    max_compute_shared_memory_size: d3d12::D3D12_CS_TGSM_REGISTER_COUNT as usize * 4,
                     max_vertex_input_attributes: d3d12::D3D12_IA_VERTEX_INPUT_RESOURCE_SLOT_COUNT as _,
                     max_vertex_input_bindings: 31, //TODO
                     max_vertex_input_attribute_offset: 255, // TODO
@@ -1065,10 +1332,25 @@ impl hal::Instance for Instance {
                     framebuffer_color_samples_count: 0b101,
                     framebuffer_depth_samples_count: 0b101,
                     framebuffer_stencil_samples_count: 0b101,
-                    max_color_attachments: 1, // TODO
+                    max_color_attachments: d3d12::D3D12_SIMULTANEOUS_RENDER_TARGET_COUNT as _,
+                    max_framebuffer_layers: d3d12::D3D12_REQ_TEXTURE2D_ARRAY_AXIS_DIMENSION as _,
+                    // Each set takes up to two tables of the root signature,
+                    // leaving space for the root constants.
+                    max_bound_descriptor_sets: 16,
+                    max_per_stage_descriptor_samplers: binding_limits.samplers,
+                    max_per_stage_descriptor_uniform_buffers: binding_limits.constant_buffers,
+                    max_per_stage_descriptor_storage_buffers: binding_limits.unordered_access_views,
+                    max_per_stage_descriptor_sampled_images: binding_limits.shader_resource_views,
+                    max_per_stage_descriptor_storage_images: binding_limits.unordered_access_views,
                     non_coherent_atom_size: 1, //TODO: confirm
                     max_sampler_anisotropy: 16.,
+                    max_draw_indirect_count: !0,
                     min_vertex_input_binding_stride_alignment: 1,
+                    // Wave intrinsics require shader model 6, while shaders
+                    // are compiled to shader model 5.1.
+                    subgroup_size: 0,
+                    subgroup_operations: hal::SubgroupOperations::empty(),
+                    subgroup_stages: hal::pso::ShaderStageFlags::empty(),
                 },
                 format_properties: Arc::new(FormatProperties::new(device)),
                 private_caps: Capabilities {
@@ -1083,7 +1365,14 @@ impl hal::Instance for Instance {
                 is_open: Arc::new(Mutex::new(false)),
             };
 
-            let queue_families = QUEUE_FAMILIES.to_vec();
+            let sparse_binding = physical_device.features.contains(Features::SPARSE_BINDING);
+            let queue_families = QUEUE_FAMILIES
+                .iter()
+                .map(|&kind| QueueFamily {
+                    kind,
+                    sparse_binding,
+                })
+                .collect();
 
             adapters.push(hal::Adapter {
                 info,
@@ -1131,6 +1420,7 @@ impl hal::Backend for Backend {
 
     type Fence = resource::Fence;
     type Semaphore = resource::Semaphore;
+    type Event = resource::Event;
     type QueryPool = resource::QueryPool;
 }
 
@@ -1141,6 +1431,11 @@ fn validate_line_width(width: f32) {
     assert_eq!(width, 1.0);
 }
 
+/// Convert to a null-terminated UTF-16 string, as expected by the debugging APIs.
+fn wide_string(name: &str) -> Vec<u16> {
+    name.encode_utf16().chain(std::iter::once(0)).collect()
+}
+
 pub struct FormatProperties(
     Box<[Mutex<Option<f::Properties>>]>,
     native::Device,
@@ -3,6 +3,7 @@ use std::sync::Arc;
 use winapi::shared::winerror::SUCCEEDED;
 
 use command::CommandBuffer;
+use hal::queue::QueueFamilyId;
 use hal::{command, pool};
 use native::command_list::CmdListType;
 use {native, Backend, Shared};
@@ -17,6 +18,7 @@ pub struct RawCommandPool {
     pub(crate) device: native::Device,
     pub(crate) list_type: CmdListType,
     pub(crate) shared: Arc<Shared>,
+    pub(crate) family: QueueFamilyId,
 }
 
 impl RawCommandPool {
@@ -92,7 +94,12 @@ impl pool::RawCommandPool<Backend> for RawCommandPool {
         // TODO: Implement secondary buffers
         assert_eq!(level, command::RawLevel::Primary);
         let (command_list, command_allocator) = self.create_command_list();
-        CommandBuffer::new(command_list, command_allocator, self.shared.clone())
+        CommandBuffer::new(
+            command_list,
+            command_allocator,
+            self.shared.clone(),
+            self.family,
+        )
     }
 
     unsafe fn free<I>(&mut self, cbufs: I)
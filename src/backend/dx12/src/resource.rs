@@ -1,6 +1,8 @@
 use winapi::shared::dxgiformat::DXGI_FORMAT;
 use winapi::shared::minwindef::UINT;
+use winapi::shared::winerror;
 use winapi::um::d3d12;
+use winapi::Interface;
 
 use hal::{buffer, format, image, memory, pass, pso, DescriptorPool as HalDescriptorPool};
 use native::{self, query};
@@ -10,6 +12,9 @@ use {Backend, MAX_VERTEX_BUFFERS};
 
 use std::collections::BTreeMap;
 use std::ops::Range;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::{mem, ptr};
 
 // ShaderModule is either a precompiled if the source comes from HLSL or
 // the SPIR-V module doesn't contain specialization constants or push constants
@@ -190,6 +195,16 @@ impl Buffer {
             Buffer::Bound(ref bound) => bound,
         }
     }
+
+    /// Underlying resource, `None` until the buffer is bound to memory.
+    ///
+    /// The resource stays valid until the buffer is destroyed.
+    pub fn as_raw(&self) -> Option<*mut d3d12::ID3D12Resource> {
+        match *self {
+            Buffer::Unbound(_) => None,
+            Buffer::Bound(ref bound) => Some(bound.resource.as_mut_ptr()),
+        }
+    }
 }
 
 #[derive(Copy, Clone, Derivative)]
@@ -208,7 +223,21 @@ unsafe impl Sync for BufferView {}
 #[derive(Clone)]
 pub enum Place {
     SwapChain,
-    Heap { raw: native::Heap, offset: u64 },
+    Heap {
+        raw: native::Heap,
+        offset: u64,
+    },
+    /// Reserved resource, which memory is mapped in tiles.
+    Reserved,
+}
+
+/// Tile layout of a reserved resource.
+pub struct Tiling {
+    pub num_tiles: UINT,
+    pub packed_mips: d3d12::D3D12_PACKED_MIP_INFO,
+    pub tile_shape: d3d12::D3D12_TILE_SHAPE,
+    /// Tilings of all subresources, the packed mips have no meaningful entry.
+    pub subresources: Vec<d3d12::D3D12_SUBRESOURCE_TILING>,
 }
 
 #[derive(Clone, Derivative)]
@@ -223,6 +252,8 @@ pub struct ImageBound {
     pub(crate) view_caps: image::ViewCapabilities,
     #[derivative(Debug = "ignore")]
     pub(crate) descriptor: d3d12::D3D12_RESOURCE_DESC,
+    // Typed format of the internal views, the resource can be typeless.
+    pub(crate) default_view_format: DXGI_FORMAT,
     pub(crate) bytes_per_block: u8,
     // Dimension of a texel block (compressed formats).
     pub(crate) block_dim: (u8, u8),
@@ -253,6 +284,87 @@ impl ImageBound {
             + (layer * self.descriptor.MipLevels as UINT)
             + (plane * self.descriptor.MipLevels as UINT * self.kind.num_layers() as UINT)
     }
+
+    /// Query the tile layout of a reserved image.
+    pub(crate) unsafe fn tiling(&self) -> Tiling {
+        let mut device = native::WeakPtr::<d3d12::ID3D12Device>::null();
+        assert_eq!(
+            winerror::S_OK,
+            self.resource
+                .GetDevice(&d3d12::ID3D12Device::uuidof(), device.mut_void())
+        );
+
+        let mut num_subresource_tilings =
+            self.descriptor.MipLevels as UINT * self.kind.num_layers() as UINT;
+        let mut tiling = Tiling {
+            num_tiles: 0,
+            packed_mips: mem::zeroed(),
+            tile_shape: mem::zeroed(),
+            subresources: vec![mem::zeroed(); num_subresource_tilings as usize],
+        };
+        device.GetResourceTiling(
+            self.resource.as_mut_ptr(),
+            &mut tiling.num_tiles,
+            &mut tiling.packed_mips,
+            &mut tiling.tile_shape,
+            &mut num_subresource_tilings,
+            0,
+            tiling.subresources.as_mut_ptr(),
+        );
+        // `GetDevice` increments the reference count
+        device.destroy();
+
+        tiling
+    }
+
+    /// Locate a tile of the opaque memory layout, as exposed by
+    /// `get_image_sparse_requirements`, in the reserved resource.
+    ///
+    /// Each layer is laid out as the tiles of its standard mips followed by
+    /// its packed mips.
+    pub(crate) fn tile_coordinate(
+        &self,
+        tiling: &Tiling,
+        tile: UINT,
+    ) -> d3d12::D3D12_TILED_RESOURCE_COORDINATE {
+        let num_layers = self.kind.num_layers() as UINT;
+        let layer_tiles = tiling.num_tiles / num_layers;
+        let layer = tile / layer_tiles;
+        let layer_tile = tile % layer_tiles;
+        let packed_start = tiling.packed_mips.StartTileIndexInOverallResource;
+
+        if layer_tile >= packed_start {
+            return d3d12::D3D12_TILED_RESOURCE_COORDINATE {
+                X: layer_tile - packed_start,
+                Y: 0,
+                Z: 0,
+                Subresource: self.calc_subresource(
+                    tiling.packed_mips.NumStandardMips as _,
+                    layer,
+                    0,
+                ),
+            };
+        }
+
+        for level in 0..tiling.packed_mips.NumStandardMips as UINT {
+            let subresource = self.calc_subresource(level, layer, 0);
+            let st = &tiling.subresources[subresource as usize];
+            let start = st.StartTileIndexInOverallResource;
+            let (width, height) = (st.WidthInTiles as UINT, st.HeightInTiles as UINT);
+            let size = width * height * st.DepthInTiles as UINT;
+            if tile >= start && tile < start + size {
+                let offset = tile - start;
+                return d3d12::D3D12_TILED_RESOURCE_COORDINATE {
+                    X: offset % width,
+                    Y: offset / width % height,
+                    Z: offset / (width * height),
+                    Subresource: subresource,
+                };
+            }
+        }
+
+        panic!("Tile {} is outside of the opaque memory of the image", tile)
+    }
 }
 
 #[derive(Copy, Clone, Derivative)]
@@ -260,6 +372,7 @@ impl ImageBound {
 pub struct ImageUnbound {
     #[derivative(Debug = "ignore")]
     pub(crate) desc: d3d12::D3D12_RESOURCE_DESC,
+    pub(crate) default_view_format: DXGI_FORMAT,
     pub(crate) dsv_format: DXGI_FORMAT,
     pub(crate) requirements: memory::Requirements,
     pub(crate) format: format::Format,
@@ -304,6 +417,16 @@ impl Image {
         }
     }
 
+    /// Underlying resource, `None` until the image is bound to memory.
+    ///
+    /// The resource stays valid until the image is destroyed.
+    pub fn as_raw(&self) -> Option<*mut d3d12::ID3D12Resource> {
+        match *self {
+            Image::Unbound(_) => None,
+            Image::Bound(ref bound) => Some(bound.resource.as_mut_ptr()),
+        }
+    }
+
     pub fn get_desc(&self) -> &d3d12::D3D12_RESOURCE_DESC {
         match self {
             Image::Bound(i) => &i.descriptor,
@@ -355,9 +478,13 @@ pub struct Sampler {
     pub(crate) handle: native::CpuDescriptor,
 }
 
-#[derive(Debug)]
+#[derive(Derivative)]
+#[derivative(Debug)]
 pub struct DescriptorSetLayout {
     pub(crate) bindings: Vec<pso::DescriptorSetLayoutBinding>,
+    // Copied into every allocated set, in the order of the bindings.
+    #[derivative(Debug = "ignore")]
+    pub(crate) immutable_samplers: Vec<native::CpuDescriptor>,
 }
 
 #[derive(Debug)]
@@ -367,6 +494,11 @@ pub struct Fence {
 unsafe impl Send for Fence {}
 unsafe impl Sync for Fence {}
 
+/// Host-visible event, flipped by the queue as it processes the submitted
+/// `set_event` and `reset_event` commands in order.
+#[derive(Clone, Debug)]
+pub struct Event(pub(crate) Arc<AtomicBool>);
+
 #[derive(Debug)]
 pub struct Semaphore {
     pub(crate) raw: native::Fence,
@@ -523,6 +655,8 @@ impl DescriptorHeapSlice {
 
 #[derive(Debug)]
 pub struct DescriptorPool {
+    // Required for copying the immutable samplers.
+    pub(crate) device: native::Device,
     pub(crate) heap_srv_cbv_uav: DescriptorHeapSlice,
     pub(crate) heap_sampler: DescriptorHeapSlice,
     pub(crate) pools: Vec<pso::DescriptorRangeDesc>,
@@ -539,6 +673,7 @@ impl HalDescriptorPool<Backend> for DescriptorPool {
         let mut binding_infos = Vec::new();
         let mut first_gpu_sampler = None;
         let mut first_gpu_view = None;
+        let mut immutable_samplers = layout.immutable_samplers.iter();
 
         for binding in &layout.bindings {
             let HeapProperties {
@@ -587,6 +722,21 @@ impl HalDescriptorPool<Backend> for DescriptorPool {
                 },
                 is_uav,
             };
+
+            if binding.immutable_samplers {
+                let range = binding_infos[binding.binding as usize]
+                    .sampler_range
+                    .as_ref()
+                    .unwrap();
+                for (i, &sampler) in immutable_samplers.by_ref().take(binding.count).enumerate() {
+                    self.device.CopyDescriptorsSimple(
+                        1,
+                        range.at(i as u64),
+                        sampler,
+                        d3d12::D3D12_DESCRIPTOR_HEAP_TYPE_SAMPLER,
+                    );
+                }
+            }
         }
 
         Ok(DescriptorSet {
@@ -9,7 +9,7 @@ use winapi::shared::windef::{HWND, RECT};
 use winapi::um::winuser::GetClientRect;
 
 use hal::{self, format as f, image as i, CompositeAlpha};
-use {native, resource as r, Backend, Instance, PhysicalDevice, QueueFamily};
+use {native, resource as r, Backend, Instance, PhysicalDevice, QueueFamily, QueueFamilyKind};
 
 use std::os::raw::c_void;
 
@@ -53,8 +53,8 @@ impl Surface {
 
 impl hal::Surface<Backend> for Surface {
     fn supports_queue_family(&self, queue_family: &QueueFamily) -> bool {
-        match queue_family {
-            &QueueFamily::Present => true,
+        match queue_family.kind {
+            QueueFamilyKind::Present => true,
             _ => false,
         }
     }
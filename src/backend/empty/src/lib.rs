@@ -49,6 +49,7 @@ impl hal::Backend for Backend {
 
     type Fence = ();
     type Semaphore = ();
+    type Event = ();
     type QueryPool = ();
 }
 
@@ -98,7 +99,8 @@ impl queue::RawCommandQueue<Backend> for RawCommandQueue {
         &mut self,
         _: queue::Submission<Ic, Iw, Is>,
         _: Option<&()>,
-    ) where
+    ) -> Result<(), device::DeviceLost>
+    where
         T: 'a + Borrow<RawCommandBuffer>,
         Ic: IntoIterator<Item = &'a T>,
         S: 'a + Borrow<()>,
@@ -108,7 +110,7 @@ impl queue::RawCommandQueue<Backend> for RawCommandQueue {
         unimplemented!()
     }
 
-    unsafe fn present<'a, W, Is, S, Iw>(&mut self, _: Is, _: Iw) -> Result<(), ()>
+    unsafe fn present<'a, W, Is, S, Iw>(&mut self, _: Is, _: Iw) -> Result<(), hal::PresentError>
     where
         W: 'a + Borrow<Swapchain>,
         Is: IntoIterator<Item = (&'a W, hal::SwapImageIndex)>,
@@ -201,7 +203,7 @@ impl hal::Device<Backend> for Device {
         _: &(),
         _: I,
         _: image::Extent,
-    ) -> Result<(), device::OutOfMemory>
+    ) -> Result<(), device::FramebufferError>
     where
         I: IntoIterator,
         I::Item: Borrow<()>,
@@ -341,6 +343,26 @@ impl hal::Device<Backend> for Device {
         unimplemented!()
     }
 
+    fn create_event(&self) -> Result<(), device::OutOfMemory> {
+        unimplemented!()
+    }
+
+    unsafe fn get_event_status(&self, _: &()) -> Result<bool, device::OomOrDeviceLost> {
+        unimplemented!()
+    }
+
+    unsafe fn set_event(&self, _: &()) -> Result<(), device::OutOfMemory> {
+        unimplemented!()
+    }
+
+    unsafe fn reset_event(&self, _: &()) -> Result<(), device::OutOfMemory> {
+        unimplemented!()
+    }
+
+    unsafe fn destroy_event(&self, _: ()) {
+        unimplemented!()
+    }
+
     unsafe fn create_query_pool(&self, _: query::Type, _: u32) -> Result<(), query::CreationError> {
         unimplemented!()
     }
@@ -446,6 +468,10 @@ impl hal::Device<Backend> for Device {
         unimplemented!()
     }
 
+    unsafe fn calibrate_timestamps(&self) -> Option<query::TimestampCalibration> {
+        unimplemented!()
+    }
+
     unsafe fn create_swapchain(
         &self,
         _: &mut Surface,
@@ -524,6 +550,24 @@ impl command::RawCommandBuffer<Backend> for RawCommandBuffer {
         unimplemented!()
     }
 
+    unsafe fn set_event(&mut self, _: &(), _: pso::PipelineStage) {
+        unimplemented!()
+    }
+
+    unsafe fn reset_event(&mut self, _: &(), _: pso::PipelineStage) {
+        unimplemented!()
+    }
+
+    unsafe fn wait_events<'a, I, J>(&mut self, _: I, _: Range<pso::PipelineStage>, _: J)
+    where
+        I: IntoIterator,
+        I::Item: Borrow<()>,
+        J: IntoIterator,
+        J::Item: Borrow<memory::Barrier<'a, Backend>>,
+    {
+        unimplemented!()
+    }
+
     unsafe fn fill_buffer<R>(&mut self, _: &(), _: R, _: u32)
     where
         R: RangeArg<buffer::Offset>,
@@ -2,17 +2,18 @@
 
 use gl;
 
-use hal::format::ChannelType;
+use hal::format::{Aspects, ChannelType};
 use hal::range::RangeArg;
 use hal::{self, buffer, command, image, memory, pass, pso, query, ColorSlot};
 
+use info::PrivateCaps;
 use pool::{self, BufferMemory};
-use {native as n, Backend};
+use {conv, native as n, Backend};
 
 use std::borrow::Borrow;
 use std::ops::Range;
 use std::sync::{Arc, Mutex};
-use std::{mem, slice};
+use std::{cmp, iter, mem, slice};
 
 // Command buffer implementation details:
 //
@@ -67,6 +68,21 @@ pub enum Command {
         base_vertex: hal::VertexOffset,
         instances: Range<hal::InstanceCount>,
     },
+    DrawIndirect {
+        primitive: gl::types::GLenum,
+        buffer: n::RawBuffer,
+        offset: buffer::Offset,
+        draw_count: hal::DrawCount,
+        stride: u32,
+    },
+    DrawIndexedIndirect {
+        primitive: gl::types::GLenum,
+        index_type: gl::types::GLenum,
+        buffer: n::RawBuffer,
+        offset: buffer::Offset,
+        draw_count: hal::DrawCount,
+        stride: u32,
+    },
     BindIndexBuffer(gl::types::GLuint),
     //BindVertexBuffers(BufferSlice),
     SetViewports {
@@ -87,31 +103,102 @@ pub enum Command {
     ClearBufferDepthStencil(Option<pso::DepthValue>, Option<pso::StencilValue>),
     /// Clear the currently bound texture with the given color.
     ClearTexture([f32; 4]),
+    /// Clear a region of a texture level with a value of the given format
+    /// and type (`glClearTexSubImage`).
+    ClearTexSubImage {
+        texture: n::Texture,
+        level: image::Level,
+        offset: [gl::types::GLint; 3],
+        extent: [gl::types::GLsizei; 3],
+        format: gl::types::GLenum,
+        ty: gl::types::GLenum,
+        value: [u32; 4],
+    },
+    /// Discard the contents of the framebuffer attachments stored in the data buffer.
+    InvalidateFramebuffer(FrameBufferTarget, BufferSlice),
 
     /// Set list of color attachments for drawing.
     /// The buffer slice contains a list of `GLenum`.
     DrawBuffers(BufferSlice),
 
     BindFrameBuffer(FrameBufferTarget, n::FrameBuffer),
+    /// Resolve a color attachment of the bound draw framebuffer into the
+    /// given framebuffer (`glBlitFramebuffer`), rebinding the former afterwards.
+    ResolveAttachment {
+        src: n::FrameBuffer,
+        attachment: AttachmentPoint,
+        dst: n::FrameBuffer,
+        extent: image::Extent,
+    },
+    /// Copy a region of the bound read framebuffer into the bound draw framebuffer
+    /// (`glBlitFramebuffer`), scaling and resolving the samples.
+    BlitFramebuffer {
+        src: Range<image::Offset>,
+        dst: Range<image::Offset>,
+        mask: gl::types::GLbitfield,
+        filter: gl::types::GLenum,
+    },
     BindTargetView(FrameBufferTarget, AttachmentPoint, n::ImageView),
     SetDrawColorBuffers(usize),
     SetPatchSize(gl::types::GLint),
+    SetPrimitiveRestart(pso::PrimitiveRestart),
+    /// Minimum fraction of samples shaded individually, `None` disables sample shading.
+    SetSampleShading(Option<f32>),
+    SetSampleCoverage(n::SampleCoverage),
+    /// Set or reset a host-visible event.
+    SetEvent(n::Event, bool),
     BindProgram(gl::types::GLuint),
+    /// Set a uniform of the bound program, with tightly packed values.
+    SetUniform {
+        location: gl::types::GLint,
+        ty: gl::types::GLenum,
+        data: BufferSlice,
+    },
     BindBlendSlot(ColorSlot, pso::ColorBlendDesc),
-    BindAttribute(
-        n::AttributeDesc,
-        gl::types::GLuint,
-        gl::types::GLsizei,
-        gl::types::GLuint,
+    SetLogicOp(Option<pso::LogicOp>),
+    BindRasterizer(pso::Rasterizer),
+    SetDepthBias(pso::DepthBias),
+    BindDepth(pso::DepthTest),
+    /// Stencil test with the (front, back) reference values, skipping the culled faces.
+    BindStencil(
+        pso::StencilTest,
+        (pso::StencilValue, pso::StencilValue),
+        pso::Face,
     ),
+    /// Bind the vertex attributes stored as `n::AttributeBinding`s in the data buffer.
+    BindAttributes(BufferSlice),
     //UnbindAttribute(n::AttributeDesc),
     CopyBufferToBuffer(n::RawBuffer, n::RawBuffer, command::BufferCopy),
-    CopyBufferToTexture(n::RawBuffer, n::Texture, command::BufferImageCopy),
-    CopyBufferToSurface(n::RawBuffer, n::Surface, command::BufferImageCopy),
+    /// Fill a byte range of a buffer with a repeated 32-bit value.
+    FillBuffer(n::RawBuffer, Range<buffer::Offset>, u32),
+    /// Upload texels into a texture, cube faces are uploaded one at a time.
+    CopyBufferToTexture {
+        buffer: n::RawBuffer,
+        texture: n::Texture,
+        target: gl::types::GLenum,
+        format: gl::types::GLenum,
+        ty: gl::types::GLenum,
+        region: command::BufferImageCopy,
+    },
+    /// Upload tightly packed texel blocks into a compressed texture.
+    CopyBufferToCompressedTexture {
+        buffer: n::RawBuffer,
+        texture: n::Texture,
+        target: gl::types::GLenum,
+        format: gl::types::GLenum,
+        size: gl::types::GLsizei,
+        region: command::BufferImageCopy,
+    },
+    /// Read back the whole level of a 2D texture (`glGetTexImage`),
+    /// used without framebuffer support.
     CopyTextureToBuffer(n::Texture, n::RawBuffer, command::BufferImageCopy),
-    CopySurfaceToBuffer(n::Surface, n::RawBuffer, command::BufferImageCopy),
-    CopyImageToTexture(n::ImageKind, n::Texture, command::ImageCopy),
-    CopyImageToSurface(n::ImageKind, n::Surface, command::ImageCopy),
+    /// Read back a region of the bound read framebuffer (`glReadPixels`).
+    CopyFramebufferToBuffer {
+        buffer: n::RawBuffer,
+        format: gl::types::GLenum,
+        ty: gl::types::GLenum,
+        region: command::BufferImageCopy,
+    },
 
     BindBufferRange(
         gl::types::GLenum,
@@ -120,9 +207,62 @@ pub enum Command {
         gl::types::GLintptr,
         gl::types::GLsizeiptr,
     ),
-    BindTexture(gl::types::GLenum, n::Texture),
+    BindTexture(gl::types::GLenum, n::Texture, gl::types::GLenum),
     BindSampler(gl::types::GLuint, n::Texture),
-    SetTextureSamplerSettings(gl::types::GLuint, n::Texture, image::SamplerInfo),
+    SetTextureSamplerSettings(
+        gl::types::GLuint,
+        n::Texture,
+        gl::types::GLenum,
+        image::SamplerInfo,
+    ),
+    /// Make the shader writes visible to the accesses of the given `glMemoryBarrier` bits.
+    MemoryBarrier(gl::types::GLbitfield),
+    /// Toggle the sRGB encoding of the framebuffer writes (`GL_FRAMEBUFFER_SRGB`).
+    SetFramebufferSrgb(bool),
+
+    BindTransformFeedback(n::TransformFeedback),
+    BeginTransformFeedback(gl::types::GLenum),
+    PauseTransformFeedback,
+    ResumeTransformFeedback,
+    EndTransformFeedback,
+    DrawTransformFeedback {
+        primitive: gl::types::GLenum,
+        feedback: n::TransformFeedback,
+        instances: hal::InstanceCount,
+    },
+
+    BeginQuery(gl::types::GLenum, n::Query),
+    EndQuery(gl::types::GLenum),
+    /// Record the GPU time once the previous commands are done (`glQueryCounter`).
+    QueryCounter(n::Query),
+    /// Skip the following draws and dispatches if the 32-bit predicate in the buffer
+    /// is zero (non-zero if inverted).
+    BeginConditionalRendering {
+        buffer: n::RawBuffer,
+        offset: buffer::Offset,
+        inverted: bool,
+    },
+    EndConditionalRendering,
+    /// Write the result of a query into a buffer (`ARB_query_buffer_object`).
+    CopyQueryResult {
+        query: n::Query,
+        buffer: n::RawBuffer,
+        offset: buffer::Offset,
+        flags: query::ResultFlags,
+    },
+
+    /// Execute the commands recorded in a secondary command buffer.
+    ExecuteCommands {
+        memory: Arc<Mutex<BufferMemory>>,
+        id: u64,
+        buf: BufferSlice,
+    },
+
+    /// Push a debug group, labeled with the UTF-8 string in the data buffer.
+    PushDebugGroup(BufferSlice),
+    PopDebugGroup,
+    /// Insert a debug marker, labeled with the UTF-8 string in the data buffer.
+    InsertDebugMarker(BufferSlice),
 }
 
 pub type FrameBufferTarget = gl::types::GLenum;
@@ -139,7 +279,7 @@ struct AttachmentClear {
 #[derive(Clone)]
 struct RenderPassCache {
     render_pass: n::RenderPass,
-    framebuffer: n::FrameBuffer,
+    framebuffer: n::FatFrameBuffer,
     attachment_clears: Vec<AttachmentClear>,
 }
 
@@ -151,6 +291,8 @@ struct Cache {
     index_type: Option<hal::IndexType>,
     // Stencil reference values (front, back).
     stencil_ref: Option<(pso::StencilValue, pso::StencilValue)>,
+    // Stencil test and culled faces, set by the current pipeline.
+    stencil: Option<(pso::StencilTest, pso::Face)>,
     // Blend color.
     blend_color: Option<pso::ColorValue>,
     ///
@@ -164,12 +306,18 @@ struct Cache {
     program: Option<gl::types::GLuint>,
     // Blend per attachment.
     blend_targets: Option<Vec<Option<pso::ColorBlendDesc>>>,
-    // Maps bound vertex buffer offset (index) to handle.
-    vertex_buffers: Vec<gl::types::GLuint>,
+    // Maps bound vertex buffer binding (index) to handle and offset.
+    vertex_buffers: Vec<(gl::types::GLuint, buffer::Offset)>,
     // Active vertex buffer descriptions.
     vertex_buffer_descs: Vec<Option<pso::VertexBufferDesc>>,
     // Active attributes.
     attributes: Vec<n::AttributeDesc>,
+    // Attribute bindings of the last draw, to skip rebinding unchanged ones.
+    bound_attributes: Option<Vec<n::AttributeBinding>>,
+    // Push constant values, persisting across pipeline changes.
+    push_constants: Vec<u32>,
+    // Uniforms of the active program emulating push constants.
+    push_constant_uniforms: Vec<n::PushConstantUniform>,
 }
 
 impl Cache {
@@ -178,6 +326,7 @@ impl Cache {
             primitive: None,
             index_type: None,
             stencil_ref: None,
+            stencil: None,
             blend_color: None,
             framebuffer: None,
             error_state: false,
@@ -187,21 +336,28 @@ impl Cache {
             vertex_buffers: Vec::new(),
             vertex_buffer_descs: Vec::new(),
             attributes: Vec::new(),
+            bound_attributes: None,
+            push_constants: Vec::new(),
+            push_constant_uniforms: Vec::new(),
         }
     }
 }
 
-// This is a subset of the device limits stripped down to the ones needed
-// for command buffer validation.
+// This is a subset of the device limits and capabilities stripped down to
+// the ones needed for command buffer validation and recording.
 #[derive(Debug, Clone, Copy)]
 pub struct Limits {
     max_viewports: usize,
+    clear_texture: bool,
+    invalidate_framebuffer: bool,
 }
 
-impl From<hal::Limits> for Limits {
-    fn from(l: hal::Limits) -> Self {
+impl Limits {
+    pub(crate) fn new(l: &hal::Limits, caps: &PrivateCaps) -> Self {
         Limits {
             max_viewports: l.max_viewports,
+            clear_texture: caps.clear_texture,
+            invalidate_framebuffer: caps.invalidate_framebuffer,
         }
     }
 }
@@ -219,6 +375,7 @@ pub struct RawCommandBuffer {
     individual_reset: bool,
 
     fbo: Option<n::FrameBuffer>,
+    read_fbo: Option<n::FrameBuffer>,
     /// The framebuffer to use for rendering to the main targets (0 by default).
     ///
     /// Use this to set the framebuffer that will be used for the screen display targets created
@@ -241,6 +398,7 @@ pub struct RawCommandBuffer {
 impl RawCommandBuffer {
     pub(crate) fn new(
         fbo: Option<n::FrameBuffer>,
+        read_fbo: Option<n::FrameBuffer>,
         limits: Limits,
         memory: Arc<Mutex<BufferMemory>>,
     ) -> Self {
@@ -270,6 +428,7 @@ impl RawCommandBuffer {
             id,
             individual_reset,
             fbo,
+            read_fbo,
             display_fb: 0 as n::FrameBuffer,
             cache: Cache::new(),
             pass_cache: None,
@@ -320,6 +479,164 @@ impl RawCommandBuffer {
         slice
     }
 
+    fn bind_descriptor_sets<I, J>(
+        &mut self,
+        layout: &n::PipelineLayout,
+        first_set: usize,
+        sets: I,
+        offsets: J,
+    ) where
+        I: IntoIterator,
+        I::Item: Borrow<n::DescriptorSet>,
+        J: IntoIterator,
+        J::Item: Borrow<command::DescriptorSetOffset>,
+    {
+        assert!(offsets.into_iter().next().is_none()); // TODO: offsets unsupported
+
+        let mut set = first_set as _;
+        let drd = &*layout.desc_remap_data.read().unwrap();
+
+        for desc_set in sets {
+            let desc_set = desc_set.borrow();
+            let bindings = desc_set.bindings.lock().unwrap();
+            for new_binding in &*bindings {
+                match new_binding {
+                    n::DescSetBindings::Buffer {
+                        ty: btype,
+                        binding,
+                        buffer,
+                        offset,
+                        size,
+                    } => {
+                        let target = match btype {
+                            n::BindingTypes::UniformBuffers => gl::UNIFORM_BUFFER,
+                            n::BindingTypes::StorageBuffers => gl::SHADER_STORAGE_BUFFER,
+                            n::BindingTypes::Images => panic!("Wrong desc set binding"),
+                        };
+                        for binding in drd.get_binding(*btype, set, *binding).unwrap() {
+                            self.push_cmd(Command::BindBufferRange(
+                                target, *binding, *buffer, *offset, *size,
+                            ))
+                        }
+                    }
+                    n::DescSetBindings::Texture(binding, texture, target) => {
+                        for binding in drd
+                            .get_binding(n::BindingTypes::Images, set, *binding)
+                            .unwrap()
+                        {
+                            self.push_cmd(Command::BindTexture(*binding, *texture, *target))
+                        }
+                    }
+                    n::DescSetBindings::Sampler(binding, sampler) => {
+                        for binding in drd
+                            .get_binding(n::BindingTypes::Images, set, *binding)
+                            .unwrap()
+                        {
+                            self.push_cmd(Command::BindSampler(*binding, *sampler))
+                        }
+                    }
+                    n::DescSetBindings::SamplerInfo(binding, sinfo) => {
+                        let mut all_txts = drd
+                            .get_binding(n::BindingTypes::Images, set, *binding)
+                            .unwrap()
+                            .into_iter()
+                            .flat_map(|binding| {
+                                bindings.iter().filter_map(move |b| {
+                                    if let n::DescSetBindings::Texture(b, t, target) = b {
+                                        let nbs =
+                                            drd.get_binding(n::BindingTypes::Images, set, *b)?;
+                                        if nbs.contains(binding) {
+                                            Some((*binding, *t, *target))
+                                        } else {
+                                            None
+                                        }
+                                    } else {
+                                        None
+                                    }
+                                })
+                            })
+                            .collect::<Vec<_>>();
+
+                        // TODO: Check that other samplers aren't using the same
+                        // textures as in `all_txts` unless all the bindings of that
+                        // texture are gonna be unbound or the two samplers have
+                        // identical properties.
+
+                        all_txts.sort_unstable_by(|a, b| a.1.cmp(&b.1));
+                        all_txts.dedup_by(|a, b| a.1 == b.1);
+
+                        for (binding, txt, target) in all_txts {
+                            self.push_cmd(Command::SetTextureSamplerSettings(
+                                binding,
+                                txt,
+                                target,
+                                sinfo.clone(),
+                            ))
+                        }
+                    }
+                }
+            }
+            set += 1;
+        }
+    }
+
+    fn update_stencil(&mut self) {
+        if let Some((stencil, cull)) = self.cache.stencil {
+            let refs = self.cache.stencil_ref.unwrap_or((0, 0));
+            self.push_cmd(Command::BindStencil(stencil, refs, cull));
+        }
+    }
+
+    /// Upload the push constant uniforms of the active program overlapping
+    /// the given range, in bytes.
+    fn update_push_constants(&mut self, range: Range<u32>) {
+        let mut commands = Vec::new();
+        for uniform in &self.cache.push_constant_uniforms {
+            let (columns, rows) = conv::uniform_type_dimensions(uniform.ty).unwrap();
+            let stride = if columns > 1 {
+                uniform.matrix_stride
+            } else {
+                rows * 4
+            };
+            let end = uniform.offset + (columns - 1) * stride + rows * 4;
+            if end <= range.start || uniform.offset >= range.end {
+                continue;
+            }
+
+            // Matrix columns may be padded in the push constant block.
+            let mut values = Vec::with_capacity((columns * rows) as usize);
+            for column in 0..columns {
+                let start = ((uniform.offset + column * stride) / 4) as usize;
+                for row in 0..rows as usize {
+                    values.push(
+                        self.cache
+                            .push_constants
+                            .get(start + row)
+                            .cloned()
+                            .unwrap_or(0),
+                    );
+                }
+            }
+            commands.push((uniform.location, uniform.ty, values));
+        }
+
+        for (location, ty, values) in commands {
+            let data = self.add(&values);
+            self.push_cmd(Command::SetUniform { location, ty, data });
+        }
+    }
+
+    fn push_constants(&mut self, offset: u32, constants: &[u32]) {
+        assert_eq!(offset % 4, 0);
+        let start = offset as usize / 4;
+        let end = start + constants.len();
+        if self.cache.push_constants.len() < end {
+            self.cache.push_constants.resize(end, 0);
+        }
+        self.cache.push_constants[start..end].copy_from_slice(constants);
+        self.update_push_constants(offset..offset + constants.len() as u32 * 4);
+    }
+
     fn update_blend_targets(&mut self, blend_targets: &Vec<pso::ColorBlendDesc>) {
         let max_blend_slots = blend_targets.len();
 
@@ -364,39 +681,51 @@ impl RawCommandBuffer {
     }
 
     pub(crate) fn bind_attributes(&mut self) {
-        let Cache {
-            ref attributes,
-            ref vertex_buffers,
-            ref vertex_buffer_descs,
-            ..
-        } = self.cache;
-
-        for attribute in attributes {
-            let binding = attribute.binding as usize;
-
-            if vertex_buffers.len() <= binding {
-                error!("No vertex buffer bound at {}", binding);
-            }
+        let bindings = {
+            let Cache {
+                ref attributes,
+                ref vertex_buffers,
+                ref vertex_buffer_descs,
+                ..
+            } = self.cache;
+
+            attributes
+                .iter()
+                .filter_map(|attribute| {
+                    let binding = attribute.binding as usize;
+
+                    let (buffer, offset) = match vertex_buffers.get(binding) {
+                        Some(&vb) => vb,
+                        None => {
+                            error!("No vertex buffer bound at {}", binding);
+                            return None;
+                        }
+                    };
+
+                    match vertex_buffer_descs.get(binding) {
+                        Some(&Some(desc)) => Some(n::AttributeBinding {
+                            attribute: *attribute,
+                            buffer,
+                            offset,
+                            stride: desc.stride as _,
+                            rate: desc.rate.as_uint() as u32,
+                        }),
+                        _ => {
+                            error!("No vertex buffer description bound at {}", binding);
+                            None
+                        }
+                    }
+                })
+                .collect::<Vec<_>>()
+        };
 
-            let handle = vertex_buffers[binding];
-
-            match vertex_buffer_descs.get(binding) {
-                Some(&Some(desc)) => {
-                    push_cmd_internal(
-                        &self.id,
-                        &mut self.memory,
-                        &mut self.buf,
-                        Command::BindAttribute(
-                            attribute.clone(),
-                            handle,
-                            desc.stride as _,
-                            desc.rate.as_uint() as u32,
-                        ),
-                    );
-                }
-                _ => error!("No vertex buffer description bound at {}", binding),
-            }
+        if self.cache.bound_attributes.as_ref() == Some(&bindings) {
+            return;
         }
+
+        let slice = self.add(&bindings);
+        self.push_cmd(Command::BindAttributes(slice));
+        self.cache.bound_attributes = Some(bindings);
     }
 
     fn begin_subpass(&mut self) {
@@ -409,7 +738,7 @@ impl RawCommandBuffer {
 
             // Bind draw buffers for mapping color output locations with
             // framebuffer attachments.
-            let draw_buffers = if state.framebuffer == n::DEFAULT_FRAMEBUFFER {
+            let draw_buffers = if state.framebuffer.raw == n::DEFAULT_FRAMEBUFFER {
                 // The default framebuffer is created by the driver
                 // We don't have influence on its layout and we treat it as single image.
                 //
@@ -490,14 +819,144 @@ impl RawCommandBuffer {
             (draw_buffers, clear_cmds)
         };
 
+        // Encode the color writes only if the targets are sRGB.
+        let srgb = {
+            let state = self.pass_cache.as_ref().unwrap();
+            state.render_pass.subpasses[self.cur_subpass]
+                .color_attachments
+                .iter()
+                .any(|&id| {
+                    state.render_pass.attachments[id]
+                        .format
+                        .map_or(false, |format| format.base_format().1 == ChannelType::Srgb)
+                })
+        };
+
         // Record commands
         let draw_buffers = self.add(&draw_buffers);
         self.push_cmd(Command::DrawBuffers(draw_buffers));
+        self.push_cmd(Command::SetFramebufferSrgb(srgb));
 
         for cmd in clear_cmds {
             self.push_cmd(cmd);
         }
     }
+
+    fn end_subpass(&mut self) {
+        // Resolve the color attachments at the end of the subpass.
+        let resolves = {
+            let state = self.pass_cache.as_ref().unwrap();
+            let subpass = &state.render_pass.subpasses[self.cur_subpass];
+            subpass
+                .color_attachments
+                .iter()
+                .zip(&subpass.resolve_attachments)
+                .filter_map(|(&src, &dst)| {
+                    let resolve = state
+                        .framebuffer
+                        .resolves
+                        .iter()
+                        .find(|&&(id, _)| id == dst);
+                    resolve.map(|&(_, resolve)| Command::ResolveAttachment {
+                        src: state.framebuffer.raw,
+                        attachment: gl::COLOR_ATTACHMENT0 + src as gl::types::GLenum,
+                        dst: resolve,
+                        extent: state.framebuffer.extent,
+                    })
+                })
+                .collect::<Vec<_>>()
+        };
+
+        for cmd in resolves {
+            self.push_cmd(cmd);
+        }
+    }
+
+    /// Begin capturing the outputs of the bound pipeline into the given buffer
+    /// ranges, one per capture binding. The progress is tracked by `feedback`,
+    /// so the capture can be paused, resumed and drawn later on.
+    ///
+    /// The bound pipeline needs to be created with `Device::create_graphics_pipeline_with_capture`.
+    pub unsafe fn begin_transform_feedback<'a, I, R>(
+        &mut self,
+        feedback: n::TransformFeedback,
+        buffers: I,
+    ) where
+        I: IntoIterator<Item = (&'a n::Buffer, R)>,
+        R: RangeArg<buffer::Offset>,
+    {
+        let primitive = match self.cache.primitive {
+            Some(gl::POINTS) => gl::POINTS,
+            Some(gl::LINES)
+            | Some(gl::LINE_STRIP)
+            | Some(gl::LINES_ADJACENCY)
+            | Some(gl::LINE_STRIP_ADJACENCY) => gl::LINES,
+            Some(_) => gl::TRIANGLES,
+            None => {
+                warn!("No primitive bound. An active pipeline needs to be bound before calling `begin_transform_feedback`.");
+                self.cache.error_state = true;
+                return;
+            }
+        };
+
+        self.push_cmd(Command::BindTransformFeedback(feedback));
+        for (index, (buffer, range)) in buffers.into_iter().enumerate() {
+            let offset = *range.start().unwrap_or(&0);
+            let size = *range.end().unwrap_or(&buffer.requirements.size) - offset;
+            self.push_cmd(Command::BindBufferRange(
+                gl::TRANSFORM_FEEDBACK_BUFFER,
+                index as _,
+                buffer.raw,
+                offset as _,
+                size as _,
+            ));
+        }
+        self.push_cmd(Command::BeginTransformFeedback(primitive));
+    }
+
+    /// Override the sRGB encoding of the color writes for the rest of the subpass.
+    ///
+    /// By default, it's enabled for subpasses with sRGB color attachments.
+    pub unsafe fn set_srgb_encoding(&mut self, enable: bool) {
+        self.push_cmd(Command::SetFramebufferSrgb(enable));
+    }
+
+    /// Stop capturing temporarily, e.g. for draws which shouldn't be recorded.
+    pub unsafe fn pause_transform_feedback(&mut self) {
+        self.push_cmd(Command::PauseTransformFeedback);
+    }
+
+    pub unsafe fn resume_transform_feedback(&mut self) {
+        self.push_cmd(Command::ResumeTransformFeedback);
+    }
+
+    pub unsafe fn end_transform_feedback(&mut self) {
+        self.push_cmd(Command::EndTransformFeedback);
+    }
+
+    /// Draw the vertices written by the last capture into `feedback`,
+    /// without reading the vertex count back.
+    pub unsafe fn draw_transform_feedback(
+        &mut self,
+        feedback: n::TransformFeedback,
+        instances: hal::InstanceCount,
+    ) {
+        self.bind_attributes();
+
+        match self.cache.primitive {
+            Some(primitive) => {
+                self.push_cmd(Command::DrawTransformFeedback {
+                    primitive,
+                    feedback,
+                    instances,
+                });
+            }
+            None => {
+                warn!("No primitive bound. An active pipeline needs to be bound before calling `draw_transform_feedback`.");
+                self.cache.error_state = true;
+            }
+        }
+    }
 }
 
 impl command::RawCommandBuffer<Backend> for RawCommandBuffer {
@@ -550,19 +1009,76 @@ impl command::RawCommandBuffer<Backend> for RawCommandBuffer {
         &mut self,
         _stages: Range<hal::pso::PipelineStage>,
         _dependencies: memory::Dependencies,
-        _barriers: T,
+        barriers: T,
     ) where
         T: IntoIterator,
         T::Item: Borrow<memory::Barrier<'a, Backend>>,
     {
-        // TODO
+        // GL keeps the other accesses coherent, only the shader writes need explicit barriers
+        let mut bits = 0;
+        for barrier in barriers {
+            match *barrier.borrow() {
+                memory::Barrier::AllBuffers(ref access) => {
+                    if access.start.contains(buffer::Access::SHADER_WRITE) {
+                        bits |= conv::buffer_access_to_barrier(access.end);
+                    }
+                }
+                memory::Barrier::AllImages(ref access) => {
+                    if access.start.contains(image::Access::SHADER_WRITE) {
+                        bits |= conv::image_access_to_barrier(access.end);
+                    }
+                }
+                memory::Barrier::Buffer { ref states, .. } => {
+                    if states.start.contains(buffer::Access::SHADER_WRITE) {
+                        bits |= conv::buffer_access_to_barrier(states.end);
+                    }
+                }
+                memory::Barrier::Image { ref states, .. } => {
+                    if states.start.0.contains(image::Access::SHADER_WRITE) {
+                        bits |= conv::image_access_to_barrier(states.end.0);
+                    }
+                }
+            }
+        }
+        if bits != 0 {
+            self.push_cmd(Command::MemoryBarrier(bits));
+        }
+    }
+
+    unsafe fn set_event(&mut self, event: &n::Event, _: hal::pso::PipelineStage) {
+        // Commands execute in order, so the barriers
+        // recorded by `wait_events` are sufficient on the device.
+        self.push_cmd(Command::SetEvent(event.clone(), true));
+    }
+
+    unsafe fn reset_event(&mut self, event: &n::Event, _: hal::pso::PipelineStage) {
+        self.push_cmd(Command::SetEvent(event.clone(), false));
+    }
+
+    unsafe fn wait_events<'a, I, J>(
+        &mut self,
+        _: I,
+        stages: Range<hal::pso::PipelineStage>,
+        barriers: J,
+    ) where
+        I: IntoIterator,
+        I::Item: Borrow<n::Event>,
+        J: IntoIterator,
+        J::Item: Borrow<memory::Barrier<'a, Backend>>,
+    {
+        self.pipeline_barrier(stages, memory::Dependencies::empty(), barriers)
     }
 
-    unsafe fn fill_buffer<R>(&mut self, _buffer: &n::Buffer, _range: R, _data: u32)
+    unsafe fn fill_buffer<R>(&mut self, buffer: &n::Buffer, range: R, data: u32)
     where
         R: RangeArg<buffer::Offset>,
     {
-        unimplemented!()
+        let start = *range.start().unwrap_or(&0);
+        let end = *range.end().unwrap_or(&buffer.requirements.size);
+        assert_eq!(start % 4, 0);
+        assert_eq!(end % 4, 0);
+
+        self.push_cmd(Command::FillBuffer(buffer.raw, start..end, data));
     }
 
     unsafe fn update_buffer(&mut self, _buffer: &n::Buffer, _offset: buffer::Offset, _data: &[u8]) {
@@ -572,7 +1088,7 @@ impl command::RawCommandBuffer<Backend> for RawCommandBuffer {
     unsafe fn begin_render_pass<T>(
         &mut self,
         render_pass: &n::RenderPass,
-        framebuffer: &n::FrameBuffer,
+        framebuffer: &n::FatFrameBuffer,
         _render_area: pso::Rect,
         clear_values: T,
         _first_subpass: command::SubpassContents,
@@ -595,7 +1111,10 @@ impl command::RawCommandBuffer<Backend> for RawCommandBuffer {
         //  >= GL 4.5: Invalidate framebuffer attachment when store op is `DONT_CARE`.
 
         // 2./3.
-        self.push_cmd(Command::BindFrameBuffer(gl::DRAW_FRAMEBUFFER, *framebuffer));
+        self.push_cmd(Command::BindFrameBuffer(
+            gl::DRAW_FRAMEBUFFER,
+            framebuffer.raw,
+        ));
 
         let mut clear_values_iter = clear_values.into_iter();
         let attachment_clears = render_pass
@@ -626,7 +1145,7 @@ impl command::RawCommandBuffer<Backend> for RawCommandBuffer {
 
         self.pass_cache = Some(RenderPassCache {
             render_pass: render_pass.clone(),
-            framebuffer: *framebuffer,
+            framebuffer: framebuffer.clone(),
             attachment_clears,
         });
 
@@ -636,11 +1155,69 @@ impl command::RawCommandBuffer<Backend> for RawCommandBuffer {
     }
 
     unsafe fn next_subpass(&mut self, _contents: command::SubpassContents) {
-        unimplemented!()
+        self.end_subpass();
+        self.cur_subpass += 1;
+        self.begin_subpass();
     }
 
     unsafe fn end_render_pass(&mut self) {
-        // TODO
+        if self.pass_cache.is_none() {
+            return;
+        }
+        self.end_subpass();
+        let state = self.pass_cache.take().unwrap();
+        if !self.limits.invalidate_framebuffer {
+            return;
+        }
+
+        // Discard the attachments which aren't stored, saving the
+        // write back of the tile memory on tiling GPUs.
+        let default = state.framebuffer.raw == n::DEFAULT_FRAMEBUFFER;
+        let mut attachments = Vec::new();
+        for (i, attachment) in state.render_pass.attachments.iter().enumerate() {
+            let format = match attachment.format {
+                Some(format) => format,
+                None => continue,
+            };
+            // Resolve attachments live in their own framebuffers.
+            if state.render_pass.is_resolve(i) {
+                continue;
+            }
+            let discard = attachment.ops.store == pass::AttachmentStoreOp::DontCare;
+            if format.is_color() {
+                if discard {
+                    attachments.push(if default {
+                        gl::COLOR
+                    } else {
+                        gl::COLOR_ATTACHMENT0 + i as gl::types::GLenum
+                    });
+                }
+                continue;
+            }
+
+            let discard_depth = format.is_depth() && discard;
+            let discard_stencil = format.is_stencil()
+                && attachment.stencil_ops.store == pass::AttachmentStoreOp::DontCare;
+            if discard_depth {
+                attachments.push(if default {
+                    gl::DEPTH
+                } else {
+                    gl::DEPTH_ATTACHMENT
+                });
+            }
+            if discard_stencil {
+                attachments.push(if default {
+                    gl::STENCIL
+                } else {
+                    gl::STENCIL_ATTACHMENT
+                });
+            }
+        }
+
+        if !attachments.is_empty() {
+            let slice = self.add(&attachments);
+            self.push_cmd(Command::InvalidateFramebuffer(gl::DRAW_FRAMEBUFFER, slice));
+        }
     }
 
     unsafe fn clear_image<T>(
@@ -649,7 +1226,7 @@ impl command::RawCommandBuffer<Backend> for RawCommandBuffer {
         _: image::Layout,
         color: command::ClearColorRaw,
         _depth_stencil: command::ClearDepthStencilRaw,
-        _subresource_ranges: T,
+        subresource_ranges: T,
     ) where
         T: IntoIterator,
         T::Item: Borrow<image::SubresourceRange>,
@@ -659,13 +1236,45 @@ impl command::RawCommandBuffer<Backend> for RawCommandBuffer {
         //  2.  < GL 4.4: glClearBuffer
         //  3. >= GL 4.4: glClearTexSubImage
 
-        match self.fbo {
-            Some(fbo) => {
-                // TODO: reset color mask
-                // 2. ClearBuffer
-                let view = match image.kind {
+        if let (true, n::ImageKind::Texture(texture)) = (self.limits.clear_texture, image.kind) {
+            // 3. ClearTexSubImage
+            let (format, ty) = match image.channel {
+                ChannelType::Uint => (gl::RGBA_INTEGER, gl::UNSIGNED_INT),
+                ChannelType::Int => (gl::RGBA_INTEGER, gl::INT),
+                _ => (gl::RGBA, gl::FLOAT),
+            };
+            for range in subresource_ranges {
+                let range = range.borrow();
+                let layers = range.layers.start..cmp::min(range.layers.end, image.layers);
+                for level in range.levels.start..cmp::min(range.levels.end, image.levels) {
+                    let extent = image.extent.at_level(level);
+                    // The layers of array and cube textures are addressed by depth.
+                    let (z, depth) = if image.target == gl::TEXTURE_3D {
+                        (0, extent.depth as _)
+                    } else {
+                        (layers.start as _, (layers.end - layers.start) as _)
+                    };
+                    self.push_cmd(Command::ClearTexSubImage {
+                        texture,
+                        level,
+                        offset: [0, 0, z],
+                        extent: [extent.width as _, extent.height as _, depth],
+                        format,
+                        ty,
+                        value: color.uint32,
+                    });
+                }
+            }
+            return;
+        }
+
+        match self.fbo {
+            Some(fbo) => {
+                // TODO: reset color mask
+                // 2. ClearBuffer
+                let view = match image.kind {
                     n::ImageKind::Surface(id) => n::ImageView::Surface(id),
-                    n::ImageKind::Texture(id) => n::ImageView::Texture(id, 0), //TODO
+                    n::ImageKind::Texture(id) => n::ImageView::Texture(id, image.target, 0), //TODO
                 };
                 self.push_cmd(Command::BindFrameBuffer(gl::DRAW_FRAMEBUFFER, fbo));
                 self.push_cmd(Command::BindTargetView(
@@ -696,7 +1305,7 @@ impl command::RawCommandBuffer<Backend> for RawCommandBuffer {
                     n::ImageKind::Surface(_id) => unimplemented!(),
                 };
 
-                self.push_cmd(Command::BindTexture(0, text));
+                self.push_cmd(Command::BindTexture(0, text, image.target));
                 self.push_cmd(Command::ClearTexture(color.float32));
             }
         }
@@ -714,31 +1323,116 @@ impl command::RawCommandBuffer<Backend> for RawCommandBuffer {
 
     unsafe fn resolve_image<T>(
         &mut self,
-        _src: &n::Image,
+        src: &n::Image,
         _src_layout: image::Layout,
-        _dst: &n::Image,
+        dst: &n::Image,
         _dst_layout: image::Layout,
-        _regions: T,
+        regions: T,
     ) where
         T: IntoIterator,
         T::Item: Borrow<command::ImageResolve>,
     {
-        unimplemented!()
+        debug_assert_eq!(src.format, dst.format);
+        debug_assert!(src.samples > 1 && dst.samples == 1);
+
+        let (draw_fbo, read_fbo) = match (self.fbo, self.read_fbo) {
+            (Some(draw_fbo), Some(read_fbo)) => (draw_fbo, read_fbo),
+            _ => {
+                error!("Image resolves require framebuffer objects");
+                return;
+            }
+        };
+
+        self.push_cmd(Command::BindFrameBuffer(gl::READ_FRAMEBUFFER, read_fbo));
+        self.push_cmd(Command::BindFrameBuffer(gl::DRAW_FRAMEBUFFER, draw_fbo));
+        self.push_cmd(Command::SetDrawColorBuffers(1));
+
+        for region in regions {
+            let r = region.borrow();
+            debug_assert_eq!(
+                r.src_subresource.layers.end - r.src_subresource.layers.start,
+                r.dst_subresource.layers.end - r.dst_subresource.layers.start
+            );
+            let layers = r
+                .src_subresource
+                .layers
+                .clone()
+                .zip(r.dst_subresource.layers.clone());
+            for (src_layer, dst_layer) in layers {
+                self.push_cmd(Command::BindTargetView(
+                    gl::READ_FRAMEBUFFER,
+                    gl::COLOR_ATTACHMENT0,
+                    layer_view(src, r.src_subresource.level, src_layer),
+                ));
+                self.push_cmd(Command::BindTargetView(
+                    gl::DRAW_FRAMEBUFFER,
+                    gl::COLOR_ATTACHMENT0,
+                    layer_view(dst, r.dst_subresource.level, dst_layer),
+                ));
+                self.push_cmd(Command::BlitFramebuffer {
+                    src: region_bounds(r.src_offset, r.extent),
+                    dst: region_bounds(r.dst_offset, r.extent),
+                    mask: gl::COLOR_BUFFER_BIT,
+                    filter: gl::NEAREST,
+                });
+            }
+        }
     }
 
     unsafe fn blit_image<T>(
         &mut self,
-        _src: &n::Image,
+        src: &n::Image,
         _src_layout: image::Layout,
-        _dst: &n::Image,
+        dst: &n::Image,
         _dst_layout: image::Layout,
-        _filter: image::Filter,
-        _regions: T,
+        filter: image::Filter,
+        regions: T,
     ) where
         T: IntoIterator,
         T::Item: Borrow<command::ImageBlit>,
     {
-        unimplemented!()
+        let (draw_fbo, read_fbo) = match (self.fbo, self.read_fbo) {
+            (Some(draw_fbo), Some(read_fbo)) => (draw_fbo, read_fbo),
+            _ => {
+                error!("Image blits require framebuffer objects");
+                return;
+            }
+        };
+
+        self.push_cmd(Command::BindFrameBuffer(gl::READ_FRAMEBUFFER, read_fbo));
+        self.push_cmd(Command::BindFrameBuffer(gl::DRAW_FRAMEBUFFER, draw_fbo));
+        self.push_cmd(Command::SetDrawColorBuffers(1));
+
+        for region in regions {
+            let r = region.borrow();
+            let (attachment, mask) = copy_attachment(r.src_subresource.aspects);
+            // Depth and stencil can only be blitted without filtering.
+            let filter = match (filter, mask) {
+                (image::Filter::Linear, gl::COLOR_BUFFER_BIT) => gl::LINEAR,
+                _ => gl::NEAREST,
+            };
+            // Scaling in depth isn't supported, slices are blitted one to one.
+            let src_layers = copy_layers(src, &r.src_subresource.layers, &r.src_bounds);
+            let dst_layers = copy_layers(dst, &r.dst_subresource.layers, &r.dst_bounds);
+            for (src_layer, dst_layer) in src_layers.zip(dst_layers) {
+                self.push_cmd(Command::BindTargetView(
+                    gl::READ_FRAMEBUFFER,
+                    attachment,
+                    layer_view(src, r.src_subresource.level, src_layer),
+                ));
+                self.push_cmd(Command::BindTargetView(
+                    gl::DRAW_FRAMEBUFFER,
+                    attachment,
+                    layer_view(dst, r.dst_subresource.level, dst_layer),
+                ));
+                self.push_cmd(Command::BlitFramebuffer {
+                    src: r.src_bounds.clone(),
+                    dst: r.dst_bounds.clone(),
+                    mask,
+                    filter,
+                });
+            }
+        }
     }
 
     unsafe fn bind_index_buffer(&mut self, ibv: buffer::IndexBufferView<Backend>) {
@@ -759,12 +1453,9 @@ impl command::RawCommandBuffer<Backend> for RawCommandBuffer {
         for (i, (buffer, offset)) in buffers.into_iter().enumerate() {
             let index = first_binding as usize + i;
             if self.cache.vertex_buffers.len() <= index {
-                self.cache.vertex_buffers.resize(index + 1, 0);
-            }
-            self.cache.vertex_buffers[index] = buffer.borrow().raw;
-            if offset != 0 {
-                error!("Vertex buffer offset {} is not supported", offset);
+                self.cache.vertex_buffers.resize(index + 1, (0, 0));
             }
+            self.cache.vertex_buffers[index] = (buffer.borrow().raw, offset);
         }
     }
 
@@ -872,6 +1563,7 @@ impl command::RawCommandBuffer<Backend> for RawCommandBuffer {
         // we assembled all the pieces to set the stencil state
         // from the pipeline.
         self.cache.stencil_ref = Some((front, back));
+        self.update_stencil();
     }
 
     unsafe fn set_stencil_read_mask(&mut self, _faces: pso::Face, _value: pso::StencilValue) {
@@ -897,18 +1589,26 @@ impl command::RawCommandBuffer<Backend> for RawCommandBuffer {
         unimplemented!()
     }
 
-    unsafe fn set_depth_bias(&mut self, _depth_bias: pso::DepthBias) {
-        unimplemented!()
+    unsafe fn set_depth_bias(&mut self, depth_bias: pso::DepthBias) {
+        self.push_cmd(Command::SetDepthBias(depth_bias));
     }
 
     unsafe fn bind_graphics_pipeline(&mut self, pipeline: &n::GraphicsPipeline) {
         let n::GraphicsPipeline {
             primitive,
+            primitive_restart,
             patch_size,
+            sample_shading,
+            sample_coverage,
             program,
             ref blend_targets,
+            logic_op,
+            ref rasterizer,
+            depth_stencil,
+            ref baked_states,
             ref attributes,
             ref vertex_buffers,
+            ref push_constants,
         } = *pipeline;
 
         if self.cache.primitive != Some(primitive) {
@@ -927,11 +1627,37 @@ impl command::RawCommandBuffer<Backend> for RawCommandBuffer {
             self.push_cmd(Command::BindProgram(program));
         }
 
+        self.cache.push_constant_uniforms = push_constants.clone();
+        self.update_push_constants(0..!0);
+
         self.cache.attributes = attributes.clone();
 
         self.cache.vertex_buffer_descs = vertex_buffers.clone();
 
         self.update_blend_targets(blend_targets);
+        self.push_cmd(Command::SetLogicOp(logic_op));
+
+        // Redundant changes are skipped by the queue, which knows the actual GL state.
+        self.push_cmd(Command::BindRasterizer(rasterizer.clone()));
+        self.push_cmd(Command::BindDepth(depth_stencil.depth));
+        self.push_cmd(Command::SetPrimitiveRestart(primitive_restart));
+        self.push_cmd(Command::SetSampleShading(sample_shading));
+        self.push_cmd(Command::SetSampleCoverage(sample_coverage));
+        self.cache.stencil = Some((depth_stencil.stencil, rasterizer.cull_face));
+        self.update_stencil();
+
+        if let Some(ref vp) = baked_states.viewport {
+            self.set_viewports(0, iter::once(vp));
+        }
+        if let Some(ref rect) = baked_states.scissor {
+            self.set_scissors(0, iter::once(rect));
+        }
+        if let Some(color) = baked_states.blend_color {
+            self.set_blend_constants(color);
+        }
+        if let Some(ref bounds) = baked_states.depth_bounds {
+            self.set_depth_bounds(bounds.clone());
+        }
     }
 
     unsafe fn bind_graphics_descriptor_sets<I, J>(
@@ -946,118 +1672,37 @@ impl command::RawCommandBuffer<Backend> for RawCommandBuffer {
         J: IntoIterator,
         J::Item: Borrow<command::DescriptorSetOffset>,
     {
-        assert!(offsets.into_iter().next().is_none()); // TODO: offsets unsupported
-
-        let mut set = first_set as _;
-        let drd = &*layout.desc_remap_data.read().unwrap();
-
-        for desc_set in sets {
-            let desc_set = desc_set.borrow();
-            let bindings = desc_set.bindings.lock().unwrap();
-            for new_binding in &*bindings {
-                match new_binding {
-                    n::DescSetBindings::Buffer {
-                        ty: btype,
-                        binding,
-                        buffer,
-                        offset,
-                        size,
-                    } => {
-                        let btype = match btype {
-                            n::BindingTypes::UniformBuffers => gl::UNIFORM_BUFFER,
-                            n::BindingTypes::Images => panic!("Wrong desc set binding"),
-                        };
-                        for binding in drd
-                            .get_binding(n::BindingTypes::UniformBuffers, set, *binding)
-                            .unwrap()
-                        {
-                            self.push_cmd(Command::BindBufferRange(
-                                btype, *binding, *buffer, *offset, *size,
-                            ))
-                        }
-                    }
-                    n::DescSetBindings::Texture(binding, texture) => {
-                        for binding in drd
-                            .get_binding(n::BindingTypes::Images, set, *binding)
-                            .unwrap()
-                        {
-                            self.push_cmd(Command::BindTexture(*binding, *texture))
-                        }
-                    }
-                    n::DescSetBindings::Sampler(binding, sampler) => {
-                        for binding in drd
-                            .get_binding(n::BindingTypes::Images, set, *binding)
-                            .unwrap()
-                        {
-                            self.push_cmd(Command::BindSampler(*binding, *sampler))
-                        }
-                    }
-                    n::DescSetBindings::SamplerInfo(binding, sinfo) => {
-                        let mut all_txts = drd
-                            .get_binding(n::BindingTypes::Images, set, *binding)
-                            .unwrap()
-                            .into_iter()
-                            .flat_map(|binding| {
-                                bindings.iter().filter_map(move |b| {
-                                    if let n::DescSetBindings::Texture(b, t) = b {
-                                        let nbs =
-                                            drd.get_binding(n::BindingTypes::Images, set, *b)?;
-                                        if nbs.contains(binding) {
-                                            Some((*binding, *t))
-                                        } else {
-                                            None
-                                        }
-                                    } else {
-                                        None
-                                    }
-                                })
-                            })
-                            .collect::<Vec<_>>();
-
-                        // TODO: Check that other samplers aren't using the same
-                        // textures as in `all_txts` unless all the bindings of that
-                        // texture are gonna be unbound or the two samplers have
-                        // identical properties.
-
-                        all_txts.sort_unstable_by(|a, b| a.1.cmp(&b.1));
-                        all_txts.dedup_by(|a, b| a.1 == b.1);
-
-                        for (binding, txt) in all_txts {
-                            self.push_cmd(Command::SetTextureSamplerSettings(
-                                binding,
-                                txt,
-                                sinfo.clone(),
-                            ))
-                        }
-                    }
-                }
-            }
-            set += 1;
-        }
+        self.bind_descriptor_sets(layout, first_set, sets, offsets);
     }
 
     unsafe fn bind_compute_pipeline(&mut self, pipeline: &n::ComputePipeline) {
-        let n::ComputePipeline { program } = *pipeline;
+        let n::ComputePipeline {
+            program,
+            ref push_constants,
+        } = *pipeline;
 
         if self.cache.program != Some(program) {
             self.cache.program = Some(program);
             self.push_cmd(Command::BindProgram(program));
         }
+
+        self.cache.push_constant_uniforms = push_constants.clone();
+        self.update_push_constants(0..!0);
     }
 
     unsafe fn bind_compute_descriptor_sets<I, J>(
         &mut self,
-        _layout: &n::PipelineLayout,
-        _first_set: usize,
-        _sets: I,
-        _offsets: J,
+        layout: &n::PipelineLayout,
+        first_set: usize,
+        sets: I,
+        offsets: J,
     ) where
         I: IntoIterator,
         I::Item: Borrow<n::DescriptorSet>,
         J: IntoIterator,
         J::Item: Borrow<command::DescriptorSetOffset>,
     {
-        // TODO
+        self.bind_descriptor_sets(layout, first_set, sets, offsets);
     }
 
     unsafe fn dispatch(&mut self, count: hal::WorkGroupCount) {
@@ -1097,18 +1742,49 @@ impl command::RawCommandBuffer<Backend> for RawCommandBuffer {
         T: IntoIterator,
         T::Item: Borrow<command::ImageCopy>,
     {
-        let old_offset = self.buf.offset;
+        let (draw_fbo, read_fbo) = match (self.fbo, self.read_fbo) {
+            (Some(draw_fbo), Some(read_fbo)) => (draw_fbo, read_fbo),
+            _ => {
+                error!("Image copies require framebuffer objects");
+                return;
+            }
+        };
+
+        // Copies are blits between single layer attachments of the same size.
+        self.push_cmd(Command::BindFrameBuffer(gl::READ_FRAMEBUFFER, read_fbo));
+        self.push_cmd(Command::BindFrameBuffer(gl::DRAW_FRAMEBUFFER, draw_fbo));
+        self.push_cmd(Command::SetDrawColorBuffers(1));
+
+        let old_size = self.buf.size;
 
         for region in regions {
-            let r = region.borrow().clone();
-            let cmd = match dst.kind {
-                n::ImageKind::Surface(s) => Command::CopyImageToSurface(src.kind, s, r),
-                n::ImageKind::Texture(t) => Command::CopyImageToTexture(src.kind, t, r),
-            };
-            self.push_cmd(cmd);
+            let r = region.borrow();
+            let (attachment, mask) = copy_attachment(r.src_subresource.aspects);
+            let src_bounds = region_bounds(r.src_offset, r.extent);
+            let dst_bounds = region_bounds(r.dst_offset, r.extent);
+            let src_layers = copy_layers(src, &r.src_subresource.layers, &src_bounds);
+            let dst_layers = copy_layers(dst, &r.dst_subresource.layers, &dst_bounds);
+            for (src_layer, dst_layer) in src_layers.zip(dst_layers) {
+                self.push_cmd(Command::BindTargetView(
+                    gl::READ_FRAMEBUFFER,
+                    attachment,
+                    layer_view(src, r.src_subresource.level, src_layer),
+                ));
+                self.push_cmd(Command::BindTargetView(
+                    gl::DRAW_FRAMEBUFFER,
+                    attachment,
+                    layer_view(dst, r.dst_subresource.level, dst_layer),
+                ));
+                self.push_cmd(Command::BlitFramebuffer {
+                    src: src_bounds.clone(),
+                    dst: dst_bounds.clone(),
+                    mask,
+                    filter: gl::NEAREST,
+                });
+            }
         }
 
-        if self.buf.offset == old_offset {
+        if self.buf.size == old_size {
             error!("At least one region must be specified");
         }
     }
@@ -1125,13 +1801,64 @@ impl command::RawCommandBuffer<Backend> for RawCommandBuffer {
     {
         let old_size = self.buf.size;
 
+        let texture = match dst.kind {
+            n::ImageKind::Texture(texture) => texture,
+            n::ImageKind::Surface(_) => {
+                error!(
+                    "Renderbuffers can't be copied into, the image requires `Usage::TRANSFER_DST`"
+                );
+                return;
+            }
+        };
+        let compressed = conv::compressed_format_to_gl(dst.format);
+        let uncompressed = conv::image_format_to_gl(dst.format);
+
         for region in regions {
-            let r = region.borrow().clone();
-            let cmd = match dst.kind {
-                n::ImageKind::Surface(s) => Command::CopyBufferToSurface(src.raw, s, r),
-                n::ImageKind::Texture(t) => Command::CopyBufferToTexture(src.raw, t, r),
+            let region = region.borrow();
+            // Cube faces are separate 2D targets.
+            let regions = if dst.target == gl::TEXTURE_CUBE_MAP {
+                split_layers(dst, region)
+            } else {
+                vec![region.clone()]
             };
-            self.push_cmd(cmd);
+            for r in regions {
+                let cmd = match (compressed, uncompressed) {
+                    (Some(format), _) => {
+                        if (r.buffer_width != 0 && r.buffer_width != r.image_extent.width)
+                            || (r.buffer_height != 0 && r.buffer_height != r.image_extent.height)
+                        {
+                            warn!("Compressed image copies require tightly packed buffer data");
+                        }
+                        let layers =
+                            (r.image_layers.layers.end - r.image_layers.layers.start) as u32;
+                        let extent = image::Extent {
+                            depth: r.image_extent.depth * layers,
+                            ..r.image_extent
+                        };
+                        Command::CopyBufferToCompressedTexture {
+                            buffer: src.raw,
+                            texture,
+                            target: dst.target,
+                            format,
+                            size: conv::image_data_size(dst.format, extent) as _,
+                            region: r,
+                        }
+                    }
+                    (None, Some((_, format, ty))) => Command::CopyBufferToTexture {
+                        buffer: src.raw,
+                        texture,
+                        target: dst.target,
+                        format,
+                        ty,
+                        region: r,
+                    },
+                    (None, None) => {
+                        error!("Unable to copy into an image of format {:?}", dst.format);
+                        return;
+                    }
+                };
+                self.push_cmd(cmd);
+            }
         }
 
         if self.buf.size == old_size {
@@ -1151,13 +1878,44 @@ impl command::RawCommandBuffer<Backend> for RawCommandBuffer {
     {
         let old_size = self.buf.size;
 
+        let read_fbo = match (self.read_fbo, src.kind) {
+            (Some(read_fbo), _) => read_fbo,
+            (None, n::ImageKind::Texture(texture)) => {
+                for region in regions {
+                    let r = region.borrow().clone();
+                    self.push_cmd(Command::CopyTextureToBuffer(texture, dst.raw, r));
+                }
+                return;
+            }
+            (None, n::ImageKind::Surface(_)) => {
+                error!("Renderbuffer copies require framebuffer objects");
+                return;
+            }
+        };
+        let (format, ty) = match conv::image_format_to_gl(src.format) {
+            Some((_, format, ty)) => (format, ty),
+            None => {
+                error!("Unable to copy from an image of format {:?}", src.format);
+                return;
+            }
+        };
+
+        // Read back the layers one at a time from a framebuffer.
+        self.push_cmd(Command::BindFrameBuffer(gl::READ_FRAMEBUFFER, read_fbo));
         for region in regions {
-            let r = region.borrow().clone();
-            let cmd = match src.kind {
-                n::ImageKind::Surface(s) => Command::CopySurfaceToBuffer(s, dst.raw, r),
-                n::ImageKind::Texture(t) => Command::CopyTextureToBuffer(t, dst.raw, r),
-            };
-            self.push_cmd(cmd);
+            for r in split_layers(src, region.borrow()) {
+                self.push_cmd(Command::BindTargetView(
+                    gl::READ_FRAMEBUFFER,
+                    copy_attachment(r.image_layers.aspects).0,
+                    layer_view(src, r.image_layers.level, r.image_layers.layers.start),
+                ));
+                self.push_cmd(Command::CopyFramebufferToBuffer {
+                    buffer: dst.raw,
+                    format,
+                    ty,
+                    region: r,
+                });
+            }
         }
 
         if self.buf.size == old_size {
@@ -1224,78 +1982,285 @@ impl command::RawCommandBuffer<Backend> for RawCommandBuffer {
 
     unsafe fn draw_indirect(
         &mut self,
-        _buffer: &n::Buffer,
-        _offset: buffer::Offset,
-        _draw_count: hal::DrawCount,
-        _stride: u32,
+        buffer: &n::Buffer,
+        offset: buffer::Offset,
+        draw_count: hal::DrawCount,
+        stride: u32,
     ) {
-        unimplemented!()
+        self.bind_attributes();
+
+        match self.cache.primitive {
+            Some(primitive) => {
+                self.push_cmd(Command::DrawIndirect {
+                    primitive,
+                    buffer: buffer.raw,
+                    offset,
+                    draw_count,
+                    stride,
+                });
+            }
+            None => {
+                warn!("No primitive bound. An active pipeline needs to be bound before calling `draw_indirect`.");
+                self.cache.error_state = true;
+            }
+        }
     }
 
     unsafe fn draw_indexed_indirect(
         &mut self,
-        _buffer: &n::Buffer,
-        _offset: buffer::Offset,
-        _draw_count: hal::DrawCount,
-        _stride: u32,
+        buffer: &n::Buffer,
+        offset: buffer::Offset,
+        draw_count: hal::DrawCount,
+        stride: u32,
     ) {
-        unimplemented!()
+        self.bind_attributes();
+
+        let index_type = match self.cache.index_type {
+            Some(hal::IndexType::U16) => gl::UNSIGNED_SHORT,
+            Some(hal::IndexType::U32) => gl::UNSIGNED_INT,
+            None => {
+                warn!("No index type bound. An index buffer needs to be bound before calling `draw_indexed_indirect`.");
+                self.cache.error_state = true;
+                return;
+            }
+        };
+        match self.cache.primitive {
+            Some(primitive) => {
+                self.push_cmd(Command::DrawIndexedIndirect {
+                    primitive,
+                    index_type,
+                    buffer: buffer.raw,
+                    offset,
+                    draw_count,
+                    stride,
+                });
+            }
+            None => {
+                warn!("No primitive bound. An active pipeline needs to be bound before calling `draw_indexed_indirect`.");
+                self.cache.error_state = true;
+            }
+        }
     }
 
-    unsafe fn begin_query(&mut self, _query: query::Query<Backend>, _flags: query::ControlFlags) {
-        unimplemented!()
+    unsafe fn begin_query(&mut self, query: query::Query<Backend>, _flags: query::ControlFlags) {
+        // Occlusion queries are always precise on desktop GL, while ES doesn't support it.
+        let pool = query.pool;
+        for (&target, &id) in pool.targets.iter().zip(pool.queries(query.id)) {
+            self.push_cmd(Command::BeginQuery(target, id));
+        }
     }
 
     unsafe fn copy_query_pool_results(
         &mut self,
-        _pool: &(),
-        _queries: Range<query::Id>,
-        _buffer: &n::Buffer,
-        _offset: buffer::Offset,
-        _stride: buffer::Offset,
-        _flags: query::ResultFlags,
+        pool: &n::QueryPool,
+        queries: Range<query::Id>,
+        buffer: &n::Buffer,
+        offset: buffer::Offset,
+        stride: buffer::Offset,
+        flags: query::ResultFlags,
     ) {
-        unimplemented!()
+        let size = if flags.contains(query::ResultFlags::BITS_64) {
+            8
+        } else {
+            4
+        };
+        let last = pool.targets.len() - 1;
+        for (i, id) in queries.enumerate() {
+            // Values are written back to back, followed by a single availability value.
+            for (k, &raw) in pool.queries(id).iter().enumerate() {
+                let mut flags = flags;
+                if k != last {
+                    flags.remove(query::ResultFlags::WITH_AVAILABILITY);
+                }
+                self.push_cmd(Command::CopyQueryResult {
+                    query: raw,
+                    buffer: buffer.raw,
+                    offset: offset + i as buffer::Offset * stride + k as buffer::Offset * size,
+                    flags,
+                });
+            }
+        }
     }
 
-    unsafe fn end_query(&mut self, _query: query::Query<Backend>) {
-        unimplemented!()
+    unsafe fn end_query(&mut self, query: query::Query<Backend>) {
+        for &target in &query.pool.targets {
+            self.push_cmd(Command::EndQuery(target));
+        }
     }
 
-    unsafe fn reset_query_pool(&mut self, _pool: &(), _queries: Range<query::Id>) {
-        unimplemented!()
+    unsafe fn reset_query_pool(&mut self, _pool: &n::QueryPool, _queries: Range<query::Id>) {
+        // Nothing to do, GL resets the results when a query begins.
     }
 
-    unsafe fn write_timestamp(&mut self, _: pso::PipelineStage, _: query::Query<Backend>) {
-        unimplemented!()
+    unsafe fn write_timestamp(&mut self, _: pso::PipelineStage, query: query::Query<Backend>) {
+        let id = query.pool.queries(query.id)[0];
+        self.push_cmd(Command::QueryCounter(id));
+    }
+
+    unsafe fn begin_conditional_rendering(
+        &mut self,
+        buffer: &n::Buffer,
+        offset: buffer::Offset,
+        inverted: bool,
+    ) {
+        self.push_cmd(Command::BeginConditionalRendering {
+            buffer: buffer.raw,
+            offset,
+            inverted,
+        });
+    }
+
+    unsafe fn end_conditional_rendering(&mut self) {
+        self.push_cmd(Command::EndConditionalRendering);
     }
 
     unsafe fn push_graphics_constants(
         &mut self,
         _layout: &n::PipelineLayout,
         _stages: pso::ShaderStageFlags,
-        _offset: u32,
-        _constants: &[u32],
+        offset: u32,
+        constants: &[u32],
     ) {
-        unimplemented!()
+        // All the stages share the program, and with it the uniforms.
+        self.push_constants(offset, constants);
     }
 
     unsafe fn push_compute_constants(
         &mut self,
         _layout: &n::PipelineLayout,
-        _offset: u32,
-        _constants: &[u32],
+        offset: u32,
+        constants: &[u32],
     ) {
-        unimplemented!()
+        self.push_constants(offset, constants);
     }
 
-    unsafe fn execute_commands<'a, T, I>(&mut self, _buffers: I)
+    unsafe fn execute_commands<'a, T, I>(&mut self, buffers: I)
     where
         T: 'a + Borrow<RawCommandBuffer>,
         I: IntoIterator<Item = &'a T>,
     {
-        unimplemented!()
+        // The commands are executed from the memory of the secondary command
+        // buffers, which must not be reset before the primary one completes.
+        for buffer in buffers {
+            let buffer = buffer.borrow();
+            self.push_cmd(Command::ExecuteCommands {
+                memory: buffer.memory.clone(),
+                id: buffer.id,
+                buf: buffer.buf,
+            });
+        }
+
+        // The state is undefined after executing secondary command buffers.
+        self.cache = Cache::new();
     }
+
+    unsafe fn begin_debug_marker(&mut self, name: &str, _color: u32) {
+        let label = self.add_raw(name.as_bytes());
+        self.push_cmd(Command::PushDebugGroup(label));
+    }
+
+    unsafe fn end_debug_marker(&mut self) {
+        self.push_cmd(Command::PopDebugGroup);
+    }
+
+    unsafe fn insert_debug_marker(&mut self, name: &str, _color: u32) {
+        let label = self.add_raw(name.as_bytes());
+        self.push_cmd(Command::InsertDebugMarker(label));
+    }
+}
+
+/// View of a single layer of the image, or depth slice of a 3D image,
+/// as layered attachments can't be read or blitted.
+fn layer_view(image: &n::Image, level: image::Level, layer: image::Layer) -> n::ImageView {
+    match image.kind {
+        n::ImageKind::Surface(surface) => n::ImageView::Surface(surface),
+        n::ImageKind::Texture(texture) if image.layers == 1 && image.target != gl::TEXTURE_3D => {
+            n::ImageView::Texture(texture, image.target, level)
+        }
+        n::ImageKind::Texture(texture) => {
+            n::ImageView::TextureLayer(texture, image.target, level, layer)
+        }
+    }
+}
+
+/// Framebuffer attachment point and blit mask of the copied aspects.
+fn copy_attachment(aspects: Aspects) -> (AttachmentPoint, gl::types::GLbitfield) {
+    if aspects.contains(Aspects::DEPTH | Aspects::STENCIL) {
+        (
+            gl::DEPTH_STENCIL_ATTACHMENT,
+            gl::DEPTH_BUFFER_BIT | gl::STENCIL_BUFFER_BIT,
+        )
+    } else if aspects.contains(Aspects::DEPTH) {
+        (gl::DEPTH_ATTACHMENT, gl::DEPTH_BUFFER_BIT)
+    } else if aspects.contains(Aspects::STENCIL) {
+        (gl::STENCIL_ATTACHMENT, gl::STENCIL_BUFFER_BIT)
+    } else {
+        (gl::COLOR_ATTACHMENT0, gl::COLOR_BUFFER_BIT)
+    }
+}
+
+/// Bounds of a copy region.
+fn region_bounds(offset: image::Offset, extent: image::Extent) -> Range<image::Offset> {
+    offset..image::Offset {
+        x: offset.x + extent.width as i32,
+        y: offset.y + extent.height as i32,
+        z: offset.z + extent.depth as i32,
+    }
+}
+
+/// Layers of a copy region, which are the depth slices for 3D images.
+fn copy_layers(
+    image: &n::Image,
+    layers: &Range<image::Layer>,
+    bounds: &Range<image::Offset>,
+) -> Range<image::Layer> {
+    if image.target == gl::TEXTURE_3D {
+        let (start, end) = (bounds.start.z, bounds.end.z);
+        start.min(end) as image::Layer..start.max(end) as image::Layer
+    } else {
+        layers.clone()
+    }
+}
+
+/// Split a buffer-image copy into regions covering a single layer,
+/// or depth slice of a 3D image, each.
+fn split_layers(image: &n::Image, r: &command::BufferImageCopy) -> Vec<command::BufferImageCopy> {
+    let bounds = region_bounds(r.image_offset, r.image_extent);
+    let layers = copy_layers(image, &r.image_layers.layers, &bounds);
+    let layer_extent = image::Extent {
+        width: if r.buffer_width == 0 {
+            r.image_extent.width
+        } else {
+            r.buffer_width
+        },
+        height: if r.buffer_height == 0 {
+            r.image_extent.height
+        } else {
+            r.buffer_height
+        },
+        depth: 1,
+    };
+    let layer_size = conv::image_data_size(image.format, layer_extent);
+
+    layers
+        .enumerate()
+        .map(|(i, layer)| command::BufferImageCopy {
+            buffer_offset: r.buffer_offset + i as buffer::Offset * layer_size,
+            image_layers: image::SubresourceLayers {
+                layers: layer..layer + 1,
+                ..r.image_layers.clone()
+            },
+            image_offset: image::Offset {
+                z: 0,
+                ..r.image_offset
+            },
+            image_extent: image::Extent {
+                depth: 1,
+                ..r.image_extent
+            },
+            ..r.clone()
+        })
+        .collect()
 }
 
 /// Avoids creating second mutable borrows of `self` by requiring mutable
@@ -1,5 +1,6 @@
 use gl::{self, types as t};
-use hal::format::Format;
+use hal::format::{Component, Format};
+use hal::query::PipelineStatistic as Ps;
 use hal::{buffer, image as i, Primitive};
 use native::VertexAttribFunction;
 
@@ -18,6 +19,29 @@ pub fn _image_kind_to_gl(kind: i::Kind) -> t::GLenum {
     }
 }*/
 
+pub fn view_kind_to_gl(kind: i::ViewKind) -> t::GLenum {
+    match kind {
+        i::ViewKind::D1 => gl::TEXTURE_1D,
+        i::ViewKind::D1Array => gl::TEXTURE_1D_ARRAY,
+        i::ViewKind::D2 => gl::TEXTURE_2D,
+        i::ViewKind::D2Array => gl::TEXTURE_2D_ARRAY,
+        i::ViewKind::D3 => gl::TEXTURE_3D,
+        i::ViewKind::Cube => gl::TEXTURE_CUBE_MAP,
+        i::ViewKind::CubeArray => gl::TEXTURE_CUBE_MAP_ARRAY,
+    }
+}
+
+pub fn component_to_gl(component: Component) -> t::GLenum {
+    match component {
+        Component::Zero => gl::ZERO,
+        Component::One => gl::ONE,
+        Component::R => gl::RED,
+        Component::G => gl::GREEN,
+        Component::B => gl::BLUE,
+        Component::A => gl::ALPHA,
+    }
+}
+
 pub fn filter_to_gl(mag: i::Filter, min: i::Filter, mip: i::Filter) -> (t::GLenum, t::GLenum) {
     use hal::image::Filter::*;
 
@@ -47,15 +71,81 @@ pub fn wrap_to_gl(w: i::WrapMode) -> t::GLenum {
 
 pub fn buffer_usage_to_gl_target(usage: buffer::Usage) -> Option<t::GLenum> {
     use self::buffer::Usage;
-    match usage & (Usage::UNIFORM | Usage::INDEX | Usage::VERTEX | Usage::INDIRECT) {
+    let roles = Usage::UNIFORM | Usage::STORAGE | Usage::INDEX | Usage::VERTEX | Usage::INDIRECT;
+    match usage & roles {
         Usage::UNIFORM => Some(gl::UNIFORM_BUFFER),
+        Usage::STORAGE => Some(gl::SHADER_STORAGE_BUFFER),
         Usage::INDEX => Some(gl::ELEMENT_ARRAY_BUFFER),
         Usage::VERTEX => Some(gl::ARRAY_BUFFER),
-        Usage::INDIRECT => unimplemented!(),
+        Usage::INDIRECT => Some(gl::DRAW_INDIRECT_BUFFER),
         _ => None,
     }
 }
 
+/// Returns the `glMemoryBarrier` bits needed for the shader writes
+/// to be visible to the given buffer accesses.
+pub fn buffer_access_to_barrier(access: buffer::Access) -> t::GLbitfield {
+    use self::buffer::Access;
+    let mut bits = 0;
+    if access.contains(Access::INDIRECT_COMMAND_READ) {
+        bits |= gl::COMMAND_BARRIER_BIT;
+    }
+    if access.contains(Access::INDEX_BUFFER_READ) {
+        bits |= gl::ELEMENT_ARRAY_BARRIER_BIT;
+    }
+    if access.contains(Access::VERTEX_BUFFER_READ) {
+        bits |= gl::VERTEX_ATTRIB_ARRAY_BARRIER_BIT;
+    }
+    if access.contains(Access::CONSTANT_BUFFER_READ) {
+        bits |= gl::UNIFORM_BARRIER_BIT;
+    }
+    if access.intersects(Access::SHADER_READ | Access::SHADER_WRITE) {
+        bits |= gl::SHADER_STORAGE_BARRIER_BIT;
+    }
+    if access.intersects(Access::TRANSFER_READ | Access::TRANSFER_WRITE) {
+        bits |= gl::BUFFER_UPDATE_BARRIER_BIT;
+    }
+    if access.intersects(Access::HOST_READ | Access::HOST_WRITE) {
+        bits |= gl::BUFFER_UPDATE_BARRIER_BIT;
+    }
+    if access.contains(Access::CONDITIONAL_RENDERING_READ) {
+        // The predicate is read back like a host access.
+        bits |= gl::BUFFER_UPDATE_BARRIER_BIT;
+    }
+    if access.intersects(Access::MEMORY_READ | Access::MEMORY_WRITE) {
+        bits |= gl::ALL_BARRIER_BITS;
+    }
+    bits
+}
+
+/// Returns the `glMemoryBarrier` bits needed for the shader writes
+/// to be visible to the given image accesses.
+pub fn image_access_to_barrier(access: i::Access) -> t::GLbitfield {
+    use self::i::Access;
+    let mut bits = 0;
+    if access.intersects(Access::SHADER_READ | Access::SHADER_WRITE) {
+        bits |= gl::SHADER_IMAGE_ACCESS_BARRIER_BIT | gl::TEXTURE_FETCH_BARRIER_BIT;
+    }
+    if access.intersects(
+        Access::COLOR_ATTACHMENT_READ
+            | Access::COLOR_ATTACHMENT_WRITE
+            | Access::DEPTH_STENCIL_ATTACHMENT_READ
+            | Access::DEPTH_STENCIL_ATTACHMENT_WRITE,
+    ) {
+        bits |= gl::FRAMEBUFFER_BARRIER_BIT;
+    }
+    if access.intersects(Access::TRANSFER_READ | Access::TRANSFER_WRITE) {
+        bits |= gl::TEXTURE_UPDATE_BARRIER_BIT;
+    }
+    if access.intersects(Access::HOST_READ | Access::HOST_WRITE) {
+        bits |= gl::PIXEL_BUFFER_BARRIER_BIT;
+    }
+    if access.intersects(Access::MEMORY_READ | Access::MEMORY_WRITE) {
+        bits |= gl::ALL_BARRIER_BITS;
+    }
+    bits
+}
+
 pub fn primitive_to_gl_primitive(primitive: Primitive) -> t::GLenum {
     match primitive {
         Primitive::PointList => gl::POINTS,
@@ -71,6 +161,199 @@ pub fn primitive_to_gl_primitive(primitive: Primitive) -> t::GLenum {
     }
 }
 
+/// Query targets of `ARB_pipeline_statistics_query`, missing from the generated bindings.
+const VERTICES_SUBMITTED: t::GLenum = 0x82EE;
+const PRIMITIVES_SUBMITTED: t::GLenum = 0x82EF;
+const VERTEX_SHADER_INVOCATIONS: t::GLenum = 0x82F0;
+const TESS_CONTROL_SHADER_PATCHES: t::GLenum = 0x82F1;
+const TESS_EVALUATION_SHADER_INVOCATIONS: t::GLenum = 0x82F2;
+const GEOMETRY_SHADER_PRIMITIVES_EMITTED: t::GLenum = 0x82F3;
+const FRAGMENT_SHADER_INVOCATIONS: t::GLenum = 0x82F4;
+const COMPUTE_SHADER_INVOCATIONS: t::GLenum = 0x82F5;
+const CLIPPING_INPUT_PRIMITIVES: t::GLenum = 0x82F6;
+const CLIPPING_OUTPUT_PRIMITIVES: t::GLenum = 0x82F7;
+
+/// Query targets of the enabled statistics, in the order their results are written.
+pub fn pipeline_statistics_to_gl(statistics: Ps) -> Vec<t::GLenum> {
+    [
+        (Ps::INPUT_ASSEMBLY_VERTICES, VERTICES_SUBMITTED),
+        (Ps::INPUT_ASSEMBLY_PRIMITIVES, PRIMITIVES_SUBMITTED),
+        (Ps::VERTEX_SHADER_INVOCATIONS, VERTEX_SHADER_INVOCATIONS),
+        (
+            Ps::GEOMETRY_SHADER_INVOCATIONS,
+            gl::GEOMETRY_SHADER_INVOCATIONS,
+        ),
+        (
+            Ps::GEOMETRY_SHADER_PRIMITIVES,
+            GEOMETRY_SHADER_PRIMITIVES_EMITTED,
+        ),
+        (Ps::CLIPPING_INVOCATIONS, CLIPPING_INPUT_PRIMITIVES),
+        (Ps::CLIPPING_PRIMITIVES, CLIPPING_OUTPUT_PRIMITIVES),
+        (Ps::FRAGMENT_SHADER_INVOCATIONS, FRAGMENT_SHADER_INVOCATIONS),
+        (Ps::HULL_SHADER_PATCHES, TESS_CONTROL_SHADER_PATCHES),
+        (
+            Ps::DOMAIN_SHADER_INVOCATIONS,
+            TESS_EVALUATION_SHADER_INVOCATIONS,
+        ),
+        (Ps::COMPUTE_SHADER_INVOCATIONS, COMPUTE_SHADER_INVOCATIONS),
+    ]
+    .iter()
+    .filter(|&&(statistic, _)| statistics.contains(statistic))
+    .map(|&(_, target)| target)
+    .collect()
+}
+
+/// Number of columns and rows of a uniform type that can hold push constants.
+pub fn uniform_type_dimensions(ty: t::GLenum) -> Option<(u32, u32)> {
+    Some(match ty {
+        gl::FLOAT | gl::INT | gl::UNSIGNED_INT | gl::BOOL => (1, 1),
+        gl::FLOAT_VEC2 | gl::INT_VEC2 | gl::UNSIGNED_INT_VEC2 | gl::BOOL_VEC2 => (1, 2),
+        gl::FLOAT_VEC3 | gl::INT_VEC3 | gl::UNSIGNED_INT_VEC3 | gl::BOOL_VEC3 => (1, 3),
+        gl::FLOAT_VEC4 | gl::INT_VEC4 | gl::UNSIGNED_INT_VEC4 | gl::BOOL_VEC4 => (1, 4),
+        gl::FLOAT_MAT2 => (2, 2),
+        gl::FLOAT_MAT3 => (3, 3),
+        gl::FLOAT_MAT4 => (4, 4),
+        _ => return None,
+    })
+}
+
+/// Internal format, pixel format and pixel type of a texture format.
+pub fn image_format_to_gl(format: Format) -> Option<(t::GLenum, t::GLenum, t::GLenum)> {
+    use gl::*;
+    use hal::format::Format::*;
+    Some(match format {
+        R8Unorm => (R8, RED, UNSIGNED_BYTE),
+        R8Inorm => (R8_SNORM, RED, BYTE),
+        R8Uint => (R8UI, RED_INTEGER, UNSIGNED_BYTE),
+        R8Int => (R8I, RED_INTEGER, BYTE),
+        Rg8Unorm => (RG8, RG, UNSIGNED_BYTE),
+        Rg8Inorm => (RG8_SNORM, RG, BYTE),
+        Rg8Uint => (RG8UI, RG_INTEGER, UNSIGNED_BYTE),
+        Rg8Int => (RG8I, RG_INTEGER, BYTE),
+        Rgba8Unorm => (RGBA8, RGBA, UNSIGNED_BYTE),
+        Rgba8Inorm => (RGBA8_SNORM, RGBA, BYTE),
+        Rgba8Uint => (RGBA8UI, RGBA_INTEGER, UNSIGNED_BYTE),
+        Rgba8Int => (RGBA8I, RGBA_INTEGER, BYTE),
+        Rgba8Srgb => (SRGB8_ALPHA8, RGBA, UNSIGNED_BYTE),
+        A2b10g10r10Unorm => (RGB10_A2, RGBA, UNSIGNED_INT_2_10_10_10_REV),
+        A2b10g10r10Uint => (RGB10_A2UI, RGBA_INTEGER, UNSIGNED_INT_2_10_10_10_REV),
+        R16Uint => (R16UI, RED_INTEGER, UNSIGNED_SHORT),
+        R16Int => (R16I, RED_INTEGER, SHORT),
+        R16Float => (R16F, RED, HALF_FLOAT),
+        Rg16Uint => (RG16UI, RG_INTEGER, UNSIGNED_SHORT),
+        Rg16Int => (RG16I, RG_INTEGER, SHORT),
+        Rg16Float => (RG16F, RG, HALF_FLOAT),
+        Rgba16Uint => (RGBA16UI, RGBA_INTEGER, UNSIGNED_SHORT),
+        Rgba16Int => (RGBA16I, RGBA_INTEGER, SHORT),
+        Rgba16Float => (RGBA16F, RGBA, HALF_FLOAT),
+        R32Uint => (R32UI, RED_INTEGER, UNSIGNED_INT),
+        R32Int => (R32I, RED_INTEGER, INT),
+        R32Float => (R32F, RED, FLOAT),
+        Rg32Uint => (RG32UI, RG_INTEGER, UNSIGNED_INT),
+        Rg32Int => (RG32I, RG_INTEGER, INT),
+        Rg32Float => (RG32F, RG, FLOAT),
+        Rgba32Uint => (RGBA32UI, RGBA_INTEGER, UNSIGNED_INT),
+        Rgba32Int => (RGBA32I, RGBA_INTEGER, INT),
+        Rgba32Float => (RGBA32F, RGBA, FLOAT),
+        B10g11r11Ufloat => (R11F_G11F_B10F, RGB, UNSIGNED_INT_10F_11F_11F_REV),
+        E5b9g9r9Ufloat => (RGB9_E5, RGB, UNSIGNED_INT_5_9_9_9_REV),
+        D16Unorm => (DEPTH_COMPONENT16, DEPTH_COMPONENT, UNSIGNED_SHORT),
+        X8D24Unorm => (DEPTH_COMPONENT24, DEPTH_COMPONENT, UNSIGNED_INT),
+        D32Float => (DEPTH_COMPONENT32F, DEPTH_COMPONENT, FLOAT),
+        D24UnormS8Uint => (DEPTH24_STENCIL8, DEPTH_STENCIL, UNSIGNED_INT_24_8),
+        D32FloatS8Uint => (
+            DEPTH32F_STENCIL8,
+            DEPTH_STENCIL,
+            FLOAT_32_UNSIGNED_INT_24_8_REV,
+        ),
+        _ => return None,
+    })
+}
+
+/// Internal format of a block-compressed texture format.
+pub fn compressed_format_to_gl(format: Format) -> Option<t::GLenum> {
+    use hal::format::Format::*;
+    Some(match format {
+        Etc2R8g8b8Unorm => gl::COMPRESSED_RGB8_ETC2,
+        Etc2R8g8b8Srgb => gl::COMPRESSED_SRGB8_ETC2,
+        Etc2R8g8b8a1Unorm => gl::COMPRESSED_RGB8_PUNCHTHROUGH_ALPHA1_ETC2,
+        Etc2R8g8b8a1Srgb => gl::COMPRESSED_SRGB8_PUNCHTHROUGH_ALPHA1_ETC2,
+        Etc2R8g8b8a8Unorm => gl::COMPRESSED_RGBA8_ETC2_EAC,
+        Etc2R8g8b8a8Srgb => gl::COMPRESSED_SRGB8_ALPHA8_ETC2_EAC,
+        EacR11Unorm => gl::COMPRESSED_R11_EAC,
+        EacR11Inorm => gl::COMPRESSED_SIGNED_R11_EAC,
+        EacR11g11Unorm => gl::COMPRESSED_RG11_EAC,
+        EacR11g11Inorm => gl::COMPRESSED_SIGNED_RG11_EAC,
+        Astc4x4Unorm => gl::COMPRESSED_RGBA_ASTC_4x4_KHR,
+        Astc4x4Srgb => gl::COMPRESSED_SRGB8_ALPHA8_ASTC_4x4_KHR,
+        Astc5x4Unorm => gl::COMPRESSED_RGBA_ASTC_5x4_KHR,
+        Astc5x4Srgb => gl::COMPRESSED_SRGB8_ALPHA8_ASTC_5x4_KHR,
+        Astc5x5Unorm => gl::COMPRESSED_RGBA_ASTC_5x5_KHR,
+        Astc5x5Srgb => gl::COMPRESSED_SRGB8_ALPHA8_ASTC_5x5_KHR,
+        Astc6x5Unorm => gl::COMPRESSED_RGBA_ASTC_6x5_KHR,
+        Astc6x5Srgb => gl::COMPRESSED_SRGB8_ALPHA8_ASTC_6x5_KHR,
+        Astc6x6Unorm => gl::COMPRESSED_RGBA_ASTC_6x6_KHR,
+        Astc6x6Srgb => gl::COMPRESSED_SRGB8_ALPHA8_ASTC_6x6_KHR,
+        Astc8x5Unorm => gl::COMPRESSED_RGBA_ASTC_8x5_KHR,
+        Astc8x5Srgb => gl::COMPRESSED_SRGB8_ALPHA8_ASTC_8x5_KHR,
+        Astc8x6Unorm => gl::COMPRESSED_RGBA_ASTC_8x6_KHR,
+        Astc8x6Srgb => gl::COMPRESSED_SRGB8_ALPHA8_ASTC_8x6_KHR,
+        Astc8x8Unorm => gl::COMPRESSED_RGBA_ASTC_8x8_KHR,
+        Astc8x8Srgb => gl::COMPRESSED_SRGB8_ALPHA8_ASTC_8x8_KHR,
+        Astc10x5Unorm => gl::COMPRESSED_RGBA_ASTC_10x5_KHR,
+        Astc10x5Srgb => gl::COMPRESSED_SRGB8_ALPHA8_ASTC_10x5_KHR,
+        Astc10x6Unorm => gl::COMPRESSED_RGBA_ASTC_10x6_KHR,
+        Astc10x6Srgb => gl::COMPRESSED_SRGB8_ALPHA8_ASTC_10x6_KHR,
+        Astc10x8Unorm => gl::COMPRESSED_RGBA_ASTC_10x8_KHR,
+        Astc10x8Srgb => gl::COMPRESSED_SRGB8_ALPHA8_ASTC_10x8_KHR,
+        Astc10x10Unorm => gl::COMPRESSED_RGBA_ASTC_10x10_KHR,
+        Astc10x10Srgb => gl::COMPRESSED_SRGB8_ALPHA8_ASTC_10x10_KHR,
+        Astc12x10Unorm => gl::COMPRESSED_RGBA_ASTC_12x10_KHR,
+        Astc12x10Srgb => gl::COMPRESSED_SRGB8_ALPHA8_ASTC_12x10_KHR,
+        Astc12x12Unorm => gl::COMPRESSED_RGBA_ASTC_12x12_KHR,
+        Astc12x12Srgb => gl::COMPRESSED_SRGB8_ALPHA8_ASTC_12x12_KHR,
+        Bc1RgbUnorm => gl::COMPRESSED_RGB_S3TC_DXT1_EXT,
+        Bc1RgbSrgb => gl::COMPRESSED_SRGB_S3TC_DXT1_EXT,
+        Bc1RgbaUnorm => gl::COMPRESSED_RGBA_S3TC_DXT1_EXT,
+        Bc1RgbaSrgb => gl::COMPRESSED_SRGB_ALPHA_S3TC_DXT1_EXT,
+        Bc2Unorm => gl::COMPRESSED_RGBA_S3TC_DXT3_EXT,
+        Bc2Srgb => gl::COMPRESSED_SRGB_ALPHA_S3TC_DXT3_EXT,
+        Bc3Unorm => gl::COMPRESSED_RGBA_S3TC_DXT5_EXT,
+        Bc3Srgb => gl::COMPRESSED_SRGB_ALPHA_S3TC_DXT5_EXT,
+        Bc4Unorm => gl::COMPRESSED_RED_RGTC1,
+        Bc4Inorm => gl::COMPRESSED_SIGNED_RED_RGTC1,
+        Bc5Unorm => gl::COMPRESSED_RG_RGTC2,
+        Bc5Inorm => gl::COMPRESSED_SIGNED_RG_RGTC2,
+        Bc6hUfloat => gl::COMPRESSED_RGB_BPTC_UNSIGNED_FLOAT,
+        Bc6hFloat => gl::COMPRESSED_RGB_BPTC_SIGNED_FLOAT,
+        Bc7Unorm => gl::COMPRESSED_RGBA_BPTC_UNORM,
+        Bc7Srgb => gl::COMPRESSED_SRGB_ALPHA_BPTC_UNORM,
+        _ => return None,
+    })
+}
+
+/// Device feature required by a block-compressed format.
+pub fn compressed_format_feature(format: Format) -> hal::Features {
+    use hal::format::SurfaceType::*;
+    match format.base_format().0 {
+        ETC2_R8_G8_B8 | ETC2_R8_G8_B8_A1 | ETC2_R8_G8_B8_A8 | EAC_R11 | EAC_R11_G11 => {
+            hal::Features::FORMAT_ETC2
+        }
+        BC1_RGB | BC1_RGBA | BC2 | BC3 | BC4 | BC5 | BC6 | BC7 => hal::Features::FORMAT_BC,
+        _ => hal::Features::FORMAT_ASTC_LDR,
+    }
+}
+
+/// Size in bytes of the texel blocks covering `extent`, which only differs
+/// from the texel count for compressed formats.
+pub fn image_data_size(format: Format, extent: i::Extent) -> u64 {
+    let desc = format.surface_desc();
+    let (block_width, block_height) = (desc.dim.0 as u64, desc.dim.1 as u64);
+    let blocks_x = (extent.width as u64 + block_width - 1) / block_width;
+    let blocks_y = (extent.height as u64 + block_height - 1) / block_height;
+    blocks_x * blocks_y * extent.depth as u64 * (desc.bits / 8) as u64
+}
+
 pub fn format_to_gl_format(
     format: Format,
 ) -> Option<(gl::types::GLint, gl::types::GLenum, VertexAttribFunction)> {
@@ -1,9 +1,13 @@
 use std::borrow::Borrow;
 use std::cell::Cell;
+use std::ffi::CString;
+use std::hash::{Hash, Hasher};
 use std::iter::repeat;
 use std::ops::Range;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex, RwLock};
-use std::{mem, ptr, slice};
+use std::time::Instant;
+use std::{cmp, mem, ptr, slice};
 
 use gl::types::{GLenum, GLfloat, GLint};
 use {gl, GlContainer};
@@ -19,7 +23,9 @@ use spirv_cross::{glsl, spirv, ErrorCode as SpirvErrorCode};
 
 use info::LegacyFeatures;
 use pool::{BufferMemory, OwnedBuffer, RawCommandPool};
-use {conv, native as n, state};
+#[cfg(feature = "glutin")]
+use upload::UploadTicket;
+use {command, conv, native as n, state};
 use {Backend as B, Share, Starc, Surface, Swapchain};
 
 /// Emit error during shader module creation. Used if we don't expect an error
@@ -34,13 +40,13 @@ fn gen_unexpected_error(err: SpirvErrorCode) -> d::ShaderError {
 
 fn get_shader_iv(gl: &GlContainer, name: n::Shader, query: GLenum) -> gl::types::GLint {
     let mut iv = 0;
-    unsafe { gl.GetShaderiv(name, query, &mut iv) };
+    unsafe { gl_call!(gl.GetShaderiv(name, query, &mut iv)) };
     iv
 }
 
 fn get_program_iv(gl: &GlContainer, name: n::Program, query: GLenum) -> gl::types::GLint {
     let mut iv = 0;
-    unsafe { gl.GetProgramiv(name, query, &mut iv) };
+    unsafe { gl_call!(gl.GetProgramiv(name, query, &mut iv)) };
     iv
 }
 
@@ -50,12 +56,12 @@ fn get_shader_log(gl: &GlContainer, name: n::Shader) -> String {
         let mut log = String::with_capacity(length as usize);
         log.extend(repeat('\0').take(length as usize));
         unsafe {
-            gl.GetShaderInfoLog(
+            gl_call!(gl.GetShaderInfoLog(
                 name,
                 length,
                 &mut length,
                 (&log[..]).as_ptr() as *mut gl::types::GLchar,
-            );
+            ));
         }
         log.truncate(length as usize);
         log
@@ -70,12 +76,12 @@ pub(crate) fn get_program_log(gl: &GlContainer, name: n::Program) -> String {
         let mut log = String::with_capacity(length as usize);
         log.extend(repeat('\0').take(length as usize));
         unsafe {
-            gl.GetProgramInfoLog(
+            gl_call!(gl.GetProgramInfoLog(
                 name,
                 length,
                 &mut length,
                 (&log[..]).as_ptr() as *mut gl::types::GLchar,
-            );
+            ));
         }
         log.truncate(length as usize);
         log
@@ -89,7 +95,7 @@ fn create_fbo_internal(share: &Starc<Share>) -> Option<gl::types::GLuint> {
         let gl = &share.context;
         let mut name = 0 as n::FrameBuffer;
         unsafe {
-            gl.GenFramebuffers(1, &mut name);
+            gl_call!(gl.GenFramebuffers(1, &mut name));
         }
         info!("\tCreated frame buffer {}", name);
         Some(name)
@@ -116,34 +122,67 @@ impl Device {
         Device { share: share }
     }
 
+    /// Make the following commands wait for an upload of an `UploadWorker`.
+    ///
+    /// Blocks until the worker issued the upload, while the wait for its
+    /// completion happens on the GPU. Returns `false` if the upload was lost.
+    #[cfg(feature = "glutin")]
+    pub fn wait_upload(&self, ticket: UploadTicket) -> bool {
+        let sync = match ticket.done.recv() {
+            Ok(sync) => sync.0,
+            Err(_) => return false,
+        };
+        if !sync.is_null() {
+            let gl = &self.share.context;
+            unsafe {
+                gl_call!(gl.WaitSync(sync, 0, gl::TIMEOUT_IGNORED));
+                gl_call!(gl.DeleteSync(sync));
+            }
+        }
+        true
+    }
+
+    /// Map the clip space depth of `[0, 1]` to the depth range, like Vulkan and D3D do,
+    /// instead of the `[-1, 1]` of OpenGL. This keeps the precision of reversed-Z
+    /// depth buffers on par with the other backends.
+    ///
+    /// Returns `false` if `ARB_clip_control` is not supported.
+    pub fn set_depth_zero_to_one(&self, enable: bool) -> bool {
+        if !self
+            .share
+            .legacy_features
+            .contains(LegacyFeatures::CLIP_CONTROL)
+        {
+            return false;
+        }
+
+        let depth = if enable {
+            gl::ZERO_TO_ONE
+        } else {
+            gl::NEGATIVE_ONE_TO_ONE
+        };
+        // Shaders already flip the Y axis, so the origin stays in the lower left.
+        unsafe { gl_call!(self.share.context.ClipControl(gl::LOWER_LEFT, depth)) };
+        true
+    }
+
     pub fn create_shader_module_from_source(
         &self,
         data: &[u8],
         stage: pso::Stage,
     ) -> Result<n::ShaderModule, d::ShaderError> {
         let gl = &self.share.context;
+        let target = self.shader_target(stage)?;
 
-        let can_compute = self.share.limits.max_compute_group_count[0] != 0;
-        let can_tessellate = self.share.limits.max_patch_size != 0;
-        let target = match stage {
-            pso::Stage::Vertex => gl::VERTEX_SHADER,
-            pso::Stage::Hull if can_tessellate => gl::TESS_CONTROL_SHADER,
-            pso::Stage::Domain if can_tessellate => gl::TESS_EVALUATION_SHADER,
-            pso::Stage::Geometry => gl::GEOMETRY_SHADER,
-            pso::Stage::Fragment => gl::FRAGMENT_SHADER,
-            pso::Stage::Compute if can_compute => gl::COMPUTE_SHADER,
-            _ => return Err(d::ShaderError::UnsupportedStage(stage)),
-        };
-
-        let name = unsafe { gl.CreateShader(target) };
+        let name = unsafe { gl_call!(gl.CreateShader(target)) };
         unsafe {
-            gl.ShaderSource(
+            gl_call!(gl.ShaderSource(
                 name,
                 1,
                 &(data.as_ptr() as *const gl::types::GLchar),
                 &(data.len() as gl::types::GLint),
-            );
-            gl.CompileShader(name);
+            ));
+            gl_call!(gl.CompileShader(name));
         }
         info!("\tCompiled shader {}", name);
         if let Err(err) = self.share.check() {
@@ -162,6 +201,66 @@ impl Device {
         }
     }
 
+    /// Create a shader from a SPIR-V module with `ARB_gl_spirv`, specialized
+    /// with the given constant IDs and values.
+    fn create_shader_from_spirv(
+        &self,
+        words: &[u32],
+        constants: &[(u32, u32)],
+        stage: pso::Stage,
+    ) -> Result<n::Shader, d::ShaderError> {
+        let gl = &self.share.context;
+        let target = self.shader_target(stage)?;
+        let (indices, values): (Vec<_>, Vec<_>) = constants.iter().cloned().unzip();
+
+        let name = unsafe { gl_call!(gl.CreateShader(target)) };
+        unsafe {
+            gl_call!(gl.ShaderBinary(
+                1,
+                &name,
+                gl::SHADER_BINARY_FORMAT_SPIR_V,
+                words.as_ptr() as *const _,
+                (words.len() * mem::size_of::<u32>()) as _,
+            ));
+            gl_call!(gl.SpecializeShader(
+                name,
+                b"main\0".as_ptr() as *const _,
+                constants.len() as _,
+                indices.as_ptr(),
+                values.as_ptr(),
+            ));
+        }
+        info!("\tSpecialized SPIR-V shader {}", name);
+        if let Err(err) = self.share.check() {
+            panic!("Error specializing shader: {:?}", err);
+        }
+
+        let status = get_shader_iv(gl, name, gl::COMPILE_STATUS);
+        let log = get_shader_log(gl, name);
+        if status != 0 {
+            if !log.is_empty() {
+                warn!("\tLog: {}", log);
+            }
+            Ok(name)
+        } else {
+            Err(d::ShaderError::CompilationFailed(log))
+        }
+    }
+
+    fn shader_target(&self, stage: pso::Stage) -> Result<GLenum, d::ShaderError> {
+        let can_compute = self.share.limits.max_compute_group_count[0] != 0;
+        let can_tessellate = self.share.limits.max_patch_size != 0;
+        match stage {
+            pso::Stage::Vertex => Ok(gl::VERTEX_SHADER),
+            pso::Stage::Hull if can_tessellate => Ok(gl::TESS_CONTROL_SHADER),
+            pso::Stage::Domain if can_tessellate => Ok(gl::TESS_EVALUATION_SHADER),
+            pso::Stage::Geometry => Ok(gl::GEOMETRY_SHADER),
+            pso::Stage::Fragment => Ok(gl::FRAGMENT_SHADER),
+            pso::Stage::Compute if can_compute => Ok(gl::COMPUTE_SHADER),
+            _ => Err(d::ShaderError::UnsupportedStage(stage)),
+        }
+    }
+
     fn bind_target_compat(
         gl: &GlContainer,
         point: GLenum,
@@ -170,40 +269,77 @@ impl Device {
     ) {
         match *view {
             n::ImageView::Surface(surface) => unsafe {
-                gl.FramebufferRenderbuffer(point, attachment, gl::RENDERBUFFER, surface);
+                gl_call!(gl.FramebufferRenderbuffer(point, attachment, gl::RENDERBUFFER, surface));
             },
-            n::ImageView::Texture(texture, level) => unsafe {
-                gl.BindTexture(gl::TEXTURE_2D, texture);
-                gl.FramebufferTexture2D(point, attachment, gl::TEXTURE_2D, texture, level as _);
+            n::ImageView::Texture(texture, target, level)
+            | n::ImageView::TextureLayer(texture, target, level, 0)
+                if target == gl::TEXTURE_2D =>
+            unsafe {
+                gl_call!(gl.FramebufferTexture2D(point, attachment, target, texture, level as _));
             },
-            n::ImageView::TextureLayer(texture, level, layer) => unsafe {
-                gl.BindTexture(gl::TEXTURE_2D, texture);
-                gl.FramebufferTexture3D(
-                    point,
-                    attachment,
-                    gl::TEXTURE_2D,
-                    texture,
-                    level as _,
-                    layer as _,
-                );
+            n::ImageView::TextureView(texture, gl::TEXTURE_2D) => unsafe {
+                gl_call!(gl.FramebufferTexture2D(point, attachment, gl::TEXTURE_2D, texture, 0));
+            },
+            n::ImageView::Texture(..) | n::ImageView::TextureView(..) => {
+                error!("Layered attachments require `glFramebufferTexture`");
+            }
+            n::ImageView::TextureLayer(texture, target, level, layer) => unsafe {
+                Self::bind_target_layer(gl, point, attachment, texture, target, level, layer);
             },
         }
     }
 
-    fn bind_target(gl: &GlContainer, point: GLenum, attachment: GLenum, view: &n::ImageView) {
+    pub(crate) fn bind_target(
+        gl: &GlContainer,
+        point: GLenum,
+        attachment: GLenum,
+        view: &n::ImageView,
+    ) {
         match *view {
             n::ImageView::Surface(surface) => unsafe {
-                gl.FramebufferRenderbuffer(point, attachment, gl::RENDERBUFFER, surface);
+                gl_call!(gl.FramebufferRenderbuffer(point, attachment, gl::RENDERBUFFER, surface));
+            },
+            // Attaching all the layers of an array or cube texture makes it layered,
+            // with the layer selected by `gl_Layer` in the shaders.
+            n::ImageView::Texture(texture, _, level) => unsafe {
+                gl_call!(gl.FramebufferTexture(point, attachment, texture, level as _));
             },
-            n::ImageView::Texture(texture, level) => unsafe {
-                gl.FramebufferTexture(point, attachment, texture, level as _);
+            n::ImageView::TextureView(texture, _) => unsafe {
+                gl_call!(gl.FramebufferTexture(point, attachment, texture, 0));
             },
-            n::ImageView::TextureLayer(texture, level, layer) => unsafe {
-                gl.FramebufferTextureLayer(point, attachment, texture, level as _, layer as _);
+            n::ImageView::TextureLayer(texture, target, level, layer) => unsafe {
+                Self::bind_target_layer(gl, point, attachment, texture, target, level, layer);
             },
         }
     }
 
+    unsafe fn bind_target_layer(
+        gl: &GlContainer,
+        point: GLenum,
+        attachment: GLenum,
+        texture: n::Texture,
+        target: GLenum,
+        level: i::Level,
+        layer: i::Layer,
+    ) {
+        match target {
+            // Cube faces are only attachable as layers since GL 4.5.
+            gl::TEXTURE_CUBE_MAP => gl_call!(gl.FramebufferTexture2D(
+                point,
+                attachment,
+                gl::TEXTURE_CUBE_MAP_POSITIVE_X + layer as GLenum,
+                texture,
+                level as _,
+            )),
+            gl::TEXTURE_2D => {
+                gl_call!(gl.FramebufferTexture2D(point, attachment, target, texture, level as _))
+            }
+            _ => gl_call!(
+                gl.FramebufferTextureLayer(point, attachment, texture, level as _, layer as _)
+            ),
+        }
+    }
+
     fn parse_spirv(&self, raw_data: &[u8]) -> Result<spirv::Ast<glsl::Target>, d::ShaderError> {
         // spec requires "codeSize must be a multiple of 4"
         assert_eq!(raw_data.len() & 3, 0);
@@ -312,6 +448,13 @@ impl Device {
             &res.uniform_buffers,
             n::BindingTypes::UniformBuffers,
         );
+        self.remap_binding(
+            ast,
+            desc_remap_data,
+            nb_map,
+            &res.storage_buffers,
+            n::BindingTypes::StorageBuffers,
+        );
     }
 
     fn remap_binding(
@@ -413,18 +556,102 @@ impl Device {
         }
     }
 
-    fn compile_shader(
+    /// Collect the members of the push constant block, which GLSL declares
+    /// as a plain uniform struct, mapping their uniform names to their
+    /// offset and matrix stride.
+    fn collect_push_constants(
+        &self,
+        ast: &mut spirv::Ast<glsl::Target>,
+        push_constant_map: &mut FastHashMap<String, (u32, u32)>,
+    ) {
+        let res = ast.get_shader_resources().unwrap();
+        for block in &res.push_constant_buffers {
+            // Unnamed objects get generated names, matching the ones of the emitted GLSL.
+            let name = match ast.get_name(block.id).unwrap() {
+                ref name if name.is_empty() => format!("_{}", block.id),
+                name => name,
+            };
+            let member_count = match ast.get_type(block.base_type_id).unwrap() {
+                spirv::Type::Struct { member_types, .. } => member_types.len() as u32,
+                _ => continue,
+            };
+            for i in 0..member_count {
+                let member = match ast.get_member_name(block.base_type_id, i).unwrap() {
+                    ref member if member.is_empty() => format!("_m{}", i),
+                    member => member,
+                };
+                let offset = ast
+                    .get_member_decoration(block.base_type_id, i, spirv::Decoration::Offset)
+                    .unwrap();
+                let matrix_stride = ast
+                    .get_member_decoration(block.base_type_id, i, spirv::Decoration::MatrixStride)
+                    .unwrap();
+                push_constant_map.insert(format!("{}.{}", name, member), (offset, matrix_stride));
+            }
+        }
+    }
+
+    /// Look up the uniforms emulating push constants in a linked program.
+    unsafe fn get_push_constant_uniforms(
+        &self,
+        program: n::Program,
+        push_constant_map: &FastHashMap<String, (u32, u32)>,
+    ) -> Vec<n::PushConstantUniform> {
+        let gl = &self.share.context;
+        let mut uniforms = Vec::new();
+        if push_constant_map.is_empty() {
+            return uniforms;
+        }
+
+        let count = get_program_iv(gl, program, gl::ACTIVE_UNIFORMS);
+        let max_length = get_program_iv(gl, program, gl::ACTIVE_UNIFORM_MAX_LENGTH);
+        let mut name = vec![0u8; max_length.max(1) as usize];
+        for index in 0..count {
+            let (mut length, mut size, mut ty) = (0, 0, 0);
+            gl_call!(gl.GetActiveUniform(
+                program,
+                index as _,
+                name.len() as _,
+                &mut length,
+                &mut size,
+                &mut ty,
+                name.as_mut_ptr() as *mut gl::types::GLchar,
+            ));
+            let uniform_name = String::from_utf8_lossy(&name[..length as usize]);
+            let &(offset, matrix_stride) = match push_constant_map.get(&*uniform_name) {
+                Some(member) => member,
+                None => continue,
+            };
+            if size != 1 || conv::uniform_type_dimensions(ty).is_none() {
+                warn!("Unsupported push constant member {}", uniform_name);
+                continue;
+            }
+
+            let c_name = CString::new(uniform_name.as_bytes()).unwrap();
+            uniforms.push(n::PushConstantUniform {
+                location: gl_call!(gl.GetUniformLocation(program, c_name.as_ptr())),
+                ty,
+                offset,
+                matrix_stride,
+            });
+        }
+
+        uniforms
+    }
+
+    /// Translate the shader of a pipeline stage to GLSL, remapping its bindings.
+    fn translate_shader(
         &self,
         point: &pso::EntryPoint<B>,
-        stage: pso::Stage,
         desc_remap_data: &mut n::DescRemapData,
         name_binding_map: &mut FastHashMap<String, pso::DescriptorBinding>,
-    ) -> n::Shader {
+        push_constant_map: &mut FastHashMap<String, (u32, u32)>,
+    ) -> ShaderSource {
         assert_eq!(point.entry, "main");
         match *point.module {
             n::ShaderModule::Raw(raw) => {
                 debug!("Can't remap bindings for raw shaders. Assuming they are already rebound.");
-                raw
+                ShaderSource::Raw(raw)
             }
             n::ShaderModule::Spirv(ref spirv) => {
                 let mut ast = self.parse_spirv(spirv).unwrap();
@@ -436,256 +663,676 @@ impl Device {
                     desc_remap_data,
                     name_binding_map,
                 );
+                self.collect_push_constants(&mut ast, push_constant_map);
 
                 let glsl = self.translate_spirv(&mut ast).unwrap();
                 info!("Generated:\n{:?}", glsl);
-                let shader = match self
+                ShaderSource::Glsl(glsl)
+            }
+        }
+    }
+
+    /// Prepare the SPIR-V of a shader to be consumed directly with `ARB_gl_spirv`,
+    /// remapping its bindings. Returns `None` if it needs to be translated to GLSL.
+    ///
+    /// Only used for compute shaders, as the GLSL of the graphics stages
+    /// also inverts the clip space Y axis, which we can't do in SPIR-V.
+    fn prepare_spirv(
+        &self,
+        point: &pso::EntryPoint<B>,
+        desc_remap_data: &mut n::DescRemapData,
+        name_binding_map: &mut FastHashMap<String, pso::DescriptorBinding>,
+    ) -> Option<ShaderSource> {
+        assert_eq!(point.entry, "main");
+        let spirv = match *point.module {
+            n::ShaderModule::Raw(_) => return None,
+            n::ShaderModule::Spirv(ref spirv) => spirv,
+        };
+
+        let mut ast = self.parse_spirv(spirv).unwrap();
+        let res = ast.get_shader_resources().unwrap();
+        // Separate images and samplers can only be combined in GLSL,
+        // and push constants are only emulated there.
+        if !res.separate_images.is_empty()
+            || !res.separate_samplers.is_empty()
+            || !res.push_constant_buffers.is_empty()
+        {
+            return None;
+        }
+
+        self.remap_bindings(&mut ast, desc_remap_data, name_binding_map);
+        let bindings = res
+            .sampled_images
+            .iter()
+            .chain(&res.uniform_buffers)
+            .chain(&res.storage_buffers)
+            .map(|res| {
+                let binding = ast
+                    .get_decoration(res.id, spirv::Decoration::Binding)
+                    .unwrap();
+                (res.id, binding)
+            })
+            .collect::<FastHashMap<_, _>>();
+
+        let words = unsafe {
+            slice::from_raw_parts(
+                spirv.as_ptr() as *const u32,
+                spirv.len() / mem::size_of::<u32>(),
+            )
+        };
+        let constants = point
+            .specialization
+            .constants
+            .iter()
+            .map(|constant| {
+                let value = point.specialization.data
+                    [constant.range.start as usize..constant.range.end as usize]
+                    .iter()
+                    .rev()
+                    .fold(0u32, |u, &b| (u << 8) + b as u32);
+                (constant.id, value)
+            })
+            .collect();
+
+        Some(ShaderSource::Spirv {
+            words: patch_spirv_bindings(words, &bindings),
+            constants,
+        })
+    }
+
+    fn compile_stage(&self, source: &ShaderSource, stage: pso::Stage) -> n::Shader {
+        match *source {
+            ShaderSource::Raw(raw) => raw,
+            ShaderSource::Spirv {
+                ref words,
+                ref constants,
+            } => self
+                .create_shader_from_spirv(words, constants, stage)
+                .unwrap(),
+            ShaderSource::Glsl(ref glsl) => {
+                match self
                     .create_shader_module_from_source(glsl.as_bytes(), stage)
                     .unwrap()
                 {
                     n::ShaderModule::Raw(raw) => raw,
                     _ => panic!("Unhandled"),
-                };
-
-                shader
+                }
             }
         }
     }
-}
 
-pub(crate) unsafe fn set_sampler_info<SetParamFloat, SetParamFloatVec, SetParamInt>(
-    share: &Starc<Share>,
-    info: &i::SamplerInfo,
-    mut set_param_float: SetParamFloat,
-    mut set_param_float_vec: SetParamFloatVec,
-    mut set_param_int: SetParamInt,
-) where
-    SetParamFloat: FnMut(GLenum, GLfloat),
-    SetParamFloatVec: FnMut(GLenum, &[GLfloat]),
-    SetParamInt: FnMut(GLenum, GLint),
-{
-    let (min, mag) = conv::filter_to_gl(info.mag_filter, info.min_filter, info.mip_filter);
-    match info.anisotropic {
-        i::Anisotropic::On(fac) if fac > 1 => {
-            if share.private_caps.sampler_anisotropy_ext {
-                set_param_float(gl::TEXTURE_MAX_ANISOTROPY_EXT, fac as GLfloat);
-            } else if share.features.contains(c::Features::SAMPLER_ANISOTROPY) {
-                set_param_float(gl::TEXTURE_MAX_ANISOTROPY_EXT, fac as GLfloat);
-            }
+    /// Link the program and release its shaders, keeping the linked binary
+    /// around if there is a cache key.
+    unsafe fn link_program(&self, name: n::Program, shaders: &[n::Shader], cache_key: Option<u64>) {
+        let gl = &self.share.context;
+
+        if cache_key.is_some() {
+            gl_call!(gl.ProgramParameteri(
+                name,
+                gl::PROGRAM_BINARY_RETRIEVABLE_HINT,
+                gl::TRUE as _
+            ));
         }
-        _ => (),
-    }
 
-    set_param_int(gl::TEXTURE_MIN_FILTER, min as GLint);
-    set_param_int(gl::TEXTURE_MAG_FILTER, mag as GLint);
+        gl_call!(gl.LinkProgram(name));
+        info!("\tLinked program {}", name);
+        if let Err(err) = self.share.check() {
+            panic!("Error linking program: {:?}", err);
+        }
 
-    let (s, t, r) = info.wrap_mode;
-    set_param_int(gl::TEXTURE_WRAP_S, conv::wrap_to_gl(s) as GLint);
-    set_param_int(gl::TEXTURE_WRAP_T, conv::wrap_to_gl(t) as GLint);
-    set_param_int(gl::TEXTURE_WRAP_R, conv::wrap_to_gl(r) as GLint);
+        for &shader in shaders {
+            gl_call!(gl.DetachShader(name, shader));
+            gl_call!(gl.DeleteShader(shader));
+        }
 
-    if share
-        .features
-        .contains(hal::Features::SAMPLER_MIP_LOD_BIAS)
-    {
-        set_param_float(gl::TEXTURE_LOD_BIAS, info.lod_bias.into());
+        if let Some(key) = cache_key {
+            if get_program_iv(gl, name, gl::LINK_STATUS) != 0 {
+                self.store_program_binary(name, key);
+            }
+        }
     }
-    if share
-        .legacy_features
-        .contains(LegacyFeatures::SAMPLER_BORDER_COLOR)
-    {
-        let border: [f32; 4] = info.border.into();
-        set_param_float_vec(gl::TEXTURE_BORDER_COLOR, &border);
+
+    /// Key of a program in the binary cache, or `None` if it can't be cached.
+    fn program_cache_key<T: Hash>(
+        &self,
+        sources: &[(pso::Stage, ShaderSource)],
+        link_state: T,
+    ) -> Option<u64> {
+        if !self.share.private_caps.program_binary {
+            return None;
+        }
+
+        let mut hasher = FnvHasher::default();
+        for &(stage, ref source) in sources {
+            match *source {
+                // Raw shaders are already compiled, so we don't know their source.
+                ShaderSource::Raw(_) => return None,
+                ShaderSource::Glsl(ref glsl) => (stage, glsl).hash(&mut hasher),
+                ShaderSource::Spirv {
+                    ref words,
+                    ref constants,
+                } => (stage, words, constants).hash(&mut hasher),
+            }
+        }
+        link_state.hash(&mut hasher);
+        Some(hasher.finish())
     }
 
-    set_param_float(gl::TEXTURE_MIN_LOD, info.lod_range.start.into());
-    set_param_float(gl::TEXTURE_MAX_LOD, info.lod_range.end.into());
+    /// Try to create the program from a cached binary, returns `false` on a miss.
+    ///
+    /// On `false`, the program has to be linked from source.
+    unsafe fn load_program_binary(&self, name: n::Program, key: u64) -> bool {
+        let gl = &self.share.context;
+        let mut binaries = self.share.program_binaries.borrow_mut();
+        match binaries.get(&key) {
+            Some(binary) => gl_call!(gl.ProgramBinary(
+                name,
+                binary.format,
+                binary.data.as_ptr() as *const _,
+                binary.data.len() as _,
+            )),
+            None => return false,
+        }
 
-    match info.comparison {
-        None => set_param_int(gl::TEXTURE_COMPARE_MODE, gl::NONE as GLint),
-        Some(cmp) => {
-            set_param_int(
-                gl::TEXTURE_COMPARE_MODE,
-                gl::COMPARE_REF_TO_TEXTURE as GLint,
-            );
-            set_param_int(
-                gl::TEXTURE_COMPARE_FUNC,
-                state::map_comparison(cmp) as GLint,
-            );
+        // Drivers may still reject the binary, e.g. after a driver update.
+        if get_program_iv(gl, name, gl::LINK_STATUS) == 0 {
+            debug!("Cached binary of program {} was rejected", name);
+            // The binary of the program linked from source replaces it.
+            binaries.remove(&key);
+            return false;
         }
+        true
     }
-}
 
-impl d::Device<B> for Device {
-    unsafe fn allocate_memory(
-        &self,
-        _mem_type: c::MemoryTypeId,
-        size: u64,
-    ) -> Result<n::Memory, d::AllocationError> {
-        // TODO
-        Ok(n::Memory {
-            properties: memory::Properties::CPU_VISIBLE | memory::Properties::CPU_CACHED,
-            first_bound_buffer: Cell::new(0),
-            size,
-        })
+    unsafe fn store_program_binary(&self, name: n::Program, key: u64) {
+        let gl = &self.share.context;
+        let length = get_program_iv(gl, name, gl::PROGRAM_BINARY_LENGTH);
+        let mut data = vec![0u8; length as usize];
+        let mut format = 0;
+        let mut written = 0;
+        gl_call!(gl.GetProgramBinary(
+            name,
+            length,
+            &mut written,
+            &mut format,
+            data.as_mut_ptr() as *mut _,
+        ));
+        data.truncate(written as usize);
+
+        self.share
+            .program_binaries
+            .borrow_mut()
+            .insert(key, n::ProgramBinary { format, data });
+    }
+
+    /// Hash identifying the driver, as program binaries aren't portable across drivers.
+    fn driver_hash(&self) -> u64 {
+        let name = &self.share.info.platform_name;
+        let mut hasher = FnvHasher::default();
+        (name.vendor, name.renderer, name.version).hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// Serialize the binaries of all programs linked or loaded so far, to be
+    /// loaded back with `load_program_cache` on the next run.
+    ///
+    /// The cache is empty if program binaries are not supported.
+    pub fn save_program_cache(&self) -> Vec<u8> {
+        let binaries = self.share.program_binaries.borrow();
+        let mut out = Vec::new();
+        out.extend_from_slice(PROGRAM_CACHE_MAGIC);
+        write_u64(&mut out, self.driver_hash());
+        write_u64(&mut out, binaries.len() as u64);
+        for (&key, binary) in binaries.iter() {
+            write_u64(&mut out, key);
+            write_u64(&mut out, binary.format as u64);
+            write_u64(&mut out, binary.data.len() as u64);
+            out.extend_from_slice(&binary.data);
+        }
+        out
     }
 
-    unsafe fn create_command_pool(
-        &self,
-        _family: QueueFamilyId,
-        flags: CommandPoolCreateFlags,
-    ) -> Result<RawCommandPool, d::OutOfMemory> {
-        let fbo = create_fbo_internal(&self.share);
-        let limits = self.share.limits.into();
-        let memory = if flags.contains(CommandPoolCreateFlags::RESET_INDIVIDUAL) {
-            BufferMemory::Individual {
-                storage: FastHashMap::default(),
-                next_buffer_id: 0,
-            }
-        } else {
-            BufferMemory::Linear(OwnedBuffer::new())
+    /// Load program binaries saved by `save_program_cache`, which are then
+    /// used instead of compiling the matching pipelines from source.
+    ///
+    /// Returns `false` and loads nothing if the data is malformed or
+    /// has been saved with a different driver.
+    pub fn load_program_cache(&self, data: &[u8]) -> bool {
+        if !self.share.private_caps.program_binary || !data.starts_with(PROGRAM_CACHE_MAGIC) {
+            return false;
+        }
+
+        let mut data = &data[PROGRAM_CACHE_MAGIC.len()..];
+        if read_u64(&mut data) != Some(self.driver_hash()) {
+            info!("Program cache was saved by a different driver");
+            return false;
+        }
+
+        let mut entries = Vec::new();
+        let count = match read_u64(&mut data) {
+            Some(count) => count,
+            None => return false,
         };
+        for _ in 0..count {
+            let (key, format, length) = match (
+                read_u64(&mut data),
+                read_u64(&mut data),
+                read_u64(&mut data),
+            ) {
+                (Some(key), Some(format), Some(length)) if length as usize <= data.len() => {
+                    (key, format, length as usize)
+                }
+                _ => return false,
+            };
+            entries.push((
+                key,
+                n::ProgramBinary {
+                    format: format as _,
+                    data: data[..length].to_vec(),
+                },
+            ));
+            data = &data[length..];
+        }
 
-        // Ignoring `TRANSIENT` hint, unsure how to make use of this.
+        self.share.program_binaries.borrow_mut().extend(entries);
+        true
+    }
 
-        Ok(RawCommandPool {
-            fbo,
-            limits,
-            memory: Arc::new(Mutex::new(memory)),
-        })
+    /// Create a graphics pipeline, which captures the given outputs of its last
+    /// vertex processing stage with transform feedback.
+    ///
+    /// See `RawCommandBuffer::begin_transform_feedback` for recording them.
+    pub unsafe fn create_graphics_pipeline_with_capture<'a>(
+        &self,
+        desc: &pso::GraphicsPipelineDesc<'a, B>,
+        capture: &n::TransformFeedbackDesc,
+    ) -> Result<n::GraphicsPipeline, pso::CreationError> {
+        if !self
+            .share
+            .legacy_features
+            .contains(LegacyFeatures::TRANSFORM_FEEDBACK)
+        {
+            error!("Transform feedback is not supported");
+            return Err(pso::CreationError::Other);
+        }
+        self.create_graphics_pipeline_impl(desc, Some(capture))
     }
 
-    unsafe fn destroy_command_pool(&self, pool: RawCommandPool) {
-        if let Some(fbo) = pool.fbo {
-            let gl = &self.share.context;
-            gl.DeleteFramebuffers(1, &fbo);
+    /// Create a transform feedback object, which keeps track of the buffers
+    /// and the number of vertices written by a capture.
+    pub fn create_transform_feedback(&self) -> Result<n::TransformFeedback, d::OutOfMemory> {
+        let gl = &self.share.context;
+        let mut name = 0;
+        unsafe {
+            gl_call!(gl.GenTransformFeedbacks(1, &mut name));
+            // The object only gets created on the first binding.
+            gl_call!(gl.BindTransformFeedback(gl::TRANSFORM_FEEDBACK, name));
+            gl_call!(gl.BindTransformFeedback(gl::TRANSFORM_FEEDBACK, 0));
         }
+        label_object(
+            &self.share,
+            gl::TRANSFORM_FEEDBACK,
+            name,
+            "TransformFeedback",
+        );
+        Ok(name)
     }
 
-    unsafe fn create_render_pass<'a, IA, IS, ID>(
+    pub unsafe fn destroy_transform_feedback(&self, feedback: n::TransformFeedback) {
+        gl_call!(self.share.context.DeleteTransformFeedbacks(1, &feedback));
+    }
+
+    /// Get the bindless handle of the texture behind a view, optionally combined
+    /// with a sampler. Returns `None` if bindless textures are not supported.
+    ///
+    /// The handle needs to be made resident before shaders access it, and made
+    /// non-resident again before the texture or sampler is destroyed.
+    pub fn get_texture_handle(
         &self,
-        attachments: IA,
-        subpasses: IS,
-        _dependencies: ID,
-    ) -> Result<n::RenderPass, d::OutOfMemory>
-    where
-        IA: IntoIterator,
-        IA::Item: Borrow<pass::Attachment>,
-        IS: IntoIterator,
-        IS::Item: Borrow<pass::SubpassDesc<'a>>,
-        ID: IntoIterator,
-        ID::Item: Borrow<pass::SubpassDependency>,
-    {
-        let subpasses = subpasses
-            .into_iter()
-            .map(|subpass| {
-                let color_attachments = subpass
-                    .borrow()
-                    .colors
-                    .iter()
-                    .map(|&(index, _)| index)
-                    .collect();
+        view: &n::ImageView,
+        sampler: Option<&n::FatSampler>,
+    ) -> Option<n::TextureHandle> {
+        if !self
+            .share
+            .legacy_features
+            .contains(LegacyFeatures::BINDLESS_TEXTURE)
+        {
+            return None;
+        }
 
-                n::SubpassDesc { color_attachments }
-            })
-            .collect();
+        let texture = match *view {
+            n::ImageView::Texture(texture, _, _)
+            | n::ImageView::TextureLayer(texture, _, _, _)
+            | n::ImageView::TextureView(texture, _) => texture,
+            n::ImageView::Surface(_) => {
+                warn!("Renderbuffers have no bindless handle");
+                return None;
+            }
+        };
 
-        Ok(n::RenderPass {
-            attachments: attachments
-                .into_iter()
-                .map(|attachment| attachment.borrow().clone())
-                .collect::<Vec<_>>(),
-            subpasses,
-        })
+        let gl = &self.share.context;
+        let raw = match sampler {
+            None => unsafe { gl_call!(gl.GetTextureHandleARB(texture)) },
+            Some(&n::FatSampler::Sampler(sampler)) => unsafe {
+                gl_call!(gl.GetTextureSamplerHandleARB(texture, sampler))
+            },
+            Some(&n::FatSampler::Info(_)) => {
+                warn!("Combined bindless handles require sampler objects");
+                return None;
+            }
+        };
+
+        if raw == 0 {
+            None
+        } else {
+            Some(n::TextureHandle(raw))
+        }
     }
 
-    unsafe fn create_pipeline_layout<IS, IR>(
+    /// Upload tightly packed texels of the image format to a region of a
+    /// texture level. The layers of array textures are addressed by
+    /// `offset.z` and `extent.depth`.
+    ///
+    /// The data is staged in a ring of pixel buffers, so the transfer gets queued
+    /// on the GPU instead of stalling until the texture is idle.
+    pub fn update_texture(
         &self,
-        layouts: IS,
-        _: IR,
-    ) -> Result<n::PipelineLayout, d::OutOfMemory>
-    where
-        IS: IntoIterator,
-        IS::Item: Borrow<n::DescriptorSetLayout>,
-        IR: IntoIterator,
-        IR::Item: Borrow<(pso::ShaderStageFlags, Range<u32>)>,
-    {
-        let mut drd = n::DescRemapData::new();
+        image: &n::Image,
+        level: i::Level,
+        offset: i::Offset,
+        extent: i::Extent,
+        data: &[u8],
+    ) {
+        let (texture, format, ty) = match Self::pixel_transfer_format(image, "updated") {
+            Some(transfer) => transfer,
+            None => return,
+        };
+        let size = conv::image_data_size(image.format, extent) as usize;
+        if data.len() < size {
+            error!("Not enough data to update {:?}", extent);
+            return;
+        }
 
-        layouts.into_iter().enumerate().for_each(|(set, layout)| {
-            layout.borrow().iter().for_each(|binding| {
-                // DescriptorType -> Descriptor
-                //
-                // Sampler -> Sampler
-                // Image -> SampledImage, StorageImage, InputAttachment
-                // CombinedImageSampler -> CombinedImageSampler
-                // Buffer -> UniformBuffer, StorageBuffer
-                // UniformTexel -> UniformTexel
-                // StorageTexel -> StorageTexel
+        let gl = &self.share.context;
+        let mut ring = self.share.unpack_ring.borrow_mut();
+        unsafe {
+            // Bound to `PIXEL_UNPACK_BUFFER` by the ring.
+            ring.acquire(&self.share, size);
+            gl_call!(gl.BufferSubData(
+                gl::PIXEL_UNPACK_BUFFER,
+                0,
+                size as _,
+                data.as_ptr() as *const _,
+            ));
+            gl_call!(gl.BindTexture(image.target, texture));
+            if image.target == gl::TEXTURE_2D {
+                gl_call!(gl.TexSubImage2D(
+                    image.target,
+                    level as _,
+                    offset.x,
+                    offset.y,
+                    extent.width as _,
+                    extent.height as _,
+                    format,
+                    ty,
+                    ptr::null(),
+                ));
+            } else {
+                gl_call!(gl.TexSubImage3D(
+                    image.target,
+                    level as _,
+                    offset.x,
+                    offset.y,
+                    offset.z,
+                    extent.width as _,
+                    extent.height as _,
+                    extent.depth as _,
+                    format,
+                    ty,
+                    ptr::null(),
+                ));
+            }
+            gl_call!(gl.BindTexture(image.target, 0));
+            gl_call!(gl.BindBuffer(gl::PIXEL_UNPACK_BUFFER, 0));
+            ring.release(&self.share);
+        }
+    }
 
-                assert!(!binding.immutable_samplers); //TODO: Implement immutable_samplers
-                use pso::DescriptorType::*;
-                match binding.ty {
-                    CombinedImageSampler => {
-                        drd.insert_missing_binding_into_spare(
-                            n::BindingTypes::Images,
-                            set as _,
-                            binding.binding,
+    /// Start reading back all the layers of a texture level into a pixel buffer,
+    /// without waiting for the GPU to get there.
+    ///
+    /// The texels can be retrieved with `try_read_pixels` once available,
+    /// tightly packed in the image format.
+    pub fn read_texture(&self, image: &n::Image, level: i::Level) -> Option<n::PixelReadback> {
+        let (texture, format, ty) = Self::pixel_transfer_format(image, "read back")?;
+        let mut extent = image.extent.at_level(level);
+        if image.target == gl::TEXTURE_2D_ARRAY {
+            extent.depth = image.layers as _;
+        }
+        let layer_size =
+            conv::image_data_size(image.format, i::Extent { depth: 1, ..extent }) as usize;
+        let size = layer_size * extent.depth as usize;
+
+        let gl = &self.share.context;
+        unsafe {
+            // ES can only read pixels from framebuffers.
+            let fbo = if self.share.info.version.is_embedded {
+                if !image.format.is_color() {
+                    error!("Textures of format {:?} can't be read back", image.format);
+                    return None;
+                }
+                let fbo = create_fbo_internal(&self.share)?;
+                gl_call!(gl.BindFramebuffer(gl::READ_FRAMEBUFFER, fbo));
+                Some(fbo)
+            } else {
+                None
+            };
+
+            let buffer = self
+                .share
+                .pack_buffers
+                .borrow_mut()
+                .pop()
+                .unwrap_or_else(|| {
+                    let mut buffer = 0;
+                    gl_call!(gl.GenBuffers(1, &mut buffer));
+                    buffer
+                });
+            gl_call!(gl.BindBuffer(gl::PIXEL_PACK_BUFFER, buffer));
+            gl_call!(gl.BufferData(
+                gl::PIXEL_PACK_BUFFER,
+                size as _,
+                ptr::null(),
+                gl::STREAM_READ,
+            ));
+            gl_call!(gl.PixelStorei(gl::PACK_ALIGNMENT, 1));
+            let mut result = Some(());
+            match fbo {
+                Some(_) => {
+                    for layer in 0..extent.depth {
+                        Self::bind_target_layer(
+                            gl,
+                            gl::READ_FRAMEBUFFER,
+                            gl::COLOR_ATTACHMENT0,
+                            texture,
+                            image.target,
+                            level,
+                            layer as _,
                         );
-                    }
-                    Sampler | SampledImage => {
-                        // We need to figure out combos once we get the shaders, until then we
-                        // do nothing
-                    }
-                    UniformBuffer => {
-                        drd.insert_missing_binding_into_spare(
-                            n::BindingTypes::UniformBuffers,
-                            set as _,
-                            binding.binding,
+                        // ES only guarantees a single format per framebuffer,
+                        // besides the one chosen by the implementation.
+                        let (mut read_format, mut read_ty) = (0, 0);
+                        gl_call!(
+                            gl.GetIntegerv(gl::IMPLEMENTATION_COLOR_READ_FORMAT, &mut read_format)
                         );
+                        gl_call!(gl.GetIntegerv(gl::IMPLEMENTATION_COLOR_READ_TYPE, &mut read_ty));
+                        let read = (read_format as GLenum, read_ty as GLenum);
+                        if read != (format, ty) && (format, ty) != (gl::RGBA, gl::UNSIGNED_BYTE) {
+                            error!("Textures of format {:?} can't be read back", image.format);
+                            result = None;
+                            break;
+                        }
+                        gl_call!(gl.ReadPixels(
+                            0,
+                            0,
+                            extent.width as _,
+                            extent.height as _,
+                            format,
+                            ty,
+                            (layer as usize * layer_size) as *mut _,
+                        ));
                     }
-                    StorageImage | UniformTexelBuffer | UniformBufferDynamic
-                    | StorageTexelBuffer | StorageBufferDynamic | StorageBuffer
-                    | InputAttachment => unimplemented!(), // 6
                 }
-            })
-        });
+                None => {
+                    gl_call!(gl.BindTexture(image.target, texture));
+                    gl_call!(gl.GetTexImage(image.target, level as _, format, ty, ptr::null_mut()));
+                    gl_call!(gl.BindTexture(image.target, 0));
+                }
+            }
+            gl_call!(gl.PixelStorei(gl::PACK_ALIGNMENT, 4));
+            gl_call!(gl.BindBuffer(gl::PIXEL_PACK_BUFFER, 0));
+            if let Some(fbo) = fbo {
+                gl_call!(gl.BindFramebuffer(gl::READ_FRAMEBUFFER, 0));
+                gl_call!(gl.DeleteFramebuffers(1, &fbo));
+            }
+            if result.is_none() {
+                self.share.pack_buffers.borrow_mut().push(buffer);
+                return None;
+            }
 
-        Ok(n::PipelineLayout {
-            desc_remap_data: Arc::new(RwLock::new(drd)),
-        })
+            let sync = if self.share.private_caps.sync {
+                gl_call!(gl.FenceSync(gl::SYNC_GPU_COMMANDS_COMPLETE, 0))
+            } else {
+                ptr::null()
+            };
+
+            Some(n::PixelReadback {
+                buffer,
+                fence: n::Fence::new(sync),
+                size,
+            })
+        }
     }
 
-    unsafe fn create_pipeline_cache(&self, _data: Option<&[u8]>) -> Result<(), d::OutOfMemory> {
-        Ok(())
+    /// Texture and pixel transfer format of an image, if texels can be
+    /// transferred to or from it.
+    fn pixel_transfer_format(
+        image: &n::Image,
+        operation: &str,
+    ) -> Option<(n::Texture, GLenum, GLenum)> {
+        let texture = match image.kind {
+            n::ImageKind::Texture(texture) => texture,
+            n::ImageKind::Surface(_) => {
+                error!("Renderbuffers can't be {}", operation);
+                return None;
+            }
+        };
+        match image.target {
+            gl::TEXTURE_2D | gl::TEXTURE_2D_ARRAY | gl::TEXTURE_3D => {}
+            target => {
+                error!("Textures of target 0x{:x} can't be {}", target, operation);
+                return None;
+            }
+        }
+        match conv::image_format_to_gl(image.format) {
+            Some((_, format, ty)) => Some((texture, format, ty)),
+            None => {
+                error!(
+                    "Textures of format {:?} can't be {}",
+                    image.format, operation
+                );
+                None
+            }
+        }
     }
 
-    unsafe fn get_pipeline_cache_data(&self, cache: &()) -> Result<Vec<u8>, d::OutOfMemory> {
-        //empty
-        Ok(Vec::new())
+    /// Get the texels of a readback, or `None` if the GPU hasn't written them yet.
+    pub fn try_read_pixels(&self, readback: &n::PixelReadback) -> Option<Vec<u8>> {
+        match wait_fence(&readback.fence, &self.share, 0) {
+            gl::ALREADY_SIGNALED | gl::CONDITION_SATISFIED => (),
+            _ => return None,
+        }
+
+        let gl = &self.share.context;
+        let mut data = vec![0u8; readback.size];
+        unsafe {
+            gl_call!(gl.BindBuffer(gl::PIXEL_PACK_BUFFER, readback.buffer));
+            let ptr = gl_call!(gl.MapBufferRange(
+                gl::PIXEL_PACK_BUFFER,
+                0,
+                readback.size as _,
+                gl::MAP_READ_BIT,
+            ));
+            if !ptr.is_null() {
+                ptr::copy_nonoverlapping(ptr as *const u8, data.as_mut_ptr(), readback.size);
+            }
+            gl_call!(gl.UnmapBuffer(gl::PIXEL_PACK_BUFFER));
+            gl_call!(gl.BindBuffer(gl::PIXEL_PACK_BUFFER, 0));
+        }
+        Some(data)
     }
 
-    unsafe fn destroy_pipeline_cache(&self, _: ()) {
-        //empty
+    /// Release a readback, keeping its pixel buffer around for the next ones.
+    pub fn destroy_pixel_readback(&self, readback: n::PixelReadback) {
+        let sync = readback.fence.0.get();
+        if !sync.is_null() {
+            unsafe { gl_call!(self.share.context.DeleteSync(sync)) };
+        }
+        self.share.pack_buffers.borrow_mut().push(readback.buffer);
     }
 
-    unsafe fn merge_pipeline_caches<I>(&self, _: &(), _: I) -> Result<(), d::OutOfMemory>
-    where
-        I: IntoIterator,
-        I::Item: Borrow<()>,
-    {
-        //empty
-        Ok(())
+    pub fn make_texture_handle_resident(&self, handle: n::TextureHandle) {
+        unsafe { gl_call!(self.share.context.MakeTextureHandleResidentARB(handle.0)) };
     }
 
-    unsafe fn create_graphics_pipeline<'a>(
+    pub fn make_texture_handle_non_resident(&self, handle: n::TextureHandle) {
+        unsafe { gl_call!(self.share.context.MakeTextureHandleNonResidentARB(handle.0)) };
+    }
+    unsafe fn create_graphics_pipeline_impl<'a>(
         &self,
         desc: &pso::GraphicsPipelineDesc<'a, B>,
-        _cache: Option<&()>,
+        capture: Option<&n::TransformFeedbackDesc>,
     ) -> Result<n::GraphicsPipeline, pso::CreationError> {
         let gl = &self.share.context;
         let share = &self.share;
         let desc = desc.borrow();
+
+        // Tessellation stages consume patches, which nothing else accepts.
+        let tessellation = desc.shaders.hull.is_some() || desc.shaders.domain.is_some();
+        if tessellation && !share.features.contains(c::Features::TESSELLATION_SHADER) {
+            return Err(pso::CreationError::Shader(
+                d::ShaderError::UnsupportedStage(pso::Stage::Hull),
+            ));
+        }
+        if !share.features.contains(c::Features::INDEPENDENT_BLENDING)
+            && desc
+                .blender
+                .targets
+                .windows(2)
+                .any(|pair| pair[0] != pair[1])
+        {
+            return Err(pso::CreationError::MissingFeature(
+                c::Features::INDEPENDENT_BLENDING,
+            ));
+        }
+        match desc.input_assembler.primitive {
+            c::Primitive::PatchList(size) if tessellation => {
+                if size == 0 || size > share.limits.max_patch_size {
+                    error!("Unsupported patch size {}", size);
+                    return Err(pso::CreationError::Other);
+                }
+            }
+            c::Primitive::PatchList(_) => {
+                error!("Patch lists require tessellation shaders");
+                return Err(pso::CreationError::Other);
+            }
+            _ if tessellation => {
+                error!("Tessellation shaders require a patch list");
+                return Err(pso::CreationError::Other);
+            }
+            _ => {}
+        }
+
         let subpass = {
             let subpass = desc.subpass;
             match subpass.main_pass.subpasses.get(subpass.index) {
@@ -694,8 +1341,9 @@ impl d::Device<B> for Device {
             }
         };
 
-        let program = {
-            let name = gl.CreateProgram();
+        let (program, push_constants) = {
+            let name = gl_call!(gl.CreateProgram());
+            label_object(&self.share, gl::PROGRAM, name, "Program");
 
             // Attach shaders to program
             let shaders = [
@@ -707,44 +1355,71 @@ impl d::Device<B> for Device {
             ];
 
             let mut name_binding_map = FastHashMap::<String, pso::DescriptorBinding>::default();
-            let shader_names = &shaders
+            let mut push_constant_map = FastHashMap::default();
+            let sources = shaders
                 .iter()
                 .filter_map(|&(stage, point_maybe)| {
                     point_maybe.map(|point| {
-                        let shader_name = self.compile_shader(
+                        let source = self.translate_shader(
                             point,
-                            stage,
                             &mut desc.layout.desc_remap_data.write().unwrap(),
                             &mut name_binding_map,
+                            &mut push_constant_map,
                         );
+                        (stage, source)
+                    })
+                })
+                .collect::<Vec<_>>();
 
-                        gl.AttachShader(name, shader_name);
+            // Everything affecting the linking, besides the shaders themselves.
+            let link_state = (
+                subpass.color_attachments.len(),
+                capture.map(|capture| (&capture.varyings, capture.separate)),
+            );
+            let cache_key = self.program_cache_key(&sources, link_state);
 
+            if !cache_key.map_or(false, |key| self.load_program_binary(name, key)) {
+                let shader_names = sources
+                    .iter()
+                    .map(|&(stage, ref source)| {
+                        let shader_name = self.compile_stage(source, stage);
+                        gl_call!(gl.AttachShader(name, shader_name));
                         shader_name
                     })
-                })
-                .collect::<Vec<_>>();
+                    .collect::<Vec<_>>();
+
+                if !share.private_caps.program_interface && share.private_caps.frag_data_location {
+                    for i in 0..subpass.color_attachments.len() {
+                        let color_name = format!("Target{}\0", i);
+                        gl_call!(gl.BindFragDataLocation(
+                            name,
+                            i as u32,
+                            (&color_name[..]).as_ptr() as *mut gl::types::GLchar,
+                        ));
+                    }
+                }
 
-            if !share.private_caps.program_interface && share.private_caps.frag_data_location {
-                for i in 0..subpass.color_attachments.len() {
-                    let color_name = format!("Target{}\0", i);
-                    gl.BindFragDataLocation(
+                if let Some(capture) = capture {
+                    let varyings = capture
+                        .varyings
+                        .iter()
+                        .map(|v| CString::new(v.as_str()).unwrap())
+                        .collect::<Vec<_>>();
+                    let pointers = varyings.iter().map(|v| v.as_ptr()).collect::<Vec<_>>();
+                    let mode = if capture.separate {
+                        gl::SEPARATE_ATTRIBS
+                    } else {
+                        gl::INTERLEAVED_ATTRIBS
+                    };
+                    gl_call!(gl.TransformFeedbackVaryings(
                         name,
-                        i as u32,
-                        (&color_name[..]).as_ptr() as *mut gl::types::GLchar,
-                    );
+                        pointers.len() as _,
+                        pointers.as_ptr(),
+                        mode,
+                    ));
                 }
-            }
 
-            gl.LinkProgram(name);
-            info!("\tLinked program {}", name);
-            if let Err(err) = share.check() {
-                panic!("Error linking program: {:?}", err);
-            }
-
-            for shader_name in shader_names {
-                gl.DetachShader(name, *shader_name);
-                gl.DeleteShader(*shader_name);
+                self.link_program(name, &shader_names, cache_key);
             }
 
             if !self
@@ -753,10 +1428,10 @@ impl d::Device<B> for Device {
                 .contains(LegacyFeatures::EXPLICIT_LAYOUTS_IN_SHADER)
             {
                 let gl = &self.share.context;
-                gl.UseProgram(name);
+                gl_call!(gl.UseProgram(name));
                 for (bname, binding) in name_binding_map.iter() {
-                    let loc = gl.GetUniformLocation(name, bname.as_ptr() as _);
-                    gl.Uniform1i(loc, *binding as _);
+                    let loc = gl_call!(gl.GetUniformLocation(name, bname.as_ptr() as _));
+                    gl_call!(gl.Uniform1i(loc, *binding as _));
                 }
             }
 
@@ -772,7 +1447,8 @@ impl d::Device<B> for Device {
                 ));
             }
 
-            name
+            let push_constants = self.get_push_constant_uniforms(name, &push_constant_map);
+            (name, push_constants)
         };
 
         let patch_size = match desc.input_assembler.primitive {
@@ -780,6 +1456,33 @@ impl d::Device<B> for Device {
             _ => None,
         };
 
+        let sample_shading = match desc.multisampling {
+            Some(ref ms) if ms.sample_shading.is_some() => {
+                if !share.features.contains(c::Features::SAMPLE_RATE_SHADING) {
+                    warn!("Sample shading isn't supported, shading once per pixel");
+                }
+                ms.sample_shading
+            }
+            _ => None,
+        };
+
+        let sample_coverage = match desc.multisampling {
+            Some(ref ms) => {
+                if ms.alpha_to_one && !share.private_caps.alpha_to_one {
+                    warn!("Alpha-to-one isn't supported, alpha is left untouched");
+                }
+                if ms.sample_mask != !0 && !share.private_caps.sample_mask {
+                    warn!("Sample masks aren't supported, all samples are covered");
+                }
+                n::SampleCoverage {
+                    alpha_to_coverage: ms.alpha_coverage,
+                    alpha_to_one: ms.alpha_to_one,
+                    sample_mask: ms.sample_mask,
+                }
+            }
+            None => n::SampleCoverage::default(),
+        };
+
         let mut vertex_buffers = Vec::new();
         for vb in &desc.vertex_buffers {
             while vertex_buffers.len() <= vb.binding as usize {
@@ -790,9 +1493,17 @@ impl d::Device<B> for Device {
 
         Ok(n::GraphicsPipeline {
             program,
+            push_constants,
             primitive: conv::primitive_to_gl_primitive(desc.input_assembler.primitive),
+            primitive_restart: desc.input_assembler.primitive_restart,
             patch_size,
+            sample_shading,
+            sample_coverage,
             blend_targets: desc.blender.targets.clone(),
+            logic_op: desc.blender.logic_op,
+            rasterizer: desc.rasterizer.clone(),
+            depth_stencil: desc.depth_stencil,
+            baked_states: desc.baked_states.clone(),
             vertex_buffers,
             attributes: desc
                 .attributes
@@ -812,6 +1523,269 @@ impl d::Device<B> for Device {
                 .collect(),
         })
     }
+}
+
+pub(crate) unsafe fn set_sampler_info<SetParamFloat, SetParamFloatVec, SetParamInt>(
+    share: &Starc<Share>,
+    info: &i::SamplerInfo,
+    mut set_param_float: SetParamFloat,
+    mut set_param_float_vec: SetParamFloatVec,
+    mut set_param_int: SetParamInt,
+) where
+    SetParamFloat: FnMut(GLenum, GLfloat),
+    SetParamFloatVec: FnMut(GLenum, &[GLfloat]),
+    SetParamInt: FnMut(GLenum, GLint),
+{
+    let (min, mag) = conv::filter_to_gl(info.mag_filter, info.min_filter, info.mip_filter);
+    // The core and extension enums share the same value.
+    if share.features.contains(c::Features::SAMPLER_ANISOTROPY) {
+        // Reset it when disabled, as the texture parameters outlive the sampler.
+        let anisotropy = match info.anisotropic {
+            i::Anisotropic::On(fac) => (fac as GLfloat)
+                .max(1.0)
+                .min(share.limits.max_sampler_anisotropy),
+            i::Anisotropic::Off => 1.0,
+        };
+        set_param_float(gl::TEXTURE_MAX_ANISOTROPY_EXT, anisotropy);
+    }
+
+    set_param_int(gl::TEXTURE_MIN_FILTER, min as GLint);
+    set_param_int(gl::TEXTURE_MAG_FILTER, mag as GLint);
+
+    let (s, t, r) = info.wrap_mode;
+    set_param_int(gl::TEXTURE_WRAP_S, conv::wrap_to_gl(s) as GLint);
+    set_param_int(gl::TEXTURE_WRAP_T, conv::wrap_to_gl(t) as GLint);
+    set_param_int(gl::TEXTURE_WRAP_R, conv::wrap_to_gl(r) as GLint);
+
+    if share
+        .features
+        .contains(hal::Features::SAMPLER_MIP_LOD_BIAS)
+    {
+        set_param_float(gl::TEXTURE_LOD_BIAS, info.lod_bias.into());
+    }
+    if share
+        .legacy_features
+        .contains(LegacyFeatures::SAMPLER_BORDER_COLOR)
+    {
+        let border: [f32; 4] = info.border.into();
+        set_param_float_vec(gl::TEXTURE_BORDER_COLOR, &border);
+    }
+
+    set_param_float(gl::TEXTURE_MIN_LOD, info.lod_range.start.into());
+    set_param_float(gl::TEXTURE_MAX_LOD, info.lod_range.end.into());
+
+    match info.comparison {
+        None => set_param_int(gl::TEXTURE_COMPARE_MODE, gl::NONE as GLint),
+        Some(cmp) => {
+            set_param_int(
+                gl::TEXTURE_COMPARE_MODE,
+                gl::COMPARE_REF_TO_TEXTURE as GLint,
+            );
+            set_param_int(
+                gl::TEXTURE_COMPARE_FUNC,
+                state::map_comparison(cmp) as GLint,
+            );
+        }
+    }
+}
+
+impl d::Device<B> for Device {
+    unsafe fn allocate_memory(
+        &self,
+        _mem_type: c::MemoryTypeId,
+        size: u64,
+    ) -> Result<n::Memory, d::AllocationError> {
+        // TODO
+        Ok(n::Memory {
+            properties: memory::Properties::CPU_VISIBLE | memory::Properties::CPU_CACHED,
+            first_bound_buffer: Cell::new(0),
+            size,
+            emulate_map_allocation: Cell::new(None),
+        })
+    }
+
+    unsafe fn create_command_pool(
+        &self,
+        _family: QueueFamilyId,
+        flags: CommandPoolCreateFlags,
+    ) -> Result<RawCommandPool, d::OutOfMemory> {
+        let fbo = create_fbo_internal(&self.share);
+        let read_fbo = create_fbo_internal(&self.share);
+        let limits = command::Limits::new(&self.share.limits, &self.share.private_caps);
+        let memory = if flags.contains(CommandPoolCreateFlags::RESET_INDIVIDUAL) {
+            BufferMemory::Individual {
+                storage: FastHashMap::default(),
+                next_buffer_id: 0,
+            }
+        } else {
+            BufferMemory::Linear(OwnedBuffer::new())
+        };
+
+        // Ignoring `TRANSIENT` hint, unsure how to make use of this.
+
+        Ok(RawCommandPool {
+            fbo,
+            read_fbo,
+            limits,
+            memory: Arc::new(Mutex::new(memory)),
+        })
+    }
+
+    unsafe fn destroy_command_pool(&self, pool: RawCommandPool) {
+        let gl = &self.share.context;
+        for fbo in pool.fbo.iter().chain(pool.read_fbo.iter()) {
+            gl_call!(gl.DeleteFramebuffers(1, fbo));
+        }
+    }
+
+    unsafe fn create_render_pass<'a, IA, IS, ID>(
+        &self,
+        attachments: IA,
+        subpasses: IS,
+        _dependencies: ID,
+    ) -> Result<n::RenderPass, d::OutOfMemory>
+    where
+        IA: IntoIterator,
+        IA::Item: Borrow<pass::Attachment>,
+        IS: IntoIterator,
+        IS::Item: Borrow<pass::SubpassDesc<'a>>,
+        ID: IntoIterator,
+        ID::Item: Borrow<pass::SubpassDependency>,
+    {
+        let subpasses = subpasses
+            .into_iter()
+            .map(|subpass| {
+                let color_attachments = subpass
+                    .borrow()
+                    .colors
+                    .iter()
+                    .map(|&(index, _)| index)
+                    .collect();
+                let depth_stencil = subpass.borrow().depth_stencil.map(|&(index, _)| index);
+                let resolve_attachments = subpass
+                    .borrow()
+                    .resolves
+                    .iter()
+                    .map(|&(index, _)| index)
+                    .collect();
+
+                n::SubpassDesc {
+                    color_attachments,
+                    depth_stencil,
+                    resolve_attachments,
+                }
+            })
+            .collect();
+
+        Ok(n::RenderPass {
+            attachments: attachments
+                .into_iter()
+                .map(|attachment| attachment.borrow().clone())
+                .collect::<Vec<_>>(),
+            subpasses,
+        })
+    }
+
+    unsafe fn create_pipeline_layout<IS, IR>(
+        &self,
+        layouts: IS,
+        _: IR,
+    ) -> Result<n::PipelineLayout, d::OutOfMemory>
+    where
+        IS: IntoIterator,
+        IS::Item: Borrow<n::DescriptorSetLayout>,
+        IR: IntoIterator,
+        IR::Item: Borrow<(pso::ShaderStageFlags, Range<u32>)>,
+    {
+        let mut drd = n::DescRemapData::new();
+
+        layouts.into_iter().enumerate().for_each(|(set, layout)| {
+            layout.borrow().bindings.iter().for_each(|binding| {
+                // DescriptorType -> Descriptor
+                //
+                // Sampler -> Sampler
+                // Image -> SampledImage, StorageImage, InputAttachment
+                // CombinedImageSampler -> CombinedImageSampler
+                // Buffer -> UniformBuffer, StorageBuffer
+                // UniformTexel -> UniformTexel
+                // StorageTexel -> StorageTexel
+
+                use pso::DescriptorType::*;
+                match binding.ty {
+                    CombinedImageSampler => {
+                        drd.insert_missing_binding_into_spare(
+                            n::BindingTypes::Images,
+                            set as _,
+                            binding.binding,
+                        );
+                    }
+                    Sampler | SampledImage => {
+                        // We need to figure out combos once we get the shaders, until then we
+                        // do nothing
+                    }
+                    UniformBuffer => {
+                        drd.insert_missing_binding_into_spare(
+                            n::BindingTypes::UniformBuffers,
+                            set as _,
+                            binding.binding,
+                        );
+                    }
+                    StorageBuffer => {
+                        drd.insert_missing_binding_into_spare(
+                            n::BindingTypes::StorageBuffers,
+                            set as _,
+                            binding.binding,
+                        );
+                    }
+                    UniformTexelBuffer => {
+                        drd.insert_missing_binding_into_spare(
+                            n::BindingTypes::Images,
+                            set as _,
+                            binding.binding,
+                        );
+                    }
+                    StorageImage | UniformBufferDynamic | StorageTexelBuffer
+                    | StorageBufferDynamic | InputAttachment => {
+                        unimplemented!() // 5
+                    }
+                }
+            })
+        });
+
+        Ok(n::PipelineLayout {
+            desc_remap_data: Arc::new(RwLock::new(drd)),
+        })
+    }
+
+    unsafe fn create_pipeline_cache(&self, _data: Option<&[u8]>) -> Result<(), d::OutOfMemory> {
+        Ok(())
+    }
+
+    unsafe fn get_pipeline_cache_data(&self, cache: &()) -> Result<Vec<u8>, d::OutOfMemory> {
+        //empty
+        Ok(Vec::new())
+    }
+
+    unsafe fn destroy_pipeline_cache(&self, _: ()) {
+        //empty
+    }
+
+    unsafe fn merge_pipeline_caches<I>(&self, _: &(), _: I) -> Result<(), d::OutOfMemory>
+    where
+        I: IntoIterator,
+        I::Item: Borrow<()>,
+    {
+        //empty
+        Ok(())
+    }
+
+    unsafe fn create_graphics_pipeline<'a>(
+        &self,
+        desc: &pso::GraphicsPipelineDesc<'a, B>,
+        _cache: Option<&()>,
+    ) -> Result<n::GraphicsPipeline, pso::CreationError> {
+        self.create_graphics_pipeline_impl(desc, None)
+    }
 
     unsafe fn create_compute_pipeline<'a>(
         &self,
@@ -821,37 +1795,47 @@ impl d::Device<B> for Device {
         let gl = &self.share.context;
         let share = &self.share;
 
-        let program = {
-            let name = gl.CreateProgram();
+        let (program, push_constants) = {
+            let name = gl_call!(gl.CreateProgram());
+            label_object(&self.share, gl::PROGRAM, name, "Program");
 
             let mut name_binding_map = FastHashMap::<String, pso::DescriptorBinding>::default();
-            let shader = self.compile_shader(
-                &desc.shader,
-                pso::Stage::Compute,
-                &mut desc.layout.desc_remap_data.write().unwrap(),
-                &mut name_binding_map,
-            );
-            gl.AttachShader(name, shader);
+            let mut push_constant_map = FastHashMap::default();
+            let source = {
+                let desc_remap_data = &mut desc.layout.desc_remap_data.write().unwrap();
+                let spirv = if share.private_caps.spirv {
+                    self.prepare_spirv(&desc.shader, desc_remap_data, &mut name_binding_map)
+                } else {
+                    None
+                };
+                spirv.unwrap_or_else(|| {
+                    self.translate_shader(
+                        &desc.shader,
+                        desc_remap_data,
+                        &mut name_binding_map,
+                        &mut push_constant_map,
+                    )
+                })
+            };
+            let sources = [(pso::Stage::Compute, source)];
+            let cache_key = self.program_cache_key(&sources, ());
 
-            gl.LinkProgram(name);
-            info!("\tLinked program {}", name);
-            if let Err(err) = share.check() {
-                panic!("Error linking program: {:?}", err);
+            if !cache_key.map_or(false, |key| self.load_program_binary(name, key)) {
+                let shader = self.compile_stage(&sources[0].1, pso::Stage::Compute);
+                gl_call!(gl.AttachShader(name, shader));
+                self.link_program(name, &[shader], cache_key);
             }
 
-            gl.DetachShader(name, shader);
-            gl.DeleteShader(shader);
-
             if !self
                 .share
                 .legacy_features
                 .contains(LegacyFeatures::EXPLICIT_LAYOUTS_IN_SHADER)
             {
                 let gl = &self.share.context;
-                gl.UseProgram(name);
+                gl_call!(gl.UseProgram(name));
                 for (bname, binding) in name_binding_map.iter() {
-                    let loc = gl.GetUniformLocation(name, bname.as_ptr() as _);
-                    gl.Uniform1i(loc, *binding as _);
+                    let loc = gl_call!(gl.GetUniformLocation(name, bname.as_ptr() as _));
+                    gl_call!(gl.Uniform1i(loc, *binding as _));
                 }
             }
 
@@ -865,43 +1849,61 @@ impl d::Device<B> for Device {
                 return Err(pso::CreationError::Other);
             }
 
-            name
+            let push_constants = self.get_push_constant_uniforms(name, &push_constant_map);
+            (name, push_constants)
         };
 
-        Ok(n::ComputePipeline { program })
+        Ok(n::ComputePipeline {
+            program,
+            push_constants,
+        })
     }
 
     unsafe fn create_framebuffer<I>(
         &self,
         pass: &n::RenderPass,
         attachments: I,
-        _extent: i::Extent,
-    ) -> Result<n::FrameBuffer, d::OutOfMemory>
+        extent: i::Extent,
+    ) -> Result<n::FatFrameBuffer, d::FramebufferError>
     where
         I: IntoIterator,
         I::Item: Borrow<n::ImageView>,
     {
         if !self.share.private_caps.framebuffer {
-            return Err(d::OutOfMemory::OutOfHostMemory);
+            return Err(d::OutOfMemory::OutOfHostMemory.into());
+        }
+        if extent.depth as usize > self.share.limits.max_framebuffer_layers {
+            return Err(d::FramebufferError::TooManyLayers);
         }
 
         let gl = &self.share.context;
         let target = gl::DRAW_FRAMEBUFFER;
         let mut name = 0;
-        gl.GenFramebuffers(1, &mut name);
-        gl.BindFramebuffer(target, name);
-
-        let att_points = [
-            gl::COLOR_ATTACHMENT0,
-            gl::COLOR_ATTACHMENT1,
-            gl::COLOR_ATTACHMENT2,
-            gl::COLOR_ATTACHMENT3,
-        ];
+        gl_call!(gl.GenFramebuffers(1, &mut name));
+        gl_call!(gl.BindFramebuffer(target, name));
 
+        // Color attachments are bound to the points matching their index in the pass.
+        let mut draw_buffers = Vec::new();
+        let mut resolves = Vec::new();
+        let mut resolve_views = Vec::new();
         let mut attachments_len = 0;
-        //TODO: exclude depth/stencil attachments from here
-        for (&att_point, view) in att_points.iter().zip(attachments.into_iter()) {
+        for (i, view) in attachments.into_iter().enumerate() {
             attachments_len += 1;
+            if pass.is_resolve(i) {
+                resolve_views.push((i, *view.borrow()));
+                continue;
+            }
+            let format = pass.attachments[i].format;
+            let att_point = match format {
+                Some(f) if f.is_depth() && f.is_stencil() => gl::DEPTH_STENCIL_ATTACHMENT,
+                Some(f) if f.is_depth() => gl::DEPTH_ATTACHMENT,
+                Some(f) if f.is_stencil() => gl::STENCIL_ATTACHMENT,
+                _ => {
+                    let point = gl::COLOR_ATTACHMENT0 + i as GLenum;
+                    draw_buffers.push(point);
+                    point
+                }
+            };
             if self.share.private_caps.framebuffer_texture {
                 Self::bind_target(gl, target, att_point, view.borrow());
             } else {
@@ -909,12 +1911,39 @@ impl d::Device<B> for Device {
             }
         }
         assert_eq!(attachments_len, pass.attachments.len());
-        // attachments_len actually equals min(attachments.len(), att_points.len()) until the next assert
 
-        assert!(pass.attachments.len() <= att_points.len());
-        gl.DrawBuffers(attachments_len as _, att_points.as_ptr());
-        let _status = gl.CheckFramebufferStatus(target); //TODO: check status
-        gl.BindFramebuffer(target, 0);
+        gl_call!(gl.DrawBuffers(draw_buffers.len() as _, draw_buffers.as_ptr()));
+        let status = gl_call!(gl.CheckFramebufferStatus(target));
+        if status == gl::FRAMEBUFFER_COMPLETE {
+            // Resolve attachments are blitted into from the main framebuffer.
+            resolves = resolve_views
+                .into_iter()
+                .map(|(i, view)| {
+                    let mut resolve = 0;
+                    gl_call!(gl.GenFramebuffers(1, &mut resolve));
+                    gl_call!(gl.BindFramebuffer(target, resolve));
+                    if self.share.private_caps.framebuffer_texture {
+                        Self::bind_target(gl, target, gl::COLOR_ATTACHMENT0, &view);
+                    } else {
+                        Self::bind_target_compat(gl, target, gl::COLOR_ATTACHMENT0, &view);
+                    }
+                    (i, resolve)
+                })
+                .collect();
+        }
+        gl_call!(gl.BindFramebuffer(target, 0));
+        if status != gl::FRAMEBUFFER_COMPLETE {
+            let reason = match status {
+                gl::FRAMEBUFFER_INCOMPLETE_ATTACHMENT => "incomplete attachment",
+                gl::FRAMEBUFFER_INCOMPLETE_MISSING_ATTACHMENT => "missing attachment",
+                gl::FRAMEBUFFER_INCOMPLETE_MULTISAMPLE => "mismatched samples",
+                // Either all attachments are layered with the same target, or none is.
+                gl::FRAMEBUFFER_INCOMPLETE_LAYER_TARGETS => "mixed layered attachments",
+                gl::FRAMEBUFFER_UNSUPPORTED => "unsupported attachment formats",
+                _ => "unknown",
+            };
+            error!("Incomplete framebuffer ({}) for {:?}", reason, pass);
+        }
 
         if let Err(err) = self.share.check() {
             //TODO: attachments have been consumed
@@ -924,7 +1953,11 @@ impl d::Device<B> for Device {
             );
         }
 
-        Ok(name)
+        Ok(n::FatFrameBuffer {
+            raw: name,
+            extent,
+            resolves,
+        })
     }
 
     unsafe fn create_shader_module(
@@ -946,16 +1979,18 @@ impl d::Device<B> for Device {
             return Ok(n::FatSampler::Info(info));
         }
 
+        // Only report the errors raised while creating the sampler.
+        self.share.clear_errors();
         let gl = &self.share.context;
         let mut name = 0 as n::Sampler;
 
-        gl.GenSamplers(1, &mut name);
+        gl_call!(gl.GenSamplers(1, &mut name));
         set_sampler_info(
             &self.share,
             &info,
-            |a, b| gl.SamplerParameterf(name, a, b),
-            |a, b| gl.SamplerParameterfv(name, a, &b[0]),
-            |a, b| gl.SamplerParameteri(name, a, b),
+            |a, b| gl_call!(gl.SamplerParameterf(name, a, b)),
+            |a, b| gl_call!(gl.SamplerParameterfv(name, a, &b[0])),
+            |a, b| gl_call!(gl.SamplerParameteri(name, a, b)),
         );
 
         if let Err(_) = self.share.check() {
@@ -992,7 +2027,7 @@ impl d::Device<B> for Device {
 
         let gl = &self.share.context;
         let mut name = 0;
-        gl.GenBuffers(1, &mut name);
+        gl_call!(gl.GenBuffers(1, &mut name));
 
         Ok(n::Buffer {
             raw: name,
@@ -1031,9 +2066,9 @@ impl d::Device<B> for Device {
             //TODO: gl::DYNAMIC_STORAGE_BIT | gl::MAP_PERSISTENT_BIT
             let flags = memory.map_flags();
             //TODO: use *Named calls to avoid binding
-            gl.BindBuffer(target, buffer.raw);
-            gl.BufferStorage(target, buffer.requirements.size as _, ptr::null(), flags);
-            gl.BindBuffer(target, 0);
+            gl_call!(gl.BindBuffer(target, buffer.raw));
+            gl_call!(gl.BufferStorage(target, buffer.requirements.size as _, ptr::null(), flags));
+            gl_call!(gl.BindBuffer(target, 0));
         } else {
             let flags = if cpu_can_read && cpu_can_write {
                 gl::DYNAMIC_DRAW
@@ -1044,11 +2079,13 @@ impl d::Device<B> for Device {
             } else {
                 gl::STATIC_DRAW
             };
-            gl.BindBuffer(target, buffer.raw);
-            gl.BufferData(target, buffer.requirements.size as _, ptr::null(), flags);
-            gl.BindBuffer(target, 0);
+            gl_call!(gl.BindBuffer(target, buffer.raw));
+            gl_call!(gl.BufferData(target, buffer.requirements.size as _, ptr::null(), flags));
+            gl_call!(gl.BindBuffer(target, 0));
         }
 
+        label_object(&self.share, gl::BUFFER, buffer.raw, "Buffer");
+
         if let Err(err) = self.share.check() {
             panic!(
                 "Error {:?} initializing buffer {:?}, memory {:?}",
@@ -1059,6 +2096,10 @@ impl d::Device<B> for Device {
         Ok(())
     }
 
+    unsafe fn set_buffer_name(&self, buffer: &mut n::Buffer, name: &str) {
+        set_object_label(&self.share, gl::BUFFER, buffer.raw, name);
+    }
+
     unsafe fn map_memory<R: RangeArg<u64>>(
         &self,
         memory: &n::Memory,
@@ -1070,17 +2111,39 @@ impl d::Device<B> for Device {
             other => other,
         };
 
-        assert!(self.share.private_caps.buffer_role_change);
-        let target = gl::PIXEL_PACK_BUFFER;
-        let access = memory.map_flags();
-
         let offset = *range.start().unwrap_or(&0);
         let size = *range.end().unwrap_or(&memory.size) - offset;
 
-        let ptr = {
-            gl.BindBuffer(target, buffer);
-            let ptr = gl.MapBufferRange(target, offset as _, size as _, access);
-            gl.BindBuffer(target, 0);
+        let ptr = if self.share.private_caps.emulate_map {
+            // WebGL2 allows binding any buffer to the copy targets
+            let target = gl::COPY_WRITE_BUFFER;
+            let base = match memory.emulate_map_allocation.get() {
+                Some(base) => base,
+                None => {
+                    let mut data = vec![0u8; memory.size as usize].into_boxed_slice();
+                    if memory.can_download() {
+                        gl_call!(gl.BindBuffer(target, buffer));
+                        gl_call!(gl.GetBufferSubData(
+                            target,
+                            0,
+                            memory.size as _,
+                            data.as_mut_ptr() as *mut _,
+                        ));
+                        gl_call!(gl.BindBuffer(target, 0));
+                    }
+                    let base = Box::into_raw(data) as *mut u8;
+                    memory.emulate_map_allocation.set(Some(base));
+                    base
+                }
+            };
+            base.offset(offset as isize)
+        } else {
+            assert!(self.share.private_caps.buffer_role_change);
+            let target = gl::PIXEL_PACK_BUFFER;
+            let access = memory.map_flags();
+            gl_call!(gl.BindBuffer(target, buffer));
+            let ptr = gl_call!(gl.MapBufferRange(target, offset as _, size as _, access));
+            gl_call!(gl.BindBuffer(target, 0));
             ptr as *mut _
         };
 
@@ -1097,24 +2160,59 @@ impl d::Device<B> for Device {
             0 => panic!("No buffer has been bound yet, can't map memory!"),
             other => other,
         };
-        let target = gl::PIXEL_PACK_BUFFER;
-
-        gl.BindBuffer(target, buffer);
-        gl.UnmapBuffer(target);
-        gl.BindBuffer(target, 0);
+        if self.share.private_caps.emulate_map {
+            if let Some(base) = memory.emulate_map_allocation.take() {
+                if memory.can_upload() {
+                    let target = gl::COPY_WRITE_BUFFER;
+                    gl_call!(gl.BindBuffer(target, buffer));
+                    gl_call!(gl.BufferSubData(target, 0, memory.size as _, base as *const _));
+                    gl_call!(gl.BindBuffer(target, 0));
+                }
+                let _ = Box::from_raw(slice::from_raw_parts_mut(base, memory.size as usize));
+            }
+        } else {
+            let target = gl::PIXEL_PACK_BUFFER;
+            gl_call!(gl.BindBuffer(target, buffer));
+            gl_call!(gl.UnmapBuffer(target));
+            gl_call!(gl.BindBuffer(target, 0));
+        }
 
         if let Err(err) = self.share.check() {
             panic!("Error unmapping memory: {:?} for memory {:?}", err, memory);
         }
     }
 
-    unsafe fn flush_mapped_memory_ranges<'a, I, R>(&self, _: I) -> Result<(), d::OutOfMemory>
+    unsafe fn flush_mapped_memory_ranges<'a, I, R>(&self, ranges: I) -> Result<(), d::OutOfMemory>
     where
         I: IntoIterator,
         I::Item: Borrow<(&'a n::Memory, R)>,
         R: RangeArg<u64>,
     {
-        warn!("memory range invalidation not implemented!");
+        if !self.share.private_caps.emulate_map {
+            warn!("memory range invalidation not implemented!");
+            return Ok(());
+        }
+
+        // upload the written data from the CPU-side copy
+        let gl = &self.share.context;
+        let target = gl::COPY_WRITE_BUFFER;
+        for range in ranges {
+            let (memory, ref range) = *range.borrow();
+            let base = match memory.emulate_map_allocation.get() {
+                Some(base) => base,
+                None => continue,
+            };
+            let offset = *range.start().unwrap_or(&0);
+            let size = *range.end().unwrap_or(&memory.size) - offset;
+            gl_call!(gl.BindBuffer(target, memory.first_bound_buffer.get()));
+            gl_call!(gl.BufferSubData(
+                target,
+                offset as _,
+                size as _,
+                base.offset(offset as isize) as *const _,
+            ));
+            gl_call!(gl.BindBuffer(target, 0));
+        }
         Ok(())
     }
 
@@ -1132,11 +2230,49 @@ impl d::Device<B> for Device {
 
     unsafe fn create_buffer_view<R: RangeArg<u64>>(
         &self,
-        _: &n::Buffer,
-        _: Option<Format>,
-        _: R,
+        buffer: &n::Buffer,
+        format: Option<Format>,
+        range: R,
     ) -> Result<n::BufferView, buffer::ViewCreationError> {
-        unimplemented!()
+        let int_format = match format.and_then(conv::image_format_to_gl) {
+            Some((int_format, _, _)) => int_format,
+            None => return Err(buffer::ViewCreationError::UnsupportedFormat { format }),
+        };
+
+        let start = *range.start().unwrap_or(&0);
+        let end = *range.end().unwrap_or(&buffer.requirements.size);
+        let whole = start == 0 && end == buffer.requirements.size;
+        if !whole && !self.share.private_caps.texture_buffer_range {
+            error!("Texture buffers can't view a sub-range of a buffer");
+            return Err(buffer::ViewCreationError::UnsupportedFormat { format });
+        }
+
+        self.share.clear_errors();
+        let gl = &self.share.context;
+        let mut name = 0;
+        gl_call!(gl.GenTextures(1, &mut name));
+        gl_call!(gl.BindTexture(gl::TEXTURE_BUFFER, name));
+        if whole {
+            gl_call!(gl.TexBuffer(gl::TEXTURE_BUFFER, int_format, buffer.raw));
+        } else {
+            gl_call!(gl.TexBufferRange(
+                gl::TEXTURE_BUFFER,
+                int_format,
+                buffer.raw,
+                start as _,
+                (end - start) as _,
+            ));
+        }
+        gl_call!(gl.BindTexture(gl::TEXTURE_BUFFER, 0));
+
+        label_object(&self.share, gl::TEXTURE, name, "BufferView");
+        if let Err(err) = self.share.check() {
+            error!("Error creating buffer view: {:?}", err);
+            gl_call!(gl.DeleteTextures(1, &name));
+            return Err(buffer::ViewCreationError::UnsupportedFormat { format });
+        }
+
+        Ok(n::BufferView { raw: name })
     }
 
     unsafe fn create_image(
@@ -1146,80 +2282,263 @@ impl d::Device<B> for Device {
         format: Format,
         _tiling: i::Tiling,
         usage: i::Usage,
-        _view_caps: i::ViewCapabilities,
+        view_caps: i::ViewCapabilities,
     ) -> Result<n::Image, i::CreationError> {
+        self.share.clear_errors();
         let gl = &self.share.context;
 
-        let (int_format, iformat, itype) = match format {
-            Format::Rgba8Unorm => (gl::RGBA8, gl::RGBA, gl::UNSIGNED_BYTE),
-            Format::Rgba8Srgb => (gl::SRGB8_ALPHA8, gl::RGBA, gl::UNSIGNED_BYTE),
-            _ => unimplemented!(),
+        let compressed = format.surface_desc().is_compressed();
+        let (int_format, iformat, itype) = if compressed {
+            let required = conv::compressed_format_feature(format);
+            match conv::compressed_format_to_gl(format) {
+                Some(int_format) if self.share.features.contains(required) => (int_format, 0, 0),
+                _ => return Err(i::CreationError::Format(format)),
+            }
+        } else {
+            match conv::image_format_to_gl(format) {
+                Some(formats) => formats,
+                None => return Err(i::CreationError::Format(format)),
+            }
         };
 
         let channel = format.base_format().1;
+        let ext = kind.extent();
+        let num_layers = kind.num_layers();
+        let samples = kind.num_samples();
 
-        let image = if num_levels > 1
+        let (image, target, immutable) = if samples > 1
+            && (usage.contains(i::Usage::STORAGE) || usage.contains(i::Usage::SAMPLED))
+        {
+            // Multisampled textures are read per sample in shaders (`sampler2DMS`).
+            if !self.share.private_caps.multisample_texture {
+                return Err(i::CreationError::Samples(samples));
+            }
+            let target = if num_layers > 1 {
+                gl::TEXTURE_2D_MULTISAMPLE_ARRAY
+            } else {
+                gl::TEXTURE_2D_MULTISAMPLE
+            };
+            let immutable = self.share.private_caps.multisample_texture_storage;
+
+            let mut name = 0;
+            gl_call!(gl.GenTextures(1, &mut name));
+            gl_call!(gl.BindTexture(target, name));
+            match (target, immutable) {
+                (gl::TEXTURE_2D_MULTISAMPLE, true) => gl_call!(gl.TexStorage2DMultisample(
+                    target,
+                    samples as _,
+                    int_format,
+                    ext.width as _,
+                    ext.height as _,
+                    gl::TRUE,
+                )),
+                (gl::TEXTURE_2D_MULTISAMPLE, false) => gl_call!(gl.TexImage2DMultisample(
+                    target,
+                    samples as _,
+                    int_format,
+                    ext.width as _,
+                    ext.height as _,
+                    gl::TRUE,
+                )),
+                (_, true) => gl_call!(gl.TexStorage3DMultisample(
+                    target,
+                    samples as _,
+                    int_format,
+                    ext.width as _,
+                    ext.height as _,
+                    num_layers as _,
+                    gl::TRUE,
+                )),
+                (_, false) => gl_call!(gl.TexImage3DMultisample(
+                    target,
+                    samples as _,
+                    int_format,
+                    ext.width as _,
+                    ext.height as _,
+                    num_layers as _,
+                    gl::TRUE,
+                )),
+            }
+            label_object(&self.share, gl::TEXTURE, name, "Texture");
+            (n::ImageKind::Texture(name), target, immutable)
+        } else if compressed
+            || num_levels > 1
             || usage.contains(i::Usage::STORAGE)
             || usage.contains(i::Usage::SAMPLED)
+            // Renderbuffers can't be uploaded to.
+            || (usage.contains(i::Usage::TRANSFER_DST) && samples == 1)
         {
+            let target = match kind {
+                i::Kind::D2(_, _, 6, 1) if view_caps.contains(i::ViewCapabilities::KIND_CUBE) => {
+                    gl::TEXTURE_CUBE_MAP
+                }
+                i::Kind::D2(_, _, 1, 1) => gl::TEXTURE_2D,
+                i::Kind::D2(_, _, _, 1) => gl::TEXTURE_2D_ARRAY,
+                i::Kind::D3(..) => gl::TEXTURE_3D,
+                _ => unimplemented!(),
+            };
+            // Layers of an array texture are its depth slices.
+            let depth = if target == gl::TEXTURE_2D_ARRAY {
+                num_layers as i::Size
+            } else {
+                ext.depth
+            };
+            let immutable = self.share.private_caps.image_storage;
+
             let mut name = 0;
-            gl.GenTextures(1, &mut name);
-            match kind {
-                i::Kind::D2(w, h, 1, 1) => {
-                    gl.BindTexture(gl::TEXTURE_2D, name);
-                    if self.share.private_caps.image_storage {
-                        gl.TexStorage2D(
-                            gl::TEXTURE_2D,
-                            num_levels as _,
-                            int_format,
+            gl_call!(gl.GenTextures(1, &mut name));
+            gl_call!(gl.BindTexture(target, name));
+            if immutable {
+                match target {
+                    gl::TEXTURE_2D | gl::TEXTURE_CUBE_MAP => gl_call!(gl.TexStorage2D(
+                        target,
+                        num_levels as _,
+                        int_format,
+                        ext.width as _,
+                        ext.height as _,
+                    )),
+                    _ => gl_call!(gl.TexStorage3D(
+                        target,
+                        num_levels as _,
+                        int_format,
+                        ext.width as _,
+                        ext.height as _,
+                        depth as _,
+                    )),
+                }
+            } else {
+                // Specify every level, otherwise the texture is incomplete.
+                gl_call!(gl.TexParameteri(target, gl::TEXTURE_MAX_LEVEL, (num_levels - 1) as _));
+                for level in 0..num_levels {
+                    let w = cmp::max(ext.width >> level, 1);
+                    let h = cmp::max(ext.height >> level, 1);
+                    if compressed {
+                        let d = if target == gl::TEXTURE_3D {
+                            cmp::max(depth >> level, 1)
+                        } else {
+                            depth
+                        };
+                        let level_extent = i::Extent {
+                            width: w,
+                            height: h,
+                            depth: d,
+                        };
+                        let size = conv::image_data_size(format, level_extent);
+                        match target {
+                            gl::TEXTURE_2D => gl_call!(gl.CompressedTexImage2D(
+                                target,
+                                level as _,
+                                int_format,
+                                w as _,
+                                h as _,
+                                0,
+                                size as _,
+                                ptr::null(),
+                            )),
+                            gl::TEXTURE_CUBE_MAP => {
+                                for face in 0..6 {
+                                    gl_call!(gl.CompressedTexImage2D(
+                                        gl::TEXTURE_CUBE_MAP_POSITIVE_X + face,
+                                        level as _,
+                                        int_format,
+                                        w as _,
+                                        h as _,
+                                        0,
+                                        size as _,
+                                        ptr::null(),
+                                    ));
+                                }
+                            }
+                            _ => gl_call!(gl.CompressedTexImage3D(
+                                target,
+                                level as _,
+                                int_format,
+                                w as _,
+                                h as _,
+                                d as _,
+                                0,
+                                size as _,
+                                ptr::null(),
+                            )),
+                        }
+                        continue;
+                    }
+                    match target {
+                        gl::TEXTURE_2D => gl_call!(gl.TexImage2D(
+                            target,
+                            level as _,
+                            int_format as _,
                             w as _,
                             h as _,
-                        );
-                    } else {
-                        gl.TexParameteri(
-                            gl::TEXTURE_2D,
-                            gl::TEXTURE_MAX_LEVEL,
-                            (num_levels - 1) as _,
-                        );
-                        let mut w = w;
-                        let mut h = h;
-                        for i in 0..num_levels {
-                            gl.TexImage2D(
-                                gl::TEXTURE_2D,
-                                i as _,
+                            0,
+                            iformat,
+                            itype,
+                            ptr::null(),
+                        )),
+                        gl::TEXTURE_CUBE_MAP => {
+                            for face in 0..6 {
+                                gl_call!(gl.TexImage2D(
+                                    gl::TEXTURE_CUBE_MAP_POSITIVE_X + face,
+                                    level as _,
+                                    int_format as _,
+                                    w as _,
+                                    h as _,
+                                    0,
+                                    iformat,
+                                    itype,
+                                    ptr::null(),
+                                ));
+                            }
+                        }
+                        _ => {
+                            let d = if target == gl::TEXTURE_3D {
+                                cmp::max(depth >> level, 1)
+                            } else {
+                                depth
+                            };
+                            gl_call!(gl.TexImage3D(
+                                target,
+                                level as _,
                                 int_format as _,
                                 w as _,
                                 h as _,
+                                d as _,
                                 0,
                                 iformat,
                                 itype,
-                                std::ptr::null(),
-                            );
-                            w = std::cmp::max(w / 2, 1);
-                            h = std::cmp::max(h / 2, 1);
+                                ptr::null(),
+                            ));
                         }
                     }
                 }
-                _ => unimplemented!(),
-            };
-            n::ImageKind::Texture(name)
+            }
+            label_object(&self.share, gl::TEXTURE, name, "Texture");
+            (n::ImageKind::Texture(name), target, immutable)
         } else {
             let mut name = 0;
-            gl.GenRenderbuffers(1, &mut name);
+            gl_call!(gl.GenRenderbuffers(1, &mut name));
             match kind {
                 i::Kind::D2(w, h, 1, 1) => {
-                    gl.BindRenderbuffer(gl::RENDERBUFFER, name);
-                    gl.RenderbufferStorage(gl::RENDERBUFFER, int_format, w as _, h as _);
+                    gl_call!(gl.BindRenderbuffer(gl::RENDERBUFFER, name));
+                    gl_call!(gl.RenderbufferStorage(gl::RENDERBUFFER, int_format, w as _, h as _));
+                }
+                i::Kind::D2(w, h, 1, samples) => {
+                    gl_call!(gl.BindRenderbuffer(gl::RENDERBUFFER, name));
+                    gl_call!(gl.RenderbufferStorageMultisample(
+                        gl::RENDERBUFFER,
+                        samples as _,
+                        int_format,
+                        w as _,
+                        h as _,
+                    ));
                 }
                 _ => unimplemented!(),
             };
-            n::ImageKind::Surface(name)
+            label_object(&self.share, gl::RENDERBUFFER, name, "Renderbuffer");
+            (n::ImageKind::Surface(name), gl::RENDERBUFFER, false)
         };
 
-        let surface_desc = format.base_format().0.desc();
-        let bytes_per_texel = surface_desc.bits / 8;
-        let ext = kind.extent();
-        let size = (ext.width * ext.height * ext.depth) as u64 * bytes_per_texel as u64;
+        let size = conv::image_data_size(format, ext) * num_layers as u64;
 
         if let Err(err) = self.share.check() {
             panic!(
@@ -1236,6 +2555,13 @@ impl d::Device<B> for Device {
                 alignment: 1,
                 type_mask: 0x7,
             },
+            target,
+            extent: ext,
+            format,
+            levels: num_levels,
+            layers: num_layers,
+            samples,
+            immutable,
         })
     }
 
@@ -1260,22 +2586,36 @@ impl d::Device<B> for Device {
         Ok(())
     }
 
+    unsafe fn set_image_name(&self, image: &mut n::Image, name: &str) {
+        match image.kind {
+            n::ImageKind::Surface(rb) => set_object_label(&self.share, gl::RENDERBUFFER, rb, name),
+            n::ImageKind::Texture(t) => set_object_label(&self.share, gl::TEXTURE, t, name),
+        }
+    }
+
     unsafe fn create_image_view(
         &self,
         image: &n::Image,
-        _kind: i::ViewKind,
-        _format: Format,
+        kind: i::ViewKind,
+        format: Format,
         swizzle: Swizzle,
         range: i::SubresourceRange,
     ) -> Result<n::ImageView, i::ViewError> {
-        //TODO: check if `layers.end` covers all the layers
         let level = range.levels.start;
-        assert_eq!(level + 1, range.levels.end);
-        //assert_eq!(format, image.format);
-        assert_eq!(swizzle, Swizzle::NO);
-        //TODO: check format
+        if level >= image.levels {
+            return Err(i::ViewError::Level(level));
+        }
+        if range.layers.start >= image.layers {
+            return Err(i::ViewError::Layer(i::LayerError::OutOfBounds(
+                range.layers,
+            )));
+        }
+        let level_end = cmp::min(range.levels.end, image.levels);
+        let layer_end = cmp::min(range.layers.end, image.layers);
+
         match image.kind {
             n::ImageKind::Surface(surface) => {
+                assert_eq!(swizzle, Swizzle::NO);
                 if range.levels.start == 0 && range.layers.start == 0 {
                     Ok(n::ImageView::Surface(surface))
                 } else if level != 0 {
@@ -1287,12 +2627,81 @@ impl d::Device<B> for Device {
                 }
             }
             n::ImageKind::Texture(texture) => {
-                //TODO: check that `level` exists
-                if range.layers.start == 0 {
-                    Ok(n::ImageView::Texture(texture, level))
-                } else if range.layers.start + 1 == range.layers.end {
+                let multisampled = image.target == gl::TEXTURE_2D_MULTISAMPLE
+                    || image.target == gl::TEXTURE_2D_MULTISAMPLE_ARRAY;
+                let target = match kind {
+                    i::ViewKind::D2 if multisampled => gl::TEXTURE_2D_MULTISAMPLE,
+                    i::ViewKind::D2Array if multisampled => gl::TEXTURE_2D_MULTISAMPLE_ARRAY,
+                    _ => conv::view_kind_to_gl(kind),
+                };
+                let whole = target == image.target
+                    && format == image.format
+                    && swizzle == Swizzle::NO
+                    && range.layers.start == 0
+                    && layer_end == image.layers
+                    && (level + 1 == level_end || (level == 0 && level_end == image.levels));
+                if whole {
+                    return Ok(n::ImageView::Texture(texture, target, level));
+                }
+
+                if self.share.private_caps.texture_view && image.immutable {
+                    let int_format = match conv::image_format_to_gl(format) {
+                        Some((int_format, _, _)) => int_format,
+                        None => return Err(i::ViewError::BadFormat(format)),
+                    };
+                    self.share.clear_errors();
+                    let gl = &self.share.context;
+                    let mut name = 0;
+                    gl_call!(gl.GenTextures(1, &mut name));
+                    gl_call!(gl.TextureView(
+                        name,
+                        target,
+                        texture,
+                        int_format,
+                        level as _,
+                        (level_end - level) as _,
+                        range.layers.start as _,
+                        (layer_end - range.layers.start) as _,
+                    ));
+                    if swizzle != Swizzle::NO {
+                        gl_call!(gl.BindTexture(target, name));
+                        let components = [
+                            (gl::TEXTURE_SWIZZLE_R, swizzle.0),
+                            (gl::TEXTURE_SWIZZLE_G, swizzle.1),
+                            (gl::TEXTURE_SWIZZLE_B, swizzle.2),
+                            (gl::TEXTURE_SWIZZLE_A, swizzle.3),
+                        ];
+                        for &(pname, component) in &components {
+                            gl_call!(gl.TexParameteri(
+                                target,
+                                pname,
+                                conv::component_to_gl(component) as _
+                            ));
+                        }
+                        gl_call!(gl.BindTexture(target, 0));
+                    }
+                    label_object(&self.share, gl::TEXTURE, name, "TextureView");
+                    if let Err(err) = self.share.check() {
+                        error!("Error creating texture view: {:?}", err);
+                        gl_call!(gl.DeleteTextures(1, &name));
+                        return Err(i::ViewError::Unsupported);
+                    }
+                    return Ok(n::ImageView::TextureView(name, target));
+                }
+
+                // Without views, only whole layers of a single level can be selected.
+                if format != image.format {
+                    Err(i::ViewError::BadFormat(format))
+                } else if swizzle != Swizzle::NO {
+                    Err(i::ViewError::Unsupported)
+                } else if level + 1 != level_end {
+                    Err(i::ViewError::Level(level_end))
+                } else if range.layers.start == 0 {
+                    Ok(n::ImageView::Texture(texture, image.target, level))
+                } else if range.layers.start + 1 == layer_end {
                     Ok(n::ImageView::TextureLayer(
                         texture,
+                        image.target,
                         level,
                         range.layers.start,
                     ))
@@ -1320,7 +2729,7 @@ impl d::Device<B> for Device {
     unsafe fn create_descriptor_set_layout<I, J>(
         &self,
         layout: I,
-        _: J,
+        immutable_samplers: J,
     ) -> Result<n::DescriptorSetLayout, d::OutOfMemory>
     where
         I: IntoIterator,
@@ -1328,8 +2737,30 @@ impl d::Device<B> for Device {
         J: IntoIterator,
         J::Item: Borrow<n::FatSampler>,
     {
-        // Just return it
-        Ok(layout.into_iter().map(|l| l.borrow().clone()).collect())
+        let bindings = layout
+            .into_iter()
+            .map(|l| l.borrow().clone())
+            .collect::<Vec<_>>();
+
+        let mut immutable_sampler_iter = immutable_samplers.into_iter();
+        let immutable_samplers = bindings
+            .iter()
+            .filter(|binding| binding.immutable_samplers)
+            .filter_map(|binding| {
+                // Bindings only hold a single sampler, skip the remaining ones.
+                let mut samplers = immutable_sampler_iter.by_ref().take(binding.count);
+                let first = samplers
+                    .next()
+                    .map(|sampler| (binding.binding, sampler.borrow().clone()));
+                samplers.for_each(drop);
+                first
+            })
+            .collect();
+
+        Ok(n::DescriptorSetLayout {
+            bindings,
+            immutable_samplers,
+        })
     }
 
     unsafe fn write_descriptor_sets<'a, I, J>(&self, writes: I)
@@ -1350,9 +2781,17 @@ impl d::Device<B> for Device {
                         let start = range.start.unwrap_or(0);
                         let end = range.end.unwrap_or(buffer.requirements.size);
                         let size = (end - start) as _;
+                        let is_storage = set.layout.bindings.iter().any(|layout_binding| {
+                            layout_binding.binding == binding
+                                && layout_binding.ty == pso::DescriptorType::StorageBuffer
+                        });
 
                         bindings.push(n::DescSetBindings::Buffer {
-                            ty: n::BindingTypes::UniformBuffers,
+                            ty: if is_storage {
+                                n::BindingTypes::StorageBuffers
+                            } else {
+                                n::BindingTypes::UniformBuffers
+                            },
                             binding,
                             buffer: buffer.raw,
                             offset,
@@ -1363,9 +2802,10 @@ impl d::Device<B> for Device {
                     }
                     pso::Descriptor::CombinedImageSampler(view, _layout, sampler) => {
                         match view {
-                            n::ImageView::Texture(tex, _)
-                            | n::ImageView::TextureLayer(tex, _, _) => {
-                                bindings.push(n::DescSetBindings::Texture(binding, *tex))
+                            n::ImageView::Texture(tex, target, _)
+                            | n::ImageView::TextureLayer(tex, target, _, _)
+                            | n::ImageView::TextureView(tex, target) => {
+                                bindings.push(n::DescSetBindings::Texture(binding, *tex, *target))
                             }
                             n::ImageView::Surface(_) => unimplemented!(),
                         }
@@ -1378,8 +2818,10 @@ impl d::Device<B> for Device {
                         }
                     }
                     pso::Descriptor::Image(view, _layout) => match view {
-                        n::ImageView::Texture(tex, _) | n::ImageView::TextureLayer(tex, _, _) => {
-                            bindings.push(n::DescSetBindings::Texture(binding, *tex))
+                        n::ImageView::Texture(tex, target, _)
+                        | n::ImageView::TextureLayer(tex, target, _, _)
+                        | n::ImageView::TextureView(tex, target) => {
+                            bindings.push(n::DescSetBindings::Texture(binding, *tex, *target))
                         }
                         n::ImageView::Surface(_) => panic!(
                             "Texture was created with only render target usage which is invalid."
@@ -1393,7 +2835,9 @@ impl d::Device<B> for Device {
                             bindings.push(n::DescSetBindings::SamplerInfo(binding, info.clone()))
                         }
                     },
-                    pso::Descriptor::UniformTexelBuffer(_view) => unimplemented!(),
+                    pso::Descriptor::UniformTexelBuffer(view) => bindings.push(
+                        n::DescSetBindings::Texture(binding, view.raw, gl::TEXTURE_BUFFER),
+                    ),
                     pso::Descriptor::StorageTexelBuffer(_view) => unimplemented!(),
                 }
             }
@@ -1411,13 +2855,13 @@ impl d::Device<B> for Device {
     }
 
     fn create_semaphore(&self) -> Result<n::Semaphore, d::OutOfMemory> {
-        Ok(n::Semaphore)
+        Ok(n::Semaphore::default())
     }
 
     fn create_fence(&self, signalled: bool) -> Result<n::Fence, d::OutOfMemory> {
         let sync = if signalled && self.share.private_caps.sync {
             let gl = &self.share.context;
-            unsafe { gl.FenceSync(gl::SYNC_GPU_COMMANDS_COMPLETE, 0) }
+            unsafe { gl_call!(gl.FenceSync(gl::SYNC_GPU_COMMANDS_COMPLETE, 0)) }
         } else {
             ptr::null()
         };
@@ -1434,8 +2878,8 @@ impl d::Device<B> for Device {
             let fence = fence.borrow();
             let sync = fence.0.get();
             if !sync.is_null() {
-                if self.share.private_caps.sync && gl.IsSync(sync) == gl::TRUE {
-                    gl.DeleteSync(sync);
+                if self.share.private_caps.sync && gl_call!(gl.IsSync(sync)) == gl::TRUE {
+                    gl_call!(gl.DeleteSync(sync));
                 }
             }
             fence.0.set(ptr::null())
@@ -1448,9 +2892,6 @@ impl d::Device<B> for Device {
         fence: &n::Fence,
         timeout_ns: u64,
     ) -> Result<bool, d::OomOrDeviceLost> {
-        if !self.share.private_caps.sync {
-            return Ok(true);
-        }
         match wait_fence(fence, &self.share, timeout_ns) {
             gl::TIMEOUT_EXPIRED => Ok(false),
             gl::WAIT_FAILED => {
@@ -1463,8 +2904,29 @@ impl d::Device<B> for Device {
         }
     }
 
-    unsafe fn get_fence_status(&self, _: &n::Fence) -> Result<bool, d::DeviceLost> {
-        unimplemented!()
+    unsafe fn get_fence_status(&self, fence: &n::Fence) -> Result<bool, d::DeviceLost> {
+        match wait_fence(fence, &self.share, 0) {
+            gl::ALREADY_SIGNALED | gl::CONDITION_SATISFIED => Ok(true),
+            _ => Ok(false),
+        }
+    }
+
+    fn create_event(&self) -> Result<n::Event, d::OutOfMemory> {
+        Ok(n::Event(Arc::new(AtomicBool::new(false))))
+    }
+
+    unsafe fn get_event_status(&self, event: &n::Event) -> Result<bool, d::OomOrDeviceLost> {
+        Ok(event.0.load(Ordering::Acquire))
+    }
+
+    unsafe fn set_event(&self, event: &n::Event) -> Result<(), d::OutOfMemory> {
+        event.0.store(true, Ordering::Release);
+        Ok(())
+    }
+
+    unsafe fn reset_event(&self, event: &n::Event) -> Result<(), d::OutOfMemory> {
+        event.0.store(false, Ordering::Release);
+        Ok(())
     }
 
     unsafe fn free_memory(&self, _memory: n::Memory) {
@@ -1473,25 +2935,102 @@ impl d::Device<B> for Device {
 
     unsafe fn create_query_pool(
         &self,
-        _ty: query::Type,
-        _count: query::Id,
-    ) -> Result<(), query::CreationError> {
-        unimplemented!()
+        ty: query::Type,
+        count: query::Id,
+    ) -> Result<n::QueryPool, query::CreationError> {
+        let targets = match ty {
+            query::Type::Occlusion => {
+                if self
+                    .share
+                    .features
+                    .contains(c::Features::PRECISE_OCCLUSION_QUERY)
+                {
+                    vec![gl::SAMPLES_PASSED]
+                } else {
+                    vec![gl::ANY_SAMPLES_PASSED]
+                }
+            }
+            // Timestamps are written with `glQueryCounter`, while queries
+            // delimited by `begin_query` and `end_query` measure the elapsed time.
+            query::Type::Timestamp if self.share.private_caps.timer_query => {
+                vec![gl::TIME_ELAPSED]
+            }
+            query::Type::PipelineStatistics(statistics)
+                if !statistics.is_empty()
+                    && self
+                        .share
+                        .features
+                        .contains(c::Features::PIPELINE_STATISTICS_QUERY) =>
+            {
+                conv::pipeline_statistics_to_gl(statistics)
+            }
+            _ => return Err(query::CreationError::Unsupported(ty)),
+        };
+
+        let mut queries = vec![0; count as usize * targets.len()];
+        let gl = &self.share.context;
+        gl_call!(gl.GenQueries(queries.len() as _, queries.as_mut_ptr()));
+
+        Ok(n::QueryPool { targets, queries })
     }
 
-    unsafe fn destroy_query_pool(&self, _: ()) {
-        unimplemented!()
+    unsafe fn destroy_query_pool(&self, pool: n::QueryPool) {
+        let gl = &self.share.context;
+        gl_call!(gl.DeleteQueries(pool.queries.len() as _, pool.queries.as_ptr()));
     }
 
     unsafe fn get_query_pool_results(
         &self,
-        _pool: &(),
-        _queries: Range<query::Id>,
-        _data: &mut [u8],
-        _stride: buffer::Offset,
-        _flags: query::ResultFlags,
+        pool: &n::QueryPool,
+        queries: Range<query::Id>,
+        data: &mut [u8],
+        stride: buffer::Offset,
+        flags: query::ResultFlags,
     ) -> Result<bool, d::OomOrDeviceLost> {
-        unimplemented!()
+        let gl = &self.share.context;
+        let wide = flags.contains(query::ResultFlags::BITS_64);
+        let write = |data: &mut [u8], offset: usize, value: u64| {
+            let size = if wide { 8 } else { 4 };
+            for (i, byte) in data[offset..offset + size].iter_mut().enumerate() {
+                *byte = (value >> (i * 8)) as u8;
+            }
+        };
+
+        let size = if wide { 8 } else { 4 };
+
+        let mut ready = true;
+        for (i, id) in queries.enumerate() {
+            let raw_queries = pool.queries(id);
+            let offset = i * stride as usize;
+
+            // Without `WAIT`, only poll the availability so the call never blocks.
+            // All the statistics of a query complete together, checking the last one is enough.
+            let mut available = 1;
+            if !flags.contains(query::ResultFlags::WAIT) {
+                let last = raw_queries[raw_queries.len() - 1];
+                gl_call!(gl.GetQueryObjectuiv(last, gl::QUERY_RESULT_AVAILABLE, &mut available));
+            }
+            if available != 0 {
+                for (k, &query) in raw_queries.iter().enumerate() {
+                    let mut value = 0;
+                    if self.share.private_caps.timer_query {
+                        gl_call!(gl.GetQueryObjectui64v(query, gl::QUERY_RESULT, &mut value));
+                    } else {
+                        let mut narrow = 0;
+                        gl_call!(gl.GetQueryObjectuiv(query, gl::QUERY_RESULT, &mut narrow));
+                        value = narrow as u64;
+                    }
+                    write(data, offset + k * size, value);
+                }
+            } else {
+                ready = false;
+            }
+            if flags.contains(query::ResultFlags::WITH_AVAILABILITY) {
+                write(data, offset + raw_queries.len() * size, available as u64);
+            }
+        }
+
+        Ok(ready || flags.contains(query::ResultFlags::PARTIAL))
     }
 
     unsafe fn destroy_shader_module(&self, _: n::ShaderModule) {
@@ -1507,41 +3046,53 @@ impl d::Device<B> for Device {
     }
 
     unsafe fn destroy_graphics_pipeline(&self, pipeline: n::GraphicsPipeline) {
-        self.share.context.DeleteProgram(pipeline.program);
+        gl_call!(self.share.context.DeleteProgram(pipeline.program));
     }
 
     unsafe fn destroy_compute_pipeline(&self, pipeline: n::ComputePipeline) {
-        self.share.context.DeleteProgram(pipeline.program);
+        gl_call!(self.share.context.DeleteProgram(pipeline.program));
     }
 
-    unsafe fn destroy_framebuffer(&self, frame_buffer: n::FrameBuffer) {
+    unsafe fn destroy_framebuffer(&self, frame_buffer: n::FatFrameBuffer) {
         let gl = &self.share.context;
-        gl.DeleteFramebuffers(1, &frame_buffer);
+        gl_call!(gl.DeleteFramebuffers(1, &frame_buffer.raw));
+        for (_, resolve) in frame_buffer.resolves {
+            gl_call!(gl.DeleteFramebuffers(1, &resolve));
+        }
     }
 
     unsafe fn destroy_buffer(&self, buffer: n::Buffer) {
-        self.share.context.DeleteBuffers(1, &buffer.raw);
+        let gl = &self.share.context;
+        if self.share.private_caps.vertex_array {
+            self.share
+                .vertex_arrays
+                .borrow_mut()
+                .evict_buffer(gl, buffer.raw);
+        }
+        gl_call!(gl.DeleteBuffers(1, &buffer.raw));
     }
-    unsafe fn destroy_buffer_view(&self, _: n::BufferView) {
-        // Nothing to do
+    unsafe fn destroy_buffer_view(&self, view: n::BufferView) {
+        gl_call!(self.share.context.DeleteTextures(1, &view.raw));
     }
 
     unsafe fn destroy_image(&self, image: n::Image) {
         let gl = &self.share.context;
         match image.kind {
-            n::ImageKind::Surface(rb) => gl.DeleteRenderbuffers(1, &rb),
-            n::ImageKind::Texture(t) => gl.DeleteTextures(1, &t),
+            n::ImageKind::Surface(rb) => gl_call!(gl.DeleteRenderbuffers(1, &rb)),
+            n::ImageKind::Texture(t) => gl_call!(gl.DeleteTextures(1, &t)),
         }
     }
 
-    unsafe fn destroy_image_view(&self, _image_view: n::ImageView) {
-        // Nothing to do
+    unsafe fn destroy_image_view(&self, image_view: n::ImageView) {
+        if let n::ImageView::TextureView(texture, _) = image_view {
+            gl_call!(self.share.context.DeleteTextures(1, &texture));
+        }
     }
 
     unsafe fn destroy_sampler(&self, sampler: n::FatSampler) {
         let gl = &self.share.context;
         match sampler {
-            n::FatSampler::Sampler(s) => gl.DeleteSamplers(1, &s),
+            n::FatSampler::Sampler(s) => gl_call!(gl.DeleteSamplers(1, &s)),
             _ => (),
         }
     }
@@ -1557,8 +3108,8 @@ impl d::Device<B> for Device {
     unsafe fn destroy_fence(&self, fence: n::Fence) {
         let gl = &self.share.context;
         let sync = fence.0.get();
-        if self.share.private_caps.sync && gl.IsSync(sync) == gl::TRUE {
-            gl.DeleteSync(sync);
+        if self.share.private_caps.sync && gl_call!(gl.IsSync(sync)) == gl::TRUE {
+            gl_call!(gl.DeleteSync(sync));
         }
     }
 
@@ -1566,6 +3117,26 @@ impl d::Device<B> for Device {
         // Nothing to do
     }
 
+    unsafe fn destroy_event(&self, _event: n::Event) {
+        // Nothing to do
+    }
+
+    unsafe fn calibrate_timestamps(&self) -> Option<query::TimestampCalibration> {
+        let gl = &self.share.context;
+        if !self.share.private_caps.timer_query || !gl.GetInteger64v.is_loaded() {
+            return None;
+        }
+
+        let mut gpu = 0;
+        gl_call!(gl.GetInteger64v(gl::TIMESTAMP, &mut gpu));
+        Some(query::TimestampCalibration {
+            cpu: Instant::now(),
+            gpu: gpu as _,
+            // GL timestamps are in nanoseconds.
+            period: 1.0,
+        })
+    }
+
     unsafe fn create_swapchain(
         &self,
         surface: &mut Surface,
@@ -1581,25 +3152,171 @@ impl d::Device<B> for Device {
 
     fn wait_idle(&self) -> Result<(), error::HostExecutionError> {
         unsafe {
-            self.share.context.Finish();
+            gl_call!(self.share.context.Finish());
         }
         Ok(())
     }
 }
 
+/// Label the object with its handle name, as shown by the debugging tools.
+pub(crate) fn label_object(
+    share: &Starc<Share>,
+    identifier: GLenum,
+    name: gl::types::GLuint,
+    kind: &str,
+) {
+    if !share.private_caps.debug {
+        return;
+    }
+    set_object_label(share, identifier, name, &format!("{} {}", kind, name));
+}
+
+/// Label the object with a user provided name.
+fn set_object_label(
+    share: &Starc<Share>,
+    identifier: GLenum,
+    name: gl::types::GLuint,
+    label: &str,
+) {
+    if !share.private_caps.debug {
+        return;
+    }
+    unsafe {
+        gl_call!(share.context.ObjectLabel(
+            identifier,
+            name,
+            label.len() as _,
+            label.as_ptr() as *const _,
+        ));
+    }
+}
+
+/// Shader of a pipeline stage, ready to be compiled.
+enum ShaderSource {
+    Raw(n::Shader),
+    Glsl(String),
+    /// SPIR-V words along with the specialization constant IDs and values.
+    Spirv {
+        words: Vec<u32>,
+        constants: Vec<(u32, u32)>,
+    },
+}
+
+/// Replace the `Binding` decorations of a SPIR-V module with the remapped
+/// ones, dropping the `DescriptorSet` decorations as GL has no sets.
+fn patch_spirv_bindings(words: &[u32], bindings: &FastHashMap<u32, u32>) -> Vec<u32> {
+    const HEADER_LENGTH: usize = 5;
+    const OP_DECORATE: u32 = 71;
+    const DECORATION_BINDING: u32 = 33;
+    const DECORATION_DESCRIPTOR_SET: u32 = 34;
+
+    let mut patched = words[..HEADER_LENGTH].to_vec();
+    let mut offset = HEADER_LENGTH;
+    while offset < words.len() {
+        let word_count = (words[offset] >> 16) as usize;
+        assert_ne!(word_count, 0, "Malformed SPIR-V instruction");
+        let mut instruction = words[offset..offset + word_count].to_vec();
+        offset += word_count;
+
+        if instruction[0] & 0xFFFF == OP_DECORATE && word_count == 4 {
+            match instruction[2] {
+                DECORATION_DESCRIPTOR_SET => continue,
+                DECORATION_BINDING => {
+                    if let Some(&binding) = bindings.get(&instruction[1]) {
+                        instruction[3] = binding;
+                    }
+                }
+                _ => (),
+            }
+        }
+        patched.extend(instruction);
+    }
+    patched
+}
+
+const PROGRAM_CACHE_MAGIC: &[u8] = b"GFXGLPC2";
+
+/// 64-bit FNV-1a hasher for the program cache keys, which are persisted
+/// and so can't depend on `DefaultHasher`, whose algorithm may change.
+struct FnvHasher(u64);
+
+impl Default for FnvHasher {
+    fn default() -> Self {
+        FnvHasher(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for FnvHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 ^ byte as u64).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+fn write_u64(out: &mut Vec<u8>, value: u64) {
+    for i in 0..8 {
+        out.push((value >> (i * 8)) as u8);
+    }
+}
+
+fn read_u64(data: &mut &[u8]) -> Option<u64> {
+    if data.len() < 8 {
+        return None;
+    }
+    let value = data[..8]
+        .iter()
+        .enumerate()
+        .fold(0, |value, (i, &byte)| value | (byte as u64) << (i * 8));
+    *data = &data[8..];
+    Some(value)
+}
+
 pub(crate) fn wait_fence(fence: &n::Fence, share: &Starc<Share>, timeout_ns: u64) -> GLenum {
     // TODO:
     // This can be called by multiple objects wanting to ensure they have exclusive
     // access to a resource. How much does this call costs ? The status of the fence
     // could be cached to avoid calling this more than once (in core or in the backend ?).
     let gl = &share.context;
-    unsafe {
-        if share.private_caps.sync {
-            gl.ClientWaitSync(fence.0.get(), gl::SYNC_FLUSH_COMMANDS_BIT, timeout_ns)
-        } else {
-            // We fallback to waiting for *everything* to finish
-            gl.Flush();
-            gl::CONDITION_SATISFIED
-        }
+    if !share.private_caps.sync {
+        // We fallback to waiting for *everything* to finish
+        unsafe { gl_call!(gl.Finish()) };
+        return gl::CONDITION_SATISFIED;
+    }
+
+    let sync = fence.0.get();
+    if sync.is_null() {
+        // Not submitted since the last reset, so it can't be signalled.
+        return gl::TIMEOUT_EXPIRED;
+    }
+
+    // WebGL2 can only poll the status without blocking
+    let timeout_ns = if share.private_caps.sync_wait {
+        timeout_ns
+    } else {
+        0
+    };
+    unsafe { gl_call!(gl.ClientWaitSync(sync, gl::SYNC_FLUSH_COMMANDS_BIT, timeout_ns)) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FnvHasher;
+    use std::hash::Hasher;
+
+    #[test]
+    fn test_fnv_hasher() {
+        let hash = |bytes: &[u8]| {
+            let mut hasher = FnvHasher::default();
+            hasher.write(bytes);
+            hasher.finish()
+        };
+        assert_eq!(hash(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(hash(b"a"), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(hash(b"foobar"), 0x8594_4171_f739_67e8);
     }
 }
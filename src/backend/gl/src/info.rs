@@ -1,6 +1,6 @@
 use hal::{Features, Limits};
 use std::collections::HashSet;
-use std::{ffi, fmt, mem, str};
+use std::{cmp, ffi, fmt, mem, str};
 use {gl, Error, GlContainer};
 
 /// A version number for a specific component of an OpenGL implementation
@@ -131,6 +131,18 @@ fn get_usize(gl: &GlContainer, name: gl::types::GLenum) -> Result<usize, Error>
     }
 }
 
+fn get_f32(gl: &GlContainer, name: gl::types::GLenum) -> Result<f32, Error> {
+    let mut value = 0.0;
+    unsafe { gl.GetFloatv(name, &mut value) };
+
+    let err = Error::from_error_code(unsafe { gl.GetError() });
+    if err != Error::NoError {
+        Err(err)
+    } else {
+        Ok(value)
+    }
+}
+
 unsafe fn c_str_as_static_str(c_str: *const i8) -> &'static str {
     //TODO: avoid transmuting
     mem::transmute(str::from_utf8(ffi::CStr::from_ptr(c_str as *const _).to_bytes()).unwrap())
@@ -143,6 +155,8 @@ pub struct PlatformName {
     pub vendor: &'static str,
     /// The name of the renderer
     pub renderer: &'static str,
+    /// The full version string, including the driver version
+    pub version: &'static str,
 }
 
 impl PlatformName {
@@ -150,6 +164,7 @@ impl PlatformName {
         PlatformName {
             vendor: get_string(gl, gl::VENDOR),
             renderer: get_string(gl, gl::RENDERER),
+            version: get_string(gl, gl::VERSION),
         }
     }
 }
@@ -170,14 +185,62 @@ pub struct PrivateCaps {
     pub buffer_role_change: bool,
     pub buffer_storage: bool,
     pub image_storage: bool,
+    /// Can create views of immutable textures (`glTextureView`).
+    pub texture_view: bool,
     pub clear_buffer: bool,
+    /// Can fill buffers without uploading the data (`glClearBufferSubData`).
+    pub clear_buffer_object: bool,
     pub program_interface: bool,
     pub frag_data_location: bool,
     pub sync: bool,
     /// Can map memory
     pub map: bool,
-    /// Indicates if we only have support via the EXT.
-    pub sampler_anisotropy_ext: bool,
+    /// Emulates the memory mapping with a CPU-side copy, since WebGL2 can't map buffers.
+    pub emulate_map: bool,
+    /// Can block on the sync objects, which WebGL2 doesn't allow.
+    pub sync_wait: bool,
+    /// Cube maps are filtered across the faces once enabled, which ES always does.
+    pub seamless_cube_map: bool,
+    /// Multisampled textures, readable per sample in shaders.
+    pub multisample_texture: bool,
+    /// Immutable storage for multisampled textures.
+    pub multisample_texture_storage: bool,
+    /// Debug message callback, object labels and groups (`KHR_debug`).
+    pub debug: bool,
+    /// Can retrieve and load linked program binaries.
+    pub program_binary: bool,
+    /// Can consume SPIR-V modules directly (`ARB_gl_spirv`).
+    pub spirv: bool,
+    /// Timestamp and elapsed time queries.
+    pub timer_query: bool,
+    /// Can write query results into buffers (`ARB_query_buffer_object`).
+    pub query_buffer: bool,
+    /// Can clear textures without attaching them to a framebuffer.
+    pub clear_texture: bool,
+    /// Can discard the contents of framebuffer attachments.
+    pub invalidate_framebuffer: bool,
+    /// Floating-point formats are color-renderable, which ES needs an extension for.
+    pub color_buffer_float: bool,
+    /// 32-bit floating-point textures can be filtered linearly.
+    pub texture_float_linear: bool,
+    /// Textures can be bound as storage images.
+    pub image_load_store: bool,
+    /// Maximum width, height and depth of 3D textures.
+    pub max_3d_texture_size: usize,
+    /// Maximum number of layers of array textures.
+    pub max_array_layers: usize,
+    /// Maximum sample count of integer formats.
+    pub max_integer_samples: usize,
+    /// Primitive restart can be toggled, WebGL2 always has it enabled.
+    pub primitive_restart: bool,
+    /// Primitive restart uses the maximum value of the index type, instead of a custom index.
+    pub primitive_restart_fixed_index: bool,
+    /// Coverage can be masked per sample (`glSampleMaski`).
+    pub sample_mask: bool,
+    /// Alpha can be forced to one after alpha-to-coverage, which ES lacks.
+    pub alpha_to_one: bool,
+    /// Texture buffers can view a sub-range of their buffer (`glTexBufferRange`).
+    pub texture_buffer_range: bool,
 }
 
 /// OpenGL implementation information
@@ -229,6 +292,124 @@ bitflags! {
         const EXPLICIT_LAYOUTS_IN_SHADER = 0x00004000;
         /// Support instanced input rate on attribute binding.
         const INSTANCED_ATTRIBUTE_BINDING = 0x00008000;
+        /// Support capturing vertex outputs with pausable transform feedback objects.
+        const TRANSFORM_FEEDBACK = 0x00010000;
+        /// Support drawing the vertices captured by transform feedback.
+        const DRAW_TRANSFORM_FEEDBACK = 0x00020000;
+        /// Support bindless texture handles.
+        const BINDLESS_TEXTURE = 0x00040000;
+        /// Support multiple indirect draws in a single call.
+        const MULTI_DRAW_INDIRECT = 0x00080000;
+        /// Support selecting the clip space depth range, see `Device::set_depth_zero_to_one`.
+        const CLIP_CONTROL = 0x00100000;
+    }
+}
+
+bitflags! {
+    /// Known driver issues, which the backend works around by
+    /// avoiding the affected code paths.
+    pub struct Quirks: u32 {
+        /// `GL_FRAMEBUFFER_SRGB` isn't honored consistently,
+        /// so the sRGB encoding of framebuffer writes is never toggled.
+        const BROKEN_FRAMEBUFFER_SRGB = 0x00000001;
+        /// Mapped buffer ranges may return stale data,
+        /// so mapping is emulated with CPU-side copies.
+        const BROKEN_BUFFER_MAP = 0x00000002;
+        /// Immutable buffer storage is unreliable.
+        const BROKEN_BUFFER_STORAGE = 0x00000004;
+        /// Core profiles have no default vertex array (macOS), so one
+        /// has to be bound for every draw, including those without attributes.
+        const NO_DEFAULT_VERTEX_ARRAY = 0x00000008;
+    }
+}
+
+/// Driver issue affecting a range of driver releases.
+struct KnownIssue {
+    /// Lowercase substring of the vendor string, empty to match any vendor.
+    vendor: &'static str,
+    /// Word preceding the driver release in the version string.
+    driver: &'static str,
+    /// Affected driver releases, the end being excluded.
+    releases: (&'static [u32], &'static [u32]),
+    quirks: Quirks,
+    /// Where the issue is described.
+    reference: &'static str,
+}
+
+/// Known issues of desktop drivers, as none are tracked for ES ones yet.
+const KNOWN_ISSUES: &[KnownIssue] = &[
+    KnownIssue {
+        vendor: "",
+        driver: "Mesa",
+        releases: (&[0], &[19, 0]),
+        quirks: Quirks::BROKEN_FRAMEBUFFER_SRGB,
+        reference: "Mesa before 19.0: `GL_FRAMEBUFFER_SRGB` ignored by non-sRGB window surfaces",
+    },
+    KnownIssue {
+        vendor: "intel",
+        driver: "Build",
+        releases: (&[0], &[26, 20, 100, 7000]),
+        quirks: Quirks::BROKEN_BUFFER_MAP,
+        reference:
+            "Intel Windows drivers before 26.20.100.7000: stale reads of persistent mappings",
+    },
+    KnownIssue {
+        vendor: "intel",
+        driver: "Build",
+        releases: (&[0], &[26, 20, 100, 7000]),
+        quirks: Quirks::BROKEN_BUFFER_STORAGE,
+        reference:
+            "Intel Windows drivers before 26.20.100.7000: `glBufferStorage` data lost on resize",
+    },
+];
+
+/// Release numbers following the `driver` word in a version string,
+/// e.g. `[18, 2, 2]` for `Mesa` in "(Core Profile) Mesa 18.2.2".
+fn driver_release(vendor_info: &str, driver: &str) -> Option<Vec<u32>> {
+    let mut words = vendor_info.split_whitespace();
+    words.find(|&word| word == driver)?;
+    let mut release = Vec::new();
+    // Stop at suffixes like "-devel".
+    for part in words.next()?.split(|c| c == '.' || c == '-') {
+        match part.parse() {
+            Ok(number) => release.push(number),
+            Err(_) => break,
+        }
+    }
+    if release.is_empty() {
+        None
+    } else {
+        Some(release)
+    }
+}
+
+impl Quirks {
+    /// Detect the quirks of an implementation from its vendor, renderer and version strings.
+    pub fn detect(platform: &PlatformName, version: &Version) -> Self {
+        let vendor = platform.vendor.to_lowercase();
+        let mut quirks = Quirks::empty();
+
+        if !version.is_embedded {
+            for issue in KNOWN_ISSUES {
+                if !vendor.contains(issue.vendor) {
+                    continue;
+                }
+                let (start, end) = issue.releases;
+                match driver_release(version.vendor_info, issue.driver) {
+                    Some(ref release) if start <= &release[..] && &release[..] < end => {
+                        info!("Driver issue: {}", issue.reference);
+                        quirks |= issue.quirks;
+                    }
+                    _ => (),
+                }
+            }
+        }
+        // Legacy contexts are limited to 2.1, newer ones are always core.
+        if cfg!(target_os = "macos") && version.major >= 3 {
+            quirks |= Quirks::NO_DEFAULT_VERTEX_ARRAY;
+        }
+
+        quirks
     }
 }
 
@@ -242,7 +423,7 @@ pub enum Requirement {
 impl Info {
     fn get(gl: &GlContainer) -> Info {
         let platform_name = PlatformName::get(gl);
-        let version = Version::parse(get_string(gl, gl::VERSION)).unwrap();
+        let version = Version::parse(platform_name.version).unwrap();
         let shading_language =
             Version::parse(get_string(gl, gl::SHADING_LANGUAGE_VERSION)).unwrap();
         let extensions = if version >= Version::new(3, 0, None, "") {
@@ -302,37 +483,63 @@ impl Info {
 
 /// Load the information pertaining to the driver and the corresponding device
 /// capabilities.
-pub(crate) fn query_all(gl: &GlContainer) -> (Info, Features, LegacyFeatures, Limits, PrivateCaps) {
+pub(crate) fn query_all(
+    gl: &GlContainer,
+) -> (Info, Features, LegacyFeatures, Limits, PrivateCaps, Quirks) {
     use self::Requirement::*;
     let info = Info::get(gl);
+    let quirks = Quirks::detect(&info.platform_name, &info.version);
 
+    let max_vertex_attributes = get_usize(gl, gl::MAX_VERTEX_ATTRIBS).unwrap_or(0);
+    let max_texture_units = get_usize(gl, gl::MAX_TEXTURE_IMAGE_UNITS).unwrap_or(0);
+    // All the sample counts up to the maximum are supported.
+    let samples_count = (get_usize(gl, gl::MAX_SAMPLES).unwrap_or(1).max(1) << 1) - 1;
     let mut limits = Limits {
         max_texture_size: get_usize(gl, gl::MAX_TEXTURE_SIZE).unwrap_or(64),
         max_texel_elements: get_usize(gl, gl::MAX_TEXTURE_BUFFER_SIZE).unwrap_or(0),
         max_viewports: 1,
+        max_vertex_input_attributes: max_vertex_attributes,
+        // Each attribute is bound to its own buffer.
+        max_vertex_input_bindings: max_vertex_attributes,
+        max_vertex_output_components: get_usize(gl, gl::MAX_VERTEX_OUTPUT_COMPONENTS).unwrap_or(0),
         min_buffer_copy_offset_alignment: 1,
         min_buffer_copy_pitch_alignment: 1,
-        min_texel_buffer_offset_alignment: 1,   // TODO
-        min_uniform_buffer_offset_alignment: 1, // TODO
+        min_texel_buffer_offset_alignment: get_usize(gl, gl::TEXTURE_BUFFER_OFFSET_ALIGNMENT)
+            .unwrap_or(1) as _,
+        min_uniform_buffer_offset_alignment: get_usize(gl, gl::UNIFORM_BUFFER_OFFSET_ALIGNMENT)
+            .unwrap_or(1) as _,
         min_storage_buffer_offset_alignment: 1, // TODO
+        framebuffer_color_samples_count: samples_count as _,
+        framebuffer_depth_samples_count: samples_count as _,
+        framebuffer_stencil_samples_count: samples_count as _,
+        max_color_attachments: get_usize(gl, gl::MAX_COLOR_ATTACHMENTS).unwrap_or(1),
+        max_framebuffer_layers: get_usize(gl, gl::MAX_FRAMEBUFFER_LAYERS).unwrap_or(1),
+        // Descriptor sets are flattened into the per-stage bindings.
+        max_bound_descriptor_sets: !0,
+        // Fragment shaders have the lowest limits on the desktop.
+        max_per_stage_descriptor_samplers: max_texture_units,
+        max_per_stage_descriptor_uniform_buffers: get_usize(gl, gl::MAX_FRAGMENT_UNIFORM_BLOCKS)
+            .unwrap_or(0),
+        max_per_stage_descriptor_sampled_images: max_texture_units,
         ..Limits::default()
     };
 
-    if info.is_supported(&[Core(4, 0), Ext("GL_ARB_tessellation_shader")]) {
-        limits.max_patch_size = get_usize(gl, gl::MAX_PATCH_VERTICES).unwrap_or(0) as _;
+    if info.is_supported(&[
+        Core(4, 0),
+        Es(3, 2),
+        Ext("GL_ARB_tessellation_shader"),
+        Ext("GL_EXT_tessellation_shader"),
+    ]) && gl.PatchParameteri.is_loaded()
+    {
+        limits.max_patch_size =
+            cmp::min(get_usize(gl, gl::MAX_PATCH_VERTICES).unwrap_or(0), 0xFF) as _;
     }
     if info.is_supported(&[Core(4, 1)]) {
         // TODO: extension
         limits.max_viewports = get_usize(gl, gl::MAX_VIEWPORTS).unwrap_or(0);
     }
 
-    if false
-        && info.is_supported(&[
-            //TODO: enable when compute is implemented
-            Core(4, 3),
-            Ext("GL_ARB_compute_shader"),
-        ])
-    {
+    if info.is_supported(&[Core(4, 3), Es(3, 1), Ext("GL_ARB_compute_shader")]) {
         let mut values = [0 as gl::types::GLint; 2];
         for (i, (count, size)) in limits
             .max_compute_group_count
@@ -347,6 +554,22 @@ pub(crate) fn query_all(gl: &GlContainer) -> (Info, Features, LegacyFeatures, Li
             *count = values[0] as _;
             *size = values[1] as _;
         }
+        limits.max_compute_shared_memory_size =
+            get_usize(gl, gl::MAX_COMPUTE_SHARED_MEMORY_SIZE).unwrap_or(0);
+    }
+    if info.is_supported(&[
+        Core(4, 3),
+        Es(3, 1),
+        Ext("GL_ARB_shader_storage_buffer_object"),
+    ]) {
+        limits.min_storage_buffer_offset_alignment =
+            get_usize(gl, gl::SHADER_STORAGE_BUFFER_OFFSET_ALIGNMENT).unwrap_or(1) as _;
+        limits.max_per_stage_descriptor_storage_buffers =
+            get_usize(gl, gl::MAX_FRAGMENT_SHADER_STORAGE_BLOCKS).unwrap_or(0);
+    }
+    if info.is_supported(&[Core(4, 2), Es(3, 1), Ext("GL_ARB_shader_image_load_store")]) {
+        limits.max_per_stage_descriptor_storage_images =
+            get_usize(gl, gl::MAX_FRAGMENT_IMAGE_UNIFORMS).unwrap_or(0);
     }
 
     let mut features = Features::empty();
@@ -358,21 +581,88 @@ pub(crate) fn query_all(gl: &GlContainer) -> (Info, Features, LegacyFeatures, Li
         Ext("GL_EXT_texture_filter_anisotropic"),
     ]) {
         features |= Features::SAMPLER_ANISOTROPY;
+        limits.max_sampler_anisotropy =
+            get_f32(gl, gl::MAX_TEXTURE_MAX_ANISOTROPY_EXT).unwrap_or(1.0);
     }
     if info.is_supported(&[Core(4, 2)]) {
         legacy |= LegacyFeatures::EXPLICIT_LAYOUTS_IN_SHADER;
     }
-    if info.is_supported(&[Core(3, 3), Es(3, 0), Ext("GL_ARB_instanced_arrays")]) {
+    // The divisor entry point of `ARB_instanced_arrays` is loaded as a fallback
+    // of the core one, so both paths go through `VertexAttribDivisor`.
+    if info.is_supported(&[Core(3, 3), Es(3, 0), Ext("GL_ARB_instanced_arrays")])
+        && gl.VertexAttribDivisor.is_loaded()
+    {
         features |= Features::INSTANCE_RATE;
+        legacy |= LegacyFeatures::INSTANCED_ATTRIBUTE_BINDING;
     }
-    if info.is_supported(&[Core(3, 3)]) {
-        // TODO: extension
+    if !info.version.is_embedded {
+        // ES has no sampler LOD bias, only the one of the shader lookups.
         features |= Features::SAMPLER_MIP_LOD_BIAS;
+        // ES has no logic operations at all.
+        features |= Features::LOGIC_OP;
+    }
+    if info.is_supported(&[
+        Core(3, 2),
+        Ext("GL_ARB_depth_clamp"),
+        Ext("GL_EXT_depth_clamp"),
+    ]) {
+        features |= Features::DEPTH_CLAMP;
+    }
+    if info.is_supported(&[Core(4, 0), Ext("GL_ARB_draw_buffers_blend")])
+        && gl.BlendFuncSeparateiARB.is_loaded()
+    {
+        features |= Features::INDEPENDENT_BLENDING;
+    }
+    if info.is_supported(&[Core(4, 0), Es(3, 2), Ext("GL_ARB_sample_shading")])
+        && gl.MinSampleShading.is_loaded()
+    {
+        features |= Features::SAMPLE_RATE_SHADING;
+    }
+    if limits.max_patch_size != 0 {
+        features |= Features::TESSELLATION_SHADER;
+    }
+    if limits.max_viewports > 1 {
+        features |= Features::MULTI_VIEWPORTS;
+    }
+    if !info.version.is_embedded {
+        // ES only counts whether any sample passed.
+        features |= Features::PRECISE_OCCLUSION_QUERY;
+    }
+    if !info.version.is_embedded && gl.GetBufferSubData.is_loaded() {
+        // Emulated by reading the predicate back when the commands are submitted.
+        features |= Features::CONDITIONAL_RENDERING;
+    }
+    if info.is_supported(&[Core(4, 6), Ext("GL_ARB_pipeline_statistics_query")]) {
+        features |= Features::PIPELINE_STATISTICS_QUERY;
+    }
+    if info.is_supported(&[Core(4, 3), Es(3, 0), Ext("GL_ARB_ES3_compatibility")]) {
+        features |= Features::FORMAT_ETC2;
+    }
+    if info.is_supported(&[Es(3, 2), Ext("GL_KHR_texture_compression_astc_ldr")]) {
+        features |= Features::FORMAT_ASTC_LDR;
+    }
+    // BC1-3 are S3TC, BC4-5 are RGTC and BC6H-7 are BPTC.
+    if info.is_supported(&[Ext("GL_EXT_texture_compression_s3tc")])
+        && info.is_supported(&[Core(3, 0), Ext("GL_ARB_texture_compression_rgtc")])
+        && info.is_supported(&[Core(4, 2), Ext("GL_ARB_texture_compression_bptc")])
+    {
+        features |= Features::FORMAT_BC;
     }
 
-    if info.is_supported(&[Core(4, 3), Es(3, 1)]) {
-        // TODO: extension
+    if info.is_supported(&[Core(4, 0), Es(3, 1), Ext("GL_ARB_draw_indirect")]) {
         legacy |= LegacyFeatures::INDIRECT_EXECUTION;
+        limits.max_draw_indirect_count = !0;
+        // Multiple draws are emulated with a loop if not supported natively.
+        features |= Features::MULTI_DRAW_INDIRECT;
+        if info.is_supported(&[Core(4, 2), Ext("GL_ARB_base_instance")]) {
+            features |= Features::DRAW_INDIRECT_FIRST_INSTANCE;
+        }
+    }
+    if info.is_supported(&[Core(4, 3), Ext("GL_ARB_multi_draw_indirect")]) {
+        legacy |= LegacyFeatures::MULTI_DRAW_INDIRECT;
+    }
+    if info.is_supported(&[Core(4, 5), Ext("GL_ARB_clip_control")]) && gl.ClipControl.is_loaded() {
+        legacy |= LegacyFeatures::CLIP_CONTROL;
     }
     if info.is_supported(&[Core(3, 1), Es(3, 0), Ext("GL_ARB_draw_instanced")]) {
         legacy |= LegacyFeatures::DRAW_INSTANCED;
@@ -428,35 +718,156 @@ pub(crate) fn query_all(gl: &GlContainer) -> (Info, Features, LegacyFeatures, Li
         // TODO: extension
         legacy |= LegacyFeatures::SAMPLER_BORDER_COLOR;
     }
-    if info.is_supported(&[Core(3, 3), Es(3, 0)]) {
-        legacy |= LegacyFeatures::INSTANCED_ATTRIBUTE_BINDING;
+    if info.is_supported(&[Core(4, 0), Es(3, 0), Ext("GL_ARB_transform_feedback2")]) {
+        legacy |= LegacyFeatures::TRANSFORM_FEEDBACK;
+    }
+    if info.is_supported(&[Core(4, 0), Ext("GL_ARB_transform_feedback2")]) {
+        legacy |= LegacyFeatures::DRAW_TRANSFORM_FEEDBACK;
+    }
+    if info.is_supported(&[Ext("GL_ARB_bindless_texture")]) && gl.GetTextureHandleARB.is_loaded() {
+        legacy |= LegacyFeatures::BINDLESS_TEXTURE;
     }
 
-    let private = PrivateCaps {
+    let mut private = PrivateCaps {
         vertex_array: info.is_supported(&[Core(3, 0), Es(3, 0), Ext("GL_ARB_vertex_array_object")])
             && gl.GenVertexArrays.is_loaded(),
         framebuffer: info.is_supported(&[Core(3, 0), Es(2, 0), Ext("GL_ARB_framebuffer_object")])
             && gl.GenFramebuffers.is_loaded(),
-        framebuffer_texture: info.is_supported(&[Core(3, 0)]), //TODO: double check
+        framebuffer_texture: info.is_supported(&[
+            Core(3, 2),
+            Es(3, 2),
+            Ext("GL_EXT_geometry_shader"),
+        ]) && gl.FramebufferTexture.is_loaded(),
         buffer_role_change: !info.version.is_embedded,
-        image_storage: info.is_supported(&[Core(4, 2), Ext("GL_ARB_texture_storage")]),
+        image_storage: info.is_supported(&[Core(4, 2), Es(3, 0), Ext("GL_ARB_texture_storage")]),
+        texture_view: info.is_supported(&[Core(4, 3), Ext("GL_ARB_texture_view")])
+            && gl.TextureView.is_loaded(),
         buffer_storage: info.is_supported(&[Core(4, 4), Ext("GL_ARB_buffer_storage")]),
         clear_buffer: info.is_supported(&[Core(3, 0), Es(3, 0)]),
+        clear_buffer_object: info.is_supported(&[Core(4, 3), Ext("GL_ARB_clear_buffer_object")]),
         program_interface: info.is_supported(&[Core(4, 3), Ext("GL_ARB_program_interface_query")]),
         frag_data_location: !info.version.is_embedded,
         sync: info.is_supported(&[Core(3, 2), Es(3, 0), Ext("GL_ARB_sync")]),
         map: !info.version.is_embedded, //TODO: OES extension
-        sampler_anisotropy_ext: !info
-            .is_supported(&[Core(4, 6), Ext("GL_ARB_texture_filter_anisotropic")])
-            && info.is_supported(&[Ext("GL_EXT_texture_filter_anisotropic")]),
+        emulate_map: cfg!(target_os = "emscripten"),
+        sync_wait: !cfg!(target_os = "emscripten"),
+        seamless_cube_map: info.is_supported(&[Core(3, 2), Ext("GL_ARB_seamless_cube_map")]),
+        multisample_texture: info.is_supported(&[
+            Core(3, 2),
+            Es(3, 1),
+            Ext("GL_ARB_texture_multisample"),
+        ]),
+        multisample_texture_storage: info.is_supported(&[
+            Core(4, 3),
+            Es(3, 1),
+            Ext("GL_ARB_texture_storage_multisample"),
+        ]),
+        debug: info.is_supported(&[Core(4, 3), Es(3, 2), Ext("GL_KHR_debug")])
+            && gl.DebugMessageCallback.is_loaded(),
+        program_binary: info.is_supported(&[
+            Core(4, 1),
+            Es(3, 0),
+            Ext("GL_ARB_get_program_binary"),
+        ]) && get_usize(gl, gl::NUM_PROGRAM_BINARY_FORMATS).unwrap_or(0) > 0,
+        spirv: info.is_supported(&[Core(4, 6), Ext("GL_ARB_gl_spirv")])
+            && gl.SpecializeShader.is_loaded(),
+        timer_query: info.is_supported(&[
+            Core(3, 3),
+            Ext("GL_ARB_timer_query"),
+            Ext("GL_EXT_disjoint_timer_query"),
+        ]) && gl.QueryCounter.is_loaded(),
+        query_buffer: info.is_supported(&[Core(4, 4), Ext("GL_ARB_query_buffer_object")]),
+        clear_texture: info.is_supported(&[Core(4, 4), Ext("GL_ARB_clear_texture")]),
+        invalidate_framebuffer: info.is_supported(&[
+            Core(4, 3),
+            Es(3, 0),
+            Ext("GL_ARB_invalidate_subdata"),
+        ]),
+        color_buffer_float: info.is_supported(&[
+            Core(3, 0),
+            Es(3, 2),
+            Ext("GL_EXT_color_buffer_float"),
+        ]),
+        texture_float_linear: !info.version.is_embedded
+            || info.is_supported(&[Ext("GL_OES_texture_float_linear")]),
+        image_load_store: info.is_supported(&[
+            Core(4, 2),
+            Es(3, 1),
+            Ext("GL_ARB_shader_image_load_store"),
+        ]),
+        max_3d_texture_size: get_usize(gl, gl::MAX_3D_TEXTURE_SIZE).unwrap_or(0),
+        max_array_layers: get_usize(gl, gl::MAX_ARRAY_TEXTURE_LAYERS).unwrap_or(1),
+        max_integer_samples: get_usize(gl, gl::MAX_INTEGER_SAMPLES).unwrap_or(1),
+        primitive_restart: info.is_supported(&[Core(3, 1), Es(3, 0)])
+            && !cfg!(target_os = "emscripten"),
+        primitive_restart_fixed_index: info.is_supported(&[
+            Core(4, 3),
+            Es(3, 0),
+            Ext("GL_ARB_ES3_compatibility"),
+        ]),
+        sample_mask: info.is_supported(&[Core(3, 2), Es(3, 1), Ext("GL_ARB_texture_multisample")])
+            && gl.SampleMaski.is_loaded(),
+        alpha_to_one: !info.version.is_embedded,
+        texture_buffer_range: info.is_supported(&[
+            Core(4, 3),
+            Es(3, 2),
+            Ext("GL_ARB_texture_buffer_range"),
+        ]) && gl.TexBufferRange.is_loaded(),
     };
 
-    (info, features, legacy, limits, private)
+    if quirks.contains(Quirks::BROKEN_FRAMEBUFFER_SRGB) {
+        legacy.remove(LegacyFeatures::SRGB_COLOR);
+    }
+    if quirks.contains(Quirks::BROKEN_BUFFER_MAP) {
+        private.emulate_map = true;
+    }
+    if quirks.contains(Quirks::BROKEN_BUFFER_STORAGE) {
+        private.buffer_storage = false;
+    }
+    if quirks.contains(Quirks::NO_DEFAULT_VERTEX_ARRAY) {
+        // The extension query can miss it, but the vertex arrays are there.
+        private.vertex_array = gl.GenVertexArrays.is_loaded();
+    }
+
+    (info, features, legacy, limits, private, quirks)
 }
 
 #[cfg(test)]
 mod tests {
-    use super::Version;
+    use super::{PlatformName, Quirks, Version};
+
+    #[test]
+    fn test_quirks_detect() {
+        let mesa = PlatformName {
+            vendor: "X.Org",
+            renderer: "AMD Radeon (TM) RX 460 Graphics",
+            version: "4.5 (Core Profile) Mesa 18.2.2",
+        };
+        let version = Version::new(4, 5, None, "(Core Profile) Mesa 18.2.2");
+        assert!(Quirks::detect(&mesa, &version).contains(Quirks::BROKEN_FRAMEBUFFER_SRGB));
+        let version = Version::new(4, 5, None, "(Core Profile) Mesa 19.1.0-devel");
+        assert!(!Quirks::detect(&mesa, &version).contains(Quirks::BROKEN_FRAMEBUFFER_SRGB));
+        let version = Version::new_embedded(3, 2, "Mesa 18.2.2");
+        assert!(!Quirks::detect(&mesa, &version).contains(Quirks::BROKEN_FRAMEBUFFER_SRGB));
+
+        let intel = PlatformName {
+            vendor: "Intel",
+            renderer: "Intel(R) UHD Graphics 620",
+            version: "4.6.0 - Build 25.20.100.6472",
+        };
+        let version = Version::new(4, 6, Some(0), "- Build 25.20.100.6472");
+        assert!(Quirks::detect(&intel, &version).contains(Quirks::BROKEN_BUFFER_MAP));
+        let version = Version::new(4, 6, Some(0), "- Build 26.20.100.7262");
+        assert!(!Quirks::detect(&intel, &version).contains(Quirks::BROKEN_BUFFER_MAP));
+
+        let nvidia = PlatformName {
+            vendor: "NVIDIA Corporation",
+            renderer: "GeForce GTX 1060/PCIe/SSE2",
+            version: "4.6.0 NVIDIA 410.73",
+        };
+        let version = Version::new(4, 6, None, "NVIDIA 410.73");
+        assert_eq!(Quirks::detect(&nvidia, &version), Quirks::empty());
+    }
 
     #[test]
     fn test_version_parse() {
@@ -498,5 +909,9 @@ mod tests {
             Version::parse("GLSL ES 1.1"),
             Ok(Version::new_embedded(1, 1, ""))
         );
+        assert_eq!(
+            Version::parse("OpenGL ES 3.0 (WebGL 2.0 (OpenGL ES 3.0 Chromium))"),
+            Ok(Version::new_embedded(3, 0, "Chromium))"))
+        );
     }
 }
@@ -14,17 +14,59 @@ pub extern crate glutin;
 extern crate smallvec;
 extern crate spirv_cross;
 
-use std::cell::Cell;
+/// Call a GL function of a `GlContainer`, e.g. `gl_call!(gl.BindBuffer(target, 0))`.
+///
+/// With the `strict-errors` feature, the error flag is checked after the call,
+/// logging the call along with its arguments if it failed.
+#[cfg(not(feature = "strict-errors"))]
+macro_rules! gl_call {
+    ($($call:tt)*) => {
+        $($call)*
+    };
+}
+
+#[cfg(feature = "strict-errors")]
+macro_rules! gl_call {
+    ($($path:ident).+ ($($arg:expr),* $(,)*)) => {
+        gl_call!(@split [] $($path)+ ; [] $($arg),*)
+    };
+    // Separate the container from the function name.
+    (@split [$($gl:ident)*] $name:ident ; $($rest:tt)*) => {
+        gl_call!(@bind [$($gl)*] $name $($rest)*)
+    };
+    (@split [$($gl:ident)*] $head:ident $($path:ident)+ ; $($rest:tt)*) => {
+        gl_call!(@split [$($gl)* $head] $($path)+ ; $($rest)*)
+    };
+    // Evaluate the arguments once, so they can be logged after the call.
+    (@bind [$($gl:ident)*] $name:ident [$($bound:ident)*] $arg:expr $(, $rest:expr)*) => {{
+        let arg = $arg;
+        gl_call!(@bind [$($gl)*] $name [$($bound)* arg] $($rest),*)
+    }};
+    (@bind [$($gl:ident)*] $name:ident [$($bound:ident)*]) => {{
+        let result = $($gl).*.$name($($bound),*);
+        $($gl).*.check_call(stringify!($name), &[$(&$bound as &::std::fmt::Debug),*]);
+        result
+    }};
+}
+
+use std::cell::{Cell, RefCell};
+use std::ffi::CStr;
 use std::fmt;
 use std::ops::Deref;
+use std::os::raw::c_void;
+use std::ptr;
 use std::sync::{Arc, Weak};
 use std::thread::{self, ThreadId};
 
+use hal::backend::FastHashMap;
 use hal::queue::{QueueFamilyId, Queues};
 use hal::{error, image, pso};
 
 pub use self::device::Device;
-pub use self::info::{Info, PlatformName, Version};
+pub use self::info::{Info, LegacyFeatures, PlatformName, Quirks, Version};
+pub use self::native::{
+    ImageKind, PixelReadback, TextureHandle, TransformFeedback, TransformFeedbackDesc,
+};
 
 mod command;
 mod conv;
@@ -33,20 +75,77 @@ mod info;
 mod native;
 mod pool;
 mod queue;
+mod staging;
 mod state;
+#[cfg(feature = "glutin")]
+mod upload;
 mod window;
 
+#[cfg(feature = "glutin")]
+pub use upload::{UploadTicket, UploadWorker};
 #[cfg(feature = "glutin")]
 pub use window::glutin::{config_context, Headless, Surface, Swapchain};
+#[cfg(all(target_os = "emscripten", not(feature = "glutin")))]
+pub use window::web::{Surface, Swapchain};
 
 pub(crate) struct GlContainer {
     context: gl::Gl,
+    /// Error raised by a checked call, which hasn't been queried yet.
+    #[cfg(feature = "strict-errors")]
+    error: Cell<gl::types::GLenum>,
 }
 
 impl GlContainer {
+    fn new(context: gl::Gl) -> Self {
+        GlContainer {
+            context,
+            #[cfg(feature = "strict-errors")]
+            error: Cell::new(gl::NO_ERROR),
+        }
+    }
+
     fn make_current(&self) {
         // Unimplemented
     }
+
+    /// Check the error flag after a call made through `gl_call!`.
+    #[cfg(feature = "strict-errors")]
+    fn check_call(&self, name: &str, args: &[&fmt::Debug]) {
+        let mut call = format!("gl{}(", name);
+        for (i, arg) in args.iter().enumerate() {
+            if i != 0 {
+                call.push_str(", ");
+            }
+            call.push_str(&format!("{:?}", arg));
+        }
+        call.push(')');
+        trace!("{}", call);
+
+        let error_code = unsafe { self.context.GetError() };
+        if error_code != gl::NO_ERROR {
+            error!(
+                "Error {:?} from {}",
+                Error::from_error_code(error_code),
+                call
+            );
+            // Keep it for the next `get_error`, as if it was still flagged.
+            if self.error.get() == gl::NO_ERROR {
+                self.error.set(error_code);
+            }
+        }
+    }
+
+    /// Query the error flag, including the errors raised by checked calls.
+    fn get_error(&self) -> gl::types::GLenum {
+        #[cfg(feature = "strict-errors")]
+        {
+            let error_code = self.error.replace(gl::NO_ERROR);
+            if error_code != gl::NO_ERROR {
+                return error_code;
+            }
+        }
+        unsafe { self.context.GetError() }
+    }
 }
 
 impl Deref for GlContainer {
@@ -76,7 +175,7 @@ impl hal::Backend for Backend {
 
     type ShaderModule = native::ShaderModule;
     type RenderPass = native::RenderPass;
-    type Framebuffer = native::FrameBuffer;
+    type Framebuffer = native::FatFrameBuffer;
 
     type Buffer = native::Buffer;
     type BufferView = native::BufferView;
@@ -94,7 +193,8 @@ impl hal::Backend for Backend {
 
     type Fence = native::Fence;
     type Semaphore = native::Semaphore;
-    type QueryPool = ();
+    type Event = native::Event;
+    type QueryPool = native::QueryPool;
 }
 
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
@@ -122,30 +222,93 @@ impl Error {
     }
 }
 
+/// Routes the messages of the `KHR_debug` callback into the log.
+extern "system" fn debug_message_callback(
+    source: gl::types::GLenum,
+    gltype: gl::types::GLenum,
+    id: gl::types::GLuint,
+    severity: gl::types::GLenum,
+    _length: gl::types::GLsizei,
+    message: *const gl::types::GLchar,
+    _user_param: *mut c_void,
+) {
+    let level = match severity {
+        gl::DEBUG_SEVERITY_HIGH => log::Level::Error,
+        gl::DEBUG_SEVERITY_MEDIUM => log::Level::Warn,
+        gl::DEBUG_SEVERITY_LOW => log::Level::Info,
+        gl::DEBUG_SEVERITY_NOTIFICATION => log::Level::Trace,
+        _ => log::Level::Debug,
+    };
+    let source = match source {
+        gl::DEBUG_SOURCE_API => "API",
+        gl::DEBUG_SOURCE_WINDOW_SYSTEM => "Window System",
+        gl::DEBUG_SOURCE_SHADER_COMPILER => "Shader Compiler",
+        gl::DEBUG_SOURCE_THIRD_PARTY => "Third Party",
+        gl::DEBUG_SOURCE_APPLICATION => "Application",
+        _ => "Other",
+    };
+    let gltype = match gltype {
+        gl::DEBUG_TYPE_ERROR => "Error",
+        gl::DEBUG_TYPE_DEPRECATED_BEHAVIOR => "Deprecated Behavior",
+        gl::DEBUG_TYPE_UNDEFINED_BEHAVIOR => "Undefined Behavior",
+        gl::DEBUG_TYPE_PORTABILITY => "Portability",
+        gl::DEBUG_TYPE_PERFORMANCE => "Performance",
+        gl::DEBUG_TYPE_MARKER => "Marker",
+        gl::DEBUG_TYPE_PUSH_GROUP => "Push Group",
+        gl::DEBUG_TYPE_POP_GROUP => "Pop Group",
+        _ => "Other",
+    };
+    let message = unsafe { CStr::from_ptr(message) }.to_string_lossy();
+    log!(level, "[{}/{}] ID {} : {}", source, gltype, id, message);
+}
+
 /// Internal struct of shared data between the physical and logical device.
 struct Share {
     context: GlContainer,
     info: Info,
     features: hal::Features,
     legacy_features: info::LegacyFeatures,
+    quirks: info::Quirks,
     limits: hal::Limits,
     private_caps: info::PrivateCaps,
     // Indicates if there is an active logical device.
     open: Cell<bool>,
+    vertex_arrays: RefCell<queue::VertexArrayCache>,
+    // Linked program binaries keyed by the hash of their sources and link state.
+    program_binaries: RefCell<FastHashMap<u64, native::ProgramBinary>>,
+    // Pixel buffers for streaming texture uploads.
+    unpack_ring: RefCell<staging::PixelBufferRing>,
+    // Pixel buffers of finished readbacks, ready for reuse.
+    pack_buffers: RefCell<Vec<native::RawBuffer>>,
 }
 
 impl Share {
-    /// Fails during a debug build if the implementation's error flag was set.
+    /// Fails during a debug build, or with the `strict-errors` feature,
+    /// if the implementation's error flag was set.
     fn check(&self) -> Result<(), Error> {
-        if cfg!(debug_assertions) {
+        if cfg!(debug_assertions) || cfg!(feature = "strict-errors") {
             let gl = &self.context;
-            let err = Error::from_error_code(unsafe { gl.GetError() });
+            let err = Error::from_error_code(gl.get_error());
             if err != Error::NoError {
                 return Err(err);
             }
         }
         Ok(())
     }
+
+    /// Reset the error flags in a debug build, or with the `strict-errors`
+    /// feature, so that `check` only reports the errors of the following calls.
+    fn clear_errors(&self) {
+        if cfg!(debug_assertions) || cfg!(feature = "strict-errors") {
+            let gl = &self.context;
+            // Bounded, since a lost context keeps reporting errors on some drivers.
+            for _ in 0..8 {
+                if gl.get_error() == gl::NO_ERROR {
+                    break;
+                }
+            }
+        }
+    }
 }
 
 /// Single-threaded `Arc`.
@@ -239,18 +402,17 @@ impl PhysicalDevice {
     where
         F: FnMut(&str) -> *const std::os::raw::c_void,
     {
-        let gl = GlContainer {
-            context: gl::Gl::load_with(fn_proc),
-        };
+        let gl = GlContainer::new(gl::Gl::load_with(fn_proc));
 
         // query information
-        let (info, features, legacy_features, limits, private_caps) = info::query_all(&gl);
+        let (info, features, legacy_features, limits, private_caps, quirks) = info::query_all(&gl);
         info!("Vendor: {:?}", info.platform_name.vendor);
         info!("Renderer: {:?}", info.platform_name.renderer);
         info!("Version: {:?}", info.version);
         info!("Shading Language: {:?}", info.shading_language);
         info!("Features: {:?}", features);
         info!("Legacy Features: {:?}", legacy_features);
+        info!("Quirks: {:?}", quirks);
         debug!("Loaded Extensions:");
         for extension in info.extensions.iter() {
             debug!("- {}", *extension);
@@ -265,9 +427,17 @@ impl PhysicalDevice {
             info,
             features,
             legacy_features,
+            quirks,
             limits,
             private_caps,
             open: Cell::new(false),
+            vertex_arrays: RefCell::new(queue::VertexArrayCache::new()),
+            program_binaries: RefCell::new(FastHashMap::default()),
+            unpack_ring: RefCell::new(staging::PixelBufferRing::new(
+                gl::PIXEL_UNPACK_BUFFER,
+                gl::STREAM_DRAW,
+            )),
+            pack_buffers: RefCell::new(Vec::new()),
         };
         if let Err(err) = share.check() {
             panic!("Error querying info: {:?}", err);
@@ -344,6 +514,11 @@ impl PhysicalDevice {
     pub fn legacy_features(&self) -> &info::LegacyFeatures {
         &self.0.legacy_features
     }
+
+    /// Get the driver issues worked around on this device.
+    pub fn quirks(&self) -> &info::Quirks {
+        &self.0.quirks
+    }
 }
 
 impl hal::PhysicalDevice<Backend> for PhysicalDevice {
@@ -366,27 +541,32 @@ impl hal::PhysicalDevice<Backend> for PhysicalDevice {
 
         // initialize permanent states
         let gl = &self.0.context;
-        if self
-            .0
-            .legacy_features
-            .contains(info::LegacyFeatures::SRGB_COLOR)
-        {
-            // TODO: Find way to emulate this on older Opengl versions.
+        // The sRGB encoding of the framebuffer is toggled per subpass,
+        // depending on the formats of its color attachments.
+
+        gl_call!(gl.PixelStorei(gl::UNPACK_ALIGNMENT, 1));
 
-            gl.Enable(gl::FRAMEBUFFER_SRGB);
+        if self.0.private_caps.debug {
+            gl_call!(gl.Enable(gl::DEBUG_OUTPUT));
+            if cfg!(debug_assertions) {
+                gl_call!(gl.Enable(gl::DEBUG_OUTPUT_SYNCHRONOUS));
+            }
+            gl.DebugMessageCallback(Some(debug_message_callback), ptr::null());
         }
 
-        gl.PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+        if self.0.private_caps.seamless_cube_map {
+            gl_call!(gl.Enable(gl::TEXTURE_CUBE_MAP_SEAMLESS));
+        }
 
         if !self.0.info.version.is_embedded {
-            gl.Enable(gl::PROGRAM_POINT_SIZE);
+            gl_call!(gl.Enable(gl::PROGRAM_POINT_SIZE));
         }
 
         // create main VAO and bind it
         let mut vao = 0;
         if self.0.private_caps.vertex_array {
-            gl.GenVertexArrays(1, &mut vao);
-            gl.BindVertexArray(vao);
+            gl_call!(gl.GenVertexArrays(1, &mut vao));
+            gl_call!(gl.BindVertexArray(vao));
         }
 
         if let Err(err) = self.0.check() {
@@ -410,19 +590,193 @@ impl hal::PhysicalDevice<Backend> for PhysicalDevice {
         })
     }
 
-    fn format_properties(&self, _: Option<hal::format::Format>) -> hal::format::Properties {
-        unimplemented!()
+    fn format_properties(&self, format: Option<hal::format::Format>) -> hal::format::Properties {
+        use hal::format::{
+            Aspects, BufferFeature as Bf, ChannelType, ImageFeature as If, SurfaceType as St,
+        };
+
+        let format = match format {
+            Some(format) => format,
+            None => return hal::format::Properties::default(),
+        };
+        let caps = &self.0.private_caps;
+        let desc = format.surface_desc();
+        let (surface, channel) = format.base_format();
+
+        let mut optimal_tiling = If::empty();
+        if desc.is_compressed() {
+            if conv::compressed_format_to_gl(format).is_some()
+                && self
+                    .0
+                    .features
+                    .contains(conv::compressed_format_feature(format))
+            {
+                optimal_tiling = If::SAMPLED | If::SAMPLED_LINEAR;
+            }
+        } else if conv::image_format_to_gl(format).is_some() {
+            optimal_tiling = If::SAMPLED | If::BLIT_SRC | If::BLIT_DST;
+            let float = channel == ChannelType::Float || channel == ChannelType::Ufloat;
+            let integer = channel == ChannelType::Uint || channel == ChannelType::Int;
+            if desc.aspects.contains(Aspects::COLOR) {
+                // Signed normalized and shared exponent formats can't be rendered to.
+                let renderable = channel != ChannelType::Inorm
+                    && surface != St::E5_B9_G9_R9
+                    && (!float || caps.color_buffer_float);
+                if renderable {
+                    optimal_tiling |= If::COLOR_ATTACHMENT;
+                    if !integer {
+                        optimal_tiling |= If::COLOR_ATTACHMENT_BLEND;
+                    }
+                }
+                let float32 = match surface {
+                    St::R32 | St::R32_G32 | St::R32_G32_B32_A32 => float,
+                    _ => false,
+                };
+                if !integer && (!float32 || caps.texture_float_linear) {
+                    optimal_tiling |= If::SAMPLED_LINEAR;
+                }
+                let storage = caps.image_load_store
+                    && channel != ChannelType::Srgb
+                    && surface != St::E5_B9_G9_R9
+                    && !(surface == St::A2_B10_G10_R10 && integer);
+                if storage {
+                    optimal_tiling |= If::STORAGE;
+                    if surface == St::R32 && integer {
+                        optimal_tiling |= If::STORAGE_ATOMIC;
+                    }
+                }
+            } else {
+                optimal_tiling |= If::DEPTH_STENCIL_ATTACHMENT;
+                if channel == ChannelType::Unorm || caps.texture_float_linear {
+                    optimal_tiling |= If::SAMPLED_LINEAR;
+                }
+            }
+        }
+
+        let mut buffer_features = Bf::empty();
+        if conv::format_to_gl_format(format).is_some() {
+            buffer_features |= Bf::VERTEX;
+        }
+        // Texture buffers take unpacked color formats, without normalized signed ones.
+        let texel = self.0.limits.max_texel_elements != 0
+            && !desc.is_compressed()
+            && !desc.packed
+            && desc.aspects == Aspects::COLOR
+            && channel != ChannelType::Srgb
+            && channel != ChannelType::Inorm
+            && conv::image_format_to_gl(format).is_some();
+        if texel {
+            buffer_features |= Bf::UNIFORM_TEXEL;
+            if caps.image_load_store {
+                buffer_features |= Bf::STORAGE_TEXEL;
+                if surface == St::R32 && channel != ChannelType::Float {
+                    buffer_features |= Bf::STORAGE_TEXEL_ATOMIC;
+                }
+            }
+        }
+
+        hal::format::Properties {
+            // Textures have no layout visible to the host.
+            linear_tiling: If::empty(),
+            optimal_tiling,
+            buffer_features,
+        }
     }
 
     fn image_format_properties(
         &self,
-        _format: hal::format::Format,
-        _dimensions: u8,
-        _tiling: image::Tiling,
-        _usage: image::Usage,
-        _view_caps: image::ViewCapabilities,
+        format: hal::format::Format,
+        dimensions: u8,
+        tiling: image::Tiling,
+        usage: image::Usage,
+        view_caps: image::ViewCapabilities,
     ) -> Option<image::FormatProperties> {
-        None //TODO
+        use hal::format::{ChannelType, ImageFeature as If};
+        use hal::image::Usage as U;
+
+        let features = match tiling {
+            image::Tiling::Optimal => self.format_properties(Some(format)).optimal_tiling,
+            image::Tiling::Linear => return None,
+        };
+        let supported_usage = {
+            let mut flags = U::empty();
+            if features.contains(If::BLIT_SRC) {
+                flags |= U::TRANSFER_SRC;
+            }
+            if features.contains(If::BLIT_DST) {
+                flags |= U::TRANSFER_DST;
+            }
+            if features.contains(If::SAMPLED) {
+                flags |= U::SAMPLED;
+            }
+            if features.contains(If::STORAGE) {
+                flags |= U::STORAGE;
+            }
+            if features.contains(If::COLOR_ATTACHMENT) {
+                flags |= U::COLOR_ATTACHMENT;
+            }
+            if features.contains(If::DEPTH_STENCIL_ATTACHMENT) {
+                flags |= U::DEPTH_STENCIL_ATTACHMENT;
+            }
+            flags
+        };
+        if features.is_empty() || !supported_usage.contains(usage) {
+            return None;
+        }
+
+        let caps = &self.0.private_caps;
+        let max_size = self.0.limits.max_texture_size as image::Size;
+        let max_extent = match dimensions {
+            1 => image::Extent {
+                width: max_size,
+                height: 1,
+                depth: 1,
+            },
+            2 => image::Extent {
+                width: max_size,
+                height: max_size,
+                depth: 1,
+            },
+            3 => {
+                let size = caps.max_3d_texture_size as image::Size;
+                image::Extent {
+                    width: size,
+                    height: size,
+                    depth: size,
+                }
+            }
+            _ => return None,
+        };
+        let max_levels = 32 - max_extent.width.max(max_extent.depth).leading_zeros();
+
+        // Only 2D images can be multisampled, and shaders need multisampled textures to read them.
+        let multisample = dimensions == 2
+            && !view_caps.contains(image::ViewCapabilities::KIND_CUBE)
+            && (caps.multisample_texture
+                || !(usage.contains(U::SAMPLED) || usage.contains(U::STORAGE)));
+        let sample_count_mask = if !multisample {
+            0x1
+        } else if format.base_format().1 == ChannelType::Uint
+            || format.base_format().1 == ChannelType::Int
+        {
+            ((caps.max_integer_samples.max(1) << 1) - 1) as _
+        } else if format.is_depth() || format.is_stencil() {
+            self.0.limits.framebuffer_depth_samples_count
+        } else {
+            self.0.limits.framebuffer_color_samples_count
+        };
+
+        Some(image::FormatProperties {
+            max_extent,
+            max_levels: max_levels as _,
+            max_layers: match dimensions {
+                3 => 1,
+                _ => caps.max_array_layers as _,
+            },
+            sample_count_mask,
+            // The texture memory is only bounded by the device.
+            max_resource_size: !0,
+        })
     }
 
     fn memory_properties(&self) -> hal::MemoryProperties {
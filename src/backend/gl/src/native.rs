@@ -1,10 +1,12 @@
 use std::cell::Cell;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex, RwLock};
 
 use hal::backend::FastHashMap;
 use hal::memory::{Properties, Requirements};
-use hal::{format, image as i, pass, pso};
+use hal::{buffer, format, image as i, pass, pso, query};
 
+use conv;
 use gl;
 use Backend;
 
@@ -15,11 +17,47 @@ pub type FrameBuffer = gl::types::GLuint;
 pub type Surface = gl::types::GLuint;
 pub type Texture = gl::types::GLuint;
 pub type Sampler = gl::types::GLuint;
+pub type TransformFeedback = gl::types::GLuint;
 
-pub type DescriptorSetLayout = Vec<pso::DescriptorSetLayoutBinding>;
+#[derive(Clone, Debug)]
+pub struct DescriptorSetLayout {
+    pub(crate) bindings: Vec<pso::DescriptorSetLayoutBinding>,
+    /// Immutable samplers by binding, written into every set allocated with the layout.
+    pub(crate) immutable_samplers: Vec<(pso::DescriptorBinding, FatSampler)>,
+}
 
 pub const DEFAULT_FRAMEBUFFER: FrameBuffer = 0;
 
+/// Framebuffer object, along with the resolve attachments which can't be part of it.
+#[derive(Clone, Debug)]
+pub struct FatFrameBuffer {
+    pub(crate) raw: FrameBuffer,
+    pub(crate) extent: i::Extent,
+    /// Single attachment framebuffers of the resolve attachments, by attachment index.
+    /// Multisampled and single-sampled attachments can't be mixed in a complete framebuffer.
+    pub(crate) resolves: Vec<(pass::AttachmentId, FrameBuffer)>,
+}
+
+impl FatFrameBuffer {
+    /// Wrap a raw framebuffer object, which has no resolve attachments.
+    pub fn from_raw(raw: FrameBuffer) -> Self {
+        FatFrameBuffer {
+            raw,
+            extent: i::Extent {
+                width: 0,
+                height: 0,
+                depth: 0,
+            },
+            resolves: Vec::new(),
+        }
+    }
+
+    /// Raw framebuffer object.
+    pub fn as_raw(&self) -> FrameBuffer {
+        self.raw
+    }
+}
+
 #[derive(Debug)]
 pub struct Buffer {
     pub(crate) raw: RawBuffer,
@@ -27,8 +65,49 @@ pub struct Buffer {
     pub(crate) requirements: Requirements,
 }
 
+impl Buffer {
+    /// Wrap a buffer object created outside of gfx, e.g. by another GL engine.
+    ///
+    /// The buffer needs to live on a context sharing its objects with the one
+    /// of the device and already own its data store of at least `size` bytes,
+    /// so the returned buffer must not be bound to any memory. It can't be mapped.
+    ///
+    /// `Device::destroy_buffer` deletes the object. When the other engine keeps
+    /// the ownership, `mem::forget` the buffer instead.
+    pub unsafe fn from_raw_buffer(raw: RawBuffer, target: gl::types::GLenum, size: u64) -> Self {
+        Buffer {
+            raw,
+            target,
+            requirements: Requirements {
+                size,
+                alignment: 1,
+                type_mask: 0x7,
+            },
+        }
+    }
+
+    /// Name of the underlying buffer object.
+    ///
+    /// The name stays valid until the buffer is destroyed.
+    pub fn as_raw(&self) -> RawBuffer {
+        self.raw
+    }
+}
+
+/// Texture buffer viewing a range of a buffer as texels.
 #[derive(Debug)]
-pub struct BufferView;
+pub struct BufferView {
+    pub(crate) raw: Texture,
+}
+
+impl BufferView {
+    /// Name of the underlying buffer texture.
+    ///
+    /// The name stays valid until the view is destroyed.
+    pub fn as_raw(&self) -> Texture {
+        self.raw
+    }
+}
 
 #[derive(Debug)]
 pub struct Fence(pub(crate) Cell<gl::types::GLsync>);
@@ -41,10 +120,19 @@ impl Fence {
     }
 }
 
+/// Texels being read back into a pixel buffer, see `Device::read_texture`.
+#[derive(Debug)]
+pub struct PixelReadback {
+    pub(crate) buffer: RawBuffer,
+    pub(crate) fence: Fence,
+    pub(crate) size: usize,
+}
+
 #[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
 pub enum BindingTypes {
     Images,
     UniformBuffers,
+    StorageBuffers,
 }
 
 #[derive(Clone, Debug)]
@@ -127,19 +215,75 @@ impl DescRemapData {
     }
 }
 
+/// Multisample coverage state of a graphics pipeline.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SampleCoverage {
+    pub alpha_to_coverage: bool,
+    pub alpha_to_one: bool,
+    pub sample_mask: pso::SampleMask,
+}
+
+impl Default for SampleCoverage {
+    fn default() -> Self {
+        SampleCoverage {
+            alpha_to_coverage: false,
+            alpha_to_one: false,
+            sample_mask: !0,
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct GraphicsPipeline {
     pub(crate) program: Program,
     pub(crate) primitive: gl::types::GLenum,
+    pub(crate) primitive_restart: pso::PrimitiveRestart,
     pub(crate) patch_size: Option<gl::types::GLint>,
+    pub(crate) sample_shading: Option<f32>,
+    pub(crate) sample_coverage: SampleCoverage,
     pub(crate) blend_targets: Vec<pso::ColorBlendDesc>,
+    pub(crate) logic_op: Option<pso::LogicOp>,
+    pub(crate) rasterizer: pso::Rasterizer,
+    pub(crate) depth_stencil: pso::DepthStencilDesc,
+    pub(crate) baked_states: pso::BakedStates,
     pub(crate) attributes: Vec<AttributeDesc>,
     pub(crate) vertex_buffers: Vec<Option<pso::VertexBufferDesc>>,
+    pub(crate) push_constants: Vec<PushConstantUniform>,
+}
+
+/// Uniform of a program emulating a member of its push constant block,
+/// as GL has no concept of push constants.
+#[derive(Clone, Debug)]
+pub struct PushConstantUniform {
+    pub(crate) location: gl::types::GLint,
+    /// Uniform type, as reported by `glGetActiveUniform`.
+    pub(crate) ty: gl::types::GLenum,
+    /// Offset of the member in the push constant block, in bytes.
+    pub(crate) offset: u32,
+    /// Stride between the columns of a matrix member, in bytes.
+    pub(crate) matrix_stride: u32,
+}
+
+/// Outputs of the last vertex processing stage captured with transform feedback.
+#[derive(Clone, Debug)]
+pub struct TransformFeedbackDesc {
+    /// Names of the captured outputs, in the order they are written.
+    pub varyings: Vec<String>,
+    /// Write each output into its own buffer instead of interleaving them all into one.
+    pub separate: bool,
+}
+
+/// Linked program binary, as retrieved from the driver.
+#[derive(Clone, Debug)]
+pub struct ProgramBinary {
+    pub(crate) format: gl::types::GLenum,
+    pub(crate) data: Vec<u8>,
 }
 
 #[derive(Clone, Debug)]
 pub struct ComputePipeline {
     pub(crate) program: Program,
+    pub(crate) push_constants: Vec<PushConstantUniform>,
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -148,6 +292,63 @@ pub struct Image {
     // Required for clearing operations
     pub(crate) channel: format::ChannelType,
     pub(crate) requirements: Requirements,
+    /// Binding target of the texture, e.g. `TEXTURE_2D_ARRAY`.
+    pub(crate) target: gl::types::GLenum,
+    pub(crate) extent: i::Extent,
+    pub(crate) format: format::Format,
+    pub(crate) levels: i::Level,
+    pub(crate) layers: i::Layer,
+    pub(crate) samples: i::NumSamples,
+    /// Allocated with `glTexStorage*`, so views can be created from it.
+    pub(crate) immutable: bool,
+}
+
+impl Image {
+    /// Wrap a texture created outside of gfx, e.g. the output of a video decoder.
+    ///
+    /// `target` is the binding target the texture was created with and `kind`,
+    /// `levels` and `format` have to describe its allocated storage. Views of
+    /// the image are only created with `glTextureView` if the storage is
+    /// `immutable`. The image doesn't need to be bound to memory.
+    ///
+    /// `Device::destroy_image` deletes the texture. When the other engine keeps
+    /// the ownership, `mem::forget` the image instead.
+    pub unsafe fn from_raw_texture(
+        texture: Texture,
+        target: gl::types::GLenum,
+        kind: i::Kind,
+        levels: i::Level,
+        format: format::Format,
+        immutable: bool,
+    ) -> Self {
+        let extent = kind.extent();
+        let layers = kind.num_layers();
+        let size = conv::image_data_size(format, extent) * layers as u64;
+
+        Image {
+            kind: ImageKind::Texture(texture),
+            channel: format.base_format().1,
+            requirements: Requirements {
+                size,
+                alignment: 1,
+                type_mask: 0x7,
+            },
+            target,
+            extent,
+            format,
+            levels,
+            layers,
+            samples: kind.num_samples(),
+            immutable,
+        }
+    }
+
+    /// Name of the underlying texture or renderbuffer.
+    ///
+    /// The name stays valid until the image is destroyed.
+    pub fn as_raw(&self) -> ImageKind {
+        self.kind
+    }
 }
 
 #[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
@@ -164,11 +365,26 @@ pub enum FatSampler {
     Info(i::SamplerInfo),
 }
 
+/// Bindless handle of a texture, see `Device::get_texture_handle`.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct TextureHandle(pub u64);
+
+impl TextureHandle {
+    /// Split the handle into the words of an `uvec2`, which shaders can turn
+    /// back into a sampler, e.g. `sampler2D(handle)`.
+    pub fn to_uvec2(&self) -> [u32; 2] {
+        [self.0 as u32, (self.0 >> 32) as u32]
+    }
+}
+
 #[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
 pub enum ImageView {
     Surface(Surface),
-    Texture(Texture, i::Level),
-    TextureLayer(Texture, i::Level, i::Layer),
+    Texture(Texture, gl::types::GLenum, i::Level),
+    /// Single layer of a texture with the given binding target, e.g. a cube map face.
+    TextureLayer(Texture, gl::types::GLenum, i::Level, i::Layer),
+    /// Texture view created with `glTextureView`, owning its name.
+    TextureView(Texture, gl::types::GLenum),
 }
 
 #[derive(Clone, PartialEq, Eq, Hash, Debug)]
@@ -180,14 +396,14 @@ pub(crate) enum DescSetBindings {
         offset: gl::types::GLintptr,
         size: gl::types::GLsizeiptr,
     },
-    Texture(pso::DescriptorBinding, Texture),
+    Texture(pso::DescriptorBinding, Texture, gl::types::GLenum),
     Sampler(pso::DescriptorBinding, Sampler),
     SamplerInfo(pso::DescriptorBinding, i::SamplerInfo),
 }
 
 #[derive(Clone, Debug)]
 pub struct DescriptorSet {
-    layout: DescriptorSetLayout,
+    pub(crate) layout: DescriptorSetLayout,
     pub(crate) bindings: Arc<Mutex<Vec<DescSetBindings>>>,
 }
 
@@ -199,9 +415,18 @@ impl pso::DescriptorPool<Backend> for DescriptorPool {
         &mut self,
         layout: &DescriptorSetLayout,
     ) -> Result<DescriptorSet, pso::AllocationError> {
+        let bindings = layout
+            .immutable_samplers
+            .iter()
+            .map(|&(binding, ref sampler)| match *sampler {
+                FatSampler::Sampler(sampler) => DescSetBindings::Sampler(binding, sampler),
+                FatSampler::Info(ref info) => DescSetBindings::SamplerInfo(binding, info.clone()),
+            })
+            .collect();
+
         Ok(DescriptorSet {
             layout: layout.clone(),
-            bindings: Arc::new(Mutex::new(Vec::new())),
+            bindings: Arc::new(Mutex::new(bindings)),
         })
     }
 
@@ -229,6 +454,8 @@ pub struct Memory {
     pub(crate) first_bound_buffer: Cell<RawBuffer>,
     /// Allocation size
     pub(crate) size: u64,
+    /// CPU-side copy of the contents, when the mapping is emulated.
+    pub(crate) emulate_map_allocation: Cell<Option<*mut u8>>,
 }
 
 unsafe impl Send for Memory {}
@@ -265,12 +492,24 @@ pub struct RenderPass {
 #[derive(Clone, Debug)]
 pub struct SubpassDesc {
     pub(crate) color_attachments: Vec<usize>,
+    pub(crate) depth_stencil: Option<usize>,
+    /// Resolve destination of each color attachment, empty if not resolving.
+    pub(crate) resolve_attachments: Vec<usize>,
 }
 
 impl SubpassDesc {
     /// Check if an attachment is used by this sub-pass.
     pub(crate) fn is_using(&self, at_id: pass::AttachmentId) -> bool {
-        self.color_attachments.iter().any(|id| *id == at_id)
+        self.color_attachments.iter().any(|id| *id == at_id) || self.depth_stencil == Some(at_id)
+    }
+}
+
+impl RenderPass {
+    /// Check if an attachment is the resolve destination of any sub-pass.
+    pub(crate) fn is_resolve(&self, at_id: pass::AttachmentId) -> bool {
+        self.subpasses
+            .iter()
+            .any(|sp| sp.resolve_attachments.contains(&at_id))
     }
 }
 
@@ -279,11 +518,30 @@ pub struct PipelineLayout {
     pub(crate) desc_remap_data: Arc<RwLock<DescRemapData>>,
 }
 
-#[derive(Debug)]
-// No inter-queue synchronization required for GL.
-pub struct Semaphore;
+/// Binary semaphore, only tracking its state to validate the usage.
+#[derive(Debug, Default)]
+pub struct Semaphore {
+    signaled: AtomicBool,
+}
+
+impl Semaphore {
+    pub(crate) fn signal(&self) {
+        let signaled = self.signaled.swap(true, Ordering::Relaxed);
+        debug_assert!(!signaled, "Semaphore is already signaled");
+    }
 
+    pub(crate) fn wait(&self) {
+        let signaled = self.signaled.swap(false, Ordering::Relaxed);
+        debug_assert!(signaled, "Semaphore is waited on without a signal");
+    }
+}
+
+/// Host-visible event, flipped by the queue as it processes the submitted
+/// `set_event` and `reset_event` commands in order.
 #[derive(Clone, Debug)]
+pub struct Event(pub(crate) Arc<AtomicBool>);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub struct AttributeDesc {
     pub(crate) location: gl::types::GLuint,
     pub(crate) offset: u32,
@@ -293,9 +551,37 @@ pub struct AttributeDesc {
     pub(crate) vertex_attrib_fn: VertexAttribFunction,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum VertexAttribFunction {
     Float,   // glVertexAttribPointer
     Integer, // glVertexAttribIPointer
     Double,  // glVertexAttribLPointer
 }
+
+/// Vertex attribute together with the vertex buffer it's sourced from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct AttributeBinding {
+    pub(crate) attribute: AttributeDesc,
+    pub(crate) buffer: RawBuffer,
+    pub(crate) offset: buffer::Offset,
+    pub(crate) stride: gl::types::GLsizei,
+    pub(crate) rate: gl::types::GLuint,
+}
+
+pub type Query = gl::types::GLuint;
+
+#[derive(Debug)]
+pub struct QueryPool {
+    /// Targets of the queries delimited by `begin_query` and `end_query`.
+    /// Pipeline statistics use one target per enabled statistic.
+    pub(crate) targets: Vec<gl::types::GLenum>,
+    /// GL queries of each hal query, one per target.
+    pub(crate) queries: Vec<Query>,
+}
+
+impl QueryPool {
+    pub(crate) fn queries(&self, id: query::Id) -> &[Query] {
+        let count = self.targets.len();
+        &self.queries[id as usize * count..(id as usize + 1) * count]
+    }
+}
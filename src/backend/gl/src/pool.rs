@@ -4,6 +4,7 @@ use hal::{self, pool};
 use native as n;
 use Backend;
 
+use std::fmt;
 use std::sync::{Arc, Mutex};
 
 pub struct OwnedBuffer {
@@ -51,8 +52,20 @@ pub enum BufferMemory {
     },
 }
 
+impl fmt::Debug for BufferMemory {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Skip the recorded commands, they are printed on their own.
+        match *self {
+            BufferMemory::Linear(_) => write!(f, "Linear"),
+            BufferMemory::Individual { .. } => write!(f, "Individual"),
+        }
+    }
+}
+
 pub struct RawCommandPool {
     pub(crate) fbo: Option<n::FrameBuffer>,
+    /// Framebuffer bound for reading in the image resolves.
+    pub(crate) read_fbo: Option<n::FrameBuffer>,
     pub(crate) limits: command::Limits,
     pub(crate) memory: Arc<Mutex<BufferMemory>>,
 }
@@ -80,7 +93,7 @@ impl pool::RawCommandPool<Backend> for RawCommandPool {
 
     fn allocate_one(&mut self, _level: hal::command::RawLevel) -> RawCommandBuffer {
         // TODO: Implement secondary buffers
-        RawCommandBuffer::new(self.fbo, self.limits, self.memory.clone())
+        RawCommandBuffer::new(self.fbo, self.read_fbo, self.limits, self.memory.clone())
     }
 
     unsafe fn free<I>(&mut self, buffers: I)
@@ -1,14 +1,19 @@
 use std::borrow::Borrow;
+use std::ops::Range;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
 use std::{mem, ptr, slice};
-use Starc;
+use {GlContainer, Starc};
 
 use hal;
+use hal::backend::FastHashMap;
 use hal::error;
 
 use gl;
 use smallvec::SmallVec;
 
 use info::LegacyFeatures;
+use pool::BufferMemory;
 use {command as com, device, native, state, window};
 use {Backend, Share};
 
@@ -32,6 +37,15 @@ struct State {
     num_viewports: usize,
     // Currently set scissor rects.
     num_scissors: usize,
+    // Whether the framebuffer writes are sRGB encoded.
+    // None denotes that we don't know the current state.
+    framebuffer_srgb: Option<bool>,
+    // Currently bound vertex attributes, required to emulate the base instance.
+    attributes: Vec<native::AttributeBinding>,
+    // Whether draws and dispatches are discarded by conditional rendering.
+    discard_draws: bool,
+    // Shadowed pipeline state and object bindings.
+    shadow: state::StateCache,
 }
 
 impl State {
@@ -43,6 +57,10 @@ impl State {
             index_buffer: None,
             num_viewports: 0,
             num_scissors: 0,
+            framebuffer_srgb: None,
+            attributes: Vec::new(),
+            discard_draws: false,
+            shadow: state::StateCache::default(),
         }
     }
 
@@ -51,12 +69,126 @@ impl State {
     fn flush(&mut self) {
         self.vao = false;
         self.index_buffer = None;
+        self.framebuffer_srgb = None;
+        self.shadow.invalidate();
 
         // TOOD: reset viewports and scissors
         //       do we need to clear everything from 0..MAX_VIEWPORTS?
     }
 }
 
+/// Maximum number of vertex array objects kept around by the `VertexArrayCache`.
+const MAX_CACHED_VERTEX_ARRAYS: usize = 256;
+
+/// Vertex array objects keyed by the complete set of attribute bindings,
+/// so the attributes don't need to be specified again on every draw.
+///
+/// The least recently used vertex array is evicted once the cache is full.
+pub(crate) struct VertexArrayCache {
+    entries: FastHashMap<Vec<native::AttributeBinding>, (ArrayBuffer, u64)>,
+    last_use: u64,
+}
+
+impl VertexArrayCache {
+    pub(crate) fn new() -> Self {
+        VertexArrayCache {
+            entries: FastHashMap::default(),
+            last_use: 0,
+        }
+    }
+
+    /// Returns the vertex array for the bindings and whether it was just created.
+    fn get_or_create(
+        &mut self,
+        gl: &GlContainer,
+        bindings: &[native::AttributeBinding],
+    ) -> (ArrayBuffer, bool) {
+        self.last_use += 1;
+        if let Some(entry) = self.entries.get_mut(bindings) {
+            entry.1 = self.last_use;
+            return (entry.0, false);
+        }
+
+        if self.entries.len() >= MAX_CACHED_VERTEX_ARRAYS {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|&(_, &(_, last_use))| last_use)
+                .map(|(bindings, _)| bindings.clone())
+                .unwrap();
+            let (vao, _) = self.entries.remove(&oldest).unwrap();
+            unsafe { gl_call!(gl.DeleteVertexArrays(1, &vao)) };
+        }
+
+        let mut vao = 0;
+        unsafe { gl_call!(gl.GenVertexArrays(1, &mut vao)) };
+        self.entries.insert(bindings.to_vec(), (vao, self.last_use));
+        (vao, true)
+    }
+
+    /// Delete all vertex arrays sourcing from the buffer, which is about to be deleted.
+    ///
+    /// Buffer names get reused, so stale vertex arrays would point to the old buffer otherwise.
+    pub(crate) fn evict_buffer(&mut self, gl: &GlContainer, buffer: native::RawBuffer) {
+        self.entries.retain(|bindings, &mut (vao, _)| {
+            let keep = bindings.iter().all(|binding| binding.buffer != buffer);
+            if !keep {
+                unsafe { gl_call!(gl.DeleteVertexArrays(1, &vao)) };
+            }
+            keep
+        });
+    }
+}
+
+/// Specify a vertex attribute on the currently bound vertex array.
+fn set_vertex_attribute(share: &Share, binding: &native::AttributeBinding) {
+    use native::VertexAttribFunction::*;
+
+    let &native::AttributeBinding {
+        attribute:
+            native::AttributeDesc {
+                location,
+                size,
+                format,
+                offset,
+                vertex_attrib_fn,
+                ..
+            },
+        buffer,
+        stride,
+        rate,
+        ..
+    } = binding;
+    let offset = (binding.offset + offset as hal::buffer::Offset) as *const gl::types::GLvoid;
+    let gl = &share.context;
+
+    unsafe {
+        gl_call!(gl.BindBuffer(gl::ARRAY_BUFFER, buffer));
+
+        match vertex_attrib_fn {
+            Float => {
+                gl_call!(gl.VertexAttribPointer(location, size, format, gl::FALSE, stride, offset))
+            }
+            Integer => gl_call!(gl.VertexAttribIPointer(location, size, format, stride, offset)),
+            Double => gl_call!(gl.VertexAttribLPointer(location, size, format, stride, offset)),
+        }
+
+        if rate != 0 {
+            if share
+                .legacy_features
+                .contains(LegacyFeatures::INSTANCED_ATTRIBUTE_BINDING)
+            {
+                gl_call!(gl.VertexAttribDivisor(location, rate));
+            } else {
+                error!("Binding attribute with instanced input rate is not supported");
+            }
+        }
+
+        gl_call!(gl.EnableVertexAttribArray(location));
+        gl_call!(gl.BindBuffer(gl::ARRAY_BUFFER, 0));
+    }
+}
+
 pub struct CommandQueue {
     pub(crate) share: Starc<Share>,
     vao: ArrayBuffer,
@@ -157,29 +289,12 @@ impl CommandQueue {
         attachment: gl::types::GLenum,
         view: &native::ImageView,
     ) {
-        let gl = &self.share.context;
-        match view {
-            &native::ImageView::Surface(surface) => unsafe {
-                gl.FramebufferRenderbuffer(point, attachment, gl::RENDERBUFFER, surface);
-            },
-            &native::ImageView::Texture(texture, level) => unsafe {
-                gl.FramebufferTexture(point, attachment, texture, level as gl::types::GLint);
-            },
-            &native::ImageView::TextureLayer(texture, level, layer) => unsafe {
-                gl.FramebufferTextureLayer(
-                    point,
-                    attachment,
-                    texture,
-                    level as gl::types::GLint,
-                    layer as gl::types::GLint,
-                );
-            },
-        }
+        device::Device::bind_target(&self.share.context, point, attachment, view);
     }
 
     fn _unbind_target(&mut self, point: gl::types::GLenum, attachment: gl::types::GLenum) {
         let gl = &self.share.context;
-        unsafe { gl.FramebufferTexture(point, attachment, 0, 0) };
+        unsafe { gl_call!(gl.FramebufferTexture(point, attachment, 0, 0)) };
     }
 
     /// Return a reference to a stored data object.
@@ -196,6 +311,70 @@ impl CommandQueue {
         &data[ptr.offset as usize..(ptr.offset + ptr.size) as usize]
     }
 
+    /// Bind the cached vertex array matching the attribute bindings,
+    /// specifying the attributes only if it's newly created.
+    fn bind_vertex_array(&mut self, bindings: &[native::AttributeBinding]) {
+        let gl = &self.share.context;
+        let (vao, created) = self
+            .share
+            .vertex_arrays
+            .borrow_mut()
+            .get_or_create(gl, bindings);
+
+        unsafe { gl_call!(gl.BindVertexArray(vao)) };
+        self.state.vao = false;
+        if created {
+            for binding in bindings {
+                set_vertex_attribute(&self.share, binding);
+            }
+        }
+
+        // The element array binding is part of the vertex array state.
+        if let Some(buffer) = self.state.index_buffer {
+            unsafe { gl_call!(gl.BindBuffer(gl::ELEMENT_ARRAY_BUFFER, buffer)) };
+        }
+    }
+
+    /// Issue an instanced draw with a non-zero base instance, for contexts
+    /// without `ARB_base_instance`.
+    ///
+    /// The instanced attributes are rebound with their offsets advanced to the
+    /// first instance, so the draw can start at instance 0. If the base isn't a
+    /// multiple of every divisor, the instances are drawn one by one instead.
+    fn emulate_base_instance<F>(&self, instances: &Range<hal::InstanceCount>, draw: F)
+    where
+        F: Fn(&GlContainer, hal::InstanceCount),
+    {
+        let gl = &self.share.context;
+        let exact = self
+            .state
+            .attributes
+            .iter()
+            .all(|binding| binding.rate == 0 || instances.start % binding.rate == 0);
+
+        let mut start = instances.start;
+        while start < instances.end {
+            let count = if exact { instances.end - start } else { 1 };
+            self.offset_instanced_attributes(start);
+            draw(gl, count);
+            start += count;
+        }
+        self.offset_instanced_attributes(0);
+    }
+
+    /// Rebind the instanced attributes to start at the given instance.
+    fn offset_instanced_attributes(&self, instance: hal::InstanceCount) {
+        for binding in self.state.attributes.iter() {
+            if binding.rate == 0 {
+                continue;
+            }
+            let mut binding = *binding;
+            let stride = binding.stride as hal::buffer::Offset;
+            binding.offset += (instance / binding.rate) as hal::buffer::Offset * stride;
+            set_vertex_attribute(&self.share, &binding);
+        }
+    }
+
     // Reset the state to match our _expected_ state before executing
     // a command buffer.
     fn reset_state(&mut self) {
@@ -204,7 +383,9 @@ impl CommandQueue {
         // Bind default VAO
         if !self.state.vao {
             if self.share.private_caps.vertex_array {
-                unsafe { gl.BindVertexArray(self.vao) };
+                unsafe { gl_call!(gl.BindVertexArray(self.vao)) };
+                // The default VAO has its own element array binding.
+                self.state.index_buffer = None;
             }
             self.state.vao = true
         }
@@ -215,22 +396,22 @@ impl CommandQueue {
             .legacy_features
             .contains(LegacyFeatures::INDIRECT_EXECUTION)
         {
-            unsafe { gl.BindBuffer(gl::DRAW_INDIRECT_BUFFER, 0) };
+            unsafe { gl_call!(gl.BindBuffer(gl::DRAW_INDIRECT_BUFFER, 0)) };
         }
 
         // Unbind index buffers
         match self.state.index_buffer {
             Some(0) => (), // Nothing to do
             Some(_) | None => {
-                unsafe { gl.BindBuffer(gl::ELEMENT_ARRAY_BUFFER, 0) };
+                unsafe { gl_call!(gl.BindBuffer(gl::ELEMENT_ARRAY_BUFFER, 0)) };
                 self.state.index_buffer = Some(0);
             }
         }
 
         // Reset viewports
         if self.state.num_viewports == 1 {
-            unsafe { gl.Viewport(0, 0, 0, 0) };
-            unsafe { gl.DepthRange(0.0, 1.0) };
+            unsafe { gl_call!(gl.Viewport(0, 0, 0, 0)) };
+            unsafe { gl_call!(gl.DepthRange(0.0, 1.0)) };
         } else if self.state.num_viewports > 1 {
             // 16 viewports is a common limit set in drivers.
             let viewports: SmallVec<[[f32; 4]; 16]> = (0..self.state.num_viewports)
@@ -238,33 +419,92 @@ impl CommandQueue {
                 .collect();
             let depth_ranges: SmallVec<[[f64; 2]; 16]> =
                 (0..self.state.num_viewports).map(|_| [0.0, 0.0]).collect();
-            unsafe { gl.ViewportArrayv(0, viewports.len() as i32, viewports.as_ptr() as *const _) };
             unsafe {
-                gl.DepthRangeArrayv(
+                gl_call!(gl.ViewportArrayv(
+                    0,
+                    viewports.len() as i32,
+                    viewports.as_ptr() as *const _
+                ))
+            };
+            unsafe {
+                gl_call!(gl.DepthRangeArrayv(
                     0,
                     depth_ranges.len() as i32,
                     depth_ranges.as_ptr() as *const _,
-                )
+                ))
             };
         }
 
         // Reset scissors
         if self.state.num_scissors == 1 {
-            unsafe { gl.Scissor(0, 0, 0, 0) };
+            unsafe { gl_call!(gl.Scissor(0, 0, 0, 0)) };
         } else if self.state.num_scissors > 1 {
             // 16 viewports is a common limit set in drivers.
             let scissors: SmallVec<[[i32; 4]; 16]> =
                 (0..self.state.num_scissors).map(|_| [0, 0, 0, 0]).collect();
-            unsafe { gl.ScissorArrayv(0, scissors.len() as i32, scissors.as_ptr() as *const _) };
+            unsafe {
+                gl_call!(gl.ScissorArrayv(0, scissors.len() as i32, scissors.as_ptr() as *const _))
+            };
+        }
+    }
+
+    /// Execute the commands of a command buffer stored in the given memory,
+    /// along with the ones of the secondary command buffers it executes.
+    fn execute(
+        &mut self,
+        memory: &BufferMemory,
+        memory_ref: &Arc<Mutex<BufferMemory>>,
+        id: u64,
+        buf: com::BufferSlice,
+    ) {
+        let buffer = match *memory {
+            BufferMemory::Linear(ref buffer) => buffer,
+            BufferMemory::Individual { ref storage, .. } => storage.get(&id).unwrap(),
+        };
+
+        assert!(buffer.commands.len() >= (buf.offset + buf.size) as usize);
+        let commands = &buffer.commands[buf.offset as usize..(buf.offset + buf.size) as usize];
+        for com in commands {
+            match *com {
+                com::Command::ExecuteCommands {
+                    memory: ref secondary,
+                    id,
+                    buf,
+                } => {
+                    // Command buffers of the same pool share their memory, which is already locked.
+                    if Arc::ptr_eq(secondary, memory_ref) {
+                        self.execute(memory, memory_ref, id, buf);
+                    } else {
+                        let secondary_memory = secondary
+                            .try_lock()
+                            .expect("Trying to submit a command buffers, while memory is in-use.");
+                        self.execute(&secondary_memory, secondary, id, buf);
+                    }
+                }
+                _ => self.process(com, &buffer.data),
+            }
         }
     }
 
     fn process(&mut self, cmd: &com::Command, data_buf: &[u8]) {
+        if self.state.discard_draws {
+            match *cmd {
+                com::Command::Draw { .. }
+                | com::Command::DrawIndexed { .. }
+                | com::Command::DrawIndirect { .. }
+                | com::Command::DrawIndexedIndirect { .. }
+                | com::Command::DrawTransformFeedback { .. }
+                | com::Command::Dispatch(..)
+                | com::Command::DispatchIndirect(..) => return,
+                _ => {}
+            }
+        }
+
         match *cmd {
             com::Command::BindIndexBuffer(buffer) => {
                 let gl = &self.share.context;
                 self.state.index_buffer = Some(buffer);
-                unsafe { gl.BindBuffer(gl::ELEMENT_ARRAY_BUFFER, buffer) };
+                unsafe { gl_call!(gl.BindBuffer(gl::ELEMENT_ARRAY_BUFFER, buffer)) };
             }
             //          com::Command::BindVertexBuffers(_data_ptr) =>
             com::Command::Draw {
@@ -276,36 +516,41 @@ impl CommandQueue {
                 let legacy = &self.share.legacy_features;
                 if instances == &(0u32..1) {
                     unsafe {
-                        gl.DrawArrays(
+                        gl_call!(gl.DrawArrays(
                             primitive,
                             vertices.start as _,
                             (vertices.end - vertices.start) as _,
-                        );
+                        ));
                     }
                 } else if legacy.contains(LegacyFeatures::DRAW_INSTANCED) {
                     if instances.start == 0 {
                         unsafe {
-                            gl.DrawArraysInstanced(
+                            gl_call!(gl.DrawArraysInstanced(
                                 primitive,
                                 vertices.start as _,
                                 (vertices.end - vertices.start) as _,
                                 instances.end as _,
-                            );
+                            ));
                         }
                     } else if legacy.contains(LegacyFeatures::DRAW_INSTANCED_BASE) {
                         unsafe {
-                            gl.DrawArraysInstancedBaseInstance(
+                            gl_call!(gl.DrawArraysInstancedBaseInstance(
                                 primitive,
                                 vertices.start as _,
                                 (vertices.end - vertices.start) as _,
                                 (instances.end - instances.start) as _,
                                 instances.start as _,
-                            );
+                            ));
                         }
                     } else {
-                        error!(
-                            "Instanced draw calls with non-zero base instance are not supported"
-                        );
+                        self.emulate_base_instance(instances, |gl, count| unsafe {
+                            gl_call!(gl.DrawArraysInstanced(
+                                primitive,
+                                vertices.start as _,
+                                (vertices.end - vertices.start) as _,
+                                count as _,
+                            ));
+                        });
                     }
                 } else {
                     error!("Instanced draw calls are not supported");
@@ -326,17 +571,22 @@ impl CommandQueue {
                 if instances == &(0u32..1) {
                     if base_vertex == 0 {
                         unsafe {
-                            gl.DrawElements(primitive, index_count as _, index_type, offset);
+                            gl_call!(gl.DrawElements(
+                                primitive,
+                                index_count as _,
+                                index_type,
+                                offset
+                            ));
                         }
                     } else if legacy.contains(LegacyFeatures::DRAW_INDEXED_BASE) {
                         unsafe {
-                            gl.DrawElementsBaseVertex(
+                            gl_call!(gl.DrawElementsBaseVertex(
                                 primitive,
                                 index_count as _,
                                 index_type,
                                 offset,
                                 base_vertex as _,
-                            );
+                            ));
                         }
                     } else {
                         error!("Base vertex with indexed drawing not supported");
@@ -344,32 +594,32 @@ impl CommandQueue {
                 } else if legacy.contains(LegacyFeatures::DRAW_INDEXED_INSTANCED) {
                     if base_vertex == 0 && instances.start == 0 {
                         unsafe {
-                            gl.DrawElementsInstanced(
+                            gl_call!(gl.DrawElementsInstanced(
                                 primitive,
                                 index_count as _,
                                 index_type,
                                 offset,
                                 instances.end as _,
-                            );
+                            ));
                         }
                     } else if instances.start == 0
                         && legacy.contains(LegacyFeatures::DRAW_INDEXED_INSTANCED_BASE_VERTEX)
                     {
                         unsafe {
-                            gl.DrawElementsInstancedBaseVertex(
+                            gl_call!(gl.DrawElementsInstancedBaseVertex(
                                 primitive,
                                 index_count as _,
                                 index_type,
                                 offset,
                                 instances.end as _,
                                 base_vertex as _,
-                            );
+                            ));
                         }
                     } else if instances.start == 0 {
                         error!("Base vertex with instanced indexed drawing is not supported");
                     } else if legacy.contains(LegacyFeatures::DRAW_INDEXED_INSTANCED_BASE) {
                         unsafe {
-                            gl.DrawElementsInstancedBaseVertexBaseInstance(
+                            gl_call!(gl.DrawElementsInstancedBaseVertexBaseInstance(
                                 primitive,
                                 index_count as _,
                                 index_type,
@@ -377,21 +627,111 @@ impl CommandQueue {
                                 (instances.end - instances.start) as _,
                                 base_vertex as _,
                                 instances.start as _,
-                            );
+                            ));
                         }
+                    } else if base_vertex == 0 {
+                        self.emulate_base_instance(instances, |gl, count| unsafe {
+                            gl_call!(gl.DrawElementsInstanced(
+                                primitive,
+                                index_count as _,
+                                index_type,
+                                offset,
+                                count as _,
+                            ));
+                        });
+                    } else if legacy.contains(LegacyFeatures::DRAW_INDEXED_INSTANCED_BASE_VERTEX) {
+                        self.emulate_base_instance(instances, |gl, count| unsafe {
+                            gl_call!(gl.DrawElementsInstancedBaseVertex(
+                                primitive,
+                                index_count as _,
+                                index_type,
+                                offset,
+                                count as _,
+                                base_vertex as _,
+                            ));
+                        });
                     } else {
-                        error!("Instance bases with instanced indexed drawing is not supported");
+                        error!("Base vertex with instanced indexed drawing is not supported");
                     }
                 } else {
                     error!("Instanced indexed drawing is not supported");
                 }
             }
+            com::Command::DrawIndirect {
+                primitive,
+                buffer,
+                offset,
+                draw_count,
+                stride,
+            } => {
+                let gl = &self.share.context;
+                let legacy = &self.share.legacy_features;
+                unsafe { gl_call!(gl.BindBuffer(gl::DRAW_INDIRECT_BUFFER, buffer)) };
+                if !legacy.contains(LegacyFeatures::INDIRECT_EXECUTION) {
+                    error!("Indirect draw calls are not supported");
+                } else if legacy.contains(LegacyFeatures::MULTI_DRAW_INDIRECT) {
+                    unsafe {
+                        gl_call!(gl.MultiDrawArraysIndirect(
+                            primitive,
+                            offset as *const gl::types::GLvoid,
+                            draw_count as _,
+                            stride as _,
+                        ));
+                    }
+                } else {
+                    // A zero stride means tightly packed `DrawArraysIndirectCommand`s.
+                    let stride = if stride == 0 { 16 } else { stride };
+                    for i in 0..draw_count {
+                        let offset = offset + (i * stride) as hal::buffer::Offset;
+                        let offset = offset as *const gl::types::GLvoid;
+                        unsafe { gl_call!(gl.DrawArraysIndirect(primitive, offset)) };
+                    }
+                }
+            }
+            com::Command::DrawIndexedIndirect {
+                primitive,
+                index_type,
+                buffer,
+                offset,
+                draw_count,
+                stride,
+            } => {
+                let gl = &self.share.context;
+                let legacy = &self.share.legacy_features;
+                unsafe { gl_call!(gl.BindBuffer(gl::DRAW_INDIRECT_BUFFER, buffer)) };
+                if !legacy.contains(LegacyFeatures::INDIRECT_EXECUTION) {
+                    error!("Indirect draw calls are not supported");
+                } else if legacy.contains(LegacyFeatures::MULTI_DRAW_INDIRECT) {
+                    unsafe {
+                        gl_call!(gl.MultiDrawElementsIndirect(
+                            primitive,
+                            index_type,
+                            offset as *const gl::types::GLvoid,
+                            draw_count as _,
+                            stride as _,
+                        ));
+                    }
+                } else {
+                    // A zero stride means tightly packed `DrawElementsIndirectCommand`s.
+                    let stride = if stride == 0 { 20 } else { stride };
+                    for i in 0..draw_count {
+                        let offset = offset + (i * stride) as hal::buffer::Offset;
+                        unsafe {
+                            gl_call!(gl.DrawElementsIndirect(
+                                primitive,
+                                index_type,
+                                offset as *const gl::types::GLvoid,
+                            ));
+                        }
+                    }
+                }
+            }
             com::Command::Dispatch(count) => {
                 // Capability support is given by which queue types will be exposed.
                 // If there is no compute support, this pattern should never be reached
                 // because no queue with compute capability can be created.
                 let gl = &self.share.context;
-                unsafe { gl.DispatchCompute(count[0], count[1], count[2]) };
+                unsafe { gl_call!(gl.DispatchCompute(count[0], count[1], count[2])) };
             }
             com::Command::DispatchIndirect(buffer, offset) => {
                 // Capability support is given by which queue types will be exposed.
@@ -399,9 +739,9 @@ impl CommandQueue {
                 // because no queue with compute capability can be created.
                 let gl = &self.share.context;
                 unsafe {
-                    gl.BindBuffer(gl::DRAW_INDIRECT_BUFFER, buffer);
+                    gl_call!(gl.BindBuffer(gl::DISPATCH_INDIRECT_BUFFER, buffer));
                     // TODO: possible integer conversion issue
-                    gl.DispatchComputeIndirect(offset as _);
+                    gl_call!(gl.DispatchComputeIndirect(offset as _));
                 }
             }
             com::Command::SetViewports {
@@ -421,30 +761,30 @@ impl CommandQueue {
                     let view = viewports[0];
                     let depth_range = depth_ranges[0];
                     unsafe {
-                        gl.Viewport(
+                        gl_call!(gl.Viewport(
                             view[0] as i32,
                             view[1] as i32,
                             view[2] as i32,
                             view[3] as i32,
-                        )
+                        ))
                     };
-                    unsafe { gl.DepthRange(depth_range[0], depth_range[1]) };
+                    unsafe { gl_call!(gl.DepthRange(depth_range[0], depth_range[1])) };
                 } else if num_viewports > 1 {
                     // Support for these functions is coupled with the support
                     // of multiple viewports.
                     unsafe {
-                        gl.ViewportArrayv(
+                        gl_call!(gl.ViewportArrayv(
                             first_viewport,
                             num_viewports as i32,
                             viewports.as_ptr() as *const _,
-                        )
+                        ))
                     };
                     unsafe {
-                        gl.DepthRangeArrayv(
+                        gl_call!(gl.DepthRangeArrayv(
                             first_viewport,
                             num_viewports as i32,
                             depth_ranges.as_ptr() as *const _,
-                        )
+                        ))
                     };
                 }
             }
@@ -456,36 +796,35 @@ impl CommandQueue {
 
                 if num_scissors == 1 {
                     let scissor = scissors[0];
-                    unsafe { gl.Scissor(scissor[0], scissor[1], scissor[2], scissor[3]) };
+                    unsafe { gl_call!(gl.Scissor(scissor[0], scissor[1], scissor[2], scissor[3])) };
                 } else {
                     // Support for this function is coupled with the support
                     // of multiple viewports.
                     unsafe {
-                        gl.ScissorArrayv(
+                        gl_call!(gl.ScissorArrayv(
                             first_scissor,
                             num_scissors as i32,
                             scissors.as_ptr() as *const _,
-                        )
+                        ))
                     };
                 }
             }
             com::Command::SetBlendColor(color) => {
-                state::set_blend_color(&self.share.context, color);
+                self.state
+                    .shadow
+                    .set_blend_color(&self.share.context, color);
             }
             com::Command::ClearBufferColorF(draw_buffer, cv) => unsafe {
-                self.share
-                    .context
-                    .ClearBufferfv(gl::COLOR, draw_buffer, cv.as_ptr());
+                let gl = &self.share.context;
+                gl_call!(gl.ClearBufferfv(gl::COLOR, draw_buffer, cv.as_ptr()));
             },
             com::Command::ClearBufferColorU(draw_buffer, cv) => unsafe {
-                self.share
-                    .context
-                    .ClearBufferuiv(gl::COLOR, draw_buffer, cv.as_ptr());
+                let gl = &self.share.context;
+                gl_call!(gl.ClearBufferuiv(gl::COLOR, draw_buffer, cv.as_ptr()));
             },
             com::Command::ClearBufferColorI(draw_buffer, cv) => unsafe {
-                self.share
-                    .context
-                    .ClearBufferiv(gl::COLOR, draw_buffer, cv.as_ptr());
+                let gl = &self.share.context;
+                gl_call!(gl.ClearBufferiv(gl::COLOR, draw_buffer, cv.as_ptr()));
             },
             com::Command::ClearBufferDepthStencil(depth, stencil) => unsafe {
                 let (target, depth, stencil) = match (depth, stencil) {
@@ -495,174 +834,655 @@ impl CommandQueue {
                     _ => unreachable!(),
                 };
 
-                self.share
-                    .context
-                    .ClearBufferfi(target, 0, depth, stencil as _);
+                let gl = &self.share.context;
+                gl_call!(gl.ClearBufferfi(target, 0, depth, stencil as _));
             },
             com::Command::ClearTexture(_color) => unimplemented!(),
-            com::Command::DrawBuffers(draw_buffers) => unsafe {
-                let draw_buffers = Self::get::<gl::types::GLenum>(data_buf, draw_buffers);
-                self.share
-                    .context
-                    .DrawBuffers(draw_buffers.len() as _, draw_buffers.as_ptr());
+            com::Command::ClearTexSubImage {
+                texture,
+                level,
+                offset,
+                extent,
+                format,
+                ty,
+                ref value,
+            } => unsafe {
+                gl_call!(self.share.context.ClearTexSubImage(
+                    texture,
+                    level as _,
+                    offset[0],
+                    offset[1],
+                    offset[2],
+                    extent[0],
+                    extent[1],
+                    extent[2],
+                    format,
+                    ty,
+                    value.as_ptr() as *const _,
+                ));
+            },
+            com::Command::InvalidateFramebuffer(point, attachments) => unsafe {
+                let attachments = Self::get::<gl::types::GLenum>(data_buf, attachments);
+                gl_call!(self.share.context.InvalidateFramebuffer(
+                    point,
+                    attachments.len() as _,
+                    attachments.as_ptr(),
+                ));
             },
+            com::Command::DrawBuffers(draw_buffers) => {
+                let draw_buffers = Self::get::<gl::types::GLenum>(data_buf, draw_buffers);
+                self.state
+                    .shadow
+                    .set_draw_buffers(&self.share.context, draw_buffers);
+            }
             com::Command::BindFrameBuffer(point, frame_buffer) => {
                 if self.share.private_caps.framebuffer {
-                    let gl = &self.share.context;
-                    unsafe { gl.BindFramebuffer(point, frame_buffer) };
+                    self.state
+                        .shadow
+                        .bind_framebuffer(&self.share.context, point, frame_buffer);
                 } else if frame_buffer != 0 {
                     error!("Tried to bind FBO {} without FBO support!", frame_buffer);
                 }
             }
+            com::Command::ResolveAttachment {
+                src,
+                attachment,
+                dst,
+                extent,
+            } => {
+                let gl = &self.share.context;
+                let (width, height) = (extent.width as i32, extent.height as i32);
+                self.state
+                    .shadow
+                    .bind_framebuffer(gl, gl::READ_FRAMEBUFFER, src);
+                self.state
+                    .shadow
+                    .bind_framebuffer(gl, gl::DRAW_FRAMEBUFFER, dst);
+                unsafe {
+                    gl_call!(gl.ReadBuffer(attachment));
+                    gl_call!(gl.BlitFramebuffer(
+                        0,
+                        0,
+                        width,
+                        height,
+                        0,
+                        0,
+                        width,
+                        height,
+                        gl::COLOR_BUFFER_BIT,
+                        gl::NEAREST,
+                    ));
+                }
+                self.state
+                    .shadow
+                    .bind_framebuffer(gl, gl::DRAW_FRAMEBUFFER, src);
+            }
+            com::Command::BlitFramebuffer {
+                ref src,
+                ref dst,
+                mask,
+                filter,
+            } => unsafe {
+                let gl = &self.share.context;
+                gl_call!(gl.ReadBuffer(gl::COLOR_ATTACHMENT0));
+                gl_call!(gl.BlitFramebuffer(
+                    src.start.x,
+                    src.start.y,
+                    src.end.x,
+                    src.end.y,
+                    dst.start.x,
+                    dst.start.y,
+                    dst.end.x,
+                    dst.end.y,
+                    mask,
+                    filter,
+                ));
+            },
             com::Command::BindTargetView(point, attachment, view) => {
                 self.bind_target(point, attachment, &view)
             }
             com::Command::SetDrawColorBuffers(num) => {
-                state::bind_draw_color_buffers(&self.share.context, num);
+                self.state
+                    .shadow
+                    .bind_draw_color_buffers(&self.share.context, num);
             }
-            com::Command::SetPatchSize(num) => unsafe {
-                self.share.context.PatchParameteri(gl::PATCH_VERTICES, num);
-            },
-            com::Command::BindProgram(program) => unsafe {
-                self.share.context.UseProgram(program);
-            },
-            com::Command::BindBlendSlot(slot, ref blend) => {
-                state::bind_blend_slot(&self.share.context, slot, blend);
+            com::Command::SetPatchSize(num) => {
+                self.state.shadow.set_patch_size(&self.share.context, num);
             }
-            com::Command::BindAttribute(ref attribute, handle, stride, rate) => unsafe {
-                use native::VertexAttribFunction::*;
-
-                let &native::AttributeDesc {
-                    location,
-                    size,
-                    format,
-                    offset,
-                    vertex_attrib_fn,
-                    ..
-                } = attribute;
-                let offset = offset as *const gl::types::GLvoid;
+            com::Command::SetPrimitiveRestart(restart) => {
+                let caps = &self.share.private_caps;
+                if caps.primitive_restart {
+                    self.state.shadow.set_primitive_restart(
+                        &self.share.context,
+                        restart,
+                        caps.primitive_restart_fixed_index,
+                    );
+                }
+            }
+            com::Command::SetSampleShading(shading) => {
+                if self
+                    .share
+                    .features
+                    .contains(hal::Features::SAMPLE_RATE_SHADING)
+                {
+                    self.state
+                        .shadow
+                        .set_sample_shading(&self.share.context, shading);
+                }
+            }
+            com::Command::SetSampleCoverage(coverage) => {
+                let caps = &self.share.private_caps;
                 let gl = &self.share.context;
-
-                gl.BindBuffer(gl::ARRAY_BUFFER, handle);
-
-                match vertex_attrib_fn {
-                    Float => {
-                        gl.VertexAttribPointer(location, size, format, gl::FALSE, stride, offset)
+                self.state.shadow.set_alpha_coverage(
+                    gl,
+                    coverage.alpha_to_coverage,
+                    if caps.alpha_to_one {
+                        Some(coverage.alpha_to_one)
+                    } else {
+                        None
+                    },
+                );
+                if caps.sample_mask {
+                    self.state
+                        .shadow
+                        .set_sample_mask(gl, coverage.sample_mask as gl::types::GLbitfield);
+                }
+            }
+            com::Command::BindProgram(program) => {
+                self.state.shadow.bind_program(&self.share.context, program);
+            }
+            com::Command::SetUniform { location, ty, data } => {
+                let gl = &self.share.context;
+                let values = Self::get::<u32>(data_buf, data);
+                let floats = values.as_ptr() as *const f32;
+                let ints = values.as_ptr() as *const i32;
+                unsafe {
+                    match ty {
+                        gl::FLOAT => gl_call!(gl.Uniform1fv(location, 1, floats)),
+                        gl::FLOAT_VEC2 => gl_call!(gl.Uniform2fv(location, 1, floats)),
+                        gl::FLOAT_VEC3 => gl_call!(gl.Uniform3fv(location, 1, floats)),
+                        gl::FLOAT_VEC4 => gl_call!(gl.Uniform4fv(location, 1, floats)),
+                        gl::INT | gl::BOOL => gl_call!(gl.Uniform1iv(location, 1, ints)),
+                        gl::INT_VEC2 | gl::BOOL_VEC2 => gl_call!(gl.Uniform2iv(location, 1, ints)),
+                        gl::INT_VEC3 | gl::BOOL_VEC3 => gl_call!(gl.Uniform3iv(location, 1, ints)),
+                        gl::INT_VEC4 | gl::BOOL_VEC4 => gl_call!(gl.Uniform4iv(location, 1, ints)),
+                        gl::UNSIGNED_INT => gl_call!(gl.Uniform1uiv(location, 1, values.as_ptr())),
+                        gl::UNSIGNED_INT_VEC2 => {
+                            gl_call!(gl.Uniform2uiv(location, 1, values.as_ptr()))
+                        }
+                        gl::UNSIGNED_INT_VEC3 => {
+                            gl_call!(gl.Uniform3uiv(location, 1, values.as_ptr()))
+                        }
+                        gl::UNSIGNED_INT_VEC4 => {
+                            gl_call!(gl.Uniform4uiv(location, 1, values.as_ptr()))
+                        }
+                        gl::FLOAT_MAT2 => {
+                            gl_call!(gl.UniformMatrix2fv(location, 1, gl::FALSE, floats))
+                        }
+                        gl::FLOAT_MAT3 => {
+                            gl_call!(gl.UniformMatrix3fv(location, 1, gl::FALSE, floats))
+                        }
+                        gl::FLOAT_MAT4 => {
+                            gl_call!(gl.UniformMatrix4fv(location, 1, gl::FALSE, floats))
+                        }
+                        _ => error!("Unsupported uniform type {:x}", ty),
                     }
-                    Integer => gl.VertexAttribIPointer(location, size, format, stride, offset),
-                    Double => gl.VertexAttribLPointer(location, size, format, stride, offset),
                 }
-
-                if rate != 0 {
-                    if self
-                        .share
-                        .legacy_features
-                        .contains(LegacyFeatures::INSTANCED_ATTRIBUTE_BINDING)
-                    {
-                        gl.VertexAttribDivisor(location, rate);
-                    } else {
-                        error!("Binding attribute with instanced input rate is not supported");
+            }
+            com::Command::BindBlendSlot(slot, ref blend) => {
+                let independent = self
+                    .share
+                    .features
+                    .contains(hal::Features::INDEPENDENT_BLENDING);
+                self.state
+                    .shadow
+                    .bind_blend_slot(&self.share.context, slot, blend, independent);
+            }
+            com::Command::SetEvent(ref event, value) => {
+                event.0.store(value, Ordering::Release);
+            }
+            com::Command::SetLogicOp(op) => {
+                if self.share.features.contains(hal::Features::LOGIC_OP) {
+                    self.state.shadow.set_logic_op(&self.share.context, op);
+                }
+            }
+            com::Command::BindRasterizer(ref rasterizer) => {
+                self.state.shadow.bind_rasterizer(
+                    &self.share.context,
+                    rasterizer,
+                    self.share.info.version.is_embedded,
+                    self.share.features.contains(hal::Features::DEPTH_CLAMP),
+                );
+            }
+            com::Command::SetDepthBias(bias) => {
+                state::set_depth_bias(&self.share.context, bias);
+            }
+            com::Command::BindDepth(ref depth) => {
+                self.state.shadow.bind_depth(&self.share.context, depth);
+            }
+            com::Command::BindStencil(ref stencil, refs, cull) => {
+                self.state
+                    .shadow
+                    .bind_stencil(&self.share.context, stencil, refs, cull);
+            }
+            com::Command::BindAttributes(slice) => {
+                let bindings = Self::get::<native::AttributeBinding>(data_buf, slice);
+                self.state.attributes.clear();
+                self.state.attributes.extend_from_slice(bindings);
+                if self.share.private_caps.vertex_array {
+                    self.bind_vertex_array(bindings);
+                } else {
+                    for binding in bindings {
+                        set_vertex_attribute(&self.share, binding);
                     }
                 }
-
-                gl.EnableVertexAttribArray(location);
-                gl.BindBuffer(gl::ARRAY_BUFFER, 0);
-            },
+            }
             /*
             com::Command::UnbindAttribute(ref attribute) => unsafe {
                 self.share.context.DisableVertexAttribArray(attribute.location);
             }*/
             com::Command::CopyBufferToBuffer(src, dst, ref r) => unsafe {
                 let gl = &self.share.context;
-                gl.BindBuffer(gl::PIXEL_UNPACK_BUFFER, src);
-                gl.BindBuffer(gl::PIXEL_PACK_BUFFER, dst);
-                gl.CopyBufferSubData(
+                gl_call!(gl.BindBuffer(gl::PIXEL_UNPACK_BUFFER, src));
+                gl_call!(gl.BindBuffer(gl::PIXEL_PACK_BUFFER, dst));
+                gl_call!(gl.CopyBufferSubData(
                     gl::PIXEL_UNPACK_BUFFER,
                     gl::PIXEL_PACK_BUFFER,
                     r.src as _,
                     r.dst as _,
                     r.size as _,
-                );
-                gl.BindBuffer(gl::PIXEL_UNPACK_BUFFER, 0);
-                gl.BindBuffer(gl::PIXEL_PACK_BUFFER, 0);
+                ));
+                gl_call!(gl.BindBuffer(gl::PIXEL_UNPACK_BUFFER, 0));
+                gl_call!(gl.BindBuffer(gl::PIXEL_PACK_BUFFER, 0));
             },
-            com::Command::CopyBufferToTexture(buffer, texture, ref r) => unsafe {
-                // TODO: Fix format and active texture
-                assert_eq!(r.image_offset.z, 0);
+            com::Command::FillBuffer(buffer, ref range, value) => unsafe {
                 let gl = &self.share.context;
-                gl.ActiveTexture(gl::TEXTURE0);
-                gl.BindBuffer(gl::PIXEL_UNPACK_BUFFER, buffer);
-                gl.BindTexture(gl::TEXTURE_2D, texture);
-                gl.TexSubImage2D(
-                    gl::TEXTURE_2D,
-                    r.image_layers.level as _,
-                    r.image_offset.x,
-                    r.image_offset.y,
-                    r.image_extent.width as _,
-                    r.image_extent.height as _,
-                    gl::RGBA,
-                    gl::UNSIGNED_BYTE,
-                    ptr::null(),
-                );
-                gl.BindBuffer(gl::PIXEL_UNPACK_BUFFER, 0);
+                gl_call!(gl.BindBuffer(gl::COPY_WRITE_BUFFER, buffer));
+                if self.share.private_caps.clear_buffer_object {
+                    gl_call!(gl.ClearBufferSubData(
+                        gl::COPY_WRITE_BUFFER,
+                        gl::R32UI,
+                        range.start as _,
+                        (range.end - range.start) as _,
+                        gl::RED_INTEGER,
+                        gl::UNSIGNED_INT,
+                        &value as *const u32 as *const _,
+                    ));
+                } else {
+                    let data = vec![value; (range.end - range.start) as usize / 4];
+                    gl_call!(gl.BufferSubData(
+                        gl::COPY_WRITE_BUFFER,
+                        range.start as _,
+                        (range.end - range.start) as _,
+                        data.as_ptr() as *const _,
+                    ));
+                }
+                gl_call!(gl.BindBuffer(gl::COPY_WRITE_BUFFER, 0));
+            },
+            com::Command::CopyBufferToTexture {
+                buffer,
+                texture,
+                target,
+                format,
+                ty,
+                region: ref r,
+            } => unsafe {
+                self.state.shadow.invalidate_textures();
+                let gl = &self.share.context;
+                gl_call!(gl.ActiveTexture(gl::TEXTURE0));
+                gl_call!(gl.BindBuffer(gl::PIXEL_UNPACK_BUFFER, buffer));
+                gl_call!(gl.PixelStorei(gl::UNPACK_ROW_LENGTH, r.buffer_width as _));
+                gl_call!(gl.PixelStorei(gl::UNPACK_IMAGE_HEIGHT, r.buffer_height as _));
+                gl_call!(gl.BindTexture(target, texture));
+                match target {
+                    gl::TEXTURE_2D | gl::TEXTURE_CUBE_MAP => {
+                        let face_target = if target == gl::TEXTURE_CUBE_MAP {
+                            gl::TEXTURE_CUBE_MAP_POSITIVE_X
+                                + r.image_layers.layers.start as gl::types::GLenum
+                        } else {
+                            target
+                        };
+                        gl_call!(gl.TexSubImage2D(
+                            face_target,
+                            r.image_layers.level as _,
+                            r.image_offset.x,
+                            r.image_offset.y,
+                            r.image_extent.width as _,
+                            r.image_extent.height as _,
+                            format,
+                            ty,
+                            r.buffer_offset as *const _,
+                        ));
+                    }
+                    _ => {
+                        // Layers of an array texture are addressed as depth slices.
+                        let (z, depth) = if target == gl::TEXTURE_3D {
+                            (r.image_offset.z, r.image_extent.depth)
+                        } else {
+                            (
+                                r.image_layers.layers.start as i32,
+                                (r.image_layers.layers.end - r.image_layers.layers.start) as u32,
+                            )
+                        };
+                        gl_call!(gl.TexSubImage3D(
+                            target,
+                            r.image_layers.level as _,
+                            r.image_offset.x,
+                            r.image_offset.y,
+                            z,
+                            r.image_extent.width as _,
+                            r.image_extent.height as _,
+                            depth as _,
+                            format,
+                            ty,
+                            r.buffer_offset as *const _,
+                        ));
+                    }
+                }
+                gl_call!(gl.PixelStorei(gl::UNPACK_ROW_LENGTH, 0));
+                gl_call!(gl.PixelStorei(gl::UNPACK_IMAGE_HEIGHT, 0));
+                gl_call!(gl.BindBuffer(gl::PIXEL_UNPACK_BUFFER, 0));
+            },
+            com::Command::CopyBufferToCompressedTexture {
+                buffer,
+                texture,
+                target,
+                format,
+                size,
+                region: ref r,
+            } => unsafe {
+                self.state.shadow.invalidate_textures();
+                let gl = &self.share.context;
+                gl_call!(gl.ActiveTexture(gl::TEXTURE0));
+                gl_call!(gl.BindBuffer(gl::PIXEL_UNPACK_BUFFER, buffer));
+                gl_call!(gl.BindTexture(target, texture));
+                match target {
+                    gl::TEXTURE_2D | gl::TEXTURE_CUBE_MAP => {
+                        let face_target = if target == gl::TEXTURE_CUBE_MAP {
+                            gl::TEXTURE_CUBE_MAP_POSITIVE_X
+                                + r.image_layers.layers.start as gl::types::GLenum
+                        } else {
+                            target
+                        };
+                        gl_call!(gl.CompressedTexSubImage2D(
+                            face_target,
+                            r.image_layers.level as _,
+                            r.image_offset.x,
+                            r.image_offset.y,
+                            r.image_extent.width as _,
+                            r.image_extent.height as _,
+                            format,
+                            size,
+                            r.buffer_offset as *const _,
+                        ));
+                    }
+                    _ => {
+                        // Layers of an array texture are addressed as depth slices.
+                        let (z, depth) = if target == gl::TEXTURE_3D {
+                            (r.image_offset.z, r.image_extent.depth)
+                        } else {
+                            (
+                                r.image_layers.layers.start as i32,
+                                (r.image_layers.layers.end - r.image_layers.layers.start) as u32,
+                            )
+                        };
+                        gl_call!(gl.CompressedTexSubImage3D(
+                            target,
+                            r.image_layers.level as _,
+                            r.image_offset.x,
+                            r.image_offset.y,
+                            z,
+                            r.image_extent.width as _,
+                            r.image_extent.height as _,
+                            depth as _,
+                            format,
+                            size,
+                            r.buffer_offset as *const _,
+                        ));
+                    }
+                }
+                gl_call!(gl.BindBuffer(gl::PIXEL_UNPACK_BUFFER, 0));
             },
-            com::Command::CopyBufferToSurface(..) => {
-                unimplemented!() //TODO: use FBO
-            }
             com::Command::CopyTextureToBuffer(texture, buffer, ref r) => unsafe {
                 // TODO: Fix format and active texture
                 // TODO: handle partial copies gracefully
                 assert_eq!(r.image_offset, hal::image::Offset { x: 0, y: 0, z: 0 });
+                self.state.shadow.invalidate_textures();
                 let gl = &self.share.context;
-                gl.ActiveTexture(gl::TEXTURE0);
-                gl.BindBuffer(gl::PIXEL_PACK_BUFFER, buffer);
-                gl.BindTexture(gl::TEXTURE_2D, texture);
-                gl.GetTexImage(
+                gl_call!(gl.ActiveTexture(gl::TEXTURE0));
+                gl_call!(gl.BindBuffer(gl::PIXEL_PACK_BUFFER, buffer));
+                gl_call!(gl.BindTexture(gl::TEXTURE_2D, texture));
+                gl_call!(gl.GetTexImage(
                     gl::TEXTURE_2D,
                     r.image_layers.level as _,
                     //r.image_offset.x, r.image_offset.y,
                     //r.image_extent.width as _, r.image_extent.height as _,
                     gl::RGBA,
                     gl::UNSIGNED_BYTE,
-                    ptr::null_mut(),
-                );
-                gl.BindBuffer(gl::PIXEL_PACK_BUFFER, 0);
+                    r.buffer_offset as *mut _,
+                ));
+                gl_call!(gl.BindBuffer(gl::PIXEL_PACK_BUFFER, 0));
+            },
+            com::Command::CopyFramebufferToBuffer {
+                buffer,
+                format,
+                ty,
+                region: ref r,
+            } => unsafe {
+                let gl = &self.share.context;
+                gl_call!(gl.BindBuffer(gl::PIXEL_PACK_BUFFER, buffer));
+                gl_call!(gl.PixelStorei(gl::PACK_ROW_LENGTH, r.buffer_width as _));
+                gl_call!(gl.ReadPixels(
+                    r.image_offset.x,
+                    r.image_offset.y,
+                    r.image_extent.width as _,
+                    r.image_extent.height as _,
+                    format,
+                    ty,
+                    r.buffer_offset as *mut _,
+                ));
+                gl_call!(gl.PixelStorei(gl::PACK_ROW_LENGTH, 0));
+                gl_call!(gl.BindBuffer(gl::PIXEL_PACK_BUFFER, 0));
             },
-            com::Command::CopySurfaceToBuffer(..) => {
-                unimplemented!() //TODO: use FBO
+            com::Command::BindBufferRange(target, index, buffer, offset, size) => {
+                self.state.shadow.bind_buffer_range(
+                    &self.share.context,
+                    target,
+                    index,
+                    buffer,
+                    offset,
+                    size,
+                );
+            }
+            com::Command::BindTexture(index, texture, target) => {
+                self.state
+                    .shadow
+                    .bind_texture(&self.share.context, index, target, texture);
             }
-            com::Command::CopyImageToTexture(..) => {
-                unimplemented!() //TODO: use FBO
+            com::Command::BindSampler(index, sampler) => {
+                self.state
+                    .shadow
+                    .bind_sampler(&self.share.context, index, sampler);
             }
-            com::Command::CopyImageToSurface(..) => {
-                unimplemented!() //TODO: use FBO
+            com::Command::MemoryBarrier(bits) => unsafe {
+                gl_call!(self.share.context.MemoryBarrier(bits));
+            },
+            com::Command::SetFramebufferSrgb(enable) => {
+                let supported = self
+                    .share
+                    .legacy_features
+                    .contains(LegacyFeatures::SRGB_COLOR);
+                if supported && self.state.framebuffer_srgb != Some(enable) {
+                    let gl = &self.share.context;
+                    if enable {
+                        unsafe { gl_call!(gl.Enable(gl::FRAMEBUFFER_SRGB)) };
+                    } else {
+                        unsafe { gl_call!(gl.Disable(gl::FRAMEBUFFER_SRGB)) };
+                    }
+                    self.state.framebuffer_srgb = Some(enable);
+                }
             }
-            com::Command::BindBufferRange(target, index, buffer, offset, size) => unsafe {
+            com::Command::BindTransformFeedback(feedback) => unsafe {
                 let gl = &self.share.context;
-                gl.BindBufferRange(target, index, buffer, offset, size);
+                gl_call!(gl.BindTransformFeedback(gl::TRANSFORM_FEEDBACK, feedback));
+            },
+            com::Command::BeginTransformFeedback(primitive) => unsafe {
+                gl_call!(self.share.context.BeginTransformFeedback(primitive));
+            },
+            com::Command::PauseTransformFeedback => unsafe {
+                gl_call!(self.share.context.PauseTransformFeedback());
             },
-            com::Command::BindTexture(index, texture) => unsafe {
+            com::Command::ResumeTransformFeedback => unsafe {
+                gl_call!(self.share.context.ResumeTransformFeedback());
+            },
+            com::Command::EndTransformFeedback => unsafe {
                 let gl = &self.share.context;
-                gl.ActiveTexture(gl::TEXTURE0 + index);
-                gl.BindTexture(gl::TEXTURE_2D, texture);
+                gl_call!(gl.EndTransformFeedback());
+                gl_call!(gl.BindTransformFeedback(gl::TRANSFORM_FEEDBACK, 0));
             },
-            com::Command::BindSampler(index, sampler) => unsafe {
+            com::Command::DrawTransformFeedback {
+                primitive,
+                feedback,
+                instances,
+            } => {
                 let gl = &self.share.context;
-                gl.BindSampler(index, sampler);
+                let legacy = &self.share.legacy_features;
+                if !legacy.contains(LegacyFeatures::DRAW_TRANSFORM_FEEDBACK) {
+                    error!("Transform feedback draw calls are not supported");
+                } else if instances == 1 {
+                    unsafe { gl_call!(gl.DrawTransformFeedback(primitive, feedback)) };
+                } else if gl.DrawTransformFeedbackInstanced.is_loaded() {
+                    unsafe {
+                        gl_call!(gl.DrawTransformFeedbackInstanced(
+                            primitive,
+                            feedback,
+                            instances as _
+                        ))
+                    };
+                } else {
+                    error!("Instanced transform feedback draw calls are not supported");
+                }
+            }
+            com::Command::BeginQuery(target, query) => unsafe {
+                gl_call!(self.share.context.BeginQuery(target, query));
+            },
+            com::Command::EndQuery(target) => unsafe {
+                gl_call!(self.share.context.EndQuery(target));
+            },
+            com::Command::QueryCounter(query) => unsafe {
+                gl_call!(self.share.context.QueryCounter(query, gl::TIMESTAMP));
             },
-            com::Command::SetTextureSamplerSettings(index, texture, ref sinfo) => unsafe {
+            com::Command::BeginConditionalRendering {
+                buffer,
+                offset,
+                inverted,
+            } => {
                 let gl = &self.share.context;
-                gl.ActiveTexture(gl::TEXTURE0 + index);
-                gl.BindTexture(gl::TEXTURE_2D, texture);
+                let mut value = 0u32;
+                unsafe {
+                    gl_call!(gl.BindBuffer(gl::COPY_READ_BUFFER, buffer));
+                    gl_call!(gl.GetBufferSubData(
+                        gl::COPY_READ_BUFFER,
+                        offset as _,
+                        mem::size_of::<u32>() as _,
+                        &mut value as *mut u32 as *mut _,
+                    ));
+                    gl_call!(gl.BindBuffer(gl::COPY_READ_BUFFER, 0));
+                }
+                self.state.discard_draws = (value == 0) != inverted;
+            }
+            com::Command::EndConditionalRendering => {
+                self.state.discard_draws = false;
+            }
+            com::Command::CopyQueryResult {
+                query,
+                buffer,
+                offset,
+                flags,
+            } => {
+                use hal::query::ResultFlags;
+
+                if self.share.private_caps.query_buffer {
+                    let gl = &self.share.context;
+                    let pname = if flags.contains(ResultFlags::WAIT) {
+                        gl::QUERY_RESULT
+                    } else {
+                        gl::QUERY_RESULT_NO_WAIT
+                    };
+                    let wide = flags.contains(ResultFlags::BITS_64);
+                    // With a query buffer bound, the pointer is an offset into it.
+                    let write = |pname, offset: hal::buffer::Offset| unsafe {
+                        if wide {
+                            gl_call!(gl.GetQueryObjectui64v(query, pname, offset as *mut _));
+                        } else {
+                            gl_call!(gl.GetQueryObjectuiv(query, pname, offset as *mut _));
+                        }
+                    };
+
+                    unsafe { gl_call!(gl.BindBuffer(gl::QUERY_BUFFER, buffer)) };
+                    write(pname, offset);
+                    if flags.contains(ResultFlags::WITH_AVAILABILITY) {
+                        let size = if wide { 8 } else { 4 };
+                        write(gl::QUERY_RESULT_AVAILABLE, offset + size);
+                    }
+                    unsafe { gl_call!(gl.BindBuffer(gl::QUERY_BUFFER, 0)) };
+                } else {
+                    error!("Copying query results into buffers is not supported");
+                }
+            }
+            com::Command::PushDebugGroup(label) => {
+                if self.share.private_caps.debug {
+                    let label = Self::get_raw(data_buf, label);
+                    unsafe {
+                        gl_call!(self.share.context.PushDebugGroup(
+                            gl::DEBUG_SOURCE_APPLICATION,
+                            0,
+                            label.len() as _,
+                            label.as_ptr() as *const _,
+                        ));
+                    }
+                }
+            }
+            com::Command::ExecuteCommands { .. } => {
+                unreachable!("Secondary command buffers are executed by `execute`")
+            }
+            com::Command::PopDebugGroup => {
+                if self.share.private_caps.debug {
+                    unsafe { gl_call!(self.share.context.PopDebugGroup()) };
+                }
+            }
+            com::Command::InsertDebugMarker(label) => {
+                if self.share.private_caps.debug {
+                    let label = Self::get_raw(data_buf, label);
+                    unsafe {
+                        gl_call!(self.share.context.DebugMessageInsert(
+                            gl::DEBUG_SOURCE_APPLICATION,
+                            gl::DEBUG_TYPE_MARKER,
+                            0,
+                            gl::DEBUG_SEVERITY_NOTIFICATION,
+                            label.len() as _,
+                            label.as_ptr() as *const _,
+                        ));
+                    }
+                }
+            }
+            com::Command::SetTextureSamplerSettings(index, texture, target, ref sinfo) => unsafe {
+                let gl = &self.share.context;
+                self.state.shadow.bind_texture(gl, index, target, texture);
+                // Multisampled textures are fetched per sample, without any filtering.
+                let multisampled = target == gl::TEXTURE_2D_MULTISAMPLE
+                    || target == gl::TEXTURE_2D_MULTISAMPLE_ARRAY;
 
                 // TODO: Optimization: only change texture properties that have changed.
-                device::set_sampler_info(
-                    &self.share,
-                    &sinfo,
-                    |a, b| gl.TexParameterf(gl::TEXTURE_2D, a, b),
-                    |a, b| gl.TexParameterfv(gl::TEXTURE_2D, a, &b[0]),
-                    |a, b| gl.TexParameteri(gl::TEXTURE_2D, a, b),
-                );
+                if !multisampled {
+                    device::set_sampler_info(
+                        &self.share,
+                        &sinfo,
+                        |a, b| gl_call!(gl.TexParameterf(target, a, b)),
+                        |a, b| gl_call!(gl.TexParameterfv(target, a, &b[0])),
+                        |a, b| gl_call!(gl.TexParameteri(target, a, b)),
+                    );
+                }
             }, /*
                com::Command::BindConstantBuffer(pso::ConstantBufferParam(buffer, _, slot)) => unsafe {
                    self.share.context.BindBufferBase(gl::UNIFORM_BUFFER, slot as gl::types::GLuint, buffer);
@@ -767,19 +1587,28 @@ impl CommandQueue {
                */
         }
         if let Err(err) = self.share.check() {
-            panic!("Error {:?} executing command: {:?}", err, cmd)
+            if cfg!(feature = "strict-errors") {
+                // Keep going, so every failing command shows up in the log.
+                error!("Error {:?} executing command: {:?}", err, cmd);
+                self.share.clear_errors();
+            } else {
+                panic!("Error {:?} executing command: {:?}", err, cmd)
+            }
         }
     }
 
     fn signal_fence(&mut self, fence: &native::Fence) {
+        // Without sync objects, waiting on the fence finishes all the work instead.
         if self.share.private_caps.sync {
-            let sync = if self.share.private_caps.sync {
-                let gl = &self.share.context;
-                unsafe { gl.FenceSync(gl::SYNC_GPU_COMMANDS_COMPLETE, 0) }
-            } else {
-                ptr::null()
-            };
+            let gl = &self.share.context;
+            let old = fence.0.get();
+            if !old.is_null() {
+                unsafe { gl_call!(gl.DeleteSync(old)) };
+            }
 
+            let sync = unsafe { gl_call!(gl.FenceSync(gl::SYNC_GPU_COMMANDS_COMPLETE, 0)) };
+            // Make sure the fence reaches the GPU, even if nobody waits on it.
+            unsafe { gl_call!(gl.Flush()) };
             fence.0.set(sync);
         }
     }
@@ -790,14 +1619,26 @@ impl hal::queue::RawCommandQueue<Backend> for CommandQueue {
         &mut self,
         submit_info: hal::queue::Submission<Ic, Iw, Is>,
         fence: Option<&native::Fence>,
-    ) where
+    ) -> Result<(), hal::device::DeviceLost>
+    where
         T: 'a + Borrow<com::RawCommandBuffer>,
         Ic: IntoIterator<Item = &'a T>,
         S: 'a + Borrow<native::Semaphore>,
         Iw: IntoIterator<Item = (&'a S, hal::pso::PipelineStage)>,
         Is: IntoIterator<Item = &'a S>,
     {
-        use pool::BufferMemory;
+        if cfg!(feature = "strict-errors") {
+            // Don't blame the first command for the errors of earlier calls.
+            if let Err(err) = self.share.check() {
+                error!("Error {:?} raised before the submission", err);
+                self.share.clear_errors();
+            }
+        }
+        for (semaphore, _) in submit_info.wait_semaphores {
+            semaphore.borrow().wait();
+        }
+        // The device may have changed the bindings since the last submission.
+        self.state.shadow.invalidate_bindings();
         {
             for buf in submit_info.command_buffers {
                 let cb = buf.borrow();
@@ -806,45 +1647,67 @@ impl hal::queue::RawCommandQueue<Backend> for CommandQueue {
                     .try_lock()
                     .expect("Trying to submit a command buffers, while memory is in-use.");
 
-                let buffer = match *memory {
-                    BufferMemory::Linear(ref buffer) => buffer,
-                    BufferMemory::Individual { ref storage, .. } => storage.get(&cb.id).unwrap(),
-                };
-
-                assert!(buffer.commands.len() >= (cb.buf.offset + cb.buf.size) as usize);
-                let commands = &buffer.commands
-                    [cb.buf.offset as usize..(cb.buf.offset + cb.buf.size) as usize];
                 self.reset_state();
-                for com in commands {
-                    self.process(com, &buffer.data);
-                }
+                self.execute(&memory, &cb.memory, cb.id, cb.buf);
             }
         }
+        for semaphore in submit_info.signal_semaphores {
+            semaphore.borrow().signal();
+        }
         fence.map(|fence| self.signal_fence(fence));
+        Ok(())
     }
 
     #[cfg(feature = "glutin")]
     unsafe fn present<'a, W, Is, S, Iw>(
         &mut self,
         swapchains: Is,
-        _wait_semaphores: Iw,
-    ) -> Result<(), ()>
+        wait_semaphores: Iw,
+    ) -> Result<(), hal::PresentError>
     where
         W: 'a + Borrow<window::glutin::Swapchain>,
         Is: IntoIterator<Item = (&'a W, hal::SwapImageIndex)>,
         S: 'a + Borrow<native::Semaphore>,
         Iw: IntoIterator<Item = &'a S>,
     {
+        for semaphore in wait_semaphores {
+            semaphore.borrow().wait();
+        }
         for swapchain in swapchains {
-            swapchain.0.borrow().window.swap_buffers().unwrap();
+            match swapchain.0.borrow().window.swap_buffers() {
+                Ok(()) => {}
+                Err(::glutin::ContextError::ContextLost) => {
+                    return Err(hal::PresentError::DeviceLost(hal::device::DeviceLost))
+                }
+                Err(err) => panic!("Failed to present frame: {:?}", err),
+            }
         }
 
         Ok(())
     }
 
+    #[cfg(all(target_os = "emscripten", not(feature = "glutin")))]
+    unsafe fn present<'a, W, Is, S, Iw>(
+        &mut self,
+        _swapchains: Is,
+        wait_semaphores: Iw,
+    ) -> Result<(), hal::PresentError>
+    where
+        W: 'a + Borrow<window::web::Swapchain>,
+        Is: IntoIterator<Item = (&'a W, hal::SwapImageIndex)>,
+        S: 'a + Borrow<native::Semaphore>,
+        Iw: IntoIterator<Item = &'a S>,
+    {
+        for semaphore in wait_semaphores {
+            semaphore.borrow().wait();
+        }
+        // the browser presents the canvas once the frame callback returns
+        Ok(())
+    }
+
     fn wait_idle(&self) -> Result<(), error::HostExecutionError> {
         unsafe {
-            self.share.context.Finish();
+            gl_call!(self.share.context.Finish());
         }
         Ok(())
     }
@@ -0,0 +1,88 @@
+use gl;
+use native as n;
+use Share;
+
+use std::ptr;
+
+/// Number of pixel buffers cycled through by a `PixelBufferRing`.
+const RING_SIZE: usize = 3;
+
+struct Slot {
+    buffer: n::RawBuffer,
+    capacity: usize,
+    // Signalled once the GPU is done with the contents of the buffer.
+    sync: gl::types::GLsync,
+}
+
+/// Ring of pixel buffer objects for streaming texel data.
+///
+/// Each buffer is only reused once the GPU has consumed its previous contents,
+/// so writing into it doesn't stall on the transfers still in flight.
+pub(crate) struct PixelBufferRing {
+    target: gl::types::GLenum,
+    usage: gl::types::GLenum,
+    slots: Vec<Slot>,
+    next: usize,
+}
+
+impl PixelBufferRing {
+    pub(crate) fn new(target: gl::types::GLenum, usage: gl::types::GLenum) -> Self {
+        PixelBufferRing {
+            target,
+            usage,
+            slots: Vec::with_capacity(RING_SIZE),
+            next: 0,
+        }
+    }
+
+    /// Acquire the next buffer of the ring with room for at least `size` bytes,
+    /// and leave it bound to the ring target.
+    pub(crate) unsafe fn acquire(&mut self, share: &Share, size: usize) -> n::RawBuffer {
+        let gl = &share.context;
+        if self.slots.len() <= self.next {
+            let mut buffer = 0;
+            gl_call!(gl.GenBuffers(1, &mut buffer));
+            self.slots.push(Slot {
+                buffer,
+                capacity: 0,
+                sync: ptr::null(),
+            });
+        }
+
+        let slot = &mut self.slots[self.next];
+        self.next = (self.next + 1) % RING_SIZE;
+
+        // Without sync objects, orphan the storage to let the driver do the tracking.
+        let mut orphan = !share.private_caps.sync;
+        if !slot.sync.is_null() {
+            if share.private_caps.sync_wait {
+                gl_call!(gl.ClientWaitSync(slot.sync, gl::SYNC_FLUSH_COMMANDS_BIT, !0));
+            } else {
+                // WebGL2 can only poll the status, orphan the storage still in use.
+                match gl_call!(gl.ClientWaitSync(slot.sync, gl::SYNC_FLUSH_COMMANDS_BIT, 0)) {
+                    gl::ALREADY_SIGNALED | gl::CONDITION_SATISFIED => {}
+                    _ => orphan = true,
+                }
+            }
+            gl_call!(gl.DeleteSync(slot.sync));
+            slot.sync = ptr::null();
+        }
+
+        gl_call!(gl.BindBuffer(self.target, slot.buffer));
+        if slot.capacity < size || orphan {
+            gl_call!(gl.BufferData(self.target, size as _, ptr::null(), self.usage));
+            slot.capacity = size;
+        }
+        slot.buffer
+    }
+
+    /// Guard the last acquired buffer until the GPU is done with the
+    /// commands issued so far.
+    pub(crate) unsafe fn release(&mut self, share: &Share) {
+        if share.private_caps.sync {
+            let index = (self.next + RING_SIZE - 1) % RING_SIZE;
+            self.slots[index].sync =
+                gl_call!(share.context.FenceSync(gl::SYNC_GPU_COMMANDS_COMPLETE, 0));
+        }
+    }
+}
@@ -1,10 +1,337 @@
 #![allow(dead_code)] //TODO: remove
 
+use hal::backend::FastHashMap;
 use hal::pso;
 use hal::ColorSlot;
 use smallvec::SmallVec;
 use {gl, GlContainer};
 
+use gl::types::{GLbitfield, GLenum, GLint, GLintptr, GLsizeiptr, GLuint};
+
+/// Shadow of the GL state applied by the command queue.
+///
+/// Every state is compared against its shadow before calling into GL, so
+/// re-applying what is already set doesn't cost a driver call. `None` denotes
+/// an unknown state, which is always applied.
+///
+/// The fixed function state is only ever changed by the queue, while the
+/// object bindings are also touched by the device, see `invalidate_bindings`.
+#[derive(Default)]
+pub(crate) struct StateCache {
+    rasterizer: Option<pso::Rasterizer>,
+    depth: Option<pso::DepthTest>,
+    stencil: Option<(
+        pso::StencilTest,
+        (pso::StencilValue, pso::StencilValue),
+        pso::Face,
+    )>,
+    blend_slots: Vec<Option<pso::ColorBlendDesc>>,
+    blend_color: Option<pso::ColorValue>,
+    logic_op: Option<Option<pso::LogicOp>>,
+    patch_size: Option<GLint>,
+    primitive_restart: Option<pso::PrimitiveRestart>,
+    sample_shading: Option<Option<f32>>,
+    alpha_coverage: Option<(bool, Option<bool>)>,
+    sample_mask: Option<GLbitfield>,
+    draw_buffers: Option<usize>,
+
+    program: Option<GLuint>,
+    // Keyed by framebuffer target, `FRAMEBUFFER` is split into its read and draw parts.
+    framebuffers: FastHashMap<GLenum, GLuint>,
+    // Keyed by texture unit.
+    textures: FastHashMap<GLuint, (GLenum, GLuint)>,
+    samplers: FastHashMap<GLuint, GLuint>,
+    buffer_ranges: FastHashMap<(GLenum, GLuint), (GLuint, GLintptr, GLsizeiptr)>,
+}
+
+impl StateCache {
+    /// Forget the complete state, e.g. after external GL calls.
+    pub(crate) fn invalidate(&mut self) {
+        *self = StateCache::default();
+    }
+
+    /// Forget the object bindings, which the device changes on its own
+    /// when creating and updating resources.
+    pub(crate) fn invalidate_bindings(&mut self) {
+        self.program = None;
+        self.draw_buffers = None;
+        self.framebuffers.clear();
+        self.invalidate_textures();
+        self.samplers.clear();
+        self.buffer_ranges.clear();
+    }
+
+    /// Forget the texture bindings, after binding textures directly.
+    pub(crate) fn invalidate_textures(&mut self) {
+        self.textures.clear();
+    }
+
+    pub(crate) fn bind_rasterizer(
+        &mut self,
+        gl: &GlContainer,
+        rasterizer: &pso::Rasterizer,
+        is_embedded: bool,
+        depth_clamp: bool,
+    ) {
+        if self.rasterizer.as_ref() != Some(rasterizer) {
+            bind_rasterizer(gl, rasterizer, is_embedded, depth_clamp);
+            self.rasterizer = Some(rasterizer.clone());
+        }
+    }
+
+    pub(crate) fn bind_depth(&mut self, gl: &GlContainer, depth: &pso::DepthTest) {
+        if self.depth != Some(*depth) {
+            bind_depth(gl, depth);
+            self.depth = Some(*depth);
+        }
+    }
+
+    pub(crate) fn bind_stencil(
+        &mut self,
+        gl: &GlContainer,
+        stencil: &pso::StencilTest,
+        refs: (pso::StencilValue, pso::StencilValue),
+        cull: pso::Face,
+    ) {
+        let state = (*stencil, refs, cull);
+        if self.stencil != Some(state) {
+            bind_stencil(gl, stencil, refs, Some(cull));
+            self.stencil = Some(state);
+        }
+    }
+
+    pub(crate) fn bind_blend_slot(
+        &mut self,
+        gl: &GlContainer,
+        slot: ColorSlot,
+        desc: &pso::ColorBlendDesc,
+        independent: bool,
+    ) {
+        let slot = slot as usize;
+        if !independent && slot != 0 {
+            // Without indexed blend states slot 0 applies to all the targets,
+            // which the pipeline creation ensures to be equal.
+            return;
+        }
+        if self.blend_slots.len() <= slot {
+            self.blend_slots.resize(slot + 1, None);
+        }
+        if self.blend_slots[slot].as_ref() != Some(desc) {
+            if independent {
+                bind_blend_slot(gl, slot as _, desc);
+            } else {
+                bind_blend(gl, desc);
+            }
+            self.blend_slots[slot] = Some(*desc);
+        }
+    }
+
+    pub(crate) fn set_blend_color(&mut self, gl: &GlContainer, color: pso::ColorValue) {
+        if self.blend_color != Some(color) {
+            set_blend_color(gl, color);
+            self.blend_color = Some(color);
+        }
+    }
+
+    /// Set the logic operation, `None` disables it and re-enables blending.
+    pub(crate) fn set_logic_op(&mut self, gl: &GlContainer, op: Option<pso::LogicOp>) {
+        if self.logic_op != Some(op) {
+            match op {
+                Some(op) => unsafe {
+                    gl_call!(gl.Enable(gl::COLOR_LOGIC_OP));
+                    gl_call!(gl.LogicOp(map_logic_op(op)));
+                },
+                None => unsafe { gl_call!(gl.Disable(gl::COLOR_LOGIC_OP)) },
+            }
+            self.logic_op = Some(op);
+        }
+    }
+
+    pub(crate) fn set_patch_size(&mut self, gl: &GlContainer, size: GLint) {
+        if self.patch_size != Some(size) {
+            unsafe { gl_call!(gl.PatchParameteri(gl::PATCH_VERTICES, size)) };
+            self.patch_size = Some(size);
+        }
+    }
+
+    /// Set the primitive restart index, preferring the fixed index if available.
+    pub(crate) fn set_primitive_restart(
+        &mut self,
+        gl: &GlContainer,
+        restart: pso::PrimitiveRestart,
+        fixed_index: bool,
+    ) {
+        if self.primitive_restart != Some(restart) {
+            let cap = if fixed_index {
+                gl::PRIMITIVE_RESTART_FIXED_INDEX
+            } else {
+                gl::PRIMITIVE_RESTART
+            };
+            let index = match restart {
+                pso::PrimitiveRestart::Disabled => None,
+                pso::PrimitiveRestart::U16 => Some(0xFFFF),
+                pso::PrimitiveRestart::U32 => Some(!0),
+            };
+            match index {
+                Some(index) => unsafe {
+                    gl_call!(gl.Enable(cap));
+                    if !fixed_index {
+                        gl_call!(gl.PrimitiveRestartIndex(index));
+                    }
+                },
+                None => unsafe { gl_call!(gl.Disable(cap)) },
+            }
+            self.primitive_restart = Some(restart);
+        }
+    }
+
+    /// Set the minimum fraction of samples shaded individually, `None` shades once per pixel.
+    pub(crate) fn set_sample_shading(&mut self, gl: &GlContainer, shading: Option<f32>) {
+        if self.sample_shading != Some(shading) {
+            match shading {
+                Some(min) => unsafe {
+                    gl_call!(gl.Enable(gl::SAMPLE_SHADING));
+                    gl_call!(gl.MinSampleShading(min));
+                },
+                None => unsafe { gl_call!(gl.Disable(gl::SAMPLE_SHADING)) },
+            }
+            self.sample_shading = Some(shading);
+        }
+    }
+
+    /// Toggle alpha-to-coverage and alpha-to-one, `to_one` is `None` if the latter is unsupported.
+    pub(crate) fn set_alpha_coverage(
+        &mut self,
+        gl: &GlContainer,
+        to_coverage: bool,
+        to_one: Option<bool>,
+    ) {
+        let value = (to_coverage, to_one);
+        if self.alpha_coverage != Some(value) {
+            match to_coverage {
+                true => unsafe { gl_call!(gl.Enable(gl::SAMPLE_ALPHA_TO_COVERAGE)) },
+                false => unsafe { gl_call!(gl.Disable(gl::SAMPLE_ALPHA_TO_COVERAGE)) },
+            }
+            match to_one {
+                Some(true) => unsafe { gl_call!(gl.Enable(gl::SAMPLE_ALPHA_TO_ONE)) },
+                Some(false) => unsafe { gl_call!(gl.Disable(gl::SAMPLE_ALPHA_TO_ONE)) },
+                None => {}
+            }
+            self.alpha_coverage = Some(value);
+        }
+    }
+
+    /// Set the coverage mask of the first 32 samples, a full mask disables masking.
+    pub(crate) fn set_sample_mask(&mut self, gl: &GlContainer, mask: GLbitfield) {
+        if self.sample_mask != Some(mask) {
+            unsafe {
+                if mask == !0 {
+                    gl_call!(gl.Disable(gl::SAMPLE_MASK));
+                } else {
+                    gl_call!(gl.Enable(gl::SAMPLE_MASK));
+                    gl_call!(gl.SampleMaski(0, mask));
+                }
+            }
+            self.sample_mask = Some(mask);
+        }
+    }
+
+    pub(crate) fn bind_draw_color_buffers(&mut self, gl: &GlContainer, num: usize) {
+        if self.draw_buffers != Some(num) {
+            bind_draw_color_buffers(gl, num);
+            self.draw_buffers = Some(num);
+        }
+    }
+
+    /// Set an explicit list of draw buffers, which are only tracked if they
+    /// match the leading color attachments.
+    pub(crate) fn set_draw_buffers(&mut self, gl: &GlContainer, draw_buffers: &[GLenum]) {
+        unsafe { gl_call!(gl.DrawBuffers(draw_buffers.len() as _, draw_buffers.as_ptr())) };
+        let sequential = draw_buffers
+            .iter()
+            .enumerate()
+            .all(|(i, &buffer)| buffer == gl::COLOR_ATTACHMENT0 + i as u32);
+        self.draw_buffers = if sequential {
+            Some(draw_buffers.len())
+        } else {
+            None
+        };
+    }
+
+    pub(crate) fn bind_program(&mut self, gl: &GlContainer, program: GLuint) {
+        if self.program != Some(program) {
+            unsafe { gl_call!(gl.UseProgram(program)) };
+            self.program = Some(program);
+        }
+    }
+
+    pub(crate) fn bind_framebuffer(
+        &mut self,
+        gl: &GlContainer,
+        point: GLenum,
+        framebuffer: GLuint,
+    ) {
+        let points: &[GLenum] = match point {
+            gl::FRAMEBUFFER => &[gl::READ_FRAMEBUFFER, gl::DRAW_FRAMEBUFFER],
+            _ => &[point],
+        };
+        if points
+            .iter()
+            .all(|point| self.framebuffers.get(point) == Some(&framebuffer))
+        {
+            return;
+        }
+
+        unsafe { gl_call!(gl.BindFramebuffer(point, framebuffer)) };
+        for &point in points {
+            self.framebuffers.insert(point, framebuffer);
+        }
+        if point != gl::READ_FRAMEBUFFER {
+            // The draw buffers are part of the framebuffer state.
+            self.draw_buffers = None;
+        }
+    }
+
+    pub(crate) fn bind_texture(
+        &mut self,
+        gl: &GlContainer,
+        unit: GLuint,
+        target: GLenum,
+        texture: GLuint,
+    ) {
+        if self.textures.get(&unit) != Some(&(target, texture)) {
+            unsafe {
+                gl_call!(gl.ActiveTexture(gl::TEXTURE0 + unit));
+                gl_call!(gl.BindTexture(target, texture));
+            }
+            self.textures.insert(unit, (target, texture));
+        }
+    }
+
+    pub(crate) fn bind_sampler(&mut self, gl: &GlContainer, unit: GLuint, sampler: GLuint) {
+        if self.samplers.get(&unit) != Some(&sampler) {
+            unsafe { gl_call!(gl.BindSampler(unit, sampler)) };
+            self.samplers.insert(unit, sampler);
+        }
+    }
+
+    pub(crate) fn bind_buffer_range(
+        &mut self,
+        gl: &GlContainer,
+        target: GLenum,
+        index: GLuint,
+        buffer: GLuint,
+        offset: GLintptr,
+        size: GLsizeiptr,
+    ) {
+        let range = (buffer, offset, size);
+        if self.buffer_ranges.get(&(target, index)) != Some(&range) {
+            unsafe { gl_call!(gl.BindBufferRange(target, index, buffer, offset, size)) };
+            self.buffer_ranges.insert((target, index), range);
+        }
+    }
+}
+
 pub(crate) fn bind_polygon_mode(
     gl: &GlContainer,
     mode: pso::PolygonMode,
@@ -15,62 +342,88 @@ pub(crate) fn bind_polygon_mode(
     let (gl_draw, gl_offset) = match mode {
         Point => (gl::POINT, gl::POLYGON_OFFSET_POINT),
         Line(width) => {
-            unsafe { gl.LineWidth(width) };
+            unsafe { gl_call!(gl.LineWidth(width)) };
             (gl::LINE, gl::POLYGON_OFFSET_LINE)
         }
         Fill => (gl::FILL, gl::POLYGON_OFFSET_FILL),
     };
 
-    unsafe { gl.PolygonMode(gl::FRONT_AND_BACK, gl_draw) };
+    unsafe { gl_call!(gl.PolygonMode(gl::FRONT_AND_BACK, gl_draw)) };
+    bind_depth_bias(gl, gl_offset, bias);
+}
 
+/// Toggle the polygon offset of the given mode, dynamic values are set with `set_depth_bias`.
+fn bind_depth_bias(
+    gl: &GlContainer,
+    offset_mode: gl::types::GLenum,
+    bias: Option<pso::State<pso::DepthBias>>,
+) {
     match bias {
         Some(pso::State::Static(bias)) => unsafe {
-            gl.Enable(gl_offset);
-            gl.PolygonOffset(bias.slope_factor as _, bias.const_factor as _);
+            gl_call!(gl.Enable(offset_mode));
+            set_depth_bias(gl, bias);
         },
-        _ => unsafe { gl.Disable(gl_offset) },
+        Some(pso::State::Dynamic) => unsafe { gl_call!(gl.Enable(offset_mode)) },
+        None => unsafe { gl_call!(gl.Disable(offset_mode)) },
     }
 }
 
-pub(crate) fn bind_rasterizer(gl: &GlContainer, r: &pso::Rasterizer, is_embedded: bool) {
+/// Set the polygon offset, the bias clamp is not supported.
+pub(crate) fn set_depth_bias(gl: &GlContainer, bias: pso::DepthBias) {
+    unsafe { gl_call!(gl.PolygonOffset(bias.slope_factor as _, bias.const_factor as _)) };
+}
+
+pub(crate) fn bind_rasterizer(
+    gl: &GlContainer,
+    r: &pso::Rasterizer,
+    is_embedded: bool,
+    depth_clamp: bool,
+) {
     use hal::pso::FrontFace::*;
 
     unsafe {
-        gl.FrontFace(match r.front_face {
+        gl_call!(gl.FrontFace(match r.front_face {
             Clockwise => gl::CW,
             CounterClockwise => gl::CCW,
-        })
+        }))
     };
 
     if !r.cull_face.is_empty() {
         unsafe {
-            gl.Enable(gl::CULL_FACE);
-            gl.CullFace(match r.cull_face {
+            gl_call!(gl.Enable(gl::CULL_FACE));
+            gl_call!(gl.CullFace(match r.cull_face {
                 pso::Face::FRONT => gl::FRONT,
                 pso::Face::BACK => gl::BACK,
                 _ => gl::FRONT_AND_BACK,
-            });
+            }));
         }
     } else {
         unsafe {
-            gl.Disable(gl::CULL_FACE);
+            gl_call!(gl.Disable(gl::CULL_FACE));
+        }
+    }
+
+    if depth_clamp {
+        match r.depth_clamping {
+            true => unsafe { gl_call!(gl.Enable(gl::DEPTH_CLAMP)) },
+            false => unsafe { gl_call!(gl.Disable(gl::DEPTH_CLAMP)) },
         }
     }
 
     if !is_embedded {
         bind_polygon_mode(gl, r.polygon_mode, r.depth_bias);
-        match false {
-            //TODO
-            true => unsafe { gl.Enable(gl::MULTISAMPLE) },
-            false => unsafe { gl.Disable(gl::MULTISAMPLE) },
-        }
+        // Only affects multisampled framebuffers, which only multisampled pipelines draw into.
+        unsafe { gl_call!(gl.Enable(gl::MULTISAMPLE)) };
+    } else {
+        // ES can't change the polygon mode, but still offsets filled polygons.
+        bind_depth_bias(gl, gl::POLYGON_OFFSET_FILL, r.depth_bias);
     }
 }
 
 pub(crate) fn bind_draw_color_buffers(gl: &GlContainer, num: usize) {
     let attachments: SmallVec<[gl::types::GLenum; 16]> =
         (0..num).map(|x| gl::COLOR_ATTACHMENT0 + x as u32).collect();
-    unsafe { gl.DrawBuffers(num as gl::types::GLint, attachments.as_ptr()) };
+    unsafe { gl_call!(gl.DrawBuffers(num as gl::types::GLint, attachments.as_ptr())) };
 }
 
 pub fn map_comparison(cmp: pso::Comparison) -> gl::types::GLenum {
@@ -90,12 +443,12 @@ pub fn map_comparison(cmp: pso::Comparison) -> gl::types::GLenum {
 pub(crate) fn bind_depth(gl: &GlContainer, depth: &pso::DepthTest) {
     match *depth {
         pso::DepthTest::On { fun, write } => unsafe {
-            gl.Enable(gl::DEPTH_TEST);
-            gl.DepthFunc(map_comparison(fun));
-            gl.DepthMask(write as _);
+            gl_call!(gl.Enable(gl::DEPTH_TEST));
+            gl_call!(gl.DepthFunc(map_comparison(fun)));
+            gl_call!(gl.DepthMask(write as _));
         },
         pso::DepthTest::Off => unsafe {
-            gl.Disable(gl::DEPTH_TEST);
+            gl_call!(gl.Disable(gl::DEPTH_TEST));
         },
     }
 }
@@ -135,14 +488,14 @@ pub(crate) fn bind_stencil(
                 pso::State::Static(v) => v,
                 pso::State::Dynamic => !0,
             };
-            gl.StencilFuncSeparate(face, map_comparison(side.fun), ref_value as _, mr);
-            gl.StencilMaskSeparate(face, mw);
-            gl.StencilOpSeparate(
+            gl_call!(gl.StencilFuncSeparate(face, map_comparison(side.fun), ref_value as _, mr));
+            gl_call!(gl.StencilMaskSeparate(face, mw));
+            gl_call!(gl.StencilOpSeparate(
                 face,
                 map_operation(side.op_fail),
                 map_operation(side.op_depth_fail),
                 map_operation(side.op_pass),
-            );
+            ));
         }
     }
     match *stencil {
@@ -150,7 +503,7 @@ pub(crate) fn bind_stencil(
             ref front,
             ref back,
         } => {
-            unsafe { gl.Enable(gl::STENCIL_TEST) };
+            unsafe { gl_call!(gl.Enable(gl::STENCIL_TEST)) };
             if let Some(cf) = cull {
                 if !cf.contains(pso::Face::FRONT) {
                     bind_side(gl, gl::FRONT, front, ref_front);
@@ -161,7 +514,7 @@ pub(crate) fn bind_stencil(
             }
         }
         pso::StencilTest::Off => unsafe {
-            gl.Disable(gl::STENCIL_TEST);
+            gl_call!(gl.Disable(gl::STENCIL_TEST));
         },
     }
 }
@@ -205,6 +558,28 @@ fn map_blend_op(
     }
 }
 
+fn map_logic_op(op: pso::LogicOp) -> gl::types::GLenum {
+    use hal::pso::LogicOp::*;
+    match op {
+        Clear => gl::CLEAR,
+        And => gl::AND,
+        AndReverse => gl::AND_REVERSE,
+        Copy => gl::COPY,
+        AndInverted => gl::AND_INVERTED,
+        NoOp => gl::NOOP,
+        Xor => gl::XOR,
+        Or => gl::OR,
+        Nor => gl::NOR,
+        Equivalent => gl::EQUIV,
+        Invert => gl::INVERT,
+        OrReverse => gl::OR_REVERSE,
+        CopyInverted => gl::COPY_INVERTED,
+        OrInverted => gl::OR_INVERTED,
+        Nand => gl::NAND,
+        Set => gl::SET,
+    }
+}
+
 pub(crate) fn bind_blend(gl: &GlContainer, desc: &pso::ColorBlendDesc) {
     use hal::pso::ColorMask as Cm;
 
@@ -212,22 +587,22 @@ pub(crate) fn bind_blend(gl: &GlContainer, desc: &pso::ColorBlendDesc) {
         pso::BlendState::On { color, alpha } => unsafe {
             let (color_eq, color_src, color_dst) = map_blend_op(color);
             let (alpha_eq, alpha_src, alpha_dst) = map_blend_op(alpha);
-            gl.Enable(gl::BLEND);
-            gl.BlendEquationSeparate(color_eq, alpha_eq);
-            gl.BlendFuncSeparate(color_src, color_dst, alpha_src, alpha_dst);
+            gl_call!(gl.Enable(gl::BLEND));
+            gl_call!(gl.BlendEquationSeparate(color_eq, alpha_eq));
+            gl_call!(gl.BlendFuncSeparate(color_src, color_dst, alpha_src, alpha_dst));
         },
         pso::BlendState::Off => unsafe {
-            gl.Disable(gl::BLEND);
+            gl_call!(gl.Disable(gl::BLEND));
         },
     };
 
     unsafe {
-        gl.ColorMask(
+        gl_call!(gl.ColorMask(
             desc.0.contains(Cm::RED) as _,
             desc.0.contains(Cm::GREEN) as _,
             desc.0.contains(Cm::BLUE) as _,
             desc.0.contains(Cm::ALPHA) as _,
-        )
+        ))
     };
 }
 
@@ -239,30 +614,32 @@ pub(crate) fn bind_blend_slot(gl: &GlContainer, slot: ColorSlot, desc: &pso::Col
             let (color_eq, color_src, color_dst) = map_blend_op(color);
             let (alpha_eq, alpha_src, alpha_dst) = map_blend_op(alpha);
             //Note: using ARB functions as they are more compatible
-            gl.Enablei(gl::BLEND, slot as _);
-            gl.BlendEquationSeparateiARB(slot as _, color_eq, alpha_eq);
-            gl.BlendFuncSeparateiARB(slot as _, color_src, color_dst, alpha_src, alpha_dst);
+            gl_call!(gl.Enablei(gl::BLEND, slot as _));
+            gl_call!(gl.BlendEquationSeparateiARB(slot as _, color_eq, alpha_eq));
+            gl_call!(
+                gl.BlendFuncSeparateiARB(slot as _, color_src, color_dst, alpha_src, alpha_dst)
+            );
         },
         pso::BlendState::Off => unsafe {
-            gl.Disablei(gl::BLEND, slot as _);
+            gl_call!(gl.Disablei(gl::BLEND, slot as _));
         },
     };
 
     unsafe {
-        gl.ColorMaski(
+        gl_call!(gl.ColorMaski(
             slot as _,
             desc.0.contains(Cm::RED) as _,
             desc.0.contains(Cm::GREEN) as _,
             desc.0.contains(Cm::BLUE) as _,
             desc.0.contains(Cm::ALPHA) as _,
-        )
+        ))
     };
 }
 
 pub(crate) fn unlock_color_mask(gl: &GlContainer) {
-    unsafe { gl.ColorMask(gl::TRUE, gl::TRUE, gl::TRUE, gl::TRUE) };
+    unsafe { gl_call!(gl.ColorMask(gl::TRUE, gl::TRUE, gl::TRUE, gl::TRUE)) };
 }
 
 pub(crate) fn set_blend_color(gl: &GlContainer, color: pso::ColorValue) {
-    unsafe { gl.BlendColor(color[0], color[1], color[2], color[3]) };
+    unsafe { gl_call!(gl.BlendColor(color[0], color[1], color[2], color[3])) };
 }
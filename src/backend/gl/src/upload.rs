@@ -0,0 +1,253 @@
+//! Uploads on a worker thread owning a secondary GL context.
+//!
+//! The worker context shares its objects with the context of the device, so
+//! buffers and textures can be filled without contending with the render thread.
+//! Every upload is followed by a sync object, which the device waits on before
+//! the resource gets used, see `Device::wait_upload`.
+
+use std::ptr;
+use std::sync::mpsc;
+use std::thread;
+
+use glutin::{self, GlContext};
+use hal::{buffer, image as i};
+
+use native as n;
+use {conv, gl};
+
+/// Sync object signalled once an upload is complete.
+pub(crate) struct UploadSync(pub(crate) gl::types::GLsync);
+
+// Only handed over to the render thread once the worker is done with it.
+unsafe impl Send for UploadSync {}
+
+struct SharedContext(glutin::Context);
+
+// The context is only ever made current on the worker thread.
+unsafe impl Send for SharedContext {}
+
+enum Job {
+    Buffer {
+        buffer: n::RawBuffer,
+        offset: buffer::Offset,
+        data: Vec<u8>,
+        done: mpsc::Sender<UploadSync>,
+    },
+    Texture {
+        texture: n::Texture,
+        target: gl::types::GLenum,
+        format: gl::types::GLenum,
+        ty: gl::types::GLenum,
+        level: i::Level,
+        offset: i::Offset,
+        extent: i::Extent,
+        data: Vec<u8>,
+        done: mpsc::Sender<UploadSync>,
+    },
+}
+
+/// Pending upload of an `UploadWorker`.
+pub struct UploadTicket {
+    pub(crate) done: mpsc::Receiver<UploadSync>,
+}
+
+/// Worker thread uploading buffer and texture data through a shared context.
+pub struct UploadWorker {
+    jobs: Option<mpsc::Sender<Job>>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl UploadWorker {
+    /// Spawn the worker for a context sharing its objects with the one of the
+    /// device, e.g. built with `ContextBuilder::with_shared_lists`.
+    pub fn new(context: glutin::Context) -> Self {
+        let context = SharedContext(context);
+        let (jobs, receiver) = mpsc::channel();
+        let thread = thread::Builder::new()
+            .name("gl-upload".into())
+            .spawn(move || {
+                let context = context;
+                unsafe { context.0.make_current().unwrap() };
+                let gl = gl::Gl::load_with(|s| context.0.get_proc_address(s) as *const _);
+                unsafe { gl.PixelStorei(gl::UNPACK_ALIGNMENT, 1) };
+                for job in receiver {
+                    unsafe { process(&gl, job) };
+                }
+            })
+            .expect("Failed to spawn the upload thread");
+
+        UploadWorker {
+            jobs: Some(jobs),
+            thread: Some(thread),
+        }
+    }
+
+    /// Queue writing `data` into the buffer at `offset`.
+    pub fn upload_buffer(
+        &self,
+        buffer: &n::Buffer,
+        offset: buffer::Offset,
+        data: Vec<u8>,
+    ) -> UploadTicket {
+        let (done, ticket) = mpsc::channel();
+        self.send(Job::Buffer {
+            buffer: buffer.raw,
+            offset,
+            data,
+            done,
+        });
+        UploadTicket { done: ticket }
+    }
+
+    /// Queue writing tightly packed texels of the image format into a region
+    /// of a texture level. The layers of array textures are addressed by
+    /// `offset.z` and `extent.depth`.
+    ///
+    /// Returns `None` for renderbuffers, cube maps, multisampled textures and
+    /// compressed formats, or if `data` doesn't cover the region.
+    pub fn upload_texture(
+        &self,
+        image: &n::Image,
+        level: i::Level,
+        offset: i::Offset,
+        extent: i::Extent,
+        data: Vec<u8>,
+    ) -> Option<UploadTicket> {
+        let texture = match image.kind {
+            n::ImageKind::Texture(texture) => texture,
+            n::ImageKind::Surface(_) => {
+                error!("Renderbuffers can't be uploaded to");
+                return None;
+            }
+        };
+        match image.target {
+            gl::TEXTURE_2D | gl::TEXTURE_2D_ARRAY | gl::TEXTURE_3D => {}
+            target => {
+                error!("Textures of target 0x{:x} can't be uploaded to", target);
+                return None;
+            }
+        }
+        let (format, ty) = match conv::image_format_to_gl(image.format) {
+            Some((_, format, ty)) => (format, ty),
+            None => {
+                error!("Textures of format {:?} can't be uploaded to", image.format);
+                return None;
+            }
+        };
+        if (data.len() as u64) < conv::image_data_size(image.format, extent) {
+            error!("Not enough data to upload into {:?}", extent);
+            return None;
+        }
+
+        let (done, ticket) = mpsc::channel();
+        self.send(Job::Texture {
+            texture,
+            target: image.target,
+            format,
+            ty,
+            level,
+            offset,
+            extent,
+            data,
+            done,
+        });
+        Some(UploadTicket { done: ticket })
+    }
+
+    fn send(&self, job: Job) {
+        if let Some(ref jobs) = self.jobs {
+            if jobs.send(job).is_err() {
+                error!("The upload thread has stopped");
+            }
+        }
+    }
+}
+
+impl Drop for UploadWorker {
+    fn drop(&mut self) {
+        // Closing the channel stops the worker once the queued uploads are done.
+        self.jobs.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+unsafe fn process(gl: &gl::Gl, job: Job) {
+    let done = match job {
+        Job::Buffer {
+            buffer,
+            offset,
+            data,
+            done,
+        } => {
+            gl.BindBuffer(gl::COPY_WRITE_BUFFER, buffer);
+            gl.BufferSubData(
+                gl::COPY_WRITE_BUFFER,
+                offset as _,
+                data.len() as _,
+                data.as_ptr() as *const _,
+            );
+            gl.BindBuffer(gl::COPY_WRITE_BUFFER, 0);
+            done
+        }
+        Job::Texture {
+            texture,
+            target,
+            format,
+            ty,
+            level,
+            offset,
+            extent,
+            data,
+            done,
+        } => {
+            gl.BindTexture(target, texture);
+            if target == gl::TEXTURE_2D {
+                gl.TexSubImage2D(
+                    target,
+                    level as _,
+                    offset.x,
+                    offset.y,
+                    extent.width as _,
+                    extent.height as _,
+                    format,
+                    ty,
+                    data.as_ptr() as *const _,
+                );
+            } else {
+                gl.TexSubImage3D(
+                    target,
+                    level as _,
+                    offset.x,
+                    offset.y,
+                    offset.z,
+                    extent.width as _,
+                    extent.height as _,
+                    extent.depth as _,
+                    format,
+                    ty,
+                    data.as_ptr() as *const _,
+                );
+            }
+            gl.BindTexture(target, 0);
+            done
+        }
+    };
+
+    let sync = if gl.FenceSync.is_loaded() {
+        let sync = gl.FenceSync(gl::SYNC_GPU_COMMANDS_COMPLETE, 0);
+        // The fence needs to reach the GPU before another context can wait on it.
+        gl.Flush();
+        sync
+    } else {
+        gl.Finish();
+        ptr::null()
+    };
+    // The ticket may have been dropped already, nobody is going to wait on the sync.
+    if let Err(mpsc::SendError(UploadSync(sync))) = done.send(UploadSync(sync)) {
+        if !sync.is_null() {
+            gl.DeleteSync(sync);
+        }
+    }
+}
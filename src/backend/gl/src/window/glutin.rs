@@ -45,7 +45,7 @@
 
 use hal::{self, format as f, image, CompositeAlpha};
 
-use {Backend as B, Device, PhysicalDevice, QueueFamily, Starc};
+use {native, Backend as B, Device, PhysicalDevice, QueueFamily, Starc};
 
 use glutin::{self, GlContext};
 
@@ -70,9 +70,12 @@ impl hal::Swapchain<B> for Swapchain {
     unsafe fn acquire_image(
         &mut self,
         _timeout_ns: u64,
-        _sync: hal::FrameSync<B>,
+        sync: hal::FrameSync<B>,
     ) -> Result<hal::SwapImageIndex, hal::AcquireError> {
-        // TODO: sync
+        // TODO: fence
+        if let hal::FrameSync::Semaphore(semaphore) = sync {
+            semaphore.signal();
+        }
         Ok(0)
     }
 }
@@ -168,7 +171,9 @@ impl Device {
         let swapchain = Swapchain {
             window: surface.window.clone(),
         };
-        let backbuffer = hal::Backbuffer::Framebuffer(0);
+        let backbuffer = hal::Backbuffer::Framebuffer(native::FatFrameBuffer::from_raw(
+            native::DEFAULT_FRAMEBUFFER,
+        ));
         (swapchain, backbuffer)
     }
 }
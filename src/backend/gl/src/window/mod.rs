@@ -1,2 +1,5 @@
 #[cfg(feature = "glutin")]
 pub mod glutin;
+
+#[cfg(all(target_os = "emscripten", not(feature = "glutin")))]
+pub mod web;
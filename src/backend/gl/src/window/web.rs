@@ -0,0 +1,130 @@
+//! Presentation to a WebGL2 canvas on the Emscripten target.
+//!
+//! The WebGL context is expected to be created by the application, for example
+//! with SDL2 or the Emscripten HTML5 API, and made current before the adapters
+//! are enumerated. The browser presents the canvas contents on its own once
+//! the frame callback returns, so there is nothing to swap.
+
+use hal::{self, format as f, image, CompositeAlpha};
+
+use {native, Backend as B, Device, PhysicalDevice, QueueFamily};
+
+use std::ffi::CString;
+use std::os::raw::{c_char, c_int, c_void};
+
+extern "C" {
+    fn emscripten_GetProcAddress(name: *const c_char) -> *const c_void;
+    fn emscripten_get_canvas_element_size(
+        target: *const c_char,
+        width: *mut c_int,
+        height: *mut c_int,
+    ) -> c_int;
+}
+
+pub struct Swapchain {
+    pub(crate) extent: image::Extent,
+}
+
+impl hal::Swapchain<B> for Swapchain {
+    unsafe fn acquire_image(
+        &mut self,
+        _timeout_ns: u64,
+        sync: hal::FrameSync<B>,
+    ) -> Result<hal::SwapImageIndex, hal::AcquireError> {
+        // TODO: fence
+        if let hal::FrameSync::Semaphore(semaphore) = sync {
+            semaphore.signal();
+        }
+        Ok(0)
+    }
+}
+
+pub struct Surface {
+    canvas: CString,
+}
+
+impl Surface {
+    /// Create a surface for the canvas matching the CSS selector, e.g. `"#canvas"`.
+    pub fn from_canvas(selector: &str) -> Self {
+        Surface {
+            canvas: CString::new(selector).expect("Invalid canvas selector"),
+        }
+    }
+
+    fn get_extent(&self) -> image::Extent {
+        let (mut width, mut height) = (0, 0);
+        unsafe {
+            emscripten_get_canvas_element_size(self.canvas.as_ptr(), &mut width, &mut height);
+        }
+        image::Extent {
+            width: width as image::Size,
+            height: height as image::Size,
+            depth: 1,
+        }
+    }
+}
+
+impl hal::Surface<B> for Surface {
+    fn kind(&self) -> hal::image::Kind {
+        let ex = self.get_extent();
+        hal::image::Kind::D2(ex.width, ex.height, 1, 1)
+    }
+
+    fn compatibility(
+        &self,
+        _: &PhysicalDevice,
+    ) -> (
+        hal::SurfaceCapabilities,
+        Option<Vec<f::Format>>,
+        Vec<hal::PresentMode>,
+    ) {
+        let ex = self.get_extent();
+        let extent = hal::window::Extent2D::from(ex);
+
+        let caps = hal::SurfaceCapabilities {
+            image_count: 2..3,
+            current_extent: Some(extent),
+            extents: extent..hal::window::Extent2D {
+                width: ex.width + 1,
+                height: ex.height + 1,
+            },
+            max_image_layers: 1,
+            usage: image::Usage::COLOR_ATTACHMENT | image::Usage::TRANSFER_SRC,
+            composite_alpha: CompositeAlpha::OPAQUE, //TODO
+        };
+        let present_modes = vec![hal::PresentMode::Fifo];
+
+        (caps, Some(vec![f::Format::Rgba8Unorm]), present_modes)
+    }
+
+    fn supports_queue_family(&self, _: &QueueFamily) -> bool {
+        true
+    }
+}
+
+impl Device {
+    pub(crate) fn create_swapchain_impl(
+        &self,
+        surface: &mut Surface,
+        _config: hal::SwapchainConfig,
+    ) -> (Swapchain, hal::Backbuffer<B>) {
+        let swapchain = Swapchain {
+            extent: surface.get_extent(),
+        };
+        let backbuffer = hal::Backbuffer::Framebuffer(native::FatFrameBuffer::from_raw(
+            native::DEFAULT_FRAMEBUFFER,
+        ));
+        (swapchain, backbuffer)
+    }
+}
+
+impl hal::Instance for Surface {
+    type Backend = B;
+    fn enumerate_adapters(&self) -> Vec<hal::Adapter<B>> {
+        let adapter = PhysicalDevice::new_adapter(|s| {
+            let name = CString::new(s).unwrap();
+            unsafe { emscripten_GetProcAddress(name.as_ptr()) }
+        });
+        vec![adapter]
+    }
+}
@@ -8,13 +8,15 @@ use {
 use std::borrow::Borrow;
 use std::cell::RefCell;
 use std::ops::{Deref, Range};
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::{cmp, iter, mem, slice, time};
 
 use hal::backend::FastHashMap;
+use hal::device::DeviceLost;
 use hal::format::{Aspects, FormatDesc};
-use hal::image::{Extent, Filter, Layout, Level, SubresourceRange};
-use hal::pass::AttachmentLoadOp;
+use hal::image::{Extent, Filter, Layer, Layout, Level, Offset, SubresourceRange};
+use hal::pass::{AttachmentLoadOp, AttachmentStoreOp, SubpassId};
 use hal::queue::{RawCommandQueue, Submission};
 use hal::range::RangeArg;
 use hal::{buffer, command as com, error, memory, pool, pso, query};
@@ -24,10 +26,12 @@ use hal::{
 };
 
 use block::ConcreteBlock;
-use cocoa::foundation::{NSRange, NSUInteger};
-use foreign_types::ForeignType;
+use cocoa::base::nil;
+use cocoa::foundation::{NSRange, NSString, NSUInteger};
+use foreign_types::{ForeignType, ForeignTypeRef};
 use metal::{self, MTLIndexType, MTLPrimitiveType, MTLScissorRect, MTLSize, MTLViewport};
 use objc::rc::autoreleasepool;
+use objc::runtime::Object;
 use parking_lot::Mutex;
 use smallvec::SmallVec;
 
@@ -178,6 +182,7 @@ struct RenderPipelineState {
 }
 
 struct SubpassInfo {
+    index: SubpassId,
     descriptor: metal::RenderPassDescriptor,
     combined_aspects: Aspects,
     formats: native::SubpassFormats,
@@ -214,6 +219,8 @@ struct State {
     target_formats: native::SubpassFormats,
     visibility_query: (metal::MTLVisibilityResultMode, buffer::Offset),
     pending_subpasses: Vec<SubpassInfo>,
+    /// Parallel pass slot of the current framebuffer, and whether it's taken by us.
+    parallel_pass_slot: Option<(Arc<Mutex<Option<native::ParallelPass>>>, bool)>,
 }
 
 impl State {
@@ -224,6 +231,73 @@ impl State {
         self.push_constants.clear();
         self.vertex_buffers.clear();
         self.pending_subpasses.clear();
+        if let Some((slot, true)) = self.parallel_pass_slot.take() {
+            slot.lock().take();
+        }
+    }
+
+    /// Share the parallel render encoder of the current subpass with the secondary
+    /// command buffers, unless the framebuffer is already used by another parallel pass.
+    fn publish_parallel_pass(
+        &mut self,
+        subpass: SubpassId,
+        encoder: &metal::ParallelRenderCommandEncoder,
+    ) {
+        if let Some((ref slot, ref mut is_taken)) = self.parallel_pass_slot {
+            let mut pass = slot.lock();
+            if pass.is_some() {
+                warn!("Framebuffer is used by another parallel pass, secondary command buffers will be replayed");
+                return;
+            }
+            *pass = Some(native::ParallelPass {
+                subpass,
+                encoder: encoder.clone(),
+                num_created: 0,
+                num_executed: 0,
+            });
+            *is_taken = true;
+        }
+    }
+
+    /// Stop sharing the parallel render encoder with the secondary command buffers.
+    fn retire_parallel_pass(&mut self) {
+        if let Some((ref slot, ref mut is_taken)) = self.parallel_pass_slot {
+            if mem::replace(is_taken, false) {
+                let pass = slot.lock().take().unwrap();
+                // Metal executes all the sub-encoders, so the secondary command buffers
+                // that were not executed would still render into the subpass.
+                assert_eq!(
+                    pass.num_created, pass.num_executed,
+                    "Secondary command buffers begun for a subpass have to be executed in it"
+                );
+            }
+        }
+    }
+
+    /// Account for the execution of a secondary command buffer in the parallel pass,
+    /// given the order of its sub-encoder creation, or `None` if it's created now.
+    fn execute_parallel_pass(&mut self, order: Option<usize>) {
+        let slot = match self.parallel_pass_slot {
+            Some((ref slot, true)) => slot,
+            _ => {
+                assert!(
+                    order.is_none(),
+                    "Secondary command buffer is encoded into a different pass"
+                );
+                return;
+            }
+        };
+        let mut guard = slot.lock();
+        let pass = guard.as_mut().unwrap();
+        let order = order.unwrap_or_else(|| {
+            pass.num_created += 1;
+            pass.num_created - 1
+        });
+        assert_eq!(
+            order, pass.num_executed,
+            "Secondary command buffers have to be executed in the order they were begun in"
+        );
+        pass.num_executed += 1;
     }
 
     fn clamp_scissor(sr: MTLScissorRect, extent: Extent) -> MTLScissorRect {
@@ -665,8 +739,14 @@ unsafe impl Send for SharedCommandBuffer {}
 
 #[cfg(feature = "dispatch")]
 impl EncodePass {
-    fn schedule(self, queue: &dispatch::Queue, cmd_buffer_arc: &Arc<Mutex<metal::CommandBuffer>>) {
+    fn schedule(
+        self,
+        queue: &dispatch::Queue,
+        cmd_buffer_arc: &Arc<Mutex<metal::CommandBuffer>>,
+        heap_fence: &Option<native::HeapFence>,
+    ) {
         let cmd_buffer = SharedCommandBuffer(Arc::clone(cmd_buffer_arc));
+        let heap_fence = heap_fence.clone();
         queue.async(move || match self {
             EncodePass::Render(list, desc) => {
                 let encoder = cmd_buffer
@@ -674,23 +754,41 @@ impl EncodePass {
                     .lock()
                     .new_render_command_encoder(&desc)
                     .to_owned();
+                if let Some(ref fence) = heap_fence {
+                    fence.wait_render(&encoder);
+                }
                 for command in list {
                     exec_render(&encoder, command);
                 }
+                if let Some(ref fence) = heap_fence {
+                    fence.update_render(&encoder);
+                }
                 encoder.end_encoding();
             }
             EncodePass::Compute(list) => {
                 let encoder = cmd_buffer.0.lock().new_compute_command_encoder().to_owned();
+                if let Some(ref fence) = heap_fence {
+                    fence.wait(&encoder);
+                }
                 for command in list {
                     exec_compute(&encoder, command);
                 }
+                if let Some(ref fence) = heap_fence {
+                    fence.update(&encoder);
+                }
                 encoder.end_encoding();
             }
             EncodePass::Blit(list) => {
                 let encoder = cmd_buffer.0.lock().new_blit_command_encoder().to_owned();
+                if let Some(ref fence) = heap_fence {
+                    fence.wait(&encoder);
+                }
                 for command in list {
                     exec_blit(&encoder, command);
                 }
+                if let Some(ref fence) = heap_fence {
+                    fence.update(&encoder);
+                }
                 encoder.end_encoding();
             }
         });
@@ -738,28 +836,50 @@ impl Journal {
         };
     }
 
-    fn record(&self, command_buf: &metal::CommandBufferRef) {
+    fn record(
+        &self,
+        command_buf: &metal::CommandBufferRef,
+        heap_fence: &Option<native::HeapFence>,
+    ) {
         for (ref pass, ref range) in &self.passes {
             match *pass {
                 soft::Pass::Render(ref desc) => {
                     let encoder = command_buf.new_render_command_encoder(desc);
+                    if let Some(ref fence) = *heap_fence {
+                        fence.wait_render(encoder);
+                    }
                     for command in &self.render_commands[range.clone()] {
                         exec_render(&encoder, command, &self.resources);
                     }
+                    if let Some(ref fence) = *heap_fence {
+                        fence.update_render(encoder);
+                    }
                     encoder.end_encoding();
                 }
                 soft::Pass::Blit => {
                     let encoder = command_buf.new_blit_command_encoder();
+                    if let Some(ref fence) = *heap_fence {
+                        fence.wait(encoder);
+                    }
                     for command in &self.blit_commands[range.clone()] {
                         exec_blit(&encoder, command);
                     }
+                    if let Some(ref fence) = *heap_fence {
+                        fence.update(encoder);
+                    }
                     encoder.end_encoding();
                 }
                 soft::Pass::Compute => {
                     let encoder = command_buf.new_compute_command_encoder();
+                    if let Some(ref fence) = *heap_fence {
+                        fence.wait(encoder);
+                    }
                     for command in &self.compute_commands[range.clone()] {
                         exec_compute(&encoder, command, &self.resources);
                     }
+                    if let Some(ref fence) = *heap_fence {
+                        fence.update(encoder);
+                    }
                     encoder.end_encoding();
                 }
             }
@@ -816,12 +936,20 @@ enum CommandSink {
         token: Token,
         encoder_state: EncoderState,
         num_passes: usize,
+        heap_fence: Option<native::HeapFence>,
     },
     Deferred {
         is_encoding: bool,
         is_inheriting: bool,
         journal: Journal,
     },
+    /// Secondary command buffer encoding into a sub-encoder of the parallel pass
+    /// of a primary command buffer.
+    Parallel {
+        encoder_state: EncoderState,
+        order: usize,
+        heap_fence: Option<native::HeapFence>,
+    },
     #[cfg(feature = "dispatch")]
     Remote {
         queue: dispatch::Queue,
@@ -829,6 +957,7 @@ enum CommandSink {
         token: Token,
         pass: Option<EncodePass>,
         capacity: Capacity,
+        heap_fence: Option<native::HeapFence>,
     },
 }
 
@@ -924,9 +1053,10 @@ impl CommandSink {
         match *self {
             CommandSink::Immediate {
                 ref mut encoder_state,
+                ref heap_fence,
                 ..
             } => {
-                encoder_state.end();
+                encoder_state.end(heap_fence);
             }
             CommandSink::Deferred {
                 ref mut is_encoding,
@@ -936,17 +1066,25 @@ impl CommandSink {
                 *is_encoding = false;
                 journal.stop();
             }
+            CommandSink::Parallel {
+                ref mut encoder_state,
+                ref heap_fence,
+                ..
+            } => {
+                encoder_state.end(heap_fence);
+            }
             #[cfg(feature = "dispatch")]
             CommandSink::Remote {
                 ref queue,
                 ref cmd_buffer,
                 ref mut pass,
                 ref mut capacity,
+                ref heap_fence,
                 ..
             } => {
                 if let Some(pass) = pass.take() {
                     pass.update(capacity);
-                    pass.schedule(queue, cmd_buffer);
+                    pass.schedule(queue, cmd_buffer, heap_fence);
                 }
             }
         }
@@ -959,7 +1097,17 @@ impl CommandSink {
             CommandSink::Immediate {
                 encoder_state: EncoderState::Render(ref encoder),
                 ..
+            }
+            | CommandSink::Parallel {
+                encoder_state: EncoderState::Render(ref encoder),
+                ..
             } => PreRender::Immediate(encoder),
+            CommandSink::Immediate {
+                encoder_state: EncoderState::ParallelRender(_),
+                ..
+            } => panic!(
+                "Inline commands can't be recorded in a subpass with secondary buffers contents"
+            ),
             CommandSink::Deferred {
                 is_encoding: true,
                 ref mut journal,
@@ -989,10 +1137,14 @@ impl CommandSink {
                 ref cmd_buffer,
                 ref mut encoder_state,
                 ref mut num_passes,
+                ref heap_fence,
                 ..
             } => {
                 *num_passes += 1;
                 let encoder = cmd_buffer.new_render_command_encoder(&descriptor);
+                if let Some(ref fence) = *heap_fence {
+                    fence.wait_render(encoder);
+                }
                 *encoder_state = EncoderState::Render(encoder.to_owned());
                 PreRender::Immediate(encoder)
             }
@@ -1008,6 +1160,9 @@ impl CommandSink {
                     .push((pass, journal.render_commands.len()..0));
                 PreRender::Deferred(&mut journal.resources, &mut journal.render_commands)
             }
+            CommandSink::Parallel { .. } => {
+                panic!("Only render commands can be recorded into a parallel pass")
+            }
             #[cfg(feature = "dispatch")]
             CommandSink::Remote {
                 ref mut pass,
@@ -1024,6 +1179,30 @@ impl CommandSink {
         }
     }
 
+    /// Switch the active encoder to a parallel render one, which is then able
+    /// to host the contents of secondary command buffers, each in its own
+    /// sub-encoder. Sinks that are not immediate fall back to `switch_render`.
+    fn switch_parallel_render(&mut self, descriptor: metal::RenderPassDescriptor) -> PreRender {
+        self.stop_encoding();
+
+        match *self {
+            CommandSink::Immediate {
+                ref cmd_buffer,
+                ref mut encoder_state,
+                ref mut num_passes,
+                ..
+            } => {
+                *num_passes += 1;
+                let encoder = cmd_buffer.new_parallel_render_command_encoder(&descriptor);
+                *encoder_state = EncoderState::ParallelRender(encoder.to_owned());
+                // Primary command buffer is not allowed to record any commands here,
+                // and the secondary ones have to set up the whole state anyway.
+                PreRender::Void
+            }
+            _ => self.switch_render(descriptor),
+        }
+    }
+
     fn quick_render<'a, I>(
         &mut self,
         label: &str,
@@ -1062,11 +1241,15 @@ impl CommandSink {
                 ref cmd_buffer,
                 ref mut encoder_state,
                 ref mut num_passes,
+                ref heap_fence,
                 ..
             } => {
                 *num_passes += 1;
-                encoder_state.end();
+                encoder_state.end(heap_fence);
                 let encoder = cmd_buffer.new_blit_command_encoder();
+                if let Some(ref fence) = *heap_fence {
+                    fence.wait(encoder);
+                }
                 *encoder_state = EncoderState::Blit(encoder.to_owned());
                 PreBlit::Immediate(encoder)
             }
@@ -1086,6 +1269,9 @@ impl CommandSink {
                 }
                 PreBlit::Deferred(&mut journal.blit_commands)
             }
+            CommandSink::Parallel { .. } => {
+                panic!("Only render commands can be recorded into a parallel pass")
+            }
             #[cfg(feature = "dispatch")]
             CommandSink::Remote {
                 pass: Some(EncodePass::Blit(ref mut list)),
@@ -1097,11 +1283,12 @@ impl CommandSink {
                 ref cmd_buffer,
                 ref mut pass,
                 ref mut capacity,
+                ref heap_fence,
                 ..
             } => {
                 if let Some(pass) = pass.take() {
                     pass.update(capacity);
-                    pass.schedule(queue, cmd_buffer);
+                    pass.schedule(queue, cmd_buffer, heap_fence);
                 }
                 let mut list = Vec::with_capacity(capacity.blit);
                 *pass = Some(EncodePass::Blit(list));
@@ -1163,11 +1350,15 @@ impl CommandSink {
                 ref cmd_buffer,
                 ref mut encoder_state,
                 ref mut num_passes,
+                ref heap_fence,
                 ..
             } => {
                 *num_passes += 1;
-                encoder_state.end();
+                encoder_state.end(heap_fence);
                 let encoder = cmd_buffer.new_compute_command_encoder();
+                if let Some(ref fence) = *heap_fence {
+                    fence.wait(encoder);
+                }
                 *encoder_state = EncoderState::Compute(encoder.to_owned());
                 (PreCompute::Immediate(encoder), true)
             }
@@ -1192,6 +1383,9 @@ impl CommandSink {
                     switch,
                 )
             }
+            CommandSink::Parallel { .. } => {
+                panic!("Only render commands can be recorded into a parallel pass")
+            }
             #[cfg(feature = "dispatch")]
             CommandSink::Remote {
                 pass: Some(EncodePass::Compute(ref mut list)),
@@ -1203,11 +1397,12 @@ impl CommandSink {
                 ref cmd_buffer,
                 ref mut pass,
                 ref mut capacity,
+                ref heap_fence,
                 ..
             } => {
                 if let Some(pass) = pass.take() {
                     pass.update(capacity);
-                    pass.schedule(queue, cmd_buffer);
+                    pass.schedule(queue, cmd_buffer, heap_fence);
                 }
                 let mut list = Vec::with_capacity(capacity.compute);
                 *pass = Some(EncodePass::Compute(list));
@@ -1252,6 +1447,37 @@ pub struct CommandBufferInner {
     retained_buffers: Vec<metal::Buffer>,
     retained_textures: Vec<metal::Texture>,
     active_visibility_queries: Vec<query::Id>,
+    timestamps: Vec<PendingTimestamp>,
+    /// Host events to set or reset, in order, once the submission is completed.
+    host_events: Vec<(native::HostEvent, bool)>,
+}
+
+/// A timestamp query written by a command buffer, resolved upon its completion.
+#[derive(Clone, Debug)]
+struct PendingTimestamp {
+    pool: Arc<native::TimestampPool>,
+    id: query::Id,
+    /// If true, the timestamp refers to the start of the command buffer execution.
+    at_start: bool,
+}
+
+/// Make the command buffer resolve the given timestamps once it's completed.
+unsafe fn resolve_timestamps(cmd_buffer: &metal::CommandBufferRef, timestamps: Vec<PendingTimestamp>) {
+    if timestamps.is_empty() {
+        return;
+    }
+    let block = ConcreteBlock::new(move |cb: *mut ()| -> () {
+        let cb = cb as *mut Object;
+        let start: f64 = msg_send![cb, GPUStartTime];
+        let end: f64 = msg_send![cb, GPUEndTime];
+        for ts in &timestamps {
+            let seconds = if ts.at_start { start } else { end };
+            ts.pool.values.lock()[ts.id as usize] = Some((seconds * 1.0e9) as u64);
+            ts.pool.condvar.notify_all();
+        }
+    })
+    .copy();
+    msg_send![cmd_buffer, addCompletedHandler: block.deref() as *const _];
 }
 
 impl Drop for CommandBufferInner {
@@ -1268,9 +1494,10 @@ impl CommandBufferInner {
             Some(CommandSink::Immediate {
                 token,
                 mut encoder_state,
+                heap_fence,
                 ..
             }) => {
-                encoder_state.end();
+                encoder_state.end(&heap_fence);
                 shared.queue.lock().release(token);
             }
             Some(CommandSink::Deferred { mut journal, .. }) => {
@@ -1279,6 +1506,13 @@ impl CommandBufferInner {
                     self.backup_journal = Some(journal);
                 }
             }
+            Some(CommandSink::Parallel {
+                mut encoder_state,
+                heap_fence,
+                ..
+            }) => {
+                encoder_state.end(&heap_fence);
+            }
             #[cfg(feature = "dispatch")]
             Some(CommandSink::Remote {
                 token, capacity, ..
@@ -1293,11 +1527,60 @@ impl CommandBufferInner {
         self.retained_buffers.clear();
         self.retained_textures.clear();
         self.active_visibility_queries.clear();
+        self.timestamps.clear();
+        self.host_events.clear();
     }
 
     fn sink(&mut self) -> &mut CommandSink {
         self.sink.as_mut().unwrap()
     }
+
+    /// Issue a debug group or signpost into the immediately recorded command buffer.
+    /// Markers of deferred and remote command buffers are not recorded.
+    fn debug_marker(&mut self, marker: DebugMarker) {
+        let (cmd_buffer, encoder_state) = match *self.sink() {
+            CommandSink::Immediate {
+                ref cmd_buffer,
+                ref encoder_state,
+                ..
+            } => (cmd_buffer, encoder_state),
+            _ => return,
+        };
+        let cmd_buffer: &metal::CommandBufferRef = cmd_buffer;
+        unsafe {
+            match marker {
+                DebugMarker::Push(name) => {
+                    let label = NSString::alloc(nil).init_str(name);
+                    msg_send![cmd_buffer, pushDebugGroup: label];
+                    msg_send![label, release];
+                }
+                DebugMarker::Pop => {
+                    msg_send![cmd_buffer, popDebugGroup];
+                }
+                DebugMarker::Signpost(name) => {
+                    let label = NSString::alloc(nil).init_str(name);
+                    match encoder_state.raw() {
+                        Some(encoder) => {
+                            msg_send![encoder, insertDebugSignpost: label];
+                        }
+                        // Command buffers have no signposts, use an empty group instead.
+                        None => {
+                            msg_send![cmd_buffer, pushDebugGroup: label];
+                            msg_send![cmd_buffer, popDebugGroup];
+                        }
+                    }
+                    msg_send![label, release];
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+enum DebugMarker<'a> {
+    Push(&'a str),
+    Pop,
+    Signpost(&'a str),
 }
 
 #[derive(Debug)]
@@ -1305,20 +1588,44 @@ enum EncoderState {
     None,
     Blit(metal::BlitCommandEncoder),
     Render(metal::RenderCommandEncoder),
+    ParallelRender(metal::ParallelRenderCommandEncoder),
     Compute(metal::ComputeCommandEncoder),
 }
 
 impl EncoderState {
-    fn end(&mut self) {
+    fn raw(&self) -> Option<&metal::CommandEncoderRef> {
+        let encoder: &metal::CommandEncoderRef = match *self {
+            EncoderState::None => return None,
+            EncoderState::Blit(ref encoder) => encoder,
+            EncoderState::Render(ref encoder) => encoder,
+            EncoderState::ParallelRender(ref encoder) => encoder,
+            EncoderState::Compute(ref encoder) => encoder,
+        };
+        Some(encoder)
+    }
+
+    fn end(&mut self, heap_fence: &Option<native::HeapFence>) {
         match mem::replace(self, EncoderState::None) {
             EncoderState::None => {}
             EncoderState::Render(ref encoder) => {
+                if let Some(ref fence) = *heap_fence {
+                    fence.update_render(encoder);
+                }
+                encoder.end_encoding();
+            }
+            EncoderState::ParallelRender(ref encoder) => {
                 encoder.end_encoding();
             }
             EncoderState::Blit(ref encoder) => {
+                if let Some(ref fence) = *heap_fence {
+                    fence.update(encoder);
+                }
                 encoder.end_encoding();
             }
             EncoderState::Compute(ref encoder) => {
+                if let Some(ref fence) = *heap_fence {
+                    fence.update(encoder);
+                }
                 encoder.end_encoding();
             }
         }
@@ -1493,6 +1800,9 @@ where
         Cmd::BindPipeline(ref pipeline_state) => {
             encoder.set_render_pipeline_state(pipeline_state.borrow());
         }
+        Cmd::UseResource { resource, usage } => {
+            encoder.use_resource(resource.as_native(), usage);
+        }
         Cmd::Draw {
             primitive_type,
             ref vertices,
@@ -1593,6 +1903,17 @@ where
                 offset,
             );
         }
+        Cmd::ExecuteIndirect {
+            commands,
+            ref range,
+        } => {
+            let raw: &metal::ResourceRef = commands.as_native();
+            let range = NSRange {
+                location: range.start as _,
+                length: (range.end - range.start) as _,
+            };
+            unsafe { msg_send![encoder, executeCommandsInBuffer: raw withRange: range] }
+        }
     }
 }
 
@@ -1786,6 +2107,9 @@ where
         Cmd::BindPipeline(ref pipeline) => {
             encoder.set_compute_pipeline_state(pipeline.borrow());
         }
+        Cmd::UseResource { resource, usage } => {
+            encoder.use_resource(resource.as_native(), usage);
+        }
         Cmd::Dispatch { wg_size, wg_count } => {
             encoder.dispatch_thread_groups(wg_count, wg_size);
         }
@@ -1855,20 +2179,38 @@ impl CommandQueue {
         T: 'a + Borrow<native::Semaphore>,
         I: IntoIterator<Item = &'a T>,
     {
-        for semaphore in wait_semaphores {
-            let sem = semaphore.borrow();
-            if let Some(ref system) = sem.system {
-                system.wait(!0);
-            }
-            if let Some(swap_image) = sem.image_ready.lock().take() {
-                let start = time::Instant::now();
-                let count = swap_image.wait_until_ready();
-                if let Some(ref mut counters) = self.perf_counters {
-                    counters.frame_wait_count += count;
-                    counters.frame_wait_duration += start.elapsed();
+        let shared = Arc::clone(&self.shared);
+        autoreleasepool(|| {
+            // for the command buffer waiting on events
+            let queue = shared.queue.lock();
+            let mut event_waits = None::<&metal::CommandBufferRef>;
+            for semaphore in wait_semaphores {
+                let sem = semaphore.borrow();
+                if let Some(ref event) = sem.event {
+                    let cmd_buffer = event_waits.get_or_insert_with(|| {
+                        let cmd_buffer = queue.spawn_temp();
+                        cmd_buffer.set_label("wait");
+                        cmd_buffer
+                    });
+                    event.wait(cmd_buffer);
+                }
+                if let Some(ref system) = sem.system {
+                    system.wait(!0);
+                }
+                if let Some(swap_image) = sem.image_ready.lock().take() {
+                    let start = time::Instant::now();
+                    let count = swap_image.wait_until_ready();
+                    if let Some(ref mut counters) = self.perf_counters {
+                        counters.frame_wait_count += count;
+                        counters.frame_wait_duration += start.elapsed();
+                    }
                 }
             }
-        }
+            // the following command buffers of the queue are blocked until it's done
+            if let Some(cmd_buffer) = event_waits {
+                cmd_buffer.commit();
+            }
+        });
     }
 }
 
@@ -1877,7 +2219,8 @@ impl RawCommandQueue<Backend> for CommandQueue {
         &mut self,
         submit: Submission<Ic, Iw, Is>,
         fence: Option<&native::Fence>,
-    ) where
+    ) -> Result<(), DeviceLost>
+    where
         T: 'a + Borrow<CommandBuffer>,
         Ic: IntoIterator<Item = &'a T>,
         S: 'a + Borrow<native::Semaphore>,
@@ -1885,6 +2228,9 @@ impl RawCommandQueue<Backend> for CommandQueue {
         Is: IntoIterator<Item = &'a S>,
     {
         debug!("submitting with fence {:?}", fence);
+        if self.shared.device_lost.load(Ordering::Acquire) {
+            return Err(DeviceLost);
+        }
         let Submission {
             command_buffers,
             wait_semaphores,
@@ -1893,19 +2239,26 @@ impl RawCommandQueue<Backend> for CommandQueue {
         self.wait(wait_semaphores.into_iter().map(|(s, _)| s));
 
         const BLOCK_BUCKET: usize = 4;
-        let system_semaphores = signal_semaphores
-            .into_iter()
-            .filter_map(|sem| sem.borrow().system.clone())
-            .collect::<SmallVec<[_; BLOCK_BUCKET]>>();
+        let mut system_semaphores = SmallVec::<[_; BLOCK_BUCKET]>::new();
+        let mut event_semaphores = SmallVec::<[&native::Event; BLOCK_BUCKET]>::new();
+        let mut host_events = SmallVec::<[(native::HostEvent, bool); BLOCK_BUCKET]>::new();
+        for semaphore in signal_semaphores {
+            let sem = semaphore.borrow();
+            system_semaphores.extend(sem.system.clone());
+            event_semaphores.extend(sem.event.as_ref());
+        }
 
         #[allow(unused_mut)]
         let (mut num_immediate, mut num_deferred, mut num_remote) = (0, 0, 0);
-        let do_signal = fence.is_some() || !system_semaphores.is_empty();
+        let do_signal =
+            fence.is_some() || !system_semaphores.is_empty() || !event_semaphores.is_empty();
 
         autoreleasepool(|| {
             // for command buffers
             let cmd_queue = self.shared.queue.lock();
             let mut deferred_cmd_buffer = None::<&metal::CommandBufferRef>;
+            // timestamps of the command buffers that didn't need to be committed
+            let mut idle_timestamps = Vec::new();
 
             for buffer in command_buffers {
                 let mut inner = buffer.borrow().inner.borrow_mut();
@@ -1914,8 +2267,11 @@ impl RawCommandQueue<Backend> for CommandQueue {
                     ref mut retained_buffers,
                     ref mut retained_textures,
                     ref mut active_visibility_queries,
+                    ref mut timestamps,
+                    host_events: ref events,
                     ..
                 } = *inner;
+                host_events.extend(events.iter().cloned());
 
                 match *sink {
                     Some(CommandSink::Immediate {
@@ -1935,7 +2291,10 @@ impl RawCommandQueue<Backend> for CommandQueue {
                             if let Some(cb) = deferred_cmd_buffer.take() {
                                 cb.commit();
                             }
+                            resolve_timestamps(cmd_buffer, timestamps.drain(..).collect());
                             cmd_buffer.commit();
+                        } else {
+                            idle_timestamps.extend(timestamps.drain(..));
                         }
                     }
                     Some(CommandSink::Deferred { ref journal, .. }) => {
@@ -1950,12 +2309,15 @@ impl RawCommandQueue<Backend> for CommandQueue {
                                 cmd_buffer.set_label("deferred");
                                 cmd_buffer
                             });
-                            journal.record(&*cmd_buffer);
+                            journal.record(&*cmd_buffer, &self.shared.heap_fence);
+                            resolve_timestamps(cmd_buffer, timestamps.clone());
                             if self.stitch_deferred {
                                 deferred_cmd_buffer = Some(cmd_buffer);
                             } else {
                                 cmd_buffer.commit();
                             }
+                        } else {
+                            idle_timestamps.extend_from_slice(timestamps);
                         }
                     }
                     #[cfg(feature = "dispatch")]
@@ -1973,11 +2335,18 @@ impl RawCommandQueue<Backend> for CommandQueue {
                             shared_cb.0.lock().commit();
                         });
                     }
+                    Some(CommandSink::Parallel { .. }) => {
+                        panic!("Secondary command buffer can't be submitted")
+                    }
                     None => panic!("Command buffer not recorded for submission"),
                 }
             }
 
-            if do_signal || !self.active_visibility_queries.is_empty() {
+            if do_signal
+                || !self.active_visibility_queries.is_empty()
+                || !idle_timestamps.is_empty()
+                || !host_events.is_empty()
+            {
                 let free_buffers = self
                     .retained_buffers
                     .drain(..)
@@ -1995,12 +2364,18 @@ impl RawCommandQueue<Backend> for CommandQueue {
                         .collect::<SmallVec<[_; BLOCK_BUCKET]>>();
                     Some((Arc::clone(&self.shared), queries))
                 };
+                let shared = Arc::clone(&self.shared);
 
-                let block = ConcreteBlock::new(move |_cb: *mut ()| -> () {
+                let block = ConcreteBlock::new(move |cb: *mut ()| -> () {
+                    let _ = shared.check_status(metal::CommandBufferRef::from_ptr(cb as *mut _));
                     // signal the semaphores
                     for semaphore in &system_semaphores {
                         semaphore.signal();
                     }
+                    // flip the host events
+                    for &(ref event, value) in &host_events {
+                        event.0.store(value, Ordering::Release);
+                    }
                     // free all the manually retained resources
                     let _ = free_buffers;
                     let _ = free_textures;
@@ -2027,6 +2402,10 @@ impl RawCommandQueue<Backend> for CommandQueue {
                     cmd_buffer
                 });
                 msg_send![cmd_buffer, addCompletedHandler: block.deref() as *const _];
+                resolve_timestamps(cmd_buffer, idle_timestamps);
+                for event in &event_semaphores {
+                    event.signal(cmd_buffer);
+                }
                 cmd_buffer.commit();
 
                 if let Some(fence) = fence {
@@ -2049,35 +2428,63 @@ impl RawCommandQueue<Backend> for CommandQueue {
                 counters.signal_command_buffers += 1;
             }
         }
+        Ok(())
     }
 
     unsafe fn present<'a, W, Is, S, Iw>(
         &mut self,
         swapchains: Is,
         wait_semaphores: Iw,
-    ) -> Result<(), ()>
+    ) -> Result<(), hal::PresentError>
     where
         W: 'a + Borrow<window::Swapchain>,
         Is: IntoIterator<Item = (&'a W, SwapImageIndex)>,
         S: 'a + Borrow<native::Semaphore>,
         Iw: IntoIterator<Item = &'a S>,
     {
+        if self.shared.device_lost.load(Ordering::Acquire) {
+            return Err(hal::PresentError::DeviceLost(DeviceLost));
+        }
         self.wait(wait_semaphores);
 
         let queue = self.shared.queue.lock();
-        autoreleasepool(|| {
+        let mut frame_throttles = Vec::new();
+        let result = autoreleasepool(|| {
             let command_buffer = queue.raw.new_command_buffer();
             command_buffer.set_label("present");
             self.record_empty(command_buffer);
 
             for (swapchain, index) in swapchains {
                 debug!("presenting frame {}", index);
-                let drawable = swapchain.borrow().take_drawable(index)?;
+                let swapchain = swapchain.borrow();
+                frame_throttles.push(Arc::clone(&swapchain.frame_throttle));
+                // The drawable is gone once the layer has been resized.
+                let drawable = swapchain
+                    .take_drawable(index)
+                    .map_err(|()| hal::PresentError::OutOfDate)?;
                 command_buffer.present_drawable(&drawable);
             }
+
+            // let the swapchains acquire new frames once these are presented
+            let throttles = mem::replace(&mut frame_throttles, Vec::new());
+            let shared = Arc::clone(&self.shared);
+            let block = ConcreteBlock::new(move |cb: *mut ()| -> () {
+                let _ = shared.check_status(metal::CommandBufferRef::from_ptr(cb as *mut _));
+                for throttle in &throttles {
+                    throttle.signal();
+                }
+            })
+            .copy();
+            msg_send![command_buffer, addCompletedHandler: block.deref() as *const _];
             command_buffer.commit();
             Ok(())
-        })?;
+        });
+        if result.is_err() {
+            for throttle in &frame_throttles {
+                throttle.signal();
+            }
+            return result;
+        }
 
         if let Some(ref mut counters) = self.perf_counters {
             counters.frame += 1;
@@ -2136,6 +2543,8 @@ impl pool::RawCommandPool<Backend> for CommandPool {
             retained_buffers: Vec::new(),
             retained_textures: Vec::new(),
             active_visibility_queries: Vec::new(),
+            timestamps: Vec::new(),
+            host_events: Vec::new(),
         }));
         self.allocated.push(Arc::clone(&inner));
 
@@ -2177,6 +2586,7 @@ impl pool::RawCommandPool<Backend> for CommandPool {
                 target_formats: native::SubpassFormats::default(),
                 visibility_query: (metal::MTLVisibilityResultMode::Disabled, 0),
                 pending_subpasses: Vec::new(),
+                parallel_pass_slot: None,
             },
             temp: Temp {
                 clear_vertices: Vec::new(),
@@ -2209,6 +2619,47 @@ impl pool::RawCommandPool<Backend> for CommandPool {
 }
 
 impl CommandBuffer {
+    /// Make the indirect command buffer available for writing
+    /// by the compute kernels dispatched next.
+    pub fn prepare_indirect_commands(&mut self, commands: &native::IndirectCommandBuffer) {
+        let command = soft::ComputeCommand::UseResource {
+            resource: AsNative::from(&*commands.raw),
+            usage: metal::MTLResourceUsage::Write,
+        };
+        let mut inner = self.inner.borrow_mut();
+        let (mut pre, init) = inner.sink().switch_compute();
+        if init {
+            pre.issue_many(self.state.make_compute_commands());
+        }
+        pre.issue(command);
+    }
+
+    /// Execute a range of commands of an indirect command buffer
+    /// within the current render pass.
+    pub fn execute_indirect_commands(
+        &mut self,
+        commands: &native::IndirectCommandBuffer,
+        range: Range<u32>,
+    ) {
+        assert!(range.end <= commands.max_commands);
+        let resource = AsNative::from(&*commands.raw);
+        let list = [
+            soft::RenderCommand::UseResource {
+                resource,
+                usage: metal::MTLResourceUsage::Read,
+            },
+            soft::RenderCommand::ExecuteIndirect {
+                commands: resource,
+                range,
+            },
+        ];
+        self.inner
+            .borrow_mut()
+            .sink()
+            .pre_render()
+            .issue_many(list.iter().cloned());
+    }
+
     fn update_depth_stencil(&self) {
         let mut inner = self.inner.borrow_mut();
         let mut pre = inner.sink().pre_render();
@@ -2233,39 +2684,73 @@ impl com::RawCommandBuffer<Backend> for CommandBuffer {
         let mut inner = self.inner.borrow_mut();
         let can_immediate = inner.level == com::RawLevel::Primary &&
             flags.contains(com::CommandBufferFlags::ONE_TIME_SUBMIT);
-        let sink = match self.pool_shared.borrow_mut().online_recording {
-            OnlineRecording::Immediate if can_immediate => {
-                let (cmd_buffer, token) = self.shared.queue.lock().spawn();
-                CommandSink::Immediate {
-                    cmd_buffer,
-                    token,
-                    encoder_state: EncoderState::None,
-                    num_passes: 0,
-                }
+        // One-time secondary command buffers encode directly into the parallel pass
+        // of the subpass they are begun for, if it's being recorded.
+        let sub_encoder = match (info.framebuffer, info.subpass.as_ref()) {
+            (Some(framebuffer), Some(sp))
+                if inner.level == com::RawLevel::Secondary
+                    && flags.contains(com::CommandBufferFlags::ONE_TIME_SUBMIT) =>
+            {
+                framebuffer
+                    .parallel_pass
+                    .lock()
+                    .as_mut()
+                    .filter(|pass| pass.subpass == sp.index)
+                    .map(|pass| {
+                        pass.num_created += 1;
+                        let encoder = pass.encoder.render_command_encoder().to_owned();
+                        (encoder, pass.num_created - 1)
+                    })
             }
-            #[cfg(feature = "dispatch")]
-            OnlineRecording::Remote(_) if can_immediate => {
-                let (cmd_buffer, token) = self.shared.queue.lock().spawn();
-                CommandSink::Remote {
-                    queue: dispatch::Queue::with_target_queue(
-                        "gfx-metal",
-                        dispatch::QueueAttribute::Serial,
-                        self.pool_shared
-                            .borrow_mut()
-                            .dispatch_queue
-                            .as_ref()
-                            .unwrap(),
-                    ),
-                    cmd_buffer: Arc::new(Mutex::new(cmd_buffer)),
-                    token,
-                    pass: None,
-                    capacity: inner.backup_capacity.take().unwrap_or_default(),
+            _ => None,
+        };
+        let sink = match sub_encoder {
+            Some((encoder, order)) => {
+                if let Some(ref fence) = self.shared.heap_fence {
+                    fence.wait_render(&encoder);
+                }
+                CommandSink::Parallel {
+                    encoder_state: EncoderState::Render(encoder),
+                    order,
+                    heap_fence: self.shared.heap_fence.clone(),
                 }
             }
-            _ => CommandSink::Deferred {
-                is_encoding: false,
-                is_inheriting: info.subpass.is_some(),
-                journal: inner.backup_journal.take().unwrap_or_default(),
+            None => match self.pool_shared.borrow_mut().online_recording {
+                OnlineRecording::Immediate if can_immediate => {
+                    let (cmd_buffer, token) = self.shared.queue.lock().spawn();
+                    CommandSink::Immediate {
+                        cmd_buffer,
+                        token,
+                        encoder_state: EncoderState::None,
+                        num_passes: 0,
+                        heap_fence: self.shared.heap_fence.clone(),
+                    }
+                }
+                #[cfg(feature = "dispatch")]
+                OnlineRecording::Remote(_) if can_immediate => {
+                    let (cmd_buffer, token) = self.shared.queue.lock().spawn();
+                    CommandSink::Remote {
+                        queue: dispatch::Queue::with_target_queue(
+                            "gfx-metal",
+                            dispatch::QueueAttribute::Serial,
+                            self.pool_shared
+                                .borrow_mut()
+                                .dispatch_queue
+                                .as_ref()
+                                .unwrap(),
+                        ),
+                        cmd_buffer: Arc::new(Mutex::new(cmd_buffer)),
+                        token,
+                        pass: None,
+                        capacity: inner.backup_capacity.take().unwrap_or_default(),
+                        heap_fence: self.shared.heap_fence.clone(),
+                    }
+                }
+                _ => CommandSink::Deferred {
+                    is_encoding: false,
+                    is_inheriting: info.subpass.is_some(),
+                    journal: inner.backup_journal.take().unwrap_or_default(),
+                },
             },
         };
         inner.sink = Some(sink);
@@ -2293,6 +2778,7 @@ impl com::RawCommandBuffer<Backend> for CommandBuffer {
                     let pass_desc = metal::RenderPassDescriptor::new().to_owned();
                     journal.passes.push((soft::Pass::Render(pass_desc), 0..0));
                 }
+                Some(CommandSink::Parallel { .. }) => {}
                 _ => unreachable!()
             }
         }
@@ -2317,6 +2803,33 @@ impl com::RawCommandBuffer<Backend> for CommandBuffer {
     ) where
         T: IntoIterator,
         T::Item: Borrow<memory::Barrier<'a, Backend>>,
+    {
+        // Metal tracks the hazards of regular resources on its own. Untracked ones,
+        // i.e. placed in native heaps or created with hazard tracking turned off,
+        // are ordered by the heap fence, which every encoder waits for and updates.
+    }
+
+    unsafe fn set_event(&mut self, event: &native::HostEvent, _: pso::PipelineStage) {
+        // Hazards are tracked the same way as for `pipeline_barrier`.
+        self.inner
+            .borrow_mut()
+            .host_events
+            .push((event.clone(), true));
+    }
+
+    unsafe fn reset_event(&mut self, event: &native::HostEvent, _: pso::PipelineStage) {
+        self.inner
+            .borrow_mut()
+            .host_events
+            .push((event.clone(), false));
+    }
+
+    unsafe fn wait_events<'a, I, J>(&mut self, _: I, _: Range<pso::PipelineStage>, _: J)
+    where
+        I: IntoIterator,
+        I::Item: Borrow<native::HostEvent>,
+        J: IntoIterator,
+        J::Item: Borrow<memory::Barrier<'a, Backend>>,
     {
     }
 
@@ -2551,10 +3064,19 @@ impl com::RawCommandBuffer<Backend> for CommandBuffer {
         let de = self.state.target_extent;
         let vertices = &mut self.temp.clear_vertices;
         vertices.clear();
+        // without layered rendering, all the vertices end up in the first layer
+        let max_layers = if self.shared.private_caps.layered_rendering {
+            de.depth as Layer
+        } else {
+            1
+        };
 
         for rect in rects {
             let r = rect.borrow();
-            for layer in r.layers.clone() {
+            if r.layers.end > max_layers {
+                warn!("Unable to clear layers {:?} of the attachments", max_layers..r.layers.end);
+            }
+            for layer in r.layers.start..cmp::min(r.layers.end, max_layers) {
                 let data = [
                     [r.rect.x, r.rect.y],
                     [r.rect.x, r.rect.y + r.rect.h],
@@ -2756,16 +3278,60 @@ impl com::RawCommandBuffer<Backend> for CommandBuffer {
 
     unsafe fn resolve_image<T>(
         &mut self,
-        _src: &native::Image,
+        src: &native::Image,
         _src_layout: Layout,
-        _dst: &native::Image,
+        dst: &native::Image,
         _dst_layout: Layout,
-        _regions: T,
+        regions: T,
     ) where
         T: IntoIterator,
         T::Item: Borrow<com::ImageResolve>,
     {
-        unimplemented!()
+        debug_assert!(src.mtl_format == dst.mtl_format);
+        debug_assert!(src.kind.num_samples() > 1 && dst.kind.num_samples() == 1);
+
+        let mut inner = self.inner.borrow_mut();
+        let src_raw = src.like.as_texture();
+        let dst_raw = dst.like.as_texture();
+
+        autoreleasepool(|| {
+            for region in regions {
+                let r = region.borrow();
+                // Resolve attachments always cover the whole render target.
+                let full_extent = src.kind.extent().at_level(r.src_subresource.level);
+                if r.src_offset != Offset::ZERO
+                    || r.dst_offset != Offset::ZERO
+                    || r.extent.width != full_extent.width
+                    || r.extent.height != full_extent.height
+                {
+                    warn!("Partial image resolves are not supported, resolving the whole level");
+                }
+
+                let layers = r
+                    .src_subresource
+                    .layers
+                    .clone()
+                    .zip(r.dst_subresource.layers.clone());
+                for (src_layer, dst_layer) in layers {
+                    let descriptor = metal::RenderPassDescriptor::new().to_owned();
+                    {
+                        let attachment = descriptor.color_attachments().object_at(0).unwrap();
+                        attachment.set_texture(Some(src_raw));
+                        attachment.set_level(r.src_subresource.level as _);
+                        attachment.set_slice(src_layer as _);
+                        attachment.set_resolve_texture(Some(dst_raw));
+                        attachment.set_resolve_level(r.dst_subresource.level as _);
+                        attachment.set_resolve_slice(dst_layer as _);
+                        attachment.set_load_action(metal::MTLLoadAction::Load);
+                        attachment.set_store_action(metal::MTLStoreAction::MultisampleResolve);
+                    }
+
+                    inner
+                        .sink()
+                        .quick_render("resolve_image", descriptor, iter::empty());
+                }
+            }
+        });
     }
 
     unsafe fn blit_image<T>(
@@ -3175,10 +3741,11 @@ impl com::RawCommandBuffer<Backend> for CommandBuffer {
 
         self.state.pending_subpasses.clear();
         self.state.target_extent = framebuffer.extent;
+        self.state.parallel_pass_slot = Some((Arc::clone(&framebuffer.parallel_pass), false));
 
         //TODO: cache produced `RenderPassDescriptor` objects
         // we stack the subpasses in the opposite order
-        for subpass in render_pass.subpasses.iter().rev() {
+        for (index, subpass) in render_pass.subpasses.iter().enumerate().rev() {
             let mut combined_aspects = Aspects::empty();
             let descriptor = metal::RenderPassDescriptor::new().to_owned();
             descriptor.set_visibility_result_buffer(Some(&self.shared.visibility.buffer));
@@ -3201,9 +3768,27 @@ impl com::RawCommandBuffer<Backend> for CommandBuffer {
                         let raw = self.temp.clear_values[at_id].unwrap().color;
                         desc.set_clear_color(channel.interpret(raw));
                     }
+                } else {
+                    desc.set_load_action(metal::MTLLoadAction::Load);
                 }
-                if op_flags.contains(native::SubpassOps::STORE) {
-                    desc.set_store_action(conv::map_store_operation(rat.ops.store));
+
+                // attachments used by the following sub-passes have to be stored
+                let store_op = if op_flags.contains(native::SubpassOps::STORE) {
+                    rat.ops.store
+                } else {
+                    AttachmentStoreOp::Store
+                };
+                match subpass.resolves.get(i) {
+                    Some(&resolve_id) => {
+                        desc.set_resolve_texture(Some(&framebuffer.attachments[resolve_id]));
+                        desc.set_store_action(conv::map_resolved_store_operation(
+                            store_op,
+                            self.shared.private_caps.store_and_resolve,
+                        ));
+                    }
+                    None => {
+                        desc.set_store_action(conv::map_store_operation(store_op));
+                    }
                 }
             }
 
@@ -3223,9 +3808,13 @@ impl com::RawCommandBuffer<Backend> for CommandBuffer {
                             let raw = self.temp.clear_values[at_id].unwrap().depth_stencil;
                             desc.set_clear_depth(raw.depth as f64);
                         }
+                    } else {
+                        desc.set_load_action(metal::MTLLoadAction::Load);
                     }
                     if op_flags.contains(native::SubpassOps::STORE) {
                         desc.set_store_action(conv::map_store_operation(rat.ops.store));
+                    } else {
+                        desc.set_store_action(metal::MTLStoreAction::Store);
                     }
                 }
                 if aspects.contains(Aspects::STENCIL) {
@@ -3238,14 +3827,19 @@ impl com::RawCommandBuffer<Backend> for CommandBuffer {
                             let raw = self.temp.clear_values[at_id].unwrap().depth_stencil;
                             desc.set_clear_stencil(raw.stencil);
                         }
+                    } else {
+                        desc.set_load_action(metal::MTLLoadAction::Load);
                     }
                     if op_flags.contains(native::SubpassOps::STORE) {
                         desc.set_store_action(conv::map_store_operation(rat.stencil_ops.store));
+                    } else {
+                        desc.set_store_action(metal::MTLStoreAction::Store);
                     }
                 }
             }
 
             self.state.pending_subpasses.push(SubpassInfo {
+                index,
                 descriptor,
                 combined_aspects,
                 formats: subpass.target_formats.clone(),
@@ -3255,7 +3849,8 @@ impl com::RawCommandBuffer<Backend> for CommandBuffer {
         self.next_subpass(first_subpass_contents);
     }
 
-    unsafe fn next_subpass(&mut self, _contents: com::SubpassContents) {
+    unsafe fn next_subpass(&mut self, contents: com::SubpassContents) {
+        self.state.retire_parallel_pass();
         let sin = self.state.pending_subpasses.pop().unwrap();
 
         self.state.render_pso_is_compatible = match self.state.render_pso {
@@ -3287,14 +3882,32 @@ impl com::RawCommandBuffer<Backend> for CommandBuffer {
             .make_render_commands(sin.combined_aspects)
             .chain(com_ds);
 
-        self.inner
-            .borrow_mut()
-            .sink()
-            .switch_render(sin.descriptor)
-            .issue_many(init_commands);
+        let mut inner = self.inner.borrow_mut();
+        match contents {
+            com::SubpassContents::Inline => {
+                inner
+                    .sink()
+                    .switch_render(sin.descriptor)
+                    .issue_many(init_commands);
+            }
+            com::SubpassContents::SecondaryBuffers => {
+                inner
+                    .sink()
+                    .switch_parallel_render(sin.descriptor)
+                    .issue_many(init_commands);
+                if let CommandSink::Immediate {
+                    encoder_state: EncoderState::ParallelRender(ref encoder),
+                    ..
+                } = *inner.sink()
+                {
+                    self.state.publish_parallel_pass(sin.index, encoder);
+                }
+            }
+        }
     }
 
     unsafe fn end_render_pass(&mut self) {
+        self.state.retire_parallel_pass();
         self.inner.borrow_mut().sink().stop_encoding();
     }
 
@@ -3511,6 +4124,7 @@ impl com::RawCommandBuffer<Backend> for CommandBuffer {
                     ref raw,
                     offset,
                     stage_flags,
+                    ref resources,
                     ..
                 } => {
                     //Note: this is incompatible with the binding scheme below
@@ -3538,6 +4152,9 @@ impl com::RawCommandBuffer<Backend> for CommandBuffer {
                             offset,
                         });
                     }
+                    for &(resource, usage) in resources.lock().values() {
+                        pre.issue(soft::RenderCommand::UseResource { resource, usage });
+                    }
                 }
             }
         }
@@ -3653,6 +4270,7 @@ impl com::RawCommandBuffer<Backend> for CommandBuffer {
                     ref raw,
                     offset,
                     stage_flags,
+                    ref resources,
                     ..
                 } => {
                     if stage_flags.contains(pso::ShaderStageFlags::COMPUTE) {
@@ -3664,6 +4282,9 @@ impl com::RawCommandBuffer<Backend> for CommandBuffer {
                             buffer: AsNative::from(raw.as_ref()),
                             offset,
                         });
+                        for &(resource, usage) in resources.lock().values() {
+                            pre.issue(soft::ComputeCommand::UseResource { resource, usage });
+                        }
                     }
                 }
             }
@@ -4019,6 +4640,10 @@ impl com::RawCommandBuffer<Backend> for CommandBuffer {
         if instances.start == instances.end {
             return;
         }
+        if instances.start != 0 && !self.shared.private_caps.base_instance {
+            error!("Base instance is not supported");
+            return;
+        }
 
         let command = soft::RenderCommand::Draw {
             primitive_type: self.state.primitive_type,
@@ -4037,6 +4662,11 @@ impl com::RawCommandBuffer<Backend> for CommandBuffer {
         if instances.start == instances.end {
             return;
         }
+        // Base vertex and base instance come together in Metal.
+        if (base_vertex != 0 || instances.start != 0) && !self.shared.private_caps.base_instance {
+            error!("Base vertex and base instance are not supported");
+            return;
+        }
 
         let command = soft::RenderCommand::DrawIndexed {
             primitive_type: self.state.primitive_type,
@@ -4120,6 +4750,9 @@ impl com::RawCommandBuffer<Backend> for CommandBuffer {
                 let com = self.state.set_visibility_query(mode, offset);
                 self.inner.borrow_mut().sink().pre_render().issue(com);
             }
+            native::QueryPool::Timestamp(_) => {
+                error!("Timestamp queries can only be written");
+            }
         }
     }
 
@@ -4137,6 +4770,9 @@ impl com::RawCommandBuffer<Backend> for CommandBuffer {
                     .set_visibility_query(metal::MTLVisibilityResultMode::Disabled, 0);
                 inner.sink().pre_render().issue(com);
             }
+            native::QueryPool::Timestamp(_) => {
+                error!("Timestamp queries can only be written");
+            }
         }
     }
 
@@ -4172,6 +4808,15 @@ impl com::RawCommandBuffer<Backend> for CommandBuffer {
                 let commands = iter::once(command_data).chain(iter::once(command_meta));
                 inner.sink().blit_commands(commands);
             }
+            native::QueryPool::Timestamp(ref pool) => {
+                let mut inner = self.inner.borrow_mut();
+                inner.timestamps.retain(|ts| {
+                    !Arc::ptr_eq(&ts.pool, pool) || ts.id < queries.start || ts.id >= queries.end
+                });
+                for value in &mut pool.values.lock()[queries.start as usize..queries.end as usize] {
+                    *value = None;
+                }
+            }
         }
     }
 
@@ -4273,11 +4918,25 @@ impl com::RawCommandBuffer<Backend> for CommandBuffer {
                     self.inner.borrow_mut().sink().blit_commands(commands);
                 }
             }
+            native::QueryPool::Timestamp(_) => {
+                error!("Timestamp results are only available on the host");
+            }
         }
     }
 
-    unsafe fn write_timestamp(&mut self, _: pso::PipelineStage, _: query::Query<Backend>) {
-        // nothing to do, timestamps are unsupported on Metal
+    unsafe fn write_timestamp(&mut self, stage: pso::PipelineStage, query: query::Query<Backend>) {
+        match query.pool {
+            native::QueryPool::Occlusion(_) => {
+                error!("Occlusion queries can't be written as timestamps");
+            }
+            native::QueryPool::Timestamp(ref pool) => {
+                self.inner.borrow_mut().timestamps.push(PendingTimestamp {
+                    pool: Arc::clone(pool),
+                    id: query.id,
+                    at_start: stage == pso::PipelineStage::TOP_OF_PIPE,
+                });
+            }
+        }
     }
 
     unsafe fn push_graphics_constants(
@@ -4335,6 +4994,11 @@ impl com::RawCommandBuffer<Backend> for CommandBuffer {
                 Some(CommandSink::Deferred { ref journal, is_inheriting, .. }) => {
                     (journal, is_inheriting)
                 }
+                Some(CommandSink::Parallel { order, .. }) => {
+                    // the contents are already encoded into the parallel pass
+                    self.state.execute_parallel_pass(Some(order));
+                    continue;
+                }
                 _ => panic!("Unexpected secondary sink!"),
             };
 
@@ -4342,19 +5006,36 @@ impl com::RawCommandBuffer<Backend> for CommandBuffer {
                 .borrow_mut()
                 .sink()
             {
-                CommandSink::Immediate { ref mut cmd_buffer, ref mut encoder_state, ref mut num_passes, .. } => {
+                CommandSink::Immediate { ref mut cmd_buffer, ref mut encoder_state, ref mut num_passes, ref heap_fence, .. } => {
                     if is_inheriting {
-                        let encoder = match encoder_state {
-                            EncoderState::Render(ref encoder) => encoder,
+                        match *encoder_state {
+                            EncoderState::Render(ref encoder) => {
+                                for command in &exec_journal.render_commands {
+                                    exec_render(encoder, command, &exec_journal.resources);
+                                }
+                            }
+                            EncoderState::ParallelRender(ref parallel) => {
+                                // Sub-encoders are executed in the order of creation,
+                                // regardless of the order they finish encoding in.
+                                self.state.execute_parallel_pass(None);
+                                let encoder = parallel.render_command_encoder();
+                                if let Some(ref fence) = *heap_fence {
+                                    fence.wait_render(encoder);
+                                }
+                                for command in &exec_journal.render_commands {
+                                    exec_render(encoder, command, &exec_journal.resources);
+                                }
+                                if let Some(ref fence) = *heap_fence {
+                                    fence.update_render(encoder);
+                                }
+                                encoder.end_encoding();
+                            }
                             _ => panic!("Expected Render encoder!"),
-                        };
-                        for command in &exec_journal.render_commands {
-                            exec_render(encoder, command, &exec_journal.resources);
                         }
                     } else {
-                        encoder_state.end();
+                        encoder_state.end(heap_fence);
                         *num_passes += exec_journal.passes.len();
-                        exec_journal.record(cmd_buffer);
+                        exec_journal.record(cmd_buffer, heap_fence);
                     }
                 }
                 CommandSink::Deferred { ref mut journal, .. } => {
@@ -4365,4 +5046,20 @@ impl com::RawCommandBuffer<Backend> for CommandBuffer {
             }
         }
     }
+
+    unsafe fn begin_debug_marker(&mut self, name: &str, _color: u32) {
+        self.inner
+            .borrow_mut()
+            .debug_marker(DebugMarker::Push(name));
+    }
+
+    unsafe fn end_debug_marker(&mut self) {
+        self.inner.borrow_mut().debug_marker(DebugMarker::Pop);
+    }
+
+    unsafe fn insert_debug_marker(&mut self, name: &str, _color: u32) {
+        self.inner
+            .borrow_mut()
+            .debug_marker(DebugMarker::Signpost(name));
+    }
 }
@@ -63,6 +63,7 @@ impl PrivateCapabilities {
             f::Rgba32Float => RGBA32Float,
             f::D16Unorm => Depth16Unorm,
             f::D32Float => Depth32Float,
+            f::S8Uint => Stencil8,
             f::Bc1RgbaUnorm if self.format_bc => BC1_RGBA,
             f::Bc1RgbaSrgb if self.format_bc => BC1_RGBA_sRGB,
             f::Bc1RgbUnorm if self.format_bc => BC1_RGBA, //TODO?
@@ -872,6 +873,7 @@ impl PrivateCapabilities {
                 ..Properties::default()
             },
             Stencil8 => Properties {
+                optimal_tiling: depth_if,
                 ..Properties::default()
             },
             Depth24Unorm_Stencil8 if self.format_depth24_stencil8 => Properties {
@@ -921,6 +923,24 @@ pub fn map_store_operation(operation: pass::AttachmentStoreOp) -> MTLStoreAction
     }
 }
 
+/// Returns the store action for a multisampled attachment that is resolved
+/// at the end of the pass, and optionally stored as well.
+pub fn map_resolved_store_operation(
+    operation: pass::AttachmentStoreOp,
+    store_and_resolve: bool,
+) -> MTLStoreAction {
+    match operation {
+        pass::AttachmentStoreOp::Store if store_and_resolve => {
+            MTLStoreAction::StoreAndMultisampleResolve
+        }
+        pass::AttachmentStoreOp::Store => {
+            warn!("Storing the multisampled attachment is not supported along with the resolve");
+            MTLStoreAction::MultisampleResolve
+        }
+        pass::AttachmentStoreOp::DontCare => MTLStoreAction::MultisampleResolve,
+    }
+}
+
 pub fn map_write_mask(mask: pso::ColorMask) -> MTLColorWriteMask {
     let mut mtl_mask = MTLColorWriteMask::empty();
 
@@ -966,6 +986,22 @@ fn map_factor(factor: pso::Factor) -> MTLBlendFactor {
     }
 }
 
+/// Check if the blend operation reads the second output of the fragment shader.
+pub fn is_dual_source_blend_op(operation: &pso::BlendOp) -> bool {
+    use hal::pso::BlendOp::*;
+    use hal::pso::Factor::*;
+
+    match *operation {
+        Add { src, dst } | Sub { src, dst } | RevSub { src, dst } => {
+            [src, dst].iter().any(|factor| match *factor {
+                Src1Color | OneMinusSrc1Color | Src1Alpha | OneMinusSrc1Alpha => true,
+                _ => false,
+            })
+        }
+        Min | Max => false,
+    }
+}
+
 pub fn map_blend_op(
     operation: &pso::BlendOp,
 ) -> (MTLBlendOperation, MTLBlendFactor, MTLBlendFactor) {
@@ -1089,6 +1125,38 @@ pub fn map_texture_usage(usage: image::Usage, tiling: image::Tiling) -> MTLTextu
     texture_usage
 }
 
+/// Mirrors `MTLTextureSwizzleChannels`, which is not exposed by `metal-rs` yet.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TextureSwizzleChannels {
+    pub red: u8,
+    pub green: u8,
+    pub blue: u8,
+    pub alpha: u8,
+}
+
+pub fn map_swizzle(swizzle: Swizzle) -> TextureSwizzleChannels {
+    use hal::format::Component;
+
+    fn map_component(component: Component) -> u8 {
+        match component {
+            Component::Zero => 0,
+            Component::One => 1,
+            Component::R => 2,
+            Component::G => 3,
+            Component::B => 4,
+            Component::A => 5,
+        }
+    }
+
+    TextureSwizzleChannels {
+        red: map_component(swizzle.0),
+        green: map_component(swizzle.1),
+        blue: map_component(swizzle.2),
+        alpha: map_component(swizzle.3),
+    }
+}
+
 pub fn map_texture_type(view_kind: image::ViewKind) -> MTLTextureType {
     use hal::image::ViewKind as Vk;
     match view_kind {
@@ -1,4 +1,4 @@
-use internal::{Channel, FastStorageMap};
+use internal::{self, Channel, FastStorageMap};
 use native;
 use range_alloc::RangeAllocator;
 use {command, conversions as conv, native as n};
@@ -11,13 +11,18 @@ use std::borrow::Borrow;
 use std::cell::RefCell;
 use std::collections::hash_map::Entry;
 use std::collections::BTreeMap;
+use std::ffi::CStr;
 use std::ops::Range;
+use std::os::raw::c_char;
 use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use std::{cmp, iter, mem, ptr, slice, thread, time};
+use std::{cmp, iter, mem, ptr, thread, time};
 
+use hal::backend::FastHashMap;
 use hal::device::{
-    AllocationError, BindError, DeviceLost, OomOrDeviceLost, OutOfMemory, ShaderError,
+    AllocationError, BindError, DeviceLost, FramebufferError, OomOrDeviceLost, OutOfMemory,
+    ShaderError,
 };
 use hal::memory::Properties;
 use hal::pool::CommandPoolCreateFlags;
@@ -26,12 +31,14 @@ use hal::queue::{QueueFamilyId, Queues};
 use hal::range::RangeArg;
 use hal::{self, buffer, error, format, image, mapping, memory, pass, pso, query, window};
 
-use cocoa::foundation::{NSRange, NSUInteger};
+use cocoa::base::nil;
+use cocoa::foundation::{NSInteger, NSRange, NSString, NSUInteger};
 use foreign_types::ForeignType;
 use metal::{
     self, CaptureManager, MTLArgumentAccess, MTLCPUCacheMode, MTLDataType, MTLLanguageVersion,
-    MTLPrimitiveTopologyClass, MTLPrimitiveType, MTLResourceOptions, MTLSamplerBorderColor,
-    MTLSamplerMipFilter, MTLStorageMode, MTLTextureType, MTLVertexStepFunction,
+    MTLPrimitiveTopologyClass, MTLPrimitiveType, MTLResourceOptions, MTLResourceUsage,
+    MTLSamplerBorderColor, MTLSamplerMipFilter, MTLStorageMode, MTLTextureType,
+    MTLVertexStepFunction,
 };
 use objc::rc::autoreleasepool;
 use objc::runtime::{Object, BOOL, NO};
@@ -42,6 +49,12 @@ const PUSH_CONSTANTS_DESC_SET: u32 = !0;
 const PUSH_CONSTANTS_DESC_BINDING: u32 = 0;
 const STRIDE_GRANULARITY: pso::ElemStride = 4; //TODO: work around?
 
+#[link(name = "QuartzCore", kind = "framework")]
+extern "C" {
+    // Host time in seconds, the clock of `GPUStartTime` and `GPUEndTime`.
+    fn CACurrentMediaTime() -> f64;
+}
+
 /// Emit error during shader module parsing.
 fn gen_parse_error(err: SpirvErrorCode) -> ShaderError {
     let msg = match err {
@@ -51,6 +64,31 @@ fn gen_parse_error(err: SpirvErrorCode) -> ShaderError {
     ShaderError::CompilationFailed(msg)
 }
 
+/// Check the SPIR-V header and copy the code into properly aligned words.
+fn spirv_words(raw_data: &[u8]) -> Result<Vec<u32>, ShaderError> {
+    const SPIRV_MAGIC: u32 = 0x0723_0203;
+    const SPIRV_HEADER_WORDS: usize = 5;
+
+    // spec requires "codeSize must be a multiple of 4"
+    if raw_data.len() & 3 != 0 {
+        return Err(ShaderError::CompilationFailed(
+            "SPIR-V code size is not a multiple of 4".into(),
+        ));
+    }
+    let words = raw_data
+        .chunks(mem::size_of::<u32>())
+        .map(|c| {
+            u32::from(c[0]) | u32::from(c[1]) << 8 | u32::from(c[2]) << 16 | u32::from(c[3]) << 24
+        })
+        .collect::<Vec<_>>();
+    if words.len() < SPIRV_HEADER_WORDS || words[0] != SPIRV_MAGIC {
+        return Err(ShaderError::CompilationFailed(
+            "SPIR-V module has an invalid header".into(),
+        ));
+    }
+    Ok(words)
+}
+
 #[derive(Clone, Debug)]
 enum FunctionError {
     InvalidEntryPoint,
@@ -119,6 +157,26 @@ fn get_final_function(
     Ok(mtl_function)
 }
 
+/// Updates the purgeable state of a resource (`MTLPurgeableState`),
+/// returning `false` if its contents have been discarded by the system.
+unsafe fn set_purgeable_state(resource: *mut Object, priority: memory::Priority) -> bool {
+    const NON_VOLATILE: NSUInteger = 2;
+    const VOLATILE: NSUInteger = 3;
+    const EMPTY: NSUInteger = 4;
+    let state = match priority {
+        memory::Priority::Normal => NON_VOLATILE,
+        memory::Priority::Discardable => VOLATILE,
+    };
+    let old_state: NSUInteger = msg_send![resource, setPurgeableState: state];
+    old_state != EMPTY
+}
+
+unsafe fn set_resource_label(resource: *mut Object, name: &str) {
+    let label = NSString::alloc(nil).init_str(name);
+    msg_send![resource, setLabel: label];
+    msg_send![label, release];
+}
+
 impl VisibilityShared {
     fn are_available(&self, pool_base: query::Id, queries: &Range<query::Id>) -> bool {
         unsafe {
@@ -237,9 +295,18 @@ impl PhysicalDevice {
         format: format::Format,
         swizzle: format::Swizzle,
     ) -> bool {
-        self.shared.private_caps
-            .map_format_with_swizzle(format, swizzle)
-            .is_some()
+        let caps = &self.shared.private_caps;
+        if caps.texture_swizzle {
+            caps.map_format(format).is_some()
+        } else {
+            caps.map_format_with_swizzle(format, swizzle).is_some()
+        }
+    }
+
+    /// Return true if the GPU family supports tile shaders and imageblocks,
+    /// which is only the case for A11 and newer iOS devices.
+    pub fn supports_tile_shading(&self) -> bool {
+        self.shared.private_caps.tile_shading
     }
 }
 
@@ -249,8 +316,6 @@ impl hal::PhysicalDevice<Backend> for PhysicalDevice {
         families: &[(&QueueFamily, &[hal::QueuePriority])],
         requested_features: hal::Features,
     ) -> Result<hal::Gpu<Backend>, error::DeviceCreationError> {
-        // TODO: Query supported features by feature set rather than hard coding in the supported
-        // features. https://developer.apple.com/metal/Metal-Feature-Set-Tables.pdf
         if !self.features().contains(requested_features) {
             return Err(error::DeviceCreationError::MissingFeature);
         }
@@ -330,28 +395,36 @@ impl hal::PhysicalDevice<Backend> for PhysicalDevice {
             // Can't create 2D/2DArray views of 3D textures
             return None;
         }
-        //TODO: actually query this data
-        let max_dimension = 4096u32;
+        let caps = &self.shared.private_caps;
+        let max_dimension = if dimensions == 3 {
+            caps.max_texture_3d_size
+        } else {
+            caps.max_texture_size
+        } as u32;
         let max_extent = image::Extent {
             width: max_dimension,
             height: if dimensions >= 2 { max_dimension } else { 1 },
             depth: if dimensions >= 3 { max_dimension } else { 1 },
         };
 
-        self.shared
-            .private_caps
-            .map_format(format)
-            .map(|_| image::FormatProperties {
-                max_extent,
-                max_levels: if dimensions == 1 { 1 } else { 12 },
-                // 3D images enforce a single layer
-                max_layers: if dimensions == 3 { 1 } else { 2048 },
-                sample_count_mask: 0x1,
-                //TODO: buffers and textures have separate limits
-                // Max buffer size is determined by feature set
-                // Max texture size does not appear to be documented publicly
-                max_resource_size: self.shared.private_caps.max_buffer_size as _,
-            })
+        caps.map_format(format).map(|_| image::FormatProperties {
+            max_extent,
+            max_levels: if dimensions == 1 {
+                1
+            } else {
+                32 - max_dimension.leading_zeros() as image::Level
+            },
+            // 3D images enforce a single layer
+            max_layers: if dimensions == 3 {
+                1
+            } else {
+                caps.max_texture_layers as _
+            },
+            sample_count_mask: 0x1,
+            //TODO: buffers and textures have separate limits
+            // Max texture size does not appear to be documented publicly
+            max_resource_size: caps.max_buffer_size as _,
+        })
     }
 
     fn memory_properties(&self) -> hal::MemoryProperties {
@@ -365,16 +438,33 @@ impl hal::PhysicalDevice<Backend> for PhysicalDevice {
     }
 
     fn features(&self) -> hal::Features {
-        hal::Features::ROBUST_BUFFER_ACCESS
-            | hal::Features::DRAW_INDIRECT_FIRST_INSTANCE
-            | hal::Features::DEPTH_CLAMP
+        let caps = &self.shared.private_caps;
+        let mut features = hal::Features::empty();
+        if caps.base_instance {
+            features |= hal::Features::DRAW_INDIRECT_FIRST_INSTANCE;
+        }
+        if caps.depth_clip_mode {
+            features |= hal::Features::DEPTH_CLAMP;
+        }
+        if caps.format_bc {
+            features |= hal::Features::FORMAT_BC;
+        }
+        if caps.format_eac_etc {
+            features |= hal::Features::FORMAT_ETC2;
+        }
+        if caps.format_astc {
+            features |= hal::Features::FORMAT_ASTC_LDR;
+        }
+
+        features
+            | hal::Features::ROBUST_BUFFER_ACCESS
+            | hal::Features::INDEPENDENT_BLENDING
             | hal::Features::SAMPLER_ANISOTROPY
-            | hal::Features::FORMAT_BC
             | hal::Features::PRECISE_OCCLUSION_QUERY
             | hal::Features::SHADER_STORAGE_BUFFER_ARRAY_DYNAMIC_INDEXING
             | hal::Features::VERTEX_STORES_AND_ATOMICS
             | hal::Features::FRAGMENT_STORES_AND_ATOMICS
-            | if self.shared.private_caps.dual_source_blending {
+            | if caps.dual_source_blending {
                 hal::Features::DUAL_SRC_BLENDING
             } else {
                 hal::Features::empty()
@@ -384,25 +474,26 @@ impl hal::PhysicalDevice<Backend> for PhysicalDevice {
     }
 
     fn limits(&self) -> hal::Limits {
+        let caps = &self.shared.private_caps;
         hal::Limits {
-            max_texture_size: self.shared.private_caps.max_texture_size as usize,
-            max_texel_elements: (self.shared.private_caps.max_texture_size
-                * self.shared.private_caps.max_texture_size)
-                as usize,
+            max_texture_size: caps.max_texture_size as usize,
+            max_texel_elements: (caps.max_texture_size * caps.max_texture_size) as usize,
             max_patch_size: 0, // No tessellation
 
             // Note: The maximum number of supported viewports and scissor rectangles varies by device.
             // TODO: read from Metal Feature Sets.
             max_viewports: 1,
 
-            min_buffer_copy_offset_alignment: self.shared.private_caps.buffer_alignment,
+            min_buffer_copy_offset_alignment: caps.buffer_alignment,
             min_buffer_copy_pitch_alignment: 4,
-            min_texel_buffer_offset_alignment: self.shared.private_caps.buffer_alignment,
-            min_uniform_buffer_offset_alignment: self.shared.private_caps.buffer_alignment,
-            min_storage_buffer_offset_alignment: self.shared.private_caps.buffer_alignment,
+            min_texel_buffer_offset_alignment: caps.buffer_alignment,
+            min_uniform_buffer_offset_alignment: caps.buffer_alignment,
+            min_storage_buffer_offset_alignment: caps.buffer_alignment,
 
-            max_compute_group_count: [16; 3], // TODO
-            max_compute_group_size: [64; 3],  // TODO
+            // Metal doesn't expose a limit on the number of threadgroups per dispatch.
+            max_compute_group_count: [u16::max_value() as u32; 3],
+            max_compute_group_size: [caps.max_threads_per_group; 3],
+            max_compute_shared_memory_size: caps.max_threadgroup_memory as usize,
 
             max_vertex_input_attributes: 31,
             max_vertex_input_bindings: 31,
@@ -410,16 +501,35 @@ impl hal::PhysicalDevice<Backend> for PhysicalDevice {
             max_vertex_input_binding_stride: 256,   // TODO
             max_vertex_output_components: 16,       // TODO
 
-            framebuffer_color_samples_count: 0b101,   // TODO
-            framebuffer_depth_samples_count: 0b101,   // TODO
-            framebuffer_stencil_samples_count: 0b101, // TODO
-            max_color_attachments: 1,                 // TODO
+            framebuffer_color_samples_count: caps.sample_count_mask,
+            framebuffer_depth_samples_count: caps.sample_count_mask,
+            framebuffer_stencil_samples_count: caps.sample_count_mask,
+            max_color_attachments: caps.max_color_attachments,
+            max_framebuffer_layers: if caps.layered_rendering {
+                caps.max_texture_layers as _
+            } else {
+                1
+            },
+
+            // Descriptor sets are flattened into the per-stage resource slots.
+            max_bound_descriptor_sets: !0,
+            max_per_stage_descriptor_samplers: caps.max_samplers_per_stage as usize,
+            max_per_stage_descriptor_uniform_buffers: caps.max_buffers_per_stage as usize,
+            max_per_stage_descriptor_storage_buffers: caps.max_buffers_per_stage as usize,
+            max_per_stage_descriptor_sampled_images: caps.max_textures_per_stage as usize,
+            max_per_stage_descriptor_storage_images: caps.max_textures_per_stage as usize,
 
             // Note: we issue Metal buffer-to-buffer copies on memory flush/invalidate,
             // and those need to operate on sizes being multiples of 4.
             non_coherent_atom_size: 4,
             max_sampler_anisotropy: 16.,
+            // Multiple indirect draws are issued one by one.
+            max_draw_indirect_count: !0,
             min_vertex_input_binding_stride_alignment: STRIDE_GRANULARITY as u64,
+            // SIMD-group functions are not translated from SPIR-V.
+            subgroup_size: 0,
+            subgroup_operations: hal::SubgroupOperations::empty(),
+            subgroup_stages: pso::ShaderStageFlags::empty(),
         }
     }
 }
@@ -435,6 +545,17 @@ impl LanguageVersion {
     }
 }
 
+/// Error starting a GPU capture with `Device::begin_gpu_capture`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum CaptureError {
+    /// Capturing into a document requires macOS 10.15 or iOS 13.
+    Unsupported,
+    /// The capture path is not valid UTF-8.
+    InvalidPath,
+    /// Metal refused to start the capture, with the reason it gave.
+    Failed(String),
+}
+
 impl Device {
     fn _is_heap_coherent(&self, heap: &n::MemoryHeap) -> bool {
         match *heap {
@@ -442,10 +563,285 @@ impl Device {
             n::MemoryHeap::Public(memory_type, _) => self.memory_types[memory_type.0]
                 .properties
                 .contains(Properties::COHERENT),
-            n::MemoryHeap::Native(ref heap) => heap.storage_mode() == MTLStorageMode::Shared,
+            n::MemoryHeap::Native(ref heap, _) => heap.storage_mode() == MTLStorageMode::Shared,
+        }
+    }
+
+    /// Resource options for the hazard tracking of dedicated allocations.
+    fn hazard_tracking_options(&self) -> MTLResourceOptions {
+        if self.shared.private_caps.untracked_resources {
+            MTLResourceOptions::HazardTrackingModeUntracked
+        } else {
+            MTLResourceOptions::empty()
         }
     }
 
+    /// Start capturing all the GPU work of this device into a `.gputrace`
+    /// document at the given path, which can then be opened in Xcode.
+    /// Requires macOS 10.15 or iOS 13, `CaptureError::Unsupported` is returned
+    /// on older systems. `METAL_CAPTURE_ENABLED=1` has to be set in the
+    /// environment when running outside of Xcode.
+    pub fn begin_gpu_capture<P: AsRef<Path>>(&self, path: P) -> Result<(), CaptureError> {
+        const MTL_CAPTURE_DESTINATION_GPU_TRACE_DOCUMENT: NSInteger = 2;
+
+        // Neither `MTLCaptureDescriptor` nor `supportsDestination:` exist before.
+        if !self.shared.private_caps.capture_descriptor {
+            return Err(CaptureError::Unsupported);
+        }
+        let path = path.as_ref().to_str().ok_or(CaptureError::InvalidPath)?;
+        let manager = CaptureManager::shared();
+        let device = self.shared.device.lock();
+
+        autoreleasepool(|| unsafe {
+            let supported: BOOL = msg_send![
+                manager,
+                supportsDestination: MTL_CAPTURE_DESTINATION_GPU_TRACE_DOCUMENT
+            ];
+            if supported == NO {
+                return Err(CaptureError::Unsupported);
+            }
+
+            let ns_path = NSString::alloc(nil).init_str(path);
+            let url: *mut Object = msg_send![class!(NSURL), fileURLWithPath: ns_path];
+            msg_send![ns_path, release];
+            let descriptor: *mut Object = msg_send![class!(MTLCaptureDescriptor), new];
+            let device_raw: &metal::DeviceRef = &*device;
+            msg_send![descriptor, setCaptureObject: device_raw];
+            msg_send![descriptor, setDestination: MTL_CAPTURE_DESTINATION_GPU_TRACE_DOCUMENT];
+            msg_send![descriptor, setOutputURL: url];
+
+            let mut error: *mut Object = ptr::null_mut();
+            let started: BOOL = msg_send![
+                manager,
+                startCaptureWithDescriptor: descriptor
+                error: &mut error
+            ];
+            msg_send![descriptor, release];
+
+            if started == NO {
+                let description: *mut Object = msg_send![error, localizedDescription];
+                let bytes: *const c_char = msg_send![description, UTF8String];
+                return Err(CaptureError::Failed(
+                    CStr::from_ptr(bytes).to_string_lossy().into_owned(),
+                ));
+            }
+            info!("Metal capture started into {}", path);
+            Ok(())
+        })
+    }
+
+    /// Stop the capture started by `begin_gpu_capture`, writing out the document.
+    pub fn end_gpu_capture(&self) {
+        info!("Metal capture stop");
+        CaptureManager::shared().stop_capture();
+    }
+
+    /// Upload the data into a bound buffer at the given offset.
+    ///
+    /// Private-storage buffers are filled from an internal staging ring by
+    /// blit commands on the queue, which makes the data visible to all the
+    /// work submitted afterwards. Host-visible buffers are written directly.
+    pub unsafe fn upload_buffer(&self, buffer: &n::Buffer, offset: u64, data: &[u8]) {
+        let (raw, range, options) = match *buffer {
+            n::Buffer::Bound {
+                ref raw,
+                ref range,
+                options,
+            } => (raw, range, options),
+            n::Buffer::Unbound { .. } => panic!("Expected bound buffer!"),
+        };
+        let start = range.start + offset;
+        assert!(start + data.len() as u64 <= range.end);
+
+        if !options.contains(MTLResourceOptions::StorageModePrivate) {
+            let ptr = (raw.contents() as *mut u8).offset(start as isize);
+            ptr::copy_nonoverlapping(data.as_ptr(), ptr, data.len());
+            if options.contains(MTLResourceOptions::StorageModeManaged) {
+                raw.did_modify_range(NSRange {
+                    location: start as _,
+                    length: data.len() as _,
+                });
+            }
+            return;
+        }
+
+        let chunk_size = internal::StagingRing::SIZE as usize;
+        for (i, chunk) in data.chunks(chunk_size).enumerate() {
+            // note: the queue is not locked while staging, since
+            // the ring may need to wait for the previous uploads
+            let mut staging = self.shared.staging.lock();
+            let staged_offset = staging.stage(chunk);
+            let queue = self.shared.queue.lock();
+            autoreleasepool(|| {
+                let cmd_buffer = queue.spawn_temp();
+                cmd_buffer.set_label("upload_buffer");
+                let encoder = cmd_buffer.new_blit_command_encoder();
+                if let Some(ref fence) = self.shared.heap_fence {
+                    fence.wait(encoder);
+                }
+                encoder.copy_from_buffer(
+                    staging.buffer(),
+                    staged_offset,
+                    raw,
+                    start + (i * chunk_size) as u64,
+                    chunk.len() as u64,
+                );
+                if let Some(ref fence) = self.shared.heap_fence {
+                    fence.update(encoder);
+                }
+                encoder.end_encoding();
+                cmd_buffer.commit();
+                staging.track(cmd_buffer);
+            });
+        }
+    }
+
+    /// Upload tightly packed texel data into a region of a bound image.
+    ///
+    /// Like `upload_buffer`, the copies are encoded on the queue through
+    /// the staging ring, or a temporary buffer for the larger regions.
+    pub unsafe fn upload_image(
+        &self,
+        image: &n::Image,
+        layers: image::SubresourceLayers,
+        offset: image::Offset,
+        extent: image::Extent,
+        data: &[u8],
+    ) {
+        let fd = image.format_desc;
+        let blocks_wide = (extent.width + fd.dim.0 as u32 - 1) / fd.dim.0 as u32;
+        let blocks_high = (extent.height + fd.dim.1 as u32 - 1) / fd.dim.1 as u32;
+        let row_pitch = blocks_wide * (fd.bits / 8) as u32;
+        let slice_pitch = blocks_high * row_pitch;
+        let layer_size = (slice_pitch * extent.depth) as usize;
+        assert_eq!(
+            data.len(),
+            layer_size * (layers.layers.end - layers.layers.start) as usize
+        );
+
+        let texture = match image.like {
+            n::ImageLike::Texture(ref texture) => texture,
+            n::ImageLike::Buffer(ref buffer) => {
+                // linear images are only single-level and single-layer
+                for z in 0..extent.depth {
+                    for y in 0..extent.height {
+                        let row_offset = image.byte_offset(image::Offset {
+                            x: offset.x,
+                            y: offset.y + y as i32,
+                            z: offset.z + z as i32,
+                        });
+                        let start = (z * slice_pitch + y * row_pitch) as usize;
+                        self.upload_buffer(
+                            buffer,
+                            row_offset,
+                            &data[start..start + row_pitch as usize],
+                        );
+                    }
+                }
+                return;
+            }
+            n::ImageLike::Unbound { .. } => panic!("Expected bound image!"),
+        };
+
+        for (layer, layer_data) in layers.layers.clone().zip(data.chunks(layer_size)) {
+            let encode =
+                |cmd_buffer: &metal::CommandBufferRef, src: &metal::BufferRef, src_offset| {
+                    cmd_buffer.set_label("upload_image");
+                    let encoder = cmd_buffer.new_blit_command_encoder();
+                    if let Some(ref fence) = self.shared.heap_fence {
+                        fence.wait(encoder);
+                    }
+                    encoder.copy_from_buffer_to_texture(
+                        src,
+                        src_offset,
+                        row_pitch as NSUInteger,
+                        slice_pitch as NSUInteger,
+                        conv::map_extent(extent),
+                        texture,
+                        layer as NSUInteger,
+                        layers.level as NSUInteger,
+                        conv::map_offset(offset),
+                        metal::MTLBlitOption::empty(),
+                    );
+                    if let Some(ref fence) = self.shared.heap_fence {
+                        fence.update(encoder);
+                    }
+                    encoder.end_encoding();
+                    cmd_buffer.commit();
+                };
+
+            if layer_size as u64 <= internal::StagingRing::SIZE {
+                let mut staging = self.shared.staging.lock();
+                let staged_offset = staging.stage(layer_data);
+                let queue = self.shared.queue.lock();
+                autoreleasepool(|| {
+                    let cmd_buffer = queue.spawn_temp();
+                    encode(cmd_buffer, staging.buffer(), staged_offset);
+                    staging.track(cmd_buffer);
+                });
+            } else {
+                let temp = self.shared.device.lock().new_buffer_with_data(
+                    layer_data.as_ptr() as *const _,
+                    layer_data.len() as u64,
+                    MTLResourceOptions::StorageModeShared,
+                );
+                let cmd_buffer = autoreleasepool(|| {
+                    let queue = self.shared.queue.lock();
+                    let cmd_buffer = queue.spawn_temp();
+                    encode(cmd_buffer, &temp, 0);
+                    cmd_buffer.to_owned()
+                });
+                // the command buffer doesn't retain the temporary buffer
+                cmd_buffer.wait_until_completed();
+            }
+        }
+    }
+
+    /// Create an indirect command buffer holding up to `max_commands` draws of the given types.
+    /// Every command uses the currently bound pipeline state, and can bind up to the
+    /// specified number of vertex and fragment buffers on its own.
+    pub fn create_indirect_command_buffer(
+        &self,
+        types: n::IndirectCommandTypes,
+        max_commands: u32,
+        max_vertex_buffers: u32,
+        max_fragment_buffers: u32,
+    ) -> Result<n::IndirectCommandBuffer, OutOfMemory> {
+        if !self.shared.private_caps.indirect_command_buffers {
+            error!("Indirect command buffers are not supported by the device");
+            return Err(OutOfMemory::OutOfDeviceMemory);
+        }
+
+        let device = self.shared.device.lock();
+        autoreleasepool(|| unsafe {
+            let descriptor: *mut Object =
+                msg_send![class!(MTLIndirectCommandBufferDescriptor), new];
+            msg_send![descriptor, setCommandTypes: types.bits()];
+            msg_send![descriptor, setInheritPipelineState: true];
+            msg_send![descriptor, setInheritBuffers: false];
+            msg_send![descriptor, setMaxVertexBufferBindCount: max_vertex_buffers as NSUInteger];
+            msg_send![descriptor, setMaxFragmentBufferBindCount: max_fragment_buffers as NSUInteger];
+
+            let device_raw: &metal::DeviceRef = &*device;
+            let raw: *mut metal::MTLResource = msg_send![
+                device_raw,
+                newIndirectCommandBufferWithDescriptor: descriptor
+                maxCommandCount: max_commands as NSUInteger
+                options: MTLResourceOptions::StorageModePrivate
+            ];
+            msg_send![descriptor, release];
+
+            if raw.is_null() {
+                error!("Unable to create an indirect command buffer");
+                return Err(OutOfMemory::OutOfDeviceMemory);
+            }
+            Ok(n::IndirectCommandBuffer {
+                raw: metal::Resource::from_ptr(raw),
+                max_commands,
+            })
+        })
+    }
+
     pub fn create_shader_library_from_file<P>(
         &self,
         _path: P,
@@ -465,7 +861,6 @@ impl Device {
     where
         S: AsRef<str>,
     {
-        let options = metal::CompileOptions::new();
         let msl_version = match version {
             LanguageVersion { major: 1, minor: 0 } => MTLLanguageVersion::V1_0,
             LanguageVersion { major: 1, minor: 1 } => MTLLanguageVersion::V1_1,
@@ -483,12 +878,9 @@ impl Device {
                 "shader model too high".into(),
             ));
         }
-        options.set_language_version(msl_version);
-
         self.shared
-            .device
-            .lock()
-            .new_library_with_source(source.as_ref(), &options)
+            .library_cache
+            .get_or_compile(source.as_ref(), msl_version, &self.shared.device)
             .map(|library| {
                 n::ShaderModule::Compiled(n::ModuleInfo {
                     library,
@@ -500,20 +892,13 @@ impl Device {
     }
 
     fn compile_shader_library(
-        device: &Mutex<metal::Device>,
+        shared: &Shared,
         raw_data: &[u8],
         compiler_options: &msl::CompilerOptions,
         msl_version: MTLLanguageVersion,
     ) -> Result<n::ModuleInfo, ShaderError> {
-        // spec requires "codeSize must be a multiple of 4"
-        assert_eq!(raw_data.len() & 3, 0);
-
-        let module = spirv::Module::from_words(unsafe {
-            slice::from_raw_parts(
-                raw_data.as_ptr() as *const u32,
-                raw_data.len() / mem::size_of::<u32>(),
-            )
-        });
+        let words = spirv_words(raw_data)?;
+        let module = spirv::Module::from_words(&words);
 
         // now parse again using the new overrides
         let mut ast = spirv::Ast::<msl::Target>::parse(&module).map_err(gen_parse_error)?;
@@ -566,12 +951,9 @@ impl Device {
         // done
         debug!("SPIRV-Cross generated shader:\n{}", shader_code);
 
-        let options = metal::CompileOptions::new();
-        options.set_language_version(msl_version);
-
-        let library = device
-            .lock()
-            .new_library_with_source(shader_code.as_ref(), &options)
+        let library = shared
+            .library_cache
+            .get_or_compile(&shader_code, msl_version, &shared.device)
             .map_err(|err| ShaderError::CompilationFailed(err.into()))?;
 
         Ok(n::ModuleInfo {
@@ -588,7 +970,6 @@ impl Device {
         primitive_class: MTLPrimitiveTopologyClass,
         pipeline_cache: Option<&n::PipelineCache>,
     ) -> Result<(metal::Library, metal::Function, metal::MTLSize, bool), pso::CreationError> {
-        let device = &self.shared.device;
         let msl_version = self.shared.private_caps.msl_version;
         let module_map;
         let (info_owned, info_guard);
@@ -605,20 +986,31 @@ impl Device {
                         module_map = cache
                             .modules
                             .get_or_create_with(compiler_options, || FastStorageMap::default());
-                        info_guard = module_map.get_or_create_with(data, || {
-                            Self::compile_shader_library(
-                                device,
+                        let mut compile_error = None;
+                        module_map.prepare_maybe(data, || {
+                            match Self::compile_shader_library(
+                                &self.shared,
                                 data,
                                 compiler_options,
                                 msl_version,
-                            )
-                            .unwrap()
+                            ) {
+                                Ok(info) => Some(info),
+                                Err(e) => {
+                                    compile_error = Some(e);
+                                    None
+                                }
+                            }
                         });
+                        if let Some(e) = compile_error {
+                            error!("Error compiling the shader {:?}", e);
+                            return Err(pso::CreationError::Other);
+                        }
+                        info_guard = module_map.get_or_create_with(data, || unreachable!());
                         &*info_guard
                     }
                     None => {
                         info_owned = Self::compile_shader_library(
-                            device,
+                            &self.shared,
                             data,
                             compiler_options,
                             msl_version,
@@ -696,6 +1088,18 @@ impl Device {
                 arg.set_data_type(MTLDataType::Struct);
                 arg.set_index(index as _);
             }
+            pso::DescriptorType::UniformTexelBuffer => {
+                arg.set_access(MTLArgumentAccess::ReadOnly);
+                arg.set_data_type(MTLDataType::Texture);
+                arg.set_index(index as _);
+            }
+            pso::DescriptorType::StorageImage | pso::DescriptorType::StorageTexelBuffer => {
+                // Writable textures in argument buffers require Tier 2,
+                // which is checked by `PrivateCapabilities::argument_buffers`.
+                arg.set_access(MTLArgumentAccess::ReadWrite);
+                arg.set_data_type(MTLDataType::Texture);
+                arg.set_index(index as _);
+            }
             _ => unimplemented!(),
         }
 
@@ -753,6 +1157,7 @@ impl hal::Device<Backend> for Device {
                         .depth_stencil
                         .map(|&(id, _)| (id, n::SubpassOps::empty())),
                     inputs: sub.inputs.iter().map(|&(id, _)| id).collect(),
+                    resolves: sub.resolves.iter().map(|&(id, _)| id).collect(),
                     target_formats: n::SubpassFormats {
                         colors: sub
                             .colors
@@ -918,7 +1323,7 @@ impl hal::Device<Backend> for Device {
                         }
                     }
                 }
-                n::DescriptorSetLayout::ArgumentBuffer(_, stage_flags) => {
+                n::DescriptorSetLayout::ArgumentBuffer { stage_flags, .. } => {
                     for &mut (stage_bit, stage, ref mut counters) in stage_infos.iter_mut() {
                         if !stage_flags.contains(stage_bit) {
                             continue;
@@ -1182,6 +1587,17 @@ impl hal::Device<Backend> for Device {
 
         pipeline.set_rasterization_enabled(enable_rasterization);
 
+        if let Some(ref ms) = pipeline_desc.multisampling {
+            pipeline.set_sample_count(ms.rasterization_samples as u64);
+            pipeline.set_alpha_to_coverage_enabled(ms.alpha_coverage);
+            pipeline.set_alpha_to_one_enabled(ms.alpha_to_one);
+            // Metal has no pipeline sample mask, and sample rate shading
+            // is only triggered by the shader reading the sample index.
+            if ms.sample_mask != !0 {
+                warn!("Sample masks are not supported, all samples are covered");
+            }
+        }
+
         // Assign target formats
         let blend_targets = pipeline_desc
             .blender
@@ -1208,6 +1624,18 @@ impl hal::Device<Backend> for Device {
                 ref alpha,
             } = *blend
             {
+                if conv::is_dual_source_blend_op(color) || conv::is_dual_source_blend_op(alpha) {
+                    // The second source is `[[color(0), index(1)]]` in MSL,
+                    // so it can only be blended into the first attachment.
+                    if !self.shared.private_caps.dual_source_blending {
+                        error!("Dual-source blending is not supported by the device");
+                        return Err(pso::CreationError::Other);
+                    }
+                    if i != 0 {
+                        error!("Dual-source blending is only possible on color attachment 0");
+                        return Err(pso::CreationError::Other);
+                    }
+                }
                 desc.set_blending_enabled(true);
                 let (color_op, color_src, color_dst) = conv::map_blend_op(color);
                 let (alpha_op, alpha_src, alpha_dst) = conv::map_blend_op(alpha);
@@ -1400,20 +1828,36 @@ impl hal::Device<Backend> for Device {
         )?;
         pipeline.set_compute_function(Some(&cs_function));
 
-        self.shared
+        let raw = self
+            .shared
             .device
             .lock()
             .new_compute_pipeline_state(&pipeline)
-            .map(|raw| n::ComputePipeline {
-                cs_lib,
-                raw,
-                work_group_size,
-                pc_info: pipeline_desc.layout.push_constants.cs,
-            })
             .map_err(|err| {
                 error!("PSO creation failed: {}", err);
                 pso::CreationError::Other
-            })
+            })?;
+
+        // The limit depends on the register usage of the kernel,
+        // so it can only be checked once the pipeline state is compiled.
+        let total_threads = work_group_size.width * work_group_size.height * work_group_size.depth;
+        if total_threads > raw.max_total_threads_per_threadgroup() {
+            error!(
+                "Work group size {:?} exceeds the pipeline limit of {} threads",
+                work_group_size,
+                raw.max_total_threads_per_threadgroup()
+            );
+            return Err(pso::CreationError::Shader(ShaderError::CompilationFailed(
+                "Work group size is too large".into(),
+            )));
+        }
+
+        Ok(n::ComputePipeline {
+            cs_lib,
+            raw,
+            work_group_size,
+            pc_info: pipeline_desc.layout.push_constants.cs,
+        })
     }
 
     unsafe fn create_framebuffer<I>(
@@ -1421,31 +1865,55 @@ impl hal::Device<Backend> for Device {
         _render_pass: &n::RenderPass,
         attachments: I,
         extent: image::Extent,
-    ) -> Result<n::Framebuffer, OutOfMemory>
+    ) -> Result<n::Framebuffer, FramebufferError>
     where
         I: IntoIterator,
         I::Item: Borrow<n::ImageView>,
     {
+        let caps = &self.shared.private_caps;
+        let max_layers = if caps.layered_rendering {
+            caps.max_texture_layers
+        } else {
+            1
+        };
+        if extent.depth as u64 > max_layers {
+            error!(
+                "Framebuffer has {} layers, the limit is {}",
+                extent.depth, max_layers
+            );
+            return Err(FramebufferError::TooManyLayers);
+        }
         Ok(n::Framebuffer {
             extent,
             attachments: attachments
                 .into_iter()
                 .map(|at| at.borrow().raw.clone())
                 .collect(),
+            parallel_pass: Arc::new(Mutex::new(None)),
         })
     }
 
     unsafe fn create_shader_module(&self, raw_data: &[u8]) -> Result<n::ShaderModule, ShaderError> {
-        //TODO: we can probably at least parse here and save the `Ast`
         let depends_on_pipeline_layout = true; //TODO: !self.private_caps.argument_buffers
         Ok(if depends_on_pipeline_layout {
+            // The MSL translation needs the resource remap table of the pipeline
+            // layout, so only parse here to report malformed modules early.
+            let words = spirv_words(raw_data)?;
+            let module = spirv::Module::from_words(&words);
+            let ast = spirv::Ast::<msl::Target>::parse(&module).map_err(gen_parse_error)?;
+            let entry_points = ast.get_entry_points().map_err(gen_parse_error)?;
+            if entry_points.is_empty() {
+                return Err(ShaderError::MissingEntryPoint(
+                    "SPIR-V module has no entry points".into(),
+                ));
+            }
             n::ShaderModule::Raw(raw_data.to_vec())
         } else {
             let mut options = msl::CompilerOptions::default();
             options.enable_point_size_builtin = false;
             options.vertex.invert_y = true;
             let info = Self::compile_shader_library(
-                &self.shared.device,
+                &self.shared,
                 raw_data,
                 &options,
                 self.shared.private_caps.msl_version,
@@ -1525,7 +1993,7 @@ impl hal::Device<Backend> for Device {
 
         let base_ptr = match memory.heap {
             n::MemoryHeap::Public(_, ref cpu_buffer) => cpu_buffer.contents() as *mut u8,
-            n::MemoryHeap::Native(_) | n::MemoryHeap::Private => panic!("Unable to map memory!"),
+            n::MemoryHeap::Native(..) | n::MemoryHeap::Private => panic!("Unable to map memory!"),
         };
         Ok(base_ptr.offset(range.start as _))
     }
@@ -1547,7 +2015,7 @@ impl hal::Device<Backend> for Device {
             debug!("\trange {:?}", range);
 
             match memory.heap {
-                n::MemoryHeap::Native(_) => unimplemented!(),
+                n::MemoryHeap::Native(..) => unimplemented!(),
                 n::MemoryHeap::Public(mt, ref cpu_buffer)
                     if 1 << mt.0 != MemoryTypes::SHARED.bits() as usize =>
                 {
@@ -1586,7 +2054,7 @@ impl hal::Device<Backend> for Device {
                 debug!("\trange {:?}", range);
 
                 match memory.heap {
-                    n::MemoryHeap::Native(_) => unimplemented!(),
+                    n::MemoryHeap::Native(..) => unimplemented!(),
                     n::MemoryHeap::Public(mt, ref cpu_buffer)
                         if 1 << mt.0 != MemoryTypes::SHARED.bits() as usize =>
                     {
@@ -1611,14 +2079,22 @@ impl hal::Device<Backend> for Device {
     }
 
     fn create_semaphore(&self) -> Result<n::Semaphore, OutOfMemory> {
+        // Semaphore synchronization between command buffers of the same queue
+        // is useless, don't bother even creating one.
+        let cross_queue = self.shared.private_caps.exposed_queues > 1;
         Ok(n::Semaphore {
-            // Semaphore synchronization between command buffers of the same queue
-            // is useless, don't bother even creating one.
-            system: if self.shared.private_caps.exposed_queues > 1 {
+            // Events keep the synchronization on the GPU timeline,
+            // otherwise we have to block on the CPU.
+            system: if cross_queue && !self.shared.private_caps.events {
                 Some(n::SystemSemaphore::new())
             } else {
                 None
             },
+            event: if cross_queue && self.shared.private_caps.events {
+                Some(n::Event::new(&*self.shared.device.lock()))
+            } else {
+                None
+            },
             image_ready: Arc::new(Mutex::new(None)),
         })
     }
@@ -1640,7 +2116,10 @@ impl hal::Device<Backend> for Device {
                 let desc = desc_range.borrow();
                 let offset_ref = match desc.ty {
                     pso::DescriptorType::Sampler => &mut counters.samplers,
-                    pso::DescriptorType::SampledImage => &mut counters.textures,
+                    pso::DescriptorType::SampledImage
+                    | pso::DescriptorType::StorageImage
+                    | pso::DescriptorType::UniformTexelBuffer
+                    | pso::DescriptorType::StorageTexelBuffer => &mut counters.textures,
                     pso::DescriptorType::UniformBuffer | pso::DescriptorType::StorageBuffer => {
                         &mut counters.buffers
                     }
@@ -1687,19 +2166,35 @@ impl hal::Device<Backend> for Device {
         J::Item: Borrow<n::Sampler>,
     {
         if self.shared.private_caps.argument_buffers {
-            let mut stage_flags = pso::ShaderStageFlags::empty();
-            let arguments = binding_iter
+            let mut bindings = binding_iter
                 .into_iter()
+                .map(|desc| desc.borrow().clone())
+                .collect::<Vec<_>>();
+            bindings.sort_by_key(|desc| desc.binding);
+
+            // Array elements occupy consecutive argument indices, so each binding
+            // starts after all the elements of the previous ones.
+            let mut stage_flags = pso::ShaderStageFlags::empty();
+            let mut binding_offsets = FastHashMap::default();
+            let mut offset = 0;
+            let arguments = bindings
+                .iter()
                 .map(|desc| {
-                    let desc = desc.borrow();
                     stage_flags |= desc.stage_flags;
-                    Self::describe_argument(desc.ty, desc.binding, desc.count)
+                    binding_offsets.insert(desc.binding, offset as NSUInteger);
+                    let arg = Self::describe_argument(desc.ty, offset, desc.count);
+                    offset += desc.count as pso::DescriptorBinding;
+                    arg
                 })
                 .collect::<Vec<_>>();
             let arg_array = metal::Array::from_owned_slice(&arguments);
             let encoder = self.shared.device.lock().new_argument_encoder(&arg_array);
 
-            Ok(n::DescriptorSetLayout::ArgumentBuffer(encoder, stage_flags))
+            Ok(n::DescriptorSetLayout::ArgumentBuffer {
+                encoder,
+                stage_flags,
+                binding_offsets: Arc::new(binding_offsets),
+            })
         } else {
             struct TempSampler {
                 sampler: metal::SamplerState,
@@ -1835,34 +2330,53 @@ impl hal::Device<Backend> for Device {
                     ref raw,
                     offset,
                     ref encoder,
+                    ref binding_offsets,
+                    ref resources,
                     ..
                 } => {
                     debug_assert!(self.shared.private_caps.argument_buffers);
 
                     encoder.set_argument_buffer(raw, offset);
-                    //TODO: range checks, need to keep some layout metadata around
-                    assert_eq!(write.array_offset, 0); //TODO
+                    let mut resources = resources.lock();
+                    //TODO: range checks
+                    let base = binding_offsets[&write.binding] + write.array_offset as NSUInteger;
 
-                    for descriptor in write.descriptors {
-                        match *descriptor.borrow() {
+                    for (i, descriptor) in write.descriptors.into_iter().enumerate() {
+                        let index = base + i as NSUInteger;
+                        let (resource, usage) = match *descriptor.borrow() {
                             pso::Descriptor::Sampler(sampler) => {
-                                encoder.set_sampler_states(&[&sampler.0], write.binding as _);
+                                encoder.set_sampler_states(&[&sampler.0], index);
+                                continue;
                             }
                             pso::Descriptor::Image(image, _layout) => {
-                                encoder.set_textures(&[&image.raw], write.binding as _);
+                                encoder.set_textures(&[&image.raw], index);
+                                let resource: &metal::ResourceRef = &image.raw;
+                                (
+                                    resource,
+                                    MTLResourceUsage::Read
+                                        | MTLResourceUsage::Sample
+                                        | MTLResourceUsage::Write,
+                                )
+                            }
+                            pso::Descriptor::UniformTexelBuffer(view)
+                            | pso::Descriptor::StorageTexelBuffer(view) => {
+                                encoder.set_textures(&[&view.raw], index);
+                                let resource: &metal::ResourceRef = &view.raw;
+                                (resource, MTLResourceUsage::Read | MTLResourceUsage::Write)
                             }
                             pso::Descriptor::Buffer(buffer, ref desc_range) => {
                                 let (raw, range) = buffer.as_bound();
                                 encoder.set_buffer(
                                     raw,
                                     range.start + desc_range.start.unwrap_or(0),
-                                    write.binding as _,
+                                    index,
                                 );
+                                let resource: &metal::ResourceRef = raw;
+                                (resource, MTLResourceUsage::Read | MTLResourceUsage::Write)
                             }
-                            pso::Descriptor::CombinedImageSampler(..)
-                            | pso::Descriptor::UniformTexelBuffer(..)
-                            | pso::Descriptor::StorageTexelBuffer(..) => unimplemented!(),
-                        }
+                            pso::Descriptor::CombinedImageSampler(..) => unimplemented!(),
+                        };
+                        resources.insert(index, (AsNative::from(resource), usage));
                     }
                 }
             }
@@ -1906,22 +2420,23 @@ impl hal::Device<Backend> for Device {
         let device = self.shared.device.lock();
         debug!("allocate_memory type {:?} of size {}", memory_type, size);
 
-        // Heaps cannot be used for CPU coherent resources
-        //TEMP: MacOS supports Private only, iOS and tvOS can do private/shared
+        // Heaps cannot be used for CPU coherent resources.
+        // MacOS supports Private only, iOS and tvOS can do private/shared,
+        // so we stick to the common denominator.
         let heap = if self.shared.private_caps.resource_heaps
-            && storage != MTLStorageMode::Shared
-            && false
+            && storage == MTLStorageMode::Private
         {
             let descriptor = metal::HeapDescriptor::new();
             descriptor.set_storage_mode(storage);
             descriptor.set_cpu_cache_mode(cache);
             descriptor.set_size(size);
             let heap_raw = device.new_heap(&descriptor);
-            n::MemoryHeap::Native(heap_raw)
+            n::MemoryHeap::Native(heap_raw, Mutex::new(Vec::new()))
         } else if storage == MTLStorageMode::Private {
             n::MemoryHeap::Private
         } else {
-            let options = conv::resource_options_from_storage_and_cache(storage, cache);
+            let options = conv::resource_options_from_storage_and_cache(storage, cache)
+                | self.hazard_tracking_options();
             let cpu_buffer = device.new_buffer(size, options);
             debug!("\tbacked by cpu buffer {:?}", cpu_buffer.as_ptr());
             n::MemoryHeap::Public(memory_type, cpu_buffer)
@@ -1999,15 +2514,21 @@ impl hal::Device<Backend> for Device {
         };
         debug!("bind_buffer_memory of size {} at offset {}", size, offset);
         *buffer = match memory.heap {
-            n::MemoryHeap::Native(ref heap) => {
+            n::MemoryHeap::Native(ref heap, _) => {
                 let options = conv::resource_options_from_storage_and_cache(
                     heap.storage_mode(),
                     heap.cpu_cache_mode(),
                 );
-                let raw = heap.new_buffer(size, options).unwrap_or_else(|| {
-                    // TODO: disable hazard tracking?
-                    self.shared.device.lock().new_buffer(size, options)
-                });
+                let raw = match heap.new_buffer(size, options) {
+                    Some(raw) => {
+                        memory.heap.place(offset..offset + size, &raw);
+                        raw
+                    }
+                    None => {
+                        warn!("\theap is exhausted, falling back to a dedicated allocation");
+                        self.shared.device.lock().new_buffer(size, options)
+                    }
+                };
                 n::Buffer::Bound {
                     raw,
                     options,
@@ -2030,7 +2551,8 @@ impl hal::Device<Backend> for Device {
             n::MemoryHeap::Private => {
                 //TODO: check for aliasing
                 let options = MTLResourceOptions::StorageModePrivate
-                    | MTLResourceOptions::CPUCacheModeDefaultCache;
+                    | MTLResourceOptions::CPUCacheModeDefaultCache
+                    | self.hazard_tracking_options();
                 let raw = self.shared.device.lock().new_buffer(size, options);
                 n::Buffer::Bound {
                     raw,
@@ -2043,6 +2565,31 @@ impl hal::Device<Backend> for Device {
         Ok(())
     }
 
+    unsafe fn set_buffer_priority(&self, buffer: &n::Buffer, priority: memory::Priority) -> bool {
+        match *buffer {
+            // buffers in the private storage own their allocations, while
+            // the others may share the mapped memory
+            n::Buffer::Bound {
+                ref raw, options, ..
+            } if options.contains(MTLResourceOptions::StorageModePrivate) => {
+                set_purgeable_state(raw.as_ptr() as *mut Object, priority)
+            }
+            _ => true,
+        }
+    }
+
+    unsafe fn set_buffer_name(&self, buffer: &mut n::Buffer, name: &str) {
+        match *buffer {
+            // only name the buffers owning their allocations, others share them
+            n::Buffer::Bound {
+                ref raw, options, ..
+            } if options.contains(MTLResourceOptions::StorageModePrivate) => {
+                set_resource_label(raw.as_ptr() as *mut Object, name)
+            }
+            _ => {}
+        }
+    }
+
     unsafe fn destroy_buffer(&self, buffer: n::Buffer) {
         if let n::Buffer::Bound { raw, range, .. } = buffer {
             debug!(
@@ -2212,11 +2759,24 @@ impl hal::Device<Backend> for Device {
             && tiling == image::Tiling::Linear
             && host_usage.contains(usage);
 
+        // Transient attachments don't need any backing memory on TBDR GPUs,
+        // as long as they aren't accessed outside of the render pass.
+        let memoryless_usage = image::Usage::TRANSIENT_ATTACHMENT
+            | image::Usage::COLOR_ATTACHMENT
+            | image::Usage::DEPTH_STENCIL_ATTACHMENT;
+        let memoryless = usage.contains(image::Usage::TRANSIENT_ATTACHMENT)
+            && memoryless_usage.contains(usage)
+            && self.shared.private_caps.memoryless;
+        if memoryless {
+            descriptor.set_storage_mode(MTLStorageMode::Memoryless);
+        }
+
         Ok(n::Image {
             like: n::ImageLike::Unbound {
                 descriptor,
                 mip_sizes,
                 host_visible,
+                memoryless,
             },
             kind,
             format_desc,
@@ -2228,10 +2788,21 @@ impl hal::Device<Backend> for Device {
 
     unsafe fn get_image_requirements(&self, image: &n::Image) -> memory::Requirements {
         let (descriptor, mip_sizes, host_visible) = match image.like {
+            n::ImageLike::Unbound {
+                memoryless: true, ..
+            } => {
+                // The texture doesn't occupy any memory, but the allocation can't be empty.
+                return memory::Requirements {
+                    size: 1,
+                    alignment: 1,
+                    type_mask: MemoryTypes::PRIVATE.bits(),
+                };
+            }
             n::ImageLike::Unbound {
                 ref descriptor,
                 ref mip_sizes,
                 host_visible,
+                ..
             } => (descriptor, mip_sizes, host_visible),
             n::ImageLike::Texture(..) | n::ImageLike::Buffer(..) => {
                 panic!("Expected Image::Unbound")
@@ -2313,28 +2884,45 @@ impl hal::Device<Backend> for Device {
         image: &mut n::Image,
     ) -> Result<(), BindError> {
         let like = {
-            let (descriptor, mip_sizes) = match image.like {
+            let (descriptor, mip_sizes, memoryless) = match image.like {
                 n::ImageLike::Unbound {
                     ref descriptor,
                     ref mip_sizes,
+                    memoryless,
                     ..
-                } => (descriptor, mip_sizes),
+                } => (descriptor, mip_sizes, memoryless),
                 n::ImageLike::Texture(..) | n::ImageLike::Buffer(..) => {
                     panic!("Expected Image::Unbound")
                 }
             };
 
             match memory.heap {
-                n::MemoryHeap::Native(ref heap) => {
+                // memoryless textures can't be placed in heaps
+                _ if memoryless => {
+                    n::ImageLike::Texture(self.shared.device.lock().new_texture(descriptor))
+                }
+                n::MemoryHeap::Native(ref heap, _) => {
                     let resource_options = conv::resource_options_from_storage_and_cache(
                         heap.storage_mode(),
                         heap.cpu_cache_mode(),
                     );
                     descriptor.set_resource_options(resource_options);
-                    n::ImageLike::Texture(heap.new_texture(descriptor).unwrap_or_else(|| {
-                        // TODO: disable hazard tracking?
-                        self.shared.device.lock().new_texture(&descriptor)
-                    }))
+                    n::ImageLike::Texture(match heap.new_texture(descriptor) {
+                        Some(raw) => {
+                            let size = self
+                                .shared
+                                .device
+                                .lock()
+                                .heap_texture_size_and_align(descriptor)
+                                .size;
+                            memory.heap.place(offset..offset + size, &raw);
+                            raw
+                        }
+                        None => {
+                            warn!("\theap is exhausted, falling back to a dedicated allocation");
+                            self.shared.device.lock().new_texture(&descriptor)
+                        }
+                    })
                 }
                 n::MemoryHeap::Public(_memory_type, ref cpu_buffer) => {
                     assert_eq!(mip_sizes.len(), 1);
@@ -2345,7 +2933,9 @@ impl hal::Device<Backend> for Device {
                     })
                 }
                 n::MemoryHeap::Private => {
-                    descriptor.set_storage_mode(MTLStorageMode::Private);
+                    descriptor.set_resource_options(
+                        MTLResourceOptions::StorageModePrivate | self.hazard_tracking_options(),
+                    );
                     n::ImageLike::Texture(self.shared.device.lock().new_texture(descriptor))
                 }
             }
@@ -2354,6 +2944,26 @@ impl hal::Device<Backend> for Device {
         Ok(image.like = like)
     }
 
+    unsafe fn set_image_priority(&self, image: &n::Image, priority: memory::Priority) -> bool {
+        match image.like {
+            n::ImageLike::Unbound { .. } => true,
+            n::ImageLike::Buffer(ref buffer) => self.set_buffer_priority(buffer, priority),
+            n::ImageLike::Texture(ref texture) => {
+                set_purgeable_state(texture.as_ptr() as *mut Object, priority)
+            }
+        }
+    }
+
+    unsafe fn set_image_name(&self, image: &mut n::Image, name: &str) {
+        match image.like {
+            n::ImageLike::Unbound { .. } => {}
+            n::ImageLike::Buffer(ref mut buffer) => self.set_buffer_name(buffer, name),
+            n::ImageLike::Texture(ref texture) => {
+                set_resource_label(texture.as_ptr() as *mut Object, name)
+            }
+        }
+    }
+
     unsafe fn destroy_image(&self, _image: n::Image) {
         //nothing to do
     }
@@ -2366,11 +2976,15 @@ impl hal::Device<Backend> for Device {
         swizzle: format::Swizzle,
         range: image::SubresourceRange,
     ) -> Result<n::ImageView, image::ViewError> {
-        let mtl_format = match self
-            .shared
-            .private_caps
-            .map_format_with_swizzle(format, swizzle)
-        {
+        let caps = &self.shared.private_caps;
+        // Newer systems can remap the channels in the view itself,
+        // otherwise we try to find a pixel format with the matching layout.
+        let native_swizzle = caps.texture_swizzle && swizzle != format::Swizzle::NO;
+        let mtl_format = match if native_swizzle {
+            caps.map_format(format)
+        } else {
+            caps.map_format_with_swizzle(format, swizzle)
+        } {
             Some(f) => f,
             None => {
                 error!("failed to swizzle format {:?} with {:?}", format, swizzle);
@@ -2393,6 +3007,25 @@ impl hal::Device<Backend> for Device {
             // Some images are marked as framebuffer-only, and we can't create aliases of them.
             // Also helps working around Metal bugs with aliased array textures.
             raw.to_owned()
+        } else if native_swizzle {
+            let levels = NSRange {
+                location: range.levels.start as _,
+                length: (range.levels.end - range.levels.start) as _,
+            };
+            let slices = NSRange {
+                location: range.layers.start as _,
+                length: (range.layers.end - range.layers.start) as _,
+            };
+            let channels = conv::map_swizzle(swizzle);
+            let view: *mut metal::MTLTexture = msg_send![
+                raw,
+                newTextureViewWithPixelFormat: mtl_format
+                textureType: mtl_type
+                levels: levels
+                slices: slices
+                swizzle: channels
+            ];
+            metal::Texture::from_ptr(view)
         } else {
             raw.new_texture_view_from_slice(
                 mtl_format,
@@ -2440,11 +3073,13 @@ impl hal::Device<Backend> for Device {
         };
         if timeout_ns == !0 {
             cmd_buf.wait_until_completed();
+            self.shared.check_status(cmd_buf)?;
             return Ok(true);
         }
 
         let start = time::Instant::now();
         loop {
+            self.shared.check_status(cmd_buf)?;
             if let metal::MTLCommandBufferStatus::Completed = cmd_buf.status() {
                 return Ok(true);
             }
@@ -2457,14 +3092,33 @@ impl hal::Device<Backend> for Device {
     unsafe fn get_fence_status(&self, fence: &n::Fence) -> Result<bool, DeviceLost> {
         Ok(match *fence.0.borrow() {
             native::FenceInner::Idle { signaled } => signaled,
-            native::FenceInner::Pending(ref cmd_buf) => match cmd_buf.status() {
-                metal::MTLCommandBufferStatus::Completed => true,
-                _ => false,
-            },
+            native::FenceInner::Pending(ref cmd_buf) => {
+                self.shared.check_status(cmd_buf)?;
+                match cmd_buf.status() {
+                    metal::MTLCommandBufferStatus::Completed => true,
+                    _ => false,
+                }
+            }
         })
     }
     unsafe fn destroy_fence(&self, _fence: n::Fence) {}
 
+    fn create_event(&self) -> Result<n::HostEvent, OutOfMemory> {
+        Ok(n::HostEvent(Arc::new(AtomicBool::new(false))))
+    }
+    unsafe fn get_event_status(&self, event: &n::HostEvent) -> Result<bool, OomOrDeviceLost> {
+        Ok(event.0.load(Ordering::Acquire))
+    }
+    unsafe fn set_event(&self, event: &n::HostEvent) -> Result<(), OutOfMemory> {
+        event.0.store(true, Ordering::Release);
+        Ok(())
+    }
+    unsafe fn reset_event(&self, event: &n::HostEvent) -> Result<(), OutOfMemory> {
+        event.0.store(false, Ordering::Release);
+        Ok(())
+    }
+    unsafe fn destroy_event(&self, _event: n::HostEvent) {}
+
     unsafe fn create_query_pool(
         &self,
         ty: query::Type,
@@ -2484,8 +3138,11 @@ impl hal::Device<Backend> for Device {
                     })?;
                 Ok(n::QueryPool::Occlusion(range))
             }
+            query::Type::Timestamp if self.shared.private_caps.gpu_timing => {
+                Ok(n::QueryPool::Timestamp(Arc::new(n::TimestampPool::new(count))))
+            }
             _ => {
-                error!("Only occlusion queries are currently supported");
+                error!("Only occlusion and timestamp queries are currently supported");
                 Err(query::CreationError::Unsupported(ty))
             }
         }
@@ -2496,6 +3153,7 @@ impl hal::Device<Backend> for Device {
             n::QueryPool::Occlusion(range) => {
                 self.shared.visibility.allocator.lock().free_range(range);
             }
+            n::QueryPool::Timestamp(_) => {}
         }
     }
 
@@ -2557,6 +3215,42 @@ impl hal::Device<Backend> for Device {
                     }
                 }
 
+                is_ready
+            }
+            native::QueryPool::Timestamp(ref pool) => {
+                let mut values = pool.values.lock();
+                let range = queries.start as usize..queries.end as usize;
+                if flags.contains(query::ResultFlags::WAIT) {
+                    while values[range.clone()].iter().any(Option::is_none) {
+                        pool.condvar.wait(&mut values);
+                    }
+                }
+
+                let mut is_ready = true;
+                for (i, value) in values[range].iter().enumerate() {
+                    let data_ptr = data[i * stride as usize..].as_mut_ptr();
+                    let availability = value.is_some() as u32;
+                    is_ready &= value.is_some();
+                    let write_value =
+                        value.is_some() || flags.contains(query::ResultFlags::PARTIAL);
+                    let value = value.unwrap_or(0);
+                    if flags.contains(query::ResultFlags::BITS_64) {
+                        if write_value {
+                            *(data_ptr as *mut u64) = value;
+                        }
+                        if flags.contains(query::ResultFlags::WITH_AVAILABILITY) {
+                            *(data_ptr as *mut u64).offset(1) = availability as u64;
+                        }
+                    } else {
+                        if write_value {
+                            *(data_ptr as *mut u32) = value as u32;
+                        }
+                        if flags.contains(query::ResultFlags::WITH_AVAILABILITY) {
+                            *(data_ptr as *mut u32).offset(1) = availability;
+                        }
+                    }
+                }
+
                 is_ready
             }
         };
@@ -2564,6 +3258,23 @@ impl hal::Device<Backend> for Device {
         Ok(is_ready)
     }
 
+    unsafe fn calibrate_timestamps(&self) -> Option<query::TimestampCalibration> {
+        if !self.shared.private_caps.gpu_timing {
+            return None;
+        }
+
+        // Timestamp queries are resolved from the command buffer GPU times,
+        // which are expressed in the host media time, in nanoseconds.
+        let before = time::Instant::now();
+        let seconds = CACurrentMediaTime();
+        let after = time::Instant::now();
+        Some(query::TimestampCalibration {
+            cpu: before + (after - before) / 2,
+            gpu: (seconds * 1.0e9) as u64,
+            period: 1.0,
+        })
+    }
+
     unsafe fn create_swapchain(
         &self,
         surface: &mut Surface,
@@ -11,7 +11,7 @@ use hal::format::{Aspects, ChannelType};
 use hal::image::Filter;
 use hal::pso;
 
-use std::mem;
+use std::{mem, ptr};
 
 pub type FastStorageMap<K, V> = StorageMap<RawRwLock, FastHashMap<K, V>>;
 pub type FastStorageGuard<'a, V> = StorageMapGuard<'a, RawRwLock, V>;
@@ -104,6 +104,41 @@ impl SamplerStates {
     }
 }
 
+/// Compiled shader libraries, keyed by their MSL source and language version,
+/// so that identical shaders across pipeline permutations are only compiled once.
+#[derive(Default)]
+pub struct LibraryCache {
+    map: FastStorageMap<(String, u64), metal::Library>,
+}
+
+impl LibraryCache {
+    pub fn get_or_compile(
+        &self,
+        source: &str,
+        version: metal::MTLLanguageVersion,
+        device: &Mutex<metal::Device>,
+    ) -> Result<metal::Library, String> {
+        let key = (source.to_string(), version as u64);
+
+        let mut compile_error = None;
+        self.map.prepare_maybe(&key, || {
+            let options = metal::CompileOptions::new();
+            options.set_language_version(version);
+            match device.lock().new_library_with_source(source, &options) {
+                Ok(library) => Some(library),
+                Err(e) => {
+                    compile_error = Some(e);
+                    None
+                }
+            }
+        });
+        match compile_error {
+            Some(e) => Err(e),
+            None => Ok(self.map.get_or_create_with(&key, || unreachable!()).clone()),
+        }
+    }
+}
+
 pub struct DepthStencilStates {
     map: FastStorageMap<pso::DepthStencilDesc, metal::DepthStencilState>,
     write_none: pso::DepthStencilDesc,
@@ -187,8 +222,9 @@ impl DepthStencilStates {
     }
 
     pub fn prepare(&self, desc: &pso::DepthStencilDesc, device: &metal::DeviceRef) {
-        self.map.prepare_maybe(desc, || {
-            Self::create_desc(desc).map(|raw_desc| device.new_depth_stencil_state(&raw_desc))
+        let desc = Self::cache_key(desc.clone());
+        self.map.prepare_maybe(&desc, || {
+            Self::create_desc(&desc).map(|raw_desc| device.new_depth_stencil_state(&raw_desc))
         });
     }
 
@@ -198,12 +234,28 @@ impl DepthStencilStates {
         desc: pso::DepthStencilDesc,
         device: &Mutex<metal::Device>,
     ) -> FastStorageGuard<metal::DepthStencilState> {
+        let desc = Self::cache_key(desc);
         self.map.get_or_create_with(&desc, || {
             let raw_desc = Self::create_desc(&desc).expect("Incomplete descriptor provided");
             device.lock().new_depth_stencil_state(&raw_desc)
         })
     }
 
+    /// Strip the state that is not baked into `MTLDepthStencilState`,
+    /// so that descriptors only differing by it share the same object.
+    fn cache_key(mut desc: pso::DepthStencilDesc) -> pso::DepthStencilDesc {
+        // stencil reference values are set on the encoder
+        if let pso::StencilTest::On {
+            ref mut front,
+            ref mut back,
+        } = desc.stencil
+        {
+            front.reference = pso::State::Dynamic;
+            back.reference = pso::State::Dynamic;
+        }
+        desc
+    }
+
     fn create_stencil(face: &pso::StencilFace) -> Option<metal::StencilDescriptor> {
         let desc = metal::StencilDescriptor::new();
         desc.set_stencil_compare_function(conv::map_compare_function(face.fun));
@@ -561,3 +613,59 @@ impl ServicePipes {
         encoder.end_encoding();
     }
 }
+
+/// Shared-storage ring buffer that stages host data for uploads into
+/// private resources. The uploads are all encoded on the same queue,
+/// so waiting for the last one guarantees the whole ring is free.
+pub struct StagingRing {
+    buffer: metal::Buffer,
+    head: u64,
+    last_upload: Option<metal::CommandBuffer>,
+}
+
+impl StagingRing {
+    pub const SIZE: u64 = 4 << 20;
+    const ALIGNMENT: u64 = 256;
+
+    pub fn new(device: &metal::DeviceRef) -> Self {
+        StagingRing {
+            buffer: device.new_buffer(
+                Self::SIZE,
+                metal::MTLResourceOptions::StorageModeShared
+                    | metal::MTLResourceOptions::CPUCacheModeWriteCombined,
+            ),
+            head: 0,
+            last_upload: None,
+        }
+    }
+
+    pub fn buffer(&self) -> &metal::BufferRef {
+        &self.buffer
+    }
+
+    /// Copy the data into the ring, returning the offset it was placed at.
+    /// Blocks on the previous uploads when the ring has to wrap around.
+    pub fn stage(&mut self, data: &[u8]) -> u64 {
+        let size = data.len() as u64;
+        assert!(size <= Self::SIZE);
+        let mut offset = (self.head + Self::ALIGNMENT - 1) & !(Self::ALIGNMENT - 1);
+        if offset + size > Self::SIZE {
+            if let Some(cmd_buf) = self.last_upload.take() {
+                debug!("staging ring is full, waiting for the uploads");
+                cmd_buf.wait_until_completed();
+            }
+            offset = 0;
+        }
+        unsafe {
+            let ptr = (self.buffer.contents() as *mut u8).offset(offset as isize);
+            ptr::copy_nonoverlapping(data.as_ptr(), ptr, data.len());
+        }
+        self.head = offset + size;
+        offset
+    }
+
+    /// Remember the command buffer reading the last staged data.
+    pub fn track(&mut self, cmd_buf: &metal::CommandBufferRef) {
+        self.last_upload = Some(cmd_buf.to_owned());
+    }
+}
@@ -30,19 +30,21 @@ mod soft;
 mod window;
 
 pub use command::CommandPool;
-pub use device::{Device, LanguageVersion, PhysicalDevice};
-pub use window::{AcquireMode, CAMetalLayer, Surface, Swapchain};
+pub use device::{CaptureError, Device, LanguageVersion, PhysicalDevice};
+pub use window::{AcquireMode, CAMetalLayer, LayerConfig, Surface, Swapchain};
 
 pub type GraphicsCommandPool = CommandPool;
 
 use std::mem;
 use std::os::raw::c_void;
 use std::ptr::NonNull;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 
+use hal::device::DeviceLost;
 use hal::queue::QueueFamilyId;
 
-use cocoa::foundation::NSInteger;
+use cocoa::foundation::{NSInteger, NSUInteger};
 use core_graphics::base::CGFloat;
 use core_graphics::geometry::CGRect;
 use foreign_types::ForeignTypeRef;
@@ -107,6 +109,15 @@ struct Shared {
     disabilities: PrivateDisabilities,
     private_caps: PrivateCapabilities,
     visibility: VisibilityShared,
+    /// Libraries compiled from the MSL source, shared by all the shader modules.
+    library_cache: internal::LibraryCache,
+    /// Stages the host data uploaded into private resources.
+    staging: Mutex<internal::StagingRing>,
+    /// Orders the encoders touching resources placed in native heaps,
+    /// or any resources when hazard tracking is turned off.
+    heap_fence: Option<native::HeapFence>,
+    /// Set once a command buffer failed, leaving the resources undefined.
+    device_lost: AtomicBool,
 }
 
 unsafe impl Send for Shared {}
@@ -129,12 +140,19 @@ impl Shared {
                 as hal::buffer::Offset,
             condvar: Condvar::new(),
         };
+        let heap_fence = if private_caps.resource_heaps || private_caps.untracked_resources {
+            Some(native::HeapFence::new(&device))
+        } else {
+            None
+        };
         Shared {
             queue: Mutex::new(command::QueueInner::new(
                 &device,
                 Some(MAX_ACTIVE_COMMAND_BUFFERS),
             )),
             service_pipes: internal::ServicePipes::new(&device),
+            library_cache: internal::LibraryCache::default(),
+            staging: Mutex::new(internal::StagingRing::new(&device)),
             disabilities: PrivateDisabilities {
                 broken_viewport_near_depth: device.name().starts_with("Intel")
                     && !device.supports_feature_set(MTLFeatureSet::macOS_GPUFamily1_v4),
@@ -142,6 +160,21 @@ impl Shared {
             private_caps,
             device: Mutex::new(device),
             visibility,
+            heap_fence,
+            device_lost: AtomicBool::new(false),
+        }
+    }
+
+    /// Check the status of a command buffer, treating errors as a device loss.
+    fn check_status(&self, cmd_buffer: &metal::CommandBufferRef) -> Result<(), DeviceLost> {
+        if let metal::MTLCommandBufferStatus::Error = cmd_buffer.status() {
+            error!("Command buffer failed, the device is considered lost");
+            self.device_lost.store(true, Ordering::Release);
+        }
+        if self.device_lost.load(Ordering::Acquire) {
+            Err(DeviceLost)
+        } else {
+            Ok(())
         }
     }
 }
@@ -347,6 +380,7 @@ impl hal::Backend for Backend {
 
     type Fence = native::Fence;
     type Semaphore = native::Semaphore;
+    type Event = native::HostEvent;
     type QueryPool = native::QueryPool;
 }
 
@@ -355,6 +389,7 @@ const RESOURCE_HEAP_SUPPORT: &[MTLFeatureSet] = &[
     MTLFeatureSet::iOS_GPUFamily2_v3,
     MTLFeatureSet::iOS_GPUFamily3_v2,
     MTLFeatureSet::tvOS_GPUFamily1_v2,
+    MTLFeatureSet::macOS_GPUFamily1_v3,
 ];
 
 const ARGUMENT_BUFFER_SUPPORT: &[MTLFeatureSet] = &[
@@ -376,6 +411,7 @@ const ASTC_PIXEL_FORMAT_FEATURES: &[MTLFeatureSet] = &[
     MTLFeatureSet::tvOS_GPUFamily1_v2,
     MTLFeatureSet::tvOS_GPUFamily1_v3,
     MTLFeatureSet::tvOS_GPUFamily2_v1,
+    MTLFeatureSet::iOS_GPUFamily5_v1,
 ];
 
 const R8UNORM_SRGB_ALL: &[MTLFeatureSet] = &[
@@ -479,6 +515,8 @@ const BGR10A2_ALL: &[MTLFeatureSet] = &[
 const BASE_INSTANCE_SUPPORT: &[MTLFeatureSet] = &[
     MTLFeatureSet::iOS_GPUFamily1_v4,
     MTLFeatureSet::iOS_GPUFamily3_v1,
+    MTLFeatureSet::tvOS_GPUFamily1_v3,
+    MTLFeatureSet::macOS_GPUFamily1_v1,
 ];
 
 const DUAL_SOURCE_BLEND_SUPPORT: &[MTLFeatureSet] = &[
@@ -492,12 +530,52 @@ const LAYERED_RENDERING_SUPPORT: &[MTLFeatureSet] = &[
     MTLFeatureSet::macOS_GPUFamily1_v1,
 ];
 
+const MEMORYLESS_SUPPORT: &[MTLFeatureSet] = &[
+    MTLFeatureSet::iOS_GPUFamily1_v3,
+    MTLFeatureSet::iOS_GPUFamily2_v3,
+    MTLFeatureSet::iOS_GPUFamily3_v2,
+    MTLFeatureSet::tvOS_GPUFamily1_v2,
+];
+
 const FUNCTION_SPECIALIZATION_SUPPORT: &[MTLFeatureSet] = &[
     MTLFeatureSet::iOS_GPUFamily1_v3,
     MTLFeatureSet::tvOS_GPUFamily1_v2,
     MTLFeatureSet::macOS_GPUFamily1_v2,
 ];
 
+const INDIRECT_COMMAND_BUFFER_SUPPORT: &[MTLFeatureSet] = &[
+    MTLFeatureSet::iOS_GPUFamily3_v3,
+    MTLFeatureSet::iOS_GPUFamily4_v1,
+    MTLFeatureSet::iOS_GPUFamily5_v1,
+    MTLFeatureSet::macOS_GPUFamily1_v4,
+];
+
+const LARGE_THREADGROUP_SUPPORT: &[MTLFeatureSet] = &[
+    MTLFeatureSet::iOS_GPUFamily4_v1,
+    MTLFeatureSet::iOS_GPUFamily5_v1,
+    MTLFeatureSet::tvOS_GPUFamily2_v1,
+    MTLFeatureSet::macOS_GPUFamily1_v1,
+];
+
+const TILE_SHADING_SUPPORT: &[MTLFeatureSet] = &[
+    MTLFeatureSet::iOS_GPUFamily4_v1,
+    MTLFeatureSet::iOS_GPUFamily5_v1,
+];
+
+const TEXTURE_16K_SUPPORT: &[MTLFeatureSet] = &[
+    MTLFeatureSet::iOS_GPUFamily3_v1,
+    MTLFeatureSet::iOS_GPUFamily4_v1,
+    MTLFeatureSet::iOS_GPUFamily5_v1,
+    MTLFeatureSet::tvOS_GPUFamily2_v1,
+    MTLFeatureSet::macOS_GPUFamily1_v1,
+];
+
+const TEXTURE_8K_SUPPORT: &[MTLFeatureSet] = &[
+    MTLFeatureSet::iOS_GPUFamily1_v2,
+    MTLFeatureSet::iOS_GPUFamily2_v2,
+    MTLFeatureSet::tvOS_GPUFamily1_v1,
+];
+
 const DEPTH_CLIP_MODE: &[MTLFeatureSet] = &[
     MTLFeatureSet::iOS_GPUFamily2_v1,
     MTLFeatureSet::tvOS_GPUFamily1_v3,
@@ -511,6 +589,7 @@ struct PrivateCapabilities {
     msl_version: metal::MTLLanguageVersion,
     exposed_queues: usize,
     resource_heaps: bool,
+    untracked_resources: bool,
     argument_buffers: bool,
     shared_textures: bool,
     base_instance: bool,
@@ -518,8 +597,16 @@ struct PrivateCapabilities {
     low_power: bool,
     headless: bool,
     layered_rendering: bool,
+    memoryless: bool,
     function_specialization: bool,
     depth_clip_mode: bool,
+    tile_shading: bool,
+    store_and_resolve: bool,
+    texture_swizzle: bool,
+    capture_descriptor: bool,
+    gpu_timing: bool,
+    events: bool,
+    indirect_command_buffers: bool,
     format_depth24_stencil8: bool,
     format_depth32_stencil8_filter: bool,
     format_depth32_stencil8_none: bool,
@@ -569,9 +656,15 @@ struct PrivateCapabilities {
     max_buffers_per_stage: ResourceIndex,
     max_textures_per_stage: ResourceIndex,
     max_samplers_per_stage: ResourceIndex,
+    max_threads_per_group: u32,
     buffer_alignment: u64,
     max_buffer_size: u64,
     max_texture_size: u64,
+    max_texture_3d_size: u64,
+    max_texture_layers: u64,
+    max_threadgroup_memory: u64,
+    max_color_attachments: usize,
+    sample_count_mask: u8,
 }
 
 impl PrivateCapabilities {
@@ -632,15 +725,61 @@ impl PrivateCapabilities {
             },
             exposed_queues: 1,
             resource_heaps: Self::supports_any(&device, RESOURCE_HEAP_SUPPORT),
-            argument_buffers: Self::supports_any(&device, ARGUMENT_BUFFER_SUPPORT) && false, //TODO
+            // Fences come together with the heaps, and the backend relies on them
+            // to order the encoders once Metal stops tracking the resources.
+            untracked_resources: cfg!(feature = "untracked-resources")
+                && Self::supports_any(&device, RESOURCE_HEAP_SUPPORT),
+            // Argument buffers require the shaders to be translated accordingly,
+            // so they are only used when explicitly opted into. Tier 1 can't write
+            // to textures through them, in which case the emulated binding is used.
+            argument_buffers: cfg!(feature = "argument-buffers")
+                && Self::supports_any(&device, ARGUMENT_BUFFER_SUPPORT)
+                && {
+                    let raw: &metal::DeviceRef = device;
+                    let tier: NSUInteger = unsafe { msg_send![raw, argumentBuffersSupport] };
+                    tier >= 1 // MTLArgumentBuffersTier2
+                },
             shared_textures: !os_is_mac,
             base_instance: Self::supports_any(&device, BASE_INSTANCE_SUPPORT),
             dual_source_blending: Self::supports_any(&device, DUAL_SOURCE_BLEND_SUPPORT),
             low_power: !os_is_mac || device.is_low_power(),
             headless: os_is_mac && device.is_headless(),
             layered_rendering: Self::supports_any(&device, LAYERED_RENDERING_SUPPORT),
+            memoryless: Self::supports_any(&device, MEMORYLESS_SUPPORT),
             function_specialization: Self::supports_any(&device, FUNCTION_SPECIALIZATION_SUPPORT),
             depth_clip_mode: Self::supports_any(&device, DEPTH_CLIP_MODE),
+            tile_shading: Self::supports_any(&device, TILE_SHADING_SUPPORT),
+            store_and_resolve: if os_is_mac {
+                Self::version_at_least(major, minor, 10, 12)
+            } else {
+                Self::version_at_least(major, minor, 10, 0)
+            },
+            texture_swizzle: if os_is_mac {
+                Self::version_at_least(major, minor, 10, 15)
+            } else {
+                Self::version_at_least(major, minor, 13, 0)
+            },
+            capture_descriptor: if os_is_mac {
+                Self::version_at_least(major, minor, 10, 15)
+            } else {
+                Self::version_at_least(major, minor, 13, 0)
+            },
+            gpu_timing: if os_is_mac {
+                Self::version_at_least(major, minor, 10, 15)
+            } else {
+                Self::version_at_least(major, minor, 10, 3)
+            },
+            events: if os_is_mac {
+                Self::version_at_least(major, minor, 10, 14)
+            } else {
+                Self::version_at_least(major, minor, 12, 0)
+            },
+            indirect_command_buffers: Self::supports_any(&device, INDIRECT_COMMAND_BUFFER_SUPPORT)
+                && if os_is_mac {
+                    Self::version_at_least(major, minor, 10, 14)
+                } else {
+                    Self::version_at_least(major, minor, 12, 0)
+                },
             format_depth24_stencil8: os_is_mac && device.d24_s8_supported(),
             format_depth32_stencil8_filter: os_is_mac,
             format_depth32_stencil8_none: !os_is_mac,
@@ -788,8 +927,19 @@ impl PrivateCapabilities {
             max_buffers_per_stage: 31,
             max_textures_per_stage: if os_is_mac { 128 } else { 31 },
             max_samplers_per_stage: 16,
+            max_threads_per_group: if Self::supports_any(&device, LARGE_THREADGROUP_SUPPORT) {
+                1024
+            } else {
+                512
+            },
             buffer_alignment: if os_is_mac { 256 } else { 64 },
-            max_buffer_size: if Self::supports_any(
+            max_buffer_size: if (os_is_mac && Self::version_at_least(major, minor, 10, 14))
+                || (!os_is_mac && Self::version_at_least(major, minor, 12, 0))
+            {
+                let raw: &metal::DeviceRef = device;
+                let length: NSUInteger = unsafe { msg_send![raw, maxBufferLength] };
+                length as u64
+            } else if Self::supports_any(
                 &device,
                 &[
                     MTLFeatureSet::macOS_GPUFamily1_v2,
@@ -800,7 +950,43 @@ impl PrivateCapabilities {
             } else {
                 1 << 28 // 256MB otherwise
             },
-            max_texture_size: 4096, //TODO
+            max_texture_size: if Self::supports_any(&device, TEXTURE_16K_SUPPORT) {
+                16384
+            } else if Self::supports_any(&device, TEXTURE_8K_SUPPORT) {
+                8192
+            } else {
+                4096
+            },
+            max_texture_3d_size: 2048,
+            max_texture_layers: 2048,
+            max_threadgroup_memory: if (os_is_mac && Self::version_at_least(major, minor, 10, 13))
+                || (!os_is_mac && Self::version_at_least(major, minor, 11, 0))
+            {
+                let raw: &metal::DeviceRef = device;
+                let length: NSUInteger = unsafe { msg_send![raw, maxThreadgroupMemoryLength] };
+                length as u64
+            } else if Self::supports_any(&device, LARGE_THREADGROUP_SUPPORT) {
+                32 << 10
+            } else {
+                16 << 10
+            },
+            max_color_attachments: if os_is_mac
+                || device.supports_feature_set(MTLFeatureSet::iOS_GPUFamily2_v1)
+                || device.supports_feature_set(MTLFeatureSet::tvOS_GPUFamily1_v1)
+            {
+                8
+            } else {
+                4
+            },
+            sample_count_mask: [1u8, 2, 4, 8]
+                .iter()
+                .filter(|&&count| {
+                    let raw: &metal::DeviceRef = device;
+                    let supported: BOOL =
+                        unsafe { msg_send![raw, supportsTextureSampleCount: count as NSUInteger] };
+                    supported == YES
+                })
+                .fold(0, |mask, &count| mask | count),
         }
     }
 
@@ -831,6 +1017,7 @@ trait AsNative {
 pub type BufferPtr = NonNull<metal::MTLBuffer>;
 pub type TexturePtr = NonNull<metal::MTLTexture>;
 pub type SamplerPtr = NonNull<metal::MTLSamplerState>;
+pub type ResourcePtr = NonNull<metal::MTLResource>;
 
 impl AsNative for BufferPtr {
     type Native = metal::BufferRef;
@@ -867,3 +1054,15 @@ impl AsNative for SamplerPtr {
         unsafe { metal::SamplerStateRef::from_ptr(self.as_ptr()) }
     }
 }
+
+impl AsNative for ResourcePtr {
+    type Native = metal::ResourceRef;
+    #[inline]
+    fn from(native: &metal::ResourceRef) -> Self {
+        unsafe { NonNull::new_unchecked(native.as_ptr()) }
+    }
+    #[inline]
+    fn as_native(&self) -> &metal::ResourceRef {
+        unsafe { metal::ResourceRef::from_ptr(self.as_ptr()) }
+    }
+}
@@ -1,24 +1,26 @@
 use internal::{Channel, FastStorageMap};
 use range_alloc::RangeAllocator;
 use window::SwapchainImage;
-use {AsNative, Backend, BufferPtr, ResourceIndex, SamplerPtr, TexturePtr};
+use {AsNative, Backend, BufferPtr, ResourceIndex, ResourcePtr, SamplerPtr, TexturePtr};
 
 use std::cell::RefCell;
 use std::fmt;
 use std::ops::Range;
 use std::os::raw::{c_long, c_void};
+use std::sync::atomic::AtomicBool;
 use std::sync::Arc;
 
 use hal::backend::FastHashMap;
 use hal::format::FormatDesc;
-use hal::pass::{Attachment, AttachmentId};
+use hal::pass::{Attachment, AttachmentId, SubpassId};
 use hal::range::RangeArg;
-use hal::{buffer, image, pso};
+use hal::{buffer, image, pso, query};
 use hal::{DescriptorPool as HalDescriptorPool, MemoryTypeId};
 
 use cocoa::foundation::{NSRange, NSUInteger};
 use metal;
-use parking_lot::{Mutex, RwLock};
+use objc::runtime::Object;
+use parking_lot::{Condvar, Mutex, RwLock};
 use smallvec::SmallVec;
 use spirv_cross::{msl, spirv};
 
@@ -74,6 +76,8 @@ pub struct Subpass {
     pub colors: Vec<(AttachmentId, SubpassOps)>,
     pub depth_stencil: Option<(AttachmentId, SubpassOps)>,
     pub inputs: Vec<AttachmentId>,
+    /// Resolve destinations, either empty or one per color attachment.
+    pub resolves: Vec<AttachmentId>,
     pub target_formats: SubpassFormats,
 }
 
@@ -90,11 +94,26 @@ unsafe impl Sync for RenderPass {}
 pub struct Framebuffer {
     pub(crate) extent: image::Extent,
     pub(crate) attachments: Vec<metal::Texture>,
+    /// Parallel pass currently recorded into this framebuffer by a primary command buffer.
+    pub(crate) parallel_pass: Arc<Mutex<Option<ParallelPass>>>,
 }
 
 unsafe impl Send for Framebuffer {}
 unsafe impl Sync for Framebuffer {}
 
+/// Parallel render encoder of a subpass with secondary buffers contents.
+/// Secondary command buffers begun for this subpass encode directly into
+/// its sub-encoders, which are executed in the order of creation.
+#[derive(Debug)]
+pub(crate) struct ParallelPass {
+    pub(crate) subpass: SubpassId,
+    pub(crate) encoder: metal::ParallelRenderCommandEncoder,
+    /// Number of sub-encoders created so far.
+    pub(crate) num_created: usize,
+    /// Number of sub-encoders executed by the primary command buffer so far.
+    pub(crate) num_executed: usize,
+}
+
 #[derive(Clone, Debug)]
 pub struct ResourceData<T> {
     pub buffers: T,
@@ -291,6 +310,8 @@ pub enum ImageLike {
         descriptor: metal::TextureDescriptor,
         mip_sizes: Vec<buffer::Offset>,
         host_visible: bool,
+        /// Transient attachments live in the tile memory only.
+        memoryless: bool,
     },
     /// This is a linearly tiled HOST-visible image, which is represented by a buffer.
     Buffer(Buffer),
@@ -318,6 +339,15 @@ pub struct Image {
 }
 
 impl Image {
+    /// Underlying texture, `None` until the image is bound to memory
+    /// or if it's a linear image backed by a buffer.
+    pub fn as_raw(&self) -> Option<&metal::TextureRef> {
+        match self.like {
+            ImageLike::Texture(ref tex) => Some(tex),
+            ImageLike::Unbound { .. } | ImageLike::Buffer(..) => None,
+        }
+    }
+
     pub(crate) fn pitches_impl(
         extent: image::Extent,
         format_desc: FormatDesc,
@@ -381,6 +411,13 @@ pub struct BufferView {
 unsafe impl Send for BufferView {}
 unsafe impl Sync for BufferView {}
 
+impl BufferView {
+    /// Texture aliasing the buffer contents.
+    pub fn as_raw(&self) -> &metal::TextureRef {
+        &self.raw
+    }
+}
+
 #[derive(Debug)]
 pub struct ImageView {
     pub(crate) raw: metal::Texture,
@@ -390,18 +427,75 @@ pub struct ImageView {
 unsafe impl Send for ImageView {}
 unsafe impl Sync for ImageView {}
 
+impl ImageView {
+    /// Texture view, or the image texture itself if no view was needed.
+    pub fn as_raw(&self) -> &metal::TextureRef {
+        &self.raw
+    }
+}
+
 #[derive(Debug)]
 pub struct Sampler(pub(crate) metal::SamplerState);
 
 unsafe impl Send for Sampler {}
 unsafe impl Sync for Sampler {}
 
+impl Sampler {
+    /// Underlying sampler state.
+    pub fn as_raw(&self) -> &metal::SamplerStateRef {
+        &self.0
+    }
+}
+
 #[derive(Debug)]
 pub struct Semaphore {
     pub(crate) system: Option<SystemSemaphore>,
+    pub(crate) event: Option<Event>,
     pub(crate) image_ready: Arc<Mutex<Option<SwapchainImage>>>,
 }
 
+/// Host-visible event, flipped once the command buffers recording
+/// `set_event` and `reset_event` are completed, in submission order.
+#[derive(Clone, Debug)]
+pub struct HostEvent(pub(crate) Arc<AtomicBool>);
+
+/// GPU-side semaphore based on `MTLEvent`. Every signal bumps the value,
+/// which the following wait is then expecting to be reached.
+#[derive(Debug)]
+pub struct Event {
+    raw: *mut Object,
+    value: Mutex<u64>,
+}
+
+unsafe impl Send for Event {}
+unsafe impl Sync for Event {}
+
+impl Event {
+    pub(crate) fn new(device: &metal::DeviceRef) -> Self {
+        Event {
+            raw: unsafe { msg_send![device, newEvent] },
+            value: Mutex::new(0),
+        }
+    }
+
+    pub(crate) fn signal(&self, cmd_buffer: &metal::CommandBufferRef) {
+        let mut value = self.value.lock();
+        *value += 1;
+        unsafe { msg_send![cmd_buffer, encodeSignalEvent: self.raw value: *value] }
+    }
+
+    pub(crate) fn wait(&self, cmd_buffer: &metal::CommandBufferRef) {
+        let value = *self.value.lock();
+        unsafe { msg_send![cmd_buffer, encodeWaitForEvent: self.raw value: value] }
+    }
+}
+
+impl Drop for Event {
+    fn drop(&mut self) {
+        unsafe { msg_send![self.raw, release] }
+    }
+}
+
 #[derive(Debug)]
 pub enum Buffer {
     Unbound {
@@ -605,8 +699,12 @@ impl HalDescriptorPool<Backend> for DescriptorPool {
                 ref raw,
                 ref mut range_allocator,
             } => {
-                let (encoder, stage_flags) = match set_layout {
-                    &DescriptorSetLayout::ArgumentBuffer(ref encoder, stages) => (encoder, stages),
+                let (encoder, stage_flags, binding_offsets) = match *set_layout {
+                    DescriptorSetLayout::ArgumentBuffer {
+                        ref encoder,
+                        stage_flags,
+                        ref binding_offsets,
+                    } => (encoder, stage_flags, binding_offsets),
                     _ => return Err(pso::AllocationError::IncompatibleLayout),
                 };
                 match range_allocator.allocate_range(encoder.encoded_length()) {
@@ -615,6 +713,8 @@ impl HalDescriptorPool<Backend> for DescriptorPool {
                         offset: range.start,
                         encoder: encoder.clone(),
                         stage_flags,
+                        binding_offsets: Arc::clone(binding_offsets),
+                        resources: Arc::new(Mutex::new(FastHashMap::default())),
                     }),
                     Err(_) => Err(pso::AllocationError::OutOfPoolMemory),
                 }
@@ -781,7 +881,12 @@ pub struct DescriptorLayout {
 #[derive(Debug)]
 pub enum DescriptorSetLayout {
     Emulated(Arc<Vec<DescriptorLayout>>, Vec<metal::SamplerState>),
-    ArgumentBuffer(metal::ArgumentEncoder, pso::ShaderStageFlags),
+    ArgumentBuffer {
+        encoder: metal::ArgumentEncoder,
+        stage_flags: pso::ShaderStageFlags,
+        /// Argument index of the first element of each binding.
+        binding_offsets: Arc<FastHashMap<pso::DescriptorBinding, NSUInteger>>,
+    },
 }
 unsafe impl Send for DescriptorSetLayout {}
 unsafe impl Sync for DescriptorSetLayout {}
@@ -798,6 +903,10 @@ pub enum DescriptorSet {
         offset: NSUInteger,
         encoder: metal::ArgumentEncoder,
         stage_flags: pso::ShaderStageFlags,
+        binding_offsets: Arc<FastHashMap<pso::DescriptorBinding, NSUInteger>>,
+        /// Resources referenced by the encoded arguments, keyed by the argument index.
+        /// They need to be made resident by every encoder using the set.
+        resources: Arc<Mutex<FastHashMap<NSUInteger, (ResourcePtr, metal::MTLResourceUsage)>>>,
     },
 }
 unsafe impl Send for DescriptorSet {}
@@ -826,12 +935,138 @@ unsafe impl Sync for Memory {}
 pub(crate) enum MemoryHeap {
     Private,
     Public(MemoryTypeId, metal::Buffer),
-    Native(metal::Heap),
+    Native(metal::Heap, Mutex<Vec<PlacedResource>>),
+}
+
+/// A resource sub-allocated from a native heap, alongside the range of the
+/// memory object it was bound to.
+#[derive(Debug)]
+pub(crate) struct PlacedResource {
+    pub range: Range<u64>,
+    pub raw: metal::Resource,
+}
+
+impl MemoryHeap {
+    /// Register a new resource placed in the native heap at the given range.
+    /// Any previous resources overlapping with it are made aliasable, so that
+    /// Metal can reuse their memory for the new one.
+    pub(crate) fn place(&self, range: Range<u64>, raw: &metal::ResourceRef) {
+        if let MemoryHeap::Native(_, ref placed) = *self {
+            let mut placed = placed.lock();
+            placed.retain(|res| {
+                let overlaps = res.range.start < range.end && range.start < res.range.end;
+                if overlaps {
+                    debug!("\taliasing the resource at {:?}", res.range);
+                    let resource: &metal::ResourceRef = &res.raw;
+                    unsafe { msg_send![resource, makeAliasable] };
+                }
+                !overlaps
+            });
+            placed.push(PlacedResource {
+                range,
+                raw: raw.to_owned(),
+            });
+        }
+    }
+}
+
+/// A fence used to order the encoders within a command buffer, since the
+/// resources placed in native heaps are not tracked by Metal.
+#[derive(Debug)]
+pub struct HeapFence(*mut Object);
+
+unsafe impl Send for HeapFence {}
+unsafe impl Sync for HeapFence {}
+
+impl HeapFence {
+    pub(crate) fn new(device: &metal::DeviceRef) -> Self {
+        HeapFence(unsafe { msg_send![device, newFence] })
+    }
+
+    pub(crate) fn wait_render(&self, encoder: &metal::RenderCommandEncoderRef) {
+        let stages: NSUInteger = MTL_RENDER_STAGE_VERTEX;
+        unsafe { msg_send![encoder, waitForFence: self.0 beforeStages: stages] }
+    }
+
+    pub(crate) fn update_render(&self, encoder: &metal::RenderCommandEncoderRef) {
+        let stages: NSUInteger = MTL_RENDER_STAGE_FRAGMENT;
+        unsafe { msg_send![encoder, updateFence: self.0 afterStages: stages] }
+    }
+
+    pub(crate) fn wait(&self, encoder: &metal::CommandEncoderRef) {
+        unsafe { msg_send![encoder, waitForFence: self.0] }
+    }
+
+    pub(crate) fn update(&self, encoder: &metal::CommandEncoderRef) {
+        unsafe { msg_send![encoder, updateFence: self.0] }
+    }
+}
+
+impl Clone for HeapFence {
+    fn clone(&self) -> Self {
+        HeapFence(unsafe { msg_send![self.0, retain] })
+    }
+}
+
+impl Drop for HeapFence {
+    fn drop(&mut self) {
+        unsafe { msg_send![self.0, release] }
+    }
+}
+
+const MTL_RENDER_STAGE_VERTEX: NSUInteger = 1;
+const MTL_RENDER_STAGE_FRAGMENT: NSUInteger = 2;
+
+bitflags! {
+    /// Kinds of commands that can be encoded into an indirect command buffer.
+    pub struct IndirectCommandTypes: NSUInteger {
+        const DRAW = 1 << 0;
+        const DRAW_INDEXED = 1 << 1;
+    }
+}
+
+/// A buffer of render commands that can be encoded by compute kernels on the GPU,
+/// and then executed within a render pass.
+#[derive(Debug)]
+pub struct IndirectCommandBuffer {
+    pub(crate) raw: metal::Resource,
+    pub(crate) max_commands: u32,
+}
+
+unsafe impl Send for IndirectCommandBuffer {}
+unsafe impl Sync for IndirectCommandBuffer {}
+
+impl IndirectCommandBuffer {
+    /// Encode this command buffer into an argument buffer, so that
+    /// a compute kernel is able to fill it up.
+    pub fn encode_argument(&self, encoder: &metal::ArgumentEncoderRef, index: NSUInteger) {
+        let raw: &metal::ResourceRef = &self.raw;
+        unsafe { msg_send![encoder, setIndirectCommandBuffer: raw atIndex: index] }
+    }
 }
 
 #[derive(Debug)]
 pub enum QueryPool {
     Occlusion(Range<u32>),
+    Timestamp(Arc<TimestampPool>),
+}
+
+/// Timestamps are resolved on the CPU when the command buffers writing them
+/// are completed, so they have the granularity of a command buffer.
+/// Values are in nanoseconds.
+#[derive(Debug)]
+pub struct TimestampPool {
+    pub(crate) values: Mutex<Vec<Option<u64>>>,
+    pub(crate) condvar: Condvar,
+}
+
+impl TimestampPool {
+    pub(crate) fn new(count: query::Id) -> Self {
+        TimestampPool {
+            values: Mutex::new(vec![None; count as usize]),
+            condvar: Condvar::new(),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -854,8 +1089,13 @@ extern "C" {
     fn dispatch_semaphore_create(value: c_long) -> *mut c_void;
 
     fn dispatch_release(object: *mut c_void);
+
+    fn dispatch_time(when: u64, delta: i64) -> u64;
 }
 
+const DISPATCH_TIME_NOW: u64 = 0;
+const DISPATCH_TIME_FOREVER: u64 = !0;
+
 #[derive(Clone, Debug)]
 pub struct SystemSemaphore(*mut c_void);
 unsafe impl Send for SystemSemaphore {}
@@ -868,7 +1108,10 @@ impl Drop for SystemSemaphore {
 }
 impl SystemSemaphore {
     pub(crate) fn new() -> Self {
-        SystemSemaphore(unsafe { dispatch_semaphore_create(1) })
+        Self::with_count(1)
+    }
+    pub(crate) fn with_count(count: usize) -> Self {
+        SystemSemaphore(unsafe { dispatch_semaphore_create(count as c_long) })
     }
     pub(crate) fn signal(&self) {
         unsafe {
@@ -880,4 +1123,14 @@ impl SystemSemaphore {
             dispatch_semaphore_wait(self.0, timeout);
         }
     }
+    /// Wait for up to the given number of nanoseconds.
+    /// Returns false if the semaphore hasn't been signalled in time.
+    pub(crate) fn wait_ns(&self, timeout_ns: u64) -> bool {
+        let timeout = if timeout_ns > i64::max_value() as u64 {
+            DISPATCH_TIME_FOREVER
+        } else {
+            unsafe { dispatch_time(DISPATCH_TIME_NOW, timeout_ns as i64) }
+        };
+        unsafe { dispatch_semaphore_wait(self.0, timeout) == 0 }
+    }
 }
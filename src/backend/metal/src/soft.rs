@@ -1,6 +1,6 @@
 use command::IndexBuffer;
 use native::RasterizerState;
-use {BufferPtr, ResourceIndex, SamplerPtr, TexturePtr};
+use {BufferPtr, ResourceIndex, ResourcePtr, SamplerPtr, TexturePtr};
 
 use hal;
 use metal;
@@ -88,6 +88,10 @@ pub enum RenderCommand<R: Resources> {
         samplers: R::SamplerArray,
     },
     BindPipeline(R::RenderPipeline),
+    UseResource {
+        resource: ResourcePtr,
+        usage: metal::MTLResourceUsage,
+    },
     Draw {
         primitive_type: metal::MTLPrimitiveType,
         vertices: Range<hal::VertexCount>,
@@ -111,6 +115,10 @@ pub enum RenderCommand<R: Resources> {
         buffer: BufferPtr,
         offset: hal::buffer::Offset,
     },
+    ExecuteIndirect {
+        commands: ResourcePtr,
+        range: Range<u32>,
+    },
 }
 
 #[derive(Clone, Debug)]
@@ -168,6 +176,10 @@ pub enum ComputeCommand<R: Resources> {
         samplers: R::SamplerArray,
     },
     BindPipeline(R::ComputePipeline),
+    UseResource {
+        resource: ResourcePtr,
+        usage: metal::MTLResourceUsage,
+    },
     Dispatch {
         wg_size: metal::MTLSize,
         wg_count: metal::MTLSize,
@@ -266,6 +278,7 @@ impl Own {
                 },
             },
             BindPipeline(pso) => BindPipeline(pso.to_owned()),
+            UseResource { resource, usage } => UseResource { resource, usage },
             Draw {
                 primitive_type,
                 vertices,
@@ -308,6 +321,7 @@ impl Own {
                 buffer,
                 offset,
             },
+            ExecuteIndirect { commands, range } => ExecuteIndirect { commands, range },
         }
     }
 
@@ -356,6 +370,7 @@ impl Own {
                 },
             },
             BindPipeline(pso) => BindPipeline(pso.to_owned()),
+            UseResource { resource, usage } => UseResource { resource, usage },
             Dispatch { wg_size, wg_count } => Dispatch { wg_size, wg_count },
             DispatchIndirect {
                 wg_size,
@@ -395,10 +410,12 @@ impl Own {
                 samplers.end += self.samplers.len() as CacheResourceIndex;
             }
             BindPipeline(..) |
+            UseResource { .. } |
             Draw { .. } |
             DrawIndexed { .. } |
             DrawIndirect { .. } |
-            DrawIndexedIndirect { .. } => {}
+            DrawIndexedIndirect { .. } |
+            ExecuteIndirect { .. } => {}
         }
     }
 
@@ -420,6 +437,7 @@ impl Own {
                 samplers.end += self.samplers.len() as CacheResourceIndex;
             }
             BindPipeline(..) |
+            UseResource { .. } |
             Dispatch { .. } |
             DispatchIndirect { .. } => {}
         }
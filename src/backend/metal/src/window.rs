@@ -3,6 +3,7 @@ use internal::Channel;
 use native;
 use {Backend, QueueFamily};
 
+use std::os::raw::c_void;
 use std::ptr::NonNull;
 use std::sync::Arc;
 use std::thread;
@@ -12,6 +13,7 @@ use hal::{self, format, image};
 use hal::{Backbuffer, SwapchainConfig, CompositeAlpha};
 
 use core_graphics::base::CGFloat;
+use core_graphics::color_space::CGColorSpace;
 use core_graphics::geometry::{CGRect, CGSize};
 use foreign_types::{ForeignType, ForeignTypeRef};
 use metal;
@@ -19,6 +21,34 @@ use objc::rc::autoreleasepool;
 use objc::runtime::Object;
 use parking_lot::{Mutex, MutexGuard};
 
+#[link(name = "CoreGraphics", kind = "framework")]
+extern "C" {
+    static kCGColorSpaceSRGB: *const c_void;
+    static kCGColorSpaceDisplayP3: *const c_void;
+    static kCGColorSpaceExtendedLinearSRGB: *const c_void;
+    fn CGColorSpaceCreateWithName(name: *const c_void) -> *mut c_void;
+}
+
+/// Pick the color space matching the swapchain format: sRGB for the 8-bit
+/// formats, Display P3 for the 10-bit wide color, and the extended linear
+/// sRGB for the half-float EDR content.
+fn default_color_space(format: format::Format) -> Option<CGColorSpace> {
+    let name = unsafe {
+        match format {
+            format::Format::Bgra8Srgb => kCGColorSpaceSRGB,
+            format::Format::A2r10g10b10Unorm => kCGColorSpaceDisplayP3,
+            format::Format::Rgba16Float => kCGColorSpaceExtendedLinearSRGB,
+            _ => return None,
+        }
+    };
+    let raw = unsafe { CGColorSpaceCreateWithName(name) };
+    if raw.is_null() {
+        None
+    } else {
+        Some(unsafe { CGColorSpace::from_ptr(raw as *mut _) })
+    }
+}
+
 //TODO: make it a weak pointer, so that we know which
 // frames can be replaced if we receive an unknown
 // texture pointer by an acquired drawable.
@@ -27,6 +57,32 @@ pub type CAMetalLayer = *mut Object;
 pub struct Surface {
     inner: Arc<SurfaceInner>,
     main_thread_id: thread::ThreadId,
+    /// Configuration of the `CAMetalLayer`, applied upon swapchain creation.
+    pub layer_config: LayerConfig,
+}
+
+/// Metal-specific settings of the `CAMetalLayer` backing a surface.
+/// Every `None` value is derived from the `SwapchainConfig` instead.
+#[derive(Clone, Default)]
+pub struct LayerConfig {
+    /// Overrides `displaySyncEnabled`, which is otherwise enabled for all
+    /// present modes but `Immediate`. Only respected on macOS 10.13+.
+    pub display_sync: Option<bool>,
+    /// Overrides `maximumDrawableCount`, which is otherwise the image count.
+    /// Metal only accepts 2 or 3.
+    pub maximum_drawable_count: Option<u32>,
+    /// Synchronizes the presentation with Core Animation transactions,
+    /// at the cost of additional latency.
+    pub presents_with_transaction: bool,
+    /// Overrides the drawable pixel format, which has to have the same
+    /// size and channel layout as the swapchain format.
+    pub pixel_format: Option<metal::MTLPixelFormat>,
+    /// Overrides the number of presented frames the GPU can be working on
+    /// before the image acquisition blocks, which is otherwise the image count.
+    pub frames_in_flight: Option<u32>,
+    /// Color space of the drawables, which is otherwise picked to match
+    /// the swapchain format. Only respected on macOS.
+    pub color_space: Option<CGColorSpace>,
 }
 
 #[derive(Debug)]
@@ -68,6 +124,7 @@ impl SurfaceInner {
         Surface {
             inner: Arc::new(self),
             main_thread_id: thread::current().id(),
+            layer_config: LayerConfig::default(),
         }
     }
 
@@ -176,6 +233,8 @@ pub struct Swapchain {
     extent: Extent2D,
     last_frame: usize,
     image_ready_callbacks: Vec<Arc<Mutex<Option<SwapchainImage>>>>,
+    /// Throttles the acquisition by the number of frames in flight.
+    pub(crate) frame_throttle: Arc<native::SystemSemaphore>,
     pub acquire_mode: AcquireMode,
 }
 
@@ -324,11 +383,19 @@ impl hal::Surface<Backend> for Surface {
             composite_alpha: CompositeAlpha::OPAQUE, //TODO
         };
 
-        let formats = vec![
+        let mut formats = vec![
             format::Format::Bgra8Unorm,
             format::Format::Bgra8Srgb,
             format::Format::Rgba16Float,
         ];
+        let can_present_bgr10a2 = if device_caps.os_is_mac {
+            device_caps.has_version_at_least(10, 13)
+        } else {
+            device_caps.format_bgr10a2_all
+        };
+        if can_present_bgr10a2 {
+            formats.push(format::Format::A2r10g10b10Unorm);
+        }
 
         let can_set_display_sync =
             device_caps.os_is_mac && device_caps.has_version_at_least(10, 13);
@@ -361,15 +428,28 @@ impl Device {
         }
 
         let caps = &self.shared.private_caps;
-        let mtl_format = caps
-            .map_format(config.format)
-            .expect("unsupported backbuffer format");
+        let layer_config = &surface.layer_config;
+        let mtl_format = match layer_config.pixel_format {
+            Some(format) => format,
+            None => caps
+                .map_format(config.format)
+                .expect("unsupported backbuffer format"),
+        };
 
         let render_layer_borrow = surface.inner.render_layer.lock();
         let render_layer = *render_layer_borrow;
         let format_desc = config.format.surface_desc();
         let framebuffer_only = config.image_usage == image::Usage::COLOR_ATTACHMENT;
-        let display_sync = config.present_mode != hal::PresentMode::Immediate;
+        let display_sync = layer_config
+            .display_sync
+            .unwrap_or(config.present_mode != hal::PresentMode::Immediate);
+        let max_drawable_count = layer_config
+            .maximum_drawable_count
+            .unwrap_or(config.image_count);
+        let frames_in_flight = layer_config
+            .frames_in_flight
+            .unwrap_or(config.image_count)
+            .max(1);
         let is_mac = caps.os_is_mac;
         let can_set_next_drawable_timeout = if is_mac {
             caps.has_version_at_least(10, 13)
@@ -388,7 +468,19 @@ impl Device {
             msg_send![render_layer, setFramebufferOnly: framebuffer_only];
 
             // this gets ignored on iOS for certain OS/device combinations (iphone5s iOS 10.3)
-            msg_send![render_layer, setMaximumDrawableCount: config.image_count as u64];
+            msg_send![render_layer, setMaximumDrawableCount: max_drawable_count as u64];
+            msg_send![render_layer, setPresentsWithTransaction: layer_config.presents_with_transaction];
+            if is_mac {
+                let color_space = layer_config
+                    .color_space
+                    .clone()
+                    .or_else(|| default_color_space(config.format));
+                if let Some(ref color_space) = color_space {
+                    msg_send![render_layer, setColorspace: color_space.as_ptr()];
+                }
+                let extended_range = config.format == format::Format::Rgba16Float;
+                msg_send![render_layer, setWantsExtendedDynamicRangeContent: extended_range];
+            }
 
             msg_send![render_layer, setDrawableSize: drawable_size];
             if can_set_next_drawable_timeout {
@@ -468,6 +560,9 @@ impl Device {
             extent: config.extent,
             last_frame: 0,
             image_ready_callbacks: Vec::new(),
+            frame_throttle: Arc::new(native::SystemSemaphore::with_count(
+                frames_in_flight as usize,
+            )),
             acquire_mode: AcquireMode::Oldest,
         };
 
@@ -478,7 +573,24 @@ impl Device {
 impl hal::Swapchain<Backend> for Swapchain {
     unsafe fn acquire_image(
         &mut self,
-        _timeout_ns: u64,
+        timeout_ns: u64,
+        sync: hal::FrameSync<Backend>,
+    ) -> Result<hal::SwapImageIndex, hal::AcquireError> {
+        // the permit is returned once the presentation of this frame completes
+        if !self.frame_throttle.wait_ns(timeout_ns) {
+            return Err(hal::AcquireError::NotReady);
+        }
+        let result = self.acquire_frame(sync);
+        if result.is_err() {
+            self.frame_throttle.signal();
+        }
+        result
+    }
+}
+
+impl Swapchain {
+    unsafe fn acquire_frame(
+        &mut self,
         sync: hal::FrameSync<Backend>,
     ) -> Result<hal::SwapImageIndex, hal::AcquireError> {
         self.last_frame += 1;
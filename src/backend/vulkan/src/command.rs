@@ -2,6 +2,7 @@ use ash::version::DeviceV1_0;
 use ash::vk;
 use smallvec::SmallVec;
 use std::borrow::Borrow;
+use std::ffi::CString;
 use std::ops::Range;
 use std::sync::Arc;
 use std::{mem, ptr};
@@ -17,6 +18,7 @@ use {Backend, RawDevice};
 pub struct CommandBuffer {
     pub raw: vk::CommandBuffer,
     pub device: Arc<RawDevice>,
+    pub(crate) pool: vk::CommandPool,
 }
 
 fn map_subpass_contents(contents: com::SubpassContents) -> vk::SubpassContents {
@@ -26,6 +28,20 @@ fn map_subpass_contents(contents: com::SubpassContents) -> vk::SubpassContents {
     }
 }
 
+fn debug_label(name: &CString, color: u32) -> vk::DebugUtilsLabelEXT {
+    vk::DebugUtilsLabelEXT {
+        s_type: vk::StructureType::DEBUG_UTILS_LABEL_EXT,
+        p_next: ptr::null(),
+        p_label_name: name.as_ptr(),
+        color: [
+            (color >> 24) as f32 / 255.0,
+            ((color >> 16) & 0xFF) as f32 / 255.0,
+            ((color >> 8) & 0xFF) as f32 / 255.0,
+            (color & 0xFF) as f32 / 255.0,
+        ],
+    }
+}
+
 fn map_buffer_image_regions<T>(_image: &n::Image, regions: T) -> SmallVec<[vk::BufferImageCopy; 16]>
 where
     T: IntoIterator,
@@ -79,6 +95,93 @@ impl CommandBuffer {
     }
 }
 
+fn map_barriers<'a, T>(
+    barriers: T,
+) -> (
+    SmallVec<[vk::MemoryBarrier; 4]>,
+    SmallVec<[vk::BufferMemoryBarrier; 4]>,
+    SmallVec<[vk::ImageMemoryBarrier; 4]>,
+)
+where
+    T: IntoIterator,
+    T::Item: Borrow<memory::Barrier<'a, Backend>>,
+{
+    let mut global_bars: SmallVec<[vk::MemoryBarrier; 4]> = SmallVec::new();
+    let mut buffer_bars: SmallVec<[vk::BufferMemoryBarrier; 4]> = SmallVec::new();
+    let mut image_bars: SmallVec<[vk::ImageMemoryBarrier; 4]> = SmallVec::new();
+
+    for barrier in barriers {
+        match *barrier.borrow() {
+            memory::Barrier::AllBuffers(ref access) => {
+                global_bars.push(vk::MemoryBarrier {
+                    s_type: vk::StructureType::MEMORY_BARRIER,
+                    p_next: ptr::null(),
+                    src_access_mask: conv::map_buffer_access(access.start),
+                    dst_access_mask: conv::map_buffer_access(access.end),
+                });
+            }
+            memory::Barrier::AllImages(ref access) => {
+                global_bars.push(vk::MemoryBarrier {
+                    s_type: vk::StructureType::MEMORY_BARRIER,
+                    p_next: ptr::null(),
+                    src_access_mask: conv::map_image_access(access.start),
+                    dst_access_mask: conv::map_image_access(access.end),
+                });
+            }
+            memory::Barrier::Buffer {
+                ref states,
+                target,
+                ref range,
+                ref families,
+            } => {
+                let families = match families {
+                    Some(f) => f.start.0 as u32..f.end.0 as u32,
+                    None => vk::QUEUE_FAMILY_IGNORED..vk::QUEUE_FAMILY_IGNORED,
+                };
+                buffer_bars.push(vk::BufferMemoryBarrier {
+                    s_type: vk::StructureType::BUFFER_MEMORY_BARRIER,
+                    p_next: ptr::null(),
+                    src_access_mask: conv::map_buffer_access(states.start),
+                    dst_access_mask: conv::map_buffer_access(states.end),
+                    src_queue_family_index: families.start,
+                    dst_queue_family_index: families.end,
+                    buffer: target.raw,
+                    offset: range.start.unwrap_or(0),
+                    size: range
+                        .end
+                        .map_or(vk::WHOLE_SIZE, |end| end - range.start.unwrap_or(0)),
+                });
+            }
+            memory::Barrier::Image {
+                ref states,
+                target,
+                ref range,
+                ref families,
+            } => {
+                let subresource_range = conv::map_subresource_range(range);
+                let families = match families {
+                    Some(f) => f.start.0 as u32..f.end.0 as u32,
+                    None => vk::QUEUE_FAMILY_IGNORED..vk::QUEUE_FAMILY_IGNORED,
+                };
+                image_bars.push(vk::ImageMemoryBarrier {
+                    s_type: vk::StructureType::IMAGE_MEMORY_BARRIER,
+                    p_next: ptr::null(),
+                    src_access_mask: conv::map_image_access(states.start.0),
+                    dst_access_mask: conv::map_image_access(states.end.0),
+                    old_layout: conv::map_image_layout(states.start.1),
+                    new_layout: conv::map_image_layout(states.end.1),
+                    src_queue_family_index: families.start,
+                    dst_queue_family_index: families.end,
+                    image: target.raw,
+                    subresource_range,
+                });
+            }
+        }
+    }
+
+    (global_bars, buffer_bars, image_bars)
+}
+
 impl com::RawCommandBuffer<Backend> for CommandBuffer {
     unsafe fn begin(
         &mut self,
@@ -111,6 +214,9 @@ impl com::RawCommandBuffer<Backend> for CommandBuffer {
             p_inheritance_info: &inheritance_info,
         };
 
+        // Beginning the command buffer implicitly resets it.
+        #[cfg(feature = "etc2-transcode")]
+        self.device.4.reset_command_buffer(&self.device.0, self.raw);
         assert_eq!(Ok(()), self.device.0.begin_command_buffer(self.raw, &info));
     }
 
@@ -119,6 +225,8 @@ impl com::RawCommandBuffer<Backend> for CommandBuffer {
     }
 
     unsafe fn reset(&mut self, release_resources: bool) {
+        #[cfg(feature = "etc2-transcode")]
+        self.device.4.reset_command_buffer(&self.device.0, self.raw);
         let flags = if release_resources {
             vk::CommandBufferResetFlags::RELEASE_RESOURCES
         } else {
@@ -191,78 +299,7 @@ impl com::RawCommandBuffer<Backend> for CommandBuffer {
         T: IntoIterator,
         T::Item: Borrow<memory::Barrier<'a, Backend>>,
     {
-        let mut global_bars: SmallVec<[vk::MemoryBarrier; 4]> = SmallVec::new();
-        let mut buffer_bars: SmallVec<[vk::BufferMemoryBarrier; 4]> = SmallVec::new();
-        let mut image_bars: SmallVec<[vk::ImageMemoryBarrier; 4]> = SmallVec::new();
-
-        for barrier in barriers {
-            match *barrier.borrow() {
-                memory::Barrier::AllBuffers(ref access) => {
-                    global_bars.push(vk::MemoryBarrier {
-                        s_type: vk::StructureType::MEMORY_BARRIER,
-                        p_next: ptr::null(),
-                        src_access_mask: conv::map_buffer_access(access.start),
-                        dst_access_mask: conv::map_buffer_access(access.end),
-                    });
-                }
-                memory::Barrier::AllImages(ref access) => {
-                    global_bars.push(vk::MemoryBarrier {
-                        s_type: vk::StructureType::MEMORY_BARRIER,
-                        p_next: ptr::null(),
-                        src_access_mask: conv::map_image_access(access.start),
-                        dst_access_mask: conv::map_image_access(access.end),
-                    });
-                }
-                memory::Barrier::Buffer {
-                    ref states,
-                    target,
-                    ref range,
-                    ref families,
-                } => {
-                    let families = match families {
-                        Some(f) => f.start.0 as u32..f.end.0 as u32,
-                        None => vk::QUEUE_FAMILY_IGNORED..vk::QUEUE_FAMILY_IGNORED,
-                    };
-                    buffer_bars.push(vk::BufferMemoryBarrier {
-                        s_type: vk::StructureType::BUFFER_MEMORY_BARRIER,
-                        p_next: ptr::null(),
-                        src_access_mask: conv::map_buffer_access(states.start),
-                        dst_access_mask: conv::map_buffer_access(states.end),
-                        src_queue_family_index: families.start,
-                        dst_queue_family_index: families.end,
-                        buffer: target.raw,
-                        offset: range.start.unwrap_or(0),
-                        size: range
-                            .end
-                            .map_or(vk::WHOLE_SIZE, |end| end - range.start.unwrap_or(0)),
-                    });
-                }
-                memory::Barrier::Image {
-                    ref states,
-                    target,
-                    ref range,
-                    ref families,
-                } => {
-                    let subresource_range = conv::map_subresource_range(range);
-                    let families = match families {
-                        Some(f) => f.start.0 as u32..f.end.0 as u32,
-                        None => vk::QUEUE_FAMILY_IGNORED..vk::QUEUE_FAMILY_IGNORED,
-                    };
-                    image_bars.push(vk::ImageMemoryBarrier {
-                        s_type: vk::StructureType::IMAGE_MEMORY_BARRIER,
-                        p_next: ptr::null(),
-                        src_access_mask: conv::map_image_access(states.start.0),
-                        dst_access_mask: conv::map_image_access(states.end.0),
-                        old_layout: conv::map_image_layout(states.start.1),
-                        new_layout: conv::map_image_layout(states.end.1),
-                        src_queue_family_index: families.start,
-                        dst_queue_family_index: families.end,
-                        image: target.raw,
-                        subresource_range,
-                    });
-                }
-            }
-        }
+        let (global_bars, buffer_bars, image_bars) = map_barriers(barriers);
 
         self.device.0.cmd_pipeline_barrier(
             self.raw, // commandBuffer
@@ -275,6 +312,46 @@ impl com::RawCommandBuffer<Backend> for CommandBuffer {
         );
     }
 
+    unsafe fn set_event(&mut self, event: &n::Event, stage_mask: pso::PipelineStage) {
+        self.device
+            .0
+            .cmd_set_event(self.raw, event.0, conv::map_pipeline_stage(stage_mask))
+    }
+
+    unsafe fn reset_event(&mut self, event: &n::Event, stage_mask: pso::PipelineStage) {
+        self.device
+            .0
+            .cmd_reset_event(self.raw, event.0, conv::map_pipeline_stage(stage_mask))
+    }
+
+    unsafe fn wait_events<'a, I, J>(
+        &mut self,
+        events: I,
+        stages: Range<pso::PipelineStage>,
+        barriers: J,
+    ) where
+        I: IntoIterator,
+        I::Item: Borrow<n::Event>,
+        J: IntoIterator,
+        J::Item: Borrow<memory::Barrier<'a, Backend>>,
+    {
+        let events = events
+            .into_iter()
+            .map(|e| e.borrow().0)
+            .collect::<SmallVec<[vk::Event; 4]>>();
+        let (global_bars, buffer_bars, image_bars) = map_barriers(barriers);
+
+        self.device.0.cmd_wait_events(
+            self.raw,
+            &events,
+            conv::map_pipeline_stage(stages.start),
+            conv::map_pipeline_stage(stages.end),
+            &global_bars,
+            &buffer_bars,
+            &image_bars,
+        )
+    }
+
     unsafe fn fill_buffer<R>(&mut self, buffer: &n::Buffer, range: R, data: u32)
     where
         R: RangeArg<buffer::Offset>,
@@ -707,6 +784,36 @@ impl com::RawCommandBuffer<Backend> for CommandBuffer {
         T: IntoIterator,
         T::Item: Borrow<com::BufferImageCopy>,
     {
+        #[cfg(feature = "etc2-transcode")]
+        {
+            if let Some(format) = dst.transcoded_format {
+                let regions = regions
+                    .into_iter()
+                    .map(|region| region.borrow().clone())
+                    .collect::<Vec<_>>();
+                let staging = self.device.4.prepare_copy(
+                    &self.device.0,
+                    self.pool,
+                    self.raw,
+                    src.raw,
+                    format,
+                    &regions,
+                );
+                // Nothing is copied if all the regions are empty.
+                if let Some((staging, regions)) = staging {
+                    let regions = map_buffer_image_regions(dst, regions);
+                    self.device.0.cmd_copy_buffer_to_image(
+                        self.raw,
+                        staging,
+                        dst.raw,
+                        conv::map_image_layout(dst_layout),
+                        &regions,
+                    );
+                }
+                return;
+            }
+        }
+
         let regions = map_buffer_image_regions(dst, regions);
 
         self.device.0.cmd_copy_buffer_to_image(
@@ -844,6 +951,40 @@ impl com::RawCommandBuffer<Backend> for CommandBuffer {
         )
     }
 
+    unsafe fn begin_conditional_rendering(
+        &mut self,
+        buffer: &n::Buffer,
+        offset: buffer::Offset,
+        inverted: bool,
+    ) {
+        let ext = self
+            .device
+            .3
+            .as_ref()
+            .expect("Conditional rendering is not enabled");
+        let info = vk::ConditionalRenderingBeginInfoEXT {
+            s_type: vk::StructureType::CONDITIONAL_RENDERING_BEGIN_INFO_EXT,
+            p_next: ptr::null(),
+            buffer: buffer.raw,
+            offset,
+            flags: if inverted {
+                vk::ConditionalRenderingFlagsEXT::INVERTED
+            } else {
+                vk::ConditionalRenderingFlagsEXT::empty()
+            },
+        };
+        ext.cmd_begin_conditional_rendering_ext(self.raw, &info);
+    }
+
+    unsafe fn end_conditional_rendering(&mut self) {
+        let ext = self
+            .device
+            .3
+            .as_ref()
+            .expect("Conditional rendering is not enabled");
+        ext.cmd_end_conditional_rendering_ext(self.raw);
+    }
+
     unsafe fn push_compute_constants(
         &mut self,
         layout: &n::PipelineLayout,
@@ -884,8 +1025,33 @@ impl com::RawCommandBuffer<Backend> for CommandBuffer {
             .into_iter()
             .map(|b| b.borrow().raw)
             .collect::<Vec<_>>();
+        #[cfg(feature = "etc2-transcode")]
+        self.device
+            .4
+            .execute_commands(self.pool, self.raw, &command_buffers);
         self.device
             .0
             .cmd_execute_commands(self.raw, &command_buffers);
     }
+
+    unsafe fn begin_debug_marker(&mut self, name: &str, color: u32) {
+        // Labels are only available when the instance enabled `VK_EXT_debug_utils`.
+        if let Some(ref ext) = self.device.2 {
+            let name = CString::new(name).unwrap_or_default();
+            ext.cmd_begin_debug_utils_label(self.raw, &debug_label(&name, color));
+        }
+    }
+
+    unsafe fn end_debug_marker(&mut self) {
+        if let Some(ref ext) = self.device.2 {
+            ext.cmd_end_debug_utils_label(self.raw);
+        }
+    }
+
+    unsafe fn insert_debug_marker(&mut self, name: &str, color: u32) {
+        if let Some(ref ext) = self.device.2 {
+            let name = CString::new(name).unwrap_or_default();
+            ext.cmd_insert_debug_utils_label(self.raw, &debug_label(&name, color));
+        }
+    }
 }
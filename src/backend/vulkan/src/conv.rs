@@ -1,7 +1,7 @@
 use ash::vk;
 
 use hal::range::RangeArg;
-use hal::{buffer, command, format, image, pass, pso, query};
+use hal::{buffer, command, format, image, memory, pass, pso, query};
 use hal::{CompositeAlpha, Features, IndexType, PresentMode, Primitive};
 
 use native as n;
@@ -267,6 +267,28 @@ pub fn map_stencil_op(op: pso::StencilOp) -> vk::StencilOp {
     }
 }
 
+pub fn map_logic_op(op: pso::LogicOp) -> vk::LogicOp {
+    use hal::pso::LogicOp::*;
+    match op {
+        Clear => vk::LogicOp::CLEAR,
+        And => vk::LogicOp::AND,
+        AndReverse => vk::LogicOp::AND_REVERSE,
+        Copy => vk::LogicOp::COPY,
+        AndInverted => vk::LogicOp::AND_INVERTED,
+        NoOp => vk::LogicOp::NO_OP,
+        Xor => vk::LogicOp::XOR,
+        Or => vk::LogicOp::OR,
+        Nor => vk::LogicOp::NOR,
+        Equivalent => vk::LogicOp::EQUIVALENT,
+        Invert => vk::LogicOp::INVERT,
+        OrReverse => vk::LogicOp::OR_REVERSE,
+        CopyInverted => vk::LogicOp::COPY_INVERTED,
+        OrInverted => vk::LogicOp::OR_INVERTED,
+        Nand => vk::LogicOp::NAND,
+        Set => vk::LogicOp::SET,
+    }
+}
+
 pub fn map_stencil_side(side: &pso::StencilFace) -> vk::StencilOpState {
     vk::StencilOpState {
         fail_op: map_stencil_op(side.op_fail),
@@ -359,6 +381,28 @@ pub fn map_query_result_flags(flags: query::ResultFlags) -> vk::QueryResultFlags
     vk::QueryResultFlags::from_raw(flags.bits() & vk::QueryResultFlags::all().as_raw())
 }
 
+pub fn map_buffer_sparse_flags(sparse: memory::SparseFlags) -> vk::BufferCreateFlags {
+    // Safe due to equivalence of HAL values and Vulkan values
+    vk::BufferCreateFlags::from_raw(sparse.bits() & vk::BufferCreateFlags::all().as_raw())
+}
+
+pub fn map_image_sparse_flags(sparse: memory::SparseFlags) -> vk::ImageCreateFlags {
+    // Safe due to equivalence of HAL values and Vulkan values
+    vk::ImageCreateFlags::from_raw(sparse.bits() & vk::ImageCreateFlags::all().as_raw())
+}
+
+pub fn map_vk_image_aspects(aspects: vk::ImageAspectFlags) -> format::Aspects {
+    format::Aspects::from_bits_truncate(aspects.as_raw() as _)
+}
+
+pub fn map_vk_extent(extent: vk::Extent3D) -> image::Extent {
+    image::Extent {
+        width: extent.width,
+        height: extent.height,
+        depth: extent.depth,
+    }
+}
+
 pub fn map_image_features(features: vk::FormatFeatureFlags) -> format::ImageFeature {
     format::ImageFeature::from_bits_truncate(features.as_raw())
 }
@@ -1,6 +1,7 @@
 use ash::extensions::khr;
 use ash::version::DeviceV1_0;
 use ash::vk;
+use ash::vk::Handle;
 use smallvec::SmallVec;
 
 use hal;
@@ -9,7 +10,7 @@ use hal::memory::Requirements;
 use hal::pool::CommandPoolCreateFlags;
 use hal::pso::VertexInputRate;
 use hal::range::RangeArg;
-use hal::{buffer, device as d, format, image, mapping, pass, pso, query, queue};
+use hal::{buffer, device as d, format, image, mapping, memory, pass, pso, query, queue};
 use hal::{Backbuffer, Features, MemoryTypeId, SwapchainConfig};
 
 use std::borrow::Borrow;
@@ -84,6 +85,8 @@ impl d::Device<B> for Device {
     }
 
     unsafe fn destroy_command_pool(&self, pool: RawCommandPool) {
+        #[cfg(feature = "etc2-transcode")]
+        self.raw.4.reset_pool(&self.raw.0, pool.raw);
         self.raw.0.destroy_command_pool(pool.raw, None);
     }
 
@@ -518,7 +521,10 @@ impl d::Device<B> for Device {
                     p_next: ptr::null(),
                     flags: vk::PipelineInputAssemblyStateCreateFlags::empty(),
                     topology: conv::map_topology(desc.input_assembler.primitive),
-                    primitive_restart_enable: vk::FALSE,
+                    primitive_restart_enable: match desc.input_assembler.primitive_restart {
+                        pso::PrimitiveRestart::U16 | pso::PrimitiveRestart::U32 => vk::TRUE,
+                        pso::PrimitiveRestart::Disabled => vk::FALSE,
+                    },
                 });
                 let depth_bias = match desc.rasterizer.depth_bias {
                     Some(pso::State::Static(db)) => db,
@@ -574,11 +580,16 @@ impl d::Device<B> for Device {
 
                 let dynamic_state_base = dynamic_states.len();
 
+                debug_assert!(
+                    desc.viewport_count == 1
+                        || (desc.baked_states.viewport.is_none()
+                            && desc.baked_states.scissor.is_none())
+                );
                 info_viewport_states.push(vk::PipelineViewportStateCreateInfo {
                     s_type: vk::StructureType::PIPELINE_VIEWPORT_STATE_CREATE_INFO,
                     p_next: ptr::null(),
                     flags: vk::PipelineViewportStateCreateFlags::empty(),
-                    scissor_count: 1, // TODO
+                    scissor_count: desc.viewport_count,
                     p_scissors: match desc.baked_states.scissor {
                         Some(ref rect) => {
                             scissors.push(conv::map_rect(rect));
@@ -589,7 +600,7 @@ impl d::Device<B> for Device {
                             ptr::null()
                         }
                     },
-                    viewport_count: 1, // TODO
+                    viewport_count: desc.viewport_count,
                     p_viewports: match desc.baked_states.viewport {
                         Some(ref vp) => {
                             viewports.push(conv::map_viewport(vp));
@@ -724,8 +735,11 @@ impl d::Device<B> for Device {
                     s_type: vk::StructureType::PIPELINE_COLOR_BLEND_STATE_CREATE_INFO,
                     p_next: ptr::null(),
                     flags: vk::PipelineColorBlendStateCreateFlags::empty(),
-                    logic_op_enable: vk::FALSE, // TODO
-                    logic_op: vk::LogicOp::CLEAR,
+                    logic_op_enable: desc.blender.logic_op.is_some() as _,
+                    logic_op: desc
+                        .blender
+                        .logic_op
+                        .map_or(vk::LogicOp::CLEAR, conv::map_logic_op),
                     attachment_count: color_attachments.last().unwrap().len() as _,
                     p_attachments: color_attachments.last().unwrap().as_ptr(), // TODO:
                     blend_constants: match desc.baked_states.blend_color {
@@ -988,11 +1002,14 @@ impl d::Device<B> for Device {
         renderpass: &n::RenderPass,
         attachments: T,
         extent: image::Extent,
-    ) -> Result<n::Framebuffer, d::OutOfMemory>
+    ) -> Result<n::Framebuffer, d::FramebufferError>
     where
         T: IntoIterator,
         T::Item: Borrow<n::ImageView>,
     {
+        if extent.depth > self.max_framebuffer_layers {
+            return Err(d::FramebufferError::TooManyLayers);
+        }
         let attachments_raw = attachments
             .into_iter()
             .map(|attachment| attachment.borrow().view)
@@ -1014,8 +1031,12 @@ impl d::Device<B> for Device {
 
         match result {
             Ok(raw) => Ok(n::Framebuffer { raw }),
-            Err(vk::Result::ERROR_OUT_OF_HOST_MEMORY) => Err(d::OutOfMemory::OutOfHostMemory),
-            Err(vk::Result::ERROR_OUT_OF_DEVICE_MEMORY) => Err(d::OutOfMemory::OutOfDeviceMemory),
+            Err(vk::Result::ERROR_OUT_OF_HOST_MEMORY) => {
+                Err(d::OutOfMemory::OutOfHostMemory.into())
+            }
+            Err(vk::Result::ERROR_OUT_OF_DEVICE_MEMORY) => {
+                Err(d::OutOfMemory::OutOfDeviceMemory.into())
+            }
             _ => unreachable!(),
         }
     }
@@ -1123,29 +1144,16 @@ impl d::Device<B> for Device {
         size: u64,
         usage: buffer::Usage,
     ) -> Result<n::Buffer, buffer::CreationError> {
-        let info = vk::BufferCreateInfo {
-            s_type: vk::StructureType::BUFFER_CREATE_INFO,
-            p_next: ptr::null(),
-            flags: vk::BufferCreateFlags::empty(), // TODO:
-            size,
-            usage: conv::map_buffer_usage(usage),
-            sharing_mode: vk::SharingMode::EXCLUSIVE, // TODO:
-            queue_family_index_count: 0,
-            p_queue_family_indices: ptr::null(),
-        };
-
-        let result = self.raw.0.create_buffer(&info, None);
+        self.create_buffer_raw(size, usage, vk::BufferCreateFlags::empty())
+    }
 
-        match result {
-            Ok(raw) => Ok(n::Buffer { raw }),
-            Err(vk::Result::ERROR_OUT_OF_HOST_MEMORY) => {
-                Err(d::OutOfMemory::OutOfHostMemory.into())
-            }
-            Err(vk::Result::ERROR_OUT_OF_DEVICE_MEMORY) => {
-                Err(d::OutOfMemory::OutOfDeviceMemory.into())
-            }
-            _ => unreachable!(),
-        }
+    unsafe fn create_sparse_buffer(
+        &self,
+        size: u64,
+        usage: buffer::Usage,
+        sparse: memory::SparseFlags,
+    ) -> Result<n::Buffer, buffer::CreationError> {
+        self.create_buffer_raw(size, usage, conv::map_buffer_sparse_flags(sparse))
     }
 
     unsafe fn get_buffer_requirements(&self, buffer: &n::Buffer) -> Requirements {
@@ -1170,7 +1178,11 @@ impl d::Device<B> for Device {
             .bind_buffer_memory(buffer.raw, memory.raw, offset);
 
         match result {
-            Ok(()) => Ok(()),
+            Ok(()) => {
+                #[cfg(feature = "etc2-transcode")]
+                self.raw.4.bind_buffer(buffer.raw, memory.raw, offset);
+                Ok(())
+            }
             Err(vk::Result::ERROR_OUT_OF_HOST_MEMORY) => {
                 Err(d::OutOfMemory::OutOfHostMemory.into())
             }
@@ -1181,6 +1193,11 @@ impl d::Device<B> for Device {
         }
     }
 
+    unsafe fn set_buffer_name(&self, buffer: &mut n::Buffer, name: &str) {
+        self.raw
+            .set_object_name(vk::ObjectType::BUFFER, buffer.raw.as_raw(), name);
+    }
+
     unsafe fn create_buffer_view<R: RangeArg<u64>>(
         &self,
         buffer: &n::Buffer,
@@ -1222,50 +1239,54 @@ impl d::Device<B> for Device {
         view_caps: image::ViewCapabilities,
     ) -> Result<n::Image, image::CreationError> {
         let flags = conv::map_view_capabilities(view_caps);
-        let extent = conv::map_extent(kind.extent());
-        let array_layers = kind.num_layers();
-        let samples = kind.num_samples() as u32;
-        let image_type = match kind {
-            image::Kind::D1(..) => vk::ImageType::TYPE_1D,
-            image::Kind::D2(..) => vk::ImageType::TYPE_2D,
-            image::Kind::D3(..) => vk::ImageType::TYPE_3D,
-        };
+        self.create_image_raw(kind, mip_levels, format, tiling, usage, flags)
+    }
 
-        let info = vk::ImageCreateInfo {
-            s_type: vk::StructureType::IMAGE_CREATE_INFO,
-            p_next: ptr::null(),
+    unsafe fn create_sparse_image(
+        &self,
+        kind: image::Kind,
+        mip_levels: image::Level,
+        format: format::Format,
+        usage: image::Usage,
+        view_caps: image::ViewCapabilities,
+        sparse: memory::SparseFlags,
+    ) -> Result<n::Image, image::CreationError> {
+        let flags = conv::map_view_capabilities(view_caps) | conv::map_image_sparse_flags(sparse);
+        self.create_image_raw(
+            kind,
+            mip_levels,
+            format,
+            image::Tiling::Optimal,
+            usage,
             flags,
-            image_type,
-            format: conv::map_format(format),
-            extent: extent.clone(),
-            mip_levels: mip_levels as u32,
-            array_layers: array_layers as u32,
-            samples: vk::SampleCountFlags::from_raw(samples & vk::SampleCountFlags::all().as_raw()),
-            tiling: conv::map_tiling(tiling),
-            usage: conv::map_image_usage(usage),
-            sharing_mode: vk::SharingMode::EXCLUSIVE, // TODO:
-            queue_family_index_count: 0,
-            p_queue_family_indices: ptr::null(),
-            initial_layout: vk::ImageLayout::UNDEFINED,
-        };
-
-        let result = self.raw.0.create_image(&info, None);
+        )
+    }
 
-        match result {
-            Ok(raw) => Ok(n::Image {
-                raw,
-                ty: image_type,
-                flags,
-                extent,
-            }),
-            Err(vk::Result::ERROR_OUT_OF_HOST_MEMORY) => {
-                Err(d::OutOfMemory::OutOfHostMemory.into())
-            }
-            Err(vk::Result::ERROR_OUT_OF_DEVICE_MEMORY) => {
-                Err(d::OutOfMemory::OutOfDeviceMemory.into())
-            }
-            _ => unreachable!(),
-        }
+    unsafe fn get_image_sparse_requirements(
+        &self,
+        image: &n::Image,
+    ) -> Vec<image::SparseRequirements> {
+        self.raw
+            .0
+            .get_image_sparse_memory_requirements(image.raw)
+            .into_iter()
+            .map(|req| image::SparseRequirements {
+                aspects: conv::map_vk_image_aspects(req.format_properties.aspect_mask),
+                granularity: conv::map_vk_extent(req.format_properties.image_granularity),
+                mip_tail_first_level: req.image_mip_tail_first_lod as _,
+                mip_tail_size: req.image_mip_tail_size,
+                mip_tail_offset: req.image_mip_tail_offset,
+                mip_tail_stride: if req
+                    .format_properties
+                    .flags
+                    .contains(vk::SparseImageFormatFlags::SINGLE_MIPTAIL)
+                {
+                    None
+                } else {
+                    Some(req.image_mip_tail_stride)
+                },
+            })
+            .collect()
     }
 
     unsafe fn get_image_requirements(&self, image: &n::Image) -> Requirements {
@@ -1316,6 +1337,11 @@ impl d::Device<B> for Device {
         }
     }
 
+    unsafe fn set_image_name(&self, image: &mut n::Image, name: &str) {
+        self.raw
+            .set_object_name(vk::ObjectType::IMAGE, image.raw.as_raw(), name);
+    }
+
     unsafe fn create_image_view(
         &self,
         image: &n::Image,
@@ -1327,6 +1353,13 @@ impl d::Device<B> for Device {
         let is_cube = image
             .flags
             .intersects(vk::ImageCreateFlags::CUBE_COMPATIBLE);
+        // Views with the ETC2 formats are redirected to the fallback format,
+        // other formats are already compatible with the fallback.
+        #[cfg(feature = "etc2-transcode")]
+        let format = match image.transcoded_format {
+            Some(_) => ::transcode::fallback_format(format).unwrap_or(format),
+            None => format,
+        };
         let info = vk::ImageViewCreateInfo {
             s_type: vk::StructureType::IMAGE_VIEW_CREATE_INFO,
             p_next: ptr::null(),
@@ -1626,7 +1659,11 @@ impl d::Device<B> for Device {
             .map_memory(memory.raw, offset, size, vk::MemoryMapFlags::empty());
 
         match result {
-            Ok(ptr) => Ok(ptr as *mut _),
+            Ok(ptr) => {
+                #[cfg(feature = "etc2-transcode")]
+                self.raw.4.map_memory(memory.raw, ptr as *mut _, offset);
+                Ok(ptr as *mut _)
+            }
             Err(vk::Result::ERROR_MEMORY_MAP_FAILED) => Err(mapping::Error::MappingFailed),
             Err(vk::Result::ERROR_OUT_OF_HOST_MEMORY) => {
                 Err(d::OutOfMemory::OutOfHostMemory.into())
@@ -1639,6 +1676,8 @@ impl d::Device<B> for Device {
     }
 
     unsafe fn unmap_memory(&self, memory: &n::Memory) {
+        #[cfg(feature = "etc2-transcode")]
+        self.raw.4.unmap_memory(memory.raw);
         self.raw.0.unmap_memory(memory.raw)
     }
 
@@ -1799,6 +1838,61 @@ impl d::Device<B> for Device {
         }
     }
 
+    fn create_event(&self) -> Result<n::Event, d::OutOfMemory> {
+        let info = vk::EventCreateInfo {
+            s_type: vk::StructureType::EVENT_CREATE_INFO,
+            p_next: ptr::null(),
+            flags: vk::EventCreateFlags::empty(),
+        };
+
+        let result = unsafe { self.raw.0.create_event(&info, None) };
+        match result {
+            Ok(e) => Ok(n::Event(e)),
+            Err(vk::Result::ERROR_OUT_OF_HOST_MEMORY) => {
+                Err(d::OutOfMemory::OutOfHostMemory.into())
+            }
+            Err(vk::Result::ERROR_OUT_OF_DEVICE_MEMORY) => {
+                Err(d::OutOfMemory::OutOfDeviceMemory.into())
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    unsafe fn get_event_status(&self, event: &n::Event) -> Result<bool, d::OomOrDeviceLost> {
+        let result = self.raw.0.get_event_status(event.0);
+        match result {
+            Ok(signaled) => Ok(signaled),
+            Err(vk::Result::ERROR_OUT_OF_HOST_MEMORY) => {
+                Err(d::OutOfMemory::OutOfHostMemory.into())
+            }
+            Err(vk::Result::ERROR_OUT_OF_DEVICE_MEMORY) => {
+                Err(d::OutOfMemory::OutOfDeviceMemory.into())
+            }
+            Err(vk::Result::ERROR_DEVICE_LOST) => Err(d::DeviceLost.into()),
+            _ => unreachable!(),
+        }
+    }
+
+    unsafe fn set_event(&self, event: &n::Event) -> Result<(), d::OutOfMemory> {
+        let result = self.raw.0.set_event(event.0);
+        match result {
+            Ok(()) => Ok(()),
+            Err(vk::Result::ERROR_OUT_OF_HOST_MEMORY) => Err(d::OutOfMemory::OutOfHostMemory),
+            Err(vk::Result::ERROR_OUT_OF_DEVICE_MEMORY) => Err(d::OutOfMemory::OutOfDeviceMemory),
+            _ => unreachable!(),
+        }
+    }
+
+    unsafe fn reset_event(&self, event: &n::Event) -> Result<(), d::OutOfMemory> {
+        let result = self.raw.0.reset_event(event.0);
+        match result {
+            Ok(()) => Ok(()),
+            Err(vk::Result::ERROR_OUT_OF_HOST_MEMORY) => Err(d::OutOfMemory::OutOfHostMemory),
+            Err(vk::Result::ERROR_OUT_OF_DEVICE_MEMORY) => Err(d::OutOfMemory::OutOfDeviceMemory),
+            _ => unreachable!(),
+        }
+    }
+
     unsafe fn free_memory(&self, memory: n::Memory) {
         self.raw.0.free_memory(memory.raw, None);
     }
@@ -1875,6 +1969,11 @@ impl d::Device<B> for Device {
         }
     }
 
+    unsafe fn calibrate_timestamps(&self) -> Option<query::TimestampCalibration> {
+        // Sampling the counter requires `VK_EXT_calibrated_timestamps`.
+        None
+    }
+
     unsafe fn create_swapchain(
         &self,
         surface: &mut w::Surface,
@@ -1964,6 +2063,7 @@ impl d::Device<B> for Device {
                     height: surface.height,
                     depth: 1,
                 },
+                transcoded_format: None,
             })
             .collect();
 
@@ -2003,6 +2103,8 @@ impl d::Device<B> for Device {
     }
 
     unsafe fn destroy_buffer(&self, buffer: n::Buffer) {
+        #[cfg(feature = "etc2-transcode")]
+        self.raw.4.destroy_buffer(buffer.raw);
         self.raw.0.destroy_buffer(buffer.raw, None);
     }
 
@@ -2038,6 +2140,10 @@ impl d::Device<B> for Device {
         self.raw.0.destroy_semaphore(semaphore.0, None);
     }
 
+    unsafe fn destroy_event(&self, event: n::Event) {
+        self.raw.0.destroy_event(event.0, None);
+    }
+
     fn wait_idle(&self) -> Result<(), HostExecutionError> {
         unsafe {
             self.raw
@@ -2049,6 +2155,110 @@ impl d::Device<B> for Device {
     }
 }
 
+impl Device {
+    unsafe fn create_buffer_raw(
+        &self,
+        size: u64,
+        usage: buffer::Usage,
+        flags: vk::BufferCreateFlags,
+    ) -> Result<n::Buffer, buffer::CreationError> {
+        let info = vk::BufferCreateInfo {
+            s_type: vk::StructureType::BUFFER_CREATE_INFO,
+            p_next: ptr::null(),
+            flags,
+            size,
+            usage: conv::map_buffer_usage(usage),
+            sharing_mode: vk::SharingMode::EXCLUSIVE, // TODO:
+            queue_family_index_count: 0,
+            p_queue_family_indices: ptr::null(),
+        };
+
+        let result = self.raw.0.create_buffer(&info, None);
+
+        match result {
+            Ok(raw) => Ok(n::Buffer { raw }),
+            Err(vk::Result::ERROR_OUT_OF_HOST_MEMORY) => {
+                Err(d::OutOfMemory::OutOfHostMemory.into())
+            }
+            Err(vk::Result::ERROR_OUT_OF_DEVICE_MEMORY) => {
+                Err(d::OutOfMemory::OutOfDeviceMemory.into())
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    unsafe fn create_image_raw(
+        &self,
+        kind: image::Kind,
+        mip_levels: image::Level,
+        format: format::Format,
+        tiling: image::Tiling,
+        usage: image::Usage,
+        flags: vk::ImageCreateFlags,
+    ) -> Result<n::Image, image::CreationError> {
+        let extent = conv::map_extent(kind.extent());
+        let array_layers = kind.num_layers();
+        let samples = kind.num_samples() as u32;
+        let image_type = match kind {
+            image::Kind::D1(..) => vk::ImageType::TYPE_1D,
+            image::Kind::D2(..) => vk::ImageType::TYPE_2D,
+            image::Kind::D3(..) => vk::ImageType::TYPE_3D,
+        };
+
+        #[cfg(feature = "etc2-transcode")]
+        let (transcoded_format, raw_format) =
+            match self.raw.4.fallback_format(format, conv::map_tiling(tiling)) {
+                Some(fallback) => {
+                    info!(
+                        "Transcoding {:?} images to {:?} on the host",
+                        format, fallback
+                    );
+                    (Some(format), fallback)
+                }
+                None => (None, format),
+            };
+        #[cfg(not(feature = "etc2-transcode"))]
+        let (transcoded_format, raw_format) = (None, format);
+
+        let info = vk::ImageCreateInfo {
+            s_type: vk::StructureType::IMAGE_CREATE_INFO,
+            p_next: ptr::null(),
+            flags,
+            image_type,
+            format: conv::map_format(raw_format),
+            extent: extent.clone(),
+            mip_levels: mip_levels as u32,
+            array_layers: array_layers as u32,
+            samples: vk::SampleCountFlags::from_raw(samples & vk::SampleCountFlags::all().as_raw()),
+            tiling: conv::map_tiling(tiling),
+            usage: conv::map_image_usage(usage),
+            sharing_mode: vk::SharingMode::EXCLUSIVE, // TODO:
+            queue_family_index_count: 0,
+            p_queue_family_indices: ptr::null(),
+            initial_layout: vk::ImageLayout::UNDEFINED,
+        };
+
+        let result = self.raw.0.create_image(&info, None);
+
+        match result {
+            Ok(raw) => Ok(n::Image {
+                raw,
+                ty: image_type,
+                flags,
+                extent,
+                transcoded_format,
+            }),
+            Err(vk::Result::ERROR_OUT_OF_HOST_MEMORY) => {
+                Err(d::OutOfMemory::OutOfHostMemory.into())
+            }
+            Err(vk::Result::ERROR_OUT_OF_DEVICE_MEMORY) => {
+                Err(d::OutOfMemory::OutOfDeviceMemory.into())
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
 #[test]
 fn test_send_sync() {
     fn foo<T: Send + Sync>() {}
@@ -22,15 +22,16 @@ extern crate x11;
 extern crate xcb;
 
 use ash::extensions::{ext, khr};
-use ash::version::{DeviceV1_0, EntryV1_0, InstanceV1_0};
+use ash::version::{DeviceV1_0, EntryV1_0, InstanceV1_0, InstanceV1_1};
 use ash::vk;
 #[cfg(not(feature = "use-rtld-next"))]
 use ash::{Entry, LoadingError};
 
 use hal::adapter::DeviceType;
+use hal::device::DeviceLost;
 use hal::error::{DeviceCreationError, HostExecutionError};
 use hal::pso::PipelineStage;
-use hal::{format, image, memory, queue};
+use hal::{format, image, memory, pso, queue};
 use hal::{Features, Limits, PatchSize, QueueType, SwapImageIndex};
 
 use std::borrow::Borrow;
@@ -50,6 +51,8 @@ mod info;
 mod native;
 mod pool;
 mod result;
+#[cfg(feature = "etc2-transcode")]
+pub mod transcode;
 mod window;
 
 // CStr's cannot be constant yet, until const fn lands we need to use a lazy_static
@@ -57,6 +60,7 @@ lazy_static! {
     static ref LAYERS: Vec<&'static CStr> = vec![#[cfg(debug_assertions)] CStr::from_bytes_with_nul(b"VK_LAYER_LUNARG_standard_validation\0").expect("Wrong extension string")];
     static ref EXTENSIONS: Vec<&'static CStr> = vec![#[cfg(debug_assertions)] CStr::from_bytes_with_nul(b"VK_EXT_debug_utils\0").expect("Wrong extension string")];
     static ref DEVICE_EXTENSIONS: Vec<&'static CStr> = vec![khr::Swapchain::name()];
+    static ref CONDITIONAL_RENDERING_EXTENSION: &'static CStr = CStr::from_bytes_with_nul(b"VK_EXT_conditional_rendering\0").expect("Wrong extension string");
     static ref SURFACE_EXTENSIONS: Vec<&'static CStr> = vec![
         khr::Surface::name(),
         // Platform-specific WSI extensions
@@ -90,6 +94,7 @@ lazy_static! {
 pub struct RawInstance(
     pub ash::Instance,
     Option<(ext::DebugUtils, vk::DebugUtilsMessengerEXT)>,
+    u32,
 );
 impl Drop for RawInstance {
     fn drop(&mut self) {
@@ -266,6 +271,12 @@ impl Instance {
             .as_ref()
             .expect("Unable to load Vulkan entry points");
 
+        // Use Vulkan 1.1 if the loader supports it, for the additional device properties.
+        let api_version = match entry.try_enumerate_instance_version() {
+            Ok(Some(version)) if version >= vk_make_version!(1, 1, 0) => vk_make_version!(1, 1, 0),
+            _ => vk_make_version!(1, 0, 0),
+        };
+
         let app_name = CString::new(name).unwrap();
         let app_info = vk::ApplicationInfo {
             s_type: vk::StructureType::APPLICATION_INFO,
@@ -274,7 +285,7 @@ impl Instance {
             application_version: version,
             p_engine_name: b"gfx-rs\0".as_ptr() as *const _,
             engine_version: 1,
-            api_version: vk_make_version!(1, 0, 0),
+            api_version,
         };
 
         let instance_extensions = entry
@@ -365,7 +376,7 @@ impl Instance {
         let debug_messenger = None;
 
         Instance {
-            raw: Arc::new(RawInstance(instance, debug_messenger)),
+            raw: Arc::new(RawInstance(instance, debug_messenger, api_version)),
             extensions,
         }
     }
@@ -443,6 +454,11 @@ impl hal::queue::QueueFamily for QueueFamily {
     fn max_queues(&self) -> usize {
         self.properties.queue_count as _
     }
+    fn supports_sparse_binding(&self) -> bool {
+        self.properties
+            .queue_flags
+            .contains(vk::QueueFlags::SPARSE_BINDING)
+    }
     fn id(&self) -> queue::QueueFamilyId {
         queue::QueueFamilyId(self.index as _)
     }
@@ -454,6 +470,42 @@ pub struct PhysicalDevice {
     properties: vk::PhysicalDeviceProperties,
 }
 
+impl PhysicalDevice {
+    fn subgroup_properties(&self) -> vk::PhysicalDeviceSubgroupProperties {
+        let mut subgroup: vk::PhysicalDeviceSubgroupProperties = unsafe { mem::zeroed() };
+        // Vulkan 1.1 properties need to be supported by both the instance and the device.
+        let version = vk_make_version!(1, 1, 0);
+        if self.instance.2 >= version && self.properties.api_version >= version {
+            subgroup.s_type = vk::StructureType::PHYSICAL_DEVICE_SUBGROUP_PROPERTIES;
+            let mut properties = vk::PhysicalDeviceProperties2 {
+                s_type: vk::StructureType::PHYSICAL_DEVICE_PROPERTIES_2,
+                p_next: &mut subgroup as *mut _ as *mut _,
+                properties: self.properties,
+            };
+            unsafe {
+                self.instance
+                    .0
+                    .get_physical_device_properties2(self.handle, &mut properties);
+            }
+        }
+        subgroup
+    }
+
+    fn supports_extension(&self, extension: &CStr) -> bool {
+        let extensions = match unsafe {
+            self.instance
+                .0
+                .enumerate_device_extension_properties(self.handle)
+        } {
+            Ok(extensions) => extensions,
+            Err(_) => return false,
+        };
+        extensions
+            .iter()
+            .any(|ext| unsafe { CStr::from_ptr(ext.extension_name.as_ptr()) == extension })
+    }
+}
+
 impl hal::PhysicalDevice<Backend> for PhysicalDevice {
     unsafe fn open(
         &self,
@@ -478,18 +530,33 @@ impl hal::PhysicalDevice<Backend> for PhysicalDevice {
 
         let enabled_features = conv::map_device_features(requested_features);
 
+        let conditional_rendering = requested_features.contains(Features::CONDITIONAL_RENDERING);
+        let conditional_rendering_features = vk::PhysicalDeviceConditionalRenderingFeaturesEXT {
+            s_type: vk::StructureType::PHYSICAL_DEVICE_CONDITIONAL_RENDERING_FEATURES_EXT,
+            p_next: ptr::null_mut(),
+            conditional_rendering: vk::TRUE,
+            inherited_conditional_rendering: vk::FALSE,
+        };
+
         // Create device
         let device_raw = {
-            let cstrings = DEVICE_EXTENSIONS
+            let mut cstrings = DEVICE_EXTENSIONS
                 .iter()
                 .map(|&s| CString::from(s))
                 .collect::<Vec<_>>();
+            if conditional_rendering {
+                cstrings.push(CString::from(*CONDITIONAL_RENDERING_EXTENSION));
+            }
 
             let str_pointers = cstrings.iter().map(|s| s.as_ptr()).collect::<Vec<_>>();
 
             let info = vk::DeviceCreateInfo {
                 s_type: vk::StructureType::DEVICE_CREATE_INFO,
-                p_next: ptr::null(),
+                p_next: if conditional_rendering {
+                    &conditional_rendering_features as *const _ as *const _
+                } else {
+                    ptr::null()
+                },
                 flags: vk::DeviceCreateFlags::empty(),
                 queue_create_info_count: family_infos.len() as u32,
                 p_queue_create_infos: family_infos.as_ptr(),
@@ -515,8 +582,39 @@ impl hal::PhysicalDevice<Backend> for PhysicalDevice {
             )
         });
 
+        let conditional_rendering_fn = if conditional_rendering {
+            Some(vk::ExtConditionalRenderingFn::load(|name| {
+                mem::transmute(
+                    self.instance
+                        .0
+                        .get_device_proc_addr(device_raw.handle(), name.as_ptr()),
+                )
+            }))
+        } else {
+            None
+        };
+
         let device = Device {
-            raw: Arc::new(RawDevice(device_raw, requested_features)),
+            raw: Arc::new(RawDevice(
+                device_raw,
+                requested_features,
+                self.instance.1.as_ref().map(|&(ref ext, _)| ext.clone()),
+                conditional_rendering_fn,
+                #[cfg(feature = "etc2-transcode")]
+                transcode::Transcoder::new(
+                    &self
+                        .instance
+                        .0
+                        .get_physical_device_memory_properties(self.handle),
+                    |format| {
+                        self.instance.0.get_physical_device_format_properties(
+                            self.handle,
+                            conv::map_format(format),
+                        )
+                    },
+                ),
+            )),
+            max_framebuffer_layers: self.properties.limits.max_framebuffer_layers,
         };
 
         let device_arc = device.raw.clone();
@@ -754,6 +852,36 @@ impl hal::PhysicalDevice<Backend> for PhysicalDevice {
         if features.fragment_stores_and_atomics != 0 {
             bits |= Features::FRAGMENT_STORES_AND_ATOMICS;
         }
+        if features.sparse_binding != 0 {
+            bits |= Features::SPARSE_BINDING;
+        }
+        if features.sparse_residency_buffer != 0 {
+            bits |= Features::SPARSE_RESIDENCY_BUFFER;
+        }
+        if features.sparse_residency_image2_d != 0 {
+            bits |= Features::SPARSE_RESIDENCY_IMAGE_2D;
+        }
+        if features.sparse_residency_image3_d != 0 {
+            bits |= Features::SPARSE_RESIDENCY_IMAGE_3D;
+        }
+        if features.sparse_residency2_samples != 0 {
+            bits |= Features::SPARSE_RESIDENCY_2_SAMPLES;
+        }
+        if features.sparse_residency4_samples != 0 {
+            bits |= Features::SPARSE_RESIDENCY_4_SAMPLES;
+        }
+        if features.sparse_residency8_samples != 0 {
+            bits |= Features::SPARSE_RESIDENCY_8_SAMPLES;
+        }
+        if features.sparse_residency16_samples != 0 {
+            bits |= Features::SPARSE_RESIDENCY_16_SAMPLES;
+        }
+        if features.sparse_residency_aliased != 0 {
+            bits |= Features::SPARSE_RESIDENCY_ALIASED;
+        }
+        if self.supports_extension(*CONDITIONAL_RENDERING_EXTENSION) {
+            bits |= Features::CONDITIONAL_RENDERING;
+        }
         //TODO: cover more features
 
         bits
@@ -761,6 +889,7 @@ impl hal::PhysicalDevice<Backend> for PhysicalDevice {
 
     fn limits(&self) -> Limits {
         let limits = &self.properties.limits;
+        let subgroup = self.subgroup_properties();
         let max_group_count = limits.max_compute_work_group_count;
         let max_group_size = limits.max_compute_work_group_size;
 
@@ -779,6 +908,7 @@ impl hal::PhysicalDevice<Backend> for PhysicalDevice {
                 max_group_size[1] as _,
                 max_group_size[2] as _,
             ],
+            max_compute_shared_memory_size: limits.max_compute_shared_memory_size as _,
             max_vertex_input_attributes: limits.max_vertex_input_attributes as _,
             max_vertex_input_bindings: limits.max_vertex_input_bindings as _,
             max_vertex_input_attribute_offset: limits.max_vertex_input_attribute_offset as _,
@@ -794,9 +924,30 @@ impl hal::PhysicalDevice<Backend> for PhysicalDevice {
             framebuffer_stencil_samples_count: limits.framebuffer_stencil_sample_counts.as_raw()
                 as _,
             max_color_attachments: limits.max_color_attachments as _,
+            max_framebuffer_layers: limits.max_framebuffer_layers as _,
+            max_bound_descriptor_sets: limits.max_bound_descriptor_sets as _,
+            max_per_stage_descriptor_samplers: limits.max_per_stage_descriptor_samplers as _,
+            max_per_stage_descriptor_uniform_buffers: limits
+                .max_per_stage_descriptor_uniform_buffers
+                as _,
+            max_per_stage_descriptor_storage_buffers: limits
+                .max_per_stage_descriptor_storage_buffers
+                as _,
+            max_per_stage_descriptor_sampled_images: limits.max_per_stage_descriptor_sampled_images
+                as _,
+            max_per_stage_descriptor_storage_images: limits.max_per_stage_descriptor_storage_images
+                as _,
             non_coherent_atom_size: limits.non_coherent_atom_size as _,
             max_sampler_anisotropy: limits.max_sampler_anisotropy,
+            max_draw_indirect_count: limits.max_draw_indirect_count,
             min_vertex_input_binding_stride_alignment: 1,
+            subgroup_size: subgroup.subgroup_size,
+            subgroup_operations: hal::SubgroupOperations::from_bits_truncate(
+                subgroup.supported_operations.as_raw(),
+            ),
+            subgroup_stages: pso::ShaderStageFlags::from_bits_truncate(
+                subgroup.supported_stages.as_raw(),
+            ),
         }
     }
 
@@ -847,12 +998,36 @@ impl hal::PhysicalDevice<Backend> for PhysicalDevice {
 }
 
 #[doc(hidden)]
-pub struct RawDevice(pub ash::Device, Features);
+pub struct RawDevice(
+    pub ash::Device,
+    Features,
+    Option<ext::DebugUtils>,
+    Option<vk::ExtConditionalRenderingFn>,
+    #[cfg(feature = "etc2-transcode")] transcode::Transcoder,
+);
 impl fmt::Debug for RawDevice {
     fn fmt(&self, _formatter: &mut fmt::Formatter) -> fmt::Result {
         unimplemented!()
     }
 }
+impl RawDevice {
+    /// Name the object with `VK_EXT_debug_utils`, if the instance has it enabled.
+    unsafe fn set_object_name(&self, object_type: vk::ObjectType, handle: u64, name: &str) {
+        if let Some(ref ext) = self.2 {
+            let name = CString::new(name).unwrap_or_default();
+            let info = vk::DebugUtilsObjectNameInfoEXT {
+                s_type: vk::StructureType::DEBUG_UTILS_OBJECT_NAME_INFO_EXT,
+                p_next: ptr::null(),
+                object_type,
+                object_handle: handle,
+                p_object_name: name.as_ptr(),
+            };
+            if let Err(err) = ext.debug_utils_set_object_name(self.0.handle(), &info) {
+                warn!("Failed to set the object name: {:?}", err);
+            }
+        }
+    }
+}
 impl Drop for RawDevice {
     fn drop(&mut self) {
         unsafe {
@@ -875,7 +1050,8 @@ impl hal::queue::RawCommandQueue<Backend> for CommandQueue {
         &mut self,
         submission: hal::queue::Submission<Ic, Iw, Is>,
         fence: Option<&native::Fence>,
-    ) where
+    ) -> Result<(), DeviceLost>
+    where
         T: 'a + Borrow<command::CommandBuffer>,
         Ic: IntoIterator<Item = &'a T>,
         S: 'a + Borrow<native::Semaphore>,
@@ -891,6 +1067,8 @@ impl hal::queue::RawCommandQueue<Backend> for CommandQueue {
             .into_iter()
             .map(|cmd| cmd.borrow().raw)
             .collect::<Vec<_>>();
+        #[cfg(feature = "etc2-transcode")]
+        self.device.4.decode_submission(&self.device.0, &buffers);
         for (semaphore, stage) in submission.wait_semaphores {
             waits.push(semaphore.borrow().0);
             stages.push(conv::map_pipeline_stage(stage));
@@ -920,15 +1098,18 @@ impl hal::queue::RawCommandQueue<Backend> for CommandQueue {
 
         let fence_raw = fence.map(|fence| fence.0).unwrap_or(vk::Fence::null());
 
-        let result = self.device.0.queue_submit(*self.raw, &[info], fence_raw);
-        assert_eq!(Ok(()), result);
+        match self.device.0.queue_submit(*self.raw, &[info], fence_raw) {
+            Ok(()) => Ok(()),
+            Err(vk::Result::ERROR_DEVICE_LOST) => Err(DeviceLost),
+            Err(err) => panic!("Submission failed: {:?}", err),
+        }
     }
 
     unsafe fn present<'a, W, Is, S, Iw>(
         &mut self,
         swapchains: Is,
         wait_semaphores: Iw,
-    ) -> Result<(), ()>
+    ) -> Result<(), hal::PresentError>
     where
         W: 'a + Borrow<window::Swapchain>,
         Is: IntoIterator<Item = (&'a W, SwapImageIndex)>,
@@ -960,11 +1141,154 @@ impl hal::queue::RawCommandQueue<Backend> for CommandQueue {
 
         match self.swapchain_fn.queue_present_khr(*self.raw, &info) {
             vk::Result::SUCCESS => Ok(()),
-            vk::Result::SUBOPTIMAL_KHR | vk::Result::ERROR_OUT_OF_DATE_KHR => Err(()),
+            vk::Result::SUBOPTIMAL_KHR | vk::Result::ERROR_OUT_OF_DATE_KHR => {
+                Err(hal::PresentError::OutOfDate)
+            }
+            vk::Result::ERROR_OUT_OF_HOST_MEMORY => Err(hal::PresentError::OutOfMemory(
+                hal::device::OutOfMemory::OutOfHostMemory,
+            )),
+            vk::Result::ERROR_OUT_OF_DEVICE_MEMORY => Err(hal::PresentError::OutOfMemory(
+                hal::device::OutOfMemory::OutOfDeviceMemory,
+            )),
+            vk::Result::ERROR_DEVICE_LOST => {
+                Err(hal::PresentError::DeviceLost(hal::device::DeviceLost))
+            }
+            vk::Result::ERROR_SURFACE_LOST_KHR => {
+                Err(hal::PresentError::SurfaceLost(hal::device::SurfaceLost))
+            }
             _ => panic!("Failed to present frame"),
         }
     }
 
+    unsafe fn bind_sparse<'a, S, Iw, Is>(
+        &mut self,
+        binding: hal::queue::SparseBinding<'a, Backend, Iw, Is>,
+        fence: Option<&native::Fence>,
+    ) where
+        S: 'a + Borrow<native::Semaphore>,
+        Iw: IntoIterator<Item = &'a S>,
+        Is: IntoIterator<Item = &'a S>,
+    {
+        fn map_memory(memory: Option<(&native::Memory, u64)>) -> (vk::DeviceMemory, u64) {
+            memory.map_or((vk::DeviceMemory::null(), 0), |(memory, offset)| {
+                (memory.raw, offset)
+            })
+        }
+        fn map_binds(binds: &[memory::SparseBind<Backend>]) -> Vec<vk::SparseMemoryBind> {
+            binds
+                .iter()
+                .map(|bind| {
+                    let (memory, memory_offset) = map_memory(bind.memory);
+                    vk::SparseMemoryBind {
+                        resource_offset: bind.resource_offset,
+                        size: bind.size,
+                        memory,
+                        memory_offset,
+                        flags: vk::SparseMemoryBindFlags::empty(),
+                    }
+                })
+                .collect()
+        }
+
+        //TODO: avoid heap allocations
+        let waits = binding
+            .wait_semaphores
+            .into_iter()
+            .map(|semaphore| semaphore.borrow().0)
+            .collect::<Vec<_>>();
+        let signals = binding
+            .signal_semaphores
+            .into_iter()
+            .map(|semaphore| semaphore.borrow().0)
+            .collect::<Vec<_>>();
+
+        let buffer_binds = binding
+            .buffers
+            .iter()
+            .map(|&(_, binds)| map_binds(binds))
+            .collect::<Vec<_>>();
+        let buffer_infos = binding
+            .buffers
+            .iter()
+            .zip(&buffer_binds)
+            .map(|(&(buffer, _), binds)| vk::SparseBufferMemoryBindInfo {
+                buffer: buffer.raw,
+                bind_count: binds.len() as u32,
+                p_binds: binds.as_ptr(),
+            })
+            .collect::<Vec<_>>();
+
+        let opaque_binds = binding
+            .image_opaques
+            .iter()
+            .map(|&(_, binds)| map_binds(binds))
+            .collect::<Vec<_>>();
+        let opaque_infos = binding
+            .image_opaques
+            .iter()
+            .zip(&opaque_binds)
+            .map(|(&(image, _), binds)| vk::SparseImageOpaqueMemoryBindInfo {
+                image: image.raw,
+                bind_count: binds.len() as u32,
+                p_binds: binds.as_ptr(),
+            })
+            .collect::<Vec<_>>();
+
+        let image_binds = binding
+            .images
+            .iter()
+            .map(|&(_, binds)| {
+                binds
+                    .iter()
+                    .map(|bind| {
+                        let (memory, memory_offset) = map_memory(bind.memory);
+                        vk::SparseImageMemoryBind {
+                            subresource: conv::map_subresource(&bind.subresource),
+                            offset: conv::map_offset(bind.offset),
+                            extent: conv::map_extent(bind.extent),
+                            memory,
+                            memory_offset,
+                            flags: vk::SparseMemoryBindFlags::empty(),
+                        }
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        let image_infos = binding
+            .images
+            .iter()
+            .zip(&image_binds)
+            .map(|(&(image, _), binds)| vk::SparseImageMemoryBindInfo {
+                image: image.raw,
+                bind_count: binds.len() as u32,
+                p_binds: binds.as_ptr(),
+            })
+            .collect::<Vec<_>>();
+
+        let info = vk::BindSparseInfo {
+            s_type: vk::StructureType::BIND_SPARSE_INFO,
+            p_next: ptr::null(),
+            wait_semaphore_count: waits.len() as u32,
+            p_wait_semaphores: waits.as_ptr(),
+            buffer_bind_count: buffer_infos.len() as u32,
+            p_buffer_binds: buffer_infos.as_ptr(),
+            image_opaque_bind_count: opaque_infos.len() as u32,
+            p_image_opaque_binds: opaque_infos.as_ptr(),
+            image_bind_count: image_infos.len() as u32,
+            p_image_binds: image_infos.as_ptr(),
+            signal_semaphore_count: signals.len() as u32,
+            p_signal_semaphores: signals.as_ptr(),
+        };
+
+        let fence_raw = fence.map(|fence| fence.0).unwrap_or(vk::Fence::null());
+
+        let result = self
+            .device
+            .0
+            .queue_bind_sparse(*self.raw, &[info], fence_raw);
+        assert_eq!(Ok(()), result);
+    }
+
     fn wait_idle(&self) -> Result<(), HostExecutionError> {
         unsafe {
             self.device
@@ -978,6 +1302,7 @@ impl hal::queue::RawCommandQueue<Backend> for CommandQueue {
 
 pub struct Device {
     raw: Arc<RawDevice>,
+    max_framebuffer_layers: u32,
 }
 
 #[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
@@ -1016,5 +1341,6 @@ impl hal::Backend for Backend {
 
     type Fence = native::Fence;
     type Semaphore = native::Semaphore;
+    type Event = native::Event;
     type QueryPool = native::QueryPool;
 }
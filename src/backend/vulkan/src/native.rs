@@ -1,6 +1,8 @@
 use ash::version::DeviceV1_0;
 use ash::vk;
-use hal::image::SubresourceRange;
+use conv;
+use hal::format;
+use hal::image::{self, SubresourceRange};
 use hal::pso;
 use std::borrow::Borrow;
 use std::sync::Arc;
@@ -12,6 +14,9 @@ pub struct Semaphore(pub vk::Semaphore);
 #[derive(Debug, Hash, PartialEq, Eq)]
 pub struct Fence(pub vk::Fence);
 
+#[derive(Debug, Hash)]
+pub struct Event(pub vk::Event);
+
 #[derive(Debug, Hash)]
 pub struct GraphicsPipeline(pub vk::Pipeline);
 
@@ -31,17 +36,76 @@ pub struct Buffer {
 unsafe impl Sync for Buffer {}
 unsafe impl Send for Buffer {}
 
+impl Buffer {
+    /// Wrap a buffer created outside of gfx, e.g. by an external library.
+    ///
+    /// The buffer needs to be created on the same device. `Device::destroy_buffer`
+    /// destroys it, when the other library keeps the ownership, `mem::forget`
+    /// the buffer instead.
+    pub unsafe fn from_raw(raw: vk::Buffer) -> Self {
+        Buffer { raw }
+    }
+
+    /// Underlying buffer handle, valid until the buffer is destroyed.
+    pub fn as_raw(&self) -> vk::Buffer {
+        self.raw
+    }
+}
+
 #[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub struct BufferView {
     pub(crate) raw: vk::BufferView,
 }
 
+impl BufferView {
+    /// Underlying buffer view handle, valid until the view is destroyed.
+    pub fn as_raw(&self) -> vk::BufferView {
+        self.raw
+    }
+}
+
 #[derive(Debug, Hash, PartialEq, Eq)]
 pub struct Image {
     pub(crate) raw: vk::Image,
     pub(crate) ty: vk::ImageType,
     pub(crate) flags: vk::ImageCreateFlags,
     pub(crate) extent: vk::Extent3D,
+    /// Unsupported ETC2 format the image was created with,
+    /// in which case it's backed by an uncompressed fallback format.
+    pub(crate) transcoded_format: Option<format::Format>,
+}
+
+impl Image {
+    /// Wrap an image created outside of gfx, e.g. by an external library.
+    ///
+    /// The image needs to be created on the same device, with the given `kind`
+    /// and creation `flags`. `Device::destroy_image` destroys it, when the
+    /// other library keeps the ownership, `mem::forget` the image instead.
+    pub unsafe fn from_raw(raw: vk::Image, kind: image::Kind, flags: vk::ImageCreateFlags) -> Self {
+        Image {
+            raw,
+            ty: match kind {
+                image::Kind::D1(..) => vk::ImageType::TYPE_1D,
+                image::Kind::D2(..) => vk::ImageType::TYPE_2D,
+                image::Kind::D3(..) => vk::ImageType::TYPE_3D,
+            },
+            flags,
+            extent: conv::map_extent(kind.extent()),
+            transcoded_format: None,
+        }
+    }
+
+    /// Underlying image handle, valid until the image is destroyed.
+    pub fn as_raw(&self) -> vk::Image {
+        self.raw
+    }
+
+    /// Returns true if the image is backed by an uncompressed format.
+    /// Copies from buffers into it are decoded on the host when the command buffer
+    /// is submitted, so the source data has to be written by the host beforehand.
+    pub fn is_transcoded(&self) -> bool {
+        self.transcoded_format.is_some()
+    }
 }
 
 #[derive(Debug, Hash, PartialEq, Eq)]
@@ -51,6 +115,13 @@ pub struct ImageView {
     pub(crate) range: SubresourceRange,
 }
 
+impl ImageView {
+    /// Underlying image view handle, valid until the view is destroyed.
+    pub fn as_raw(&self) -> vk::ImageView {
+        self.view
+    }
+}
+
 #[derive(Debug, Hash)]
 pub struct Sampler(pub vk::Sampler);
 
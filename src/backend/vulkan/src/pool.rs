@@ -16,6 +16,8 @@ pub struct RawCommandPool {
 
 impl pool::RawCommandPool<Backend> for RawCommandPool {
     unsafe fn reset(&mut self) {
+        #[cfg(feature = "etc2-transcode")]
+        self.device.4.reset_pool(&self.device.0, self.raw);
         assert_eq!(
             Ok(()),
             self.device
@@ -42,6 +44,7 @@ impl pool::RawCommandPool<Backend> for RawCommandPool {
             .map(|buffer| CommandBuffer {
                 raw: buffer,
                 device: device.clone(),
+                pool: self.raw,
             })
             .collect()
     }
@@ -52,6 +55,10 @@ impl pool::RawCommandPool<Backend> for RawCommandPool {
     {
         let buffers: SmallVec<[vk::CommandBuffer; 16]> =
             cbufs.into_iter().map(|buffer| buffer.raw).collect();
+        #[cfg(feature = "etc2-transcode")]
+        for &buffer in &buffers {
+            self.device.4.reset_command_buffer(&self.device.0, buffer);
+        }
         self.device.0.free_command_buffers(self.raw, &buffers);
     }
 }
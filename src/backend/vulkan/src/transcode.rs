@@ -0,0 +1,894 @@
+//! CPU fallback for ETC2 compressed images on devices that can't sample them
+//! (most desktop GPUs).
+//!
+//! Images created with an ETC2 format on such a device are backed by an
+//! uncompressed RGBA8 image instead. Copies from buffers into them are decoded
+//! on the host when the command buffers are submitted, so the source data has
+//! to be written by the host into host-visible memory before the submission.
+
+use ash::version::DeviceV1_0;
+use ash::vk;
+use hal::backend::FastHashMap;
+use hal::command::BufferImageCopy;
+use hal::format::Format;
+
+use std::sync::Mutex;
+use std::{ptr, slice};
+
+/// Modifier tables for the individual and differential modes.
+const ETC1_MODIFIERS: [[i32; 2]; 8] = [
+    [2, 8],
+    [5, 17],
+    [9, 29],
+    [13, 42],
+    [18, 60],
+    [24, 80],
+    [33, 106],
+    [47, 183],
+];
+
+/// Distance table for the T and H modes.
+const ETC2_DISTANCES: [i32; 8] = [3, 6, 11, 16, 23, 32, 41, 64];
+
+/// Modifier tables for the EAC alpha channel.
+const EAC_MODIFIERS: [[i32; 8]; 16] = [
+    [-3, -6, -9, -15, 2, 5, 8, 14],
+    [-3, -7, -10, -13, 2, 6, 9, 12],
+    [-2, -5, -8, -13, 1, 4, 7, 12],
+    [-2, -4, -6, -13, 1, 3, 5, 12],
+    [-3, -6, -8, -12, 2, 5, 7, 11],
+    [-3, -7, -9, -11, 2, 6, 8, 10],
+    [-4, -7, -8, -11, 3, 6, 7, 10],
+    [-3, -5, -8, -11, 2, 4, 7, 10],
+    [-2, -6, -8, -10, 1, 5, 7, 9],
+    [-2, -5, -8, -10, 1, 4, 7, 9],
+    [-2, -4, -8, -10, 1, 3, 7, 9],
+    [-2, -5, -7, -10, 1, 4, 6, 9],
+    [-3, -4, -7, -10, 2, 3, 6, 9],
+    [-1, -2, -3, -10, 0, 1, 2, 9],
+    [-4, -6, -8, -9, 3, 5, 7, 8],
+    [-3, -5, -7, -9, 2, 4, 6, 8],
+];
+
+/// Returns the uncompressed format used in place of `format`,
+/// if it's an ETC2 format that can be transcoded.
+pub fn fallback_format(format: Format) -> Option<Format> {
+    match format {
+        Format::Etc2R8g8b8Unorm | Format::Etc2R8g8b8a1Unorm | Format::Etc2R8g8b8a8Unorm => {
+            Some(Format::Rgba8Unorm)
+        }
+        Format::Etc2R8g8b8Srgb | Format::Etc2R8g8b8a1Srgb | Format::Etc2R8g8b8a8Srgb => {
+            Some(Format::Rgba8Srgb)
+        }
+        _ => None,
+    }
+}
+
+/// Returns the size in bytes of a 4x4 block of the given ETC2 format.
+fn block_size(format: Format) -> Option<usize> {
+    match format {
+        Format::Etc2R8g8b8Unorm
+        | Format::Etc2R8g8b8Srgb
+        | Format::Etc2R8g8b8a1Unorm
+        | Format::Etc2R8g8b8a1Srgb => Some(8),
+        Format::Etc2R8g8b8a8Unorm | Format::Etc2R8g8b8a8Srgb => Some(16),
+        _ => None,
+    }
+}
+
+/// Decode a single mip level of ETC2 data of the given `format` into tightly packed
+/// RGBA8 texels, ready to be uploaded into an image using `fallback_format`.
+///
+/// Returns `None` if the format isn't supported or `data` is too short for the extent.
+pub fn decode(format: Format, width: u32, height: u32, data: &[u8]) -> Option<Vec<u8>> {
+    let block_size = block_size(format)?;
+    let punchthrough = match format {
+        Format::Etc2R8g8b8a1Unorm | Format::Etc2R8g8b8a1Srgb => true,
+        _ => false,
+    };
+    let alpha = block_size == 16;
+
+    let blocks_x = (width as usize + 3) / 4;
+    let blocks_y = (height as usize + 3) / 4;
+    if data.len() < blocks_x * blocks_y * block_size {
+        return None;
+    }
+
+    let mut output = vec![0u8; width as usize * height as usize * 4];
+    let mut texels = [[0u8; 4]; 16];
+    for (i, chunk) in data
+        .chunks(block_size)
+        .take(blocks_x * blocks_y)
+        .enumerate()
+    {
+        let (color, alpha_block) = if alpha {
+            (read_block(&chunk[8..]), Some(read_block(&chunk[..8])))
+        } else {
+            (read_block(chunk), None)
+        };
+        decode_color_block(color, punchthrough, &mut texels);
+        if let Some(bits) = alpha_block {
+            decode_alpha_block(bits, &mut texels);
+        }
+
+        let (bx, by) = (i % blocks_x * 4, i / blocks_x * 4);
+        for (j, texel) in texels.iter().enumerate() {
+            // texels are stored in column-major order within a block
+            let (x, y) = (bx + j / 4, by + j % 4);
+            if x < width as usize && y < height as usize {
+                let offset = (y * width as usize + x) * 4;
+                output[offset..offset + 4].copy_from_slice(texel);
+            }
+        }
+    }
+
+    Some(output)
+}
+
+/// Layout of a region of ETC2 data to copy, and of its decoded counterpart.
+#[derive(Debug)]
+struct RegionLayout {
+    /// Region to copy from the staging buffer.
+    staging: BufferImageCopy,
+    /// Offset of the compressed data in the source buffer.
+    src_offset: usize,
+    /// Size in bytes of a single slice of the compressed data.
+    slice_size: usize,
+    num_slices: usize,
+}
+
+/// Lay out the decoded data of the non-empty `regions` in a staging buffer.
+/// Returns the layouts along with the staging buffer size.
+fn layout_regions(block_size: usize, regions: &[BufferImageCopy]) -> (Vec<RegionLayout>, usize) {
+    let mut layouts = Vec::with_capacity(regions.len());
+    let mut size = 0;
+    for region in regions {
+        let extent = region.image_extent;
+        let layers = &region.image_layers.layers;
+        if extent.width == 0
+            || extent.height == 0
+            || extent.depth == 0
+            || layers.start >= layers.end
+        {
+            continue;
+        }
+        let width = if region.buffer_width == 0 {
+            extent.width
+        } else {
+            region.buffer_width
+        };
+        let height = if region.buffer_height == 0 {
+            extent.height
+        } else {
+            region.buffer_height
+        };
+        let num_slices = (layers.end - layers.start) as usize * extent.depth as usize;
+
+        layouts.push(RegionLayout {
+            staging: BufferImageCopy {
+                buffer_offset: size as _,
+                buffer_width: width,
+                buffer_height: height,
+                ..region.clone()
+            },
+            src_offset: region.buffer_offset as usize,
+            slice_size: (width as usize + 3) / 4 * ((height as usize + 3) / 4) * block_size,
+            num_slices,
+        });
+        size += width as usize * height as usize * 4 * num_slices;
+    }
+    (layouts, size)
+}
+
+/// Staging buffer of a copy recorded into a command buffer,
+/// holding the decoded data of the source buffer.
+struct Staging {
+    pool: vk::CommandPool,
+    command_buffer: vk::CommandBuffer,
+    buffer: vk::Buffer,
+    memory: vk::DeviceMemory,
+    src: vk::Buffer,
+    format: Format,
+    layouts: Vec<RegionLayout>,
+}
+
+/// Host-side state needed to decode the copies into fallback images.
+pub(crate) struct Transcoder {
+    /// Mask of the host-visible and coherent memory types.
+    staging_types: u32,
+    /// Properties of the ETC2 formats reported by the physical device.
+    format_properties: Vec<(Format, vk::FormatProperties)>,
+    /// Memory and offset each buffer is bound to.
+    bindings: Mutex<FastHashMap<vk::Buffer, (vk::DeviceMemory, u64)>>,
+    /// Host address and offset of the currently mapped memory objects.
+    mappings: Mutex<FastHashMap<vk::DeviceMemory, (usize, u64)>>,
+    staging: Mutex<Vec<Staging>>,
+    /// Secondary command buffers executed by the primary ones, along with their pools.
+    executed: Mutex<Vec<(vk::CommandPool, vk::CommandBuffer, vk::CommandBuffer)>>,
+}
+
+impl Transcoder {
+    pub(crate) fn new<F>(
+        memory_properties: &vk::PhysicalDeviceMemoryProperties,
+        format_properties: F,
+    ) -> Self
+    where
+        F: Fn(Format) -> vk::FormatProperties,
+    {
+        let flags = vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT;
+        let staging_types = memory_properties.memory_types
+            [..memory_properties.memory_type_count as usize]
+            .iter()
+            .enumerate()
+            .filter(|&(_, ty)| ty.property_flags.contains(flags))
+            .fold(0, |mask, (i, _)| mask | 1 << i);
+
+        let format_properties = [
+            Format::Etc2R8g8b8Unorm,
+            Format::Etc2R8g8b8Srgb,
+            Format::Etc2R8g8b8a1Unorm,
+            Format::Etc2R8g8b8a1Srgb,
+            Format::Etc2R8g8b8a8Unorm,
+            Format::Etc2R8g8b8a8Srgb,
+        ]
+        .iter()
+        .map(|&format| (format, format_properties(format)))
+        .collect();
+
+        Transcoder {
+            staging_types,
+            format_properties,
+            bindings: Mutex::new(FastHashMap::default()),
+            mappings: Mutex::new(FastHashMap::default()),
+            staging: Mutex::new(Vec::new()),
+            executed: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Returns the uncompressed format backing the images of the given `format`,
+    /// if it's an ETC2 format the device can't sample with the given `tiling`.
+    pub(crate) fn fallback_format(
+        &self,
+        format: Format,
+        tiling: vk::ImageTiling,
+    ) -> Option<Format> {
+        let properties = self
+            .format_properties
+            .iter()
+            .find(|&&(etc2, _)| etc2 == format)?
+            .1;
+        let features = if tiling == vk::ImageTiling::LINEAR {
+            properties.linear_tiling_features
+        } else {
+            properties.optimal_tiling_features
+        };
+        if features.contains(vk::FormatFeatureFlags::SAMPLED_IMAGE) {
+            None
+        } else {
+            fallback_format(format)
+        }
+    }
+
+    pub(crate) fn bind_buffer(&self, buffer: vk::Buffer, memory: vk::DeviceMemory, offset: u64) {
+        self.bindings
+            .lock()
+            .unwrap()
+            .insert(buffer, (memory, offset));
+    }
+
+    pub(crate) fn destroy_buffer(&self, buffer: vk::Buffer) {
+        self.bindings.lock().unwrap().remove(&buffer);
+    }
+
+    pub(crate) fn map_memory(&self, memory: vk::DeviceMemory, ptr: *mut u8, offset: u64) {
+        self.mappings
+            .lock()
+            .unwrap()
+            .insert(memory, (ptr as usize, offset));
+    }
+
+    pub(crate) fn unmap_memory(&self, memory: vk::DeviceMemory) {
+        self.mappings.lock().unwrap().remove(&memory);
+    }
+
+    /// Free the staging buffers of a command buffer, once it's reset or freed.
+    pub(crate) unsafe fn reset_command_buffer(
+        &self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+    ) {
+        self.executed
+            .lock()
+            .unwrap()
+            .retain(|&(_, primary, _)| primary != command_buffer);
+        self.release(device, |staging| staging.command_buffer == command_buffer);
+    }
+
+    /// Free the staging buffers of all the command buffers of a pool,
+    /// once it's reset or destroyed.
+    pub(crate) unsafe fn reset_pool(&self, device: &ash::Device, pool: vk::CommandPool) {
+        self.executed
+            .lock()
+            .unwrap()
+            .retain(|&(primary_pool, _, _)| primary_pool != pool);
+        self.release(device, |staging| staging.pool == pool);
+    }
+
+    unsafe fn release<F: Fn(&Staging) -> bool>(&self, device: &ash::Device, filter: F) {
+        self.staging.lock().unwrap().retain(|staging| {
+            if filter(staging) {
+                device.destroy_buffer(staging.buffer, None);
+                device.free_memory(staging.memory, None);
+                false
+            } else {
+                true
+            }
+        });
+    }
+
+    /// Remember the secondary command buffers executed by a primary one,
+    /// so that their copies are decoded when the latter is submitted.
+    pub(crate) fn execute_commands(
+        &self,
+        pool: vk::CommandPool,
+        primary: vk::CommandBuffer,
+        secondaries: &[vk::CommandBuffer],
+    ) {
+        self.executed.lock().unwrap().extend(
+            secondaries
+                .iter()
+                .map(|&secondary| (pool, primary, secondary)),
+        );
+    }
+
+    /// Create a staging buffer owned by `command_buffer` for the decoded `regions` of `src`,
+    /// holding data of the ETC2 `format`. The data is decoded when the command buffer is
+    /// submitted. Returns the staging buffer along with the regions to copy from it,
+    /// or `None` if all the regions are empty.
+    pub(crate) unsafe fn prepare_copy(
+        &self,
+        device: &ash::Device,
+        pool: vk::CommandPool,
+        command_buffer: vk::CommandBuffer,
+        src: vk::Buffer,
+        format: Format,
+        regions: &[BufferImageCopy],
+    ) -> Option<(vk::Buffer, Vec<BufferImageCopy>)> {
+        let block_size = block_size(format).expect("Only ETC2 images are transcoded");
+        assert!(
+            self.bindings.lock().unwrap().contains_key(&src),
+            "Buffer {:?} is not bound to memory",
+            src
+        );
+        let (layouts, size) = layout_regions(block_size, regions);
+        if layouts.is_empty() {
+            return None;
+        }
+
+        let (buffer, memory) = match self.create_staging(device, size as _) {
+            Ok(staging) => staging,
+            Err(err) => panic!("Unable to allocate the ETC2 staging buffer: {:?}", err),
+        };
+        let staging_regions = layouts
+            .iter()
+            .map(|layout| layout.staging.clone())
+            .collect();
+        self.staging.lock().unwrap().push(Staging {
+            pool,
+            command_buffer,
+            buffer,
+            memory,
+            src,
+            format,
+            layouts,
+        });
+        Some((buffer, staging_regions))
+    }
+
+    /// Decode the ETC2 data of the copies recorded into the submitted command buffers,
+    /// and the secondary ones they execute, into their staging buffers.
+    ///
+    /// The data is read by the host, so it has to be written by the host as well.
+    pub(crate) unsafe fn decode_submission(
+        &self,
+        device: &ash::Device,
+        command_buffers: &[vk::CommandBuffer],
+    ) {
+        let executed = self.executed.lock().unwrap();
+        let is_submitted = |command_buffer: vk::CommandBuffer| {
+            command_buffers.contains(&command_buffer)
+                || executed.iter().any(|&(_, primary, secondary)| {
+                    secondary == command_buffer && command_buffers.contains(&primary)
+                })
+        };
+
+        for staging in self.staging.lock().unwrap().iter() {
+            if is_submitted(staging.command_buffer) {
+                self.decode_staging(device, staging);
+            }
+        }
+    }
+
+    unsafe fn decode_staging(&self, device: &ash::Device, staging: &Staging) {
+        let (memory, bind_offset) = match self.bindings.lock().unwrap().get(&staging.src) {
+            Some(&binding) => binding,
+            None => panic!("Buffer {:?} is not bound to memory", staging.src),
+        };
+
+        // Read the compressed data through the current mapping, if any.
+        let mapping = self.mappings.lock().unwrap().get(&memory).cloned();
+        let base = match mapping {
+            Some((address, offset)) => {
+                (address as *const u8).offset(bind_offset as isize - offset as isize)
+            }
+            None => match device.map_memory(
+                memory,
+                bind_offset,
+                vk::WHOLE_SIZE,
+                vk::MemoryMapFlags::empty(),
+            ) {
+                Ok(ptr) => ptr as *const u8,
+                Err(err) => panic!(
+                    "Unable to read the ETC2 data of {:?}: {:?}",
+                    staging.src, err
+                ),
+            },
+        };
+        let target = match device.map_memory(
+            staging.memory,
+            0,
+            vk::WHOLE_SIZE,
+            vk::MemoryMapFlags::empty(),
+        ) {
+            Ok(ptr) => ptr as *mut u8,
+            Err(err) => panic!("Unable to write the ETC2 staging buffer: {:?}", err),
+        };
+
+        for layout in &staging.layouts {
+            let data = slice::from_raw_parts(
+                base.offset(layout.src_offset as isize),
+                layout.slice_size * layout.num_slices,
+            );
+            let mut offset = layout.staging.buffer_offset as usize;
+            for chunk in data.chunks(layout.slice_size) {
+                let decoded = decode(
+                    staging.format,
+                    layout.staging.buffer_width,
+                    layout.staging.buffer_height,
+                    chunk,
+                )
+                .unwrap();
+                ptr::copy_nonoverlapping(decoded.as_ptr(), target.add(offset), decoded.len());
+                offset += decoded.len();
+            }
+        }
+
+        device.unmap_memory(staging.memory);
+        if mapping.is_none() {
+            device.unmap_memory(memory);
+        }
+    }
+
+    unsafe fn create_staging(
+        &self,
+        device: &ash::Device,
+        size: vk::DeviceSize,
+    ) -> Result<(vk::Buffer, vk::DeviceMemory), vk::Result> {
+        let info = vk::BufferCreateInfo {
+            s_type: vk::StructureType::BUFFER_CREATE_INFO,
+            p_next: ptr::null(),
+            flags: vk::BufferCreateFlags::empty(),
+            size,
+            usage: vk::BufferUsageFlags::TRANSFER_SRC,
+            sharing_mode: vk::SharingMode::EXCLUSIVE,
+            queue_family_index_count: 0,
+            p_queue_family_indices: ptr::null(),
+        };
+        let buffer = device.create_buffer(&info, None)?;
+
+        // Buffers can always be allocated from a host-visible and coherent type.
+        let requirements = device.get_buffer_memory_requirements(buffer);
+        let info = vk::MemoryAllocateInfo {
+            s_type: vk::StructureType::MEMORY_ALLOCATE_INFO,
+            p_next: ptr::null(),
+            allocation_size: requirements.size,
+            memory_type_index: (requirements.memory_type_bits & self.staging_types)
+                .trailing_zeros(),
+        };
+        let memory = match device.allocate_memory(&info, None) {
+            Ok(memory) => memory,
+            Err(err) => {
+                device.destroy_buffer(buffer, None);
+                return Err(err);
+            }
+        };
+
+        match device.bind_buffer_memory(buffer, memory, 0) {
+            Ok(()) => Ok((buffer, memory)),
+            Err(err) => {
+                device.destroy_buffer(buffer, None);
+                device.free_memory(memory, None);
+                Err(err)
+            }
+        }
+    }
+}
+
+fn read_block(bytes: &[u8]) -> u64 {
+    bytes[..8]
+        .iter()
+        .fold(0, |acc, &byte| (acc << 8) | byte as u64)
+}
+
+fn bits(block: u64, high: u32, low: u32) -> i32 {
+    ((block >> low) & ((1 << (high - low + 1)) - 1)) as i32
+}
+
+fn extend_4(value: i32) -> i32 {
+    (value << 4) | value
+}
+
+fn extend_5(value: i32) -> i32 {
+    (value << 3) | (value >> 2)
+}
+
+fn extend_6(value: i32) -> i32 {
+    (value << 2) | (value >> 4)
+}
+
+fn extend_7(value: i32) -> i32 {
+    (value << 1) | (value >> 6)
+}
+
+fn clamp(value: i32) -> u8 {
+    value.max(0).min(255) as u8
+}
+
+fn pixel_index(block: u64, i: usize) -> usize {
+    let msb = (block >> (16 + i)) & 1;
+    let lsb = (block >> i) & 1;
+    (msb << 1 | lsb) as usize
+}
+
+fn decode_color_block(block: u64, punchthrough: bool, texels: &mut [[u8; 4]; 16]) {
+    let diff = block & (1 << 33) != 0;
+    let opaque = !punchthrough || diff;
+
+    if !diff && !punchthrough {
+        let base = [
+            [
+                extend_4(bits(block, 63, 60)),
+                extend_4(bits(block, 55, 52)),
+                extend_4(bits(block, 47, 44)),
+            ],
+            [
+                extend_4(bits(block, 59, 56)),
+                extend_4(bits(block, 51, 48)),
+                extend_4(bits(block, 43, 40)),
+            ],
+        ];
+        return decode_subblocks(block, base, true, texels);
+    }
+
+    let r = bits(block, 63, 59);
+    let g = bits(block, 55, 51);
+    let b = bits(block, 47, 43);
+    let r2 = r + (bits(block, 58, 56) << 29 >> 29);
+    let g2 = g + (bits(block, 50, 48) << 29 >> 29);
+    let b2 = b + (bits(block, 42, 40) << 29 >> 29);
+
+    if r2 < 0 || r2 > 31 {
+        decode_t_block(block, opaque, texels);
+    } else if g2 < 0 || g2 > 31 {
+        decode_h_block(block, opaque, texels);
+    } else if b2 < 0 || b2 > 31 {
+        decode_planar_block(block, texels);
+    } else {
+        let base = [
+            [extend_5(r), extend_5(g), extend_5(b)],
+            [extend_5(r2), extend_5(g2), extend_5(b2)],
+        ];
+        decode_subblocks(block, base, opaque, texels);
+    }
+}
+
+fn decode_subblocks(block: u64, base: [[i32; 3]; 2], opaque: bool, texels: &mut [[u8; 4]; 16]) {
+    let flip = block & (1 << 32) != 0;
+    let tables = [bits(block, 39, 37) as usize, bits(block, 36, 34) as usize];
+
+    for (i, texel) in texels.iter_mut().enumerate() {
+        let (x, y) = (i / 4, i % 4);
+        let sub = if flip { y >= 2 } else { x >= 2 } as usize;
+        let [small, large] = ETC1_MODIFIERS[tables[sub]];
+        let modifier = match pixel_index(block, i) {
+            0 if opaque => small,
+            0 => 0,
+            1 => large,
+            2 if opaque => -small,
+            2 => {
+                *texel = [0; 4];
+                continue;
+            }
+            _ => -large,
+        };
+        let color = base[sub];
+        *texel = [
+            clamp(color[0] + modifier),
+            clamp(color[1] + modifier),
+            clamp(color[2] + modifier),
+            255,
+        ];
+    }
+}
+
+fn apply_paint(block: u64, paint: [[i32; 3]; 4], opaque: bool, texels: &mut [[u8; 4]; 16]) {
+    for (i, texel) in texels.iter_mut().enumerate() {
+        let index = pixel_index(block, i);
+        *texel = if !opaque && index == 2 {
+            [0; 4]
+        } else {
+            let color = paint[index];
+            [clamp(color[0]), clamp(color[1]), clamp(color[2]), 255]
+        };
+    }
+}
+
+fn decode_t_block(block: u64, opaque: bool, texels: &mut [[u8; 4]; 16]) {
+    let c1 = [
+        extend_4(bits(block, 60, 59) << 2 | bits(block, 57, 56)),
+        extend_4(bits(block, 55, 52)),
+        extend_4(bits(block, 51, 48)),
+    ];
+    let c2 = [
+        extend_4(bits(block, 47, 44)),
+        extend_4(bits(block, 43, 40)),
+        extend_4(bits(block, 39, 36)),
+    ];
+    let d = ETC2_DISTANCES[(bits(block, 35, 34) << 1 | bits(block, 32, 32)) as usize];
+
+    let paint = [
+        c1,
+        [c2[0] + d, c2[1] + d, c2[2] + d],
+        c2,
+        [c2[0] - d, c2[1] - d, c2[2] - d],
+    ];
+    apply_paint(block, paint, opaque, texels);
+}
+
+fn decode_h_block(block: u64, opaque: bool, texels: &mut [[u8; 4]; 16]) {
+    let raw1 = [
+        bits(block, 62, 59),
+        bits(block, 58, 56) << 1 | bits(block, 52, 52),
+        bits(block, 51, 51) << 3 | bits(block, 49, 47),
+    ];
+    let raw2 = [
+        bits(block, 46, 43),
+        bits(block, 42, 39),
+        bits(block, 38, 35),
+    ];
+    let order = |c: [i32; 3]| c[0] << 16 | c[1] << 8 | c[2];
+    let index =
+        bits(block, 34, 34) << 2 | bits(block, 32, 32) << 1 | (order(raw1) >= order(raw2)) as i32;
+    let d = ETC2_DISTANCES[index as usize];
+
+    let c1 = [extend_4(raw1[0]), extend_4(raw1[1]), extend_4(raw1[2])];
+    let c2 = [extend_4(raw2[0]), extend_4(raw2[1]), extend_4(raw2[2])];
+    let paint = [
+        [c1[0] + d, c1[1] + d, c1[2] + d],
+        [c1[0] - d, c1[1] - d, c1[2] - d],
+        [c2[0] + d, c2[1] + d, c2[2] + d],
+        [c2[0] - d, c2[1] - d, c2[2] - d],
+    ];
+    apply_paint(block, paint, opaque, texels);
+}
+
+fn decode_planar_block(block: u64, texels: &mut [[u8; 4]; 16]) {
+    let origin = [
+        extend_6(bits(block, 62, 57)),
+        extend_7(bits(block, 56, 56) << 6 | bits(block, 54, 49)),
+        extend_6(bits(block, 48, 48) << 5 | bits(block, 44, 43) << 3 | bits(block, 41, 39)),
+    ];
+    let horizontal = [
+        extend_6(bits(block, 38, 34) << 1 | bits(block, 32, 32)),
+        extend_7(bits(block, 31, 25)),
+        extend_6(bits(block, 24, 19)),
+    ];
+    let vertical = [
+        extend_6(bits(block, 18, 13)),
+        extend_7(bits(block, 12, 6)),
+        extend_6(bits(block, 5, 0)),
+    ];
+
+    for (i, texel) in texels.iter_mut().enumerate() {
+        let (x, y) = ((i / 4) as i32, (i % 4) as i32);
+        let channel = |c: usize| {
+            clamp(
+                (x * (horizontal[c] - origin[c])
+                    + y * (vertical[c] - origin[c])
+                    + 4 * origin[c]
+                    + 2)
+                    >> 2,
+            )
+        };
+        *texel = [channel(0), channel(1), channel(2), 255];
+    }
+}
+
+fn decode_alpha_block(block: u64, texels: &mut [[u8; 4]; 16]) {
+    let base = bits(block, 63, 56);
+    let multiplier = bits(block, 55, 52);
+    let table = &EAC_MODIFIERS[bits(block, 51, 48) as usize];
+
+    for (i, texel) in texels.iter_mut().enumerate() {
+        let index = ((block >> (45 - 3 * i)) & 7) as usize;
+        texel[3] = clamp(base + table[index] * multiplier);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, layout_regions};
+    use hal::command::BufferImageCopy;
+    use hal::format::{Aspects, Format};
+    use hal::image::{Extent, Offset, SubresourceLayers};
+
+    fn decode_block(format: Format, block: &[u8]) -> Vec<[u8; 4]> {
+        decode(format, 4, 4, block)
+            .unwrap()
+            .chunks(4)
+            .map(|t| [t[0], t[1], t[2], t[3]])
+            .collect()
+    }
+
+    // Texels are listed in row-major order, pixel indices are column-major.
+    fn texel(texels: &[[u8; 4]], x: usize, y: usize) -> [u8; 4] {
+        texels[y * 4 + x]
+    }
+
+    #[test]
+    fn test_decode_individual_block() {
+        // An all-zero block is in individual mode with black base colors,
+        // the first modifier table and every pixel using the small positive modifier.
+        let data = decode(Format::Etc2R8g8b8Unorm, 4, 4, &[0; 8]).unwrap();
+        assert_eq!(data.len(), 4 * 4 * 4);
+        for texel in data.chunks(4) {
+            assert_eq!(texel, &[2, 2, 2, 255]);
+        }
+
+        // Red left and green right sub-blocks, the first pixel uses the
+        // large negative modifier.
+        let texels = decode_block(
+            Format::Etc2R8g8b8Unorm,
+            &[0xF0, 0x0F, 0x00, 0x00, 0x00, 0x01, 0x00, 0x01],
+        );
+        assert_eq!(texel(&texels, 0, 0), [247, 0, 0, 255]);
+        assert_eq!(texel(&texels, 1, 3), [255, 2, 2, 255]);
+        assert_eq!(texel(&texels, 2, 0), [2, 255, 2, 255]);
+        assert_eq!(texel(&texels, 3, 3), [2, 255, 2, 255]);
+    }
+
+    #[test]
+    fn test_decode_differential_block() {
+        // Flipped sub-blocks with the base colors (16, 8, 0) and (17, 7, 0).
+        let texels = decode_block(
+            Format::Etc2R8g8b8Unorm,
+            &[0x81, 0x47, 0x00, 0x03, 0x00, 0x00, 0x00, 0x00],
+        );
+        for x in 0..4 {
+            assert_eq!(texel(&texels, x, 1), [134, 68, 2, 255]);
+            assert_eq!(texel(&texels, x, 2), [142, 59, 2, 255]);
+        }
+    }
+
+    #[test]
+    fn test_decode_t_block() {
+        // Red overflows: green and (8, 8, 8) colors at the distance 3,
+        // the first column uses the four paint colors in order.
+        let texels = decode_block(
+            Format::Etc2R8g8b8Unorm,
+            &[0x04, 0xF0, 0x88, 0x82, 0x00, 0x0C, 0x00, 0x0A],
+        );
+        assert_eq!(texel(&texels, 0, 0), [0, 255, 0, 255]);
+        assert_eq!(texel(&texels, 0, 1), [139, 139, 139, 255]);
+        assert_eq!(texel(&texels, 0, 2), [136, 136, 136, 255]);
+        assert_eq!(texel(&texels, 0, 3), [133, 133, 133, 255]);
+        assert_eq!(texel(&texels, 3, 3), [0, 255, 0, 255]);
+    }
+
+    #[test]
+    fn test_decode_h_block() {
+        // Green overflows: red and green colors at the distance 6,
+        // the first column uses the four paint colors in order.
+        let texels = decode_block(
+            Format::Etc2R8g8b8Unorm,
+            &[0x40, 0x04, 0x04, 0x02, 0x00, 0x0C, 0x00, 0x0A],
+        );
+        assert_eq!(texel(&texels, 0, 0), [142, 6, 6, 255]);
+        assert_eq!(texel(&texels, 0, 1), [130, 0, 0, 255]);
+        assert_eq!(texel(&texels, 0, 2), [6, 142, 6, 255]);
+        assert_eq!(texel(&texels, 0, 3), [0, 130, 0, 255]);
+    }
+
+    #[test]
+    fn test_decode_planar_block() {
+        // Blue overflows: black origin with a horizontal red gradient.
+        let texels = decode_block(
+            Format::Etc2R8g8b8Unorm,
+            &[0x00, 0x00, 0x04, 0x7F, 0x00, 0x00, 0x00, 0x00],
+        );
+        for (x, &red) in [0, 64, 128, 191].iter().enumerate() {
+            for y in 0..4 {
+                assert_eq!(texel(&texels, x, y), [red, 0, 0, 255]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_decode_eac_block() {
+        // Base alpha 128 with the first modifier table, the second pixel
+        // uses the largest positive modifier.
+        let mut block = [0; 16];
+        block[..3].copy_from_slice(&[0x80, 0x10, 0x1C]);
+        let texels = decode_block(Format::Etc2R8g8b8a8Unorm, &block);
+        assert_eq!(texel(&texels, 0, 0), [2, 2, 2, 125]);
+        assert_eq!(texel(&texels, 0, 1), [2, 2, 2, 142]);
+        assert_eq!(texel(&texels, 3, 3), [2, 2, 2, 125]);
+    }
+
+    #[test]
+    fn test_decode_short_data() {
+        assert_eq!(decode(Format::Etc2R8g8b8Unorm, 8, 4, &[0; 8]), None);
+        assert_eq!(decode(Format::Rgba8Unorm, 4, 4, &[0; 64]), None);
+    }
+
+    #[test]
+    fn test_decode_empty() {
+        assert_eq!(decode(Format::Etc2R8g8b8Unorm, 0, 4, &[]), Some(Vec::new()));
+    }
+
+    fn region(buffer_offset: u64, buffer_width: u32, width: u32, layers: u16) -> BufferImageCopy {
+        BufferImageCopy {
+            buffer_offset,
+            buffer_width,
+            buffer_height: 0,
+            image_layers: SubresourceLayers {
+                aspects: Aspects::COLOR,
+                level: 0,
+                layers: 0..layers,
+            },
+            image_offset: Offset::ZERO,
+            image_extent: Extent {
+                width,
+                height: 8,
+                depth: 1,
+            },
+        }
+    }
+
+    #[test]
+    fn test_layout_regions() {
+        let regions = [
+            region(0, 0, 8, 2),
+            region(64, 0, 0, 1),
+            region(96, 16, 4, 1),
+            region(128, 0, 8, 0),
+        ];
+        let (layouts, size) = layout_regions(8, &regions);
+
+        // Empty regions are skipped.
+        assert_eq!(layouts.len(), 2);
+        assert_eq!(layouts[0].staging.buffer_offset, 0);
+        assert_eq!(layouts[0].staging.buffer_width, 8);
+        assert_eq!(layouts[0].staging.buffer_height, 8);
+        assert_eq!(layouts[0].src_offset, 0);
+        assert_eq!(layouts[0].slice_size, 2 * 2 * 8);
+        assert_eq!(layouts[0].num_slices, 2);
+
+        // Decoded rows keep the length of the compressed ones.
+        assert_eq!(layouts[1].staging.buffer_offset, 8 * 8 * 4 * 2);
+        assert_eq!(layouts[1].staging.buffer_width, 16);
+        assert_eq!(layouts[1].src_offset, 96);
+        assert_eq!(layouts[1].slice_size, 4 * 2 * 8);
+        assert_eq!(size, 8 * 8 * 4 * 2 + 16 * 8 * 4);
+    }
+}
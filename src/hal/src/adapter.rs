@@ -49,6 +49,25 @@ pub struct MemoryProperties {
     pub memory_heaps: Vec<u64>,
 }
 
+impl MemoryProperties {
+    /// Find the first memory type allowed by the `type_mask` of the resource
+    /// `Requirements`, which has all of the requested properties.
+    ///
+    /// Memory types are ordered by the backends from the most to the least
+    /// performant, so the first match is usually the best one.
+    pub fn find_memory_type(
+        &self,
+        type_mask: u64,
+        properties: memory::Properties,
+    ) -> Option<MemoryTypeId> {
+        self.memory_types
+            .iter()
+            .enumerate()
+            .position(|(id, ty)| type_mask & (1 << id) != 0 && ty.properties.contains(properties))
+            .map(MemoryTypeId)
+    }
+}
+
 /// Represents a physical device (such as a GPU) capable of supporting the given backend.
 pub trait PhysicalDevice<B: Backend>: Any + Send + Sync {
     /// Create a new logical device with the requested features. If `requested_features` is
@@ -80,6 +99,11 @@ pub trait PhysicalDevice<B: Backend>: Any + Send + Sync {
     ) -> Result<Gpu<B>, DeviceCreationError>;
 
     /// Fetch details for a particular format.
+    ///
+    /// The features tell if images of the format can be sampled, filtered linearly,
+    /// used as storage or rendered to with or without blending, which allows to pick
+    /// depth and HDR formats at runtime. The supported sample counts depend on the
+    /// image usage and are reported by `image_format_properties`.
     fn format_properties(&self, format: Option<format::Format>) -> format::Properties;
 
     /// Fetch details for a particular image format.
@@ -92,6 +116,34 @@ pub trait PhysicalDevice<B: Backend>: Any + Send + Sync {
         view_caps: image::ViewCapabilities,
     ) -> Option<image::FormatProperties>;
 
+    /// Find the highest sample count, up to `samples`, supported by 2D images
+    /// of every one of the `formats` with the given usage.
+    ///
+    /// Supported sample counts vary between backends and formats, so this picks
+    /// a count usable by all the attachments of a render pass at once.
+    /// Returns `None` if one of the formats can't be used for such images.
+    fn max_common_samples(
+        &self,
+        samples: image::NumSamples,
+        formats: &[format::Format],
+        usage: image::Usage,
+    ) -> Option<image::NumSamples> {
+        let masks = formats
+            .iter()
+            .map(|&format| {
+                self.image_format_properties(
+                    format,
+                    2,
+                    image::Tiling::Optimal,
+                    usage,
+                    image::ViewCapabilities::empty(),
+                )
+                .map(|properties| properties.sample_count_mask)
+            })
+            .collect::<Option<Vec<_>>>()?;
+        Some(max_common_sample_count(samples, masks))
+    }
+
     /// Fetch details for the memory regions provided by the device.
     fn memory_properties(&self) -> MemoryProperties;
 
@@ -106,6 +158,21 @@ pub trait PhysicalDevice<B: Backend>: Any + Send + Sync {
     fn is_valid_cache(&self, _cache: &[u8]) -> bool { false }
 }
 
+/// Highest sample count, up to `samples`, present in every one of the sample
+/// count `masks`. Falls back to a single sample if there is no such count.
+fn max_common_sample_count<I>(samples: image::NumSamples, masks: I) -> image::NumSamples
+where
+    I: IntoIterator<Item = image::NumSamples>,
+{
+    let mask = masks.into_iter().fold(!0, |common, mask| common & mask);
+    // Each power of two sample count has its own bit in the mask.
+    let mut count = 1 << (7 - samples.max(1).leading_zeros());
+    while count > 1 && mask & count == 0 {
+        count >>= 1;
+    }
+    count
+}
+
 /// Supported physical device types
 #[derive(Clone, PartialEq, Eq, Debug)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
@@ -205,3 +272,35 @@ impl<B: Backend> Adapter<B> {
         Ok((device, queues.take(id).unwrap()))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::max_common_sample_count;
+
+    #[test]
+    fn test_max_common_sample_count() {
+        assert_eq!(max_common_sample_count(8, vec![0b1111, 0b0111]), 4);
+        assert_eq!(max_common_sample_count(4, vec![0b1111]), 4);
+        assert_eq!(max_common_sample_count(6, vec![0b1111]), 4);
+        assert_eq!(max_common_sample_count(0, vec![0b1111]), 1);
+        assert_eq!(max_common_sample_count(8, vec![]), 8);
+    }
+
+    #[test]
+    fn test_max_common_sample_count_disjoint() {
+        assert_eq!(max_common_sample_count(16, vec![0b0101, 0b1010]), 1);
+        assert_eq!(max_common_sample_count(16, vec![0b1101, 0b1010]), 8);
+    }
+
+    #[test]
+    fn test_max_common_sample_count_zero_mask() {
+        assert_eq!(max_common_sample_count(4, vec![0]), 1);
+        assert_eq!(max_common_sample_count(4, vec![0b0111, 0]), 1);
+    }
+
+    #[test]
+    fn test_max_common_sample_count_above_maximum() {
+        assert_eq!(max_common_sample_count(64, vec![0b0111]), 4);
+        assert_eq!(max_common_sample_count(255, vec![0b1111_1111]), 128);
+    }
+}
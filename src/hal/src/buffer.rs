@@ -80,6 +80,8 @@ bitflags!(
         const VERTEX = 0x80;
         ///
         const INDIRECT = 0x100;
+        /// Predicate of conditional rendering, see `begin_conditional_rendering`.
+        const CONDITIONAL_RENDERING = 0x200;
     }
 );
 
@@ -124,6 +126,8 @@ bitflags!(
         const MEMORY_READ = 0x8000;
         ///
         const MEMORY_WRITE = 0x10000;
+        /// Read the condition of conditional rendering.
+        const CONDITIONAL_RENDERING_READ = 0x100000;
     }
 );
 
@@ -6,6 +6,7 @@ use super::{
     ClearColorRaw, ClearDepthStencilRaw, ClearValueRaw, CommandBuffer, DescriptorSetOffset, Level,
     Primary, RawCommandBuffer, RenderPassInlineEncoder, RenderPassSecondaryEncoder, Shot,
 };
+use memory::Barrier;
 use queue::capability::{Graphics, GraphicsOrCompute, Supports};
 use Backend;
 use {buffer, image, pso, query};
@@ -386,4 +387,45 @@ impl<B: Backend, C: Supports<GraphicsOrCompute>, S: Shot, L: Level> CommandBuffe
     pub unsafe fn write_timestamp(&mut self, stage: pso::PipelineStage, query: query::Query<B>) {
         self.raw.write_timestamp(stage, query)
     }
+
+    /// Identical to the `RawCommandBuffer` method of the same name.
+    pub unsafe fn begin_conditional_rendering(
+        &mut self,
+        buffer: &B::Buffer,
+        offset: buffer::Offset,
+        inverted: bool,
+    ) {
+        self.raw
+            .begin_conditional_rendering(buffer, offset, inverted)
+    }
+
+    /// Identical to the `RawCommandBuffer` method of the same name.
+    pub unsafe fn end_conditional_rendering(&mut self) {
+        self.raw.end_conditional_rendering()
+    }
+
+    /// Identical to the `RawCommandBuffer` method of the same name.
+    pub unsafe fn set_event(&mut self, event: &B::Event, stages: pso::PipelineStage) {
+        self.raw.set_event(event, stages)
+    }
+
+    /// Identical to the `RawCommandBuffer` method of the same name.
+    pub unsafe fn reset_event(&mut self, event: &B::Event, stages: pso::PipelineStage) {
+        self.raw.reset_event(event, stages)
+    }
+
+    /// Identical to the `RawCommandBuffer` method of the same name.
+    pub unsafe fn wait_events<'i, I, J>(
+        &mut self,
+        events: I,
+        stages: Range<pso::PipelineStage>,
+        barriers: J,
+    ) where
+        I: IntoIterator,
+        I::Item: Borrow<B::Event>,
+        J: IntoIterator,
+        J::Item: Borrow<Barrier<'i, B>>,
+    {
+        self.raw.wait_events(events, stages, barriers)
+    }
 }
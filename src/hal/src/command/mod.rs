@@ -182,6 +182,21 @@ impl<B: Backend, C, S: Shot, L: Level> CommandBuffer<B, C, S, L> {
         &mut self.raw
     }*/
 
+    /// Identical to the `RawCommandBuffer` method of the same name.
+    pub unsafe fn begin_debug_marker(&mut self, name: &str, color: u32) {
+        self.raw.begin_debug_marker(name, color);
+    }
+
+    /// Identical to the `RawCommandBuffer` method of the same name.
+    pub unsafe fn end_debug_marker(&mut self) {
+        self.raw.end_debug_marker();
+    }
+
+    /// Identical to the `RawCommandBuffer` method of the same name.
+    pub unsafe fn insert_debug_marker(&mut self, name: &str, color: u32) {
+        self.raw.insert_debug_marker(name, color);
+    }
+
     /// Downgrade a command buffer to a lesser capability type.
     pub unsafe fn downgrade<D>(&mut self) -> &mut CommandBuffer<B, D, S>
     where
@@ -74,6 +74,9 @@ bitflags! {
 
         /// If set on a secondary command buffer, it says the command buffer takes place entirely inside
         /// a render pass. Ignored on primary command buffer.
+        ///
+        /// The subpass, and optionally the framebuffer, are given by the `CommandBufferInheritanceInfo`.
+        /// Only commands allowed inside of a subpass may be recorded.
         const RENDER_PASS_CONTINUE = 0x2;
 
         // TODO: I feel like this could be better.
@@ -136,6 +139,10 @@ pub trait RawCommandBuffer<B: Backend>: Any + Send + Sync {
     // Should probably be a whole book chapter on synchronization and stuff really.
     /// Inserts a synchronization dependency between pipeline stages
     /// in the command buffer.
+    ///
+    /// Image barriers transition the given subresources between the layouts of their states,
+    /// backends without explicit layouts only use them to derive the resource states.
+    /// Backends tracking hazards on their own may ignore the barriers entirely.
     unsafe fn pipeline_barrier<'a, T>(
         &mut self,
         stages: Range<pso::PipelineStage>,
@@ -145,6 +152,29 @@ pub trait RawCommandBuffer<B: Backend>: Any + Send + Sync {
         T: IntoIterator,
         T::Item: Borrow<Barrier<'a, B>>;
 
+    /// Signal the event once all previously recorded commands
+    /// have completed the given pipeline stages.
+    unsafe fn set_event(&mut self, event: &B::Event, stages: pso::PipelineStage);
+
+    /// Unsignal the event once all previously recorded commands
+    /// have completed the given pipeline stages.
+    unsafe fn reset_event(&mut self, event: &B::Event, stages: pso::PipelineStage);
+
+    /// Wait for all the given events to be signaled before executing the `stages.end`
+    /// stages of the following commands. Only the `stages.start` stages of the commands
+    /// recorded before each `set_event` are synchronized with, which allows work
+    /// to overlap between the signal and the wait, unlike `pipeline_barrier`.
+    unsafe fn wait_events<'a, I, J>(
+        &mut self,
+        events: I,
+        stages: Range<pso::PipelineStage>,
+        barriers: J,
+    ) where
+        I: IntoIterator,
+        I::Item: Borrow<B::Event>,
+        J: IntoIterator,
+        J::Item: Borrow<Barrier<'a, B>>;
+
     /// Fill a buffer with the given `u32` value.
     unsafe fn fill_buffer<R>(&mut self, buffer: &B::Buffer, range: R, data: u32)
     where
@@ -176,6 +206,10 @@ pub trait RawCommandBuffer<B: Backend>: Any + Send + Sync {
 
     /// "Resolves" a multisampled image, converting it into a non-multisampled
     /// image. Takes an iterator of regions to apply the resolution to.
+    ///
+    /// `src` has to be multisampled and `dst` single-sampled, both with the same
+    /// color format. The source and destination regions need the same number of layers.
+    /// Some backends only resolve whole subresources, ignoring the offsets and extent.
     unsafe fn resolve_image<T>(
         &mut self,
         src: &B::Image,
@@ -189,6 +223,13 @@ pub trait RawCommandBuffer<B: Backend>: Any + Send + Sync {
 
     /// Copies regions from the source to destination image,
     /// applying scaling, filtering and potentially format conversion.
+    ///
+    /// The bounds of a region may be flipped to mirror the image. The source format has
+    /// to support `ImageFeature::BLIT_SRC` and the destination format `ImageFeature::BLIT_DST`.
+    /// Formats are converted like in a shader read and write, as long as they
+    /// are both floating point (including normalized), or both integer of the same
+    /// signedness. Depth and stencil formats have to match, and only support `Filter::Nearest`.
+    /// `Filter::Linear` additionally requires `ImageFeature::SAMPLED_LINEAR` for the source.
     unsafe fn blit_image<T>(
         &mut self,
         src: &B::Image,
@@ -377,6 +418,10 @@ pub trait RawCommandBuffer<B: Backend>: Any + Send + Sync {
 
     /// Works similarly to `dispatch()` but reads parameters from the given
     /// buffer during execution.
+    ///
+    /// The dispatch parameters are 3 `u32` values, the number of work groups in
+    /// each dimension.
+    /// Requires `Limits::max_draw_indirect_count` to be non-zero.
     unsafe fn dispatch_indirect(&mut self, buffer: &B::Buffer, offset: buffer::Offset);
 
     /// Adds a command to copy regions from the source to destination buffer.
@@ -427,6 +472,10 @@ pub trait RawCommandBuffer<B: Backend>: Any + Send + Sync {
     /// from the currently bound vertex buffers.  It performs instanced
     /// drawing, drawing `instances.len()`
     /// times with an `instanceIndex` starting with the start of the range.
+    ///
+    /// A non-zero start of `instances` is emulated on OpenGL without `ARB_base_instance`
+    /// by offsetting the per-instance vertex buffers, and is not supported on Metal devices
+    /// lacking base instance support, where the draw is skipped with an error.
     unsafe fn draw(&mut self, vertices: Range<VertexCount>, instances: Range<InstanceCount>);
 
     /// Performs indexed drawing, drawing the range of indices
@@ -435,6 +484,11 @@ pub trait RawCommandBuffer<B: Backend>: Any + Send + Sync {
     /// That is, the offset into the vertex buffer is `(current_index + base_vertex)`
     ///
     /// It also performs instanced drawing, identical to `draw()`.
+    ///
+    /// A non-zero `base_vertex` requires OpenGL 3.2 or GLES 3.2 and base instance support on
+    /// Metal, the draw is skipped with an error otherwise. 32-bit indices may only use the
+    /// full range of values with `Features::FULL_DRAW_INDEX_U32`, the maximum value being
+    /// reserved for primitive restart.
     unsafe fn draw_indexed(
         &mut self,
         indices: Range<IndexCount>,
@@ -451,6 +505,11 @@ pub trait RawCommandBuffer<B: Backend>: Any + Send + Sync {
     /// in order, the number of vertices to draw, the number of instances to draw,
     /// the index of the first vertex to draw, and the instance ID of the first
     /// instance to draw.
+    ///
+    /// `draw_count` must not exceed `Limits::max_draw_indirect_count`, and
+    /// must be at most 1 unless the `MULTI_DRAW_INDIRECT` feature is enabled.
+    /// The first instance must be 0 unless the `DRAW_INDIRECT_FIRST_INSTANCE`
+    /// feature is enabled.
     unsafe fn draw_indirect(
         &mut self,
         buffer: &B::Buffer,
@@ -466,6 +525,8 @@ pub trait RawCommandBuffer<B: Backend>: Any + Send + Sync {
     /// in order, the number of indices, the number of instances, the first index,
     /// the vertex offset, and the first instance.  All are `u32`'s except
     /// the vertex offset, which is an `i32`.
+    ///
+    /// The same restrictions as `draw_indirect()` apply to `draw_count`.
     unsafe fn draw_indexed_indirect(
         &mut self,
         buffer: &B::Buffer,
@@ -499,6 +560,30 @@ pub trait RawCommandBuffer<B: Backend>: Any + Send + Sync {
     /// Requests a timestamp to be written.
     unsafe fn write_timestamp(&mut self, stage: pso::PipelineStage, query: query::Query<B>);
 
+    /// Begin conditional rendering: the draw and dispatch commands recorded until
+    /// `end_conditional_rendering` are discarded if the 32-bit value at `offset` in `buffer`
+    /// is zero, or if it's non-zero when `inverted` is set. Occlusion query results can be
+    /// copied into the buffer with `copy_query_pool_results` to skip occluded draws.
+    ///
+    /// The value is read as a 64-bit integer on some backends, so `offset` must be a multiple
+    /// of 8 and the following 4 bytes must be zero. The buffer has to be created with
+    /// `buffer::Usage::CONDITIONAL_RENDERING` and be accessible with
+    /// `buffer::Access::CONDITIONAL_RENDERING_READ`.
+    /// Requires `Features::CONDITIONAL_RENDERING`, conditional rendering can't be nested.
+    unsafe fn begin_conditional_rendering(
+        &mut self,
+        _buffer: &B::Buffer,
+        _offset: buffer::Offset,
+        _inverted: bool,
+    ) {
+        panic!("Conditional rendering is not supported by the backend");
+    }
+
+    /// End conditional rendering.
+    unsafe fn end_conditional_rendering(&mut self) {
+        panic!("Conditional rendering is not supported by the backend");
+    }
+
     /// Modify constant data in a graphics pipeline.
     /// Push constants are intended to modify data in a pipeline more
     /// quickly than a updating the values inside a descriptor set.
@@ -521,10 +606,30 @@ pub trait RawCommandBuffer<B: Backend>: Any + Send + Sync {
     );
 
     /// Execute the given secondary command buffers.
+    ///
+    /// Inside of a render pass, the current subpass must have been started with
+    /// `SubpassContents::SecondaryBuffers`, and the secondary command buffers must
+    /// have been recorded with `RENDER_PASS_CONTINUE`, inheriting that subpass.
+    /// No other state is inherited from the primary command buffer: pipelines,
+    /// descriptor sets and dynamic state must be bound by the secondary ones,
+    /// and are undefined in the primary command buffer afterwards.
+    ///
+    /// The secondary command buffers must not be reset or destroyed before
+    /// the primary command buffer has completed execution.
     unsafe fn execute_commands<'a, T, I>(&mut self, cmd_buffers: I)
     where
         T: 'a + Borrow<B::CommandBuffer>,
         I: IntoIterator<Item = &'a T>;
+
+    /// Begin a labeled group of commands, shown by the debugging tools.
+    /// The color is packed as `0xRRGGBBAA` and may be ignored.
+    unsafe fn begin_debug_marker(&mut self, _name: &str, _color: u32) {}
+
+    /// End the group of commands started by the last `begin_debug_marker`.
+    unsafe fn end_debug_marker(&mut self) {}
+
+    /// Insert a single label into the command stream, shown by the debugging tools.
+    unsafe fn insert_debug_marker(&mut self, _name: &str, _color: u32) {}
 }
 
 /// A trait for types that can be converted into raw command buffer.
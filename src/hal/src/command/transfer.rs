@@ -73,6 +73,36 @@ impl<B: Backend, C: Supports<Transfer>, S: Shot, L: Level> CommandBuffer<B, C, S
         self.raw.pipeline_barrier(stages, dependencies, barriers)
     }
 
+    /// Release the ownership of the resources to another queue family, once the given
+    /// stages of the previous commands are done. The barriers should be created with
+    /// `Barrier::buffer_ownership_transfer` or `Barrier::image_ownership_transfer`.
+    pub unsafe fn release_ownership<'i, T>(&mut self, stages: PipelineStage, barriers: T)
+    where
+        T: IntoIterator,
+        T::Item: Borrow<Barrier<'i, B>>,
+    {
+        self.raw.pipeline_barrier(
+            stages..PipelineStage::BOTTOM_OF_PIPE,
+            Dependencies::empty(),
+            barriers,
+        )
+    }
+
+    /// Acquire the ownership of the resources released by another queue family, before
+    /// the given stages of the following commands. The barriers have to match the ones
+    /// passed to `release_ownership`.
+    pub unsafe fn acquire_ownership<'i, T>(&mut self, stages: PipelineStage, barriers: T)
+    where
+        T: IntoIterator,
+        T::Item: Borrow<Barrier<'i, B>>,
+    {
+        self.raw.pipeline_barrier(
+            PipelineStage::TOP_OF_PIPE..stages,
+            Dependencies::empty(),
+            barriers,
+        )
+    }
+
     /// Identical to the `RawCommandBuffer` method of the same name.
     pub unsafe fn fill_buffer<R>(&mut self, buffer: &B::Buffer, range: R, data: u32)
     where
@@ -20,7 +20,7 @@ use {buffer, format, image, mapping, pass, pso, query};
 use {Backend, MemoryTypeId};
 
 use error::HostExecutionError;
-use memory::Requirements;
+use memory::{Priority, Requirements, SparseFlags};
 use pool::{CommandPool, CommandPoolCreateFlags};
 use queue::{QueueFamilyId, QueueGroup};
 use range::RangeArg;
@@ -115,6 +115,23 @@ impl From<OutOfMemory> for BindError {
     }
 }
 
+/// Error creating a framebuffer.
+#[derive(Clone, Copy, Debug, Fail, PartialEq, Eq)]
+pub enum FramebufferError {
+    /// Out of either host or device memory.
+    #[fail(display = "{}", _0)]
+    OutOfMemory(OutOfMemory),
+    /// Requested more layers than `Limits::max_framebuffer_layers`.
+    #[fail(display = "Too many framebuffer layers")]
+    TooManyLayers,
+}
+
+impl From<OutOfMemory> for FramebufferError {
+    fn from(error: OutOfMemory) -> Self {
+        FramebufferError::OutOfMemory(error)
+    }
+}
+
 /// Specifies the waiting targets.
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
@@ -171,6 +188,17 @@ impl From<OutOfMemory> for ShaderError {
 /// are not enforced at the HAL level due to OpenGL constraint (to be revised). Users can still
 /// benefit from the backends that support synchronization of the `Device`.
 ///
+/// ## Device Loss
+///
+/// A device can be lost because of a driver reset (e.g. after a GPU timeout), a hardware
+/// failure or the removal of the physical device. The loss is reported by `DeviceLost` errors
+/// from submissions, fence and event waits, query results and presentation. From then on, the contents of the resources are undefined and the
+/// remaining commands may fail or have no effect, but the objects still need to be destroyed.
+///
+/// To recover, destroy all the objects created from the device and drop it along with its
+/// queues, then open a new device. If the adapter is gone as well, enumerate the adapters
+/// of the instance again.
+///
 pub trait Device<B: Backend>: Any + Send + Sync {
     /// Allocates a memory segment of a specified type.
     ///
@@ -348,12 +376,15 @@ pub trait Device<B: Backend>: Any + Send + Sync {
     unsafe fn destroy_compute_pipeline(&self, pipeline: B::ComputePipeline);
 
     /// Create a new framebuffer object
+    ///
+    /// `extent.depth` is the number of layers, which must not exceed
+    /// `Limits::max_framebuffer_layers`.
     unsafe fn create_framebuffer<I>(
         &self,
         pass: &B::RenderPass,
         attachments: I,
         extent: image::Extent,
-    ) -> Result<B::Framebuffer, OutOfMemory>
+    ) -> Result<B::Framebuffer, FramebufferError>
     where
         I: IntoIterator,
         I::Item: Borrow<B::ImageView>;
@@ -387,6 +418,23 @@ pub trait Device<B: Backend>: Any + Send + Sync {
         usage: buffer::Usage,
     ) -> Result<B::Buffer, buffer::CreationError>;
 
+    /// Create a new sparse buffer.
+    ///
+    /// The memory of the created buffer is bound with `bind_sparse` on a queue
+    /// supporting sparse binding, instead of `bind_buffer_memory`. The requirements
+    /// `alignment` is the granularity of the sparse ranges.
+    ///
+    /// `sparse` has to contain `SparseFlags::SPARSE_BINDING`, and the matching features
+    /// have to be enabled on the device.
+    unsafe fn create_sparse_buffer(
+        &self,
+        _size: u64,
+        usage: buffer::Usage,
+        _sparse: SparseFlags,
+    ) -> Result<B::Buffer, buffer::CreationError> {
+        Err(buffer::CreationError::UnsupportedUsage { usage })
+    }
+
     /// Get memory requirements for the buffer
     unsafe fn get_buffer_requirements(&self, buf: &B::Buffer) -> Requirements;
 
@@ -394,6 +442,15 @@ pub trait Device<B: Backend>: Any + Send + Sync {
     ///
     /// Be sure to check that there is enough memory available for the buffer.
     /// Use `get_buffer_requirements` to acquire the memory requirements.
+    ///
+    /// The memory type has to be allowed by the requirements `type_mask`, and the
+    /// `offset` has to be a multiple of their `alignment`. A single memory object
+    /// can back multiple resources at different offsets, which is the basis for
+    /// sub-allocation. Resources may also alias the same memory range, in which
+    /// case only one of them holds meaningful contents at a time, and the contents
+    /// of the other ones are undefined until fully written.
+    ///
+    /// A buffer can only be bound once, and can't be used before being bound.
     unsafe fn bind_buffer_memory(
         &self,
         memory: &B::Memory,
@@ -401,6 +458,22 @@ pub trait Device<B: Backend>: Any + Send + Sync {
         buf: &mut B::Buffer,
     ) -> Result<(), BindError>;
 
+    /// Hint the system about the importance of keeping the buffer contents in memory.
+    ///
+    /// Returns `false` if the contents have been discarded while the buffer
+    /// was marked as `Discardable`, in which case they need to be filled again.
+    /// The hint is ignored by the backends that can't reclaim memory.
+    unsafe fn set_buffer_priority(&self, _buf: &B::Buffer, _priority: Priority) -> bool {
+        true
+    }
+
+    /// Give the buffer a name, shown by the debugging tools and in validation messages.
+    ///
+    /// Some backends only create the native object when binding memory,
+    /// so the name should be set after `bind_buffer_memory`.
+    /// The name is ignored by the backends without a native naming facility.
+    unsafe fn set_buffer_name(&self, _buf: &mut B::Buffer, _name: &str) {}
+
     /// Destroy a buffer.
     ///
     /// The buffer shouldn't be destroyed before any submitted command buffer,
@@ -429,6 +502,33 @@ pub trait Device<B: Backend>: Any + Send + Sync {
         view_caps: image::ViewCapabilities,
     ) -> Result<B::Image, image::CreationError>;
 
+    /// Create a new sparse image with the optimal tiling.
+    ///
+    /// The memory of the created image is bound with `bind_sparse` on a queue
+    /// supporting sparse binding, instead of `bind_image_memory`.
+    ///
+    /// `sparse` has to contain `SparseFlags::SPARSE_BINDING`, and the matching features
+    /// have to be enabled on the device.
+    unsafe fn create_sparse_image(
+        &self,
+        _kind: image::Kind,
+        _mip_levels: image::Level,
+        _format: format::Format,
+        usage: image::Usage,
+        _view_caps: image::ViewCapabilities,
+        _sparse: SparseFlags,
+    ) -> Result<B::Image, image::CreationError> {
+        Err(image::CreationError::Usage(usage))
+    }
+
+    /// Get the tile layout of a sparse image created with `SparseFlags::SPARSE_RESIDENCY`.
+    unsafe fn get_image_sparse_requirements(
+        &self,
+        _image: &B::Image,
+    ) -> Vec<image::SparseRequirements> {
+        Vec::new()
+    }
+
     /// Get memory requirements for the Image
     unsafe fn get_image_requirements(&self, image: &B::Image) -> Requirements;
 
@@ -439,7 +539,10 @@ pub trait Device<B: Backend>: Any + Send + Sync {
         subresource: image::Subresource,
     ) -> image::SubresourceFootprint;
 
-    /// Bind device memory to an image object
+    /// Bind device memory to an image object.
+    ///
+    /// The same rules as for `bind_buffer_memory` apply, with the requirements
+    /// acquired by `get_image_requirements`.
     unsafe fn bind_image_memory(
         &self,
         memory: &B::Memory,
@@ -447,6 +550,22 @@ pub trait Device<B: Backend>: Any + Send + Sync {
         image: &mut B::Image,
     ) -> Result<(), BindError>;
 
+    /// Hint the system about the importance of keeping the image contents in memory.
+    ///
+    /// Returns `false` if the contents have been discarded while the image
+    /// was marked as `Discardable`, in which case they need to be filled again.
+    /// The hint is ignored by the backends that can't reclaim memory.
+    unsafe fn set_image_priority(&self, _image: &B::Image, _priority: Priority) -> bool {
+        true
+    }
+
+    /// Give the image a name, shown by the debugging tools and in validation messages.
+    ///
+    /// Some backends only create the native object when binding memory,
+    /// so the name should be set after `bind_image_memory`.
+    /// The name is ignored by the backends without a native naming facility.
+    unsafe fn set_image_name(&self, _image: &mut B::Image, _name: &str) {}
+
     /// Destroy an image.
     ///
     /// The image shouldn't be destroyed before any submitted command buffer,
@@ -454,6 +573,12 @@ pub trait Device<B: Backend>: Any + Send + Sync {
     unsafe fn destroy_image(&self, image: B::Image);
 
     /// Create an image view from an existing image
+    ///
+    /// The view format has to match the image format, unless the image was created with
+    /// `ViewCapabilities::MUTABLE_FORMAT`, in which case any format with the same texel
+    /// layout can be used, like the sRGB and linear variants of a format. The swizzle
+    /// remaps the components read through the view; it may be unsupported by
+    /// some backends, which then return `ViewError::Unsupported`.
     unsafe fn create_image_view(
         &self,
         image: &B::Image,
@@ -501,6 +626,8 @@ pub trait Device<B: Backend>: Any + Send + Sync {
     /// Each individual descriptor binding is specified by a descriptor type, a count (array size)
     /// of the number of descriptors in the binding, a set of shader stages that **can** access the
     /// binding, and (if using immutable samplers) an array of sampler descriptors.
+    ///
+    /// The immutable samplers have to outlive the layout and all the sets allocated with it.
     unsafe fn create_descriptor_set_layout<I, J>(
         &self,
         bindings: I,
@@ -635,12 +762,16 @@ pub trait Device<B: Backend>: Any + Send + Sync {
     /// *wait_for_fences* command, and the current state **can** be queried with *get_fence_status*.
     fn create_fence(&self, signaled: bool) -> Result<B::Fence, OutOfMemory>;
 
+    /// Reset the given fence to the unsignaled state.
     ///
+    /// The fence must not be used by a pending queue submission.
     unsafe fn reset_fence(&self, fence: &B::Fence) -> Result<(), OutOfMemory> {
         self.reset_fences(iter::once(fence))
     }
 
+    /// Reset the given fences to the unsignaled state.
     ///
+    /// The fences must not be used by pending queue submissions.
     unsafe fn reset_fences<I>(&self, fences: I) -> Result<(), OutOfMemory>
     where
         I: IntoIterator,
@@ -654,6 +785,9 @@ pub trait Device<B: Backend>: Any + Send + Sync {
 
     /// Blocks until the given fence is signaled.
     /// Returns true if the fence was signaled before the timeout.
+    ///
+    /// A timeout of `0` only polls the fence status, while `!0` waits indefinitely.
+    /// Backends may wait longer than the timeout, but never shorter.
     unsafe fn wait_for_fence(
         &self,
         fence: &B::Fence,
@@ -664,6 +798,8 @@ pub trait Device<B: Backend>: Any + Send + Sync {
 
     /// Blocks until all or one of the given fences are signaled.
     /// Returns true if fences were signaled before the timeout.
+    ///
+    /// The timeout follows the same rules as for `wait_for_fence`.
     unsafe fn wait_for_fences<I>(
         &self,
         fences: I,
@@ -712,12 +848,37 @@ pub trait Device<B: Backend>: Any + Send + Sync {
         }
     }
 
-    /// true for signaled, false for not ready
+    /// Query the fence status without blocking.
+    /// Returns true for signaled, false for not ready.
     unsafe fn get_fence_status(&self, fence: &B::Fence) -> Result<bool, DeviceLost>;
 
     /// Destroy a fence object
     unsafe fn destroy_fence(&self, fence: B::Fence);
 
+    /// Create a new event object in the unsignaled state.
+    ///
+    /// Events are a synchronization primitive that **can** be used to insert a fine-grained
+    /// dependency between commands submitted to the same queue, or between the host and a queue.
+    /// Events **can** be signaled and unsignaled by command buffers with *set_event* and
+    /// *reset_event*, or on the host with the methods of the same name, and **can** be waited on
+    /// by command buffers with *wait_events*.
+    fn create_event(&self) -> Result<B::Event, OutOfMemory>;
+
+    /// Query the event status without blocking.
+    /// Returns true for signaled, false for unsignaled.
+    unsafe fn get_event_status(&self, event: &B::Event) -> Result<bool, OomOrDeviceLost>;
+
+    /// Set the event to the signaled state on the host.
+    unsafe fn set_event(&self, event: &B::Event) -> Result<(), OutOfMemory>;
+
+    /// Reset the event to the unsignaled state on the host.
+    ///
+    /// The event must not be waited on by a pending queue submission.
+    unsafe fn reset_event(&self, event: &B::Event) -> Result<(), OutOfMemory>;
+
+    /// Destroy an event object
+    unsafe fn destroy_event(&self, event: B::Event);
+
     /// Create a new query pool object
     ///
     /// Queries are managed using query pool objects. Each query pool is a collection of a specific
@@ -742,6 +903,43 @@ pub trait Device<B: Backend>: Any + Send + Sync {
         flags: query::ResultFlags,
     ) -> Result<bool, OomOrDeviceLost>;
 
+    /// Get query pool results as 64-bit values.
+    ///
+    /// `results` is split evenly between the queries, each one receiving its
+    /// values (see `query::Type::result_count`) followed by the availability
+    /// value if `WITH_AVAILABILITY` is set.
+    /// Returns `Ok(false)` if the results are not ready yet and neither of `WAIT` or `PARTIAL` flags are set.
+    unsafe fn get_query_pool_results_u64(
+        &self,
+        pool: &B::QueryPool,
+        queries: Range<query::Id>,
+        results: &mut [u64],
+        flags: query::ResultFlags,
+    ) -> Result<bool, OomOrDeviceLost> {
+        let count = (queries.end - queries.start) as usize;
+        if count == 0 {
+            return Ok(true);
+        }
+        assert_eq!(results.len() % count, 0);
+        let stride = (results.len() / count * mem::size_of::<u64>()) as buffer::Offset;
+        let data = slice::from_raw_parts_mut(
+            results.as_mut_ptr() as *mut u8,
+            results.len() * mem::size_of::<u64>(),
+        );
+        self.get_query_pool_results(
+            pool,
+            queries,
+            data,
+            stride,
+            flags | query::ResultFlags::BITS_64,
+        )
+    }
+
+    /// Sample the GPU timestamp counter along with the CPU time, in order to place
+    /// the results of timestamp queries on the CPU timeline.
+    /// Returns `None` if the backend can't sample the counter.
+    unsafe fn calibrate_timestamps(&self) -> Option<query::TimestampCalibration>;
+
     /// Create a new swapchain from a surface and a queue family, optionally providing the old
     /// swapchain to aid in resource reuse and rendering continuity.
     ///
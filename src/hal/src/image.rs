@@ -363,7 +363,7 @@ bitflags!(
     /// Capabilities to create views into an image.
     #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
     pub struct ViewCapabilities: u32 {
-        /// Support creation of views with different formats.
+        /// Support creation of views with different formats of the same texel layout.
         const MUTABLE_FORMAT = 0x00000008;
         /// Support creation of `Cube` and `CubeArray` kinds of views.
         const KIND_CUBE      = 0x00000010;
@@ -604,6 +604,25 @@ pub struct Subresource {
     pub layer: Layer,
 }
 
+/// Sparse memory requirements of a set of image aspects.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub struct SparseRequirements {
+    /// Aspects covered by these requirements.
+    pub aspects: format::Aspects,
+    /// Size of a tile in texels. Image regions are bound in multiples of it.
+    pub granularity: Extent,
+    /// First level of the mip tail, which is too small to be tiled
+    /// and is bound as opaque memory instead.
+    pub mip_tail_first_level: Level,
+    /// Size of the mip tail in bytes.
+    pub mip_tail_size: u64,
+    /// Offset of the mip tail within the opaque memory of the image.
+    pub mip_tail_offset: u64,
+    /// Offset between the mip tails of consecutive layers,
+    /// or `None` if a single mip tail is shared by all the layers.
+    pub mip_tail_stride: Option<u64>,
+}
+
 /// A subset of resource layers contained within an image's level.
 #[derive(Clone, Debug, Hash, PartialEq, Eq)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
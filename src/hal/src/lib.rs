@@ -33,9 +33,10 @@ pub use self::queue::{
     Capability, CommandQueue, Compute, General, Graphics, QueueFamily, QueueGroup, QueueType,
     Submission, Supports, Transfer,
 };
+pub use self::upload::Uploader;
 pub use self::window::{
-    AcquireError, Backbuffer, CompositeAlpha, FrameSync, PresentMode, Surface, SurfaceCapabilities,
-    SwapImageIndex, Swapchain, SwapchainConfig,
+    AcquireError, Backbuffer, CompositeAlpha, FrameSync, PresentError, PresentMode, Surface,
+    SurfaceCapabilities, SwapImageIndex, Swapchain, SwapchainConfig,
 };
 
 pub mod adapter;
@@ -53,6 +54,7 @@ pub mod pso;
 pub mod query;
 pub mod queue;
 pub mod range;
+pub mod upload;
 pub mod window;
 
 #[doc(hidden)]
@@ -105,6 +107,8 @@ bitflags! {
         /// Support cube array image views.
         const IMAGE_CUBE_ARRAY = 0x000_0000_0000_0004;
         /// Support different color blending settings per attachments on graphics pipeline creation.
+        /// If not enabled, all the entries of `BlendDesc::targets` **must** be equal, otherwise
+        /// pipeline creation fails with `pso::CreationError::MissingFeature`.
         const INDEPENDENT_BLENDING = 0x000_0000_0000_0008;
         /// Support geometry shader.
         const GEOMETRY_SHADER = 0x000_0000_0000_0010;
@@ -191,28 +195,31 @@ bitflags! {
         const SHADER_RESOURCE_RESIDENCY = 0x000_0400_0000_0000;
         ///
         const SHADER_RESOURCE_MIN_LOD = 0x000_0800_0000_0000;
-        ///
+        /// Support sparse buffers and images, created with `SparseFlags::SPARSE_BINDING`.
+        /// This is the base tier of the sparse resources, fully bound in opaque ranges.
         const SPARSE_BINDING = 0x000_1000_0000_0000;
-        ///
+        /// Support partially bound sparse buffers.
         const SPARSE_RESIDENCY_BUFFER = 0x000_2000_0000_0000;
-        ///
+        /// Support partially bound single-sampled 2D sparse images, bound in tiles.
         const SPARSE_RESIDENCY_IMAGE_2D = 0x000_4000_0000_0000;
-        ///
+        /// Support partially bound 3D sparse images, bound in tiles.
         const SPARSE_RESIDENCY_IMAGE_3D = 0x000_8000_0000_0000;
-        ///
+        /// Support partially bound 2D sparse images with 2 samples.
         const SPARSE_RESIDENCY_2_SAMPLES = 0x001_0000_0000_0000;
-        ///
+        /// Support partially bound 2D sparse images with 4 samples.
         const SPARSE_RESIDENCY_4_SAMPLES = 0x002_0000_0000_0000;
-        ///
+        /// Support partially bound 2D sparse images with 8 samples.
         const SPARSE_RESIDENCY_8_SAMPLES = 0x004_0000_0000_0000;
-        ///
+        /// Support partially bound 2D sparse images with 16 samples.
         const SPARSE_RESIDENCY_16_SAMPLES = 0x008_0000_0000_0000;
-        ///
+        /// Support sparse resources aliasing the same memory, created with `SparseFlags::SPARSE_ALIASED`.
         const SPARSE_RESIDENCY_ALIASED = 0x010_0000_0000_0000;
         ///
         const VARIABLE_MULTISAMPLE_RATE = 0x020_0000_0000_0000;
         ///
         const INHERITED_QUERIES = 0x040_0000_0000_0000;
+        /// Support conditional rendering with `begin_conditional_rendering`.
+        const CONDITIONAL_RENDERING = 0x080_0000_0000_0000;
 
         /// Support triangle fan primitive topology.
         const TRIANGLE_FAN = 0x1000_0000_0000_0000;
@@ -225,6 +232,31 @@ bitflags! {
     }
 }
 
+bitflags! {
+    /// Subgroup operations that shaders can perform, matching the SPIR-V
+    /// `GroupNonUniform*` capabilities.
+    #[derive(Default)]
+    #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+    pub struct SubgroupOperations: u32 {
+        /// Elect and barriers within the subgroup.
+        const BASIC = 0x1;
+        /// All, any and all-equal votes.
+        const VOTE = 0x2;
+        /// Reductions and scans.
+        const ARITHMETIC = 0x4;
+        /// Ballots and broadcasts.
+        const BALLOT = 0x8;
+        /// Reading from arbitrary invocations.
+        const SHUFFLE = 0x10;
+        /// Reading from invocations at a relative index.
+        const SHUFFLE_RELATIVE = 0x20;
+        /// Arithmetic operations on clusters of invocations.
+        const CLUSTERED = 0x40;
+        /// Operations within quads of invocations.
+        const QUAD = 0x80;
+    }
+}
+
 /// Resource limits of a particular graphics device.
 #[derive(Clone, Copy, Debug, Default, PartialEq)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
@@ -241,6 +273,8 @@ pub struct Limits {
     pub max_compute_group_count: WorkGroupCount,
     ///
     pub max_compute_group_size: [u32; 3],
+    /// Maximum total size of the workgroup-shared variables of a compute shader, in bytes.
+    pub max_compute_shared_memory_size: usize,
 
     /// Maximum number of vertex input attributes that can be specified for a graphics pipeline.
     pub max_vertex_input_attributes: usize,
@@ -272,13 +306,37 @@ pub struct Limits {
     pub framebuffer_stencil_samples_count: image::NumSamples,
     /// Maximum number of color attachments that can be used by a subpass in a render pass.
     pub max_color_attachments: usize,
+    /// Maximum number of layers of a framebuffer, one if layered rendering is not supported.
+    pub max_framebuffer_layers: usize,
+    /// Maximum number of descriptor sets that can be used by a pipeline layout.
+    pub max_bound_descriptor_sets: usize,
+    /// Maximum number of samplers accessible to a single shader stage.
+    pub max_per_stage_descriptor_samplers: usize,
+    /// Maximum number of uniform buffers accessible to a single shader stage.
+    pub max_per_stage_descriptor_uniform_buffers: usize,
+    /// Maximum number of storage buffers accessible to a single shader stage.
+    pub max_per_stage_descriptor_storage_buffers: usize,
+    /// Maximum number of sampled images accessible to a single shader stage.
+    pub max_per_stage_descriptor_sampled_images: usize,
+    /// Maximum number of storage images accessible to a single shader stage.
+    pub max_per_stage_descriptor_storage_images: usize,
     /// Size and alignment in bytes that bounds concurrent access to host-mapped device memory.
     pub non_coherent_atom_size: usize,
     /// Maximum degree of sampler anisotropy.
     pub max_sampler_anisotropy: f32,
+    /// Maximum number of draws of a single indirect draw call.
+    /// Zero if indirect drawing and dispatching is not supported.
+    pub max_draw_indirect_count: DrawCount,
 
     /// The alignment of the vertex buffer stride.
     pub min_vertex_input_binding_stride_alignment: buffer::Offset,
+
+    /// Number of invocations in a subgroup, zero if subgroups are not supported.
+    pub subgroup_size: u32,
+    /// Operations supported on subgroups.
+    pub subgroup_operations: SubgroupOperations,
+    /// Shader stages supporting subgroup operations.
+    pub subgroup_stages: pso::ShaderStageFlags,
 }
 
 /// Describes the type of geometric primitives,
@@ -406,6 +464,7 @@ pub trait Backend: 'static + Sized + Eq + Clone + Hash + fmt::Debug + Any + Send
 
     type Fence: fmt::Debug + Any + Send + Sync;
     type Semaphore: fmt::Debug + Any + Send + Sync;
+    type Event: fmt::Debug + Any + Send + Sync;
     type QueryPool: fmt::Debug + Any + Send + Sync;
 }
 
@@ -1,8 +1,10 @@
 #![deny(missing_docs, missing_copy_implementations)]
 
 //! Memory mapping
-use device;
+use device::{self, Device, DeviceLost};
+use std::collections::VecDeque;
 use std::ops::{self, Range};
+use std::{mem, slice};
 use Backend;
 
 // TODO
@@ -77,3 +79,156 @@ impl<'a, B: Backend, T: 'a> ops::DerefMut for Writer<'a, B, T> {
         self.slice
     }
 }
+
+/// A ring of persistently mapped memory, for writing dynamic data every frame.
+///
+/// Allocations are placed one after the other, wrapping around to the start of the
+/// range once its end is reached. Their space is reclaimed once the fence given to
+/// `end_frame` after them is signaled, so it has to be signaled by the submission
+/// reading them. Writes to non-coherent memory must be flushed before the submission.
+pub struct Ring<'a, B: Backend, T: 'a> {
+    memory: &'a B::Memory,
+    offset: u64,
+    ptr: *mut T,
+    capacity: usize,
+    // Start of the free elements.
+    head: usize,
+    // Start of the elements still in use, the ring is empty if equal to `head`.
+    tail: usize,
+    // Fences of the ended frames, along with the end of their allocations.
+    frames: VecDeque<(&'a B::Fence, usize)>,
+}
+
+impl<'a, B: Backend, T: 'a + Copy> Ring<'a, B, T> {
+    /// Map the given range of host visible memory as a ring.
+    pub unsafe fn new(
+        device: &B::Device,
+        memory: &'a B::Memory,
+        range: Range<u64>,
+    ) -> Result<Self, Error> {
+        let ptr = device.map_memory(memory, range.clone())? as *mut T;
+        assert_eq!(ptr as usize % mem::align_of::<T>(), 0);
+        Ok(Ring {
+            memory,
+            offset: range.start,
+            ptr,
+            capacity: (range.end - range.start) as usize / mem::size_of::<T>(),
+            head: 0,
+            tail: 0,
+            frames: VecDeque::new(),
+        })
+    }
+
+    /// Allocate `count` contiguous elements, returning them along with their offset in bytes
+    /// within the memory. Returns `None` if the space isn't available until the device
+    /// is done with the pending frames.
+    pub fn allocate(
+        &mut self,
+        device: &B::Device,
+        count: usize,
+    ) -> Result<Option<(&mut [T], u64)>, DeviceLost> {
+        loop {
+            if let Some(start) = find_space(self.capacity, self.head, self.tail, count, 1) {
+                self.head = start + count;
+                let elements = unsafe { slice::from_raw_parts_mut(self.ptr.add(start), count) };
+                let offset = self.offset + (start * mem::size_of::<T>()) as u64;
+                return Ok(Some((elements, offset)));
+            }
+
+            // Reclaim the space of the oldest frame, if the device is done with it.
+            match self.frames.front() {
+                Some(&(fence, end)) if unsafe { device.get_fence_status(fence)? } => {
+                    self.tail = end;
+                }
+                _ => return Ok(None),
+            }
+            self.frames.pop_front();
+            if self.head == self.tail {
+                self.head = 0;
+                self.tail = 0;
+            }
+        }
+    }
+
+    /// Mark the end of the allocations of a frame, which are freed once `fence` is signaled.
+    pub fn end_frame(&mut self, fence: &'a B::Fence) {
+        self.frames.push_back((fence, self.head));
+    }
+
+    /// Unmap the memory of the ring.
+    pub unsafe fn unmap(self, device: &B::Device) {
+        device.unmap_memory(self.memory);
+    }
+}
+
+/// Start of `count` free elements of a ring, between its `head` and `tail`,
+/// aligned to `alignment`.
+///
+/// The head never catches up with the tail, so that a full ring can't be
+/// mistaken for an empty one.
+pub(crate) fn find_space<T>(capacity: T, head: T, tail: T, count: T, alignment: T) -> Option<T>
+where
+    T: Copy + Default + Ord + ops::Add<Output = T> + ops::Sub<Output = T> + ops::Rem<Output = T>,
+{
+    let zero = T::default();
+    let start = match head % alignment {
+        rem if rem == zero => head,
+        rem => head + (alignment - rem),
+    };
+    if head >= tail {
+        if start <= capacity && capacity - start >= count {
+            Some(start)
+        } else if tail > count {
+            Some(zero)
+        } else {
+            None
+        }
+    } else if start < tail && tail - start > count {
+        Some(start)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::find_space;
+
+    #[test]
+    fn test_find_space_empty() {
+        assert_eq!(find_space(16, 0, 0, 16, 1), Some(0));
+        assert_eq!(find_space(16, 0, 0, 17, 1), None);
+    }
+
+    #[test]
+    fn test_find_space_exact_fit() {
+        // Up to the end of the ring.
+        assert_eq!(find_space(16, 10, 4, 6, 1), Some(10));
+        assert_eq!(find_space(16, 10, 4, 7, 1), None);
+        // Up to one element before the tail.
+        assert_eq!(find_space(16, 2, 8, 5, 1), Some(2));
+        assert_eq!(find_space(16, 2, 8, 6, 1), None);
+    }
+
+    #[test]
+    fn test_find_space_wrap() {
+        assert_eq!(find_space(16, 12, 8, 7, 1), Some(0));
+        assert_eq!(find_space(16, 12, 8, 8, 1), None);
+    }
+
+    #[test]
+    fn test_find_space_full() {
+        assert_eq!(find_space(16, 16, 0, 1, 1), None);
+        assert_eq!(find_space(16, 7, 8, 1, 1), None);
+    }
+
+    #[test]
+    fn test_find_space_alignment() {
+        assert_eq!(find_space(16, 3, 0, 4, 4), Some(4));
+        assert_eq!(find_space(16, 13, 8, 4, 4), Some(0));
+        assert_eq!(find_space(16, 13, 8, 8, 4), None);
+        // Aligning the head would reach the tail.
+        assert_eq!(find_space(16, 1, 4, 1, 4), None);
+        assert_eq!(find_space(16, 1, 6, 1, 4), Some(4));
+    }
+}
@@ -119,6 +119,52 @@ impl<'a, B: Backend> Barrier<'a, B> {
             range: None..None,
         }
     }
+
+    /// Create a barrier transferring the ownership of the whole buffer between queue families.
+    ///
+    /// The same barrier has to be recorded with `release_ownership` in a command buffer
+    /// submitted to the source family, and with `acquire_ownership` in a command buffer
+    /// submitted to the destination family after the release has been executed.
+    /// The release ignores the destination state and the acquire ignores the source state.
+    pub fn buffer_ownership_transfer(
+        target: &'a B::Buffer,
+        states: Range<buffer::State>,
+        families: Range<queue::QueueFamilyId>,
+    ) -> Self {
+        Barrier::Buffer {
+            states,
+            target,
+            families: Some(families),
+            range: None..None,
+        }
+    }
+
+    /// Create a barrier transferring the ownership of an image subresource range
+    /// between queue families, see `buffer_ownership_transfer`.
+    pub fn image_ownership_transfer(
+        target: &'a B::Image,
+        states: Range<image::State>,
+        families: Range<queue::QueueFamilyId>,
+        range: image::SubresourceRange,
+    ) -> Self {
+        Barrier::Image {
+            states,
+            target,
+            families: Some(families),
+            range,
+        }
+    }
+}
+
+/// Hint about the importance of keeping the contents of a resource in memory.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Priority {
+    /// The contents have to be preserved.
+    Normal,
+    /// The contents are not needed at the moment, and the system is allowed
+    /// to discard them when running low on memory.
+    Discardable,
 }
 
 /// Memory requirements for a certain resource (buffer/image).
@@ -126,8 +172,56 @@ impl<'a, B: Backend> Barrier<'a, B> {
 pub struct Requirements {
     /// Size in the memory.
     pub size: u64,
-    /// Memory alignment.
+    /// Memory alignment, the binding offset has to be a multiple of it.
     pub alignment: u64,
-    /// Supported memory types.
+    /// Supported memory types, where bit `i` is set if the resource can be
+    /// bound to the memory of the type `MemoryTypeId(i)`.
     pub type_mask: u64,
 }
+
+bitflags!(
+    /// Sparse memory capabilities of a buffer or an image.
+    #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+    pub struct SparseFlags: u32 {
+        /// Memory is bound with `bind_sparse` on a queue instead of `bind_*_memory`,
+        /// and can be rebound while the resource is in use.
+        ///
+        /// Requires `Features::SPARSE_BINDING`.
+        const SPARSE_BINDING = 0x1;
+        /// The resource may be partially bound, leaving some ranges or tiles without memory.
+        ///
+        /// Requires `Features::SPARSE_RESIDENCY_BUFFER` for buffers, or the
+        /// `Features::SPARSE_RESIDENCY_IMAGE_*` matching the kind of the image.
+        const SPARSE_RESIDENCY = 0x2;
+        /// The bound memory may alias other resources, or other parts of the same resource.
+        ///
+        /// Requires `Features::SPARSE_RESIDENCY_ALIASED`.
+        const SPARSE_ALIASED = 0x4;
+    }
+);
+
+/// Binding of memory to a range of a sparse buffer, or to the opaque
+/// memory of a sparse image (e.g. its mip tail).
+#[derive(Debug)]
+pub struct SparseBind<'a, B: Backend> {
+    /// Offset into the resource in bytes, a multiple of the requirements alignment.
+    pub resource_offset: u64,
+    /// Size of the range in bytes, a multiple of the requirements alignment.
+    pub size: u64,
+    /// Memory with the offset into it, or `None` to unbind the range.
+    pub memory: Option<(&'a B::Memory, u64)>,
+}
+
+/// Binding of memory to a region of a sparse image, in texels.
+#[derive(Debug)]
+pub struct SparseImageBind<'a, B: Backend> {
+    /// The subresource containing the region.
+    pub subresource: image::Subresource,
+    /// Offset of the region, a multiple of the sparse granularity.
+    pub offset: image::Offset,
+    /// Extent of the region, a multiple of the sparse granularity
+    /// unless it reaches the edge of the subresource.
+    pub extent: image::Extent,
+    /// Memory with the offset into it, or `None` to unbind the region.
+    pub memory: Option<(&'a B::Memory, u64)>,
+}
@@ -93,6 +93,10 @@ pub struct DescriptorSetLayoutBinding {
     /// Valid shader stages.
     pub stage_flags: ShaderStageFlags,
     /// Use the associated list of immutable samplers.
+    ///
+    /// Takes `count` samplers from the list passed to `Device::create_descriptor_set_layout`,
+    /// in binding order. Only valid for `Sampler` and `CombinedImageSampler` bindings,
+    /// the samplers are bound without having to be written into the descriptor sets.
     pub immutable_samplers: bool,
 }
 
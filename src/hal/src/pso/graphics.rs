@@ -80,12 +80,16 @@ pub struct GraphicsShaderSet<'a, B: Backend> {
 }
 
 /// Baked-in pipeline states.
+///
+/// States left as `None` are dynamic: they have to be set on the command buffer
+/// with `set_viewports`, `set_scissors`, `set_blend_constants` and `set_depth_bounds`
+/// respectively before drawing, and persist across pipeline changes.
 #[derive(Clone, Debug, Default, PartialEq)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct BakedStates {
-    /// Static viewport. TODO: multiple viewports
+    /// Static viewport, only allowed if the pipeline uses a single viewport.
     pub viewport: Option<Viewport>,
-    /// Static scissor. TODO: multiple scissors
+    /// Static scissor, only allowed if the pipeline uses a single viewport.
     pub scissor: Option<Rect>,
     /// Static blend constant color.
     pub blend_color: Option<ColorValue>,
@@ -116,6 +120,10 @@ pub struct GraphicsPipelineDesc<'a, B: Backend> {
     pub multisampling: Option<Multisampling>,
     /// Static pipeline states.
     pub baked_states: BakedStates,
+    /// Number of viewports and scissors used by the pipeline, selected by the
+    /// viewport index in the shaders. More than one requires `Features::MULTI_VIEWPORTS`,
+    /// and can't exceed `Limits::max_viewports`.
+    pub viewport_count: u32,
     /// Pipeline layout.
     pub layout: &'a B::PipelineLayout,
     /// Subpass in which the pipeline can be executed.
@@ -146,6 +154,7 @@ impl<'a, B: Backend> GraphicsPipelineDesc<'a, B> {
             depth_stencil: DepthStencilDesc::default(),
             multisampling: None,
             baked_states: BakedStates::default(),
+            viewport_count: 1,
             layout,
             subpass,
             flags: PipelineCreationFlags::empty(),
@@ -234,13 +243,18 @@ impl Rasterizer {
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct BlendDesc {
     /// The logic operation to apply to the blending equation, if any.
+    ///
+    /// Requires `Features::LOGIC_OP`. When set, blending is disabled for all
+    /// targets and the operation is applied to integer and normalized formats.
     pub logic_op: Option<LogicOp>,
     /// Which color targets to apply the blending operation to.
+    ///
+    /// The targets may only differ if `Features::INDEPENDENT_BLENDING` is enabled.
     pub targets: Vec<ColorBlendDesc>,
 }
 
 /// Logic operations used for specifying blend equations.
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[allow(missing_docs)]
 pub enum LogicOp {
@@ -262,17 +276,22 @@ pub enum LogicOp {
     Set = 15,
 }
 
-///
+/// Bitmask of samples that may be written by a pipeline, one bit per sample.
 pub type SampleMask = u64;
 
-///
+/// Multisampling state of a graphics pipeline.
 #[derive(Clone, Debug, PartialEq)]
 pub struct Multisampling {
-    ///
+    /// Number of samples used during rasterization, matching the attachments.
+    /// `PhysicalDevice::max_common_samples` picks one supported by all of them.
     pub rasterization_samples: image::NumSamples,
+    /// Enables per-sample shading when set, with the value giving the minimum
+    /// fraction of samples to shade independently, in the `[0, 1]` range.
     ///
+    /// Requires `Features::SAMPLE_RATE_SHADING`.
     pub sample_shading: Option<f32>,
-    ///
+    /// Coverage mask ANDed with the rasterized coverage of every fragment.
+    /// Bits beyond `rasterization_samples` are ignored.
     pub sample_mask: SampleMask,
     /// Toggles alpha-to-coverage multisampling, which can produce nicer edges
     /// when many partially-transparent polygons are overlapping.
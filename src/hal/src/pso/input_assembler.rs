@@ -78,6 +78,10 @@ pub struct AttributeDesc {
 /// allows a mark to be placed in an index buffer where it is
 /// is "broken" into multiple pieces of geometry.
 ///
+/// The restart index is the maximum value of the index type, and only applies to strip
+/// and fan primitives. Some backends (e.g. DX11 and Metal) always restart strips
+/// on this index, so it shouldn't be used as a regular index even when disabled.
+///
 /// See <https://www.khronos.org/opengl/wiki/Vertex_Rendering#Primitive_Restart>
 /// for more detail.
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, PartialOrd, Ord)]
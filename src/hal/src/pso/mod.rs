@@ -4,7 +4,7 @@
 
 use std::fmt;
 use std::ops::Range;
-use {device, pass};
+use {device, pass, Features};
 
 mod compute;
 mod descriptor;
@@ -32,6 +32,9 @@ pub enum CreationError {
     /// Shader compilation error.
     #[fail(display = "Shader compilation error: {}", _0)]
     Shader(device::ShaderError),
+    /// The pipeline requires features that were not enabled on device creation.
+    #[fail(display = "Missing features: {:?}", _0)]
+    MissingFeature(Features),
 
     /// Out of either host or device memory.
     #[fail(display = "{}", _0)]
@@ -82,11 +85,14 @@ bitflags!(
         /// Read/Write access from host.
         /// (Not a real pipeline stage)
         const HOST = 0x4000;
+        /// Conditional rendering predicate consumption.
+        const CONDITIONAL_RENDERING = 0x40000;
     }
 );
 
 bitflags!(
     /// Combination of different shader pipeline stages.
+    #[derive(Default)]
     #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
     pub struct ShaderStageFlags: u32 {
         /// Vertex shader stage.
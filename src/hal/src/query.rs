@@ -4,6 +4,7 @@
 //! operation as it is running.
 
 use device::OutOfMemory;
+use std::time::Instant;
 use Backend;
 
 /// A query identifier.
@@ -27,6 +28,17 @@ impl From<OutOfMemory> for CreationError {
     }
 }
 
+/// A GPU timestamp sampled at a known CPU time, see `Device::calibrate_timestamps`.
+#[derive(Clone, Copy, Debug)]
+pub struct TimestampCalibration {
+    /// CPU time at which the GPU timestamp was sampled.
+    pub cpu: Instant,
+    /// GPU timestamp, in the same ticks as the results of timestamp queries.
+    pub gpu: u64,
+    /// Number of nanoseconds per GPU tick.
+    pub period: f32,
+}
+
 /// A `Query` object has a particular identifier and saves its results to a given `QueryPool`.
 /// It is passed as a parameter to the command buffer's query methods.
 #[derive(Debug)]
@@ -78,6 +90,17 @@ pub enum Type {
     Timestamp,
 }
 
+impl Type {
+    /// Number of result values written for a single query of this type,
+    /// not including the availability value.
+    pub fn result_count(&self) -> usize {
+        match *self {
+            Type::PipelineStatistics(statistics) => statistics.bits().count_ones() as usize,
+            Type::Occlusion | Type::Timestamp => 1,
+        }
+    }
+}
+
 bitflags!(
     /// Pipeline statistic flags
     #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
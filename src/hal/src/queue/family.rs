@@ -28,6 +28,10 @@ pub trait QueueFamily: Debug + Any + Send + Sync {
     fn supports_transfer(&self) -> bool {
         Transfer::supported_by(self.queue_type())
     }
+    /// Returns true if the queue supports sparse memory binding with `bind_sparse`.
+    fn supports_sparse_binding(&self) -> bool {
+        false
+    }
     /// Returns the queue family ID.
     fn id(&self) -> QueueFamilyId;
 }
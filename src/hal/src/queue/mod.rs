@@ -15,9 +15,11 @@ use std::iter;
 use std::marker::PhantomData;
 
 use command::{Primary, Submittable};
+use device::DeviceLost;
 use error::HostExecutionError;
+use memory::{SparseBind, SparseImageBind};
 use pso;
-use window::SwapImageIndex;
+use window::{PresentError, SwapImageIndex};
 use Backend;
 
 pub use self::capability::{Capability, Compute, General, Graphics, Supports, Transfer};
@@ -38,15 +40,38 @@ pub enum QueueType {
 }
 
 /// Submission information for a command queue.
+///
+/// Semaphores are binary: a signal operation must be submitted before any wait on the
+/// same semaphore, and each signal is consumed by exactly one wait, which unsignals
+/// the semaphore again. Semaphores can be shared by queues of different families.
+///
+/// Backends exposing a single queue, which executes the submissions in order,
+/// satisfy the waits without synchronizing, since the signals are submitted first.
 pub struct Submission<Ic, Iw, Is> {
     /// Command buffers to submit.
     pub command_buffers: Ic,
     /// Semaphores to wait being signalled before submission.
+    /// Only the given pipeline stages of the command buffers are blocked by the wait,
+    /// although backends may block earlier stages too.
     pub wait_semaphores: Iw,
     /// Semaphores to signal after all command buffers in the submission have finished execution.
     pub signal_semaphores: Is,
 }
 
+/// Sparse memory bindings to update on a command queue.
+pub struct SparseBinding<'a, B: Backend, Iw, Is> {
+    /// Memory bindings of the sparse buffers.
+    pub buffers: &'a [(&'a B::Buffer, &'a [SparseBind<'a, B>])],
+    /// Opaque memory bindings of the sparse images, including the mip tails.
+    pub image_opaques: &'a [(&'a B::Image, &'a [SparseBind<'a, B>])],
+    /// Memory bindings of the sparse image regions, in tiles.
+    pub images: &'a [(&'a B::Image, &'a [SparseImageBind<'a, B>])],
+    /// Semaphores to wait being signalled before updating the bindings.
+    pub wait_semaphores: Iw,
+    /// Semaphores to signal after the bindings have been updated.
+    pub signal_semaphores: Is,
+}
+
 /// `RawCommandQueue` are abstractions to the internal GPU execution engines.
 /// Commands are executed on the the device by submitting command buffers to queues.
 pub trait RawCommandQueue<B: Backend>: Any + Send + Sync {
@@ -57,11 +82,15 @@ pub trait RawCommandQueue<B: Backend>: Any + Send + Sync {
     /// Unsafe because it's not checked that the queue can process the submitted command buffers.
     /// Trying to submit compute commands to a graphics queue will result in undefined behavior.
     /// Each queue implements safe wrappers according to their supported functionalities!
+    ///
+    /// Returns `DeviceLost` if the device is known to be lost, in which case the
+    /// submission may be dropped. The loss may also only be reported by the fence waits.
     unsafe fn submit<'a, T, Ic, S, Iw, Is>(
         &mut self,
         submission: Submission<Ic, Iw, Is>,
         fence: Option<&B::Fence>,
-    ) where
+    ) -> Result<(), DeviceLost>
+    where
         T: 'a + Borrow<B::CommandBuffer>,
         Ic: IntoIterator<Item = &'a T>,
         S: 'a + Borrow<B::Semaphore>,
@@ -77,7 +106,7 @@ pub trait RawCommandQueue<B: Backend>: Any + Send + Sync {
         &mut self,
         swapchains: Is,
         wait_semaphores: Iw,
-    ) -> Result<(), ()>
+    ) -> Result<(), PresentError>
     where
         Self: Sized,
         W: 'a + Borrow<B::Swapchain>,
@@ -85,6 +114,26 @@ pub trait RawCommandQueue<B: Backend>: Any + Send + Sync {
         S: 'a + Borrow<B::Semaphore>,
         Iw: IntoIterator<Item = &'a S>;
 
+    /// Update the memory bindings of sparse resources.
+    /// `fence` must be in unsignalled state, and will be signalled after all the bindings
+    /// have been updated.
+    ///
+    /// The queue has to support sparse binding, see `QueueFamily::supports_sparse_binding`.
+    /// The bindings are executed in order and may overlap, the last one wins.
+    /// Unsafe because it's not checked that the resources are not in use by the device
+    /// in the ranges being rebound.
+    unsafe fn bind_sparse<'a, S, Iw, Is>(
+        &mut self,
+        _binding: SparseBinding<'a, B, Iw, Is>,
+        _fence: Option<&B::Fence>,
+    ) where
+        S: 'a + Borrow<B::Semaphore>,
+        Iw: IntoIterator<Item = &'a S>,
+        Is: IntoIterator<Item = &'a S>,
+    {
+        panic!("Sparse binding is not supported by the backend");
+    }
+
     /// Wait for the queue to idle.
     fn wait_idle(&self) -> Result<(), HostExecutionError>;
 }
@@ -125,7 +174,8 @@ impl<B: Backend, C: Capability> CommandQueue<B, C> {
         &mut self,
         submission: Submission<Ic, Iw, Is>,
         fence: Option<&B::Fence>,
-    ) where
+    ) -> Result<(), DeviceLost>
+    where
         T: 'a + Submittable<B, C, Primary>,
         Ic: IntoIterator<Item = &'a T>,
         S: 'a + Borrow<B::Semaphore>,
@@ -140,7 +190,8 @@ impl<B: Backend, C: Capability> CommandQueue<B, C> {
         &mut self,
         command_buffers: I,
         fence: Option<&B::Fence>,
-    ) where
+    ) -> Result<(), DeviceLost>
+    where
         T: 'a + Submittable<B, C, Primary>,
         I: IntoIterator<Item = &'a T>,
     {
@@ -159,7 +210,7 @@ impl<B: Backend, C: Capability> CommandQueue<B, C> {
         &mut self,
         swapchains: Is,
         wait_semaphores: Iw,
-    ) -> Result<(), ()>
+    ) -> Result<(), PresentError>
     where
         W: 'a + Borrow<B::Swapchain>,
         Is: IntoIterator<Item = (&'a W, SwapImageIndex)>,
@@ -169,6 +220,23 @@ impl<B: Backend, C: Capability> CommandQueue<B, C> {
         self.0.present(swapchains, wait_semaphores)
     }
 
+    /// Update the memory bindings of sparse resources.
+    /// `fence` must be in unsignalled state, and will be signalled after all the bindings
+    /// have been updated.
+    ///
+    /// The queue has to be created from a family supporting sparse binding.
+    pub unsafe fn bind_sparse<'a, S, Iw, Is>(
+        &mut self,
+        binding: SparseBinding<'a, B, Iw, Is>,
+        fence: Option<&B::Fence>,
+    ) where
+        S: 'a + Borrow<B::Semaphore>,
+        Iw: IntoIterator<Item = &'a S>,
+        Is: IntoIterator<Item = &'a S>,
+    {
+        self.0.bind_sparse(binding, fence)
+    }
+
     /// Wait for the queue to idle.
     pub fn wait_idle(&self) -> Result<(), HostExecutionError> {
         self.0.wait_idle()
@@ -0,0 +1,487 @@
+//! Streaming uploads through a staging buffer.
+//!
+//! An `Uploader` owns a ring of host-visible staging memory. Buffer and image
+//! data is copied into it and the transfers are recorded into command buffers,
+//! which are submitted in batches with a fence signalled on completion.
+//! The staging memory of a batch is reused once its fence is signalled.
+//!
+//! The uploads can go through any queue supporting transfers. Backends without
+//! dedicated transfer queues, like DX11 and GL, use the graphics queue.
+
+use std::collections::VecDeque;
+use std::ops::Range;
+use std::{iter, ptr};
+
+use command::{BufferCopy, BufferImageCopy, CommandBuffer, OneShot};
+use device::{AllocationError, BindError, Device, DeviceLost, OomOrDeviceLost, OutOfMemory};
+use memory::{Barrier, Dependencies, Properties};
+use pool::CommandPool;
+use pso::PipelineStage;
+use queue::capability::{Capability, Supports, Transfer};
+use queue::CommandQueue;
+use {buffer, format, image, mapping, Backend, Limits, MemoryProperties};
+
+/// Error creating an `Uploader`.
+#[derive(Clone, Debug, Fail, PartialEq, Eq)]
+pub enum CreationError {
+    /// The staging buffer couldn't be created.
+    #[fail(display = "{}", _0)]
+    Buffer(buffer::CreationError),
+    /// The staging memory couldn't be allocated.
+    #[fail(display = "{}", _0)]
+    Allocation(AllocationError),
+    /// The staging buffer couldn't be bound to its memory.
+    #[fail(display = "{}", _0)]
+    Bind(BindError),
+    /// The staging memory couldn't be mapped.
+    #[fail(display = "{}", _0)]
+    Mapping(mapping::Error),
+    /// No host-visible memory type can hold the staging buffer.
+    #[fail(display = "No host-visible memory type for the staging buffer")]
+    NoMemoryType,
+}
+
+impl From<buffer::CreationError> for CreationError {
+    fn from(error: buffer::CreationError) -> Self {
+        CreationError::Buffer(error)
+    }
+}
+
+impl From<AllocationError> for CreationError {
+    fn from(error: AllocationError) -> Self {
+        CreationError::Allocation(error)
+    }
+}
+
+impl From<BindError> for CreationError {
+    fn from(error: BindError) -> Self {
+        CreationError::Bind(error)
+    }
+}
+
+impl From<mapping::Error> for CreationError {
+    fn from(error: mapping::Error) -> Self {
+        CreationError::Mapping(error)
+    }
+}
+
+/// Error uploading data.
+#[derive(Clone, Copy, Debug, Fail, PartialEq, Eq)]
+pub enum UploadError {
+    /// Out of either host or device memory.
+    #[fail(display = "{}", _0)]
+    OutOfMemory(OutOfMemory),
+    /// Device is lost.
+    #[fail(display = "{}", _0)]
+    DeviceLost(DeviceLost),
+    /// The data doesn't fit in the staging buffer.
+    #[fail(display = "Upload larger than the staging buffer")]
+    TooLarge,
+}
+
+impl From<OutOfMemory> for UploadError {
+    fn from(error: OutOfMemory) -> Self {
+        UploadError::OutOfMemory(error)
+    }
+}
+
+impl From<DeviceLost> for UploadError {
+    fn from(error: DeviceLost) -> Self {
+        UploadError::DeviceLost(error)
+    }
+}
+
+impl From<OomOrDeviceLost> for UploadError {
+    fn from(error: OomOrDeviceLost) -> Self {
+        match error {
+            OomOrDeviceLost::OutOfMemory(error) => UploadError::OutOfMemory(error),
+            OomOrDeviceLost::DeviceLost(error) => UploadError::DeviceLost(error),
+        }
+    }
+}
+
+/// Submitted batch of uploads, see `Uploader::flush`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct UploadTicket(u64);
+
+struct Recording<B: Backend, C> {
+    command_buffer: CommandBuffer<B, C, OneShot>,
+    /// Staging offset of the first upload.
+    start: u64,
+}
+
+struct Batch<B: Backend, C> {
+    id: u64,
+    command_buffer: CommandBuffer<B, C, OneShot>,
+    fence: B::Fence,
+    start: u64,
+}
+
+/// Batches buffer and image uploads through a ring of staging memory.
+pub struct Uploader<B: Backend, C> {
+    pool: CommandPool<B, C>,
+    staging: B::Buffer,
+    memory: B::Memory,
+    mapping: *mut u8,
+    capacity: u64,
+    coherent: bool,
+    offset_alignment: u64,
+    pitch_alignment: u64,
+    /// End of the last allocation in the ring.
+    head: u64,
+    recording: Option<Recording<B, C>>,
+    pending: VecDeque<Batch<B, C>>,
+    fences: Vec<B::Fence>,
+    next_id: u64,
+    completed: u64,
+}
+
+fn align(value: u64, alignment: u64) -> u64 {
+    (value + alignment - 1) / alignment * alignment
+}
+
+fn lcm(a: u64, b: u64) -> u64 {
+    let (mut x, mut y) = (a, b);
+    while y != 0 {
+        let r = x % y;
+        x = y;
+        y = r;
+    }
+    a / x * b
+}
+
+impl<B: Backend, C: Capability + Supports<Transfer>> Uploader<B, C> {
+    /// Create an uploader with `capacity` bytes of staging memory, recording
+    /// into command buffers of the `pool`.
+    ///
+    /// Coherent memory is preferred for the staging buffer, otherwise the
+    /// mapping is flushed on every submission.
+    pub unsafe fn new(
+        device: &B::Device,
+        memory_properties: &MemoryProperties,
+        limits: &Limits,
+        pool: CommandPool<B, C>,
+        capacity: u64,
+    ) -> Result<Self, CreationError> {
+        let mut staging = device.create_buffer(capacity, buffer::Usage::TRANSFER_SRC)?;
+        let requirements = device.get_buffer_requirements(&staging);
+        let (memory_type, coherent) = match memory_properties
+            .find_memory_type(
+                requirements.type_mask,
+                Properties::CPU_VISIBLE | Properties::COHERENT,
+            )
+            .map(|ty| (ty, true))
+            .or_else(|| {
+                memory_properties
+                    .find_memory_type(requirements.type_mask, Properties::CPU_VISIBLE)
+                    .map(|ty| (ty, false))
+            }) {
+            Some(found) => found,
+            None => {
+                device.destroy_buffer(staging);
+                return Err(CreationError::NoMemoryType);
+            }
+        };
+
+        let memory = device.allocate_memory(memory_type, requirements.size)?;
+        device.bind_buffer_memory(&memory, 0, &mut staging)?;
+        let mapping = device.map_memory(&memory, 0..capacity)?;
+
+        Ok(Uploader {
+            pool,
+            staging,
+            memory,
+            mapping,
+            capacity,
+            coherent,
+            offset_alignment: limits.min_buffer_copy_offset_alignment.max(1),
+            pitch_alignment: limits.min_buffer_copy_pitch_alignment.max(1),
+            head: 0,
+            recording: None,
+            pending: VecDeque::new(),
+            fences: Vec::new(),
+            next_id: 1,
+            completed: 0,
+        })
+    }
+
+    /// Allocate staging memory for an upload, submitting the recorded ones and
+    /// waiting for the pending ones until enough memory is free.
+    unsafe fn allocate(
+        &mut self,
+        device: &B::Device,
+        queue: &mut CommandQueue<B, C>,
+        size: u64,
+        alignment: u64,
+    ) -> Result<u64, UploadError> {
+        if size > self.capacity {
+            return Err(UploadError::TooLarge);
+        }
+
+        loop {
+            // Start of the oldest staging memory still in use.
+            let in_use = self
+                .pending
+                .front()
+                .map(|batch| batch.start)
+                .or_else(|| self.recording.as_ref().map(|recording| recording.start));
+            let space = match in_use {
+                Some(start) => {
+                    mapping::find_space(self.capacity, self.head, start, size, alignment)
+                }
+                None => Some(0),
+            };
+            if let Some(offset) = space {
+                if self.recording.is_none() {
+                    let mut command_buffer = self.pool.acquire_command_buffer::<OneShot>();
+                    command_buffer.begin();
+                    self.recording = Some(Recording {
+                        command_buffer,
+                        start: offset,
+                    });
+                }
+                self.head = offset + size;
+                return Ok(offset);
+            }
+
+            if self.recording.is_some() {
+                self.flush(device, queue)?;
+            }
+            let batch = self.pending.pop_front().unwrap();
+            self.retire(device, batch)?;
+        }
+    }
+
+    /// Wait for a submitted batch and recycle its resources.
+    unsafe fn retire(&mut self, device: &B::Device, batch: Batch<B, C>) -> Result<(), UploadError> {
+        device.wait_for_fence(&batch.fence, !0)?;
+        device.reset_fence(&batch.fence)?;
+        self.fences.push(batch.fence);
+        self.pool.free(iter::once(batch.command_buffer));
+        self.completed = batch.id;
+        Ok(())
+    }
+
+    /// Record an upload of `data` into `buffer` at `offset`.
+    ///
+    /// The upload is submitted with the next `flush`, or earlier once the
+    /// staging memory runs out. No barrier is recorded after the copy, so the
+    /// caller has to record one for `buffer` before the device uses its contents.
+    pub unsafe fn upload_buffer(
+        &mut self,
+        device: &B::Device,
+        queue: &mut CommandQueue<B, C>,
+        buffer: &B::Buffer,
+        offset: buffer::Offset,
+        data: &[u8],
+    ) -> Result<(), UploadError> {
+        if data.is_empty() {
+            return Ok(());
+        }
+
+        let size = data.len() as u64;
+        let alignment = self.offset_alignment;
+        let staging_offset = self.allocate(device, queue, size, alignment)?;
+        ptr::copy_nonoverlapping(
+            data.as_ptr(),
+            self.mapping.offset(staging_offset as isize),
+            data.len(),
+        );
+
+        let command_buffer = &mut self.recording.as_mut().unwrap().command_buffer;
+        command_buffer.copy_buffer(
+            &self.staging,
+            buffer,
+            iter::once(BufferCopy {
+                src: staging_offset,
+                dst: offset,
+                size,
+            }),
+        );
+        Ok(())
+    }
+
+    /// Record an upload of `data` into a region of the `layers` of `image`.
+    ///
+    /// `data` holds tightly packed rows of texel blocks of the `format`, for
+    /// every depth slice of every layer. The image is transitioned from
+    /// `layouts.start` for the copy and left in `layouts.end`.
+    pub unsafe fn upload_image(
+        &mut self,
+        device: &B::Device,
+        queue: &mut CommandQueue<B, C>,
+        image: &B::Image,
+        format: format::Format,
+        layouts: Range<image::Layout>,
+        layers: image::SubresourceLayers,
+        offset: image::Offset,
+        extent: image::Extent,
+        data: &[u8],
+    ) -> Result<(), UploadError> {
+        let desc = format.surface_desc();
+        let (block_width, block_height) = (desc.dim.0 as u32, desc.dim.1 as u32);
+        let block_size = desc.bits as u64 / 8;
+        let row_size = ((extent.width + block_width - 1) / block_width) as u64 * block_size;
+        let rows = (extent.height + block_height - 1) / block_height;
+        let slices = extent.depth as u64 * (layers.layers.end - layers.layers.start) as u64;
+        assert_eq!(data.len() as u64, row_size * rows as u64 * slices);
+
+        // Staging rows are padded to a whole number of blocks.
+        let row_pitch = align(row_size, lcm(self.pitch_alignment, block_size));
+        let alignment = lcm(lcm(self.offset_alignment, 4), block_size);
+        let staging_offset =
+            self.allocate(device, queue, row_pitch * rows as u64 * slices, alignment)?;
+        for (i, row) in data.chunks(row_size as usize).enumerate() {
+            ptr::copy_nonoverlapping(
+                row.as_ptr(),
+                self.mapping
+                    .offset((staging_offset + i as u64 * row_pitch) as isize),
+                row.len(),
+            );
+        }
+
+        let range = image::SubresourceRange {
+            aspects: layers.aspects,
+            levels: layers.level..layers.level + 1,
+            layers: layers.layers.clone(),
+        };
+        let command_buffer = &mut self.recording.as_mut().unwrap().command_buffer;
+        command_buffer.pipeline_barrier(
+            PipelineStage::TOP_OF_PIPE..PipelineStage::TRANSFER,
+            Dependencies::empty(),
+            iter::once(Barrier::Image {
+                states: (image::Access::empty(), layouts.start)
+                    ..(
+                        image::Access::TRANSFER_WRITE,
+                        image::Layout::TransferDstOptimal,
+                    ),
+                target: image,
+                families: None,
+                range: range.clone(),
+            }),
+        );
+        command_buffer.copy_buffer_to_image(
+            &self.staging,
+            image,
+            image::Layout::TransferDstOptimal,
+            iter::once(BufferImageCopy {
+                buffer_offset: staging_offset,
+                buffer_width: (row_pitch / block_size) as u32 * block_width,
+                buffer_height: rows * block_height,
+                image_layers: layers,
+                image_offset: offset,
+                image_extent: extent,
+            }),
+        );
+        command_buffer.pipeline_barrier(
+            PipelineStage::TRANSFER..PipelineStage::BOTTOM_OF_PIPE,
+            Dependencies::empty(),
+            iter::once(Barrier::Image {
+                states: (
+                    image::Access::TRANSFER_WRITE,
+                    image::Layout::TransferDstOptimal,
+                )..(image::Access::empty(), layouts.end),
+                target: image,
+                families: None,
+                range,
+            }),
+        );
+        Ok(())
+    }
+
+    /// Submit the recorded uploads to the `queue`.
+    ///
+    /// The returned ticket completes once they, and all the previously
+    /// submitted ones, are done.
+    pub unsafe fn flush(
+        &mut self,
+        device: &B::Device,
+        queue: &mut CommandQueue<B, C>,
+    ) -> Result<UploadTicket, UploadError> {
+        let Recording {
+            mut command_buffer,
+            start,
+        } = match self.recording.take() {
+            Some(recording) => recording,
+            None => return Ok(UploadTicket(self.next_id - 1)),
+        };
+        command_buffer.finish();
+
+        if !self.coherent {
+            // The whole mapping is flushed, partial ranges would have to be
+            // aligned to the `non_coherent_atom_size`.
+            device.flush_mapped_memory_ranges(iter::once((&self.memory, ..)))?;
+        }
+        let fence = match self.fences.pop() {
+            Some(fence) => fence,
+            None => device.create_fence(false)?,
+        };
+        queue.submit_nosemaphores(iter::once(&command_buffer), Some(&fence))?;
+
+        let id = self.next_id;
+        self.next_id += 1;
+        self.pending.push_back(Batch {
+            id,
+            command_buffer,
+            fence,
+            start,
+        });
+        Ok(UploadTicket(id))
+    }
+
+    /// Check if the uploads of the `ticket` are done, recycling the staging
+    /// memory of the completed batches.
+    pub unsafe fn is_complete(
+        &mut self,
+        device: &B::Device,
+        ticket: UploadTicket,
+    ) -> Result<bool, UploadError> {
+        while self.completed < ticket.0 {
+            let done = match self.pending.front() {
+                Some(batch) => device.get_fence_status(&batch.fence)?,
+                None => break,
+            };
+            if !done {
+                break;
+            }
+            let batch = self.pending.pop_front().unwrap();
+            self.retire(device, batch)?;
+        }
+        Ok(self.completed >= ticket.0)
+    }
+
+    /// Block until the uploads of the `ticket` are done.
+    pub unsafe fn wait(
+        &mut self,
+        device: &B::Device,
+        ticket: UploadTicket,
+    ) -> Result<(), UploadError> {
+        while self.completed < ticket.0 {
+            let batch = self.pending.pop_front().unwrap();
+            self.retire(device, batch)?;
+        }
+        Ok(())
+    }
+
+    /// Destroy the uploader, blocking until the pending uploads are done.
+    ///
+    /// Uploads recorded since the last `flush` are discarded.
+    pub unsafe fn destroy(mut self, device: &B::Device) {
+        while let Some(batch) = self.pending.pop_front() {
+            // A lost device won't touch the staging memory anymore.
+            let _ = device.wait_for_fence(&batch.fence, !0);
+            self.fences.push(batch.fence);
+            self.pool.free(iter::once(batch.command_buffer));
+        }
+        if let Some(recording) = self.recording.take() {
+            self.pool.free(iter::once(recording.command_buffer));
+        }
+        for fence in self.fences {
+            device.destroy_fence(fence);
+        }
+        device.destroy_command_pool(self.pool.into_raw());
+        device.unmap_memory(&self.memory);
+        device.destroy_buffer(self.staging);
+        device.free_memory(self.memory);
+    }
+}
@@ -371,6 +371,52 @@ impl SwapchainConfig {
         self
     }
 
+    /// Specify the presentation mode by order of preference,
+    /// picking the first one contained in the `available` modes returned by
+    /// `Surface::compatibility`.
+    ///
+    /// Falls back to `PresentMode::Fifo`, which is always supported.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # extern crate gfx_hal;
+    /// # fn main() {
+    /// # use gfx_hal::{PresentMode, SwapchainConfig};
+    /// # use gfx_hal::format::Format;
+    /// # let available = vec![PresentMode::Fifo, PresentMode::Immediate];
+    /// let config = SwapchainConfig::new(100, 100, Format::Bgra8Unorm, 3)
+    ///     .with_preferred_modes(&available, &[PresentMode::Mailbox, PresentMode::Immediate]);
+    /// assert_eq!(config.present_mode, PresentMode::Immediate);
+    /// # }
+    /// ```
+    pub fn with_preferred_modes(
+        mut self,
+        available: &[PresentMode],
+        preferred: &[PresentMode],
+    ) -> Self {
+        self.present_mode = preferred
+            .iter()
+            .cloned()
+            .find(|mode| available.contains(mode))
+            .unwrap_or(PresentMode::Fifo);
+        self
+    }
+
+    /// Specify the number of images in the swapchain.
+    ///
+    /// The count must be in `SurfaceCapabilities::image_count` range.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    ///
+    /// ```
+    pub fn with_image_count(mut self, count: SwapImageIndex) -> Self {
+        self.image_count = count;
+        self
+    }
+
     /// Specify the usage of backbuffer images.
     ///
     /// # Examples
@@ -383,7 +429,7 @@ impl SwapchainConfig {
         self
     }
 
-    // TODO: depth-only, stencil-only, swapchain size, present modes, etc.
+    // TODO: depth-only, stencil-only, swapchain size, etc.
 }
 
 /// Swapchain backbuffer type
@@ -406,6 +452,41 @@ pub enum AcquireError {
     SurfaceLost(device::SurfaceLost),
 }
 
+/// Error on presenting the images of a swapchain.
+#[derive(Clone, Copy, Debug, Fail, PartialEq, Eq)]
+pub enum PresentError {
+    /// Out of either host or device memory.
+    #[fail(display = "{}", _0)]
+    OutOfMemory(device::OutOfMemory),
+    /// The swapchain is no longer in sync with the surface, needs to be re-created.
+    #[fail(display = "Swapchain is out of date")]
+    OutOfDate,
+    /// Surface is lost
+    #[fail(display = "{}", _0)]
+    SurfaceLost(device::SurfaceLost),
+    /// Device is lost
+    #[fail(display = "{}", _0)]
+    DeviceLost(device::DeviceLost),
+}
+
+impl From<device::OutOfMemory> for PresentError {
+    fn from(error: device::OutOfMemory) -> Self {
+        PresentError::OutOfMemory(error)
+    }
+}
+
+impl From<device::SurfaceLost> for PresentError {
+    fn from(error: device::SurfaceLost) -> Self {
+        PresentError::SurfaceLost(error)
+    }
+}
+
+impl From<device::DeviceLost> for PresentError {
+    fn from(error: device::DeviceLost) -> Self {
+        PresentError::DeviceLost(error)
+    }
+}
+
 /// The `Swapchain` is the backend representation of the surface.
 /// It consists of multiple buffers, which will be presented on the surface.
 pub trait Swapchain<B: Backend>: Any + Send + Sync {
@@ -433,6 +514,10 @@ pub trait Swapchain<B: Backend>: Any + Send + Sync {
 
     /// Present one acquired image.
     ///
+    /// Fails with `PresentError::OutOfDate` once the surface has changed, for example
+    /// after a window resize, in which case the swapchain has to be re-created
+    /// by passing it as the old swapchain to `Device::create_swapchain`.
+    ///
     /// # Safety
     ///
     /// The passed queue _must_ support presentation on the surface, which is
@@ -448,7 +533,7 @@ pub trait Swapchain<B: Backend>: Any + Send + Sync {
         present_queue: &mut CommandQueue<B, C>,
         image_index: SwapImageIndex,
         wait_semaphores: Iw,
-    ) -> Result<(), ()>
+    ) -> Result<(), PresentError>
     where
         Self: 'a + Sized + Borrow<B::Swapchain>,
         C: Capability,
@@ -463,7 +548,7 @@ pub trait Swapchain<B: Backend>: Any + Send + Sync {
         &'a self,
         present_queue: &mut CommandQueue<B, C>,
         image_index: SwapImageIndex,
-    ) -> Result<(), ()>
+    ) -> Result<(), PresentError>
     where
         Self: 'a + Sized + Borrow<B::Swapchain>,
         C: Capability,
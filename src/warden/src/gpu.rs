@@ -754,6 +754,7 @@ impl<B: hal::Backend> Scene<B, hal::General> {
                         depth_stencil: depth_stencil.clone(),
                         baked_states: pso::BakedStates::default(), //TODO
                         multisampling: None,                       // TODO
+                        viewport_count: 1,
                         layout: &resources.pipeline_layouts[layout],
                         subpass: hal::pass::Subpass {
                             main_pass: &resources.render_passes[&subpass.parent].handle,
@@ -1262,7 +1263,9 @@ impl<B: hal::Backend> Scene<B, hal::General> {
 
         let command_buffers = iter::once(&self.init_submit).chain(submits);
         unsafe {
-            self.queue_group.queues[0].submit_nosemaphores(command_buffers, None);
+            self.queue_group.queues[0]
+                .submit_nosemaphores(command_buffers, None)
+                .unwrap();
         }
     }
 
@@ -1337,7 +1340,8 @@ impl<B: hal::Backend> Scene<B, hal::General> {
             .expect("Can't create copy-fence");
         unsafe {
             self.queue_group.queues[0]
-                .submit_nosemaphores(iter::once(&cmd_buffer), Some(&copy_fence));
+                .submit_nosemaphores(iter::once(&cmd_buffer), Some(&copy_fence))
+                .unwrap();
             self.device.wait_for_fence(&copy_fence, !0).unwrap();
             self.device.destroy_fence(copy_fence);
             self.device.destroy_command_pool(command_pool.into_raw());
@@ -1465,7 +1469,8 @@ impl<B: hal::Backend> Scene<B, hal::General> {
             .expect("Can't create copy-fence");
         unsafe {
             self.queue_group.queues[0]
-                .submit_nosemaphores(iter::once(&cmd_buffer), Some(&copy_fence));
+                .submit_nosemaphores(iter::once(&cmd_buffer), Some(&copy_fence))
+                .unwrap();
             self.device.wait_for_fence(&copy_fence, !0).unwrap();
             self.device.destroy_fence(copy_fence);
             self.device.destroy_command_pool(command_pool.into_raw());